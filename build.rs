@@ -0,0 +1,5 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // Generate the gRPC remote control service.
+    tonic_build::compile_protos("proto/roktrack.proto")?;
+    Ok(())
+}
@@ -32,7 +32,27 @@ pub fn main() -> Result<(), Box<dyn std::error::Error>> {
     }
 
     // Prepare the resources by initializing the property struct
-    let property = init();
+    let mut property = init();
+    // Run the pilots without driving the motors.
+    if args.iter().skip(1).any(|arg| arg == "dry_run") {
+        property.conf.drive.dry_run = true;
+    }
+    // Review the frames kept by the dataset capture on the console, without driving.
+    if args.iter().skip(1).any(|arg| arg == "review") {
+        let stdin = std::io::stdin();
+        module::vision::dataset::review(&property.path.dir.data, stdin.lock(), std::io::stdout())?;
+        return Ok(());
+    }
+    // Export the detection events of the sessions to COCO JSON, without driving.
+    if args.iter().skip(1).any(|arg| arg == "export") {
+        module::vision::events::export(&property.path.dir.data, std::io::stdout())?;
+        return Ok(());
+    }
+    // Benchmark the models on the sample images for the speed and the accuracy, without driving.
+    if args.iter().skip(1).any(|arg| arg == "benchmark") {
+        module::vision::benchmark::run(&property.conf, std::io::stdout())?;
+        return Ok(());
+    }
 
     // Initialize the logging system with the data directory and the system name
     init_log(
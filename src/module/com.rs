@@ -2,41 +2,180 @@
 //!
 //! This module provides functionality to handle BLE (Bluetooth Low Energy) communications.
 
-use crate::module::pilot::Modes;
-use bitreader::BitReader;
-use btleplug::api::{bleuuid::BleUuid, Central, CentralEvent, Manager as _, ScanFilter};
+pub mod channel;
+pub mod checksum;
+pub mod codec;
+pub mod espnow;
+pub mod fragment;
+pub mod gatt;
+pub mod grpc;
+pub mod hci;
+pub mod lora;
+pub mod mqtt;
+pub mod nus;
+pub mod pairing;
+pub mod queue;
+pub mod reliable;
+pub mod secure;
+pub mod signal;
+pub mod table;
+pub mod telemetry;
+pub mod timesync;
+pub mod transport;
+pub mod uart;
+pub mod udp;
+pub mod websocket;
+
+use self::channel::BoundedSender;
+use self::codec::StateFrame;
+use self::fragment::Fragment;
+use self::queue::{BroadcastQueue, Priority};
+use self::secure::{PayloadCipher, ReplayGuard, DEVICE_ID_LEN, FIELDS_LEN};
+use self::signal::{Calibration, Proximity, SignalHistory};
+use self::telemetry::Telemetry;
+use self::transport::{Capabilities, CommTransport};
+use crate::module::define;
+use crate::module::pilot::{Modes, CUSTOM_MODES};
+use crate::module::util::init::RoktrackProperty;
+use btleplug::api::{
+    bleuuid::{uuid_from_u16, uuid_from_u32, BleUuid},
+    Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter,
+};
 use btleplug::platform::{Adapter, Manager};
-use futures::stream::StreamExt;
-use std::process::Command;
-use std::sync::mpsc::Sender;
+use futures::stream::{Stream, StreamExt};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
 use std::sync::{Arc, Mutex};
 use std::thread;
 use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+// Interval for saving the replay protection state.
+const REPLAY_SAVE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Length of the legacy payload. Telemetry follows it in extended advertisements.
+pub const PAYLOAD_LEN: usize = 23;
 
 /// BLE Broadcast Handler
 pub struct BleBroadCast {
     pub inner: Arc<Mutex<BleBroadCastInner>>,
+    queue: Arc<Mutex<BroadcastQueue>>,
+    cipher: Option<Arc<PayloadCipher>>,
+    replay_state: PathBuf,
+    scan_dev_id: Option<u16>,
+    manufacturer_id: u16,
+    scan_filter: ScanFilter,
+    scan_all: bool,
+    watchdog_timeout: Duration,
 }
 
-/// Default implementation for BLE Broadcast Handler
-impl Default for BleBroadCast {
-    fn default() -> Self {
-        Self::new()
+impl BleBroadCast {
+    /// Creates a new instance of BLE Broadcast Handler with the given property.
+    ///
+    /// Returns an error if an adapter name or a scan UUID in the config is invalid.
+    pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+        let cipher = cipher_from_property(&property)?;
+        // Broadcast and scan may run on different adapters, e.g. a USB dongle and the onboard radio.
+        let dev_id = resolve_dev_id(&property.conf.com.adapter)?;
+        let scan_dev_id = match property.conf.com.scan_adapter.as_str() {
+            "" => dev_id,
+            name => resolve_dev_id(name)?,
+        };
+        // Only devices advertising the given services are reported by the adapter.
+        let services = property
+            .conf
+            .com
+            .scan_uuids
+            .iter()
+            .map(|s| parse_uuid(s).map_err(|e| format!("Invalid scan UUID: {}, {}", s, e)))
+            .collect::<Result<Vec<Uuid>, String>>()?;
+        let broadcast = Self {
+            inner: Arc::new(Mutex::new(BleBroadCastInner::new(
+                cipher.clone(),
+                property.conf.com.extended,
+                dev_id.unwrap_or(DEFAULT_DEV_ID),
+                property.conf.com.manufacturer_id,
+            ))),
+            queue: Arc::new(Mutex::new(BroadcastQueue::new(BROADCAST_PACE))),
+            cipher,
+            replay_state: Path::new(&property.path.dir.data).join(define::path::REPLAY_STATE_FILE),
+            scan_dev_id,
+            manufacturer_id: property.conf.com.manufacturer_id,
+            scan_filter: ScanFilter { services },
+            scan_all: property.conf.com.scan_mode == "all",
+            watchdog_timeout: Duration::from_secs(property.conf.com.scan_watchdog),
+        };
+        broadcast.run_queue();
+        Ok(broadcast)
     }
-}
 
-impl BleBroadCast {
-    /// Creates a new instance of BLE Broadcast Handler
-    pub fn new() -> Self {
-        Self {
-            inner: Arc::new(Mutex::new(BleBroadCastInner::new())),
+    /// Sends queued payloads in a thread, so that callers never block on the adapter.
+    fn run_queue(&self) -> JoinHandle<()> {
+        let inner = self.inner.clone();
+        let queue = self.queue.clone();
+        thread::spawn(move || loop {
+            thread::sleep(QUEUE_POLL_INTERVAL);
+            let next = queue.lock().unwrap().pop(Instant::now());
+            if let Some((identifier, data)) = next {
+                if let Err(e) = inner.lock().unwrap().cast(&identifier, data) {
+                    log::warn!("BLE BroadCast Failed: {}", e);
+                }
+            }
+        })
+    }
+
+    /// Get the Bluetooth adapter `hci<dev_id>`, or the first available one.
+    ///
+    /// Returns an error if no adapter is available.
+    async fn get_central(
+        manager: &Manager,
+        dev_id: Option<u16>,
+    ) -> Result<Adapter, Box<dyn std::error::Error>> {
+        let adapters = manager.adapters().await?;
+        if let Some(dev_id) = dev_id {
+            for adapter in adapters.iter() {
+                // The info starts with the adapter name, e.g. "hci1 (usb:v1D6Bp0246d0540)".
+                let info = adapter.adapter_info().await.unwrap_or_default();
+                let name = info.split_whitespace().next().unwrap_or("");
+                if hci::parse_dev_id(name) == Some(dev_id) {
+                    return Ok(adapter.clone());
+                }
+            }
+            log::warn!("hci{} not found. Use the first adapter.", dev_id);
         }
+        adapters
+            .into_iter()
+            .next()
+            .ok_or_else(|| "No Bluetooth adapter.".into())
+    }
+}
+
+impl CommTransport for BleBroadCast {
+    /// Queues the payload for BLE advertising.
+    ///
+    /// Urgent messages (e.g. Halt) are advertised before pending state updates.
+    fn send(&self, identifier: &u8, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let priority = priority(&payload);
+        self.queue
+            .lock()
+            .unwrap()
+            .push(*identifier, payload, priority);
+        Ok(())
     }
 
     /// Listens to BLE advertisements and sends neighbor information via a channel.
     ///
     /// /// https://github.com/deviceplug/btleplug/blob/master/examples/discover_adapters_peripherals.rs
-    pub fn listen(&self, tx: Sender<Neighbor>) -> JoinHandle<()> {
+    fn listen(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let cipher = self.cipher.clone();
+        let replay_state = self.replay_state.clone();
+        let scan_dev_id = self.scan_dev_id;
+        let manufacturer_id = self.manufacturer_id;
+        let scan_filter = self.scan_filter.clone();
+        let scan_all = self.scan_all;
+        let watchdog_timeout = self.watchdog_timeout;
+        let inner = self.inner.clone();
         thread::spawn(move || {
             log::debug!("Com Thread Started");
             // Create an asynchronous runtime.
@@ -47,18 +186,36 @@ impl BleBroadCast {
 
             // Run asynchronous tasks at runtime.
             rt.block_on(async {
-                let manager = Manager::new().await.unwrap();
+                // Get the configured Bluetooth adapter, create an event stream for it and start scanning.
+                let scan = async {
+                    let manager = Manager::new().await?;
+                    let central = Self::get_central(&manager, scan_dev_id).await?;
+                    let events = central.events().await?;
+                    central.start_scan(scan_filter.clone()).await?;
+                    Ok::<_, Box<dyn std::error::Error>>((manager, central, events))
+                };
+                let (_manager, central, mut events) = match scan.await {
+                    Ok(scan) => scan,
+                    Err(e) => {
+                        log::error!("BLE Scan Not Started: {}", e);
+                        return;
+                    }
+                };
 
-                // Get the first Bluetooth adapter.
-                let central = Self::get_central(&manager).await;
+                // Restore the counters seen before the last shutdown.
+                let mut guard = ReplayGuard::load(&replay_state);
+                let mut last_save = Instant::now();
 
-                // Create an event stream for the adapter.
-                let mut events = central.events().await.unwrap();
+                // Signal strength history of neighbors.
+                let mut signals = SignalHistory::new();
 
-                // Start scanning for devices.
-                central.start_scan(ScanFilter::default()).await.unwrap();
+                // Restart scanning when the event stream stalls.
+                let mut watchdog = ScanWatchdog::new(watchdog_timeout);
 
-                while let Some(event) = events.next().await {
+                while let Some(event) = watchdog
+                    .next_event(&central, &mut events, &scan_filter, &inner, scan_dev_id)
+                    .await
+                {
                     match event {
                         CentralEvent::DeviceDiscovered(id) => {
                             format!("DeviceDiscovered: {:?}", id);
@@ -73,31 +230,71 @@ impl BleBroadCast {
                             id,
                             manufacturer_data,
                         } => {
-                            log::debug!(
-                                "id:{}, key:{:?}, data:{:?}",
-                                id.clone().to_string(),
-                                *manufacturer_data.keys().last().unwrap(),
-                                manufacturer_data.values().last().unwrap()
-                            );
-                            let manufacturer_id: u16 = *manufacturer_data.keys().last().unwrap();
-                            let data: &Vec<u8> = manufacturer_data.values().last().unwrap();
-                            if manufacturer_id == 65535 {
-                                // Get the MAC address.
-                                let mut mac_addr: String = id.to_string();
-                                mac_addr = mac_addr.replace("hci0/dev_", "");
-                                mac_addr = mac_addr.replace('_', ":");
-
-                                // Generate neighbor information.
-                                let mut neighbor = Neighbor::from_manufacture_data(data);
-                                neighbor.mac = mac_addr.clone();
-                                neighbor.manufacturer_id = manufacturer_id;
-                                tx.send(neighbor).unwrap();
-                                log::debug!(
-                                    "BLE BroadCast Received From: {:?}, Content: {:?}",
+                            // Skip advertisements of other devices.
+                            let data: &Vec<u8> = match manufacturer_data.get(&manufacturer_id) {
+                                Some(data) => data,
+                                None => {
+                                    if scan_all {
+                                        log::debug!(
+                                            "Other Device: {}, data:{:?}",
+                                            id,
+                                            manufacturer_data
+                                        );
+                                    }
+                                    continue;
+                                }
+                            };
+                            // Get the signal strength of the advertisement.
+                            let rssi = match central.peripheral(&id).await {
+                                Ok(p) => p.properties().await.ok().flatten().and_then(|p| p.rssi),
+                                Err(_) => None,
+                            };
+
+                            // Get the MAC address.
+                            let id = id.to_string();
+                            let mac_addr = id.rsplit("dev_").next().unwrap_or("").replace('_', ":");
+
+                            // Generate neighbor information.
+                            let mut neighbor =
+                                match Neighbor::from_manufacture_data(data, cipher.as_deref()) {
+                                    Ok(neighbor) => neighbor,
+                                    Err(e) => {
+                                        log::debug!(
+                                            "BLE BroadCast Rejected From: {:?}, {}",
+                                            mac_addr,
+                                            e
+                                        );
+                                        continue;
+                                    }
+                                };
+                            // Drop replayed frames.
+                            if !fresh(&mut guard, &neighbor) {
+                                log::warn!(
+                                    "BLE BroadCast Replay Dropped From: {:?}, counter: {}",
                                     mac_addr,
-                                    data
+                                    neighbor.counter
                                 );
+                                continue;
+                            }
+                            if last_save.elapsed() > REPLAY_SAVE_INTERVAL {
+                                let _ = guard.save(&replay_state);
+                                last_save = Instant::now();
                             }
+                            if let Some(rssi) = rssi {
+                                neighbor.rssi = rssi.clamp(i8::MIN as i16, 0) as i8;
+                                neighbor.rssi_smoothed = signals.push(&mac_addr, neighbor.rssi);
+                            }
+                            neighbor.mac = mac_addr.clone();
+                            neighbor.manufacturer_id = manufacturer_id;
+                            if tx.send(neighbor).is_err() {
+                                log::error!("Neighbor Receiver Gone. Stop Listening.");
+                                break;
+                            }
+                            log::debug!(
+                                "BLE BroadCast Received From: {:?}, Content: {:?}",
+                                mac_addr,
+                                data
+                            );
                         }
                         CentralEvent::ServiceDataAdvertisement { id, service_data } => {
                             format!("ServiceDataAdvertisement: {:?}, {:?}", id, service_data);
@@ -114,70 +311,314 @@ impl BleBroadCast {
         })
     }
 
-    /// Get the first available Bluetooth adapter.
-    async fn get_central(manager: &Manager) -> Adapter {
-        let adapters = manager.adapters().await.unwrap();
-        adapters.into_iter().next().unwrap()
+    /// Features of BLE advertising.
+    fn capabilities(&self) -> Capabilities {
+        // Flags, manufacturer specific data header and the identifier.
+        let header = 8;
+        let max_adv = if self.inner.lock().unwrap().extended {
+            hci::MAX_EXT_ADV_DATA_LEN
+        } else {
+            hci::MAX_ADV_DATA_LEN
+        };
+        Capabilities {
+            name: "ble",
+            max_payload: max_adv - header,
+            bidirectional: true,
+            range_m: 30,
+        }
+    }
+}
+
+/// Priority of the payload in the broadcast queue.
+fn priority(payload: &[u8]) -> Priority {
+    match payload.get(3) {
+        // Fragments must be sent in order without coalescing.
+        _ if fragment::is_fragment(payload) => Priority::Urgent,
+        Some(msg) if ChildMsg::is_urgent(*msg) => Priority::Urgent,
+        _ => Priority::Normal,
+    }
+}
+
+/// File of the highest counters received over a transport other than BLE, e.g. `replay_state_lora`.
+fn replay_state_path(property: &RoktrackProperty, transport: &str) -> PathBuf {
+    Path::new(&property.path.dir.data).join(format!(
+        "{}_{}",
+        define::path::REPLAY_STATE_FILE,
+        transport
+    ))
+}
+
+/// Whether the sealed frame is not a replay, recording its counter.
+///
+/// Frames are told apart by the authenticated device ID, not the MAC or the identifier,
+/// which a replaying sender can change. Plain frames carry no counter and always pass.
+pub fn fresh(guard: &mut ReplayGuard, neighbor: &Neighbor) -> bool {
+    match neighbor.device {
+        Some(device) => guard.check(&device, neighbor.counter),
+        None => true,
+    }
+}
+
+/// Creates the payload cipher from the key in the config, or `None` if encryption is disabled.
+///
+/// An invalid key is an error, so that it doesn't silently fall back to plaintext.
+pub fn cipher_from_property(
+    property: &RoktrackProperty,
+) -> Result<Option<Arc<PayloadCipher>>, Box<dyn std::error::Error>> {
+    let data_dir = Path::new(&property.path.dir.data);
+    let cipher = PayloadCipher::from_hex(
+        &property.conf.com.key,
+        Some(data_dir.join(define::path::NONCE_COUNTER_FILE)),
+        Some(data_dir.join(define::path::DEVICE_ID_FILE)),
+    )
+    .map_err(|e| format!("Invalid com key: {}", e))?;
+    Ok(cipher.map(Arc::new))
+}
+
+/// Watches the scan event stream and restarts scanning when it stalls.
+///
+/// BlueZ occasionally wedges and the event stream silently stops. A restart of the
+/// scan is tried first, then the adapter is power cycled.
+struct ScanWatchdog {
+    timeout: Duration,
+    stalls: u32,
+}
+
+impl ScanWatchdog {
+    /// Creates a watchdog firing after `timeout` without events. Zero disables it.
+    fn new(timeout: Duration) -> Self {
+        Self { timeout, stalls: 0 }
+    }
+
+    /// Waits for the next event, recovering the scan on every stall.
+    async fn next_event(
+        &mut self,
+        central: &Adapter,
+        events: &mut Pin<Box<dyn Stream<Item = CentralEvent> + Send>>,
+        scan_filter: &ScanFilter,
+        inner: &Arc<Mutex<BleBroadCastInner>>,
+        dev_id: Option<u16>,
+    ) -> Option<CentralEvent> {
+        if self.timeout.is_zero() {
+            return events.next().await;
+        }
+        loop {
+            match tokio::time::timeout(self.timeout, events.next()).await {
+                Ok(event) => {
+                    if self.stalls > 0 {
+                        log::info!("BLE Scan Recovered After {} Restarts", self.stalls);
+                        self.stalls = 0;
+                    }
+                    return event;
+                }
+                Err(_) => {
+                    self.stalls += 1;
+                    log::warn!(
+                        "No BLE Event for {:?}. Restarting Scan. (attempt {})",
+                        self.timeout,
+                        self.stalls
+                    );
+                    // Restarting the scan didn't help. Reset the adapter.
+                    if self.stalls > 1 {
+                        Self::reset_adapter(inner, dev_id.unwrap_or(DEFAULT_DEV_ID)).await;
+                    }
+                    let _ = central.stop_scan().await;
+                    if let Err(e) = central.start_scan(scan_filter.clone()).await {
+                        log::error!("BLE Scan Restart Failed: {}", e);
+                        continue;
+                    }
+                    match central.events().await {
+                        Ok(new_events) => *events = new_events,
+                        Err(e) => log::error!("BLE Event Stream Reopen Failed: {}", e),
+                    }
+                }
+            }
+        }
+    }
+
+    /// Power cycles the adapter `hci<dev_id>` and restarts advertising if it's on the same one.
+    async fn reset_adapter(inner: &Arc<Mutex<BleBroadCastInner>>, dev_id: u16) {
+        log::warn!("Resetting hci{}", dev_id);
+        match hci::HciSocket::open(dev_id).and_then(|socket| socket.power_cycle()) {
+            Ok(_) => {
+                // Give BlueZ time to pick up the adapter again.
+                tokio::time::sleep(ADAPTER_RESET_WAIT).await;
+                let mut inner = inner.lock().unwrap();
+                if inner.dev_id() == Some(dev_id) {
+                    inner.restart();
+                }
+            }
+            Err(e) => log::error!("hci{} Reset Failed: {}", dev_id, e),
+        }
+    }
+}
+
+/// Converts the adapter name in the config into its controller index.
+///
+/// Returns `None` for an empty name, which means the first adapter.
+pub fn resolve_dev_id(name: &str) -> Result<Option<u16>, Box<dyn std::error::Error>> {
+    if name.is_empty() {
+        return Ok(None);
+    }
+    // An invalid name must not silently fall back to another adapter.
+    match hci::parse_dev_id(name) {
+        Some(dev_id) => Ok(Some(dev_id)),
+        None => Err(format!("Invalid Bluetooth adapter: {}", name).into()),
     }
 }
 
+/// Parses a service UUID, either 16-bit ("FEAA"), 32-bit or 128-bit.
+pub fn parse_uuid(s: &str) -> Result<Uuid, Box<dyn std::error::Error>> {
+    let uuid = match s.len() {
+        4 => uuid_from_u16(u16::from_str_radix(s, 16)?),
+        8 => uuid_from_u32(u32::from_str_radix(s, 16)?),
+        _ => Uuid::parse_str(s)?,
+    };
+    Ok(uuid)
+}
+
 /// BLE Broadcast Handler Inner
-#[derive(Default)]
-pub struct BleBroadCastInner {}
+pub struct BleBroadCastInner {
+    socket: Option<hci::HciSocket>,
+    cipher: Option<Arc<PayloadCipher>>,
+    extended: bool,
+    manufacturer_id: u16,
+}
+
+// Controller used when no adapter is specified.
+const DEFAULT_DEV_ID: u16 = 0;
+
+// Advertisement interval in units of 0.625 ms (0xA0 = 100 ms).
+const ADV_INTERVAL: u16 = 0x00A0;
+
+// Non-connectable undirected advertising.
+const ADV_NONCONN_IND: u8 = 0x03;
+
+// Extended advertising set used for broadcasting.
+const ADV_HANDLE: u8 = 0x00;
+
+// Minimum time an update stays on air (3 advertising events).
+const BROADCAST_PACE: Duration = Duration::from_millis(ADV_INTERVAL as u64 * 625 * 3 / 1000);
+
+// Interval for checking the broadcast queue.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Time for BlueZ to pick up a power cycled adapter.
+const ADAPTER_RESET_WAIT: Duration = Duration::from_secs(2);
 
 impl BleBroadCastInner {
-    /// Creates a new instance of the BLE Broadcast Handler Inner.
-    pub fn new() -> Self {
-        // Set Advertisement Interval using hcitool commands.
-        let _output = Command::new("hcitool")
-            .args([
-                "-i", "hci0", "cmd", "0x08", "0x0006", "A0", "00", "A0", "00", "03", "00", "00",
-                "00", "00", "00", "00", "00", "00", "07", "00",
-            ])
-            .output()
-            .expect("failed");
-
-        // Start Advertisement using hcitool commands.
-        let _output = Command::new("hcitool")
-            .args(["-i", "hci0", "cmd", "0x08", "0x000a", "01"])
-            .output()
-            .expect("failed");
-
-        Self {}
+    /// Creates a new instance of the BLE Broadcast Handler Inner on the adapter `hci<dev_id>`.
+    ///
+    /// If `cipher` is given, every payload is encrypted and authenticated.
+    /// If `extended` is true, BLE 5 extended advertising is used to carry telemetry.
+    pub fn new(
+        cipher: Option<Arc<PayloadCipher>>,
+        extended: bool,
+        dev_id: u16,
+        manufacturer_id: u16,
+    ) -> Self {
+        Self {
+            socket: Self::open_socket(dev_id, extended),
+            cipher,
+            extended,
+            manufacturer_id,
+        }
+    }
+
+    /// Index of the adapter, or `None` if it couldn't be opened.
+    pub fn dev_id(&self) -> Option<u16> {
+        self.socket.as_ref().map(|socket| socket.dev_id)
+    }
+
+    /// Opens the adapter again and restarts advertising, e.g. after it was reset.
+    pub fn restart(&mut self) {
+        if let Some(dev_id) = self.dev_id() {
+            // Close the old socket first.
+            self.socket = None;
+            self.socket = Self::open_socket(dev_id, self.extended);
+        }
+    }
+
+    /// Opens the given adapter and starts advertising.
+    ///
+    /// If the adapter can't be opened, broadcasting is disabled and `cast` returns an error.
+    fn open_socket(dev_id: u16, extended: bool) -> Option<hci::HciSocket> {
+        let socket = match hci::HciSocket::open(dev_id) {
+            Ok(socket) => {
+                // Set Advertisement Interval and start Advertisement.
+                let res = if extended {
+                    socket
+                        .set_extended_advertising_parameters(ADV_HANDLE, ADV_INTERVAL)
+                        .and_then(|_| socket.set_extended_advertise_enable(ADV_HANDLE, true))
+                } else {
+                    socket
+                        .set_advertising_parameters(ADV_INTERVAL, ADV_NONCONN_IND)
+                        .and_then(|_| socket.set_advertise_enable(true))
+                };
+                if let Err(e) = res {
+                    log::warn!("BLE Advertisement Setup Failed. hci{}: {}", dev_id, e);
+                }
+                Some(socket)
+            }
+            Err(e) => {
+                log::error!("Can't open hci{}: {}", dev_id, e);
+                None
+            }
+        };
+        socket
     }
 
     /// Broadcasts the advertisement data.
-    pub fn cast(&self, identifier: &u8, data: Vec<u8>) {
-        // Payload identifier and data in hexadecimal format.
-        let payload_identifier = format!("{:02X}", identifier);
-        let payload_data: Vec<_> = data.iter().map(|x| format!("{:02X}", x)).collect();
-
-        // Combine payload elements.
-        let mut payload: Vec<String> = vec![payload_identifier];
-        payload.extend(payload_data);
-
-        // Header and content for advertisement.
-        let header: Vec<&str> = vec![
-            "-i", "hci0", "cmd", "0x08", "0x0008", "1E", "02", "01", "06", "1A", "FF", "FF", "FF",
-        ];
-        let header: Vec<String> = header.iter().map(|x| x.to_string()).collect();
-        let mut content: Vec<String> = vec![];
-        content.extend(header);
-        content.extend(payload);
-
-        // Execute hcitool command for advertisement.
-        let _output = Command::new("hcitool")
-            .args(content)
-            .output()
-            .expect("failed");
+    ///
+    /// Telemetry after the legacy payload is dropped unless extended advertising is enabled.
+    pub fn cast(&self, identifier: &u8, data: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let socket = self
+            .socket
+            .as_ref()
+            .ok_or("BLE adapter is not available.")?;
+        let data = match &self.cipher {
+            Some(cipher) => cipher.seal(*identifier, &data)?,
+            None => data,
+        };
+        if self.extended {
+            let adv = build_adv_data(
+                self.manufacturer_id,
+                identifier,
+                &data,
+                hci::MAX_EXT_ADV_DATA_LEN,
+            );
+            socket.set_extended_advertising_data(ADV_HANDLE, &adv)
+        } else {
+            let adv = build_adv_data(
+                self.manufacturer_id,
+                identifier,
+                &data,
+                hci::MAX_ADV_DATA_LEN,
+            );
+            socket.set_advertising_data(&adv)
+        }
     }
 }
 
+/// Builds advertising data carrying the payload as manufacturer specific data.
+///
+/// Flags(02 01 06) + Manufacturer Specific Data(LL FF <company id> ...) truncated to `max_len` bytes.
+fn build_adv_data(manufacturer_id: u16, identifier: &u8, data: &[u8], max_len: usize) -> Vec<u8> {
+    let [id_low, id_high] = manufacturer_id.to_le_bytes();
+    let mut adv = vec![0x02, 0x01, 0x06, 0x00, 0xFF, id_low, id_high, *identifier];
+    adv.extend_from_slice(data);
+    adv.truncate(max_len);
+    // Length of the manufacturer specific data structure.
+    adv[3] = (adv.len() - 4) as u8;
+    adv
+}
+
 /// Neighbor State
 #[derive(Debug, Clone)]
 pub struct Neighbor {
     pub timestamp: String,
     pub rssi: i8,
+    pub rssi_smoothed: f32,
     pub mac: String,
     pub manufacturer_id: u16,
     pub identifier: u8,
@@ -187,37 +628,137 @@ pub struct Neighbor {
     pub mode: Modes,
     pub msg: u8,
     pub dest: u8,
+    pub seq: u8,
+    pub ack: u8,
+    pub counter: u32,
+    /// Authenticated device ID of the sender, `None` for plain frames.
+    pub device: Option<[u8; DEVICE_ID_LEN]>,
+    /// Wire version of the sender, `None` if unknown (local frames).
+    pub version: Option<u8>,
+    pub telemetry: Telemetry,
+    pub fragment: Option<Fragment>,
 }
 
 impl Neighbor {
     /// Generates neighbor state from advertisement data.
-    pub fn from_manufacture_data(data: &[u8]) -> Self {
+    ///
+    /// If `cipher` is given, frames that can't be authenticated are rejected.
+    /// Otherwise, frames with a wrong length or checksum are rejected.
+    pub fn from_manufacture_data(
+        data: &[u8],
+        cipher: Option<&PayloadCipher>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Decrypt the payload fields and rebuild a plain frame.
+        if let Some(cipher) = cipher {
+            let identifier = *data.get(3).ok_or("Frame too short.")?;
+            let opened = cipher.open(identifier, &data[4..])?;
+            let mut plain = data[..4].to_vec();
+            plain.extend_from_slice(&opened.fields[..FIELDS_LEN]);
+            plain.resize(4 + PAYLOAD_LEN, 0);
+            plain.extend_from_slice(&opened.fields[FIELDS_LEN..]);
+            let mut neighbor = Self::parse(&plain)?;
+            neighbor.counter = opened.counter;
+            neighbor.device = Some(opened.device);
+            neighbor.version = Some(opened.version);
+            return Ok(neighbor);
+        }
+        let identifier = *data.get(3).ok_or("Frame too short.")?;
+        checksum::validate(identifier, &data[4..])?;
+        Self::parse(data)
+    }
+
+    /// Parses a validated plain frame.
+    fn parse(data: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
         // Parse data elements.
         // Since the first 3 bytes of the data acquired by btleplug are filled with FF,
         // the data should be acquired from the 4th byte.
         let identifier = data[3];
-        let buf = [data[4]];
-        let mut bit_reader = BitReader::new(&buf);
-        let state: bool = bit_reader.read_u8(1).unwrap() != 0;
-        let rest: u8 = bit_reader.read_u8(7).unwrap();
-        let pi_temp = data[5];
-        let mode = data[6];
-        let msg = data[7];
-        let dest = data[8];
+        let frame = StateFrame::decode(&data[4..])?;
+        // Telemetry is only present in extended advertisements.
+        let telemetry = Telemetry::decode(data.get(4 + PAYLOAD_LEN..).unwrap_or(&[]));
+        // A fragment of a large message instead of a state
+        let fragment = Fragment::from_payload(&data[4..]);
 
         // Set neighbor information.
-        Self {
+        Ok(Self {
             timestamp: chrono::Utc::now().timestamp().to_string(),
             rssi: 0,
+            rssi_smoothed: 0.0,
             mac: String::from(""),
             manufacturer_id: 0,
             identifier,
-            state,
-            rest,
-            pi_temp,
-            mode: Modes::from_u8(mode),
-            msg,
-            dest,
+            state: frame.state,
+            rest: frame.rest,
+            pi_temp: frame.pi_temp,
+            mode: frame.mode,
+            msg: frame.msg,
+            dest: frame.dest,
+            seq: frame.seq,
+            ack: frame.ack,
+            counter: 0,
+            device: None,
+            version: Some(frame.version),
+            telemetry,
+            fragment,
+        })
+    }
+
+    /// Generates neighbor state from a frame of a non-BLE transport.
+    ///
+    /// The frame is laid out as the advertisement after the manufacturer header: `| identifier | payload |`.
+    pub fn from_frame(
+        frame: &[u8],
+        cipher: Option<&PayloadCipher>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut data = vec![0xFF; 3];
+        data.extend_from_slice(frame);
+        Self::from_manufacture_data(&data, cipher)
+    }
+
+    /// Rough distance to the neighbor in meters estimated from the smoothed RSSI.
+    ///
+    /// Returns `None` if no signal strength has been received yet.
+    pub fn distance(&self) -> Option<f32> {
+        if self.rssi == 0 {
+            None
+        } else {
+            Some(signal::estimate_distance(self.rssi_smoothed))
+        }
+    }
+
+    /// Coarse distance and zone (near/mid/far) of the neighbor from the smoothed RSSI.
+    ///
+    /// Returns `None` if no signal strength has been received yet.
+    pub fn proximity(&self, calibration: &Calibration) -> Option<Proximity> {
+        if self.rssi == 0 {
+            None
+        } else {
+            Some(calibration.proximity(self.rssi_smoothed))
+        }
+    }
+
+    /// Generates a command from the commander (identifier 0) addressed to everyone.
+    pub fn from_parent_msg(msg: ParentMsg) -> Self {
+        Self {
+            timestamp: chrono::Utc::now().timestamp().to_string(),
+            rssi: 0,
+            rssi_smoothed: 0.0,
+            mac: String::from(""),
+            manufacturer_id: 0,
+            identifier: 0,
+            state: false,
+            rest: 0,
+            pi_temp: 0,
+            mode: Modes::Unknown,
+            msg: ParentMsg::to_u8(msg),
+            dest: 255,
+            seq: 0,
+            ack: 0,
+            counter: 0,
+            device: None,
+            version: None,
+            telemetry: Telemetry::default(),
+            fragment: None,
         }
     }
 }
@@ -242,6 +783,12 @@ pub enum ChildMsg {
     Ack,
     PersonFoundWarn,
     AnimalFound,
+    InclineExceeded,
+    Stuck,
+    LowBattery,
+    ChargeInsufficient,
+    OutOfBounds,
+    Rain,
     Unknown,
 }
 
@@ -267,6 +814,12 @@ impl ChildMsg {
             14 => ChildMsg::Ack,
             15 => ChildMsg::PersonFoundWarn,
             16 => ChildMsg::AnimalFound,
+            17 => ChildMsg::InclineExceeded,
+            18 => ChildMsg::Stuck,
+            19 => ChildMsg::LowBattery,
+            20 => ChildMsg::ChargeInsufficient,
+            21 => ChildMsg::OutOfBounds,
+            22 => ChildMsg::Rain,
             _ => ChildMsg::Unknown,
         }
     }
@@ -292,9 +845,48 @@ impl ChildMsg {
             ChildMsg::Ack => 14,
             ChildMsg::PersonFoundWarn => 15,
             ChildMsg::AnimalFound => 16,
+            ChildMsg::InclineExceeded => 17,
+            ChildMsg::Stuck => 18,
+            ChildMsg::LowBattery => 19,
+            ChildMsg::ChargeInsufficient => 20,
+            ChildMsg::OutOfBounds => 21,
+            ChildMsg::Rain => 22,
             _ => 255,
         }
     }
+
+    /// Whether the message must go on air before pending state updates.
+    pub fn is_urgent(msg: u8) -> bool {
+        matches!(
+            ChildMsg::from_u8(msg),
+            ChildMsg::Halt
+                | ChildMsg::Bumped
+                | ChildMsg::PiTempHighHalt
+                | ChildMsg::InclineExceeded
+                | ChildMsg::OutOfBounds
+        )
+    }
+
+    /// Whether the message must be delivered reliably to the commander.
+    pub fn requires_ack(msg: u8) -> bool {
+        matches!(
+            ChildMsg::from_u8(msg),
+            ChildMsg::Halt
+                | ChildMsg::Bumped
+                | ChildMsg::PersonFoundPause
+                | ChildMsg::PiTempHighHalt
+                | ChildMsg::MissionComplete
+                | ChildMsg::TargetNotFound
+                | ChildMsg::PersonFoundWarn
+                | ChildMsg::AnimalFound
+                | ChildMsg::InclineExceeded
+                | ChildMsg::Stuck
+                | ChildMsg::LowBattery
+                | ChildMsg::ChargeInsufficient
+                | ChildMsg::OutOfBounds
+                | ChildMsg::Rain
+        )
+    }
 }
 
 /// Parent Message
@@ -308,6 +900,8 @@ pub enum ParentMsg {
     Backward,
     Left,
     Right,
+    Pause,
+    Resume,
     Fill,
     Oneway,
     Climb,
@@ -316,6 +910,22 @@ pub enum ParentMsg {
     MonitorAnimal,
     RoundTrip,
     FollowPerson,
+    ReturnToDock,
+    Spiral,
+    Stripe,
+    PerimeterTrim,
+    Waypoint,
+    Spot,
+    EdgeFollow,
+    AnimalDeterrent,
+    Patrol,
+    Mission,
+    Orchard,
+    TrailerDock,
+    Calibrate,
+    CameraCalibrate,
+    DatasetCapture,
+    Custom(u8), // Switch to a custom mode (100 - 199)
     Unknown,
 }
 
@@ -332,6 +942,8 @@ impl ParentMsg {
             5 => ParentMsg::Backward,
             6 => ParentMsg::Left,
             7 => ParentMsg::Right,
+            8 => ParentMsg::Pause,
+            9 => ParentMsg::Resume,
             10 => ParentMsg::Fill,
             11 => ParentMsg::Oneway,
             12 => ParentMsg::Climb,
@@ -340,7 +952,117 @@ impl ParentMsg {
             15 => ParentMsg::MonitorAnimal,
             16 => ParentMsg::RoundTrip,
             17 => ParentMsg::FollowPerson,
+            18 => ParentMsg::ReturnToDock,
+            19 => ParentMsg::Spiral,
+            20 => ParentMsg::Stripe,
+            21 => ParentMsg::PerimeterTrim,
+            22 => ParentMsg::Waypoint,
+            23 => ParentMsg::Spot,
+            24 => ParentMsg::EdgeFollow,
+            25 => ParentMsg::AnimalDeterrent,
+            26 => ParentMsg::Patrol,
+            27 => ParentMsg::Mission,
+            28 => ParentMsg::Orchard,
+            29 => ParentMsg::TrailerDock,
+            30 => ParentMsg::Calibrate,
+            31 => ParentMsg::CameraCalibrate,
+            32 => ParentMsg::DatasetCapture,
+            i if CUSTOM_MODES.contains(&i) => ParentMsg::Custom(i),
             _ => ParentMsg::Unknown,
         }
     }
+
+    /// Converts a command name or a number to a ParentMsg enum.
+    ///
+    /// Mode names are the same as in the config (e.g. "fill", "monitor_person").
+    pub fn from_string(s: &str) -> ParentMsg {
+        if let Ok(i) = s.parse::<u8>() {
+            return ParentMsg::from_u8(i);
+        }
+        match s {
+            "off" => ParentMsg::Off,
+            "on" => ParentMsg::On,
+            "reset" => ParentMsg::Reset,
+            "stop" => ParentMsg::Stop,
+            "forward" => ParentMsg::Forward,
+            "backward" => ParentMsg::Backward,
+            "left" => ParentMsg::Left,
+            "right" => ParentMsg::Right,
+            "pause" => ParentMsg::Pause,
+            "resume" => ParentMsg::Resume,
+            _ => ParentMsg::from_mode(Modes::from_string(s)),
+        }
+    }
+
+    /// Converts a ParentMsg enum to a u8 value.
+    pub fn to_u8(msg: ParentMsg) -> u8 {
+        match msg {
+            ParentMsg::Off => 0,
+            ParentMsg::On => 1,
+            ParentMsg::Reset => 2,
+            ParentMsg::Stop => 3,
+            ParentMsg::Forward => 4,
+            ParentMsg::Backward => 5,
+            ParentMsg::Left => 6,
+            ParentMsg::Right => 7,
+            ParentMsg::Pause => 8,
+            ParentMsg::Resume => 9,
+            ParentMsg::Fill => 10,
+            ParentMsg::Oneway => 11,
+            ParentMsg::Climb => 12,
+            ParentMsg::Around => 13,
+            ParentMsg::MonitorPerson => 14,
+            ParentMsg::MonitorAnimal => 15,
+            ParentMsg::RoundTrip => 16,
+            ParentMsg::FollowPerson => 17,
+            ParentMsg::ReturnToDock => 18,
+            ParentMsg::Spiral => 19,
+            ParentMsg::Stripe => 20,
+            ParentMsg::PerimeterTrim => 21,
+            ParentMsg::Waypoint => 22,
+            ParentMsg::Spot => 23,
+            ParentMsg::EdgeFollow => 24,
+            ParentMsg::AnimalDeterrent => 25,
+            ParentMsg::Patrol => 26,
+            ParentMsg::Mission => 27,
+            ParentMsg::Orchard => 28,
+            ParentMsg::TrailerDock => 29,
+            ParentMsg::Calibrate => 30,
+            ParentMsg::CameraCalibrate => 31,
+            ParentMsg::DatasetCapture => 32,
+            ParentMsg::Custom(i) => i,
+            ParentMsg::Unknown => 255,
+        }
+    }
+
+    /// Converts an operation mode to the ParentMsg switching to it.
+    pub fn from_mode(mode: Modes) -> ParentMsg {
+        match mode {
+            Modes::Fill => ParentMsg::Fill,
+            Modes::OneWay => ParentMsg::Oneway,
+            Modes::Climb => ParentMsg::Climb,
+            Modes::Around => ParentMsg::Around,
+            Modes::MonitorPerson => ParentMsg::MonitorPerson,
+            Modes::MonitorAnimal => ParentMsg::MonitorAnimal,
+            Modes::RoundTrip => ParentMsg::RoundTrip,
+            Modes::FollowPerson => ParentMsg::FollowPerson,
+            Modes::ReturnToDock => ParentMsg::ReturnToDock,
+            Modes::Spiral => ParentMsg::Spiral,
+            Modes::Stripe => ParentMsg::Stripe,
+            Modes::PerimeterTrim => ParentMsg::PerimeterTrim,
+            Modes::Waypoint => ParentMsg::Waypoint,
+            Modes::Spot => ParentMsg::Spot,
+            Modes::EdgeFollow => ParentMsg::EdgeFollow,
+            Modes::AnimalDeterrent => ParentMsg::AnimalDeterrent,
+            Modes::Patrol => ParentMsg::Patrol,
+            Modes::Mission => ParentMsg::Mission,
+            Modes::Orchard => ParentMsg::Orchard,
+            Modes::TrailerDock => ParentMsg::TrailerDock,
+            Modes::Calibrate => ParentMsg::Calibrate,
+            Modes::CameraCalibrate => ParentMsg::CameraCalibrate,
+            Modes::DatasetCapture => ParentMsg::DatasetCapture,
+            Modes::Custom(i) => ParentMsg::Custom(i),
+            Modes::Unknown => ParentMsg::Unknown,
+        }
+    }
 }
@@ -0,0 +1,162 @@
+//! Bounded Channel Module
+//!
+//! Carries neighbor events from the listeners, and the detections from the vision, to the
+//! drive loop. The channel holds at most `capacity` events; when the loop falls behind, the
+//! oldest events are dropped, as the latest state of a neighbor supersedes the previous ones,
+//! and the detections of the latest frame those of the previous frames.
+
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::mpsc::{SendError, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+/// Default number of events held by a channel.
+pub const DEFAULT_CAPACITY: usize = 64;
+
+/// State shared by both ends.
+struct Shared<T> {
+    queue: Mutex<VecDeque<T>>,
+    capacity: usize,
+    dropped: AtomicU64,
+    senders: AtomicUsize,
+    receiver_alive: AtomicBool,
+}
+
+/// Sending end of a bounded channel.
+pub struct BoundedSender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Receiving end of a bounded channel.
+pub struct BoundedReceiver<T> {
+    shared: Arc<Shared<T>>,
+}
+
+/// Creates a channel holding at most `capacity` events, dropping the oldest on overflow.
+pub fn bounded<T>(capacity: usize) -> (BoundedSender<T>, BoundedReceiver<T>) {
+    let shared = Arc::new(Shared {
+        queue: Mutex::new(VecDeque::with_capacity(capacity)),
+        capacity: capacity.max(1),
+        dropped: AtomicU64::new(0),
+        senders: AtomicUsize::new(1),
+        receiver_alive: AtomicBool::new(true),
+    });
+    (
+        BoundedSender {
+            shared: shared.clone(),
+        },
+        BoundedReceiver { shared },
+    )
+}
+
+impl<T> BoundedSender<T> {
+    /// Sends an event without blocking.
+    ///
+    /// If the channel is full, the oldest event is dropped. Returns an error with the
+    /// event if the receiver is gone.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if !self.shared.receiver_alive.load(Ordering::Acquire) {
+            return Err(SendError(value));
+        }
+        let mut queue = self.shared.queue.lock().unwrap();
+        if queue.len() >= self.shared.capacity {
+            queue.pop_front();
+            let dropped = self.shared.dropped.fetch_add(1, Ordering::Relaxed) + 1;
+            // Don't flood the log while the receiver is stuck.
+            if dropped.is_power_of_two() {
+                log::warn!("Channel Full. {} events dropped so far.", dropped);
+            }
+        }
+        queue.push_back(value);
+        Ok(())
+    }
+
+    /// Number of events dropped on overflow.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Clone for BoundedSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.senders.fetch_add(1, Ordering::AcqRel);
+        Self {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl<T> Drop for BoundedSender<T> {
+    fn drop(&mut self) {
+        self.shared.senders.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+impl<T> BoundedReceiver<T> {
+    /// Takes the oldest event without blocking.
+    ///
+    /// Returns `Disconnected` once the channel is empty and all senders are gone.
+    pub fn try_recv(&self) -> Result<T, TryRecvError> {
+        match self.shared.queue.lock().unwrap().pop_front() {
+            Some(value) => Ok(value),
+            None if self.shared.senders.load(Ordering::Acquire) == 0 => {
+                Err(TryRecvError::Disconnected)
+            }
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    /// Number of events waiting.
+    pub fn len(&self) -> usize {
+        self.shared.queue.lock().unwrap().len()
+    }
+
+    /// Whether no event is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of events dropped on overflow.
+    pub fn dropped(&self) -> u64 {
+        self.shared.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Drop for BoundedReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.receiver_alive.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_oldest_test() {
+        let (tx, rx) = bounded(2);
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Empty));
+        for i in 0..5 {
+            assert!(tx.send(i).is_ok());
+        }
+        // Only the latest ones are kept.
+        assert_eq!(rx.len(), 2);
+        assert_eq!(tx.dropped(), 3);
+        assert_eq!(rx.try_recv(), Ok(3));
+        assert_eq!(rx.try_recv(), Ok(4));
+        // Disconnected once all senders are gone
+        let tx2 = tx.clone();
+        drop(tx);
+        assert!(tx2.send(5).is_ok());
+        drop(tx2);
+        assert_eq!(rx.try_recv(), Ok(5));
+        assert_eq!(rx.try_recv(), Err(TryRecvError::Disconnected));
+    }
+
+    #[test]
+    fn receiver_gone_test() {
+        let (tx, rx) = bounded(4);
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError(1)));
+    }
+}
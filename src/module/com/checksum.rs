@@ -0,0 +1,113 @@
+//! Checksum Module
+//!
+//! Protects plain payloads with a CRC-8, so that corrupted frames are discarded
+//! instead of being mis-parsed.
+//!
+//! The CRC is stored right after the payload fields and covers the identifier,
+//! the payload fields, the version and the telemetry. Padding is not covered.
+//! Before version 1 the version byte was padding (0), so it's covered only when set,
+//! and the payloads of the older senders still validate.
+
+use super::secure::FIELDS_LEN;
+use super::PAYLOAD_LEN;
+
+/// Position of the CRC in the payload.
+pub const CRC_INDEX: usize = FIELDS_LEN;
+
+/// Minimum length of a valid payload (fields and CRC).
+pub const MIN_PAYLOAD_LEN: usize = CRC_INDEX + 1;
+
+/// Position of the version (see `codec`).
+pub const VERSION_INDEX: usize = CRC_INDEX + 1;
+
+/// Calculates CRC-8 (polynomial 0x07, initial value 0x00).
+pub fn crc8(bytes: &[u8]) -> u8 {
+    let mut crc: u8 = 0;
+    for byte in bytes {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// Calculates the CRC of the payload of the robot with the identifier.
+fn payload_crc(identifier: u8, payload: &[u8]) -> u8 {
+    let mut covered = vec![identifier];
+    covered.extend_from_slice(&payload[..FIELDS_LEN]);
+    covered.extend(payload.get(VERSION_INDEX).filter(|version| **version != 0));
+    covered.extend_from_slice(payload.get(PAYLOAD_LEN..).unwrap_or(&[]));
+    crc8(&covered)
+}
+
+/// Writes the CRC into the payload.
+pub fn set_crc(identifier: u8, payload: &mut [u8]) {
+    if payload.len() >= MIN_PAYLOAD_LEN {
+        payload[CRC_INDEX] = payload_crc(identifier, payload);
+    }
+}
+
+/// Validates the length and the CRC of the payload.
+pub fn validate(identifier: u8, payload: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+    if payload.len() < MIN_PAYLOAD_LEN {
+        return Err(format!("Payload too short. len: {}", payload.len()).into());
+    }
+    if payload[CRC_INDEX] != payload_crc(identifier, payload) {
+        return Err("Payload checksum mismatch.".into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crc8_test() {
+        // CRC-8/SMBUS check value
+        assert_eq!(crc8(b"123456789"), 0xF4);
+        assert_eq!(crc8(&[]), 0x00);
+    }
+
+    #[test]
+    fn validate_test() {
+        let mut payload = vec![100, 45, 0, 255, 255, 0, 0, 0, 1];
+        payload.resize(PAYLOAD_LEN, 0);
+        payload.extend_from_slice(&[0x02, 0x01, 42]);
+        set_crc(12, &mut payload);
+        assert!(validate(12, &payload).is_ok());
+        // Another identifier
+        assert!(validate(13, &payload).is_err());
+        // Corrupted field
+        let mut corrupted = payload.clone();
+        corrupted[1] ^= 0x10;
+        assert!(validate(12, &corrupted).is_err());
+        // Corrupted version
+        let mut corrupted = payload.clone();
+        corrupted[VERSION_INDEX] = 2;
+        assert!(validate(12, &corrupted).is_err());
+        let mut corrupted = payload.clone();
+        corrupted[VERSION_INDEX] = 0;
+        assert!(validate(12, &corrupted).is_err());
+        // Sender before version 1
+        let mut legacy = payload.clone();
+        legacy[VERSION_INDEX] = 0;
+        set_crc(12, &mut legacy);
+        assert!(validate(12, &legacy).is_ok());
+        // Corrupted telemetry
+        let mut corrupted = payload.clone();
+        corrupted[PAYLOAD_LEN + 2] = 43;
+        assert!(validate(12, &corrupted).is_err());
+        // Padding is not covered.
+        let mut padded = payload.clone();
+        padded[PAYLOAD_LEN - 1] = 1;
+        assert!(validate(12, &padded).is_ok());
+        // Truncated
+        assert!(validate(12, &payload[..CRC_INDEX]).is_err());
+    }
+}
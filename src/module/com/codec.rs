@@ -0,0 +1,227 @@
+//! Wire Codec Module
+//!
+//! Single place defining the layout of the state payload, so that the format can evolve safely.
+//!
+//! # State Payload (version 1)
+//! | Index | Field                                   |
+//! |-------|-----------------------------------------|
+//! | 0     | state (bit 7) and rest in % (bit 0-6)   |
+//! | 1     | Pi temperature                          |
+//! | 2     | mode                                    |
+//! | 3     | msg (`ChildMsg` / `ParentMsg`)          |
+//! | 4     | destination                             |
+//! | 5     | sequence number                         |
+//! | 6     | acknowledged sequence number            |
+//! | 7     | CRC-8 (see `checksum`)                  |
+//! | 8     | version (0 for senders before version 1)|
+//! | 9-22  | padding                                 |
+//! | 23-   | telemetry (extended transports only)    |
+//!
+//! Sealed frames carry the fields and the telemetry only, with the version in the header
+//! (see `secure`).
+//!
+//! # Compatibility
+//! Newer versions may only use the padding and the telemetry for new fields, so a
+//! receiver decodes the fields it knows from any version and ignores the rest.
+//! `VersionMonitor` reports senders whose version differs, so that the operator can
+//! update the fleet before relying on newer fields.
+
+use super::checksum::{CRC_INDEX, VERSION_INDEX};
+use super::PAYLOAD_LEN;
+use crate::module::pilot::Modes;
+use std::collections::HashMap;
+
+/// Current version of the wire format.
+pub const WIRE_VERSION: u8 = 1;
+
+/// State fields carried by a payload.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateFrame {
+    pub state: bool,
+    pub rest: u8,
+    pub pi_temp: u8,
+    pub mode: Modes,
+    pub msg: u8,
+    pub dest: u8,
+    pub seq: u8,
+    pub ack: u8,
+    pub version: u8,
+}
+
+impl StateFrame {
+    /// Encodes the fields into a legacy-sized payload. The CRC is left for `checksum::set_crc`.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut payload = vec![
+            pack_state_rest(self.state, self.rest),
+            self.pi_temp,
+            Modes::to_u8(self.mode),
+            self.msg,
+            self.dest,
+            self.seq,
+            self.ack,
+            0, // CRC
+            self.version,
+        ];
+        payload.resize(PAYLOAD_LEN, 0);
+        payload
+    }
+
+    /// Decodes the fields from a payload.
+    ///
+    /// Payloads from a newer version are decoded as the subset known to this version.
+    /// Returns an error if the payload is too short.
+    pub fn decode(payload: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        if payload.len() < CRC_INDEX {
+            return Err(format!("Payload too short. len: {}", payload.len()).into());
+        }
+        let version = payload.get(VERSION_INDEX).copied().unwrap_or(0);
+        let (state, rest) = unpack_state_rest(payload[0]);
+        Ok(Self {
+            state,
+            rest,
+            pi_temp: payload[1],
+            mode: Modes::from_u8(payload[2]),
+            msg: payload[3],
+            dest: payload[4],
+            seq: payload[5],
+            ack: payload[6],
+            version,
+        })
+    }
+}
+
+/// Compatibility of a sender's wire version with this robot.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compatibility {
+    Same,
+    /// The sender doesn't know the newer fields of this robot.
+    Older(u8),
+    /// Only the subset known to this robot is decoded.
+    Newer(u8),
+}
+
+impl Compatibility {
+    /// Compares the version of a sender with `WIRE_VERSION`.
+    pub fn of(version: u8) -> Self {
+        match version.cmp(&WIRE_VERSION) {
+            std::cmp::Ordering::Equal => Self::Same,
+            std::cmp::Ordering::Less => Self::Older(version),
+            std::cmp::Ordering::Greater => Self::Newer(version),
+        }
+    }
+}
+
+/// Tracks the wire version of each neighbor.
+#[derive(Default)]
+pub struct VersionMonitor {
+    versions: HashMap<u8, u8>,
+}
+
+impl VersionMonitor {
+    /// Creates an empty monitor.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records the version of a sender.
+    ///
+    /// Returns the mismatch the first time it is seen, so that it is reported once per sender.
+    pub fn check(&mut self, identifier: u8, version: u8) -> Option<Compatibility> {
+        if self.versions.insert(identifier, version) == Some(version) {
+            return None;
+        }
+        match Compatibility::of(version) {
+            Compatibility::Same => None,
+            mismatch => Some(mismatch),
+        }
+    }
+}
+
+/// Packs the state and the rest (0-100 %) into one byte.
+pub fn pack_state_rest(state: bool, rest: u8) -> u8 {
+    ((state as u8) << 7) | (rest.min(100) & 0x7F)
+}
+
+/// Unpacks the state and the rest from one byte.
+pub fn unpack_state_rest(byte: u8) -> (bool, u8) {
+    (byte & 0x80 != 0, byte & 0x7F)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::{ChildMsg, ParentMsg};
+
+    #[test]
+    fn state_frame_round_trip_test() {
+        let frame = StateFrame {
+            state: true,
+            rest: 5,
+            pi_temp: 61,
+            mode: Modes::MonitorPerson,
+            msg: ChildMsg::to_u8(ChildMsg::PersonFoundWarn),
+            dest: 255,
+            seq: 3,
+            ack: 9,
+            version: WIRE_VERSION,
+        };
+        let payload = frame.encode();
+        assert_eq!(payload.len(), PAYLOAD_LEN);
+        assert_eq!(payload[0], 0x85);
+        assert_eq!(payload[VERSION_INDEX], WIRE_VERSION);
+        assert_eq!(StateFrame::decode(&payload).unwrap(), frame);
+        // Senders before versioning
+        let mut legacy = payload.clone();
+        legacy[VERSION_INDEX] = 0;
+        let decoded = StateFrame::decode(&legacy).unwrap();
+        assert_eq!(decoded.version, 0);
+        assert_eq!(decoded.seq, frame.seq);
+        // Newer versions decode as the known subset.
+        let mut newer = payload.clone();
+        newer[VERSION_INDEX] = WIRE_VERSION + 1;
+        newer[VERSION_INDEX + 1] = 0xAB;
+        let decoded = StateFrame::decode(&newer).unwrap();
+        assert_eq!(decoded.version, WIRE_VERSION + 1);
+        assert_eq!(decoded.mode, frame.mode);
+        assert!(StateFrame::decode(&payload[..3]).is_err());
+    }
+
+    #[test]
+    fn version_monitor_test() {
+        let mut monitor = VersionMonitor::new();
+        assert_eq!(monitor.check(1, WIRE_VERSION), None);
+        assert_eq!(monitor.check(2, 0), Some(Compatibility::Older(0)));
+        // Reported once per sender
+        assert_eq!(monitor.check(2, 0), None);
+        assert_eq!(
+            monitor.check(1, WIRE_VERSION + 1),
+            Some(Compatibility::Newer(WIRE_VERSION + 1))
+        );
+        // Updated
+        assert_eq!(monitor.check(2, WIRE_VERSION), None);
+        assert_eq!(monitor.check(2, 0), Some(Compatibility::Older(0)));
+    }
+
+    #[test]
+    fn state_rest_test() {
+        assert_eq!(pack_state_rest(false, 100), 100);
+        assert_eq!(pack_state_rest(true, 100), 228);
+        assert_eq!(unpack_state_rest(228), (true, 100));
+        assert_eq!(unpack_state_rest(pack_state_rest(true, 1)), (true, 1));
+        assert_eq!(unpack_state_rest(pack_state_rest(false, 0)), (false, 0));
+    }
+
+    #[test]
+    fn msg_round_trip_test() {
+        for i in 0..=255u8 {
+            match ParentMsg::from_u8(i) {
+                ParentMsg::Unknown => {}
+                msg => assert_eq!(ParentMsg::to_u8(msg), i),
+            }
+            match ChildMsg::from_u8(i) {
+                ChildMsg::Unknown => {}
+                msg => assert_eq!(ChildMsg::to_u8(msg), i),
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+//! ESP-NOW Serial Bridge Transport Module
+//!
+//! Exchanges states and commands through an ESP32 running ESP-NOW, connected via UART.
+//! The ESP32 broadcasts what it receives on the UART and forwards what it hears back.
+//!
+//! # Serial Frames
+//! * To the ESP32: `| 0x7E | len | data (len) | checksum |`
+//! * From the ESP32: `| 0x7E | len | mac (6) | rssi (1) | data (len - 7) | checksum |`
+//!
+//! `data` is `| identifier | payload (sealed if a key is set) |`, and the checksum is
+//! the XOR of `len` and all following bytes.
+
+use super::channel::BoundedSender;
+use super::secure::{PayloadCipher, ReplayGuard};
+use super::transport::{Capabilities, CommTransport};
+use super::{cipher_from_property, fresh, replay_state_path, Neighbor, REPLAY_SAVE_INTERVAL};
+use crate::module::util::init::RoktrackProperty;
+use rppal::uart::{Parity, Uart};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Start of a serial frame.
+const FRAME_START: u8 = 0x7E;
+
+/// Maximum payload of an ESP-NOW packet.
+pub const MAX_ESPNOW_LEN: usize = 250;
+
+// Length of the sender information prepended by the ESP32.
+const SENDER_LEN: usize = 7;
+
+// Timeout of a UART read, so that the lock is released for sending.
+const READ_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// ESP-NOW Transport Handler
+pub struct EspNowTransport {
+    uart: Arc<Mutex<Uart>>,
+    cipher: Option<Arc<PayloadCipher>>,
+    replay_state: PathBuf,
+}
+
+impl EspNowTransport {
+    /// Creates a new instance of ESP-NOW Transport Handler with the given property.
+    pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+        let conf = &property.conf.espnow;
+        let mut uart = Uart::with_path(&conf.port, conf.baud_rate, Parity::None, 8, 1)?;
+        uart.set_read_mode(0, READ_TIMEOUT)?;
+        Ok(Self {
+            uart: Arc::new(Mutex::new(uart)),
+            cipher: cipher_from_property(&property)?,
+            replay_state: replay_state_path(&property, "espnow"),
+        })
+    }
+}
+
+impl CommTransport for EspNowTransport {
+    /// Hands the payload over to the ESP32 for broadcasting.
+    fn send(&self, identifier: &u8, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = vec![*identifier];
+        match &self.cipher {
+            Some(cipher) => data.extend(cipher.seal(*identifier, &payload)?),
+            None => data.extend(payload),
+        }
+        data.truncate(MAX_ESPNOW_LEN);
+        self.uart.lock().unwrap().write(&encode_frame(&data))?;
+        Ok(())
+    }
+
+    /// Reads packets forwarded by the ESP32.
+    fn listen(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let uart = self.uart.clone();
+        let cipher = self.cipher.clone();
+        let replay_state = self.replay_state.clone();
+        thread::spawn(move || {
+            log::debug!("ESP-NOW Thread Started");
+            // Restore the counters seen before the last shutdown.
+            let mut guard = ReplayGuard::load(&replay_state);
+            let mut last_save = Instant::now();
+            let mut decoder = FrameDecoder::new();
+            let mut buf = [0u8; 64];
+            loop {
+                let len = match uart.lock().unwrap().read(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        log::error!("ESP-NOW Read Failed: {}", e);
+                        break;
+                    }
+                };
+                if len == 0 {
+                    // Let the sender take the lock.
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                for frame in decoder.push(&buf[..len]) {
+                    if frame.len() <= SENDER_LEN {
+                        continue;
+                    }
+                    let mac: Vec<String> =
+                        frame[..6].iter().map(|b| format!("{:02X}", b)).collect();
+                    let mac = mac.join(":");
+                    let rssi = frame[6] as i8;
+                    let mut neighbor =
+                        match Neighbor::from_frame(&frame[SENDER_LEN..], cipher.as_deref()) {
+                            Ok(neighbor) => neighbor,
+                            Err(e) => {
+                                log::debug!("ESP-NOW Packet Rejected From: {}, {}", mac, e);
+                                continue;
+                            }
+                        };
+                    if !fresh(&mut guard, &neighbor) {
+                        log::warn!("ESP-NOW Replay Dropped From: {}", mac);
+                        continue;
+                    }
+                    if last_save.elapsed() > REPLAY_SAVE_INTERVAL {
+                        let _ = guard.save(&replay_state);
+                        last_save = Instant::now();
+                    }
+                    neighbor.mac = mac;
+                    neighbor.rssi = rssi;
+                    if tx.send(neighbor).is_err() {
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Features of ESP-NOW.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            name: "espnow",
+            max_payload: MAX_ESPNOW_LEN - 1,
+            bidirectional: true,
+            range_m: 200,
+        }
+    }
+}
+
+/// Wraps data into a serial frame.
+pub(super) fn encode_frame(data: &[u8]) -> Vec<u8> {
+    let mut frame = vec![FRAME_START, data.len() as u8];
+    frame.extend_from_slice(data);
+    frame.push(checksum(&frame[1..]));
+    frame
+}
+
+/// XOR of all bytes.
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |acc, b| acc ^ b)
+}
+
+/// Reassembles serial frames from a byte stream.
+pub(super) struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    /// Creates an empty decoder.
+    pub(super) fn new() -> Self {
+        Self { buf: vec![] }
+    }
+
+    /// Feeds received bytes and returns the data of complete, valid frames.
+    pub(super) fn push(&mut self, bytes: &[u8]) -> Vec<Vec<u8>> {
+        self.buf.extend_from_slice(bytes);
+        let mut frames = vec![];
+        loop {
+            // Skip to the start of a frame.
+            match self.buf.iter().position(|b| *b == FRAME_START) {
+                Some(start) => {
+                    self.buf.drain(..start);
+                }
+                None => {
+                    self.buf.clear();
+                    break;
+                }
+            }
+            if self.buf.len() < 2 {
+                break;
+            }
+            let len = self.buf[1] as usize;
+            if self.buf.len() < len + 3 {
+                break;
+            }
+            let frame: Vec<u8> = self.buf.drain(..len + 3).collect();
+            if checksum(&frame[1..len + 2]) == frame[len + 2] {
+                frames.push(frame[2..len + 2].to_vec());
+            } else {
+                log::debug!("ESP-NOW Frame Checksum Error");
+            }
+        }
+        frames
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_test() {
+        let frame = encode_frame(&[1, 2, 3]);
+        assert_eq!(frame, vec![0x7E, 3, 1, 2, 3, 3 ^ 1 ^ 2 ^ 3]);
+        // Split, with garbage before and a corrupted frame after.
+        let mut decoder = FrameDecoder::new();
+        assert!(decoder.push(&[0x00, 0x11, 0x7E, 3, 1]).is_empty());
+        let mut corrupted = encode_frame(&[9]);
+        corrupted[2] = 8;
+        let mut rest = vec![2, 3, 3 ^ 1 ^ 2 ^ 3];
+        rest.extend(corrupted);
+        rest.extend(encode_frame(&[4, 5]));
+        assert_eq!(decoder.push(&rest), vec![vec![1, 2, 3], vec![4, 5]]);
+    }
+}
@@ -0,0 +1,209 @@
+//! Fragmentation Module
+//!
+//! Splits messages larger than one frame (e.g. waypoint lists, config blobs) into
+//! fragments, and reassembles them on the receiver.
+//!
+//! # Fragment Payload
+//! | 0xFF | msg_id | index | count | 0 | 0 | 0 | crc | padding | data |
+//!
+//! The header takes the place of the payload fields and the data follows the legacy
+//! payload like telemetry, so the checksum and the encryption apply unchanged.
+//! 0xFF never appears as the first byte of a state payload (rest is at most 100).
+//! As the data follows the legacy payload, a legacy BLE advertisement can't carry fragments.
+
+use super::PAYLOAD_LEN;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// First byte of a fragment payload.
+pub const FRAGMENT_MARKER: u8 = 0xFF;
+
+/// Default time to wait for the missing fragments of a message.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Id of the message carrying the waypoints file.
+pub const WAYPOINTS_MSG_ID: u8 = 1;
+
+/// Message reassembled from the fragments of a neighbor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub identifier: u8, // Sender
+    pub msg_id: u8,
+    pub data: Vec<u8>,
+}
+
+/// Fragment of a message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Fragment {
+    pub msg_id: u8,
+    pub index: u8,
+    pub count: u8,
+    pub data: Vec<u8>,
+}
+
+impl Fragment {
+    /// Parses a fragment payload, or returns `None` for other payloads.
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        if payload.len() < PAYLOAD_LEN || payload[0] != FRAGMENT_MARKER {
+            return None;
+        }
+        Some(Self {
+            msg_id: payload[1],
+            index: payload[2],
+            count: payload[3],
+            data: payload[PAYLOAD_LEN..].to_vec(),
+        })
+    }
+
+    /// Builds the fragment payload. The CRC is left for `checksum::set_crc`.
+    pub fn to_payload(&self) -> Vec<u8> {
+        let mut payload = vec![FRAGMENT_MARKER, self.msg_id, self.index, self.count];
+        payload.resize(PAYLOAD_LEN, 0);
+        payload.extend_from_slice(&self.data);
+        payload
+    }
+}
+
+/// Whether the payload is a fragment.
+pub fn is_fragment(payload: &[u8]) -> bool {
+    payload.first() == Some(&FRAGMENT_MARKER)
+}
+
+/// Splits a message into fragment payloads fitting in `max_payload` bytes.
+pub fn split(
+    msg_id: u8,
+    data: &[u8],
+    max_payload: usize,
+) -> Result<Vec<Vec<u8>>, Box<dyn std::error::Error>> {
+    let chunk = max_payload.saturating_sub(PAYLOAD_LEN);
+    if chunk == 0 {
+        return Err("The transport can't carry fragments.".into());
+    }
+    let chunks: Vec<&[u8]> = if data.is_empty() {
+        vec![&[]]
+    } else {
+        data.chunks(chunk).collect()
+    };
+    if chunks.len() > u8::MAX as usize {
+        return Err(format!("Message too long. len: {}", data.len()).into());
+    }
+    let count = chunks.len() as u8;
+    Ok(chunks
+        .into_iter()
+        .enumerate()
+        .map(|(index, data)| {
+            Fragment {
+                msg_id,
+                index: index as u8,
+                count,
+                data: data.to_vec(),
+            }
+            .to_payload()
+        })
+        .collect())
+}
+
+/// Message being reassembled.
+struct Partial {
+    parts: Vec<Option<Vec<u8>>>,
+    started: Instant,
+}
+
+/// Reassembles messages from fragments per sender.
+pub struct Reassembler {
+    partials: HashMap<(String, u8), Partial>,
+    pub timeout: Duration,
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Reassembler {
+    /// Creates an empty reassembler with the default timeout.
+    pub fn new() -> Self {
+        Self {
+            partials: HashMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Adds a fragment from the sender and returns the message once it's complete.
+    ///
+    /// Repeated fragments are ignored, and incomplete messages are dropped after the timeout.
+    pub fn push(&mut self, sender: &str, fragment: Fragment, now: Instant) -> Option<Vec<u8>> {
+        let timeout = self.timeout;
+        self.partials
+            .retain(|_, p| now.saturating_duration_since(p.started) <= timeout);
+        if fragment.count == 0 || fragment.index >= fragment.count {
+            return None;
+        }
+        let key = (sender.to_string(), fragment.msg_id);
+        let partial = self.partials.entry(key.clone()).or_insert_with(|| Partial {
+            parts: vec![None; fragment.count as usize],
+            started: now,
+        });
+        // A new message reusing the id
+        if partial.parts.len() != fragment.count as usize {
+            *partial = Partial {
+                parts: vec![None; fragment.count as usize],
+                started: now,
+            };
+        }
+        partial.parts[fragment.index as usize] = Some(fragment.data);
+        if partial.parts.iter().all(|p| p.is_some()) {
+            let partial = self.partials.remove(&key)?;
+            Some(partial.parts.into_iter().flatten().flatten().collect())
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_reassemble_test() {
+        let data: Vec<u8> = (0..=255).collect();
+        let payloads = split(7, &data, PAYLOAD_LEN + 100).unwrap();
+        assert_eq!(payloads.len(), 3);
+        assert!(payloads.iter().all(|p| is_fragment(p)));
+        let mut reassembler = Reassembler::new();
+        let now = Instant::now();
+        // Out of order and repeated
+        for i in [2, 0, 2] {
+            let fragment = Fragment::from_payload(&payloads[i]).unwrap();
+            assert_eq!(reassembler.push("AA", fragment, now), None);
+        }
+        let fragment = Fragment::from_payload(&payloads[1]).unwrap();
+        assert_eq!(reassembler.push("AA", fragment, now), Some(data));
+        // Too small transport
+        assert!(split(7, &[1], PAYLOAD_LEN).is_err());
+    }
+
+    #[test]
+    fn timeout_test() {
+        let payloads = split(1, &[1, 2, 3, 4], PAYLOAD_LEN + 2).unwrap();
+        let mut reassembler = Reassembler::new();
+        reassembler.timeout = Duration::from_secs(1);
+        let now = Instant::now();
+        let first = Fragment::from_payload(&payloads[0]).unwrap();
+        let second = Fragment::from_payload(&payloads[1]).unwrap();
+        assert_eq!(reassembler.push("AA", first.clone(), now), None);
+        // The first fragment has expired.
+        assert_eq!(
+            reassembler.push("AA", second.clone(), now + Duration::from_secs(2)),
+            None
+        );
+        assert_eq!(
+            reassembler.push("AA", first, now + Duration::from_secs(2)),
+            Some(vec![1, 2, 3, 4])
+        );
+        // State payloads are not fragments.
+        assert_eq!(Fragment::from_payload(&[100; PAYLOAD_LEN]), None);
+    }
+}
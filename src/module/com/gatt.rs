@@ -0,0 +1,245 @@
+//! BLE GATT Server Module
+//!
+//! This module exposes Roktrack as a BLE peripheral so that a phone app can connect
+//! directly to select the mode, start/stop the robot and read out its state.
+//!
+//! # Characteristics
+//! * Mode (write) - One byte of `Modes`.
+//! * Control (write) - One byte of `ParentMsg` (0: Off, 1: On, 2: Reset, ...).
+//! * State (read/notify) - The same payload that is broadcast to neighbors.
+//!
+//! # Security
+//! Mode and Control start the blades, so they accept writes only over a link encrypted with a
+//! device paired with a passkey. The passkey is shown in the log while pairing. Writes without
+//! response are not supported, since their rejections go unnoticed by the phone.
+
+use super::channel::BoundedSender;
+use super::{Neighbor, ParentMsg};
+use crate::module::define;
+use crate::module::pilot::Modes;
+use bluer::{
+    adv::Advertisement,
+    agent::Agent,
+    gatt::local::{
+        Application, Characteristic, CharacteristicNotify, CharacteristicNotifyMethod,
+        CharacteristicRead, CharacteristicWrite, CharacteristicWriteMethod, ReqError, Service,
+    },
+    Uuid,
+};
+use futures::FutureExt;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// Roktrack Service UUID
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x8d5a0001_4b4a_4c7e_9a3b_726f6b747261);
+/// Mode Characteristic UUID
+pub const MODE_UUID: Uuid = Uuid::from_u128(0x8d5a0002_4b4a_4c7e_9a3b_726f6b747261);
+/// Control Characteristic UUID
+pub const CONTROL_UUID: Uuid = Uuid::from_u128(0x8d5a0003_4b4a_4c7e_9a3b_726f6b747261);
+/// State Characteristic UUID
+pub const STATE_UUID: Uuid = Uuid::from_u128(0x8d5a0004_4b4a_4c7e_9a3b_726f6b747261);
+
+// Interval between state notifications.
+const NOTIFY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// GATT Server Handler
+pub struct GattServer {
+    state: Arc<Mutex<Vec<u8>>>,
+    dev_id: Option<u16>,
+}
+
+impl GattServer {
+    /// Creates a new instance of GATT Server Handler on the adapter `hci<dev_id>`,
+    /// or on the default adapter if `None`.
+    pub fn new(dev_id: Option<u16>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(vec![])),
+            dev_id,
+        }
+    }
+
+    /// Updates the state exposed by the State characteristic.
+    pub fn update(&self, payload: Vec<u8>) {
+        *self.state.lock().unwrap() = payload;
+    }
+
+    /// Serves the GATT application and sends received commands via a channel.
+    ///
+    /// Commands are delivered as a `Neighbor` from the commander (identifier 0),
+    /// so they are handled in the same way as broadcast commands.
+    pub fn serve(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let local_state = self.state.clone();
+        let dev_id = self.dev_id;
+        thread::spawn(move || {
+            log::debug!("GATT Thread Started");
+            // Create an asynchronous runtime.
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            // Run the server until an error occurs.
+            rt.block_on(async {
+                if let Err(e) =
+                    Self::run_server(dev_id, local_state, Arc::new(Mutex::new(tx))).await
+                {
+                    log::error!("GATT Server Stopped: {}", e);
+                }
+            });
+        })
+    }
+
+    /// Registers the advertisement and the GATT application on the adapter.
+    async fn run_server(
+        dev_id: Option<u16>,
+        state: Arc<Mutex<Vec<u8>>>,
+        tx: Arc<Mutex<BoundedSender<Neighbor>>>,
+    ) -> bluer::Result<()> {
+        let session = bluer::Session::new().await?;
+        let adapter = match dev_id {
+            Some(dev_id) => session.adapter(&format!("hci{}", dev_id))?,
+            None => session.default_adapter().await?,
+        };
+        adapter.set_powered(true).await?;
+
+        // Pair with the passkey shown in the log, so that nobody nearby writes the commands unseen.
+        let agent = Agent {
+            request_default: true,
+            display_passkey: Some(Box::new(|req| {
+                async move {
+                    log::info!(
+                        "GATT Pairing Passkey for {}: {:06}",
+                        req.device,
+                        req.passkey
+                    );
+                    Ok(())
+                }
+                .boxed()
+            })),
+            ..Default::default()
+        };
+        let _agent_handle = session.register_agent(agent).await?;
+
+        // Advertise the service so that the phone can find us.
+        let advertisement = Advertisement {
+            service_uuids: vec![SERVICE_UUID].into_iter().collect(),
+            discoverable: Some(true),
+            local_name: Some(define::system::NAME.to_string()),
+            ..Default::default()
+        };
+        let _adv_handle = adapter.advertise(advertisement).await?;
+
+        let mode_tx = tx.clone();
+        let control_tx = tx;
+        let read_state = state.clone();
+        let notify_state = state;
+
+        let app = Application {
+            services: vec![Service {
+                uuid: SERVICE_UUID,
+                primary: true,
+                characteristics: vec![
+                    // Mode selection
+                    Characteristic {
+                        uuid: MODE_UUID,
+                        write: Some(CharacteristicWrite {
+                            write: true,
+                            encrypt_authenticated_write: true,
+                            method: CharacteristicWriteMethod::Fun(Box::new(move |value, _req| {
+                                let tx = mode_tx.clone();
+                                async move {
+                                    let mode = Modes::from_u8(
+                                        *value.first().ok_or(ReqError::InvalidValueLength)?,
+                                    );
+                                    if mode == Modes::Unknown {
+                                        return Err(ReqError::NotSupported);
+                                    }
+                                    log::debug!("GATT Mode Received: {:?}", mode);
+                                    send_command(&tx, ParentMsg::from_mode(mode))
+                                }
+                                .boxed()
+                            })),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                    // Start/Stop/Reset
+                    Characteristic {
+                        uuid: CONTROL_UUID,
+                        write: Some(CharacteristicWrite {
+                            write: true,
+                            encrypt_authenticated_write: true,
+                            method: CharacteristicWriteMethod::Fun(Box::new(move |value, _req| {
+                                let tx = control_tx.clone();
+                                async move {
+                                    let msg = ParentMsg::from_u8(
+                                        *value.first().ok_or(ReqError::InvalidValueLength)?,
+                                    );
+                                    if msg == ParentMsg::Unknown {
+                                        return Err(ReqError::NotSupported);
+                                    }
+                                    send_command(&tx, msg)
+                                }
+                                .boxed()
+                            })),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                    // State readout
+                    Characteristic {
+                        uuid: STATE_UUID,
+                        read: Some(CharacteristicRead {
+                            read: true,
+                            fun: Box::new(move |_req| {
+                                let value = read_state.lock().unwrap().clone();
+                                async move { Ok(value) }.boxed()
+                            }),
+                            ..Default::default()
+                        }),
+                        notify: Some(CharacteristicNotify {
+                            notify: true,
+                            method: CharacteristicNotifyMethod::Fun(Box::new(
+                                move |mut notifier| {
+                                    let state = notify_state.clone();
+                                    async move {
+                                        tokio::spawn(async move {
+                                            loop {
+                                                let value = state.lock().unwrap().clone();
+                                                if notifier.notify(value).await.is_err() {
+                                                    log::debug!("GATT Notification Stopped");
+                                                    break;
+                                                }
+                                                tokio::time::sleep(NOTIFY_INTERVAL).await;
+                                            }
+                                        });
+                                    }
+                                    .boxed()
+                                },
+                            )),
+                            ..Default::default()
+                        }),
+                        ..Default::default()
+                    },
+                ],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let _app_handle = adapter.serve_gatt_application(app).await?;
+        log::info!("GATT Server Started on {}", adapter.name());
+
+        // Keep the handles alive.
+        std::future::pending::<()>().await;
+        Ok(())
+    }
+}
+
+/// Forwards a command from the phone to the drive thread.
+fn send_command(tx: &Mutex<BoundedSender<Neighbor>>, msg: ParentMsg) -> Result<(), ReqError> {
+    tx.lock()
+        .unwrap()
+        .send(Neighbor::from_parent_msg(msg))
+        .map_err(|_| ReqError::Failed)
+}
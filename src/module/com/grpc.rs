@@ -0,0 +1,279 @@
+//! gRPC Remote Control Module
+//!
+//! Exposes a typed remote API (see `proto/roktrack.proto`) for integrators, so that
+//! they don't have to speak the advertisement format. Commands are delivered to the
+//! drive thread as a `Neighbor` from the commander, like commands from the phone app.
+//!
+//! # Security
+//! Only the commands stopping the robot (Stop, Off and Pause) are accepted from anyone. The
+//! others start the blades or change what the robot does, so they need the token of the config
+//! in the `authorization` metadata (`Bearer <token>`), and are refused while it's empty. There's
+//! no TLS, so the server listens on the loopback by default, and the token is sent in the clear
+//! when it's exposed to the LAN.
+
+use super::channel::BoundedSender;
+use super::{Neighbor, ParentMsg};
+use crate::module::pilot::{Modes, RoktrackState};
+use crate::module::util::conf::Grpc;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use tonic::metadata::MetadataMap;
+use tonic::{Request, Response, Status};
+
+/// Generated messages and service.
+pub mod proto {
+    tonic::include_proto!("roktrack");
+}
+
+use proto::remote_server::{Remote, RemoteServer};
+use proto::{CommandReply, CommandRequest, Empty, NeighborList, SetModeRequest};
+
+/// gRPC Server Handler
+pub struct GrpcServer {
+    addr: SocketAddr,
+    token: String,
+    state: Arc<Mutex<proto::State>>,
+    neighbors: Arc<Mutex<Vec<proto::Neighbor>>>,
+}
+
+impl GrpcServer {
+    /// Creates a new instance of gRPC Server Handler with the given config.
+    pub fn new(conf: Grpc) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            addr: format!("{}:{}", conf.bind, conf.port).parse()?,
+            token: conf.token,
+            state: Arc::new(Mutex::new(proto::State::default())),
+            neighbors: Arc::new(Mutex::new(vec![])),
+        })
+    }
+
+    /// Updates the state returned by GetState.
+    pub fn update_state(&self, state: &RoktrackState) {
+        *self.state.lock().unwrap() = to_state(state);
+    }
+
+    /// Updates the neighbors returned by ListNeighbors.
+    pub fn update_neighbors(&self, neighbors: &HashMap<u8, Neighbor>) {
+        let mut list: Vec<proto::Neighbor> = neighbors.values().map(to_neighbor).collect();
+        list.sort_by_key(|n| n.identifier);
+        *self.neighbors.lock().unwrap() = list;
+    }
+
+    /// Serves the remote API and sends received commands via a channel.
+    pub fn serve(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let addr = self.addr;
+        let service = RemoteService {
+            state: self.state.clone(),
+            neighbors: self.neighbors.clone(),
+            token: self.token.clone(),
+            tx,
+        };
+        thread::spawn(move || {
+            log::debug!("gRPC Thread Started");
+            // Create an asynchronous runtime.
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            // Run the server until an error occurs.
+            rt.block_on(async {
+                log::info!("gRPC Server Listening on {}", addr);
+                if let Err(e) = tonic::transport::Server::builder()
+                    .add_service(RemoteServer::new(service))
+                    .serve(addr)
+                    .await
+                {
+                    log::error!("gRPC Server Stopped: {}", e);
+                }
+            });
+        })
+    }
+}
+
+/// Implementation of the Remote service.
+struct RemoteService {
+    state: Arc<Mutex<proto::State>>,
+    neighbors: Arc<Mutex<Vec<proto::Neighbor>>>,
+    token: String,
+    tx: BoundedSender<Neighbor>,
+}
+
+impl RemoteService {
+    /// Forwards a command to the drive thread.
+    ///
+    /// Commands other than the stopping ones need the token in the metadata.
+    fn forward(
+        &self,
+        metadata: &MetadataMap,
+        msg: ParentMsg,
+    ) -> Result<Response<CommandReply>, Status> {
+        if msg == ParentMsg::Unknown {
+            return Err(Status::invalid_argument("Unknown command."));
+        }
+        if !matches!(msg, ParentMsg::Stop | ParentMsg::Off | ParentMsg::Pause) {
+            self.authorize(metadata)?;
+        }
+        let name = format!("{:?}", msg);
+        self.tx
+            .send(Neighbor::from_parent_msg(msg))
+            .map_err(|_| Status::unavailable("The drive thread is not running."))?;
+        log::debug!("gRPC Command Received: {}", name);
+        Ok(Response::new(CommandReply {
+            accepted: true,
+            message: name,
+        }))
+    }
+
+    /// Checks the token in the `authorization` metadata.
+    fn authorize(&self, metadata: &MetadataMap) -> Result<(), Status> {
+        if self.token.is_empty() {
+            return Err(Status::permission_denied(
+                "Set grpc.token to start the robot or change its mode remotely.",
+            ));
+        }
+        let expected = format!("Bearer {}", self.token);
+        match metadata.get("authorization").and_then(|v| v.to_str().ok()) {
+            Some(value) if same(value.as_bytes(), expected.as_bytes()) => Ok(()),
+            _ => Err(Status::unauthenticated("Invalid token.")),
+        }
+    }
+}
+
+/// Compares the bytes in a constant time, so that the token can't be guessed by the timing.
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[tonic::async_trait]
+impl Remote for RemoteService {
+    async fn set_mode(
+        &self,
+        request: Request<SetModeRequest>,
+    ) -> Result<Response<CommandReply>, Status> {
+        match Modes::from_string(&request.get_ref().mode) {
+            Modes::Unknown => Err(Status::invalid_argument("Unknown mode.")),
+            mode => self.forward(request.metadata(), ParentMsg::from_mode(mode)),
+        }
+    }
+
+    async fn start(&self, request: Request<Empty>) -> Result<Response<CommandReply>, Status> {
+        self.forward(request.metadata(), ParentMsg::On)
+    }
+
+    async fn stop(&self, request: Request<Empty>) -> Result<Response<CommandReply>, Status> {
+        self.forward(request.metadata(), ParentMsg::Off)
+    }
+
+    async fn reset(&self, request: Request<Empty>) -> Result<Response<CommandReply>, Status> {
+        self.forward(request.metadata(), ParentMsg::Reset)
+    }
+
+    async fn command(
+        &self,
+        request: Request<CommandRequest>,
+    ) -> Result<Response<CommandReply>, Status> {
+        let msg = ParentMsg::from_string(request.get_ref().command.trim());
+        self.forward(request.metadata(), msg)
+    }
+
+    async fn get_state(&self, _: Request<Empty>) -> Result<Response<proto::State>, Status> {
+        Ok(Response::new(self.state.lock().unwrap().clone()))
+    }
+
+    async fn list_neighbors(&self, _: Request<Empty>) -> Result<Response<NeighborList>, Status> {
+        Ok(Response::new(NeighborList {
+            neighbors: self.neighbors.lock().unwrap().clone(),
+        }))
+    }
+}
+
+/// Converts my state into the message.
+fn to_state(state: &RoktrackState) -> proto::State {
+    proto::State {
+        identifier: state.identifier as u32,
+        on: state.state,
+        mode: format!("{:?}", state.mode),
+        rest: state.rest,
+        pi_temp: state.pi_temp,
+        msg: state.msg as u32,
+        timestamp: chrono::Utc::now().timestamp(),
+    }
+}
+
+/// Converts a neighbor into the message.
+fn to_neighbor(neighbor: &Neighbor) -> proto::Neighbor {
+    proto::Neighbor {
+        identifier: neighbor.identifier as u32,
+        mac: neighbor.mac.clone(),
+        on: neighbor.state,
+        rest: neighbor.rest as u32,
+        mode: format!("{:?}", neighbor.mode),
+        msg: neighbor.msg as u32,
+        rssi: neighbor.rssi as i32,
+        timestamp: neighbor.timestamp.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::channel;
+
+    #[test]
+    fn command_test() {
+        let (tx, rx) = channel::bounded(4);
+        let service = remote("secret", tx);
+        let none = MetadataMap::new();
+        assert!(service.forward(&none, ParentMsg::Stop).is_ok());
+        let neighbor = rx.try_recv().unwrap();
+        assert_eq!(neighbor.identifier, 0);
+        assert_eq!(neighbor.msg, ParentMsg::to_u8(ParentMsg::Stop));
+        assert!(service.forward(&none, ParentMsg::Unknown).is_err());
+        // The drive thread is gone.
+        drop(rx);
+        assert!(service.forward(&token("secret"), ParentMsg::On).is_err());
+    }
+
+    #[test]
+    fn token_test() {
+        let (tx, rx) = channel::bounded(4);
+        let service = remote("secret", tx);
+        // Starting and changing the mode need the token.
+        for msg in [ParentMsg::On, ParentMsg::Fill, ParentMsg::Forward] {
+            let denied = service.forward(&MetadataMap::new(), msg).unwrap_err();
+            assert_eq!(denied.code(), tonic::Code::Unauthenticated);
+        }
+        let denied = service.forward(&token("wrong"), ParentMsg::On).unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::Unauthenticated);
+        assert!(rx.try_recv().is_err());
+        assert!(service.forward(&token("secret"), ParentMsg::On).is_ok());
+        assert_eq!(rx.try_recv().unwrap().msg, ParentMsg::to_u8(ParentMsg::On));
+        // Nothing but stopping without a token in the config
+        let (tx, _rx) = channel::bounded(4);
+        let service = remote("", tx);
+        let denied = service.forward(&token(""), ParentMsg::On).unwrap_err();
+        assert_eq!(denied.code(), tonic::Code::PermissionDenied);
+        assert!(service.forward(&MetadataMap::new(), ParentMsg::Off).is_ok());
+    }
+
+    fn remote(token: &str, tx: BoundedSender<Neighbor>) -> RemoteService {
+        RemoteService {
+            state: Arc::new(Mutex::new(proto::State::default())),
+            neighbors: Arc::new(Mutex::new(vec![])),
+            token: token.to_string(),
+            tx,
+        }
+    }
+
+    fn token(token: &str) -> MetadataMap {
+        let mut metadata = MetadataMap::new();
+        metadata.insert(
+            "authorization",
+            format!("Bearer {}", token).parse().unwrap(),
+        );
+        metadata
+    }
+}
@@ -0,0 +1,390 @@
+//! Raw HCI Socket Module
+//!
+//! This module talks to the Bluetooth controller directly through a raw HCI socket,
+//! so advertising works without the deprecated `hcitool` binary.
+
+use std::io;
+use std::os::unix::io::RawFd;
+
+// Bluetooth socket protocol and options (see linux/include/net/bluetooth/hci.h).
+const BTPROTO_HCI: libc::c_int = 1;
+const SOL_HCI: libc::c_int = 0;
+const HCI_FILTER: libc::c_int = 2;
+const HCI_CHANNEL_RAW: u16 = 0;
+
+// HCI packet types.
+const HCI_COMMAND_PKT: u8 = 0x01;
+const HCI_EVENT_PKT: u8 = 0x04;
+
+// HCI events.
+const EVT_CMD_COMPLETE: u8 = 0x0E;
+const EVT_CMD_STATUS: u8 = 0x0F;
+
+// LE Controller Commands.
+const OGF_LE_CTL: u16 = 0x08;
+const OCF_LE_SET_ADVERTISING_PARAMETERS: u16 = 0x0006;
+const OCF_LE_SET_ADVERTISING_DATA: u16 = 0x0008;
+const OCF_LE_SET_ADVERTISE_ENABLE: u16 = 0x000A;
+const OCF_LE_SET_EXT_ADVERTISING_PARAMETERS: u16 = 0x0036;
+const OCF_LE_SET_EXT_ADVERTISING_DATA: u16 = 0x0037;
+const OCF_LE_SET_EXT_ADVERTISE_ENABLE: u16 = 0x0039;
+
+// ioctl requests to bring a controller up and down (_IOW('H', 201/202, int)).
+const HCIDEVUP: libc::c_ulong = 0x400448C9;
+const HCIDEVDOWN: libc::c_ulong = 0x400448CA;
+
+// Milliseconds to wait for the controller to answer a command.
+const COMMAND_TIMEOUT: libc::c_int = 1000;
+
+/// Maximum length of legacy advertising data.
+pub const MAX_ADV_DATA_LEN: usize = 31;
+
+/// Maximum length of extended advertising data sent in a single fragment.
+pub const MAX_EXT_ADV_DATA_LEN: usize = 251;
+
+/// Socket address for the HCI protocol.
+#[repr(C)]
+struct SockaddrHci {
+    hci_family: libc::sa_family_t,
+    hci_dev: u16,
+    hci_channel: u16,
+}
+
+/// Event filter for the HCI protocol.
+#[repr(C)]
+struct HciFilter {
+    type_mask: u32,
+    event_mask: [u32; 2],
+    opcode: u16,
+}
+
+/// Raw HCI socket bound to one Bluetooth controller.
+pub struct HciSocket {
+    fd: RawFd,
+    pub dev_id: u16,
+}
+
+impl HciSocket {
+    /// Opens a raw HCI socket on the controller `hci<dev_id>`.
+    ///
+    /// # Arguments
+    ///
+    /// * `dev_id` - Index of the controller (0 for hci0, 1 for hci1, ...).
+    ///
+    pub fn open(dev_id: u16) -> Result<Self, Box<dyn std::error::Error>> {
+        let fd = unsafe {
+            libc::socket(
+                libc::AF_BLUETOOTH,
+                libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                BTPROTO_HCI,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        // Wrap the descriptor first so it is closed on every error path.
+        let socket = Self { fd, dev_id };
+
+        // Only let command completion events through.
+        let filter = HciFilter {
+            type_mask: 1 << HCI_EVENT_PKT,
+            event_mask: [(1 << EVT_CMD_COMPLETE) | (1 << EVT_CMD_STATUS), 0],
+            opcode: 0,
+        };
+        let res = unsafe {
+            libc::setsockopt(
+                socket.fd,
+                SOL_HCI,
+                HCI_FILTER,
+                &filter as *const HciFilter as *const libc::c_void,
+                std::mem::size_of::<HciFilter>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        // Bind the socket to the controller.
+        let addr = SockaddrHci {
+            hci_family: libc::AF_BLUETOOTH as libc::sa_family_t,
+            hci_dev: dev_id,
+            hci_channel: HCI_CHANNEL_RAW,
+        };
+        let res = unsafe {
+            libc::bind(
+                socket.fd,
+                &addr as *const SockaddrHci as *const libc::sockaddr,
+                std::mem::size_of::<SockaddrHci>() as libc::socklen_t,
+            )
+        };
+        if res < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        Ok(socket)
+    }
+
+    /// Sends an HCI command and waits for the controller's answer.
+    ///
+    /// Returns an error if the socket fails, the controller does not answer in time,
+    /// or the controller reports a non-zero status.
+    pub fn send_command(
+        &self,
+        ogf: u16,
+        ocf: u16,
+        params: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let opcode: u16 = (ogf << 10) | ocf;
+        let mut packet = vec![
+            HCI_COMMAND_PKT,
+            (opcode & 0xFF) as u8,
+            (opcode >> 8) as u8,
+            params.len() as u8,
+        ];
+        packet.extend_from_slice(params);
+
+        let written = unsafe {
+            libc::write(
+                self.fd,
+                packet.as_ptr() as *const libc::c_void,
+                packet.len(),
+            )
+        };
+        if written < 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+        self.wait_status(opcode)
+    }
+
+    /// Waits for the Command Complete / Command Status event of the given opcode.
+    fn wait_status(&self, opcode: u16) -> Result<(), Box<dyn std::error::Error>> {
+        let mut buf = [0u8; 260];
+        loop {
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let ready = unsafe { libc::poll(&mut pfd, 1, COMMAND_TIMEOUT) };
+            if ready < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            if ready == 0 {
+                return Err(format!("HCI command 0x{:04X} timed out", opcode).into());
+            }
+            let len =
+                unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if len < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            if let Some(status) = parse_command_status(&buf[..len as usize], opcode) {
+                return match status {
+                    0 => Ok(()),
+                    s => Err(
+                        format!("HCI command 0x{:04X} failed. status: 0x{:02X}", opcode, s).into(),
+                    ),
+                };
+            }
+        }
+    }
+
+    /// Sets the advertising interval and the advertising type.
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - Advertising interval in units of 0.625 ms.
+    /// * `adv_type` - Advertising type (0x03: ADV_NONCONN_IND).
+    ///
+    pub fn set_advertising_parameters(
+        &self,
+        interval: u16,
+        adv_type: u8,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut params = vec![];
+        params.extend_from_slice(&interval.to_le_bytes()); // Min interval
+        params.extend_from_slice(&interval.to_le_bytes()); // Max interval
+        params.push(adv_type); // Advertising type
+        params.push(0x00); // Own address type (public)
+        params.push(0x00); // Peer address type
+        params.extend_from_slice(&[0x00; 6]); // Peer address
+        params.push(0x07); // Channel map (37, 38, 39)
+        params.push(0x00); // Filter policy
+        self.send_command(OGF_LE_CTL, OCF_LE_SET_ADVERTISING_PARAMETERS, &params)
+    }
+
+    /// Sets the advertising data.
+    ///
+    /// The data is zero padded to the fixed legacy length.
+    pub fn set_advertising_data(&self, data: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if data.len() > MAX_ADV_DATA_LEN {
+            return Err(format!("Advertising data too long. len: {}", data.len()).into());
+        }
+        let mut params = vec![data.len() as u8];
+        params.extend_from_slice(data);
+        params.resize(MAX_ADV_DATA_LEN + 1, 0);
+        self.send_command(OGF_LE_CTL, OCF_LE_SET_ADVERTISING_DATA, &params)
+    }
+
+    /// Enables or disables advertising.
+    pub fn set_advertise_enable(&self, enable: bool) -> Result<(), Box<dyn std::error::Error>> {
+        self.send_command(OGF_LE_CTL, OCF_LE_SET_ADVERTISE_ENABLE, &[enable as u8])
+    }
+
+    /// Sets the parameters of an extended advertising set (BLE 5).
+    ///
+    /// The set is non-connectable and non-scannable, and uses the 1M PHY on both
+    /// the primary and the secondary channels so that BLE 4.x scanners can follow it.
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - Advertising set handle.
+    /// * `interval` - Advertising interval in units of 0.625 ms.
+    ///
+    pub fn set_extended_advertising_parameters(
+        &self,
+        handle: u8,
+        interval: u16,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let interval = (interval as u32).to_le_bytes();
+        let mut params = vec![handle];
+        params.extend_from_slice(&[0x00, 0x00]); // Event properties (non-connectable, non-scannable)
+        params.extend_from_slice(&interval[..3]); // Min interval
+        params.extend_from_slice(&interval[..3]); // Max interval
+        params.push(0x07); // Channel map (37, 38, 39)
+        params.push(0x00); // Own address type (public)
+        params.push(0x00); // Peer address type
+        params.extend_from_slice(&[0x00; 6]); // Peer address
+        params.push(0x00); // Filter policy
+        params.push(0x7F); // TX power (no preference)
+        params.push(0x01); // Primary PHY (1M)
+        params.push(0x00); // Secondary max skip
+        params.push(0x01); // Secondary PHY (1M)
+        params.push(0x00); // Advertising SID
+        params.push(0x00); // Scan request notification
+        self.send_command(OGF_LE_CTL, OCF_LE_SET_EXT_ADVERTISING_PARAMETERS, &params)
+    }
+
+    /// Sets the data of an extended advertising set as a single fragment.
+    pub fn set_extended_advertising_data(
+        &self,
+        handle: u8,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if data.len() > MAX_EXT_ADV_DATA_LEN {
+            return Err(format!("Extended advertising data too long. len: {}", data.len()).into());
+        }
+        let mut params = vec![
+            handle,
+            0x03, // Operation (complete data)
+            0x01, // Fragment preference (should not fragment)
+            data.len() as u8,
+        ];
+        params.extend_from_slice(data);
+        self.send_command(OGF_LE_CTL, OCF_LE_SET_EXT_ADVERTISING_DATA, &params)
+    }
+
+    /// Enables or disables an extended advertising set without duration or event limits.
+    pub fn set_extended_advertise_enable(
+        &self,
+        handle: u8,
+        enable: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let params = [
+            enable as u8,
+            0x01, // Number of sets
+            handle,
+            0x00, // Duration (low)
+            0x00, // Duration (high)
+            0x00, // Max extended advertising events
+        ];
+        self.send_command(OGF_LE_CTL, OCF_LE_SET_EXT_ADVERTISE_ENABLE, &params)
+    }
+
+    /// Brings the controller down and up again, e.g. to recover a wedged adapter.
+    ///
+    /// Advertising parameters are lost and must be set again.
+    pub fn power_cycle(&self) -> Result<(), Box<dyn std::error::Error>> {
+        for request in [HCIDEVDOWN, HCIDEVUP] {
+            let res = unsafe { libc::ioctl(self.fd, request as _, self.dev_id as libc::c_int) };
+            if res < 0 {
+                let err = io::Error::last_os_error();
+                // The controller may already be down.
+                if request != HCIDEVDOWN || err.raw_os_error() != Some(libc::EALREADY) {
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HciSocket {
+    /// Close the socket when the handler goes out of scope.
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// Extracts the status of a Command Complete / Command Status event for the opcode.
+///
+/// Returns `None` if the packet is not an answer to the opcode.
+fn parse_command_status(packet: &[u8], opcode: u16) -> Option<u8> {
+    if packet.len() < 3 || packet[0] != HCI_EVENT_PKT {
+        return None;
+    }
+    let body = &packet[3..];
+    match packet[1] {
+        // Command Complete: num_hci_command_packets, opcode(2), status, ...
+        EVT_CMD_COMPLETE if body.len() >= 4 => {
+            if u16::from_le_bytes([body[1], body[2]]) == opcode {
+                Some(body[3])
+            } else {
+                None
+            }
+        }
+        // Command Status: status, num_hci_command_packets, opcode(2)
+        EVT_CMD_STATUS if body.len() >= 4 => {
+            if u16::from_le_bytes([body[2], body[3]]) == opcode {
+                Some(body[0])
+            } else {
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Parses an adapter name such as "hci1", or a bare index such as "1", into its controller index.
+pub fn parse_dev_id(name: &str) -> Option<u16> {
+    name.strip_prefix("hci").unwrap_or(name).parse::<u16>().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_command_status_test() {
+        let opcode = (OGF_LE_CTL << 10) | OCF_LE_SET_ADVERTISE_ENABLE;
+        // Command Complete with success
+        let packet = [0x04, 0x0E, 0x04, 0x01, 0x0A, 0x20, 0x00];
+        assert_eq!(parse_command_status(&packet, opcode), Some(0));
+        // Command Complete with "Command Disallowed"
+        let packet = [0x04, 0x0E, 0x04, 0x01, 0x0A, 0x20, 0x0C];
+        assert_eq!(parse_command_status(&packet, opcode), Some(0x0C));
+        // Command Status for another opcode
+        let packet = [0x04, 0x0F, 0x04, 0x00, 0x01, 0x06, 0x20];
+        assert_eq!(parse_command_status(&packet, opcode), None);
+        // Truncated
+        assert_eq!(parse_command_status(&[0x04, 0x0E], opcode), None);
+    }
+
+    #[test]
+    fn parse_dev_id_test() {
+        assert_eq!(parse_dev_id("hci0"), Some(0));
+        assert_eq!(parse_dev_id("hci12"), Some(12));
+        assert_eq!(parse_dev_id("1"), Some(1));
+        assert_eq!(parse_dev_id("usb0"), None);
+    }
+}
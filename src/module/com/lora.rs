@@ -0,0 +1,395 @@
+//! LoRa Transport Module
+//!
+//! Exchanges states and commands over an SX1276/RFM95 LoRa radio connected via SPI,
+//! for fields larger than the range of BLE advertising.
+//!
+//! # Frame
+//! | identifier (1) | payload (sealed if a key is set) |
+//!
+//! # Duty Cycle
+//! A packet stays on air for hundreds of ms, and the regulations limit the share of
+//! the time on air. Payloads are queued like those of BLE advertising and transmitted
+//! in a thread, waiting after each packet as long as the duty cycle of the config requires.
+
+use super::channel::BoundedSender;
+use super::queue::BroadcastQueue;
+use super::secure::{PayloadCipher, ReplayGuard};
+use super::transport::{Capabilities, CommTransport};
+use super::{
+    cipher_from_property, fresh, priority, replay_state_path, Neighbor, REPLAY_SAVE_INTERVAL,
+};
+use crate::module::util::init::RoktrackProperty;
+use rppal::gpio::Gpio;
+use rppal::spi::{Bus, Mode, SlaveSelect, Spi};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+// Registers (see SX1276 datasheet, LoRa mode).
+const REG_FIFO: u8 = 0x00;
+const REG_OP_MODE: u8 = 0x01;
+const REG_FRF_MSB: u8 = 0x06;
+const REG_FRF_MID: u8 = 0x07;
+const REG_FRF_LSB: u8 = 0x08;
+const REG_PA_CONFIG: u8 = 0x09;
+const REG_FIFO_ADDR_PTR: u8 = 0x0D;
+const REG_FIFO_TX_BASE_ADDR: u8 = 0x0E;
+const REG_FIFO_RX_BASE_ADDR: u8 = 0x0F;
+const REG_FIFO_RX_CURRENT_ADDR: u8 = 0x10;
+const REG_IRQ_FLAGS: u8 = 0x12;
+const REG_RX_NB_BYTES: u8 = 0x13;
+const REG_PKT_RSSI_VALUE: u8 = 0x1A;
+const REG_MODEM_CONFIG_1: u8 = 0x1D;
+const REG_MODEM_CONFIG_2: u8 = 0x1E;
+const REG_PAYLOAD_LENGTH: u8 = 0x22;
+const REG_MODEM_CONFIG_3: u8 = 0x26;
+const REG_SYNC_WORD: u8 = 0x39;
+const REG_VERSION: u8 = 0x42;
+
+// Operation modes.
+const MODE_LONG_RANGE: u8 = 0x80;
+const MODE_SLEEP: u8 = 0x00;
+const MODE_STDBY: u8 = 0x01;
+const MODE_TX: u8 = 0x03;
+const MODE_RX_CONTINUOUS: u8 = 0x05;
+
+// IRQ flags.
+const IRQ_TX_DONE: u8 = 0x08;
+const IRQ_PAYLOAD_CRC_ERROR: u8 = 0x20;
+const IRQ_RX_DONE: u8 = 0x40;
+
+// Silicon revision of SX1276/RFM95.
+const CHIP_VERSION: u8 = 0x12;
+
+// Private sync word so that LoRaWAN gateways ignore us.
+const SYNC_WORD: u8 = 0x12;
+
+// Frequency of the crystal oscillator.
+const FXOSC: u64 = 32_000_000;
+
+/// Maximum length of a LoRa packet.
+pub const MAX_PACKET_LEN: usize = 255;
+
+// Time to wait for a transmission to complete.
+const TX_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Interval for polling received packets.
+const RX_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Interval for checking the transmit queue.
+const QUEUE_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+// Bandwidth in Hz and preamble length in symbols (see `Sx1276::new`).
+const BANDWIDTH: f64 = 125_000.0;
+const PREAMBLE_LEN: f64 = 8.0;
+
+/// SX1276 radio driver.
+pub struct Sx1276 {
+    spi: Spi,
+}
+
+impl Sx1276 {
+    /// Resets and configures the radio.
+    ///
+    /// # Arguments
+    ///
+    /// * `bus` - SPI bus number (0 for /dev/spidev0.x).
+    /// * `reset_pin` - GPIO pin number connected to RESET.
+    /// * `frequency` - Carrier frequency in Hz.
+    /// * `spreading_factor` - Spreading factor (7-12). Higher reaches further but slower.
+    /// * `tx_power` - Output power in dBm (2-17).
+    ///
+    pub fn new(
+        bus: u8,
+        reset_pin: u8,
+        frequency: u32,
+        spreading_factor: u8,
+        tx_power: u8,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        // Reset the chip.
+        let mut reset = Gpio::new()?.get(reset_pin)?.into_output();
+        reset.set_low();
+        thread::sleep(Duration::from_millis(10));
+        reset.set_high();
+        thread::sleep(Duration::from_millis(10));
+
+        let bus = match bus {
+            0 => Bus::Spi0,
+            1 => Bus::Spi1,
+            _ => return Err(format!("Unsupported SPI bus: {}", bus).into()),
+        };
+        let spi = Spi::new(bus, SlaveSelect::Ss0, 8_000_000, Mode::Mode0)?;
+        let mut radio = Self { spi };
+
+        let version = radio.read_register(REG_VERSION)?;
+        if version != CHIP_VERSION {
+            return Err(format!("LoRa radio not found. version: 0x{:02X}", version).into());
+        }
+
+        // LoRa mode can only be selected in sleep mode.
+        radio.write_register(REG_OP_MODE, MODE_LONG_RANGE | MODE_SLEEP)?;
+        // Carrier frequency
+        let frf = (frequency as u64) * (1 << 19) / FXOSC;
+        radio.write_register(REG_FRF_MSB, (frf >> 16) as u8)?;
+        radio.write_register(REG_FRF_MID, (frf >> 8) as u8)?;
+        radio.write_register(REG_FRF_LSB, frf as u8)?;
+        // Use the whole FIFO for both directions.
+        radio.write_register(REG_FIFO_TX_BASE_ADDR, 0)?;
+        radio.write_register(REG_FIFO_RX_BASE_ADDR, 0)?;
+        // BW 125 kHz, CR 4/5, explicit header
+        radio.write_register(REG_MODEM_CONFIG_1, 0x72)?;
+        // Spreading factor, CRC on
+        let sf = spreading_factor.clamp(7, 12);
+        radio.write_register(REG_MODEM_CONFIG_2, (sf << 4) | 0x04)?;
+        // Low data rate optimization for SF11/12, AGC on
+        radio.write_register(REG_MODEM_CONFIG_3, if sf >= 11 { 0x0C } else { 0x04 })?;
+        // PA_BOOST pin
+        let power = tx_power.clamp(2, 17);
+        radio.write_register(REG_PA_CONFIG, 0x80 | (power - 2))?;
+        radio.write_register(REG_SYNC_WORD, SYNC_WORD)?;
+
+        radio.set_mode(MODE_RX_CONTINUOUS)?;
+        Ok(radio)
+    }
+
+    /// Reads a register.
+    fn read_register(&mut self, addr: u8) -> Result<u8, Box<dyn std::error::Error>> {
+        let mut read = [0u8; 2];
+        self.spi.transfer(&mut read, &[addr & 0x7F, 0])?;
+        Ok(read[1])
+    }
+
+    /// Writes a register.
+    fn write_register(&mut self, addr: u8, value: u8) -> Result<(), Box<dyn std::error::Error>> {
+        self.spi.write(&[addr | 0x80, value])?;
+        Ok(())
+    }
+
+    /// Switches the operation mode.
+    fn set_mode(&mut self, mode: u8) -> Result<(), Box<dyn std::error::Error>> {
+        self.write_register(REG_OP_MODE, MODE_LONG_RANGE | mode)
+    }
+
+    /// Transmits a packet and returns to receive mode.
+    pub fn transmit(&mut self, packet: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        if packet.len() > MAX_PACKET_LEN {
+            return Err(format!("LoRa packet too long. len: {}", packet.len()).into());
+        }
+        self.set_mode(MODE_STDBY)?;
+        self.write_register(REG_FIFO_ADDR_PTR, 0)?;
+        for byte in packet {
+            self.write_register(REG_FIFO, *byte)?;
+        }
+        self.write_register(REG_PAYLOAD_LENGTH, packet.len() as u8)?;
+        self.set_mode(MODE_TX)?;
+
+        let start = Instant::now();
+        let res = loop {
+            if self.read_register(REG_IRQ_FLAGS)? & IRQ_TX_DONE != 0 {
+                break Ok(());
+            }
+            if start.elapsed() > TX_TIMEOUT {
+                break Err("LoRa transmission timed out.".into());
+            }
+            thread::sleep(Duration::from_millis(1));
+        };
+        self.write_register(REG_IRQ_FLAGS, 0xFF)?;
+        self.set_mode(MODE_RX_CONTINUOUS)?;
+        res
+    }
+
+    /// Returns a received packet and its RSSI, if any.
+    pub fn receive(&mut self) -> Result<Option<(Vec<u8>, i16)>, Box<dyn std::error::Error>> {
+        let flags = self.read_register(REG_IRQ_FLAGS)?;
+        if flags & IRQ_RX_DONE == 0 {
+            return Ok(None);
+        }
+        self.write_register(REG_IRQ_FLAGS, 0xFF)?;
+        if flags & IRQ_PAYLOAD_CRC_ERROR != 0 {
+            return Err("LoRa packet CRC error.".into());
+        }
+        let len = self.read_register(REG_RX_NB_BYTES)?;
+        let addr = self.read_register(REG_FIFO_RX_CURRENT_ADDR)?;
+        self.write_register(REG_FIFO_ADDR_PTR, addr)?;
+        let mut packet = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            packet.push(self.read_register(REG_FIFO)?);
+        }
+        // RSSI for the high frequency port
+        let rssi = self.read_register(REG_PKT_RSSI_VALUE)? as i16 - 157;
+        Ok(Some((packet, rssi)))
+    }
+}
+
+/// Time on air of a packet of `len` bytes at the spreading factor (see `Sx1276::new`).
+pub fn time_on_air(len: usize, spreading_factor: u8) -> Duration {
+    let sf = spreading_factor.clamp(7, 12) as f64;
+    let symbol = 2f64.powf(sf) / BANDWIDTH;
+    // Explicit header, CRC on, CR 4/5, low data rate optimization for SF11/12
+    let ldro = if sf >= 11.0 { 1.0 } else { 0.0 };
+    let bits = 8.0 * len as f64 - 4.0 * sf + 28.0 + 16.0;
+    let payload = 8.0 + ((bits / (4.0 * (sf - 2.0 * ldro))).ceil() * 5.0).max(0.0);
+    Duration::from_secs_f64((PREAMBLE_LEN + 4.25 + payload) * symbol)
+}
+
+/// Time to stay off air after a packet of the time on air, for the duty cycle in %.
+pub fn off_time(on_air: Duration, duty_cycle: f32) -> Duration {
+    let duty_cycle = duty_cycle.clamp(0.01, 100.0) as f64;
+    on_air.mul_f64(100.0 / duty_cycle - 1.0)
+}
+
+/// LoRa Transport Handler
+pub struct LoraTransport {
+    radio: Arc<Mutex<Sx1276>>,
+    queue: Arc<Mutex<BroadcastQueue>>,
+    cipher: Option<Arc<PayloadCipher>>,
+    replay_state: PathBuf,
+}
+
+impl LoraTransport {
+    /// Creates a new instance of LoRa Transport Handler with the given property.
+    pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+        let lora = &property.conf.lora;
+        let radio = Sx1276::new(
+            lora.spi_bus,
+            lora.reset_pin,
+            lora.frequency,
+            lora.spreading_factor,
+            lora.tx_power,
+        )?;
+        let transport = Self {
+            radio: Arc::new(Mutex::new(radio)),
+            queue: Arc::new(Mutex::new(BroadcastQueue::new(Duration::ZERO))),
+            cipher: cipher_from_property(&property)?,
+            replay_state: replay_state_path(&property, "lora"),
+        };
+        transport.run_queue(lora.spreading_factor, lora.duty_cycle);
+        Ok(transport)
+    }
+
+    /// Transmits queued payloads in a thread within the duty cycle, so that callers never block.
+    fn run_queue(&self, spreading_factor: u8, duty_cycle: f32) -> JoinHandle<()> {
+        let radio = self.radio.clone();
+        let queue = self.queue.clone();
+        let cipher = self.cipher.clone();
+        thread::spawn(move || {
+            let mut next_time = Instant::now();
+            loop {
+                thread::sleep(QUEUE_POLL_INTERVAL);
+                if Instant::now() < next_time {
+                    continue;
+                }
+                let Some((identifier, payload)) = queue.lock().unwrap().pop(Instant::now()) else {
+                    continue;
+                };
+                let mut packet = vec![identifier];
+                match &cipher {
+                    Some(cipher) => match cipher.seal(identifier, &payload) {
+                        Ok(sealed) => packet.extend(sealed),
+                        Err(e) => {
+                            log::warn!("LoRa Seal Failed: {}", e);
+                            continue;
+                        }
+                    },
+                    None => packet.extend(payload),
+                }
+                packet.truncate(MAX_PACKET_LEN);
+                if let Err(e) = radio.lock().unwrap().transmit(&packet) {
+                    log::warn!("LoRa Transmission Failed: {}", e);
+                }
+                let on_air = time_on_air(packet.len(), spreading_factor);
+                next_time = Instant::now() + off_time(on_air, duty_cycle);
+            }
+        })
+    }
+}
+
+impl CommTransport for LoraTransport {
+    /// Queues the payload to be transmitted as one LoRa packet.
+    ///
+    /// Urgent messages (e.g. Halt) are transmitted before pending state updates.
+    fn send(&self, identifier: &u8, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let priority = priority(&payload);
+        self.queue
+            .lock()
+            .unwrap()
+            .push(*identifier, payload, priority);
+        Ok(())
+    }
+
+    /// Polls the radio for packets from neighbors.
+    fn listen(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let radio = self.radio.clone();
+        let cipher = self.cipher.clone();
+        let replay_state = self.replay_state.clone();
+        thread::spawn(move || {
+            log::debug!("LoRa Thread Started");
+            // Restore the counters seen before the last shutdown.
+            let mut guard = ReplayGuard::load(&replay_state);
+            let mut last_save = Instant::now();
+            loop {
+                thread::sleep(RX_POLL_INTERVAL);
+                let received = radio.lock().unwrap().receive();
+                let (packet, rssi) = match received {
+                    Ok(Some(received)) => received,
+                    Ok(None) => continue,
+                    Err(e) => {
+                        log::debug!("LoRa Receive Failed: {}", e);
+                        continue;
+                    }
+                };
+                let mut neighbor = match Neighbor::from_frame(&packet, cipher.as_deref()) {
+                    Ok(neighbor) => neighbor,
+                    Err(e) => {
+                        log::debug!("LoRa Packet Rejected: {}", e);
+                        continue;
+                    }
+                };
+                // There is no MAC address, so senders are told apart by the identifier.
+                let sender = format!("lora:{}", neighbor.identifier);
+                if !fresh(&mut guard, &neighbor) {
+                    log::warn!("LoRa Replay Dropped From: {}", sender);
+                    continue;
+                }
+                if last_save.elapsed() > REPLAY_SAVE_INTERVAL {
+                    let _ = guard.save(&replay_state);
+                    last_save = Instant::now();
+                }
+                neighbor.mac = sender;
+                neighbor.rssi = rssi.clamp(i8::MIN as i16, 0) as i8;
+                log::debug!("LoRa Received: {:?}", packet);
+                if tx.send(neighbor).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Features of the LoRa radio.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            name: "lora",
+            max_payload: MAX_PACKET_LEN - 1,
+            bidirectional: true,
+            range_m: 1000,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duty_cycle_test() {
+        // 185.3 ms for 20 bytes at SF9, and 991.2 ms for 10 bytes at SF12
+        let on_air = time_on_air(20, 9);
+        assert!((on_air.as_secs_f64() - 0.1853).abs() < 0.001);
+        assert!((time_on_air(10, 12).as_secs_f64() - 0.9912).abs() < 0.001);
+        assert!(time_on_air(40, 9) > on_air);
+        // 99 times as long off air at 1 %, and no wait without a limit
+        assert_eq!(off_time(on_air, 1.0), on_air.mul_f64(99.0));
+        assert_eq!(off_time(on_air, 100.0), Duration::ZERO);
+    }
+}
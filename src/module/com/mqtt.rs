@@ -0,0 +1,162 @@
+//! MQTT Bridge Module
+//!
+//! Publishes the state of each robot to an MQTT broker and maps messages on the
+//! command topic onto `ParentMsg`, so that home automation systems can monitor
+//! and control the mower.
+//!
+//! # Topics
+//! * `<prefix>/<identifier>/state` (published, retained) - JSON state of a robot.
+//! * `<prefix>/command` (subscribed) - A `ParentMsg` by name ("on", "off", "fill", ...) or number.
+
+use super::channel::BoundedSender;
+use super::{Neighbor, ParentMsg};
+use crate::module::util::conf::Mqtt;
+use rumqttc::{Client, Connection, Event, MqttOptions, Packet, QoS};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+// Keep alive interval of the connection.
+const KEEP_ALIVE: Duration = Duration::from_secs(30);
+
+// Wait before reconnecting after a connection error.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// MQTT Bridge Handler
+pub struct MqttBridge {
+    client: Client,
+    connection: Mutex<Option<Connection>>,
+    prefix: String,
+}
+
+impl MqttBridge {
+    /// Creates a new instance of MQTT Bridge Handler with the given configuration.
+    ///
+    /// The connection is established in `serve`.
+    pub fn new(conf: Mqtt) -> Self {
+        let client_id = if conf.client_id.is_empty() {
+            format!("roktrack-{:08x}", rand::random::<u32>())
+        } else {
+            conf.client_id
+        };
+        let mut options = MqttOptions::new(client_id, conf.host, conf.port);
+        options.set_keep_alive(KEEP_ALIVE);
+        if !conf.username.is_empty() {
+            options.set_credentials(conf.username, conf.password);
+        }
+        let (client, connection) = Client::new(options, 10);
+        Self {
+            client,
+            connection: Mutex::new(Some(connection)),
+            prefix: conf.topic_prefix,
+        }
+    }
+
+    /// Runs the connection and sends received commands via a channel.
+    ///
+    /// Commands are delivered as a `Neighbor` from the commander (identifier 0),
+    /// so they are handled in the same way as broadcast commands.
+    pub fn serve(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let mut connection = self
+            .connection
+            .lock()
+            .unwrap()
+            .take()
+            .expect("MQTT bridge is already running.");
+        let client = self.client.clone();
+        let command_topic = command_topic(&self.prefix);
+        thread::spawn(move || {
+            log::debug!("MQTT Thread Started");
+            for notification in connection.iter() {
+                match notification {
+                    // (Re)subscribe on every connection, as the session is not persistent.
+                    Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                        log::info!("MQTT Connected");
+                        if let Err(e) = client.subscribe(command_topic.as_str(), QoS::AtLeastOnce) {
+                            log::error!("MQTT Subscribe Failed: {}", e);
+                        }
+                    }
+                    Ok(Event::Incoming(Packet::Publish(publish))) => {
+                        let payload = String::from_utf8_lossy(&publish.payload);
+                        let msg = ParentMsg::from_string(payload.trim());
+                        if msg == ParentMsg::Unknown {
+                            log::warn!("MQTT Unknown Command: {}", payload);
+                            continue;
+                        }
+                        log::debug!("MQTT Command Received: {}", payload);
+                        if tx.send(Neighbor::from_parent_msg(msg)).is_err() {
+                            break;
+                        }
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        log::warn!("MQTT Connection Error: {}", e);
+                        thread::sleep(RECONNECT_INTERVAL);
+                    }
+                }
+            }
+        })
+    }
+
+    /// Publishes the state of a robot.
+    pub fn publish(&self, neighbor: &Neighbor) {
+        let topic = format!("{}/{}/state", self.prefix, neighbor.identifier);
+        // Don't block the caller if the broker is unreachable.
+        if let Err(e) = self
+            .client
+            .try_publish(topic, QoS::AtMostOnce, true, to_json(neighbor))
+        {
+            log::debug!("MQTT Publish Failed: {}", e);
+        }
+    }
+}
+
+/// Topic to receive commands on.
+fn command_topic(prefix: &str) -> String {
+    format!("{}/command", prefix)
+}
+
+/// Formats the state of a robot as JSON. Telemetry not received is `null`.
+fn to_json(neighbor: &Neighbor) -> String {
+    let telemetry = &neighbor.telemetry;
+    format!(
+        "{{\"identifier\":{},\"mac\":\"{}\",\"state\":{},\"rest\":{},\"pi_temp\":{},\"mode\":\"{:?}\",\"msg\":{},\"rssi\":{},\"timestamp\":{},\"battery_mv\":{},\"progress\":{},\"gps_fix\":{},\"satellites\":{}}}",
+        neighbor.identifier,
+        neighbor.mac,
+        neighbor.state,
+        neighbor.rest,
+        neighbor.pi_temp,
+        neighbor.mode,
+        neighbor.msg,
+        neighbor.rssi,
+        neighbor.timestamp,
+        json_or_null(telemetry.battery_mv),
+        json_or_null(telemetry.progress),
+        json_or_null(telemetry.gps_fix.map(|f| format!("\"{:?}\"", f.quality))),
+        json_or_null(telemetry.gps_fix.map(|f| f.satellites))
+    )
+}
+
+/// Formats an optional value, `null` if absent.
+fn json_or_null<T: std::fmt::Display>(value: Option<T>) -> String {
+    value.map_or(String::from("null"), |v| v.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn to_json_test() {
+        let mut neighbor = Neighbor::from_parent_msg(ParentMsg::On);
+        neighbor.identifier = 3;
+        neighbor.timestamp = String::from("1700000000");
+        assert_eq!(
+            to_json(&neighbor),
+            "{\"identifier\":3,\"mac\":\"\",\"state\":false,\"rest\":0,\"pi_temp\":0,\"mode\":\"Unknown\",\"msg\":1,\"rssi\":0,\"timestamp\":1700000000,\"battery_mv\":null,\"progress\":null,\"gps_fix\":null,\"satellites\":null}"
+        );
+        neighbor.telemetry.battery_mv = Some(12600);
+        assert!(to_json(&neighbor).contains("\"battery_mv\":12600,"));
+        assert_eq!(command_topic("roktrack"), "roktrack/command");
+    }
+}
@@ -0,0 +1,275 @@
+//! Nordic UART Service Client Module
+//!
+//! Connects as a BLE central to peripherals speaking the Nordic UART Service (NUS),
+//! e.g. an ESP32 sensor pod on the chassis, and forwards their readings.
+//!
+//! # Readings
+//! The peripheral notifies text lines of `<key>=<value>`, e.g. `battery_mv=12600`.
+//! Known keys update the telemetry: `battery_mv`, `lat`, `lon`, `fix`, `sats`, `heading`
+//! and `range_cm`.
+
+use super::telemetry::{FixQuality, GpsFix, Telemetry};
+use super::BleBroadCast;
+use crate::module::util::conf::Nus;
+use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter};
+use btleplug::platform::{Adapter, Manager, Peripheral};
+use futures::stream::StreamExt;
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use uuid::Uuid;
+
+/// NUS Service UUID
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x6e400001_b5a3_f393_e0a9_e50e24dcca9e);
+/// NUS RX Characteristic UUID (written by the central)
+pub const RX_UUID: Uuid = Uuid::from_u128(0x6e400002_b5a3_f393_e0a9_e50e24dcca9e);
+/// NUS TX Characteristic UUID (notified by the peripheral)
+pub const TX_UUID: Uuid = Uuid::from_u128(0x6e400003_b5a3_f393_e0a9_e50e24dcca9e);
+
+// Maximum length of a line, longer ones are discarded.
+const MAX_LINE_LEN: usize = 128;
+
+// Interval between connection attempts.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Reading from a peripheral.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorReading {
+    pub key: String,
+    pub value: f64,
+}
+
+impl SensorReading {
+    /// Parses a `<key>=<value>` line.
+    pub fn parse(line: &str) -> Option<Self> {
+        let (key, value) = line.trim().split_once('=')?;
+        Some(Self {
+            key: key.trim().to_string(),
+            value: value.trim().parse().ok()?,
+        })
+    }
+
+    /// Applies the reading to the telemetry. Returns `false` for unknown keys.
+    pub fn apply(&self, telemetry: &mut Telemetry) -> bool {
+        let (lat, lon) = telemetry.position.unwrap_or((0.0, 0.0));
+        let fix = telemetry.gps_fix.unwrap_or(GpsFix {
+            quality: FixQuality::NoFix,
+            satellites: 0,
+        });
+        match self.key.as_str() {
+            "battery_mv" => telemetry.battery_mv = Some(self.value as u16),
+            "lat" => telemetry.position = Some((self.value, lon)),
+            "lon" => telemetry.position = Some((lat, self.value)),
+            "fix" => {
+                telemetry.gps_fix = Some(GpsFix {
+                    quality: FixQuality::from_u8(self.value as u8),
+                    ..fix
+                })
+            }
+            "heading" => telemetry.heading = Some(self.value as f32),
+            "range_cm" => telemetry.range_cm = Some(self.value as u16),
+            "sats" => {
+                telemetry.gps_fix = Some(GpsFix {
+                    satellites: self.value as u8,
+                    ..fix
+                })
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+/// Splits notifications into lines.
+struct LineDecoder {
+    buf: Vec<u8>,
+    overflow: bool,
+}
+
+impl LineDecoder {
+    /// Creates an empty decoder.
+    fn new() -> Self {
+        Self {
+            buf: vec![],
+            overflow: false,
+        }
+    }
+
+    /// Feeds a notification and returns the complete lines.
+    fn push(&mut self, bytes: &[u8]) -> Vec<String> {
+        let mut lines = vec![];
+        for byte in bytes {
+            match byte {
+                b'\n' => {
+                    if !self.overflow {
+                        lines.push(String::from_utf8_lossy(&self.buf).trim().to_string());
+                    }
+                    self.buf.clear();
+                    self.overflow = false;
+                }
+                // Drop the rest of an overlong line.
+                _ if self.overflow || self.buf.len() >= MAX_LINE_LEN => {
+                    self.buf.clear();
+                    self.overflow = true;
+                }
+                _ => self.buf.push(*byte),
+            }
+        }
+        lines.retain(|l| !l.is_empty());
+        lines
+    }
+}
+
+/// NUS Client Handler
+pub struct NusClient {
+    conf: Nus,
+    dev_id: Option<u16>,
+}
+
+impl NusClient {
+    /// Creates a new instance of NUS Client Handler on the adapter `hci<dev_id>`,
+    /// or on the first adapter if `None`.
+    pub fn new(conf: Nus, dev_id: Option<u16>) -> Self {
+        Self { conf, dev_id }
+    }
+
+    /// Connects to the peripheral and sends its readings via a channel, reconnecting as needed.
+    pub fn run(&self, tx: Sender<SensorReading>) -> JoinHandle<()> {
+        let name = self.conf.name.clone();
+        let dev_id = self.dev_id;
+        thread::spawn(move || {
+            log::debug!("NUS Thread Started");
+            // Create an asynchronous runtime.
+            let rt = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+
+            rt.block_on(async {
+                let manager = Manager::new().await.unwrap();
+                let central = match BleBroadCast::get_central(&manager, dev_id).await {
+                    Ok(central) => central,
+                    Err(e) => {
+                        log::error!("NUS Not Started: {}", e);
+                        return;
+                    }
+                };
+                loop {
+                    match Self::session(&central, &name, &tx).await {
+                        Ok(_) => log::warn!("NUS Peripheral Disconnected"),
+                        Err(e) => log::debug!("NUS Session Failed: {}", e),
+                    }
+                    tokio::time::sleep(RECONNECT_INTERVAL).await;
+                }
+            });
+        })
+    }
+
+    /// Finds, connects and reads one peripheral until it disconnects.
+    async fn session(
+        central: &Adapter,
+        name: &str,
+        tx: &Sender<SensorReading>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let peripheral = Self::find(central, name).await?;
+        peripheral.connect().await?;
+        peripheral.discover_services().await?;
+        let characteristic = peripheral
+            .characteristics()
+            .into_iter()
+            .find(|c| c.uuid == TX_UUID)
+            .ok_or("NUS TX characteristic not found.")?;
+        peripheral.subscribe(&characteristic).await?;
+        log::info!("NUS Peripheral Connected: {}", peripheral.address());
+
+        let mut notifications = peripheral.notifications().await?;
+        let mut decoder = LineDecoder::new();
+        while let Some(notification) = notifications.next().await {
+            if notification.uuid != TX_UUID {
+                continue;
+            }
+            for line in decoder.push(&notification.value) {
+                match SensorReading::parse(&line) {
+                    Some(reading) => tx.send(reading)?,
+                    None => log::debug!("NUS Line Ignored: {}", line),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Scans for a peripheral advertising NUS whose name starts with `name`.
+    async fn find(central: &Adapter, name: &str) -> Result<Peripheral, Box<dyn std::error::Error>> {
+        let mut events = central.events().await?;
+        central
+            .start_scan(ScanFilter {
+                services: vec![SERVICE_UUID],
+            })
+            .await?;
+        while let Some(event) = events.next().await {
+            if let CentralEvent::DeviceDiscovered(id) = event {
+                let peripheral = central.peripheral(&id).await?;
+                let local_name = peripheral
+                    .properties()
+                    .await?
+                    .and_then(|p| p.local_name)
+                    .unwrap_or_default();
+                if local_name.starts_with(name) {
+                    central.stop_scan().await?;
+                    return Ok(peripheral);
+                }
+            }
+        }
+        Err("BLE event stream ended.".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_decoder_test() {
+        let mut decoder = LineDecoder::new();
+        // Split across notifications
+        assert!(decoder.push(b"battery_").is_empty());
+        assert_eq!(
+            decoder.push(b"mv=12600\r\nsats=9\n\n"),
+            vec!["battery_mv=12600", "sats=9"]
+        );
+        // Overlong lines are discarded.
+        let long = vec![b'a'; MAX_LINE_LEN + 10];
+        decoder.push(&long);
+        assert_eq!(decoder.push(b"\nfix=4\n"), vec!["fix=4"]);
+    }
+
+    #[test]
+    fn reading_test() {
+        assert_eq!(SensorReading::parse("temp"), None);
+        assert_eq!(SensorReading::parse("temp=hot"), None);
+        let mut telemetry = Telemetry::default();
+        for line in [
+            "battery_mv=12600",
+            "lat=35.5",
+            "lon=139.7",
+            "fix=4",
+            "sats=12",
+            "range_cm=42",
+        ] {
+            assert!(SensorReading::parse(line).unwrap().apply(&mut telemetry));
+        }
+        assert_eq!(telemetry.battery_mv, Some(12600));
+        assert_eq!(telemetry.range_cm, Some(42));
+        assert_eq!(telemetry.position, Some((35.5, 139.7)));
+        assert_eq!(
+            telemetry.gps_fix,
+            Some(GpsFix {
+                quality: FixQuality::RtkFixed,
+                satellites: 12
+            })
+        );
+        let reading = SensorReading::parse(" humidity = 40.5 ").unwrap();
+        assert_eq!(reading.key, "humidity");
+        assert!(!reading.apply(&mut telemetry));
+    }
+}
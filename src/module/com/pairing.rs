@@ -0,0 +1,134 @@
+//! Pairing Module
+//!
+//! Keeps the devices trusted to exchange states and commands with this robot.
+//! Devices heard during the pairing window are recorded to disk, and broadcasts
+//! from any other device are ignored, so that a neighbor's robot (or an attacker)
+//! can't inject commands.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Devices trusted by this robot.
+pub struct TrustStore {
+    devices: BTreeMap<String, u8>, // MAC address (or transport sender) and the last identifier
+    path: Option<PathBuf>,
+    pairing_until: Option<Instant>,
+}
+
+impl TrustStore {
+    /// Loads the trusted devices from the file, or starts with none.
+    ///
+    /// Each line of the file is `<mac> <identifier>`.
+    pub fn load(path: Option<PathBuf>) -> Self {
+        let mut devices = BTreeMap::new();
+        if let Some(content) = path.as_ref().and_then(|p| fs::read_to_string(p).ok()) {
+            for line in content.lines() {
+                if let Some((mac, identifier)) = line.split_once(' ') {
+                    if let Ok(identifier) = identifier.trim().parse::<u8>() {
+                        devices.insert(mac.to_string(), identifier);
+                    }
+                }
+            }
+        }
+        Self {
+            devices,
+            path,
+            pairing_until: None,
+        }
+    }
+
+    /// Saves the trusted devices.
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(path) = &self.path {
+            let content: Vec<String> = self
+                .devices
+                .iter()
+                .map(|(mac, identifier)| format!("{} {}", mac, identifier))
+                .collect();
+            fs::write(path, content.join("\n"))?;
+        }
+        Ok(())
+    }
+
+    /// Trusts every device heard until the window elapses.
+    pub fn start_pairing(&mut self, window: Duration, now: Instant) {
+        self.pairing_until = Some(now + window);
+    }
+
+    /// Whether the pairing window is open.
+    pub fn is_pairing(&self, now: Instant) -> bool {
+        self.pairing_until.is_some_and(|until| now < until)
+    }
+
+    /// Whether the device is trusted.
+    pub fn is_trusted(&self, mac: &str) -> bool {
+        self.devices.contains_key(mac)
+    }
+
+    /// Returns `true` if a broadcast from the device may be processed.
+    ///
+    /// During the pairing window, unknown devices are trusted and recorded.
+    pub fn admit(&mut self, mac: &str, identifier: u8, now: Instant) -> bool {
+        if self.devices.get(mac) == Some(&identifier) {
+            return true;
+        }
+        if self.is_trusted(mac) || self.is_pairing(now) {
+            // A new device or a new identifier of a trusted one
+            if self.devices.insert(mac.to_string(), identifier).is_none() {
+                log::info!("Device Paired: {} ({})", mac, identifier);
+            }
+            if let Err(e) = self.save() {
+                log::error!("Failed to Save Trusted Devices: {}", e);
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Forgets the device.
+    pub fn remove(&mut self, mac: &str) -> bool {
+        self.devices.remove(mac).is_some()
+    }
+
+    /// Number of trusted devices.
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    /// Whether no device is trusted.
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairing_test() {
+        let path = std::env::temp_dir().join("roktrack_trusted_devices_test");
+        let _ = fs::remove_file(&path);
+        let mut store = TrustStore::load(Some(path.clone()));
+        let now = Instant::now();
+        // Nobody is trusted outside the pairing window.
+        assert!(!store.admit("AA:AA:AA:AA:AA:AA", 1, now));
+        store.start_pairing(Duration::from_secs(60), now);
+        assert!(store.admit("AA:AA:AA:AA:AA:AA", 1, now));
+        // After the window
+        let later = now + Duration::from_secs(61);
+        assert!(!store.is_pairing(later));
+        assert!(!store.admit("BB:BB:BB:BB:BB:BB", 2, later));
+        // A trusted device may change the identifier.
+        assert!(store.admit("AA:AA:AA:AA:AA:AA", 3, later));
+        // Restored from the file
+        let mut restored = TrustStore::load(Some(path.clone()));
+        assert_eq!(restored.len(), 1);
+        assert!(restored.admit("AA:AA:AA:AA:AA:AA", 3, later));
+        assert!(restored.remove("AA:AA:AA:AA:AA:AA"));
+        assert!(restored.is_empty());
+        let _ = fs::remove_file(&path);
+    }
+}
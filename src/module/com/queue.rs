@@ -0,0 +1,160 @@
+//! Broadcast Queue Module
+//!
+//! Advertising data can only change so fast: an update must stay on air for a few
+//! advertising events to be heard. This queue paces outgoing payloads, puts urgent
+//! messages first, and coalesces state updates so that only the latest one is sent.
+//!
+//! An urgent message is repeated in every frame while it holds (e.g. Halt), with the
+//! telemetry changing each time, so the queued one of the same msg is replaced instead.
+
+use super::fragment;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Most urgent payloads waiting, enough for the fragments of the longest message.
+/// The oldest ones are dropped beyond it.
+pub const MAX_URGENT: usize = u8::MAX as usize + 1;
+
+// Position of the msg in a state payload.
+const MSG_INDEX: usize = 3;
+
+/// Priority of an outgoing payload.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    Urgent, // Sent in order before any state update, the latest one per msg
+    Normal, // Only the latest one is sent
+}
+
+/// Outgoing payloads waiting to be broadcast.
+pub struct BroadcastQueue {
+    urgent: VecDeque<(u8, Vec<u8>)>,
+    latest: Option<(u8, Vec<u8>)>,
+    last_sent: Option<(u8, Vec<u8>)>,
+    last_time: Option<Instant>,
+    pub pace: Duration,
+}
+
+impl BroadcastQueue {
+    /// Creates an empty queue sending at most once per `pace`.
+    pub fn new(pace: Duration) -> Self {
+        Self {
+            urgent: VecDeque::new(),
+            latest: None,
+            last_sent: None,
+            last_time: None,
+            pace,
+        }
+    }
+
+    /// Queues a payload of the robot with the identifier.
+    pub fn push(&mut self, identifier: u8, data: Vec<u8>, priority: Priority) {
+        match priority {
+            Priority::Urgent => {
+                // Update the queued one of the same msg in its place. Fragments are all sent.
+                let queued = self.urgent.iter_mut().find(|(queued_id, queued)| {
+                    *queued_id == identifier && same_msg(queued, &data)
+                });
+                if let Some(queued) = queued {
+                    queued.1 = data;
+                    return;
+                }
+                if self.urgent.len() >= MAX_URGENT {
+                    self.urgent.pop_front();
+                    log::warn!("Broadcast Queue Full. The Oldest Urgent Payload Dropped.");
+                }
+                self.urgent.push_back((identifier, data));
+            }
+            Priority::Normal => self.latest = Some((identifier, data)),
+        }
+    }
+
+    /// Returns the next payload to broadcast, if it's time to send.
+    ///
+    /// A state update identical to the one on air is dropped.
+    pub fn pop(&mut self, now: Instant) -> Option<(u8, Vec<u8>)> {
+        if let Some(last_time) = self.last_time {
+            if now.saturating_duration_since(last_time) < self.pace {
+                return None;
+            }
+        }
+        let next = match self.urgent.pop_front() {
+            Some(next) => next,
+            None => {
+                let next = self.latest.take()?;
+                if self.last_sent.as_ref() == Some(&next) {
+                    return None;
+                }
+                next
+            }
+        };
+        self.last_sent = Some(next.clone());
+        self.last_time = Some(now);
+        Some(next)
+    }
+
+    /// Number of payloads waiting.
+    pub fn len(&self) -> usize {
+        self.urgent.len() + self.latest.is_some() as usize
+    }
+
+    /// Whether nothing is waiting.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Whether the state payloads carry the same msg.
+fn same_msg(a: &[u8], b: &[u8]) -> bool {
+    !fragment::is_fragment(a) && !fragment::is_fragment(b) && a.get(MSG_INDEX) == b.get(MSG_INDEX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn queue_test() {
+        let pace = Duration::from_millis(300);
+        let mut queue = BroadcastQueue::new(pace);
+        let start = Instant::now();
+        assert_eq!(queue.pop(start), None);
+        // State updates are coalesced.
+        queue.push(1, vec![1], Priority::Normal);
+        queue.push(1, vec![2], Priority::Normal);
+        // Urgent messages go first.
+        queue.push(1, vec![9], Priority::Urgent);
+        queue.push(1, vec![9], Priority::Urgent);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(start), Some((1, vec![9])));
+        // Paced
+        assert_eq!(queue.pop(start + Duration::from_millis(100)), None);
+        assert_eq!(queue.pop(start + pace), Some((1, vec![2])));
+        assert!(queue.is_empty());
+        // The same state is not sent again.
+        queue.push(1, vec![2], Priority::Normal);
+        assert_eq!(queue.pop(start + pace * 2), None);
+        queue.push(1, vec![3], Priority::Normal);
+        assert_eq!(queue.pop(start + pace * 2), Some((1, vec![3])));
+
+        // An urgent msg repeated with new telemetry is sent once, with the latest telemetry.
+        let halt = |telemetry: u8| vec![100, 45, 0, 4, 255, 0, 0, 0, telemetry];
+        for telemetry in 0..100 {
+            queue.push(1, halt(telemetry), Priority::Urgent);
+        }
+        queue.push(1, vec![100, 45, 0, 5, 255, 0, 0, 0, 0], Priority::Urgent);
+        assert_eq!(queue.len(), 2);
+        assert_eq!(queue.pop(start + pace * 3), Some((1, halt(99))));
+        // Fragments are all sent, up to the limit.
+        let mut queue = BroadcastQueue::new(pace);
+        for index in 0..=MAX_URGENT {
+            let fragment = fragment::Fragment {
+                msg_id: 1,
+                index: index as u8,
+                count: 0,
+                data: vec![],
+            };
+            queue.push(1, fragment.to_payload(), Priority::Urgent);
+        }
+        assert_eq!(queue.len(), MAX_URGENT);
+    }
+}
@@ -0,0 +1,209 @@
+//! Reliable Messaging Module
+//!
+//! Broadcast messages are fire-and-forget. This module adds sequence numbers,
+//! acknowledgements and bounded retries on top of them.
+//!
+//! # Flow
+//! * The sender attaches a sequence number (1-255) to a message and keeps broadcasting it.
+//! * The receiver echoes the sequence number in its `ack` field and sets `ChildMsg::Ack`.
+//! * The sender stops when the echo arrives, or gives up after the retry budget.
+//!
+//! Sequence number 0 means "unsequenced" and is never acknowledged.
+
+use std::collections::HashMap;
+
+use super::Neighbor;
+
+/// Default number of retries before giving up.
+pub const DEFAULT_MAX_RETRIES: u8 = 10;
+
+/// Default interval between retries in milliseconds.
+pub const DEFAULT_RETRY_INTERVAL: u64 = 500;
+
+/// Outgoing message waiting for an acknowledgement.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingMsg {
+    pub seq: u8,
+    pub msg: u8,
+    pub dest: u8,
+    pub retries: u8,
+    pub last_sent: u64,
+}
+
+/// Result of receiving a message from a neighbor.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Incoming {
+    New(u8),     // A new sequenced message which must be acknowledged
+    Duplicate,   // A retransmission of a message already handled
+    Unsequenced, // A message without reliability (legacy senders)
+}
+
+/// Delivery status of the outgoing message.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Delivery {
+    Idle,           // Nothing to deliver
+    Sending(u8),    // Broadcasting with this sequence number
+    Delivered(u8),  // Acknowledged by the destination
+    Failed(u8, u8), // Given up (sequence number, message)
+}
+
+/// Reliability layer for one robot.
+pub struct ReliableLink {
+    next_seq: u8,
+    pending: Option<PendingMsg>,
+    last_received: HashMap<u8, u8>,
+    delivered: Option<u8>,
+    pub max_retries: u8,
+    pub retry_interval: u64,
+}
+
+impl Default for ReliableLink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ReliableLink {
+    /// Creates a new link with default retry settings.
+    pub fn new() -> Self {
+        Self {
+            next_seq: 1,
+            pending: None,
+            last_received: HashMap::new(),
+            delivered: None,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_interval: DEFAULT_RETRY_INTERVAL,
+        }
+    }
+
+    /// Starts sending a message reliably and returns its sequence number.
+    ///
+    /// A message still waiting for an acknowledgement is replaced.
+    pub fn send(&mut self, msg: u8, dest: u8, now: u64) -> u8 {
+        let seq = self.next_seq;
+        // Skip 0, which means unsequenced.
+        self.next_seq = if self.next_seq == 255 {
+            1
+        } else {
+            self.next_seq + 1
+        };
+        if let Some(p) = &self.pending {
+            log::debug!("Pending Message Replaced. seq: {}, msg: {}", p.seq, p.msg);
+        }
+        self.pending = Some(PendingMsg {
+            seq,
+            msg,
+            dest,
+            retries: 0,
+            last_sent: now,
+        });
+        seq
+    }
+
+    /// Handles a message from a neighbor.
+    ///
+    /// Matches its `ack` against the pending message, and classifies its `seq`
+    /// so that retransmitted commands are executed only once.
+    pub fn receive(&mut self, neighbor: &Neighbor) -> Incoming {
+        // Acknowledgement for our pending message
+        if let Some(p) = &self.pending {
+            if neighbor.ack == p.seq && (p.dest == 255 || p.dest == neighbor.identifier) {
+                log::debug!("Message Delivered. seq: {}, msg: {}", p.seq, p.msg);
+                self.delivered = Some(p.seq);
+                self.pending = None;
+            }
+        }
+        // Sequence of the incoming message
+        if neighbor.seq == 0 {
+            return Incoming::Unsequenced;
+        }
+        match self.last_received.insert(neighbor.identifier, neighbor.seq) {
+            Some(last) if last == neighbor.seq => Incoming::Duplicate,
+            _ => Incoming::New(neighbor.seq),
+        }
+    }
+
+    /// Advances retry timers and reports the delivery status.
+    pub fn poll(&mut self, now: u64) -> Delivery {
+        if let Some(seq) = self.delivered.take() {
+            return Delivery::Delivered(seq);
+        }
+        match self.pending.as_mut() {
+            None => Delivery::Idle,
+            Some(p) => {
+                if now >= p.last_sent + self.retry_interval {
+                    p.retries += 1;
+                    p.last_sent = now;
+                }
+                if p.retries > self.max_retries {
+                    let failed = Delivery::Failed(p.seq, p.msg);
+                    self.pending = None;
+                    failed
+                } else {
+                    Delivery::Sending(p.seq)
+                }
+            }
+        }
+    }
+
+    /// The message currently being delivered.
+    pub fn pending(&self) -> Option<&PendingMsg> {
+        self.pending.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::ParentMsg;
+
+    fn neighbor(identifier: u8, seq: u8, ack: u8) -> Neighbor {
+        let mut n = Neighbor::from_parent_msg(ParentMsg::On);
+        n.identifier = identifier;
+        n.seq = seq;
+        n.ack = ack;
+        n
+    }
+
+    #[test]
+    fn delivery_test() {
+        let mut link = ReliableLink::new();
+        assert_eq!(link.poll(0), Delivery::Idle);
+        let seq = link.send(8, 0, 0);
+        assert_eq!(seq, 1);
+        assert_eq!(link.poll(100), Delivery::Sending(1));
+        // Ack from another robot is ignored
+        link.receive(&neighbor(5, 0, 1));
+        assert_eq!(link.poll(200), Delivery::Sending(1));
+        // Ack from the destination
+        link.receive(&neighbor(0, 0, 1));
+        assert_eq!(link.poll(300), Delivery::Delivered(1));
+        assert_eq!(link.poll(400), Delivery::Idle);
+    }
+
+    #[test]
+    fn retry_test() {
+        let mut link = ReliableLink::new();
+        link.max_retries = 2;
+        link.retry_interval = 100;
+        link.send(9, 255, 0);
+        assert_eq!(link.poll(100), Delivery::Sending(1));
+        assert_eq!(link.poll(200), Delivery::Sending(1));
+        assert_eq!(link.poll(300), Delivery::Failed(1, 9));
+        assert_eq!(link.poll(400), Delivery::Idle);
+    }
+
+    #[test]
+    fn sequence_test() {
+        let mut link = ReliableLink::new();
+        assert_eq!(link.receive(&neighbor(0, 0, 0)), Incoming::Unsequenced);
+        assert_eq!(link.receive(&neighbor(0, 3, 0)), Incoming::New(3));
+        assert_eq!(link.receive(&neighbor(0, 3, 0)), Incoming::Duplicate);
+        assert_eq!(link.receive(&neighbor(7, 3, 0)), Incoming::New(3));
+        assert_eq!(link.receive(&neighbor(0, 4, 0)), Incoming::New(4));
+        // Sequence numbers wrap around without 0
+        link.next_seq = 255;
+        assert_eq!(link.send(1, 255, 0), 255);
+        assert_eq!(link.send(1, 255, 0), 1);
+    }
+}
@@ -0,0 +1,511 @@
+//! Payload Security Module
+//!
+//! Encrypts and authenticates the advertisement payload with AES-128-CCM
+//! using a key shared by the commander and all robots.
+//!
+//! # Sealed Payload
+//! | version (1) | device (6) | counter (4, LE) | ciphertext (FIELDS_LEN + telemetry) | tag (4) |
+//!
+//! The nonce is built from the device ID, the counter and the sender's identifier.
+//! The identifiers are reassigned on collisions, so the nonce can't rely on them alone:
+//! the device ID is drawn at random once and kept in a file, so that no two robots
+//! sharing the key seal with the same nonce. The wire version and the identifier are
+//! authenticated as associated data.
+//!
+//! # Replay Protection
+//! The counter increases monotonically, also across reboots, by reserving blocks of
+//! values in a file. Receivers keep a sliding window per device ID and drop stale or
+//! repeated counters, so captured frames can't be replayed later. The windows are keyed
+//! by the device ID rather than the MAC or the identifier, which anyone can change when
+//! resending a captured frame, as the device ID is authenticated through the nonce.
+
+use aes::Aes128;
+use ccm::aead::{generic_array::GenericArray, Aead, KeyInit, Payload};
+use ccm::consts::{U13, U4};
+use ccm::Ccm;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use super::codec::WIRE_VERSION;
+use super::PAYLOAD_LEN;
+
+/// Number of payload bytes carrying fields (state_rest, temp, mode, msg, dest, seq, ack).
+pub const FIELDS_LEN: usize = 7;
+
+/// Length of the device ID.
+pub const DEVICE_ID_LEN: usize = 6;
+
+/// Length of the counter.
+pub const COUNTER_LEN: usize = 4;
+
+/// Length of the header before the ciphertext (version, device ID and counter).
+pub const HEADER_LEN: usize = 1 + DEVICE_ID_LEN + COUNTER_LEN;
+
+/// Length of the authentication tag.
+pub const TAG_LEN: usize = 4;
+
+/// Minimum length of a sealed payload (without telemetry).
+pub const SEALED_LEN: usize = HEADER_LEN + FIELDS_LEN + TAG_LEN;
+
+// Sealed payloads fit in the legacy advertisement.
+const _: () = assert!(SEALED_LEN <= PAYLOAD_LEN);
+
+/// Number of counter values reserved per write of the counter file.
+const COUNTER_RESERVE: u32 = 1000;
+
+/// Number of counters behind the highest one that are still accepted once.
+const REPLAY_WINDOW: u32 = 64;
+
+/// Maximum number of devices whose windows are kept. The least recently seen one is evicted.
+pub const MAX_REPLAY_DEVICES: usize = 256;
+
+type Cipher = Ccm<Aes128, U4, U13>;
+
+/// Seals and opens payloads with the shared key.
+pub struct PayloadCipher {
+    cipher: Cipher,
+    device: [u8; DEVICE_ID_LEN],
+    counter: Mutex<NonceCounter>,
+}
+
+/// Payload opened by `PayloadCipher::open`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Opened {
+    pub version: u8,                 // Wire version of the sender
+    pub device: [u8; DEVICE_ID_LEN], // Device ID of the sender
+    pub counter: u32,                // Counter of the sender
+    pub fields: Vec<u8>,             // Payload fields followed by the telemetry
+}
+
+impl PayloadCipher {
+    /// Creates a new cipher from a 128-bit key.
+    ///
+    /// If `counter_file` is given, the counter continues from the value stored in it.
+    /// If `device_file` is given, the device ID is kept in it, otherwise a new one is drawn.
+    /// Returns an error if a file exists but can't be read or parsed, or the device ID can't be
+    /// saved, rather than risking a nonce used before.
+    pub fn new(
+        key: [u8; 16],
+        counter_file: Option<PathBuf>,
+        device_file: Option<PathBuf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        Ok(Self {
+            cipher: Cipher::new(GenericArray::from_slice(&key)),
+            device: load_device_id(device_file)?,
+            counter: Mutex::new(NonceCounter::load(counter_file)?),
+        })
+    }
+
+    /// Creates a new cipher from a hex encoded key in the config.
+    ///
+    /// Returns `Ok(None)` if the key is empty, which disables encryption.
+    pub fn from_hex(
+        hex: &str,
+        counter_file: Option<PathBuf>,
+        device_file: Option<PathBuf>,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if hex.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(Self::new(parse_key(hex)?, counter_file, device_file)?))
+    }
+
+    /// Encrypts the payload fields and the telemetry, and appends the authentication tag.
+    ///
+    /// The padding of the legacy payload is not sent.
+    pub fn seal(&self, identifier: u8, data: &[u8]) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        if data.len() < FIELDS_LEN {
+            return Err("Payload too short to seal.".into());
+        }
+        let mut msg = data[..FIELDS_LEN].to_vec();
+        msg.extend_from_slice(data.get(PAYLOAD_LEN..).unwrap_or(&[]));
+        let counter = self.counter.lock().unwrap().next()?;
+        let nonce = build_nonce(&self.device, counter, identifier);
+        let sealed = self
+            .cipher
+            .encrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: &msg,
+                    aad: &[WIRE_VERSION, identifier],
+                },
+            )
+            .map_err(|_| "Payload encryption failed.")?;
+        let mut payload = vec![WIRE_VERSION];
+        payload.extend_from_slice(&self.device);
+        payload.extend_from_slice(&counter.to_le_bytes());
+        payload.extend(sealed);
+        Ok(payload)
+    }
+
+    /// Verifies and decrypts a sealed payload.
+    ///
+    /// Returns an error if the frame is not authentic.
+    pub fn open(&self, identifier: u8, data: &[u8]) -> Result<Opened, Box<dyn std::error::Error>> {
+        if data.len() < SEALED_LEN {
+            return Err("Sealed payload too short.".into());
+        }
+        let version = data[0];
+        let mut device = [0u8; DEVICE_ID_LEN];
+        device.copy_from_slice(&data[1..1 + DEVICE_ID_LEN]);
+        let counter = &data[1 + DEVICE_ID_LEN..HEADER_LEN];
+        let counter = u32::from_le_bytes([counter[0], counter[1], counter[2], counter[3]]);
+        let nonce = build_nonce(&device, counter, identifier);
+        let fields = self
+            .cipher
+            .decrypt(
+                GenericArray::from_slice(&nonce),
+                Payload {
+                    msg: &data[HEADER_LEN..],
+                    aad: &[version, identifier],
+                },
+            )
+            .map_err(|_| "Payload authentication failed.")?;
+        Ok(Opened {
+            version,
+            device,
+            counter,
+            fields,
+        })
+    }
+}
+
+/// Monotonic counter used as the nonce.
+struct NonceCounter {
+    next: u32,
+    reserved: u32,
+    path: Option<PathBuf>,
+}
+
+impl NonceCounter {
+    /// Loads the counter from the file, or starts from 0 without the file.
+    fn load(path: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error>> {
+        let start = match read_state(path.as_deref())? {
+            Some(s) => s
+                .trim()
+                .parse::<u32>()
+                .map_err(|e| format!("Invalid nonce counter file: {}", e))?,
+            None => 0,
+        };
+        Ok(Self {
+            next: start,
+            reserved: start,
+            path,
+        })
+    }
+
+    /// Takes the next counter value, reserving a new block in the file when needed.
+    fn next(&mut self) -> Result<u32, Box<dyn std::error::Error>> {
+        if self.next == u32::MAX {
+            return Err("Nonce counter exhausted. Change the key.".into());
+        }
+        if self.next >= self.reserved {
+            self.reserved = self.next.saturating_add(COUNTER_RESERVE);
+            if let Some(path) = &self.path {
+                fs::write(path, self.reserved.to_string())?;
+            }
+        }
+        let counter = self.next;
+        self.next += 1;
+        Ok(counter)
+    }
+}
+
+/// Sliding window of a device.
+#[derive(Debug, Clone)]
+struct Window {
+    highest: u32,
+    bitmap: u64,
+    seen: u64, // Tick of the last check
+}
+
+/// Drops stale or repeated counters per device ID.
+#[derive(Default)]
+pub struct ReplayGuard {
+    windows: HashMap<[u8; DEVICE_ID_LEN], Window>,
+    tick: u64,
+}
+
+impl ReplayGuard {
+    /// Creates an empty guard.
+    pub fn new() -> Self {
+        Self {
+            windows: HashMap::new(),
+            tick: 0,
+        }
+    }
+
+    /// Loads the highest counters seen before the last shutdown.
+    pub fn load(path: &Path) -> Self {
+        let mut guard = Self::new();
+        if let Ok(content) = fs::read_to_string(path) {
+            for line in content.lines() {
+                let Some((device, counter)) = line.split_once(' ') else {
+                    continue;
+                };
+                if let (Ok(device), Ok(highest)) = (parse_hex(device), counter.parse::<u32>()) {
+                    // Everything up to the highest one is treated as seen.
+                    guard.insert(device, highest, u64::MAX);
+                }
+            }
+        }
+        guard
+    }
+
+    /// Saves the highest counter of each device.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        let content: Vec<String> = self
+            .windows
+            .iter()
+            .map(|(device, w)| format!("{} {}", to_hex(device), w.highest))
+            .collect();
+        fs::write(path, content.join("\n"))?;
+        Ok(())
+    }
+
+    /// Returns `true` if the counter from the device is fresh, and records it.
+    pub fn check(&mut self, device: &[u8; DEVICE_ID_LEN], counter: u32) -> bool {
+        self.tick += 1;
+        let window = match self.windows.get_mut(device) {
+            Some(window) => window,
+            None => {
+                self.insert(*device, counter, 1);
+                return true;
+            }
+        };
+        window.seen = self.tick;
+        if counter > window.highest {
+            // Slide the window forward.
+            let shift = counter - window.highest;
+            window.bitmap = if shift >= REPLAY_WINDOW {
+                0
+            } else {
+                window.bitmap << shift
+            };
+            window.bitmap |= 1;
+            window.highest = counter;
+            true
+        } else {
+            let offset = window.highest - counter;
+            if offset >= REPLAY_WINDOW || window.bitmap & (1 << offset) != 0 {
+                false // Stale or repeated
+            } else {
+                window.bitmap |= 1 << offset;
+                true
+            }
+        }
+    }
+
+    /// Adds the window of a device, evicting the least recently seen one when full.
+    fn insert(&mut self, device: [u8; DEVICE_ID_LEN], highest: u32, bitmap: u64) {
+        if self.windows.len() >= MAX_REPLAY_DEVICES && !self.windows.contains_key(&device) {
+            let oldest = self
+                .windows
+                .iter()
+                .min_by_key(|(_, w)| w.seen)
+                .map(|(device, _)| *device);
+            if let Some(oldest) = oldest {
+                self.windows.remove(&oldest);
+            }
+        }
+        self.windows.insert(
+            device,
+            Window {
+                highest,
+                bitmap,
+                seen: self.tick,
+            },
+        );
+    }
+}
+
+/// Builds the 13 byte nonce from the device ID, the counter and the sender's identifier.
+fn build_nonce(device: &[u8], counter: u32, identifier: u8) -> [u8; 13] {
+    let mut nonce = [0u8; 13];
+    nonce[..DEVICE_ID_LEN].copy_from_slice(device);
+    nonce[DEVICE_ID_LEN..DEVICE_ID_LEN + COUNTER_LEN].copy_from_slice(&counter.to_le_bytes());
+    nonce[DEVICE_ID_LEN + COUNTER_LEN] = identifier;
+    nonce
+}
+
+/// Loads the device ID from the file, or draws a new one and saves it.
+fn load_device_id(
+    path: Option<PathBuf>,
+) -> Result<[u8; DEVICE_ID_LEN], Box<dyn std::error::Error>> {
+    if let Some(s) = read_state(path.as_deref())? {
+        return parse_hex(s.trim()).map_err(|e| format!("Invalid device ID file: {}", e).into());
+    }
+    let device: [u8; DEVICE_ID_LEN] = rand::random();
+    if let Some(path) = &path {
+        fs::write(path, to_hex(&device))
+            .map_err(|e| format!("Can't save the device ID: {}, {}", path.display(), e))?;
+    }
+    Ok(device)
+}
+
+/// Reads a state file. Returns `None` if there's no file yet.
+fn read_state(path: Option<&Path>) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let Some(path) = path else {
+        return Ok(None);
+    };
+    match fs::read_to_string(path) {
+        Ok(s) => Ok(Some(s)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(format!("Can't read {}: {}", path.display(), e).into()),
+    }
+}
+
+/// Formats the bytes as a hex string.
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a 32 character hex string into a 128-bit key.
+pub fn parse_key(hex: &str) -> Result<[u8; 16], Box<dyn std::error::Error>> {
+    parse_hex(hex).map_err(|_| "The key must be 32 hex characters.".into())
+}
+
+/// Parses a hex string of `N` bytes.
+fn parse_hex<const N: usize>(hex: &str) -> Result<[u8; N], Box<dyn std::error::Error>> {
+    if hex.len() != N * 2 || !hex.is_ascii() {
+        return Err(format!("Not {} hex characters.", N * 2).into());
+    }
+    let mut bytes = [0u8; N];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::{fresh, Neighbor};
+
+    const KEY: &str = "000102030405060708090a0b0c0d0e0f";
+
+    #[test]
+    fn seal_open_test() {
+        let cipher = PayloadCipher::from_hex(KEY, None, None).unwrap().unwrap();
+        let data = [100, 45, 0, 255, 255, 0, 0, 0, 0];
+        let sealed = cipher.seal(12, &data).unwrap();
+        assert_eq!(sealed.len(), SEALED_LEN);
+        let opened = cipher.open(12, &sealed).unwrap();
+        assert_eq!(opened.fields, data[..FIELDS_LEN].to_vec());
+        assert_eq!(opened.version, WIRE_VERSION);
+        // Tampered frame
+        let mut tampered = sealed.clone();
+        tampered[HEADER_LEN] ^= 0x01;
+        assert!(cipher.open(12, &tampered).is_err());
+        // Tampered version
+        let mut tampered = sealed.clone();
+        tampered[0] ^= 0x01;
+        assert!(cipher.open(12, &tampered).is_err());
+        // Spoofed identifier
+        assert!(cipher.open(13, &sealed).is_err());
+        // Another key
+        let other = PayloadCipher::new([0xAA; 16], None, None).unwrap();
+        assert!(other.open(12, &sealed).is_err());
+        // Robots sharing the key and the identifier never seal with the same nonce.
+        let other = PayloadCipher::from_hex(KEY, None, None).unwrap().unwrap();
+        let first = other.seal(12, &data).unwrap();
+        assert_ne!(first[1..HEADER_LEN], sealed[1..HEADER_LEN]);
+        assert_ne!(first[HEADER_LEN..], sealed[HEADER_LEN..]);
+        assert!(cipher.open(12, &first).is_ok());
+        // Telemetry after the legacy payload is sealed too.
+        let mut data = data.to_vec();
+        data.resize(PAYLOAD_LEN, 0);
+        data.extend_from_slice(&[0x02, 0x01, 42]);
+        let sealed = cipher.seal(12, &data).unwrap();
+        assert_eq!(sealed.len(), SEALED_LEN + 3);
+        let opened = cipher.open(12, &sealed).unwrap();
+        assert_eq!(opened.fields[FIELDS_LEN..], [0x02, 0x01, 42]);
+    }
+
+    #[test]
+    fn parse_key_test() {
+        assert_eq!(parse_key(KEY).unwrap()[15], 0x0F);
+        assert!(parse_key("0011").is_err());
+        assert!(parse_key("zz0102030405060708090a0b0c0d0e0f").is_err());
+        assert!(PayloadCipher::from_hex("", None, None).unwrap().is_none());
+    }
+
+    #[test]
+    fn counter_persistence_test() {
+        let dir = Path::new("/tmp/roktracktest/");
+        fs::create_dir_all(dir).unwrap();
+        let path = dir.join("nonce_counter_test");
+        let device_path = dir.join("device_id_test");
+        let _ = fs::remove_file(&path);
+        let _ = fs::remove_file(&device_path);
+        let counter = 1 + DEVICE_ID_LEN..HEADER_LEN;
+        let cipher =
+            PayloadCipher::new([0xAA; 16], Some(path.clone()), Some(device_path.clone())).unwrap();
+        let first = cipher.seal(1, &[0; FIELDS_LEN]).unwrap();
+        let second = cipher.seal(1, &[0; FIELDS_LEN]).unwrap();
+        assert_eq!(first[counter.clone()], 0u32.to_le_bytes());
+        assert_eq!(second[counter.clone()], 1u32.to_le_bytes());
+        // After a restart, the counter continues after the reserved block with the same device ID.
+        let cipher =
+            PayloadCipher::new([0xAA; 16], Some(path.clone()), Some(device_path.clone())).unwrap();
+        let third = cipher.seal(1, &[0; FIELDS_LEN]).unwrap();
+        assert_eq!(third[counter], COUNTER_RESERVE.to_le_bytes());
+        assert_eq!(third[1..1 + DEVICE_ID_LEN], first[1..1 + DEVICE_ID_LEN]);
+        // Broken files are errors, not a counter starting over.
+        fs::write(&path, "broken").unwrap();
+        assert!(PayloadCipher::new([0xAA; 16], Some(path), Some(device_path.clone())).is_err());
+        fs::write(&device_path, "broken").unwrap();
+        assert!(PayloadCipher::new([0xAA; 16], None, Some(device_path)).is_err());
+    }
+
+    #[test]
+    fn replay_guard_test() {
+        let (a, b) = ([0xAA; DEVICE_ID_LEN], [0xBB; DEVICE_ID_LEN]);
+        let mut guard = ReplayGuard::new();
+        assert!(guard.check(&a, 10));
+        assert!(!guard.check(&a, 10)); // Repeated
+        assert!(guard.check(&a, 12));
+        assert!(guard.check(&a, 11)); // Late but unseen
+        assert!(!guard.check(&a, 11));
+        assert!(guard.check(&b, 11)); // Another device
+        assert!(guard.check(&a, 100));
+        assert!(!guard.check(&a, 12)); // Out of window
+
+        // Restored guards reject everything up to the saved counter.
+        let path = Path::new("/tmp/roktracktest/replay_state_test");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        guard.save(path).unwrap();
+        let mut guard = ReplayGuard::load(path);
+        assert!(!guard.check(&a, 99));
+        assert!(!guard.check(&a, 100));
+        assert!(guard.check(&a, 101));
+
+        // The least recently seen device is evicted when full.
+        for i in 0..MAX_REPLAY_DEVICES as u16 {
+            let [hi, lo] = i.to_be_bytes();
+            guard.check(&[0, 0, 0, 0, hi, lo], 0);
+            guard.check(&a, 102 + i as u32);
+        }
+        assert_eq!(guard.windows.len(), MAX_REPLAY_DEVICES);
+        assert!(!guard.check(&a, 101));
+        assert!(guard.check(&b, 11));
+    }
+
+    #[test]
+    fn replay_other_sender_test() {
+        // A captured frame resent under another MAC is still a replay.
+        let cipher = PayloadCipher::from_hex(KEY, None, None).unwrap().unwrap();
+        let mut frame = vec![0xFF, 0xFF, 0xFF, 12];
+        frame.extend(cipher.seal(12, &[100, 45, 0, 255, 255, 0, 0]).unwrap());
+        let mut guard = ReplayGuard::new();
+        let mut first = Neighbor::from_manufacture_data(&frame, Some(&cipher)).unwrap();
+        first.mac = "AA:AA:AA:AA:AA:AA".to_string();
+        assert!(fresh(&mut guard, &first));
+        let mut replayed = Neighbor::from_manufacture_data(&frame, Some(&cipher)).unwrap();
+        replayed.mac = "BB:BB:BB:BB:BB:BB".to_string();
+        assert!(!fresh(&mut guard, &replayed));
+    }
+}
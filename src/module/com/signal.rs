@@ -0,0 +1,192 @@
+//! Signal Strength Module
+//!
+//! Keeps a short rolling history of RSSI per neighbor and smooths it,
+//! so that pilots can estimate how close a neighbor is.
+
+use crate::module::util::conf;
+use std::collections::{HashMap, VecDeque};
+
+/// Default number of samples kept per neighbor.
+pub const DEFAULT_CAPACITY: usize = 10;
+
+/// RSSI at 1 m from the transmitter in dBm.
+const RSSI_AT_1M: f32 = -59.0;
+
+/// Path loss exponent (2.0 in free space, larger outdoors among grass).
+const PATH_LOSS_EXPONENT: f32 = 2.5;
+
+/// Upper bound of the near zone in meters.
+const NEAR_DISTANCE: f32 = 1.5;
+
+/// Lower bound of the far zone in meters.
+const FAR_DISTANCE: f32 = 5.0;
+
+/// Rolling RSSI history per MAC address.
+pub struct SignalHistory {
+    samples: HashMap<String, VecDeque<i8>>,
+    pub capacity: usize,
+}
+
+impl Default for SignalHistory {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SignalHistory {
+    /// Creates an empty history with the default capacity.
+    pub fn new() -> Self {
+        Self {
+            samples: HashMap::new(),
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+
+    /// Records a sample and returns the smoothed RSSI of the neighbor.
+    pub fn push(&mut self, mac: &str, rssi: i8) -> f32 {
+        let history = self.samples.entry(mac.to_string()).or_default();
+        history.push_back(rssi);
+        while history.len() > self.capacity {
+            history.pop_front();
+        }
+        self.smoothed(mac).unwrap_or(rssi as f32)
+    }
+
+    /// Smoothed RSSI of the neighbor (median of the history, robust against spikes).
+    pub fn smoothed(&self, mac: &str) -> Option<f32> {
+        let history = self.samples.get(mac)?;
+        if history.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<i8> = history.iter().copied().collect();
+        sorted.sort_unstable();
+        let mid = sorted.len() / 2;
+        Some(if sorted.len() % 2 == 0 {
+            (sorted[mid - 1] as f32 + sorted[mid] as f32) / 2.0
+        } else {
+            sorted[mid] as f32
+        })
+    }
+
+    /// Samples of the neighbor, oldest first.
+    pub fn history(&self, mac: &str) -> Vec<i8> {
+        self.samples
+            .get(mac)
+            .map(|h| h.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+/// Estimates the distance in meters from an RSSI with the log-distance path loss model.
+///
+/// This is only a rough proximity estimate.
+pub fn estimate_distance(rssi: f32) -> f32 {
+    Calibration::default().distance(rssi)
+}
+
+/// Coarse distance zone of a neighbor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Near, // Close enough to collide
+    Mid,
+    Far,
+}
+
+/// Distance estimate of a neighbor.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Proximity {
+    pub distance: f32, // Meters
+    pub zone: Zone,
+}
+
+/// Radio calibration for converting RSSI into distance, measured per site and hardware.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    pub rssi_at_1m: f32,
+    pub path_loss_exponent: f32,
+    pub near_distance: f32,
+    pub far_distance: f32,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            rssi_at_1m: RSSI_AT_1M,
+            path_loss_exponent: PATH_LOSS_EXPONENT,
+            near_distance: NEAR_DISTANCE,
+            far_distance: FAR_DISTANCE,
+        }
+    }
+}
+
+impl From<&conf::Proximity> for Calibration {
+    fn from(conf: &conf::Proximity) -> Self {
+        Self {
+            rssi_at_1m: conf.rssi_at_1m,
+            path_loss_exponent: conf.path_loss_exponent,
+            near_distance: conf.near_distance,
+            far_distance: conf.far_distance,
+        }
+    }
+}
+
+impl Calibration {
+    /// Estimates the distance in meters from an RSSI.
+    pub fn distance(&self, rssi: f32) -> f32 {
+        10f32.powf((self.rssi_at_1m - rssi) / (10.0 * self.path_loss_exponent))
+    }
+
+    /// Estimates the distance and the zone from an RSSI.
+    pub fn proximity(&self, rssi: f32) -> Proximity {
+        let distance = self.distance(rssi);
+        let zone = if distance < self.near_distance {
+            Zone::Near
+        } else if distance < self.far_distance {
+            Zone::Mid
+        } else {
+            Zone::Far
+        };
+        Proximity { distance, zone }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signal_history_test() {
+        let mut history = SignalHistory::new();
+        history.capacity = 3;
+        assert_eq!(history.smoothed("AA"), None);
+        assert_eq!(history.push("AA", -60), -60.0);
+        assert_eq!(history.push("AA", -70), -65.0);
+        // A spike is suppressed.
+        assert_eq!(history.push("AA", -20), -60.0);
+        // The oldest sample is dropped.
+        history.push("AA", -80);
+        assert_eq!(history.history("AA"), vec![-70, -20, -80]);
+        assert_eq!(history.smoothed("AA"), Some(-70.0));
+        assert!(history.history("BB").is_empty());
+    }
+
+    #[test]
+    fn estimate_distance_test() {
+        assert!((estimate_distance(RSSI_AT_1M) - 1.0).abs() < 1e-6);
+        assert!(estimate_distance(-80.0) > estimate_distance(-60.0));
+    }
+
+    #[test]
+    fn proximity_test() {
+        let calibration = Calibration::default();
+        assert_eq!(calibration.proximity(-50.0).zone, Zone::Near);
+        assert_eq!(calibration.proximity(-65.0).zone, Zone::Mid);
+        assert_eq!(calibration.proximity(-90.0).zone, Zone::Far);
+        // A stronger transmitter looks nearer than it is without calibration.
+        let strong = Calibration {
+            rssi_at_1m: -45.0,
+            ..Default::default()
+        };
+        assert_eq!(strong.proximity(-50.0).zone, Zone::Mid);
+    }
+}
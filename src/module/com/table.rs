@@ -0,0 +1,151 @@
+//! Neighbor Table Module
+//!
+//! Tracks when each neighbor was last seen, expires silent ones,
+//! and reports when neighbors join or leave.
+
+use super::Neighbor;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Default time after which a silent neighbor is considered gone.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Presence change of a neighbor.
+#[derive(Debug, Clone)]
+pub enum PresenceEvent {
+    Joined(Neighbor), // First message from the neighbor
+    Left(Neighbor),   // The neighbor went silent (last known state)
+}
+
+/// Entry of the table.
+#[derive(Debug, Clone)]
+struct Entry {
+    neighbor: Neighbor,
+    last_seen: Instant,
+}
+
+/// Neighbors keyed by MAC address.
+pub struct NeighborTable {
+    entries: HashMap<String, Entry>,
+    pub timeout: Duration,
+}
+
+impl Default for NeighborTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NeighborTable {
+    /// Creates an empty table with the default timeout.
+    pub fn new() -> Self {
+        Self {
+            entries: HashMap::new(),
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Records a message from a neighbor.
+    ///
+    /// Returns `PresenceEvent::Joined` if the neighbor was not known.
+    pub fn update(&mut self, neighbor: Neighbor, now: Instant) -> Option<PresenceEvent> {
+        let key = neighbor.mac.clone();
+        let joined = !self.entries.contains_key(&key);
+        let event = joined.then(|| PresenceEvent::Joined(neighbor.clone()));
+        self.entries.insert(
+            key,
+            Entry {
+                neighbor,
+                last_seen: now,
+            },
+        );
+        event
+    }
+
+    /// Removes neighbors not seen within the timeout and returns `PresenceEvent::Left` for each.
+    pub fn expire(&mut self, now: Instant) -> Vec<PresenceEvent> {
+        let timeout = self.timeout;
+        let expired: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| now.saturating_duration_since(e.last_seen) > timeout)
+            .map(|(mac, _)| mac.clone())
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|mac| self.entries.remove(&mac))
+            .map(|e| PresenceEvent::Left(e.neighbor))
+            .collect()
+    }
+
+    /// Latest state of the neighbor.
+    pub fn get(&self, mac: &str) -> Option<&Neighbor> {
+        self.entries.get(mac).map(|e| &e.neighbor)
+    }
+
+    /// Time since the neighbor was last seen.
+    pub fn last_seen(&self, mac: &str, now: Instant) -> Option<Duration> {
+        self.entries
+            .get(mac)
+            .map(|e| now.saturating_duration_since(e.last_seen))
+    }
+
+    /// Neighbors keyed by identifier.
+    pub fn by_identifier(&self) -> HashMap<u8, Neighbor> {
+        self.entries
+            .values()
+            .map(|e| (e.neighbor.identifier, e.neighbor.clone()))
+            .collect()
+    }
+
+    /// Number of neighbors present.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether no neighbor is present.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::ParentMsg;
+
+    fn neighbor(mac: &str, identifier: u8) -> Neighbor {
+        let mut n = Neighbor::from_parent_msg(ParentMsg::On);
+        n.mac = mac.to_string();
+        n.identifier = identifier;
+        n
+    }
+
+    #[test]
+    fn presence_test() {
+        let mut table = NeighborTable::new();
+        table.timeout = Duration::from_secs(10);
+        let start = Instant::now();
+        assert!(matches!(
+            table.update(neighbor("AA", 1), start),
+            Some(PresenceEvent::Joined(_))
+        ));
+        assert!(table.update(neighbor("AA", 1), start).is_none());
+        table.update(neighbor("BB", 2), start + Duration::from_secs(5));
+        assert_eq!(table.len(), 2);
+        assert_eq!(table.by_identifier().len(), 2);
+        assert_eq!(
+            table.last_seen("BB", start + Duration::from_secs(6)),
+            Some(Duration::from_secs(1))
+        );
+        // Only AA goes silent.
+        let events = table.expire(start + Duration::from_secs(11));
+        assert_eq!(events.len(), 1);
+        match &events[0] {
+            PresenceEvent::Left(n) => assert_eq!(n.mac, "AA"),
+            _ => panic!("unexpected event"),
+        }
+        assert!(table.get("AA").is_none());
+        assert!(table.get("BB").is_some());
+    }
+}
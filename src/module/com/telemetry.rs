@@ -0,0 +1,312 @@
+//! Telemetry Module
+//!
+//! Extended advertisements can carry much more than the 31 bytes of a legacy one.
+//! This module encodes the additional telemetry appended after the legacy payload.
+//!
+//! # Format
+//! A sequence of `| tag (1) | length (1) | value (length) |` entries.
+//! Unknown tags are skipped, so new entries can be added without breaking old receivers.
+
+/// Battery voltage in mV (u16, LE)
+const TAG_BATTERY: u8 = 0x01;
+/// Work progress in % (u8)
+const TAG_PROGRESS: u8 = 0x02;
+/// Latitude and longitude in 1e-7 degrees (i32 LE x 2)
+const TAG_POSITION: u8 = 0x03;
+/// Free text message (UTF-8)
+const TAG_TEXT: u8 = 0x04;
+/// GPS fix quality and number of satellites (u8 x 2)
+const TAG_GPS_FIX: u8 = 0x05;
+/// Synchronized clock in ms since the UNIX epoch (i64 LE)
+const TAG_CLOCK: u8 = 0x06;
+/// Compass heading in 0.01 degrees clockwise from north (u16 LE)
+const TAG_HEADING: u8 = 0x07;
+/// Distance to the obstacle ahead in cm, measured by an ultrasonic sensor (u16 LE)
+const TAG_RANGE: u8 = 0x08;
+/// Covered area in m², area per hour in m² and minutes to completion (u16 LE x 3, 0xFFFF: unknown)
+const TAG_WORK: u8 = 0x09;
+/// Detections per second in 0.1 fps and latency from the capture in ms (u16 LE x 2)
+const TAG_VISION: u8 = 0x0A;
+
+/// Maximum length of the text message in bytes.
+pub const MAX_TEXT_LEN: usize = 64;
+
+// Scale of the position values.
+const POSITION_SCALE: f64 = 1e7;
+// Scale of the heading value.
+const HEADING_SCALE: f32 = 100.0;
+// Scale of the FPS value.
+const FPS_SCALE: f32 = 10.0;
+
+/// GPS fix quality as reported in NMEA GGA sentences.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FixQuality {
+    NoFix,
+    Gps,
+    Dgps,
+    RtkFixed,
+    RtkFloat,
+    Unknown,
+}
+
+impl FixQuality {
+    /// Convert an integer to a fix quality.
+    pub fn from_u8(i: u8) -> FixQuality {
+        match i {
+            0 => FixQuality::NoFix,
+            1 => FixQuality::Gps,
+            2 => FixQuality::Dgps,
+            4 => FixQuality::RtkFixed,
+            5 => FixQuality::RtkFloat,
+            _ => FixQuality::Unknown,
+        }
+    }
+
+    /// Convert a fix quality to an integer.
+    pub fn to_u8(quality: FixQuality) -> u8 {
+        match quality {
+            FixQuality::NoFix => 0,
+            FixQuality::Gps => 1,
+            FixQuality::Dgps => 2,
+            FixQuality::RtkFixed => 4,
+            FixQuality::RtkFloat => 5,
+            FixQuality::Unknown => 255,
+        }
+    }
+}
+
+/// GPS fix state.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsFix {
+    pub quality: FixQuality,
+    pub satellites: u8,
+}
+
+/// Work rate of a fill-type job.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Work {
+    pub area: u16,
+    pub area_per_hour: u16,
+    pub eta_min: Option<u16>,
+}
+
+/// Metrics of the vision pipeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vision {
+    pub fps: f32,
+    pub latency_ms: u16,
+}
+
+/// Additional telemetry of a robot.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Telemetry {
+    pub battery_mv: Option<u16>,
+    pub progress: Option<u8>,
+    pub position: Option<(f64, f64)>,
+    pub text: Option<String>,
+    pub gps_fix: Option<GpsFix>,
+    pub clock_ms: Option<i64>,
+    pub heading: Option<f32>,
+    pub range_cm: Option<u16>,
+    pub work: Option<Work>,
+    pub vision: Option<Vision>,
+}
+
+impl Telemetry {
+    /// Encodes the available entries.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = vec![];
+        if let Some(mv) = self.battery_mv {
+            push_entry(&mut buf, TAG_BATTERY, &mv.to_le_bytes());
+        }
+        if let Some(progress) = self.progress {
+            push_entry(&mut buf, TAG_PROGRESS, &[progress]);
+        }
+        if let Some((lat, lon)) = self.position {
+            let mut value = ((lat * POSITION_SCALE) as i32).to_le_bytes().to_vec();
+            value.extend_from_slice(&((lon * POSITION_SCALE) as i32).to_le_bytes());
+            push_entry(&mut buf, TAG_POSITION, &value);
+        }
+        if let Some(fix) = self.gps_fix {
+            push_entry(
+                &mut buf,
+                TAG_GPS_FIX,
+                &[FixQuality::to_u8(fix.quality), fix.satellites],
+            );
+        }
+        if let Some(clock) = self.clock_ms {
+            push_entry(&mut buf, TAG_CLOCK, &clock.to_le_bytes());
+        }
+        if let Some(heading) = self.heading {
+            let value = (heading.rem_euclid(360.0) * HEADING_SCALE) as u16;
+            push_entry(&mut buf, TAG_HEADING, &value.to_le_bytes());
+        }
+        if let Some(range) = self.range_cm {
+            push_entry(&mut buf, TAG_RANGE, &range.to_le_bytes());
+        }
+        if let Some(work) = self.work {
+            let mut value = work.area.to_le_bytes().to_vec();
+            value.extend_from_slice(&work.area_per_hour.to_le_bytes());
+            value.extend_from_slice(&work.eta_min.unwrap_or(u16::MAX).to_le_bytes());
+            push_entry(&mut buf, TAG_WORK, &value);
+        }
+        if let Some(vision) = self.vision {
+            let mut value = ((vision.fps * FPS_SCALE).round() as u16)
+                .to_le_bytes()
+                .to_vec();
+            value.extend_from_slice(&vision.latency_ms.to_le_bytes());
+            push_entry(&mut buf, TAG_VISION, &value);
+        }
+        if let Some(text) = &self.text {
+            // Cut at a character boundary.
+            let mut end = text.len().min(MAX_TEXT_LEN);
+            while !text.is_char_boundary(end) {
+                end -= 1;
+            }
+            push_entry(&mut buf, TAG_TEXT, &text.as_bytes()[..end]);
+        }
+        buf
+    }
+
+    /// Decodes entries, ignoring unknown tags and a truncated tail.
+    pub fn decode(buf: &[u8]) -> Self {
+        let mut telemetry = Self::default();
+        let mut pos = 0;
+        while pos + 2 <= buf.len() {
+            let tag = buf[pos];
+            let len = buf[pos + 1] as usize;
+            let value = match buf.get(pos + 2..pos + 2 + len) {
+                Some(value) => value,
+                None => break,
+            };
+            match (tag, len) {
+                (TAG_BATTERY, 2) => {
+                    telemetry.battery_mv = Some(u16::from_le_bytes([value[0], value[1]]))
+                }
+                (TAG_PROGRESS, 1) => telemetry.progress = Some(value[0]),
+                (TAG_POSITION, 8) => {
+                    let lat = i32::from_le_bytes([value[0], value[1], value[2], value[3]]);
+                    let lon = i32::from_le_bytes([value[4], value[5], value[6], value[7]]);
+                    telemetry.position =
+                        Some((lat as f64 / POSITION_SCALE, lon as f64 / POSITION_SCALE));
+                }
+                (TAG_TEXT, _) => telemetry.text = Some(String::from_utf8_lossy(value).to_string()),
+                (TAG_CLOCK, 8) => {
+                    let mut bytes = [0u8; 8];
+                    bytes.copy_from_slice(value);
+                    telemetry.clock_ms = Some(i64::from_le_bytes(bytes));
+                }
+                (TAG_HEADING, 2) => {
+                    let value = u16::from_le_bytes([value[0], value[1]]);
+                    telemetry.heading = Some(value as f32 / HEADING_SCALE)
+                }
+                (TAG_RANGE, 2) => {
+                    telemetry.range_cm = Some(u16::from_le_bytes([value[0], value[1]]))
+                }
+                (TAG_WORK, 6) => {
+                    let eta_min = u16::from_le_bytes([value[4], value[5]]);
+                    telemetry.work = Some(Work {
+                        area: u16::from_le_bytes([value[0], value[1]]),
+                        area_per_hour: u16::from_le_bytes([value[2], value[3]]),
+                        eta_min: (eta_min != u16::MAX).then_some(eta_min),
+                    })
+                }
+                (TAG_VISION, 4) => {
+                    telemetry.vision = Some(Vision {
+                        fps: u16::from_le_bytes([value[0], value[1]]) as f32 / FPS_SCALE,
+                        latency_ms: u16::from_le_bytes([value[2], value[3]]),
+                    })
+                }
+                (TAG_GPS_FIX, 2) => {
+                    telemetry.gps_fix = Some(GpsFix {
+                        quality: FixQuality::from_u8(value[0]),
+                        satellites: value[1],
+                    })
+                }
+                _ => {}
+            }
+            pos += 2 + len;
+        }
+        telemetry
+    }
+}
+
+/// Appends one TLV entry.
+fn push_entry(buf: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    buf.push(tag);
+    buf.push(value.len() as u8);
+    buf.extend_from_slice(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_test() {
+        let telemetry = Telemetry {
+            battery_mv: Some(12600),
+            progress: Some(42),
+            position: Some((35.6812362, 139.7671248)),
+            text: Some("Mission Complete".to_string()),
+            gps_fix: Some(GpsFix {
+                quality: FixQuality::RtkFixed,
+                satellites: 14,
+            }),
+            clock_ms: Some(1_700_000_000_123),
+            heading: Some(271.5),
+            range_cm: Some(85),
+            work: Some(Work {
+                area: 150,
+                area_per_hour: 450,
+                eta_min: None,
+            }),
+            vision: Some(Vision {
+                fps: 4.5,
+                latency_ms: 230,
+            }),
+        };
+        let buf = telemetry.encode();
+        let decoded = Telemetry::decode(&buf);
+        assert_eq!(decoded.battery_mv, Some(12600));
+        assert_eq!(decoded.progress, Some(42));
+        let (lat, lon) = decoded.position.unwrap();
+        assert!((lat - 35.6812362).abs() < 1e-6);
+        assert!((lon - 139.7671248).abs() < 1e-6);
+        assert_eq!(decoded.text, telemetry.text);
+        assert_eq!(decoded.gps_fix, telemetry.gps_fix);
+        assert_eq!(decoded.clock_ms, telemetry.clock_ms);
+        assert_eq!(decoded.heading, Some(271.5));
+        assert_eq!(decoded.range_cm, Some(85));
+        assert_eq!(decoded.work, telemetry.work);
+        assert_eq!(decoded.vision, telemetry.vision);
+        assert_eq!(
+            FixQuality::from_u8(FixQuality::to_u8(FixQuality::Dgps)),
+            FixQuality::Dgps
+        );
+        // Empty
+        assert_eq!(Telemetry::default().encode(), Vec::<u8>::new());
+        assert_eq!(Telemetry::decode(&[]), Telemetry::default());
+    }
+
+    #[test]
+    fn decode_robustness_test() {
+        // Unknown tag is skipped.
+        let buf = [0x7F, 0x02, 0xAA, 0xBB, TAG_PROGRESS, 0x01, 50];
+        assert_eq!(Telemetry::decode(&buf).progress, Some(50));
+        // Truncated tail is ignored.
+        let buf = [TAG_PROGRESS, 0x01, 50, TAG_BATTERY, 0x02, 0x10];
+        let decoded = Telemetry::decode(&buf);
+        assert_eq!(decoded.progress, Some(50));
+        assert_eq!(decoded.battery_mv, None);
+        // Long text is cut.
+        let telemetry = Telemetry {
+            text: Some("a".repeat(100)),
+            ..Default::default()
+        };
+        assert_eq!(
+            Telemetry::decode(&telemetry.encode()).text.unwrap().len(),
+            MAX_TEXT_LEN
+        );
+    }
+}
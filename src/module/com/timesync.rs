@@ -0,0 +1,122 @@
+//! Time Synchronization Module
+//!
+//! Robots without network access have drifting clocks. Each robot broadcasts its
+//! synchronized clock in the telemetry and follows the clock of the commander
+//! (identifier 0) or, without one, of the neighbor with the lowest identifier.
+//! So the whole fleet converges to a single reference clock.
+//!
+//! The transit time of a broadcast is ignored, so the accuracy is some tens of ms.
+//! The clock is carried in the telemetry, which a legacy BLE advertisement can't hold.
+
+use std::time::{Duration, Instant};
+
+/// Default time after which a silent reference is abandoned.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+// Weight of a new sample in the smoothed offset (1/n).
+const SMOOTHING: i64 = 4;
+
+/// Clock offset to the reference robot.
+pub struct TimeSync {
+    offset_ms: i64,
+    reference: Option<u8>,
+    last_sync: Option<Instant>,
+    pub timeout: Duration,
+}
+
+impl Default for TimeSync {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TimeSync {
+    /// Creates an unsynchronized clock with the default timeout.
+    pub fn new() -> Self {
+        Self {
+            offset_ms: 0,
+            reference: None,
+            last_sync: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+
+    /// Synchronized time in ms since the UNIX epoch from the local one.
+    pub fn now_ms(&self, local_ms: i64) -> i64 {
+        local_ms + self.offset_ms
+    }
+
+    /// Current offset to the local clock in ms.
+    pub fn offset_ms(&self) -> i64 {
+        self.offset_ms
+    }
+
+    /// Identifier of the robot followed, or `None` if this robot is the reference.
+    pub fn reference(&self, now: Instant) -> Option<u8> {
+        match self.last_sync {
+            Some(last) if now.saturating_duration_since(last) <= self.timeout => self.reference,
+            _ => None,
+        }
+    }
+
+    /// Takes a clock broadcast by the sender into account.
+    ///
+    /// Returns `true` if the sender became the new reference.
+    pub fn update(
+        &mut self,
+        my_identifier: u8,
+        sender: u8,
+        clock_ms: i64,
+        local_ms: i64,
+        now: Instant,
+    ) -> bool {
+        // Only follow robots ranked higher (lower identifier) than the current reference.
+        let candidate = match self.reference(now) {
+            Some(reference) => sender <= reference,
+            None => sender < my_identifier,
+        };
+        if !candidate {
+            return false;
+        }
+        let sample = clock_ms - local_ms;
+        let changed = self.reference(now) != Some(sender);
+        if changed {
+            self.offset_ms = sample;
+        } else {
+            self.offset_ms += (sample - self.offset_ms) / SMOOTHING;
+        }
+        self.reference = Some(sender);
+        self.last_sync = Some(now);
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn time_sync_test() {
+        let mut sync = TimeSync::new();
+        let now = Instant::now();
+        // The robot with the lowest identifier is the reference.
+        assert!(!sync.update(5, 9, 10_000, 0, now));
+        assert_eq!(sync.reference(now), None);
+        assert_eq!(sync.now_ms(1_000), 1_000);
+        // Follow a lower identifier.
+        assert!(sync.update(5, 3, 10_000, 0, now));
+        assert_eq!(sync.now_ms(1_000), 11_000);
+        // Samples from the reference are smoothed.
+        assert!(!sync.update(5, 3, 10_400, 0, now));
+        assert_eq!(sync.offset_ms(), 10_100);
+        // Ignore higher identifiers than the reference.
+        assert!(!sync.update(5, 4, 0, 0, now));
+        // The commander has priority.
+        assert!(sync.update(5, 0, 20_000, 0, now));
+        assert_eq!(sync.offset_ms(), 20_000);
+        // A silent reference is abandoned.
+        let later = now + DEFAULT_TIMEOUT + Duration::from_secs(1);
+        assert_eq!(sync.reference(later), None);
+        assert!(sync.update(5, 4, 30_000, 0, later));
+    }
+}
@@ -0,0 +1,64 @@
+//! Transport Module
+//!
+//! Abstracts the link used to exchange states and commands with neighbors,
+//! so that BLE advertising is just one of interchangeable backends.
+
+use super::channel::BoundedSender;
+use super::checksum;
+use super::espnow::EspNowTransport;
+use super::fragment;
+use super::lora::LoraTransport;
+use super::uart::UartTransport;
+use super::udp::UdpTransport;
+use super::{BleBroadCast, Neighbor};
+use crate::module::util::init::RoktrackProperty;
+use std::thread::JoinHandle;
+
+/// Features of a transport.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capabilities {
+    pub name: &'static str,  // Name for logging
+    pub max_payload: usize,  // Maximum payload length in bytes (after the identifier)
+    pub bidirectional: bool, // Whether the robot can receive as well as send
+    pub range_m: u32,        // Typical range in meters
+}
+
+/// Backend for exchanging states and commands with neighbors.
+pub trait CommTransport: Send {
+    /// Broadcasts the payload of the robot with the identifier.
+    fn send(&self, identifier: &u8, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Receives messages from neighbors in a thread and sends them via a channel.
+    fn listen(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()>;
+
+    /// Features of the transport.
+    fn capabilities(&self) -> Capabilities;
+
+    /// Sends a message larger than one frame in fragments.
+    fn send_message(
+        &self,
+        identifier: &u8,
+        msg_id: u8,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        for mut payload in fragment::split(msg_id, data, self.capabilities().max_payload)? {
+            checksum::set_crc(*identifier, &mut payload);
+            self.send(identifier, payload)?;
+        }
+        Ok(())
+    }
+}
+
+/// Creates the transport selected in the config.
+pub fn from_property(
+    property: RoktrackProperty,
+) -> Result<Box<dyn CommTransport>, Box<dyn std::error::Error>> {
+    match property.conf.com.transport.as_str() {
+        "" | "ble" => Ok(Box::new(BleBroadCast::new(property)?)),
+        "lora" => Ok(Box::new(LoraTransport::new(property)?)),
+        "udp" => Ok(Box::new(UdpTransport::new(property)?)),
+        "espnow" => Ok(Box::new(EspNowTransport::new(property)?)),
+        "uart" => Ok(Box::new(UartTransport::new(property)?)),
+        other => Err(format!("Unknown transport: {}", other).into()),
+    }
+}
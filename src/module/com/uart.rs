@@ -0,0 +1,123 @@
+//! Serial UART Transport Module
+//!
+//! Exchanges states and commands over a wire with a physically attached controller,
+//! e.g. of a trailer or an implement, which doesn't need its own BLE stack.
+//!
+//! # Serial Frames
+//! `| 0x7E | len | data (len) | checksum |` as with the ESP-NOW bridge, where `data` is
+//! `| identifier | payload (sealed if a key is set) |`.
+
+use super::channel::BoundedSender;
+use super::espnow::{encode_frame, FrameDecoder};
+use super::secure::{PayloadCipher, ReplayGuard};
+use super::transport::{Capabilities, CommTransport};
+use super::{cipher_from_property, fresh, replay_state_path, Neighbor, REPLAY_SAVE_INTERVAL};
+use crate::module::util::init::RoktrackProperty;
+use rppal::uart::{Parity, Uart};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// Maximum data length of a serial frame.
+pub const MAX_FRAME_LEN: usize = u8::MAX as usize;
+
+// Timeout of a UART read, so that the lock is released for sending.
+const READ_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// UART Transport Handler
+pub struct UartTransport {
+    uart: Arc<Mutex<Uart>>,
+    cipher: Option<Arc<PayloadCipher>>,
+    replay_state: PathBuf,
+}
+
+impl UartTransport {
+    /// Creates a new instance of UART Transport Handler with the given property.
+    pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+        let conf = &property.conf.uart;
+        let mut uart = Uart::with_path(&conf.port, conf.baud_rate, Parity::None, 8, 1)?;
+        uart.set_read_mode(0, READ_TIMEOUT)?;
+        Ok(Self {
+            uart: Arc::new(Mutex::new(uart)),
+            cipher: cipher_from_property(&property)?,
+            replay_state: replay_state_path(&property, "uart"),
+        })
+    }
+}
+
+impl CommTransport for UartTransport {
+    /// Writes the payload to the wire.
+    fn send(&self, identifier: &u8, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut data = vec![*identifier];
+        match &self.cipher {
+            Some(cipher) => data.extend(cipher.seal(*identifier, &payload)?),
+            None => data.extend(payload),
+        }
+        data.truncate(MAX_FRAME_LEN);
+        self.uart.lock().unwrap().write(&encode_frame(&data))?;
+        Ok(())
+    }
+
+    /// Reads frames from the attached controller.
+    fn listen(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let uart = self.uart.clone();
+        let cipher = self.cipher.clone();
+        let replay_state = self.replay_state.clone();
+        thread::spawn(move || {
+            log::debug!("UART Thread Started");
+            // Restore the counters seen before the last shutdown.
+            let mut guard = ReplayGuard::load(&replay_state);
+            let mut last_save = Instant::now();
+            let mut decoder = FrameDecoder::new();
+            let mut buf = [0u8; 64];
+            loop {
+                let len = match uart.lock().unwrap().read(&mut buf) {
+                    Ok(len) => len,
+                    Err(e) => {
+                        log::error!("UART Read Failed: {}", e);
+                        break;
+                    }
+                };
+                if len == 0 {
+                    // Let the sender take the lock.
+                    thread::sleep(Duration::from_millis(1));
+                    continue;
+                }
+                for frame in decoder.push(&buf[..len]) {
+                    let mut neighbor = match Neighbor::from_frame(&frame, cipher.as_deref()) {
+                        Ok(neighbor) => neighbor,
+                        Err(e) => {
+                            log::debug!("UART Frame Rejected: {}", e);
+                            continue;
+                        }
+                    };
+                    // There is no MAC address, so senders are told apart by the identifier.
+                    let sender = format!("uart:{}", neighbor.identifier);
+                    if !fresh(&mut guard, &neighbor) {
+                        log::warn!("UART Replay Dropped From: {}", sender);
+                        continue;
+                    }
+                    if last_save.elapsed() > REPLAY_SAVE_INTERVAL {
+                        let _ = guard.save(&replay_state);
+                        last_save = Instant::now();
+                    }
+                    neighbor.mac = sender;
+                    if tx.send(neighbor).is_err() {
+                        return;
+                    }
+                }
+            }
+        })
+    }
+
+    /// Features of the wired link.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            name: "uart",
+            max_payload: MAX_FRAME_LEN - 1,
+            bidirectional: true,
+            range_m: 5,
+        }
+    }
+}
@@ -0,0 +1,197 @@
+//! UDP Multicast Transport Module
+//!
+//! Exchanges states and commands over UDP multicast on the local network,
+//! for yards covered by Wi-Fi and for development on machines without BLE.
+//!
+//! # Datagram
+//! | identifier (1) | payload (sealed if a key is set) |
+
+use super::channel::BoundedSender;
+use super::secure::{PayloadCipher, ReplayGuard};
+use super::transport::{Capabilities, CommTransport};
+use super::{cipher_from_property, fresh, replay_state_path, Neighbor, REPLAY_SAVE_INTERVAL};
+use crate::module::util::init::RoktrackProperty;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+use std::os::unix::io::FromRawFd;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Instant;
+
+/// Maximum length of a datagram.
+pub const MAX_DATAGRAM_LEN: usize = 512;
+
+/// UDP Multicast Transport Handler
+pub struct UdpTransport {
+    socket: UdpSocket,
+    sender: UdpSocket,
+    group: SocketAddrV4,
+    cipher: Option<Arc<PayloadCipher>>,
+    replay_state: PathBuf,
+}
+
+impl UdpTransport {
+    /// Creates a new instance of UDP Multicast Transport Handler with the given property.
+    pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+        let udp = &property.conf.udp;
+        let group: Ipv4Addr = udp.group.parse()?;
+        if !group.is_multicast() {
+            return Err(format!("Not a multicast address: {}", group).into());
+        }
+        let interface: Ipv4Addr = udp.interface.parse()?;
+
+        // Several robots on the same host share the port, so enable SO_REUSEADDR.
+        let socket = bind_reuse(udp.port)?;
+        socket.join_multicast_v4(&group, &interface)?;
+
+        // Send from an ephemeral port so that our own datagrams can be told apart.
+        let sender = UdpSocket::bind(SocketAddrV4::new(interface, 0))?;
+        sender.set_multicast_loop_v4(true)?;
+        sender.set_multicast_ttl_v4(1)?;
+
+        Ok(Self {
+            socket,
+            sender,
+            group: SocketAddrV4::new(group, udp.port),
+            cipher: cipher_from_property(&property)?,
+            replay_state: replay_state_path(&property, "udp"),
+        })
+    }
+
+    /// Address our own datagrams come back from.
+    ///
+    /// The sender bound to all interfaces sends from the one routed to the group.
+    fn own_addr(&self) -> io::Result<SocketAddr> {
+        let mut addr = self.sender.local_addr()?;
+        if addr.ip().is_unspecified() {
+            let probe = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))?;
+            probe.connect(self.group)?;
+            addr.set_ip(probe.local_addr()?.ip());
+        }
+        Ok(addr)
+    }
+}
+
+impl CommTransport for UdpTransport {
+    /// Sends the payload to the multicast group.
+    fn send(&self, identifier: &u8, payload: Vec<u8>) -> Result<(), Box<dyn std::error::Error>> {
+        let mut datagram = vec![*identifier];
+        match &self.cipher {
+            Some(cipher) => datagram.extend(cipher.seal(*identifier, &payload)?),
+            None => datagram.extend(payload),
+        }
+        datagram.truncate(MAX_DATAGRAM_LEN);
+        self.sender.send_to(&datagram, self.group)?;
+        Ok(())
+    }
+
+    /// Receives datagrams from the multicast group.
+    fn listen(&self, tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+        let socket = self
+            .socket
+            .try_clone()
+            .expect("Can't clone the UDP socket.");
+        let cipher = self.cipher.clone();
+        let own_addr = self.own_addr().ok();
+        let replay_state = self.replay_state.clone();
+        thread::spawn(move || {
+            log::debug!("UDP Thread Started");
+            // Restore the counters seen before the last shutdown.
+            let mut guard = ReplayGuard::load(&replay_state);
+            let mut last_save = Instant::now();
+            let mut buf = [0u8; MAX_DATAGRAM_LEN];
+            loop {
+                let (len, src) = match socket.recv_from(&mut buf) {
+                    Ok(received) => received,
+                    Err(e) => {
+                        log::error!("UDP Receive Failed: {}", e);
+                        break;
+                    }
+                };
+                // Own datagrams come back through the loopback.
+                if Some(src) == own_addr {
+                    continue;
+                }
+                let mut neighbor = match Neighbor::from_frame(&buf[..len], cipher.as_deref()) {
+                    Ok(neighbor) => neighbor,
+                    Err(e) => {
+                        log::debug!("UDP Datagram Rejected From: {}, {}", src, e);
+                        continue;
+                    }
+                };
+                if !fresh(&mut guard, &neighbor) {
+                    log::warn!("UDP Replay Dropped From: {}", src);
+                    continue;
+                }
+                if last_save.elapsed() > REPLAY_SAVE_INTERVAL {
+                    let _ = guard.save(&replay_state);
+                    last_save = Instant::now();
+                }
+                neighbor.mac = src.to_string();
+                log::debug!("UDP Received From: {}, Content: {:?}", src, &buf[..len]);
+                if tx.send(neighbor).is_err() {
+                    break;
+                }
+            }
+        })
+    }
+
+    /// Features of the UDP multicast.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            name: "udp",
+            max_payload: MAX_DATAGRAM_LEN - 1,
+            bidirectional: true,
+            range_m: 100,
+        }
+    }
+}
+
+/// Binds a UDP socket to the port on all interfaces with SO_REUSEADDR.
+fn bind_reuse(port: u16) -> Result<UdpSocket, Box<dyn std::error::Error>> {
+    let fd = unsafe {
+        libc::socket(
+            libc::AF_INET,
+            libc::SOCK_DGRAM | libc::SOCK_CLOEXEC,
+            libc::IPPROTO_UDP,
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    // Wrap the descriptor first so it is closed on every error path.
+    let socket = unsafe { UdpSocket::from_raw_fd(fd) };
+
+    let enable: libc::c_int = 1;
+    let res = unsafe {
+        libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_REUSEADDR,
+            &enable as *const libc::c_int as *const libc::c_void,
+            std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+
+    let addr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: port.to_be(),
+        sin_addr: libc::in_addr { s_addr: 0 }, // INADDR_ANY
+        sin_zero: [0; 8],
+    };
+    let res = unsafe {
+        libc::bind(
+            fd,
+            &addr as *const libc::sockaddr_in as *const libc::sockaddr,
+            std::mem::size_of::<libc::sockaddr_in>() as libc::socklen_t,
+        )
+    };
+    if res < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(socket)
+}
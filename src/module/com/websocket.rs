@@ -0,0 +1,203 @@
+//! WebSocket Stream Module
+//!
+//! Streams live neighbor updates, state snapshots and detections as JSON to any
+//! connected client, so that dashboards and debugging tools can observe the robot.
+//!
+//! # Messages
+//! Every message is a JSON object with a `type` of `neighbor`, `state` or `detections`.
+
+use super::Neighbor;
+use crate::module::pilot::RoktrackState;
+use crate::module::util::conf::WebSocket as WebSocketConf;
+use crate::module::vision::detector::Detection;
+use serde_json::{json, Value};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use tungstenite::{Message, WebSocket};
+
+// Time to wait for a slow client before dropping it.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+
+/// WebSocket Stream Server
+pub struct WebSocketServer {
+    clients: Arc<Mutex<Vec<WebSocket<TcpStream>>>>,
+    tx: Sender<String>,
+}
+
+impl WebSocketServer {
+    /// Binds the server and starts accepting clients.
+    pub fn new(conf: WebSocketConf) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind((conf.bind.as_str(), conf.port))?;
+        log::info!("WebSocket Stream Listening on {}:{}", conf.bind, conf.port);
+        let (tx, rx) = mpsc::channel();
+        let server = Self {
+            clients: Arc::new(Mutex::new(vec![])),
+            tx,
+        };
+        server.accept(listener);
+        server.forward(rx);
+        Ok(server)
+    }
+
+    /// Accepts clients in a thread.
+    fn accept(&self, listener: TcpListener) -> JoinHandle<()> {
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("WebSocket Accept Failed: {}", e);
+                        continue;
+                    }
+                };
+                let peer = stream
+                    .peer_addr()
+                    .map(|a| a.to_string())
+                    .unwrap_or_default();
+                let _ = stream.set_write_timeout(Some(WRITE_TIMEOUT));
+                match tungstenite::accept(stream) {
+                    Ok(socket) => {
+                        log::info!("WebSocket Client Connected: {}", peer);
+                        clients.lock().unwrap().push(socket);
+                    }
+                    Err(e) => log::warn!("WebSocket Handshake Failed: {}, {}", peer, e),
+                }
+            }
+        })
+    }
+
+    /// Sends published messages to every client in a thread, so that publishers never block.
+    fn forward(&self, rx: Receiver<String>) -> JoinHandle<()> {
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            for text in rx {
+                // Clients that fail or are too slow are dropped.
+                clients
+                    .lock()
+                    .unwrap()
+                    .retain_mut(|client| client.send(Message::Text(text.clone())).is_ok());
+            }
+        })
+    }
+
+    /// Publishes a message to the clients.
+    pub fn publish(&self, message: Value) {
+        let _ = self.tx.send(message.to_string());
+    }
+}
+
+/// Message carrying the state of a neighbor.
+pub fn neighbor_message(neighbor: &Neighbor) -> Value {
+    let telemetry = &neighbor.telemetry;
+    json!({
+        "type": "neighbor",
+        "identifier": neighbor.identifier,
+        "mac": neighbor.mac,
+        "state": neighbor.state,
+        "rest": neighbor.rest,
+        "pi_temp": neighbor.pi_temp,
+        "mode": format!("{:?}", neighbor.mode),
+        "msg": neighbor.msg,
+        "rssi": neighbor.rssi,
+        "rssi_smoothed": neighbor.rssi_smoothed,
+        "timestamp": neighbor.timestamp,
+        "battery_mv": telemetry.battery_mv,
+        "progress": telemetry.progress,
+        "position": telemetry.position,
+        "work": telemetry.work.map(|work| json!({
+            "area": work.area,
+            "area_per_hour": work.area_per_hour,
+            "eta_min": work.eta_min,
+        })),
+        "vision": telemetry.vision.map(|vision| json!({
+            "fps": vision.fps,
+            "latency_ms": vision.latency_ms,
+        })),
+    })
+}
+
+/// Message carrying a snapshot of my state.
+pub fn state_message(state: &RoktrackState) -> Value {
+    json!({
+        "type": "state",
+        "identifier": state.identifier,
+        "state": state.state,
+        "mode": format!("{:?}", state.mode),
+        "rest": state.rest,
+        "phase": format!("{:?}", state.phase),
+        "turn_count": state.turn_count,
+        "pi_temp": state.pi_temp,
+        "msg": state.msg,
+        "model": state.model,
+        "seq": state.seq,
+        "ack": state.ack,
+        "work": state.telemetry.work.map(|work| json!({
+            "area": work.area,
+            "area_per_hour": work.area_per_hour,
+            "eta_min": work.eta_min,
+        })),
+        "vision": state.vision.map(|vision| json!({
+            "fps": vision.fps,
+            "latency_ms": vision.latency_ms,
+        })),
+        "timestamp": chrono::Utc::now().timestamp_millis(),
+    })
+}
+
+/// Message carrying the detections of an inference.
+pub fn detections_message(detections: &[Detection]) -> Value {
+    let detections: Vec<Value> = detections
+        .iter()
+        .map(|d| {
+            json!({
+                "cls": d.cls,
+                "prob": d.prob,
+                "box": [d.x1, d.y1, d.x2, d.y2],
+                "ids": d.ids,
+                "camera": d.camera,
+            })
+        })
+        .collect();
+    json!({
+        "type": "detections",
+        "detections": detections,
+        "timestamp": chrono::Utc::now().timestamp_millis(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::ParentMsg;
+
+    #[test]
+    fn message_test() {
+        let mut neighbor = Neighbor::from_parent_msg(ParentMsg::On);
+        neighbor.identifier = 3;
+        neighbor.telemetry.progress = Some(40);
+        let message = neighbor_message(&neighbor);
+        assert_eq!(message["type"], "neighbor");
+        assert_eq!(message["identifier"], 3);
+        assert_eq!(message["progress"], 40);
+        assert!(message["battery_mv"].is_null());
+
+        let state = RoktrackState::new();
+        let message = state_message(&state);
+        assert_eq!(message["type"], "state");
+        assert_eq!(message["mode"], "Fill");
+
+        let detection = Detection {
+            cls: 2,
+            x2: 10,
+            ..Default::default()
+        };
+        let message = detections_message(&[detection]);
+        assert_eq!(message["detections"][0]["cls"], 2);
+        assert_eq!(message["detections"][0]["box"][2], 10);
+        assert_eq!(message["detections"][0]["camera"], 0);
+    }
+}
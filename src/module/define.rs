@@ -29,9 +29,54 @@ pub mod path {
     // Last Captured Image
     pub const LAST_IMAGE: &str = "vision.jpg";
 
+    // Last Captured Image of the Second Camera
+    pub const LAST_RIGHT_IMAGE: &str = "vision_right.jpg";
+
+    // Last Captured Depth of the Depth Camera
+    pub const LAST_DEPTH_IMAGE: &str = "vision_depth.raw";
+
+    // Last Captured Image of the Rear Camera
+    pub const LAST_REAR_IMAGE: &str = "vision_rear.jpg";
+
     // Cropped Image
     pub const CROP_IMAGE: &str = "crop.jpg";
 
+    // Tile of the Image Around the Horizon
+    pub const TILE_IMAGE: &str = "tile.jpg";
+
+    // Nonce Counter of Encrypted Broadcasts
+    pub const NONCE_COUNTER_FILE: &str = "nonce_counter";
+
+    // Random Device ID in the Nonces of Encrypted Broadcasts
+    pub const DEVICE_ID_FILE: &str = "device_id";
+
+    // Highest Counters Received from Neighbors
+    pub const REPLAY_STATE_FILE: &str = "replay_state";
+
+    // Devices Paired with This Robot
+    pub const TRUSTED_DEVICES_FILE: &str = "trusted_devices";
+
+    // Progress of the Fill Mission to Resume
+    pub const FILL_PROGRESS_FILE: &str = "fill_progress.toml";
+
+    // Results of the Calibration Mode
+    pub const CALIBRATION_FILE: &str = "calibration.toml";
+
+    // Intrinsics of the Camera from the Camera Calibration Mode
+    pub const CAMERA_CALIBRATION_FILE: &str = "camera_calibration.toml";
+
+    // Frames of the Checkerboard Kept by the Camera Calibration Mode
+    pub const CAMERA_CALIBRATION_DIR: &str = "camera_calibration";
+
+    // Frames and Labels Kept by the Dataset Capture Mode
+    pub const DATASET_DIR: &str = "dataset";
+
+    // Frames Drawn with the Decisions of the Pilots
+    pub const DEBUG_DIR: &str = "debug";
+
+    // Detection Events of the Sessions
+    pub const EVENTS_DIR: &str = "events";
+
     // YOLOv8 Model (320x320)
     pub const PYLON_320_MODEL: &str = "asset/model/roktrack_yolov8_nano_fixed_320_320.onnx";
 
@@ -46,4 +91,18 @@ pub mod path {
 
     // Animal Detection Model (640x640)
     pub const ANIMAL_640_MODEL: &str = "asset/model/animal_yolov8_nano_fixed_640_640.onnx";
+
+    // Grass Segmentation Model (320x320), not shipped. The edge follow mode and the grass check
+    // need it.
+    pub const GRASS_SEG_320_MODEL: &str = "asset/model/grass_seg_320_320.onnx";
+
+    // Person Pose Estimation Model (320x320), not shipped. The fallen person alert and the
+    // gestures need it.
+    pub const POSE_320_MODEL: &str = "asset/model/pose_yolov8_nano_fixed_320_320.onnx";
+
+    // Sample Images
+    pub const IMG_ASSET_DIR: &str = "asset/img";
+
+    // Reference Labels of the Sample Images for the Benchmark
+    pub const BENCHMARK_DIR: &str = "asset/bench";
 }
@@ -13,7 +13,7 @@ use std::thread;
 use std::{sync::mpsc::Receiver, thread::JoinHandle, time::Duration};
 
 use crate::module::device::motor::Motor;
-use crate::module::util::conf::Config;
+use crate::module::util::conf::{Config, SpeedProfile};
 
 // File path to get the temperature of the SoC of Raspberry Pi.
 const TEMPERATURE_FILE: &str = "/sys/class/thermal/thermal_zone0/temp";
@@ -23,6 +23,17 @@ pub enum DeviceMgmtCommand {
     Stop,
 }
 
+/// Movement commanded to the chassis.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Movement {
+    #[default]
+    Stop,
+    Forward,
+    Backward,
+    Left,
+    Right,
+}
+
 /// Device set.
 pub struct Roktrack {
     pub inner: Arc<Mutex<RoktrackInner>>,
@@ -74,13 +85,52 @@ pub struct RoktrackInner {
     pub drive_motor_left: motor::DriveMotor,
     pub work_motor: motor::WorkMotor,
     pub bumper: base::Bumper,
-    pub turn_adj: f32,    // Turn time adjustment factor
-    pub target_time: u64, // Milliseconds
+    pub pause_button: base::PauseButton,
+    pub estop: base::EmergencyStop,
+    pub rain: base::RainSensor,
+    pub light: base::Light,
+    pub night_vision: base::NightVision,
+    pub imu: base::Imu,
+    pub turn_adj: f32,              // Turn time adjustment factor
+    pub turn_ratio: f32,            // Rotation adjustment measured by the calibration
+    pub target_time: u64,           // Milliseconds
+    pub forward_since: Option<u64>, // Time since when forward has been commanded in ms
+    pub movement: Movement,         // Last movement commanded
+    pub speed: SpeedProfile,        // Speeds of the current mode
+    pub approaching: bool,          // Whether the marker is close, driving at the approach speed
+    pub caution: Option<f64>,       // Speed limit while a person is in sight
+    pub speed_limit: f64,           // Speed limit of the time of day, e.g. at night
+    pub volume: f32,                // Volume of the voice
 }
 
 impl RoktrackInner {
     /// Creates a new RoktrackInner instance with the given configuration.
     pub fn new(conf: Config) -> Self {
+        if conf.drive.dry_run {
+            log::warn!("Dry-run mode: motor commands are logged instead of sent to the hardware.");
+            return Self {
+                drive_motor_right: motor::DriveMotor::dry_run("right", conf.pwm.pwm_power_right),
+                drive_motor_left: motor::DriveMotor::dry_run("left", conf.pwm.pwm_power_left),
+                work_motor: motor::WorkMotor::dry_run(),
+                bumper: base::Bumper::new(conf.pin.bumper_pin),
+                pause_button: base::PauseButton::new(conf.pin.pause_button_pin),
+                estop: base::EmergencyStop::new(conf.pin.estop_pin),
+                rain: base::RainSensor::new(conf.pin.rain_pin),
+                light: base::Light::new(conf.pin.light_pin),
+                night_vision: base::NightVision::new(conf.pin.ir_cut_pin, conf.pin.ir_pin),
+                imu: base::Imu::new(conf.pin.imu_address),
+                turn_adj: conf.drive.turn_adj,
+                turn_ratio: 1.0,
+                target_time: 0, // Milliseconds
+                forward_since: None,
+                movement: Movement::Stop,
+                speed: SpeedProfile::default(),
+                approaching: false,
+                caution: None,
+                speed_limit: 1.0,
+                volume: 1.0,
+            };
+        }
         Self {
             drive_motor_right: motor::DriveMotor::new(
                 conf.pin.right_pin1,
@@ -94,14 +144,28 @@ impl RoktrackInner {
             ),
             work_motor: motor::WorkMotor::new(conf.pin.work1_pin, conf.pin.work_ctrl_positive),
             bumper: base::Bumper::new(conf.pin.bumper_pin),
+            pause_button: base::PauseButton::new(conf.pin.pause_button_pin),
+            estop: base::EmergencyStop::new(conf.pin.estop_pin),
+            rain: base::RainSensor::new(conf.pin.rain_pin),
+            light: base::Light::new(conf.pin.light_pin),
+            night_vision: base::NightVision::new(conf.pin.ir_cut_pin, conf.pin.ir_pin),
+            imu: base::Imu::new(conf.pin.imu_address),
             turn_adj: conf.drive.turn_adj,
+            turn_ratio: 1.0,
             target_time: 0, // Milliseconds
+            forward_since: None,
+            movement: Movement::Stop,
+            speed: SpeedProfile::default(),
+            approaching: false,
+            caution: None,
+            speed_limit: 1.0,
+            volume: 1.0,
         }
     }
 
     /// Plays audio files stored in the asset/audio/ folder.
     pub fn speak(&self, name: &str) {
-        let _ = speaker::speak(name);
+        let _ = speaker::speak_at(name, self.volume);
     }
 
     /// Measures the temperature of the Raspberry Pi's SoC.
@@ -128,6 +192,14 @@ impl RoktrackInner {
             self.drive_motor_right.power = new_right;
         }
     }
+
+    /// Applies the speed of the profile to the drive motors, within the limits.
+    fn apply_speed(&mut self, speed: f64) {
+        let speed = self.caution.map_or(speed, |limit| speed.min(limit));
+        let speed = speed.min(self.speed_limit);
+        self.drive_motor_left.speed = speed;
+        self.drive_motor_right.speed = speed;
+    }
 }
 
 /// Defines drive system operations.
@@ -157,40 +229,64 @@ impl Chassis for RoktrackInner {
         self.drive_motor_left.stop();
         self.drive_motor_right.stop();
         self.work_motor.stop();
+        self.forward_since = None;
+        self.movement = Movement::Stop;
     }
 
     /// Pause drive motors (left and right).
     fn pause(&mut self) {
         self.drive_motor_left.stop();
         self.drive_motor_right.stop();
+        self.forward_since = None;
+        self.movement = Movement::Stop;
     }
 
     /// Move the machine forward for the specified duration.
     fn forward(&mut self, milsec: u64) {
+        self.apply_speed(if self.approaching {
+            self.speed.approach
+        } else {
+            self.speed.cruise
+        });
         self.drive_motor_left.cw();
         self.drive_motor_right.cw();
         self.set_target_time(milsec);
+        self.forward_since
+            .get_or_insert(chrono::Utc::now().timestamp_millis() as u64);
+        self.movement = Movement::Forward;
     }
 
     /// Move the machine backward for the specified duration.
     fn backward(&mut self, milsec: u64) {
+        self.apply_speed(self.speed.turn);
+        self.approaching = false;
         self.drive_motor_left.ccw();
         self.drive_motor_right.ccw();
         self.set_target_time(milsec);
+        self.forward_since = None;
+        self.movement = Movement::Backward;
     }
 
     /// Move the machine left for the specified duration.
     fn left(&mut self, milsec: u64) {
+        self.apply_speed(self.speed.turn);
+        self.approaching = false;
         self.drive_motor_left.ccw();
         self.drive_motor_right.cw();
-        self.set_target_time(milsec);
+        self.set_target_time((milsec as f32 * self.turn_ratio) as u64);
+        self.forward_since = None;
+        self.movement = Movement::Left;
     }
 
     /// Move the machine right for the specified duration.
     fn right(&mut self, milsec: u64) {
+        self.apply_speed(self.speed.turn);
+        self.approaching = false;
         self.drive_motor_left.cw();
         self.drive_motor_right.ccw();
-        self.set_target_time(milsec);
+        self.set_target_time((milsec as f32 * self.turn_ratio) as u64);
+        self.forward_since = None;
+        self.movement = Movement::Right;
     }
 }
 
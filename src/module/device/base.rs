@@ -1,6 +1,15 @@
 //! Provides miscellaneous devices.
 
 use rppal::gpio::Gpio;
+use rppal::i2c::I2c;
+use std::thread;
+use std::time::Duration;
+
+// Duration of each on and off of a flash in ms.
+const FLASH_MS: u64 = 300;
+// Registers of the MPU-6050 IMU.
+const IMU_PWR_MGMT_1: u8 = 0x6B;
+const IMU_ACCEL_XOUT_H: u8 = 0x3B;
 
 /// Defines the LimitSwitch trait.
 ///
@@ -42,6 +51,256 @@ impl LimitSwitch for Bumper {
     }
 }
 
+/// Represents a button to pause and resume the drive.
+pub struct PauseButton {
+    pub switch: Option<rppal::gpio::InputPin>,
+    last: bool, // Whether it was pressed at the last check
+}
+
+impl PauseButton {
+    /// Creates a new PauseButton instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - GPIO pin number for the button. 0 means no button.
+    ///
+    pub fn new(pin: u8) -> Self {
+        let switch = match pin {
+            0 => None,
+            _ => Some(Gpio::new().unwrap().get(pin).unwrap().into_input_pullup()),
+        };
+        Self {
+            switch,
+            last: false,
+        }
+    }
+
+    /// Whether the button has been pressed since the last check.
+    pub fn pressed(&mut self) -> bool {
+        let now = self.get();
+        let pressed = now && !self.last;
+        self.last = now;
+        pressed
+    }
+}
+
+impl LimitSwitch for PauseButton {
+    /// Get the state of the PauseButton.
+    ///
+    /// Returns `true` while the button is held down.
+    fn get(&self) -> bool {
+        self.switch.as_ref().is_some_and(|switch| switch.is_low())
+    }
+}
+
+/// Represents an emergency stop button.
+pub struct EmergencyStop {
+    pub switch: Option<rppal::gpio::InputPin>,
+}
+
+impl EmergencyStop {
+    /// Creates a new EmergencyStop instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - GPIO pin number for the button. 0 means no button.
+    ///
+    pub fn new(pin: u8) -> Self {
+        let switch = match pin {
+            0 => None,
+            _ => Some(Gpio::new().unwrap().get(pin).unwrap().into_input_pullup()),
+        };
+        Self { switch }
+    }
+
+    /// Whether the button is pressed.
+    pub fn pressed(&self) -> bool {
+        self.get()
+    }
+}
+
+impl LimitSwitch for EmergencyStop {
+    /// Get the state of the EmergencyStop.
+    ///
+    /// Returns `true` while the button is pressed.
+    fn get(&self) -> bool {
+        self.switch.as_ref().is_some_and(|switch| switch.is_low())
+    }
+}
+
+/// Represents a rain sensor, whose contact closes when wet.
+pub struct RainSensor {
+    pub switch: Option<rppal::gpio::InputPin>,
+}
+
+impl RainSensor {
+    /// Creates a new RainSensor instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - GPIO pin number for the sensor. 0 means no sensor.
+    ///
+    pub fn new(pin: u8) -> Self {
+        let switch = match pin {
+            0 => None,
+            _ => Some(Gpio::new().unwrap().get(pin).unwrap().into_input_pullup()),
+        };
+        Self { switch }
+    }
+
+    /// Whether it's raining.
+    pub fn raining(&self) -> bool {
+        self.get()
+    }
+}
+
+impl LimitSwitch for RainSensor {
+    /// Get the state of the RainSensor.
+    ///
+    /// Returns `true` while the sensor is wet.
+    fn get(&self) -> bool {
+        self.switch.as_ref().is_some_and(|switch| switch.is_low())
+    }
+}
+
+/// Represents the switches of the camera for the night: the IR-cut filter and the IR illuminator.
+pub struct NightVision {
+    pub ir_cut: Option<rppal::gpio::OutputPin>, // High removes the IR-cut filter
+    pub illuminator: Option<rppal::gpio::OutputPin>,
+}
+
+impl NightVision {
+    /// Creates a new NightVision instance, in the day mode.
+    ///
+    /// # Arguments
+    ///
+    /// * `ir_cut_pin` - GPIO pin number switching the IR-cut filter. 0 means no switch, e.g. of the Pi NoIR camera.
+    /// * `illuminator_pin` - GPIO pin number for the IR illuminator. 0 means no illuminator.
+    ///
+    pub fn new(ir_cut_pin: u8, illuminator_pin: u8) -> Self {
+        let output = |pin: u8| match pin {
+            0 => None,
+            _ => Some(Gpio::new().unwrap().get(pin).unwrap().into_output_low()),
+        };
+        Self {
+            ir_cut: output(ir_cut_pin),
+            illuminator: output(illuminator_pin),
+        }
+    }
+
+    /// Switches the camera to see by the IR, or back to the day. Does nothing without the switches.
+    pub fn set(&mut self, ir: bool) {
+        for pin in [self.ir_cut.as_mut(), self.illuminator.as_mut()]
+            .into_iter()
+            .flatten()
+        {
+            match ir {
+                true => pin.set_high(),
+                false => pin.set_low(),
+            }
+        }
+    }
+}
+
+/// Represents a light flashed to deter animals.
+pub struct Light {
+    pub pin: Option<rppal::gpio::OutputPin>,
+}
+
+impl Light {
+    /// Creates a new Light instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `pin` - GPIO pin number for the light. 0 means no light.
+    ///
+    pub fn new(pin: u8) -> Self {
+        let pin = match pin {
+            0 => None,
+            _ => Some(Gpio::new().unwrap().get(pin).unwrap().into_output_low()),
+        };
+        Self { pin }
+    }
+
+    /// Turns the light on or off, e.g. as the headlight at night. Does nothing without a light.
+    pub fn set(&mut self, on: bool) {
+        if let Some(pin) = self.pin.as_mut() {
+            match on {
+                true => pin.set_high(),
+                false => pin.set_low(),
+            }
+        }
+    }
+
+    /// Flashes the light. Does nothing without a light.
+    pub fn flash(&mut self, times: u8) {
+        if let Some(pin) = self.pin.as_mut() {
+            for _ in 0..times {
+                pin.set_high();
+                thread::sleep(Duration::from_millis(FLASH_MS));
+                pin.set_low();
+                thread::sleep(Duration::from_millis(FLASH_MS));
+            }
+        }
+    }
+}
+
+/// Represents an IMU (MPU-6050) to measure the tilt of the body.
+///
+/// The x axis points forward and the z axis points up.
+pub struct Imu {
+    pub i2c: Option<I2c>,
+    pub bias: (f32, f32), // Pitch and roll read at rest on level ground, measured by the calibration
+}
+
+impl Imu {
+    /// Creates a new Imu instance.
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - I2C address of the IMU. 0 means no IMU.
+    ///
+    pub fn new(address: u16) -> Self {
+        let i2c = match address {
+            0 => None,
+            _ => I2c::new()
+                .and_then(|mut i2c| {
+                    i2c.set_slave_address(address)?;
+                    // Wake up from sleep mode.
+                    i2c.smbus_write_byte(IMU_PWR_MGMT_1, 0)?;
+                    Ok(i2c)
+                })
+                .map_err(|e| log::error!("Can't Open IMU: {}", e))
+                .ok(),
+        };
+        Self {
+            i2c,
+            bias: (0.0, 0.0),
+        }
+    }
+
+    /// Reads the pitch (nose up is positive) and the roll (right side down is positive) in degrees.
+    /// None without an IMU or on a read error.
+    pub fn tilt(&mut self) -> Option<(f32, f32)> {
+        let mut buf = [0u8; 6];
+        self.i2c
+            .as_mut()?
+            .write_read(&[IMU_ACCEL_XOUT_H], &mut buf)
+            .map_err(|e| log::error!("Can't Read IMU: {}", e))
+            .ok()?;
+        let axis = |i: usize| i16::from_be_bytes([buf[i], buf[i + 1]]) as f32;
+        let (pitch, roll) = tilt(axis(0), axis(2), axis(4));
+        Some((pitch - self.bias.0, roll - self.bias.1))
+    }
+}
+
+/// Pitch and roll in degrees from the acceleration of the gravity.
+pub fn tilt(ax: f32, ay: f32, az: f32) -> (f32, f32) {
+    let pitch = ax.atan2((ay * ay + az * az).sqrt()).to_degrees();
+    let roll = ay.atan2(az).to_degrees();
+    (pitch, roll)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -51,4 +310,43 @@ mod tests {
         let bumper = Bumper::new(24);
         assert!(!bumper.get());
     }
+
+    #[test]
+    fn pause_button_disabled_test() {
+        let mut button = PauseButton::new(0);
+        assert!(!button.get());
+        assert!(!button.pressed());
+    }
+
+    #[test]
+    fn emergency_stop_disabled_test() {
+        let estop = EmergencyStop::new(0);
+        assert!(!estop.pressed());
+    }
+
+    #[test]
+    fn rain_sensor_disabled_test() {
+        let rain = RainSensor::new(0);
+        assert!(!rain.raining());
+    }
+
+    #[test]
+    fn light_disabled_test() {
+        let mut light = Light::new(0);
+        assert!(light.pin.is_none());
+        light.flash(3);
+    }
+
+    #[test]
+    fn tilt_test() {
+        let (pitch, roll) = tilt(0.0, 0.0, 1.0);
+        assert!(pitch.abs() < 0.01 && roll.abs() < 0.01);
+        // Nose up by 30 degrees.
+        let (pitch, roll) = tilt(0.5, 0.0, 0.75f32.sqrt());
+        assert!((pitch - 30.0).abs() < 0.01 && roll.abs() < 0.01);
+        // Right side down by 45 degrees.
+        let (pitch, roll) = tilt(0.0, 1.0, 1.0);
+        assert!(pitch.abs() < 0.01 && (roll - 45.0).abs() < 0.01);
+        assert!(Imu::new(0).tilt().is_none());
+    }
 }
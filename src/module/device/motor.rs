@@ -1,4 +1,6 @@
 //! Provides Motor Control functionality.
+//!
+//! In dry-run mode, motors don't touch the GPIO pins and log their commands instead.
 
 use rppal::gpio::Gpio;
 
@@ -12,11 +14,34 @@ pub trait Motor {
     fn stop(&mut self) {}
 }
 
+/// Captures the commands of a motor in dry-run mode.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DryRun {
+    pub name: &'static str,
+    pub last: Option<String>, // Last command, which is printed only when it changes
+}
+
+impl DryRun {
+    /// Creates a new DryRun instance for the named motor.
+    pub fn new(name: &'static str) -> Self {
+        Self { name, last: None }
+    }
+
+    /// Records the command and logs it if it differs from the last one.
+    pub fn capture(&mut self, command: String) {
+        if self.last.as_ref() != Some(&command) {
+            log::info!("Dry-run {}: {}", self.name, command);
+            self.last = Some(command);
+        }
+    }
+}
+
 /// Represents a Drive Motor.
 pub struct DriveMotor {
-    pin1: rppal::gpio::OutputPin,
-    pin2: rppal::gpio::OutputPin,
+    pins: Option<(rppal::gpio::OutputPin, rppal::gpio::OutputPin)>, // None in dry-run mode
+    dry_run: DryRun,
     pub power: f64,
+    pub speed: f64, // Ratio to the power of the speed profile
 }
 
 impl DriveMotor {
@@ -33,36 +58,87 @@ impl DriveMotor {
         let gpio2 = Gpio::new().unwrap();
 
         Self {
-            pin1: gpio1.get(pin1).unwrap().into_output(),
-            pin2: gpio2.get(pin2).unwrap().into_output(),
+            pins: Some((
+                gpio1.get(pin1).unwrap().into_output(),
+                gpio2.get(pin2).unwrap().into_output(),
+            )),
+            dry_run: DryRun::new("drive"),
+            power,
+            speed: 1.0,
+        }
+    }
+
+    /// Creates a DriveMotor which prints its commands instead of driving the pins.
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - Name of the motor in the printed commands.
+    /// * `power` - Motor power (0.0 to 1.0).
+    ///
+    pub fn dry_run(name: &'static str, power: f64) -> Self {
+        Self {
+            pins: None,
+            dry_run: DryRun::new(name),
             power,
+            speed: 1.0,
         }
     }
+
+    /// Commands captured in dry-run mode.
+    pub fn captured(&self) -> Option<&DryRun> {
+        self.pins.is_none().then_some(&self.dry_run)
+    }
+
+    /// Duty cycle of the PWM output.
+    pub fn duty(&self) -> f64 {
+        (self.power * self.speed).clamp(0.0, 1.0)
+    }
 }
 
 impl Motor for DriveMotor {
     /// Rotate the drive motor clockwise (CW).
     fn cw(&mut self) {
-        self.pin1.clear_pwm().unwrap();
-        self.pin2.clear_pwm().unwrap();
-        self.pin1.set_low();
-        self.pin2.set_pwm_frequency(100.0, self.power).unwrap();
+        match self.pins.as_mut() {
+            Some((pin1, pin2)) => {
+                pin1.clear_pwm().unwrap();
+                pin2.clear_pwm().unwrap();
+                pin1.set_low();
+                pin2.set_pwm_frequency(100.0, self.duty()).unwrap();
+            }
+            None => {
+                let duty = self.duty();
+                self.dry_run.capture(format!("cw (power {:.2})", duty))
+            }
+        }
     }
 
     /// Rotate the drive motor counterclockwise (CCW).
     fn ccw(&mut self) {
-        self.pin1.clear_pwm().unwrap();
-        self.pin2.clear_pwm().unwrap();
-        self.pin1.set_pwm_frequency(100.0, self.power).unwrap();
-        self.pin2.set_low();
+        match self.pins.as_mut() {
+            Some((pin1, pin2)) => {
+                pin1.clear_pwm().unwrap();
+                pin2.clear_pwm().unwrap();
+                pin1.set_pwm_frequency(100.0, self.duty()).unwrap();
+                pin2.set_low();
+            }
+            None => {
+                let duty = self.duty();
+                self.dry_run.capture(format!("ccw (power {:.2})", duty))
+            }
+        }
     }
 
     /// Stop the drive motor.
     fn stop(&mut self) {
-        self.pin1.clear_pwm().unwrap();
-        self.pin2.clear_pwm().unwrap();
-        self.pin1.set_low();
-        self.pin2.set_low();
+        match self.pins.as_mut() {
+            Some((pin1, pin2)) => {
+                pin1.clear_pwm().unwrap();
+                pin2.clear_pwm().unwrap();
+                pin1.set_low();
+                pin2.set_low();
+            }
+            None => self.dry_run.capture("stop".to_string()),
+        }
     }
 }
 
@@ -77,7 +153,8 @@ impl Drop for DriveMotor {
 
 /// Represents a Work Motor for tasks like cutting grass.
 pub struct WorkMotor {
-    pin1: rppal::gpio::OutputPin,
+    pin1: Option<rppal::gpio::OutputPin>, // None in dry-run mode
+    dry_run: DryRun,
     positive_relay: bool,
 }
 
@@ -93,19 +170,34 @@ impl WorkMotor {
         let gpio1 = Gpio::new().unwrap();
 
         Self {
-            pin1: gpio1.get(pin1).unwrap().into_output(),
+            pin1: Some(gpio1.get(pin1).unwrap().into_output()),
+            dry_run: DryRun::new("work"),
             positive_relay,
         }
     }
+
+    /// Creates a WorkMotor which prints its commands instead of driving the relay.
+    pub fn dry_run() -> Self {
+        Self {
+            pin1: None,
+            dry_run: DryRun::new("work"),
+            positive_relay: false,
+        }
+    }
+
+    /// Commands captured in dry-run mode.
+    pub fn captured(&self) -> Option<&DryRun> {
+        self.pin1.is_none().then_some(&self.dry_run)
+    }
 }
 
 impl Motor for WorkMotor {
     /// Rotate the work motor clockwise (CW).
     fn cw(&mut self) {
-        if self.positive_relay {
-            self.pin1.set_high();
-        } else {
-            self.pin1.set_low();
+        match self.pin1.as_mut() {
+            Some(pin1) if self.positive_relay => pin1.set_high(),
+            Some(pin1) => pin1.set_low(),
+            None => self.dry_run.capture("on".to_string()),
         }
     }
 
@@ -117,10 +209,10 @@ impl Motor for WorkMotor {
 
     /// Stop the work motor.
     fn stop(&mut self) {
-        if self.positive_relay {
-            self.pin1.set_low();
-        } else {
-            self.pin1.set_high();
+        match self.pin1.as_mut() {
+            Some(pin1) if self.positive_relay => pin1.set_low(),
+            Some(pin1) => pin1.set_high(),
+            None => self.dry_run.capture("off".to_string()),
         }
     }
 }
@@ -218,6 +310,24 @@ mod tests {
         dmr.stop();
     }
 
+    #[test]
+    fn dry_run_test() {
+        let mut dm = DriveMotor::dry_run("left", 1.0);
+        assert_eq!(dm.captured().unwrap().last, None);
+        dm.cw();
+        assert_eq!(
+            dm.captured().unwrap().last.as_deref(),
+            Some("cw (power 1.00)")
+        );
+        dm.stop();
+        dm.stop();
+        assert_eq!(dm.captured().unwrap().last.as_deref(), Some("stop"));
+
+        let mut wm = WorkMotor::dry_run();
+        wm.cw();
+        assert_eq!(wm.captured().unwrap().last.as_deref(), Some("on"));
+    }
+
     #[test]
     fn work_motor_test() {
         let mut wm = WorkMotor::new(14, false);
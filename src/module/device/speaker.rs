@@ -18,11 +18,17 @@ use std::path::Path;
 /// play("asset/audio/ja/start_mowing.mp3");
 /// ```
 pub fn play(file: &str) -> Result<(), Box<dyn std::error::Error>> {
+    play_at(file, 1.0)
+}
+
+/// Play an audio file at the volume (0.0 -> 1.0).
+pub fn play_at(file: &str, volume: f32) -> Result<(), Box<dyn std::error::Error>> {
     let path = Path::new(file);
 
     // Check if the file exists
     if path.is_file() {
-        let sl = Soloud::default()?;
+        let mut sl = Soloud::default()?;
+        sl.set_global_volume(volume.clamp(0.0, 1.0));
         let mut wav = audio::Wav::default();
 
         // Load the audio file
@@ -56,8 +62,13 @@ pub fn play(file: &str) -> Result<(), Box<dyn std::error::Error>> {
 /// speak("start_mowing");
 /// ```
 pub fn speak(name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    speak_at(name, 1.0)
+}
+
+/// Play an asset audio file at the volume (0.0 -> 1.0), e.g. quieter at night.
+pub fn speak_at(name: &str, volume: f32) -> Result<(), Box<dyn std::error::Error>> {
     let path = Path::new("./asset/audio/ja/").join(format!("{name}.mp3"));
-    play(path.to_str().unwrap())
+    play_at(path.to_str().unwrap(), volume)
 }
 
 /// Logger functions for speaking audio messages based on log levels.
@@ -1,41 +1,83 @@
 //! Provides a loop for autonomous driving.
 
-use crate::module::com::{BleBroadCast, Neighbor, ParentMsg};
-use crate::module::pilot::{Modes, RoktrackState};
+use crate::module::com::channel::{self, BoundedReceiver, BoundedSender};
+use crate::module::com::checksum;
+use crate::module::com::codec::{Compatibility, VersionMonitor, WIRE_VERSION};
+use crate::module::com::fragment::{self, Message, Reassembler};
+use crate::module::com::gatt::GattServer;
+use crate::module::com::grpc::GrpcServer;
+use crate::module::com::mqtt::MqttBridge;
+use crate::module::com::nus::{NusClient, SensorReading};
+use crate::module::com::pairing::TrustStore;
+use crate::module::com::reliable::{Delivery, Incoming, ReliableLink};
+use crate::module::com::signal::{Calibration, Zone};
+use crate::module::com::table::{NeighborTable, PresenceEvent};
+use crate::module::com::telemetry;
+use crate::module::com::timesync::TimeSync;
+use crate::module::com::transport::CommTransport;
+use crate::module::com::uart::UartTransport;
+use crate::module::com::websocket::{self, WebSocketServer};
+use crate::module::com::{resolve_dev_id, transport, ChildMsg, Neighbor, ParentMsg, PAYLOAD_LEN};
+use crate::module::define;
+use crate::module::pilot::{
+    battery,
+    calibration::{self, Calibration},
+    geofence::Geofence,
+    lifecycle, night,
+    pid::Pid,
+    progress::{self, FillProgress},
+    rain::{self, RainAbort},
+    speed, waypoint, work_rate, Modes, RoktrackState,
+};
+use crate::module::util::common::send_line_notify_with_image;
 use crate::module::util::init::RoktrackProperty;
+use crate::module::vision::camera;
 use crate::module::vision::detector::Detection;
+use crate::module::vision::overlay::DebugOverlay;
+use crate::module::vision::qr;
 use crate::module::vision::{RoktrackVision, VisionMgmtCommand};
-use std::collections::HashMap;
+use std::path::Path;
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::thread::{self, JoinHandle};
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use super::device::{Chassis, DeviceMgmtCommand, Roktrack};
 use super::pilot::base::{post_process, pre_process};
-use super::pilot::fill::Fill;
-use super::pilot::follow_person::FollowPerson;
-use super::pilot::monitor_animal::MonitorAnimal;
-use super::pilot::monitor_person::MonitorPerson;
-use super::pilot::oneway::OneWay;
-use super::pilot::round_trip::RoundTrip;
+use super::pilot::registry::{self, SharedRegistry};
 use super::pilot::PilotHandler;
 use super::util::conf::Config;
 
 /// Start the autonomous driving thread.
 pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
+    run_with_registry(property, registry::builtin())
+}
+
+/// Start the autonomous driving thread with the handlers of a registry.
+///
+/// Factories registered while running are used from the next mode switch,
+/// or immediately for the current mode while the robot is off.
+pub fn run_with_registry(property: RoktrackProperty, registry: SharedRegistry) -> JoinHandle<()> {
     // Prepare communication channels for threads.
     // For Vision
     let (channel_vision_mgmt_tx, channel_vision_mgmt_rx): (
         Sender<VisionMgmtCommand>,
         Receiver<VisionMgmtCommand>,
     ) = mpsc::channel();
+    // Only the detections of the latest frame are kept, not to act on the stale ones when the loop falls behind.
     let (channel_detections_tx, channel_detections_rx): (
-        Sender<Vec<Detection>>,
-        Receiver<Vec<Detection>>,
-    ) = mpsc::channel();
-    // For BLE Communication
-    let (_channel_neighbor_tx, channel_neighbor_rx): (Sender<Neighbor>, Receiver<Neighbor>) =
-        mpsc::channel();
+        BoundedSender<Vec<Detection>>,
+        BoundedReceiver<Vec<Detection>>,
+    ) = channel::bounded(1);
+    // For BLE Communication. The oldest events are dropped when the loop falls behind.
+    let (channel_neighbor_tx, channel_neighbor_rx): (
+        BoundedSender<Neighbor>,
+        BoundedReceiver<Neighbor>,
+    ) = channel::bounded(channel::DEFAULT_CAPACITY);
+    // For the large messages reassembled from the fragments of the neighbors.
+    let (channel_message_tx, channel_message_rx): (
+        BoundedSender<Message>,
+        BoundedReceiver<Message>,
+    ) = channel::bounded(channel::DEFAULT_CAPACITY);
     // For Device Thread (not used in this code)
     let (_channel_device_mgmt_tx, channel_device_mgmt_rx): (
         Sender<DeviceMgmtCommand>,
@@ -43,12 +85,141 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
     ) = mpsc::channel();
 
     // Initialize the neighbors table.
-    let mut neighbors = HashMap::new();
+    let mut neighbors = NeighborTable::new();
+    // Initialize the reassembler for large messages.
+    let mut reassembler = Reassembler::new();
+    // Initialize the reliability layer.
+    let mut link = ReliableLink::new();
+    let mut last_msg = 255;
+    // Follow the clock of the reference robot.
+    let mut clock = TimeSync::new();
+    // Track the wire versions of the neighbors.
+    let mut versions = VersionMonitor::new();
+    // Snapshot of the state while paused.
+    let mut paused: Option<RoktrackState> = None;
+    // Calibration for estimating how close other robots are.
+    let calibration = Calibration::from(&property.conf.proximity);
+    // Load the paired devices and accept new ones for a while.
+    let mut trust = TrustStore::load(Some(
+        Path::new(&property.path.dir.data).join(define::path::TRUSTED_DEVICES_FILE),
+    ));
+    if property.conf.com.trusted_only && property.conf.com.pairing_window > 0 {
+        trust.start_pairing(
+            Duration::from_secs(property.conf.com.pairing_window),
+            Instant::now(),
+        );
+        log::info!(
+            "Pairing for {} seconds. Trusted Devices: {}",
+            property.conf.com.pairing_window,
+            trust.len()
+        );
+    }
+
+    // Start the communication thread.
+    let com = match transport::from_property(property.clone()) {
+        Ok(com) => {
+            log::info!("Transport: {:?}", com.capabilities());
+            Some(com)
+        }
+        Err(e) => {
+            log::error!(
+                "Can't initialize the transport, run without neighbors: {}",
+                e
+            );
+            None
+        }
+    };
+    // Receive the states of the neighbors and the commands over the transport.
+    let _com_handler = com
+        .as_deref()
+        .and_then(|com| listen_neighbors(com, channel_neighbor_tx.clone()));
+
+    // Start the wired link to the trailer controller.
+    let trailer = if property.conf.uart.trailer {
+        match UartTransport::new(property.clone()) {
+            Ok(trailer) => {
+                trailer.listen(channel_neighbor_tx.clone());
+                Some(trailer)
+            }
+            Err(e) => {
+                log::error!("Can't open the trailer link: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Start the GATT server for direct phone control.
+    let gatt = if property.conf.com.gatt {
+        match resolve_dev_id(&property.conf.com.adapter) {
+            Ok(dev_id) => {
+                let gatt = GattServer::new(dev_id);
+                gatt.serve(channel_neighbor_tx.clone());
+                Some(gatt)
+            }
+            Err(e) => {
+                log::error!("Can't start the GATT server: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
 
-    // Start the BLE communication thread.
-    let com = BleBroadCast::new();
-    // Receiving commands via BLE from the phone is disabled until the test is completed.
-    // let _com_handler = com.listen(channel_neighbor_tx);
+    // Start the MQTT bridge for home automation systems.
+    let mqtt = if property.conf.mqtt.enable {
+        let mqtt = MqttBridge::new(property.conf.mqtt.clone());
+        mqtt.serve(channel_neighbor_tx.clone());
+        Some(mqtt)
+    } else {
+        None
+    };
+
+    // Start the gRPC server for integrators.
+    let grpc = if property.conf.grpc.enable {
+        match GrpcServer::new(property.conf.grpc.clone()) {
+            Ok(server) => {
+                server.serve(channel_neighbor_tx.clone());
+                Some(server)
+            }
+            Err(e) => {
+                log::error!("Can't start the gRPC server: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Start the WebSocket stream for dashboards.
+    let websocket = if property.conf.websocket.enable {
+        match WebSocketServer::new(property.conf.websocket.clone()) {
+            Ok(server) => Some(server),
+            Err(e) => {
+                log::error!("Can't start the WebSocket stream: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    // Read sensors of the peripheral on the chassis.
+    let (channel_sensor_tx, channel_sensor_rx): (Sender<SensorReading>, Receiver<SensorReading>) =
+        mpsc::channel();
+    if property.conf.nus.enable {
+        let scan_adapter = match property.conf.com.scan_adapter.as_str() {
+            "" => property.conf.com.adapter.as_str(),
+            name => name,
+        };
+        match resolve_dev_id(scan_adapter) {
+            Ok(dev_id) => {
+                NusClient::new(property.conf.nus.clone(), dev_id).run(channel_sensor_tx);
+            }
+            Err(e) => log::error!("Can't start the NUS client: {}", e),
+        }
+    }
 
     // Start the device thread.
     let mut device = crate::module::device::Roktrack::new(property.conf.clone());
@@ -57,40 +228,391 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
     // Initialize the vision module and start the inference thread.
     let vision = RoktrackVision::new(property.clone());
     vision.run(channel_detections_tx, channel_vision_mgmt_rx);
+    let active_model = vision.model();
+    let active_grass = vision.grass();
+    let active_depth_ahead = vision.depth_ahead();
+    let active_part_temp = vision.part_temp();
+    let active_metrics = vision.metrics();
+    let active_odometry = vision.odometry();
+    let stream = vision.stream();
 
     // Initialize the state.
     let mut state = RoktrackState::new();
+    state.heading = Pid::from_conf(&property.conf.heading);
+    // Drive with the results of the last calibration.
+    if let Some(calibration) = Calibration::load(&calibration::path(&property.path.dir.data)) {
+        log::info!("Calibration Loaded: {:?}", calibration);
+        calibration.apply(&mut device, &property.conf);
+        state.calibration = calibration;
+    }
     // Initialize drive handler.
     let mut handler: Box<dyn PilotHandler> = mode_to_handler(
+        &registry,
         Modes::from_string(property.conf.drive.mode.as_str()),
         channel_vision_mgmt_tx.clone(),
         property.conf.clone(),
     )
+    .or_else(|| {
+        // e.g. the edge follow mode without the grass segmentation model.
+        log::error!("Mode Refused: {}. Fill Instead.", property.conf.drive.mode);
+        mode_to_handler(
+            &registry,
+            Modes::Fill,
+            channel_vision_mgmt_tx.clone(),
+            property.conf.clone(),
+        )
+    })
     .expect("Can't initialize handler.");
+    let mut registry_revision = registry.lock().unwrap().revision();
+    // Resume the fill mission where it stopped, e.g. before a battery swap or a crash.
+    let progress_path = progress::path(&property.path.dir.data);
+    if property.conf.progress.resume
+        && Modes::from_string(property.conf.drive.mode.as_str()) == Modes::Fill
+    {
+        if let Some(saved) = FillProgress::load(&progress_path) {
+            log::info!("Resume Fill: {:?}", saved);
+            saved.apply(&mut state);
+        }
+    }
+    let mut progress_saved = Instant::now();
+    // Mode whose speed profile is applied.
+    let mut speed_mode = Modes::Unknown;
+    // Keep the robot in the allowed area under every pilot.
+    let mut geofence = Geofence::from_conf(&property.conf.geofence, &property.path.dir.data);
+    // Whether the robot was running in the last loop, to tell when a job starts.
+    let mut was_driving = false;
+    // Rain reported by the sensor peripheral, and the abort waiting for the end of the pass.
+    let mut rain_reported = false;
+    let mut rain = RainAbort::default();
+    // Report the start, completion and abort of the missions.
+    let mut lifecycle = lifecycle::builtin();
+    // Draw the decisions of the pilots on the frames to diagnose them.
+    let mut debug_overlay = DebugOverlay::default();
+    // Give the commands of the QR code signs once in view.
+    let mut signs = qr::Signs::default();
 
     thread::spawn(move || loop {
         // Sleep to control the loop rate.
         thread::sleep(Duration::from_millis(10));
 
         // Get new neighbor information.
-        if let Ok(neighbor) = channel_neighbor_rx.try_recv() {
+        // Unknown devices and fragments skip the neighbor handling only, not the rest of the loop.
+        let received = channel_neighbor_rx
+            .try_recv()
+            .ok()
+            .filter(|neighbor| trusted(&property.conf, &mut trust, neighbor))
+            .and_then(|neighbor| reassemble(&mut reassembler, neighbor, &channel_message_tx));
+        // Handle the large messages by their ids.
+        while let Ok(message) = channel_message_rx.try_recv() {
+            let driving = state.state || paused.is_some();
+            let route = handle_message(&property.conf, &property.path.dir.data, driving, message);
+            // Drive the new route from the next job.
+            if route && state.mode == Modes::Waypoint {
+                if let Some(n) = mode_to_handler(
+                    &registry,
+                    state.mode,
+                    channel_vision_mgmt_tx.clone(),
+                    property.conf.clone(),
+                ) {
+                    handler = n;
+                }
+            }
+        }
+        if let Some(mut neighbor) = received {
+            // Synchronize the clock and stamp the neighbor with it.
+            let local_ms = chrono::Utc::now().timestamp_millis();
+            if let Some(clock_ms) = neighbor.telemetry.clock_ms {
+                if clock.update(
+                    state.identifier,
+                    neighbor.identifier,
+                    clock_ms,
+                    local_ms,
+                    Instant::now(),
+                ) {
+                    log::info!(
+                        "Clock Synchronized to: {}, offset: {} ms",
+                        neighbor.identifier,
+                        clock.offset_ms()
+                    );
+                }
+            }
+            neighbor.timestamp = (clock.now_ms(local_ms) / 1000).to_string();
             log::debug!("New Neighbor Info Received: {:?}", neighbor.clone());
+            if let Some(websocket) = &websocket {
+                websocket.publish(websocket::neighbor_message(&neighbor));
+            }
+            // Relay the states of other robots to the broker.
+            if let Some(mqtt) = &mqtt {
+                if neighbor.identifier != 0 {
+                    mqtt.publish(&neighbor);
+                }
+            }
             // Update the neighbor table.
-            neighbors.insert(neighbor.identifier, neighbor.clone());
-            // Check command
-            if let Some(n) = command_to_handler(
+            if let Some(PresenceEvent::Joined(n)) =
+                neighbors.update(neighbor.clone(), Instant::now())
+            {
+                log::info!("Neighbor Joined: {} ({})", n.identifier, n.mac);
+            }
+            // Warn once per neighbor whose wire version differs.
+            if let Some(version) = neighbor.version {
+                match versions.check(neighbor.identifier, version) {
+                    Some(Compatibility::Newer(v)) => log::warn!(
+                        "Protocol Version Mismatch: {} speaks v{}, this robot v{}. Only the compatible fields are used, update this robot.",
+                        neighbor.identifier,
+                        v,
+                        WIRE_VERSION
+                    ),
+                    Some(Compatibility::Older(v)) => log::warn!(
+                        "Protocol Version Mismatch: {} speaks v{}, this robot v{}. Update the neighbor.",
+                        neighbor.identifier,
+                        v,
+                        WIRE_VERSION
+                    ),
+                    _ => {}
+                }
+            }
+            if let Some(proximity) = neighbor.proximity(&calibration) {
+                if neighbor.identifier != 0 && proximity.zone == Zone::Near {
+                    log::warn!(
+                        "Neighbor Near: {} ({:.1} m)",
+                        neighbor.identifier,
+                        proximity.distance
+                    );
+                }
+            }
+            // Keep the time of the last contact to detect the loss of the commander.
+            if neighbor.identifier == 0 {
+                state.last_contact = Some(chrono::Utc::now().timestamp_millis());
+            }
+            // Acknowledge sequenced commands from the commander.
+            let incoming = link.receive(&neighbor);
+            if let Incoming::New(seq) = incoming {
+                if neighbor.identifier == 0 {
+                    state.ack = seq;
+                    state.msg = ChildMsg::to_u8(ChildMsg::Ack);
+                }
+            }
+            // Check command. Retransmitted commands are executed only once.
+            if incoming != Incoming::Duplicate
+                && !(neighbor.identifier == 0
+                    && neighbor.dest == 255
+                    && pause_command(
+                        ParentMsg::from_u8(neighbor.msg),
+                        &mut paused,
+                        &mut state,
+                        &mut handler,
+                        &mut device,
+                        channel_vision_mgmt_tx.clone(),
+                    ))
+            {
+                // A reset starts the fill mission over.
+                if neighbor.identifier == 0
+                    && neighbor.dest == 255
+                    && !state.state
+                    && matches!(ParentMsg::from_u8(neighbor.msg), ParentMsg::Reset)
+                {
+                    progress::clear(&property.path.dir.data);
+                }
+                if let Some(n) = command_to_handler(
+                    &registry,
+                    &mut state,
+                    &neighbor,
+                    &mut device,
+                    channel_vision_mgmt_tx.clone(),
+                    property.conf.clone(),
+                ) {
+                    log::debug!("Replace Handle");
+                    // If there are new instructions, replace the handler.
+                    handler = n;
+                }
+            }
+        }
+
+        // Toggle the pause by the button.
+        let pressed = device.inner.clone().lock().unwrap().pause_button.pressed();
+        if pressed {
+            let msg = match paused {
+                Some(_) => ParentMsg::Resume,
+                None => ParentMsg::Pause,
+            };
+            pause_command(
+                msg,
+                &mut paused,
                 &mut state,
-                &neighbor,
+                &mut handler,
                 &mut device,
                 channel_vision_mgmt_tx.clone(),
+            );
+        }
+
+        // Swap the handler when its factory has been replaced. Not while running or paused.
+        let revision = registry.lock().unwrap().revision();
+        if revision != registry_revision && !state.state && paused.is_none() {
+            registry_revision = revision;
+            if let Some(n) = mode_to_handler(
+                &registry,
+                state.mode,
+                channel_vision_mgmt_tx.clone(),
                 property.conf.clone(),
             ) {
-                log::debug!("Replace Handle");
-                // If there are new instructions, replace the handler.
+                log::info!("Handler Replaced: {:?}", state.mode);
                 handler = n;
             }
         }
 
+        // Apply readings of the sensor peripheral.
+        while let Ok(mut reading) = channel_sensor_rx.try_recv() {
+            // Correct the deviation of the compass.
+            if reading.key == "heading" {
+                reading.value = state.calibration.correct_heading(reading.value as f32) as f64;
+            }
+            if reading.key == "rain" {
+                rain_reported = 0.5 <= reading.value;
+                continue;
+            }
+            if !reading.apply(&mut state.telemetry) {
+                log::debug!("Sensor Reading Ignored: {:?}", reading);
+            }
+        }
+
+        // Head back to the dock or stop when the battery runs low.
+        if state.state
+            && battery::is_low(
+                state.telemetry.battery_mv,
+                property.conf.dock.low_battery_mv,
+            )
+        {
+            match battery::action(&property.conf.battery) {
+                battery::Action::Return if state.mode != Modes::ReturnToDock => {
+                    log::warn!(
+                        "Low Battery: {} mV. Return to Dock.",
+                        state.telemetry.battery_mv.unwrap_or_default()
+                    );
+                    state.msg = ChildMsg::to_u8(ChildMsg::LowBattery);
+                    state.mode = Modes::ReturnToDock;
+                    if let Some(n) = mode_to_handler(
+                        &registry,
+                        state.mode,
+                        channel_vision_mgmt_tx.clone(),
+                        property.conf.clone(),
+                    ) {
+                        handler = n;
+                    }
+                }
+                battery::Action::Stop => {
+                    log::warn!(
+                        "Low Battery: {} mV. Stop.",
+                        state.telemetry.battery_mv.unwrap_or_default()
+                    );
+                    state.msg = ChildMsg::to_u8(ChildMsg::LowBattery);
+                    state.state = false;
+                    device.inner.clone().lock().unwrap().stop();
+                    channel_vision_mgmt_tx.send(VisionMgmtCommand::Off).unwrap();
+                }
+                _ => {}
+            }
+        }
+
+        // Abort the mission when it rains, after the current pass.
+        let raining = rain_reported || device.inner.lock().unwrap().rain.raining();
+        if !state.state {
+            rain.reset();
+        } else if state.mode != Modes::ReturnToDock
+            && rain.observe(
+                raining,
+                state.mode,
+                state.turn_count,
+                Instant::now(),
+                &property.conf.rain,
+            )
+        {
+            rain.reset();
+            log::warn!("Rain. Abort {:?}.", state.mode);
+            if state.mode == Modes::Fill {
+                let saved = FillProgress::from_state(&state, chrono::Utc::now().timestamp_millis());
+                if let Err(e) = saved.save(&progress_path) {
+                    log::warn!("Can't Save Fill Progress: {}", e);
+                }
+            }
+            state.msg = ChildMsg::to_u8(ChildMsg::Rain);
+            let _ = send_line_notify_with_image(
+                "It's raining. The mission is aborted.",
+                &property.path.img.last,
+                property.conf.clone(),
+            );
+            match rain::action(&property.conf.rain) {
+                rain::Action::Return => {
+                    state.mode = Modes::ReturnToDock;
+                    if let Some(n) = mode_to_handler(
+                        &registry,
+                        state.mode,
+                        channel_vision_mgmt_tx.clone(),
+                        property.conf.clone(),
+                    ) {
+                        handler = n;
+                    }
+                }
+                rain::Action::Stop => {
+                    state.state = false;
+                    device.inner.clone().lock().unwrap().stop();
+                    channel_vision_mgmt_tx.send(VisionMgmtCommand::Off).unwrap();
+                }
+            }
+        }
+
+        // Stop when the robot leaves the allowed area, whatever the pilot does.
+        // The distance is measured from where the job started or resumed, by On, Resume or a new mode.
+        let started = state.state && !was_driving;
+        was_driving = state.state;
+        if started {
+            geofence.reset();
+        }
+        // Share the route with the neighbors when a waypoint job starts.
+        if started && state.mode == Modes::Waypoint && property.conf.waypoint.share {
+            if let Some(com) = &com {
+                if let Err(e) = share_waypoints(
+                    com.as_ref(),
+                    state.identifier,
+                    &property.conf,
+                    &property.path.dir.data,
+                ) {
+                    log::warn!("Waypoints Not Shared: {}", e);
+                }
+            }
+        }
+        if state.state && geofence.is_enabled() {
+            if let Some(breach) = geofence.check(state.telemetry.position) {
+                log::warn!("Out of Bounds: {:?}. Stop.", breach);
+                state.msg = ChildMsg::to_u8(ChildMsg::OutOfBounds);
+                state.state = false;
+                device.inner.clone().lock().unwrap().stop();
+                channel_vision_mgmt_tx.send(VisionMgmtCommand::Off).unwrap();
+                let _ = send_line_notify_with_image(
+                    "Out of the allowed area. Stopped.",
+                    &property.path.img.last,
+                    property.conf.clone(),
+                );
+            }
+        }
+
+        // Detect neighbors that went silent.
+        for event in neighbors.expire(Instant::now()) {
+            if let PresenceEvent::Left(n) = event {
+                log::warn!("Neighbor Left: {} ({})", n.identifier, n.mac);
+            }
+        }
+
+        // Report the mission started or ended by the commands, the pilot or the checks above.
+        if let Some(mission) = lifecycle.observe(
+            state.state,
+            paused.is_some(),
+            state.msg,
+            state.mode,
+            Instant::now(),
+        ) {
+            lifecycle.dispatch(&mission, &mut handler, &mut state, &mut device, &property);
+        }
+
         // Get new inference results.
         let detections = match channel_detections_rx.try_recv() {
             Ok(detections) => Some(detections),
@@ -99,11 +621,74 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
 
         // If there is no detections, skip the rest of the loop.
         if let Some(dets) = detections {
-            // Binding for detections
-            let mut dets = dets;
+            // Binding for detections, of the front camera. The pilots look behind by the rear ones.
+            let (rear, mut dets): (Vec<_>, Vec<_>) =
+                dets.into_iter().partition(|det| det.camera == camera::REAR);
+            state.rear = rear;
+
+            // The motion by the flow of the ground up to the detections
+            state.odometry = *active_odometry.lock().unwrap();
+
+            // Watch the scene to detect getting stuck.
+            state.stuck.observe(
+                &dets,
+                state.telemetry.position,
+                state.odometry,
+                (state.img_width, state.img_height),
+                &property.conf.stuck,
+                chrono::Utc::now().timestamp_millis() as u64,
+            );
+
+            // Drive at the speeds of the mode.
+            if state.mode != speed_mode {
+                speed_mode = state.mode;
+                device.inner.clone().lock().unwrap().speed =
+                    speed::profile(&property.conf.speed, state.mode);
+            }
+
+            // Switch between the profiles of the day and the night at the configured hours.
+            let is_night = night::is_night(chrono::Local::now().time(), &property.conf.night);
+            if is_night != state.night {
+                log::info!("Night Profile: {}", is_night);
+                state.night = is_night;
+                night::apply(&mut device, &property.conf.night, is_night);
+            }
+
+            // The model the detections came from
+            state.model = active_model.lock().unwrap().clone();
+            // The drivable grass in the frame of the detections
+            state.grass = active_grass.lock().unwrap().clone();
+            // The nearest depth ahead in the frame of the detections
+            state.depth_ahead = *active_depth_ahead.lock().unwrap();
+            // The hottest part of the robot in view of the thermal camera
+            state.part_temp = *active_part_temp.lock().unwrap();
+            // The FPS and the latency of the detections, told to the commander too
+            state.vision = *active_metrics.lock().unwrap();
+            state.telemetry.vision = state.vision.map(|metrics| telemetry::Vision {
+                fps: metrics.fps,
+                latency_ms: metrics.latency_ms.min(u16::MAX as u64) as u16,
+            });
+
+            // Take the commands of the QR code signs stopping the robot as those of the commander.
+            if property.conf.qr.enabled {
+                let conf = &property.conf.qr;
+                state.no_go = qr::no_go_close(&dets, state.img_width, conf.no_go_ratio);
+                let now = chrono::Utc::now().timestamp_millis() as u64;
+                for command in signs.observe(&dets, now, conf.gap * 1000) {
+                    match qr::to_parent_msg(&command) {
+                        Some(msg) => {
+                            log::info!("Command Sign Read: {}", command);
+                            let _ = channel_neighbor_tx.send(Neighbor::from_parent_msg(msg));
+                        }
+                        None => log::warn!("Command Sign Ignored, Not Stopping: {}", command),
+                    }
+                }
+            }
 
             // Pre-processing for handling
             let _ = pre_process(&mut state, &mut device);
+            // The target is chosen again for the detections.
+            state.target = None;
 
             // Drive Handling
             handler.handle(
@@ -117,19 +702,272 @@ pub fn run(property: RoktrackProperty) -> JoinHandle<()> {
             // Post-processing for handling
             let _ = post_process(&mut state, &mut device);
 
+            // Draw the decision on the frame now and then.
+            if property.conf.debug_overlay.enabled {
+                let movement = device.inner.lock().unwrap().movement;
+                if let Err(e) =
+                    debug_overlay.update(&property, &state, &dets, movement, stream.as_ref())
+                {
+                    log::warn!("Can't Draw Debug Overlay: {}", e);
+                }
+            }
+
+            // Save the progress of the fill mission now and then.
+            let interval = property.conf.progress.interval;
+            if state.state
+                && state.mode == Modes::Fill
+                && 0 < interval
+                && Duration::from_secs(interval) <= progress_saved.elapsed()
+            {
+                progress_saved = Instant::now();
+                let saved = FillProgress::from_state(&state, chrono::Utc::now().timestamp_millis());
+                if let Err(e) = saved.save(&progress_path) {
+                    log::warn!("Can't Save Fill Progress: {}", e);
+                }
+            }
+
+            // Estimate the work rate of fill-type jobs.
+            state.telemetry.work = if work_rate::is_fill_type(state.mode) {
+                let (forward, cruise) = {
+                    let device_lock = device.inner.lock().unwrap();
+                    (
+                        device_lock.forward_since.is_some(),
+                        device_lock.speed.cruise,
+                    )
+                };
+                state.work.observe(
+                    state.state,
+                    forward,
+                    property.conf.motion.speed as f64 * cruise,
+                    chrono::Utc::now().timestamp_millis() as u64,
+                );
+                let lane_width = work_rate::lane_width(state.mode, &property.conf);
+                Some(state.work.summary(lane_width, state.rest))
+            } else {
+                None
+            };
+
+            if let Some(grpc) = &grpc {
+                grpc.update_state(&state);
+                grpc.update_neighbors(&neighbors.by_identifier());
+            }
+            if let Some(websocket) = &websocket {
+                websocket.publish(websocket::detections_message(
+                    &[&dets[..], &state.rear].concat(),
+                ));
+                websocket.publish(websocket::state_message(&state));
+            }
+
+            // Deliver important messages to the commander reliably.
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            if state.msg != last_msg && ChildMsg::requires_ack(state.msg) {
+                link.send(state.msg, 0, now);
+            }
+            last_msg = state.msg;
+            match link.poll(now) {
+                Delivery::Sending(seq) => {
+                    state.seq = seq;
+                    state.msg = link.pending().map(|p| p.msg).unwrap_or(state.msg);
+                }
+                Delivery::Failed(seq, msg) => {
+                    log::warn!("Message Not Delivered. seq: {}, msg: {}", seq, msg);
+                    state.seq = 0;
+                }
+                Delivery::Delivered(_) | Delivery::Idle => state.seq = 0,
+            }
+
             // Broadcast my state to neighbors.
-            let payload = state.dump(&neighbors.clone());
-            com.inner
-                .clone()
-                .lock()
-                .unwrap()
-                .cast(&state.identifier, payload);
+            let mut payload = state.dump(&neighbors.by_identifier());
+            // Telemetry follows the legacy payload where the transport can carry it.
+            if com
+                .as_ref()
+                .is_some_and(|com| com.capabilities().max_payload > PAYLOAD_LEN)
+            {
+                state.telemetry.progress = Some(((1.0 - state.rest) * 100.0) as u8);
+                state.telemetry.clock_ms =
+                    Some(clock.now_ms(chrono::Utc::now().timestamp_millis()));
+                payload.extend(state.telemetry.encode());
+            }
+            checksum::set_crc(state.identifier, &mut payload);
+            if let Some(gatt) = &gatt {
+                gatt.update(payload.clone());
+            }
+            if let Some(mqtt) = &mqtt {
+                let mut frame = vec![state.identifier];
+                frame.extend_from_slice(&payload);
+                if let Ok(me) = Neighbor::from_frame(&frame, None) {
+                    mqtt.publish(&me);
+                }
+            }
+            if let Some(trailer) = &trailer {
+                if let Err(e) = trailer.send(&state.identifier, payload.clone()) {
+                    log::warn!("Trailer Link Failed: {}", e);
+                }
+            }
+            if let Some(com) = &com {
+                if let Err(e) = com.send(&state.identifier, payload) {
+                    log::warn!("BroadCast Failed: {}", e);
+                }
+            }
         }
     })
 }
 
+/// Start receiving the states of the neighbors and the commands over the transport into the channel.
+///
+/// Returns `None` for transports that can only send.
+fn listen_neighbors(
+    com: &dyn CommTransport,
+    tx: BoundedSender<Neighbor>,
+) -> Option<JoinHandle<()>> {
+    com.capabilities().bidirectional.then(|| com.listen(tx))
+}
+
+/// Whether the broadcasts of the neighbor are accepted. Direct links (GATT, MQTT) have no MAC.
+fn trusted(conf: &Config, trust: &mut TrustStore, neighbor: &Neighbor) -> bool {
+    let trusted = !conf.com.trusted_only
+        || neighbor.mac.is_empty()
+        || trust.admit(&neighbor.mac, neighbor.identifier, Instant::now());
+    if !trusted {
+        log::debug!("Untrusted Device Ignored: {}", neighbor.mac);
+    }
+    trusted
+}
+
+/// Reassembles large messages into the channel. Returns the neighbor unless it carries a fragment, which carries no state.
+fn reassemble(
+    reassembler: &mut Reassembler,
+    mut neighbor: Neighbor,
+    tx: &BoundedSender<Message>,
+) -> Option<Neighbor> {
+    let Some(fragment) = neighbor.fragment.take() else {
+        return Some(neighbor);
+    };
+    let msg_id = fragment.msg_id;
+    if let Some(data) = reassembler.push(&neighbor.mac, fragment, Instant::now()) {
+        log::info!(
+            "Message Received From: {}, id: {}, {} bytes",
+            neighbor.identifier,
+            msg_id,
+            data.len()
+        );
+        let _ = tx.send(Message {
+            identifier: neighbor.identifier,
+            msg_id,
+            data,
+        });
+    }
+    None
+}
+
+/// Handles a large message by its id. Returns whether the waypoints file has been replaced.
+fn handle_message(conf: &Config, data_dir: &str, driving: bool, message: Message) -> bool {
+    match message.msg_id {
+        // Take the route of the neighbors only while stopped, not to change it under the wheels.
+        fragment::WAYPOINTS_MSG_ID if conf.waypoint.share && !driving => {
+            match save_waypoints(conf, data_dir, &message.data) {
+                Ok(n) => {
+                    log::info!(
+                        "Waypoints Received From: {}, {} waypoints",
+                        message.identifier,
+                        n
+                    );
+                    true
+                }
+                Err(e) => {
+                    log::warn!("Waypoints From: {} Not Taken: {}", message.identifier, e);
+                    false
+                }
+            }
+        }
+        msg_id => {
+            log::debug!(
+                "Message Ignored From: {}, id: {}",
+                message.identifier,
+                msg_id
+            );
+            false
+        }
+    }
+}
+
+/// Saves the waypoints received as the waypoints file, once they are parsed. Returns their number.
+fn save_waypoints(
+    conf: &Config,
+    data_dir: &str,
+    data: &[u8],
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let waypoints = waypoint::parse(std::str::from_utf8(data)?)?;
+    if waypoints.is_empty() {
+        return Err("No waypoints.".into());
+    }
+    std::fs::write(Path::new(data_dir).join(&conf.waypoint.file), data)?;
+    Ok(waypoints.len())
+}
+
+/// Sends the waypoints file to the neighbors in fragments.
+fn share_waypoints(
+    com: &dyn CommTransport,
+    identifier: u8,
+    conf: &Config,
+    data_dir: &str,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let data = std::fs::read(Path::new(data_dir).join(&conf.waypoint.file))?;
+    com.send_message(&identifier, fragment::WAYPOINTS_MSG_ID, &data)
+}
+
+/// Handle pause and resume.
+///
+/// While paused, the other commands than Off are ignored so that the snapshot stays valid.
+/// Off discards the snapshot. Returns whether the command has been handled.
+fn pause_command(
+    msg: ParentMsg,
+    paused: &mut Option<RoktrackState>,
+    state: &mut RoktrackState,
+    handler: &mut Box<dyn PilotHandler>,
+    device: &mut Roktrack,
+    tx: Sender<VisionMgmtCommand>,
+) -> bool {
+    match (msg, paused.take()) {
+        (ParentMsg::Pause, None) => {
+            if state.state {
+                handler.pause(device);
+                *paused = Some(state.clone());
+                state.state = false;
+                let binding = device.inner.clone();
+                let mut device_lock = binding.lock().unwrap();
+                device_lock.stop();
+                // Cancel the move in progress. The pilot has kept the rest of it.
+                device_lock.target_time = 0;
+                tx.send(VisionMgmtCommand::Off).unwrap();
+                log::info!("Paused.");
+            }
+            true
+        }
+        (ParentMsg::Resume, Some(snapshot)) => {
+            state.restore(snapshot);
+            handler.resume(device);
+            tx.send(VisionMgmtCommand::On).unwrap();
+            log::info!("Resumed.");
+            true
+        }
+        (ParentMsg::Resume, None) => true,
+        (ParentMsg::Off, Some(_)) => {
+            log::info!("Pause Discarded.");
+            true
+        }
+        (_, Some(snapshot)) => {
+            log::debug!("Paused. Resume or turn off first.");
+            *paused = Some(snapshot);
+            true
+        }
+        _ => false,
+    }
+}
+
 /// Handle commands received from neighbors.
 fn command_to_handler(
+    registry: &SharedRegistry,
     state: &mut RoktrackState,
     neighbor: &Neighbor,
     device: &mut Roktrack,
@@ -150,8 +988,18 @@ fn command_to_handler(
             }
             ParentMsg::On => {
                 if !state.state {
-                    state.state = true;
-                    tx.send(VisionMgmtCommand::On).unwrap();
+                    // Refuse to start a job that the battery can't finish.
+                    if battery::is_sufficient(state.telemetry.battery_mv, state.mode, &conf) {
+                        state.state = true;
+                        state.work = work_rate::WorkRate::default();
+                        tx.send(VisionMgmtCommand::On).unwrap();
+                    } else {
+                        log::warn!(
+                            "Charge Insufficient: {} mV. Not Started.",
+                            state.telemetry.battery_mv.unwrap_or_default()
+                        );
+                        state.msg = ChildMsg::to_u8(ChildMsg::ChargeInsufficient);
+                    }
                 }
                 None
             }
@@ -166,7 +1014,7 @@ fn command_to_handler(
             ParentMsg::Fill => {
                 if !state.state && state.mode != Modes::Fill {
                     state.mode = Modes::Fill;
-                    mode_to_handler(state.mode, tx, conf)
+                    mode_to_handler(registry, state.mode, tx, conf)
                 } else {
                     None
                 }
@@ -174,17 +1022,31 @@ fn command_to_handler(
             ParentMsg::Oneway => {
                 if !state.state && state.mode != Modes::OneWay {
                     state.mode = Modes::OneWay;
-                    mode_to_handler(state.mode, tx, conf)
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Climb => {
+                if !state.state && state.mode != Modes::Climb {
+                    state.mode = Modes::Climb;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Around => {
+                if !state.state && state.mode != Modes::Around {
+                    state.mode = Modes::Around;
+                    mode_to_handler(registry, state.mode, tx, conf)
                 } else {
                     None
                 }
             }
-            ParentMsg::Climb => None,
-            ParentMsg::Around => None,
             ParentMsg::MonitorPerson => {
                 if !state.state && state.mode != Modes::MonitorPerson {
                     state.mode = Modes::MonitorPerson;
-                    mode_to_handler(state.mode, tx, conf)
+                    mode_to_handler(registry, state.mode, tx, conf)
                 } else {
                     None
                 }
@@ -192,7 +1054,7 @@ fn command_to_handler(
             ParentMsg::MonitorAnimal => {
                 if !state.state && state.mode != Modes::MonitorAnimal {
                     state.mode = Modes::MonitorAnimal;
-                    mode_to_handler(state.mode, tx, conf)
+                    mode_to_handler(registry, state.mode, tx, conf)
                 } else {
                     None
                 }
@@ -201,7 +1063,144 @@ fn command_to_handler(
             ParentMsg::FollowPerson => {
                 if !state.state && state.mode != Modes::FollowPerson {
                     state.mode = Modes::FollowPerson;
-                    mode_to_handler(state.mode, tx, conf)
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Spiral => {
+                if !state.state && state.mode != Modes::Spiral {
+                    state.mode = Modes::Spiral;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Stripe => {
+                if !state.state && state.mode != Modes::Stripe {
+                    state.mode = Modes::Stripe;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::PerimeterTrim => {
+                if !state.state && state.mode != Modes::PerimeterTrim {
+                    state.mode = Modes::PerimeterTrim;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Waypoint => {
+                if !state.state && state.mode != Modes::Waypoint {
+                    state.mode = Modes::Waypoint;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Spot => {
+                if !state.state && state.mode != Modes::Spot {
+                    state.mode = Modes::Spot;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::EdgeFollow => {
+                if !state.state && state.mode != Modes::EdgeFollow {
+                    // Refused without the grass segmentation model, keeping the current mode.
+                    let handler = mode_to_handler(registry, Modes::EdgeFollow, tx, conf);
+                    if handler.is_some() {
+                        state.mode = Modes::EdgeFollow;
+                    }
+                    handler
+                } else {
+                    None
+                }
+            }
+            ParentMsg::AnimalDeterrent => {
+                if !state.state && state.mode != Modes::AnimalDeterrent {
+                    state.mode = Modes::AnimalDeterrent;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Patrol => {
+                if !state.state && state.mode != Modes::Patrol {
+                    state.mode = Modes::Patrol;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Mission => {
+                if !state.state && state.mode != Modes::Mission {
+                    state.mode = Modes::Mission;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Orchard => {
+                if !state.state && state.mode != Modes::Orchard {
+                    state.mode = Modes::Orchard;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::TrailerDock => {
+                if !state.state && state.mode != Modes::TrailerDock {
+                    state.mode = Modes::TrailerDock;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Calibrate => {
+                if !state.state && state.mode != Modes::Calibrate {
+                    state.mode = Modes::Calibrate;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::CameraCalibrate => {
+                if !state.state && state.mode != Modes::CameraCalibrate {
+                    state.mode = Modes::CameraCalibrate;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::DatasetCapture => {
+                if !state.state && state.mode != Modes::DatasetCapture {
+                    state.mode = Modes::DatasetCapture;
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            ParentMsg::Custom(i) => {
+                if !state.state && state.mode != Modes::Custom(i) {
+                    state.mode = Modes::Custom(i);
+                    mode_to_handler(registry, state.mode, tx, conf)
+                } else {
+                    None
+                }
+            }
+            // Return to the dock even while running.
+            ParentMsg::ReturnToDock => {
+                if state.mode != Modes::ReturnToDock {
+                    state.mode = Modes::ReturnToDock;
+                    if !state.state {
+                        state.state = true;
+                        tx.send(VisionMgmtCommand::On).unwrap();
+                    }
+                    mode_to_handler(registry, state.mode, tx, conf)
                 } else {
                     None
                 }
@@ -221,46 +1220,190 @@ fn command_to_handler(
 }
 /// Convert mode to handler
 fn mode_to_handler(
+    registry: &SharedRegistry,
     mode: Modes,
     tx: Sender<VisionMgmtCommand>,
     conf: Config,
 ) -> Option<Box<dyn PilotHandler>> {
-    match mode {
-        Modes::Fill => {
-            match conf.vision.ocr {
-                true => tx.send(VisionMgmtCommand::SwitchSessionPylonOcr).unwrap(),
-                false => tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap(),
-            }
-            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
-            Some(Box::new(Fill::new()))
-        }
-        Modes::OneWay => {
-            tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
-            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
-            Some(Box::new(OneWay::new()))
-        }
-        Modes::Climb => None,
-        Modes::Around => None,
-        Modes::MonitorPerson => {
-            tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
-            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
-            Some(Box::new(MonitorPerson::new()))
-        }
-        Modes::MonitorAnimal => {
-            tx.send(VisionMgmtCommand::SwitchSessionAnimal).unwrap();
-            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
-            Some(Box::new(MonitorAnimal::new()))
-        }
-        Modes::RoundTrip => {
-            tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
-            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
-            Some(Box::new(RoundTrip::new()))
-        }
-        Modes::FollowPerson => {
-            tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
-            tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
-            Some(Box::new(FollowPerson::new()))
-        }
-        _ => None,
+    registry.lock().unwrap().create(mode, tx, conf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::com::transport::Capabilities;
+    use crate::module::com::udp::UdpTransport;
+    use crate::module::util::path::{RoktrackDir, RoktrackImg, RoktrackPath};
+    use std::net::UdpSocket;
+
+    #[test]
+    fn listen_neighbors_test() {
+        let dir = std::env::temp_dir().join("roktrack_listen_neighbors_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = dir.to_string_lossy().to_string();
+        let file = |name: &str| dir.join(name).to_string_lossy().to_string();
+        let mut conf = crate::module::util::conf::toml::load(&data).unwrap();
+        conf.udp.port = 18275;
+        conf.udp.interface = String::from("127.0.0.1");
+        let property = RoktrackProperty {
+            path: RoktrackPath {
+                dir: RoktrackDir {
+                    data: data.clone(),
+                    tmp: data.clone(),
+                    img: data.clone(),
+                    log: data.clone(),
+                },
+                img: RoktrackImg {
+                    last: file("last.jpg"),
+                    right: file("right.jpg"),
+                    depth: file("depth.png"),
+                    rear: file("rear.jpg"),
+                    crop: file("crop.jpg"),
+                    tile: file("tile.jpg"),
+                },
+            },
+            conf,
+        };
+        let com = UdpTransport::new(property).unwrap();
+        let (tx, rx) = channel::bounded(channel::DEFAULT_CAPACITY);
+        assert!(listen_neighbors(&com, tx).is_some());
+
+        // A state of the robot 12 from another host
+        let mut payload = vec![100, 45, 0, 255, 255, 0, 0, 0, WIRE_VERSION];
+        payload.resize(PAYLOAD_LEN, 0);
+        checksum::set_crc(12, &mut payload);
+        let mut frame = vec![12];
+        frame.extend_from_slice(&payload);
+        UdpSocket::bind("127.0.0.1:0")
+            .unwrap()
+            .send_to(&frame, ("127.0.0.1", 18275))
+            .unwrap();
+
+        let start = Instant::now();
+        let neighbor = loop {
+            if let Ok(neighbor) = rx.try_recv() {
+                break neighbor;
+            }
+            assert!(
+                start.elapsed() < Duration::from_secs(2),
+                "Nothing received."
+            );
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(neighbor.identifier, 12);
+        assert_eq!(neighbor.pi_temp, 45);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Transport handing the frames sent back to the test.
+    struct Loopback {
+        frames: std::sync::Mutex<Vec<Vec<u8>>>,
+    }
+
+    impl CommTransport for Loopback {
+        fn send(
+            &self,
+            identifier: &u8,
+            payload: Vec<u8>,
+        ) -> Result<(), Box<dyn std::error::Error>> {
+            let mut frame = vec![*identifier];
+            frame.extend(payload);
+            self.frames.lock().unwrap().push(frame);
+            Ok(())
+        }
+
+        fn listen(&self, _tx: BoundedSender<Neighbor>) -> JoinHandle<()> {
+            thread::spawn(|| {})
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            Capabilities {
+                name: "loopback",
+                max_payload: PAYLOAD_LEN + 16,
+                bidirectional: true,
+                range_m: 0,
+            }
+        }
+    }
+
+    #[test]
+    fn message_test() {
+        let dir = std::env::temp_dir().join("roktrack_message_test");
+        std::fs::create_dir_all(&dir).unwrap();
+        let data = dir.to_string_lossy().to_string();
+        let mut conf = crate::module::util::conf::toml::load(&data).unwrap();
+        conf.waypoint.share = true;
+        let file = dir.join(&conf.waypoint.file);
+        let route = "35.0,139.0\n35.001,139.001\n35.002,139.002\n";
+        std::fs::write(&file, route).unwrap();
+
+        // The waypoints are sent in fragments.
+        let com = Loopback {
+            frames: std::sync::Mutex::new(vec![]),
+        };
+        share_waypoints(&com, 12, &conf, &data).unwrap();
+        let frames = com.frames.into_inner().unwrap();
+        assert!(frames.len() > 1);
+
+        // The receiver gets the whole message.
+        let (tx, rx) = channel::bounded(channel::DEFAULT_CAPACITY);
+        let mut reassembler = Reassembler::new();
+        for frame in &frames {
+            let neighbor = Neighbor::from_frame(frame, None).unwrap();
+            assert!(reassemble(&mut reassembler, neighbor, &tx).is_none());
+        }
+        let message = rx.try_recv().unwrap();
+        assert_eq!(message.identifier, 12);
+        assert_eq!(message.msg_id, fragment::WAYPOINTS_MSG_ID);
+        assert_eq!(message.data, route.as_bytes());
+
+        // The waypoints are taken only while stopped.
+        std::fs::remove_file(&file).unwrap();
+        assert!(!handle_message(&conf, &data, true, message.clone()));
+        assert!(!file.exists());
+        assert!(handle_message(&conf, &data, false, message));
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), route);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    /// Test that each sign stops the robot, through the same handling as the commands.
+    ///
+    /// NOTE: This test needs the GPIO of the Raspberry Pi.
+    #[test]
+    #[ignore]
+    fn sign_command_test() {
+        let paths = crate::module::util::path::dir::create_app_sub_dir();
+        let mut conf = crate::module::util::conf::toml::load(&paths.dir.data).unwrap();
+        conf.drive.dry_run = true;
+        let registry = registry::builtin();
+        let mut device = crate::module::device::Roktrack::new(conf.clone());
+        let (tx, _rx) = mpsc::channel();
+        for sign in ["off", "stop", "pause"] {
+            let msg = qr::to_parent_msg(sign).unwrap();
+            let neighbor = Neighbor::from_parent_msg(msg);
+            let mut state = RoktrackState::new();
+            state.state = true;
+            let mut handler = mode_to_handler(&registry, state.mode, tx.clone(), conf.clone())
+                .expect("Can't initialize handler.");
+            let mut paused = None;
+            if !pause_command(
+                ParentMsg::from_u8(neighbor.msg),
+                &mut paused,
+                &mut state,
+                &mut handler,
+                &mut device,
+                tx.clone(),
+            ) {
+                command_to_handler(
+                    &registry,
+                    &mut state,
+                    &neighbor,
+                    &mut device,
+                    tx.clone(),
+                    conf.clone(),
+                );
+            }
+            assert!(!state.state, "Not stopped by {}", sign);
+        }
     }
 }
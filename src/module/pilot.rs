@@ -1,26 +1,72 @@
 //! This module provides automatic operation modes.
 
 // Import the submodules for operation modes
+pub mod animal_deterrent; // Animal deterrent module
+pub mod around; // Around module
 pub mod base; // Base module
+pub mod battery; // Battery policy module
+pub mod calibrate; // Calibration dance module
+pub mod calibration; // Calibration results module
+pub mod camera_calibrate; // Camera calibration module
+pub mod climb; // Climb module
+pub mod dataset_capture; // Dataset capture module
+pub mod edge_follow; // Edge following module
 pub mod fill; // Fill module
 pub mod follow_person; // Follow person module
+pub mod geofence; // Geofence module
+pub mod lifecycle; // Mission lifecycle module
+pub mod lost; // Vision-loss failsafe module
+pub mod maneuver; // Timed maneuvers module
+pub mod mission; // Mission sequencer module
 pub mod monitor_animal; // Monitoring animal module
 pub mod monitor_person; // Monitoring person module
+pub mod night; // Night operation profile module
 pub mod oneway; // One-way module
+pub mod orchard; // Orchard lane module
+pub mod patrol; // Patrol module
+pub mod perimeter_trim; // Perimeter trim module
+pub mod person; // Person detected policy module
+pub mod pid; // PID heading controller module
+pub mod progress; // Fill progress persistence module
+pub mod rain; // Rain abort policy module
+pub mod registry; // Pilot handler registry module
+pub mod return_to_dock; // Return to dock module
+pub mod risk; // System risk engine module
 pub mod round_trip; // Round-trip between person and marker module
+pub mod speed; // Speed profile module
+pub mod spiral; // Spiral module
+pub mod spot; // Spot module
+pub mod stripe; // Stripe module
+pub mod stuck; // Stuck detection module
+pub mod trailer_dock; // Trailer docking assist module
+pub mod triangulation; // Multi-marker triangulation module
+pub mod waypoint; // GPS waypoint module
+pub mod work_rate; // Work rate estimation module
 
 use super::{
-    com::Neighbor, // Import the Neighbor type from the com module
+    com::{
+        codec::{StateFrame, WIRE_VERSION},
+        telemetry::Telemetry,
+        Neighbor,
+    }, // Import the Neighbor type, the codec and telemetry from the com module
     device::Roktrack,
     util::init::RoktrackProperty,
-    vision::{detector::Detection, VisionMgmtCommand},
+    vision::{
+        detector::{segment::GrassMask, Detection},
+        metrics::Metrics,
+        odometry::Pose,
+        VisionMgmtCommand,
+    },
 };
 use rand::{self, seq::SliceRandom, Rng}; // Import random number generation
 use std::collections::HashMap;
 use std::sync::mpsc::Sender; // Import HashMap for storage
 
+/// Numbers reserved for custom modes.
+pub const CUSTOM_MODES: std::ops::RangeInclusive<u8> = 100..=199;
+
 /// Automatic operation modes.
-#[derive(Debug, Clone, PartialEq, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Copy)]
 pub enum Modes {
     Fill,
     OneWay,
@@ -30,6 +76,22 @@ pub enum Modes {
     MonitorAnimal,
     RoundTrip,
     FollowPerson,
+    ReturnToDock,
+    Spiral,
+    Stripe,
+    PerimeterTrim,
+    Waypoint,
+    Spot,
+    EdgeFollow,
+    AnimalDeterrent,
+    Patrol,
+    Mission,
+    Orchard,
+    TrailerDock,
+    Calibrate,
+    CameraCalibrate,
+    DatasetCapture,
+    Custom(u8), // Handlers registered out of this crate (100 - 199)
     Unknown,
 }
 
@@ -45,7 +107,26 @@ impl Modes {
             "monitor_person" => Modes::MonitorPerson,
             "round_trip" => Modes::RoundTrip,
             "follow_person" => Modes::FollowPerson,
-            _ => Modes::Unknown,
+            "return_to_dock" => Modes::ReturnToDock,
+            "spiral" => Modes::Spiral,
+            "stripe" => Modes::Stripe,
+            "perimeter_trim" => Modes::PerimeterTrim,
+            "waypoint" => Modes::Waypoint,
+            "spot" => Modes::Spot,
+            "edge_follow" => Modes::EdgeFollow,
+            "animal_deterrent" => Modes::AnimalDeterrent,
+            "patrol" => Modes::Patrol,
+            "mission" => Modes::Mission,
+            "orchard" => Modes::Orchard,
+            "trailer_dock" => Modes::TrailerDock,
+            "calibrate" => Modes::Calibrate,
+            "camera_calibrate" => Modes::CameraCalibrate,
+            "dataset_capture" => Modes::DatasetCapture,
+            // e.g. "custom_100"
+            _ => match s.strip_prefix("custom_").and_then(|i| i.parse::<u8>().ok()) {
+                Some(i) if CUSTOM_MODES.contains(&i) => Modes::Custom(i),
+                _ => Modes::Unknown,
+            },
         }
     }
 
@@ -60,6 +141,22 @@ impl Modes {
             5 => Modes::MonitorAnimal,
             6 => Modes::RoundTrip,
             7 => Modes::FollowPerson,
+            8 => Modes::ReturnToDock,
+            9 => Modes::Spiral,
+            10 => Modes::Stripe,
+            11 => Modes::PerimeterTrim,
+            12 => Modes::Waypoint,
+            13 => Modes::Spot,
+            14 => Modes::EdgeFollow,
+            15 => Modes::AnimalDeterrent,
+            16 => Modes::Patrol,
+            17 => Modes::Mission,
+            18 => Modes::Orchard,
+            19 => Modes::TrailerDock,
+            20 => Modes::Calibrate,
+            21 => Modes::CameraCalibrate,
+            22 => Modes::DatasetCapture,
+            i if CUSTOM_MODES.contains(&i) => Modes::Custom(i),
             _ => Modes::Unknown,
         }
     }
@@ -75,6 +172,22 @@ impl Modes {
             Modes::MonitorAnimal => 5,
             Modes::RoundTrip => 6,
             Modes::FollowPerson => 7,
+            Modes::ReturnToDock => 8,
+            Modes::Spiral => 9,
+            Modes::Stripe => 10,
+            Modes::PerimeterTrim => 11,
+            Modes::Waypoint => 12,
+            Modes::Spot => 13,
+            Modes::EdgeFollow => 14,
+            Modes::AnimalDeterrent => 15,
+            Modes::Patrol => 16,
+            Modes::Mission => 17,
+            Modes::Orchard => 18,
+            Modes::TrailerDock => 19,
+            Modes::Calibrate => 20,
+            Modes::CameraCalibrate => 21,
+            Modes::DatasetCapture => 22,
+            Modes::Custom(i) => i,
             _ => 255,
         }
     }
@@ -90,20 +203,40 @@ pub enum Phase {
 /// This struct represents the state for auto-pilot.
 #[derive(Debug, Clone)]
 pub struct RoktrackState {
-    pub state: bool,           // On / Off
-    pub mode: Modes,           // Drive mode
-    pub turn_count: i8,        // Continuous turn counter
-    pub ex_height: u16,        // Last seen marker height for searching the next one
-    pub rest: f32,             // Remaining work (0.0 -> 1.0)
+    pub state: bool,                           // On / Off
+    pub mode: Modes,                           // Drive mode
+    pub turn_count: i8,                        // Continuous turn counter
+    pub ex_height: u16,                        // Last seen marker height for searching the next one
+    pub rest: f32,                             // Remaining work (0.0 -> 1.0)
     pub target_height: u16, // When you approach this target height, start looking for the next marker.
     pub phase: Phase,       // Direction of laps
     pub constant: f32,      // Amount to be subtracted from rest for each marker approach
     pub marker_id: Option<u8>, // Record the ID assigned to the marker when OCR mode is on
+    pub marker_track: Option<u32>, // Tracking ID of the marker being approached
+    pub target: Option<Detection>, // Marker proceeded to for the last detections (None: none)
     pub pi_temp: f32,       // Raspberry Pi's SoC temperature
     pub msg: u8,            // Current state message
     pub identifier: u8,     // My identifier
     pub img_width: u32,     // Width of the image to process
     pub img_height: u32,    // Height of the image to process
+    pub seq: u8,            // Sequence number of the message being delivered (0: none)
+    pub ack: u8,            // Last sequence number received from the commander
+    pub telemetry: Telemetry, // Additional telemetry for extended advertisements
+    pub laps: u8,           // Completed laps in Around mode
+    pub heading: Option<pid::Pid>, // Heading controller (None: bang-bang corrections)
+    pub last_contact: Option<i64>, // Time of the last message from the commander in ms
+    pub stuck: stuck::StuckMonitor, // Changes of the scene to detect getting stuck
+    pub work: work_rate::WorkRate, // Distance and time of the current fill-type job
+    pub night: bool,        // Whether the night profile is in effect
+    pub calibration: calibration::Calibration, // Results of the calibration mode
+    pub model: String,      // Name of the active detection model
+    pub grass: Option<GrassMask>, // Drivable grass in the last frame (None: not segmented)
+    pub depth_ahead: Option<f32>, // Nearest depth ahead in m (None: not measured)
+    pub odometry: Option<Pose>, // Pose by the visual odometry since the start (None: not estimated)
+    pub rear: Vec<Detection>, // Detections of the rear camera in the last frame
+    pub no_go: bool,        // Whether a no-go sign is close ahead
+    pub part_temp: Option<f32>, // Hottest part seen by the thermal camera in °C (None: not seen)
+    pub vision: Option<Metrics>, // FPS and latency of the detections (None: not detected yet)
 }
 
 impl Default for RoktrackState {
@@ -125,6 +258,8 @@ impl RoktrackState {
             phase: Phase::CCW,
             constant: 0.005,
             marker_id: None,
+            marker_track: None,
+            target: None,
             pi_temp: 0.0,
             msg: 255,
             // Identifier's Preserved Addresses
@@ -134,6 +269,24 @@ impl RoktrackState {
             identifier: rand::thread_rng().gen_range(1..250),
             img_width: 320,
             img_height: 240,
+            seq: 0,
+            ack: 0,
+            telemetry: Telemetry::default(),
+            laps: 0,
+            heading: None,
+            last_contact: None,
+            stuck: stuck::StuckMonitor::default(),
+            work: work_rate::WorkRate::default(),
+            night: false,
+            calibration: calibration::Calibration::default(),
+            model: String::new(),
+            grass: None,
+            depth_ahead: None,
+            odometry: None,
+            rear: vec![],
+            no_go: false,
+            part_temp: None,
+            vision: None,
         }
     }
 
@@ -147,9 +300,23 @@ impl RoktrackState {
         self.phase = Phase::CCW;
         self.constant = 0.005;
         self.marker_id = None;
+        self.marker_track = None;
         self.msg = 255;
         self.img_width = 320;
         self.img_height = 240;
+        self.laps = 0;
+    }
+
+    /// Restore the progress of the drive from a snapshot, keeping the live readings.
+    pub fn restore(&mut self, snapshot: RoktrackState) {
+        let live = std::mem::replace(self, snapshot);
+        self.pi_temp = live.pi_temp;
+        self.msg = live.msg;
+        self.identifier = live.identifier;
+        self.seq = live.seq;
+        self.ack = live.ack;
+        self.telemetry = live.telemetry;
+        self.last_contact = live.last_contact;
     }
 
     /// Invert the phase (CCW -> CW) and reset counters.
@@ -165,19 +332,19 @@ impl RoktrackState {
             let pool: Vec<u8> = (1..250).filter(|x| !used_identifiers.contains(x)).collect();
             self.identifier = *pool.choose(&mut rand::thread_rng()).unwrap();
         }
-        // Construct the first byte
-        let state_and_rest = format!("{:b}{:b}", self.state as u8, (self.rest * 100.0) as u8);
-        let state_and_rest: u8 = isize::from_str_radix(&state_and_rest, 2).unwrap_or(0) as u8;
         // Construct the payload
-        let mut val = vec![
-            state_and_rest,          // State and rest
-            self.pi_temp as u8,      // Pi temperature
-            Modes::to_u8(self.mode), // Mode as int
-            self.msg,                // Message
-            255,                     // Destination
-        ];
-        // Padding
-        val.resize(23, 0);
+        let val = StateFrame {
+            state: self.state,
+            rest: (self.rest * 100.0) as u8,
+            pi_temp: self.pi_temp as u8,
+            mode: self.mode,
+            msg: self.msg,
+            dest: 255,
+            seq: self.seq,
+            ack: self.ack,
+            version: WIRE_VERSION,
+        }
+        .encode();
         log::debug!("Dump My State: {:?}", val);
         val
     }
@@ -192,9 +359,13 @@ mod tests {
         // from u8
         assert_eq!(Modes::from_u8(0), Modes::Fill);
         assert_eq!(Modes::from_u8(254), Modes::Unknown);
+        assert_eq!(Modes::from_u8(100), Modes::Custom(100));
+        assert_eq!(Modes::from_string("custom_150"), Modes::Custom(150));
+        assert_eq!(Modes::from_string("custom_50"), Modes::Unknown);
         // to u8
         assert_eq!(Modes::to_u8(Modes::Fill), 0);
         assert_eq!(Modes::to_u8(Modes::Unknown), 255);
+        assert_eq!(Modes::to_u8(Modes::Custom(150)), 150);
     }
 
     #[test]
@@ -209,11 +380,18 @@ mod tests {
         assert_eq!(state.phase, Phase::CCW);
         state.invert_phase();
         assert_eq!(state.phase, Phase::CW);
+        // restore test
+        let snapshot = state.clone();
+        state.reset();
+        state.pi_temp = 50.0;
+        state.restore(snapshot);
+        assert_eq!(state.phase, Phase::CW);
+        assert_eq!(state.pi_temp, 50.0);
         // dump test
         let neighbors = HashMap::new();
         assert_eq!(
             state.dump(&neighbors),
-            [100, 0, 0, 255, 255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,]
+            [100, 0, 0, 255, 255, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,]
         )
     }
 }
@@ -230,4 +408,38 @@ pub trait PilotHandler: Send + Sync {
         property: RoktrackProperty,
     ) {
     }
+
+    /// Called when the robot is paused, before the motors are frozen.
+    /// Pilots keep what they need to continue from the same point.
+    fn pause(&mut self, device: &mut Roktrack) {}
+
+    /// Called when the robot resumes from a pause.
+    fn resume(&mut self, device: &mut Roktrack) {}
+
+    /// Called when a mission starts, before the first handling.
+    fn on_start(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: &RoktrackProperty,
+    ) {
+    }
+
+    /// Called when the mission is complete.
+    fn on_complete(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: &RoktrackProperty,
+    ) {
+    }
+
+    /// Called when the mission is aborted. The reason is in `state.msg`.
+    fn on_abort(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: &RoktrackProperty,
+    ) {
+    }
 }
@@ -0,0 +1,375 @@
+//! Animal Deterrent Pilot
+//!
+
+// # Normal flow of act phase
+//
+// Watch * n  <- Stay at the post, like MonitorAnimal.
+//    |
+// TurnLeft / TurnRight / Approach * n  <- An enabled species is detected. Head for it.
+//    |
+// Deter  <- Close enough, or the distance limit is reached. Play the sound.
+//    |
+// Return * n  <- Retrace the moves back to the post.
+//    |
+// Watch * n
+//
+// Every move from the post is recorded, so the way back is the same moves inverted in reverse order.
+// When the animal is lost or the bumper is hit on the way, the robot returns without deterring.
+// The pets of the house, enrolled in the `[pets]` section, are never chased (see `vision::pet`).
+
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use super::maneuver::{self, Maneuver};
+use super::{
+    risk::{self, Response, RiskEngine, SystemRisk},
+    PilotHandler,
+};
+use crate::module::{
+    device::motor::Motor,
+    device::{speaker, Chassis, Roktrack},
+    pilot::RoktrackState,
+    util::{common::send_line_notify_with_image, init::RoktrackProperty},
+    vision::detector::{sort, AnimalClasses, Detection},
+    vision::{pet::Pets, VisionMgmtCommand},
+};
+
+// Height of the animal in the image (ratio) at which it is deterred.
+const CLOSE_HEIGHT_RATIO: f32 = 0.5;
+
+// Tolerated difference (ratio to the image width) from the center before turning.
+const STEER_TOLERANCE: f32 = 0.1;
+
+// Duration of a turn in ms.
+const TURN_STEP_MS: u64 = 200;
+
+// Duration of a forward move in ms.
+const FORWARD_STEP_MS: u64 = 500;
+
+pub struct AnimalDeterrent {
+    route: Vec<Maneuver>, // Moves from the post
+    returning: bool,
+    retracing: Option<Maneuver>, // Move of the route being undone
+    last_detected_time: u64,
+    pets: Option<Pets>, // Pets enrolled at the first call
+    risks: RiskEngine,  // System risks to check before driving
+}
+
+impl AnimalDeterrent {
+    pub fn new() -> Self {
+        Self {
+            route: vec![],
+            returning: false,
+            retracing: None,
+            last_detected_time: 0,
+            pets: None,
+            risks: risk::builtin().respond_with(SystemRisk::Bumped, Response::Stop),
+        }
+    }
+
+    /// Whether the robot is at the post, not chasing nor returning.
+    pub fn is_at_post(&self) -> bool {
+        self.route.is_empty() && !self.returning
+    }
+}
+
+impl Default for AnimalDeterrent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for AnimalDeterrent {
+    /// Function called from a thread to handle the Animal Deterrent Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        _tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start AnimalDeterrent Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk == Some(SystemRisk::Bumped) {
+            // Escaping would lose the way back, so give up and return.
+            self.returning = true;
+        }
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Wait for the previous move.
+        if !maneuver::is_idle(device) {
+            return;
+        }
+
+        // The work motor is never used.
+        device.inner.clone().lock().unwrap().work_motor.stop();
+
+        // Retrace the route back to the post.
+        if self.returning {
+            self.retracing = self.route.pop();
+            match self.retracing {
+                Some(step) => maneuver::start(device, step.inverse()),
+                None => {
+                    log::info!("Back to the Post.");
+                    self.returning = false;
+                    device.inner.clone().lock().unwrap().pause();
+                }
+            }
+            return;
+        }
+
+        // The biggest animal of the enabled species is the target.
+        let conf = property.conf.deterrent.clone();
+        let mut animals = wild(&mut self.pets, detections, state, &property);
+        let detections = sort::big(&mut animals);
+        let target = detections
+            .iter()
+            .find(|det| is_enabled(det.cls, &conf.species))
+            .cloned()
+            .unwrap_or_default();
+        log::debug!("Target Animal Selected: {:?}", target);
+
+        let driven = driven_time(&self.route);
+        let limit = maneuver::travel_time(conf.max_distance, property.conf.motion.speed);
+        let action = assess_situation(state, &target, self.route.is_empty(), driven, limit);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let step = match action {
+            ActPhase::Watch => {
+                device.inner.clone().lock().unwrap().pause();
+                None
+            }
+            ActPhase::Lost => {
+                log::info!("Animal Lost. Return to the Post.");
+                self.returning = true;
+                None
+            }
+            ActPhase::Deter => {
+                device.inner.clone().lock().unwrap().pause();
+                log::warn!("Deter Animal: {:?}", AnimalClasses::from_u32(target.cls));
+                for _ in 0..conf.repeat {
+                    if let Err(e) = speaker::play(&conf.sound) {
+                        log::error!("Can't Play Deterrent Sound: {}, {}", conf.sound, e);
+                        device
+                            .inner
+                            .clone()
+                            .lock()
+                            .unwrap()
+                            .speak("animal_detecting");
+                        break;
+                    }
+                }
+                self.returning = true;
+                None
+            }
+            ActPhase::TurnLeft => Some(Maneuver::Left(TURN_STEP_MS)),
+            ActPhase::TurnRight => Some(Maneuver::Right(TURN_STEP_MS)),
+            ActPhase::Approach => Some(Maneuver::Forward(FORWARD_STEP_MS.min(limit - driven))),
+        };
+        if let Some(step) = step {
+            if self.route.is_empty() {
+                log::warn!("Animal Detected!! Start Deterring.");
+                device
+                    .inner
+                    .clone()
+                    .lock()
+                    .unwrap()
+                    .speak("animal_detecting");
+                // Get now.
+                let utc = chrono::Utc::now();
+                if self.last_detected_time + 60000 < utc.timestamp_millis() as u64 {
+                    log::debug!("Interval time has elapsed. Re-detection is notified.");
+                    self.last_detected_time = utc.timestamp_millis() as u64;
+                    let msg = format!(
+                        "{:?} detected. Deterring.",
+                        AnimalClasses::from_u32(target.cls).expect("Unknown animal.")
+                    );
+                    let _ =
+                        send_line_notify_with_image(&msg, &property.path.img.last, property.conf);
+                }
+            }
+            self.route.push(step);
+            maneuver::start(device, step);
+        }
+        log::debug!("End AnimalDeterrent Handle");
+    }
+
+    /// Keep the route exactly as driven, so that the way back is not lost by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        let remaining = maneuver::remaining(device);
+        if remaining == 0 {
+            return;
+        }
+        if self.returning {
+            // The part not undone yet stays on the route.
+            if let Some(step) = self.retracing.take() {
+                self.route.push(step.with_duration(remaining));
+            }
+        } else if let Some(step) = self.route.last_mut() {
+            // Only the part driven is on the route.
+            *step = step.with_duration(step.duration().saturating_sub(remaining));
+        }
+    }
+}
+
+/// Whether the species of the class is enabled, by its name (e.g. `deer`).
+pub fn is_enabled(cls: u32, species: &[String]) -> bool {
+    AnimalClasses::from_u32(cls).is_some_and(|animal| {
+        let name = format!("{:?}", animal).to_lowercase();
+        species.iter().any(|s| s.to_lowercase() == name)
+    })
+}
+
+/// The animals of the detections other than the pets, enrolled at the first call.
+pub fn wild(
+    pets: &mut Option<Pets>,
+    detections: &[Detection],
+    state: &RoktrackState,
+    property: &RoktrackProperty,
+) -> Vec<Detection> {
+    let conf = &property.conf.pets;
+    if !conf.enabled {
+        return detections.to_vec();
+    }
+    let pets = pets.get_or_insert_with(|| {
+        let dir = Path::new(&property.path.dir.data).join(&conf.dir);
+        match Pets::load(&dir) {
+            Ok(pets) => {
+                log::info!("Pets Enrolled: {} Photos ({})", pets.len(), dir.display());
+                pets
+            }
+            Err(e) => {
+                log::error!("Can't Enroll Pets: {}, {}", dir.display(), e);
+                Pets::default()
+            }
+        }
+    });
+    // The frame is read only when any animal may be a pet.
+    if pets.is_empty() || !detections.iter().any(|det| pets.enrolled(det.cls)) {
+        return detections.to_vec();
+    }
+    let img = match image::open(&property.path.img.last) {
+        Ok(img) => img.to_rgb8(),
+        Err(e) => {
+            log::warn!("Can't Read Frame For Pets: {}", e);
+            return detections.to_vec();
+        }
+    };
+    detections
+        .iter()
+        .filter(|det| {
+            let pet = pets.is_pet(&img, det, state.img_width, conf.similarity);
+            if pet {
+                log::debug!("Pet Ignored: {:?}", AnimalClasses::from_u32(det.cls));
+            }
+            !pet
+        })
+        .cloned()
+        .collect()
+}
+
+/// Time driven forward along the route in ms.
+fn driven_time(route: &[Maneuver]) -> u64 {
+    route
+        .iter()
+        .map(|step| match step {
+            Maneuver::Forward(ms) => *ms,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Actions for Animal Deterrent Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    Watch,
+    Lost,
+    Deter,
+    TurnLeft,
+    TurnRight,
+    Approach,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(
+    state: &RoktrackState,
+    target: &Detection,
+    at_post: bool,
+    driven: u64,
+    limit: u64,
+) -> ActPhase {
+    let x = target.xc / state.img_width as f32 - 0.5;
+    if target.h == 0 {
+        if at_post {
+            ActPhase::Watch
+        } else {
+            ActPhase::Lost
+        }
+    } else if state.img_height as f32 * CLOSE_HEIGHT_RATIO <= target.h as f32 || limit <= driven {
+        ActPhase::Deter
+    } else if x < -STEER_TOLERANCE {
+        ActPhase::TurnLeft
+    } else if STEER_TOLERANCE < x {
+        ActPhase::TurnRight
+    } else {
+        ActPhase::Approach
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn deterrent_test() {
+        let species = vec!["deer".to_string(), "Boar".to_string()];
+        assert!(is_enabled(AnimalClasses::DEER.to_u32(), &species));
+        assert!(is_enabled(AnimalClasses::BOAR.to_u32(), &species));
+        assert!(!is_enabled(AnimalClasses::BEAR.to_u32(), &species));
+        assert!(!is_enabled(99, &species));
+
+        let route = vec![
+            Maneuver::Forward(500),
+            Maneuver::Left(200),
+            Maneuver::Forward(300),
+        ];
+        assert_eq!(driven_time(&route), 800);
+
+        let state = RoktrackState::new(); // 320 x 240
+        let animal = |xc: f32, h: u32| Detection {
+            xc,
+            h,
+            ..Default::default()
+        };
+        assert_eq!(
+            assess_situation(&state, &animal(0.0, 0), true, 0, 5000),
+            ActPhase::Watch
+        );
+        assert_eq!(
+            assess_situation(&state, &animal(0.0, 0), false, 500, 5000),
+            ActPhase::Lost
+        );
+        assert_eq!(
+            assess_situation(&state, &animal(160.0, 30), true, 0, 5000),
+            ActPhase::Approach
+        );
+        assert_eq!(
+            assess_situation(&state, &animal(40.0, 30), true, 0, 5000),
+            ActPhase::TurnLeft
+        );
+        assert_eq!(
+            assess_situation(&state, &animal(160.0, 120), false, 500, 5000),
+            ActPhase::Deter
+        );
+        assert_eq!(
+            assess_situation(&state, &animal(160.0, 30), false, 5000, 5000),
+            ActPhase::Deter
+        );
+    }
+}
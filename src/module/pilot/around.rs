@@ -0,0 +1,257 @@
+//! Around Drive Pilot
+//!
+//! Goes around the markers without shrinking to the inside, counting the laps.
+//! The start marker is told apart by its number, so the OCR session is used.
+//! The first passage of the start marker starts the first lap, and each passage after that completes one.
+
+// # Normal flow of act phase
+//
+// Proceed * n
+//    |
+// ReachMarker  <- A lap is completed at the start marker.
+//    |
+// TurnKeep
+//    |
+// TurnMarkerInvisible * n
+//    |
+// TurnMarkerFound
+//    |
+// Proceed * n
+//
+// # General flow
+//
+// Start
+//   | (laps along the markers)
+// MissionComplete  <- After the configured number of laps.
+
+use std::sync::mpsc::Sender;
+
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::Roktrack,
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+use super::{
+    lost::LossMonitor,
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+
+pub struct Around {
+    passages: u8,      // Number of passages of the start marker
+    lost: LossMonitor, // Frames without the tracked marker
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Around {
+    pub fn new() -> Self {
+        Self {
+            passages: 0,
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Around {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Around {
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Around Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Sort markers based on the current phase
+        let detections = match state.phase {
+            Phase::CCW => sort::right(detections),
+            Phase::CW => sort::left(detections),
+        };
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+
+        let conf = property.conf.around.clone();
+
+        // Get the first detected marker or a default one
+        let marker = detections.first().cloned().unwrap_or_default();
+        log::debug!("Marker Selected: {:?}", marker);
+
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
+        // Turn on the work motor
+        device.inner.clone().lock().unwrap().work_motor.cw();
+
+        let action = assess_situation(state, &marker, conf.approach);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnMarkerInvisible) => base::reset_ex_height(state, device),
+            Some(ActPhase::TurnMarkerFound) => {
+                // Keep the same distance to every marker instead of shrinking the laps.
+                state.msg = ChildMsg::to_u8(ChildMsg::NewTargetFound);
+                device.inner.clone().lock().unwrap().speak("new_cone_found");
+                state.target_height = (state.img_height as f32 * conf.approach) as u16;
+                state.turn_count = 0;
+                Ok(())
+            }
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::Stand) => base::stand(state, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) => {
+                if marker.ids.contains(&conf.start_marker) {
+                    self.passages = self.passages.saturating_add(1);
+                    state.laps = laps(self.passages);
+                    log::info!("Start Marker Passed. laps: {}", state.laps);
+                }
+                if is_finished(state.laps, conf.laps) {
+                    state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                    base::mission_complete(state, device)
+                } else {
+                    base::reach_marker(state, device, marker)
+                }
+            }
+            Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
+            None => Ok(()),
+        };
+        log::debug!("End Around Handle");
+    }
+}
+
+/// Completed laps from the passages of the start marker. The first passage starts the first lap.
+fn laps(passages: u8) -> u8 {
+    passages.saturating_sub(1)
+}
+
+/// Whether the configured laps are completed. 0 laps means no limit.
+fn is_finished(laps: u8, limit: u8) -> bool {
+    0 < limit && limit <= laps
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Around Drive Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnMarkerInvisible,
+    TurnMarkerFound,
+    TurnKeep,
+    Stand,
+    StartTurn,
+    ReachMarker,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection, approach: f32) -> Option<ActPhase> {
+    if 10 <= state.turn_count {
+        Some(ActPhase::TurnCountExceeded)
+    } else if 0 < state.turn_count {
+        if marker.h == 0 {
+            Some(ActPhase::TurnMarkerInvisible)
+        } else if (marker.h as f32) < state.ex_height as f32 - state.img_height as f32 * 0.015 {
+            Some(ActPhase::TurnMarkerFound)
+        } else {
+            Some(ActPhase::TurnKeep)
+        }
+    } else if marker.h == 0 {
+        if state.turn_count == -1 {
+            Some(ActPhase::Stand)
+        } else if state.turn_count == 0 {
+            Some(ActPhase::StartTurn)
+        } else {
+            None
+        }
+    } else if state.img_height as f32 * approach <= marker.h as f32 {
+        Some(ActPhase::ReachMarker)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn laps_test() {
+        assert_eq!(laps(0), 0);
+        assert_eq!(laps(1), 0);
+        assert_eq!(laps(3), 2);
+        assert!(!is_finished(1, 2));
+        assert!(is_finished(2, 2));
+        assert!(!is_finished(100, 0));
+    }
+}
@@ -10,10 +10,10 @@ use crate::module::device::Chassis;
 use crate::module::device::Roktrack;
 use crate::module::pilot::RoktrackState;
 use crate::module::util::init::RoktrackProperty;
-use crate::module::vision::detector::Detection;
+use crate::module::vision::detector::{Detection, RoktrackClasses};
 use crate::module::vision::VisionMgmtCommand;
 
-use super::Phase;
+use super::{speed, Phase};
 
 /// Pre-processing for handle.
 pub fn pre_process(
@@ -24,6 +24,8 @@ pub fn pre_process(
     if let Ok(t) = device.inner.clone().lock().unwrap().measure_temp() {
         state.pi_temp = t
     };
+    // The caution lasts while the person stays in sight.
+    device.inner.clone().lock().unwrap().caution = None;
     Ok(())
 }
 
@@ -88,6 +90,46 @@ pub fn escape(
     Ok(())
 }
 
+/// Perform a recovery action to get out of being stuck.
+///
+/// This function reverses the Roktrack out of the place where it got stuck and rotates away from it,
+/// so that the pilot can retry. The rotation is the opposite of the phase, like the escape action.
+/// It skips the reversing and only rotates when a person is seen behind by the rear camera.
+///
+/// # Arguments
+///
+/// * `state` - A mutable reference to the RoktrackState representing the current state of the pilot.
+/// * `device` - A mutable reference to the Roktrack device.
+///
+/// # Returns
+///
+/// An `Option<()>` where `Some(())` indicates success.
+pub fn recover(
+    state: &mut RoktrackState,
+    device: &mut Roktrack,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let binding = device.inner.clone();
+    let mut device_lock = binding.lock().unwrap();
+    log::warn!("Stuck! Recovery attempt: {}", state.stuck.attempts + 1);
+    let person = RoktrackClasses::PERSON.to_u32();
+    if state.rear.iter().any(|det| det.cls == person) {
+        log::warn!("Person Behind. Recovery without reversing.");
+    } else {
+        device_lock.backward(1500);
+        thread::sleep(time::Duration::from_millis(1500));
+    }
+    match state.phase {
+        Phase::CCW => device_lock.left(1000),
+        Phase::CW => device_lock.right(1000),
+    };
+    thread::sleep(time::Duration::from_millis(1000));
+    device_lock.pause();
+    state
+        .stuck
+        .recovered(chrono::Utc::now().timestamp_millis() as u64);
+    Ok(())
+}
+
 /// Terminate the driving and set the state to off.
 ///
 /// This function stops the Roktrack, sets the state to off, and sends a message indicating
@@ -298,6 +340,7 @@ pub fn mission_complete(
 ) -> Result<(), Box<dyn std::error::Error>> {
     // Set the pilot's state to false (off)
     state.state = false;
+    state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
     // Stop the Roktrack's movement
     device.inner.clone().lock().unwrap().stop();
     log::debug!("Mission Completed!");
@@ -549,7 +592,8 @@ fn get_diff(
 ///
 /// This function calculates the difference between the target direction and the current direction of travel
 /// based on the marker's position and dimensions. It adjusts the left and right motors' output to align with
-/// the target direction. If the difference is large, it initiates a turn in the counter-direction; if it's
+/// the target direction. With the PID heading controller, it steers by the difference of the power only.
+/// Otherwise, if the difference is large, it initiates a turn in the counter-direction; if it's
 /// small, the machine moves forward. It also checks whether high-resolution processing is required and
 /// sends the corresponding command to the vision system.
 ///
@@ -569,6 +613,9 @@ pub fn proceed(
     marker: Detection,
     tx: Sender<VisionMgmtCommand>,
 ) -> Result<(), Box<dyn std::error::Error>> {
+    // Keep the target for the debug overlay
+    state.target = Some(marker.clone());
+
     // Calculate the difference between the target direction and the current direction of travel
     let diff = get_diff(
         marker.xc,
@@ -578,6 +625,41 @@ pub fn proceed(
         state.phase.clone(),
     );
 
+    // Slow down to the approach speed close to the marker.
+    device.inner.clone().lock().unwrap().approaching =
+        speed::is_approaching(marker.h, state.target_height);
+
+    // Steer by the difference of the power with the PID controller.
+    if let Some(pid) = state.heading.as_mut() {
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        let step = pid.update(diff, now);
+        log::debug!(
+            "PID adjust power and forward. left: {}, right: {}",
+            -step,
+            step
+        );
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .adjust_power(-step, step);
+        device.inner.clone().lock().unwrap().forward(0);
+    } else {
+        bang_bang(diff, device);
+    }
+
+    // Check if high-resolution processing is needed based on marker height and current image resolution
+    if marker.h as f32 > state.img_height as f32 * 0.05 && state.img_width == 640 {
+        // Send a command to downscale the resolution
+        let _ = downscale(state, tx);
+    }
+
+    Ok(())
+}
+
+/// Correct the direction of travel in steps, pivoting when the difference is big.
+fn bang_bang(diff: f32, device: &mut Roktrack) {
     // Calculate a value based on the difference for motor adjustments
     let val = (0.1 * diff).abs() as f64;
 
@@ -610,14 +692,6 @@ pub fn proceed(
         log::debug!("Forwarding");
         device.inner.clone().lock().unwrap().forward(0);
     }
-
-    // Check if high-resolution processing is needed based on marker height and current image resolution
-    if marker.h as f32 > state.img_height as f32 * 0.05 && state.img_width == 640 {
-        // Send a command to downscale the resolution
-        let _ = downscale(state, tx);
-    }
-
-    Ok(())
 }
 
 /// Determine if this marker is eligible for pass-through
@@ -0,0 +1,117 @@
+//! Battery Policy
+//!
+//! Below the warning voltage, the robot returns to the dock or stops safely, and tells the commander.
+//! A job isn't started when the charge is not enough for its estimated time plus the reserve.
+//! The charge is estimated linearly between the empty and the full voltage.
+
+use super::Modes;
+use crate::module::util::conf::{Battery as BatteryConf, Config};
+
+/// Actions below the warning voltage.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Return,
+    Stop,
+}
+
+/// Action configured below the warning voltage.
+pub fn action(conf: &BatteryConf) -> Action {
+    match conf.action.as_str() {
+        "return" => Action::Return,
+        "stop" => Action::Stop,
+        _ => {
+            log::warn!("Invalid Battery Action: {}", conf.action);
+            Action::Return
+        }
+    }
+}
+
+/// Whether the battery is below the warning voltage. 0 disables the warning.
+pub fn is_low(battery_mv: Option<u16>, warning_mv: u16) -> bool {
+    0 < warning_mv && battery_mv.is_some_and(|mv| mv < warning_mv)
+}
+
+/// Charge in % estimated from the voltage.
+pub fn charge(battery_mv: u16, conf: &BatteryConf) -> f32 {
+    let range = conf.full_mv.saturating_sub(conf.empty_mv).max(1) as f32;
+    (battery_mv.saturating_sub(conf.empty_mv) as f32 / range * 100.0).min(100.0)
+}
+
+/// Estimated minutes of the job of the mode.
+/// Steps of a mission with a time limit take their minutes, and the others the configured minutes.
+pub fn job_minutes(mode: Modes, conf: &Config) -> u64 {
+    match mode {
+        Modes::Mission => conf
+            .mission
+            .steps
+            .iter()
+            .map(|step| match step.until.as_str() {
+                "time" => step.minutes,
+                _ => conf.battery.job_minutes,
+            })
+            .sum(),
+        _ => conf.battery.job_minutes,
+    }
+}
+
+/// Charge in % required for the job, including the reserve.
+pub fn required_charge(minutes: u64, conf: &BatteryConf) -> f32 {
+    minutes as f32 / 60.0 * conf.drain_per_hour + conf.reserve
+}
+
+/// Whether the charge is enough to start the job of the mode.
+/// Without the voltage or the drain, it's always enough.
+pub fn is_sufficient(battery_mv: Option<u16>, mode: Modes, conf: &Config) -> bool {
+    match battery_mv {
+        Some(mv) if 0.0 < conf.battery.drain_per_hour => {
+            let required = required_charge(job_minutes(mode, conf), &conf.battery);
+            let charge = charge(mv, &conf.battery);
+            log::debug!("Battery Charge: {}%, Required: {}%", charge, required);
+            required <= charge
+        }
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::Path;
+
+    use super::*;
+    use crate::module::util::conf::{self, MissionStep};
+
+    #[test]
+    fn battery_test() {
+        fs::create_dir_all(Path::new("/tmp/roktracktest/")).unwrap();
+        let mut conf = conf::toml::load("/tmp/roktracktest/").unwrap();
+        conf.battery = BatteryConf::default();
+        assert!(is_low(Some(11000), 11500));
+        assert!(!is_low(Some(12000), 11500));
+        assert!(!is_low(None, 11500));
+        assert!(!is_low(Some(11000), 0));
+
+        assert_eq!(charge(12600, &conf.battery), 100.0);
+        assert_eq!(charge(11550, &conf.battery), 50.0);
+        assert_eq!(charge(10000, &conf.battery), 0.0);
+
+        // Disabled by default.
+        assert!(is_sufficient(Some(10600), Modes::Fill, &conf));
+        conf.battery.drain_per_hour = 40.0;
+        // 60 minutes take 40% and 20% is kept.
+        assert!(is_sufficient(Some(11900), Modes::Fill, &conf));
+        assert!(!is_sufficient(Some(11600), Modes::Fill, &conf));
+        assert!(is_sufficient(None, Modes::Fill, &conf));
+
+        conf.mission.steps = vec![
+            MissionStep {
+                until: "time".to_string(),
+                minutes: 30,
+                ..Default::default()
+            },
+            MissionStep::default(),
+        ];
+        assert_eq!(job_minutes(Modes::Mission, &conf), 90);
+        assert_eq!(job_minutes(Modes::Fill, &conf), 60);
+    }
+}
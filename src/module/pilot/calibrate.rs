@@ -0,0 +1,257 @@
+//! Calibration Dance Pilot
+//!
+//! Drives a scripted pattern on open, level ground to measure the biases of the IMU and the compass
+//! and the drift of the wheels. The results are saved for the other modes (see `calibration`).
+
+// # Normal flow of act phase
+//
+// Rest * n  <- Stand still and read the tilt, which is the bias of the IMU on level ground.
+//    |
+// Left (full turn) / Right (full turn)  <- A figure-eight on the spot. Read the heading while turning.
+//    |
+// Forward / Backward  <- A straight run forth and back. Read the drift of the heading.
+//    |
+// Complete  <- Save and apply the results, and report MissionComplete.
+//
+// Without the compass, only the bias of the IMU is measured. Without the IMU, only the others.
+// The previous results are cleared at the start, so that the measurements are raw.
+
+use std::sync::mpsc::Sender;
+
+use super::calibration::{self, Calibration};
+use super::maneuver::{self, Maneuver};
+use super::{
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg, device::motor::Motor, device::Roktrack, pilot::base, pilot::RoktrackState,
+    util::init::RoktrackProperty, vision::detector::Detection, vision::VisionMgmtCommand,
+};
+
+/// Steps of the dance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Step {
+    Rest,
+    Left,
+    Right,
+    Forward,
+    Backward,
+    Complete,
+}
+
+impl Step {
+    fn next(&self) -> Step {
+        match self {
+            Step::Rest => Step::Left,
+            Step::Left => Step::Right,
+            Step::Right => Step::Forward,
+            Step::Forward => Step::Backward,
+            Step::Backward | Step::Complete => Step::Complete,
+        }
+    }
+}
+
+pub struct Calibrate {
+    step: Option<Step>,            // Current step (None: not started)
+    started: bool,                 // Whether the move of the step has been commanded
+    tilts: Vec<(f32, f32)>,        // Tilt at rest
+    headings: Vec<(u64, f32)>,     // Time in ms and heading of the current move
+    rotations: Vec<f32>,           // Degrees of the full turns
+    deviation: Option<(f32, f32)>, // Deviation of the compass from the left turn
+    drift: Option<f32>,            // Drift of the heading in degrees per second
+    risks: RiskEngine,             // System risks to check before driving
+}
+
+impl Calibrate {
+    pub fn new() -> Self {
+        Self {
+            step: None,
+            started: false,
+            tilts: vec![],
+            headings: vec![],
+            rotations: vec![],
+            deviation: None,
+            drift: None,
+            risks: risk::builtin().without(risk::SystemRisk::Stuck),
+        }
+    }
+}
+
+impl Default for Calibrate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Calibrate {
+    /// Function called from a thread to handle the Calibration Dance Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        _detections: &mut [Detection],
+        _tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Calibrate Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // No mowing while calibrating
+        device.inner.clone().lock().unwrap().work_motor.stop();
+        let conf = &property.conf.calibrate;
+
+        // Measure without the previous results.
+        let step = match self.step {
+            Some(step) => step,
+            None => {
+                log::info!("Start Calibration.");
+                Calibration::default().apply(device, &property.conf);
+                state.calibration = Calibration::default();
+                self.step = Some(Step::Rest);
+                Step::Rest
+            }
+        };
+
+        let now = chrono::Utc::now().timestamp_millis() as u64;
+        match step {
+            Step::Rest => {
+                device.inner.clone().lock().unwrap().pause();
+                if let Some(tilt) = device.inner.clone().lock().unwrap().imu.tilt() {
+                    self.tilts.push(tilt);
+                }
+                if conf.rest_frames as usize <= self.tilts.len() || self.tilts.is_empty() {
+                    self.step = Some(step.next());
+                }
+            }
+            Step::Left | Step::Right | Step::Forward | Step::Backward => {
+                if let Some(heading) = state.telemetry.heading {
+                    self.headings.push((now, heading));
+                }
+                if !self.started {
+                    let full_turn = property.conf.motion.quarter_turn_ms * 4;
+                    maneuver::start(
+                        device,
+                        match step {
+                            Step::Left => Maneuver::Left(full_turn),
+                            Step::Right => Maneuver::Right(full_turn),
+                            Step::Forward => Maneuver::Forward(conf.straight_ms),
+                            _ => Maneuver::Backward(conf.straight_ms),
+                        },
+                    );
+                    self.started = true;
+                } else if maneuver::is_idle(device) {
+                    device.inner.clone().lock().unwrap().pause();
+                    self.measure(step);
+                    self.headings.clear();
+                    self.started = false;
+                    self.step = Some(step.next());
+                }
+            }
+            Step::Complete => self.complete(state, device, property),
+        }
+        log::debug!("End Calibrate Handle");
+    }
+
+    /// Start over next time, and drive with the previous results again.
+    fn on_abort(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: &RoktrackProperty,
+    ) {
+        log::info!("Calibration Aborted. Restore the previous results.");
+        *self = Self::new();
+        let previous =
+            Calibration::load(&calibration::path(&property.path.dir.data)).unwrap_or_default();
+        previous.apply(device, &property.conf);
+        state.calibration = previous;
+    }
+}
+
+impl Calibrate {
+    /// Takes the measurement of the move just finished.
+    fn measure(&mut self, step: Step) {
+        let headings: Vec<f32> = self.headings.iter().map(|(_, h)| *h).collect();
+        match step {
+            Step::Left | Step::Right => {
+                self.rotations.push(calibration::rotation(&headings));
+                self.deviation
+                    .get_or_insert(calibration::deviation(&self.headings));
+            }
+            Step::Forward => {
+                if let (Some(first), Some(last)) = (self.headings.first(), self.headings.last()) {
+                    if first.0 < last.0 {
+                        let seconds = (last.0 - first.0) as f32 / 1000.0;
+                        self.drift = Some(calibration::rotation(&headings) / seconds);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Saves and applies the results, and finishes the mission.
+    fn complete(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: RoktrackProperty,
+    ) {
+        let result = Calibration {
+            imu_bias: calibration::imu_bias(&self.tilts),
+            deviation: self.deviation.unwrap_or_default(),
+            turn_ratio: calibration::turn_ratio(360.0, &self.rotations),
+            balance: calibration::balance(self.drift.unwrap_or_default()),
+            calibrated_at: chrono::Utc::now().timestamp_millis(),
+        };
+        log::info!("Calibration Complete: {:?}", result);
+        if let Err(e) = result.save(&calibration::path(&property.path.dir.data)) {
+            log::error!("Can't Save Calibration: {}", e);
+        }
+        result.apply(device, &property.conf);
+        state.calibration = result;
+        state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+        let _ = base::mission_complete(state, device);
+        *self = Self::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn step_test() {
+        let mut step = Step::Rest;
+        let mut steps = vec![step];
+        while step != Step::Complete {
+            step = step.next();
+            steps.push(step);
+        }
+        assert_eq!(
+            steps,
+            vec![
+                Step::Rest,
+                Step::Left,
+                Step::Right,
+                Step::Forward,
+                Step::Backward,
+                Step::Complete
+            ]
+        );
+
+        let mut calibrate = Calibrate::new();
+        calibrate.headings = (0..=36).map(|i| (i * 100, (i * 10 % 360) as f32)).collect();
+        calibrate.measure(Step::Left);
+        calibrate.headings = vec![(0, 0.0), (2000, 10.0)];
+        calibrate.measure(Step::Forward);
+        assert_eq!(calibrate.rotations, vec![360.0]);
+        assert_eq!(calibrate.drift, Some(5.0));
+    }
+}
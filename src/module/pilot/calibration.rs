@@ -0,0 +1,232 @@
+//! Calibration Results
+//!
+//! The calibration mode measures the biases of the sensors and the drift of the wheels, and saves
+//! them to the data directory. They are applied to the device at boot, so that every mode drives
+//! with them.
+//!
+//! | result     | measured by                                        | applied to                    |
+//! |------------|----------------------------------------------------|-------------------------------|
+//! | imu_bias   | The tilt standing still on level ground            | The tilt read from the IMU    |
+//! | deviation  | The wobble of the heading turning at a steady rate | The heading of the compass    |
+//! | turn_ratio | The rotation of a commanded full turn              | The duration of the turns     |
+//! | balance    | The drift of the heading driving straight          | The power of the drive motors |
+
+use std::f32::consts::PI;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::module::{define, device::Roktrack, util::conf::Config};
+
+// Power shifted between the wheels per degree per second of the drift.
+const BALANCE_PER_DEG_S: f32 = 0.02;
+// Largest power shifted between the wheels.
+const MAX_BALANCE: f32 = 0.2;
+
+/// Results of the calibration.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Calibration {
+    pub imu_bias: (f32, f32), // Pitch and roll read at rest on level ground in degrees
+    pub deviation: (f32, f32), // Semicircular deviation of the compass (sin, cos) in degrees
+    pub turn_ratio: f32,      // Commanded rotation / measured rotation
+    pub balance: f32,         // Power shifted from the left wheel to the right one (ratio)
+    pub calibrated_at: i64,   // Time of the calibration in ms
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Self {
+            imu_bias: (0.0, 0.0),
+            deviation: (0.0, 0.0),
+            turn_ratio: 1.0,
+            balance: 0.0,
+            calibrated_at: 0,
+        }
+    }
+}
+
+impl Calibration {
+    /// Applies the results to the device, on top of the configured powers.
+    pub fn apply(&self, device: &mut Roktrack, conf: &Config) {
+        let binding = device.inner.clone();
+        let mut device_lock = binding.lock().unwrap();
+        device_lock.imu.bias = self.imu_bias;
+        device_lock.turn_ratio = self.turn_ratio;
+        device_lock.drive_motor_left.power = conf.pwm.pwm_power_left * (1.0 - self.balance) as f64;
+        device_lock.drive_motor_right.power =
+            conf.pwm.pwm_power_right * (1.0 + self.balance) as f64;
+    }
+
+    /// Corrects the heading of the compass in degrees.
+    pub fn correct_heading(&self, heading: f32) -> f32 {
+        let rad = heading.to_radians();
+        (heading - self.deviation.0 * rad.sin() - self.deviation.1 * rad.cos()).rem_euclid(360.0)
+    }
+
+    /// Loads the saved results. None if there are none.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        match toml::from_str(&content) {
+            Ok(calibration) => Some(calibration),
+            Err(e) => {
+                log::warn!("Invalid Calibration: {}, {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Saves the results.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        // Write to a temporary file first, so that a crash doesn't leave a broken one.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Path of the calibration file in the data directory.
+pub fn path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join(define::path::CALIBRATION_FILE)
+}
+
+/// Mean pitch and roll of the samples taken at rest.
+pub fn imu_bias(samples: &[(f32, f32)]) -> (f32, f32) {
+    if samples.is_empty() {
+        return (0.0, 0.0);
+    }
+    let n = samples.len() as f32;
+    let (pitch, roll) = samples
+        .iter()
+        .fold((0.0, 0.0), |(p, r), (pitch, roll)| (p + pitch, r + roll));
+    (pitch / n, roll / n)
+}
+
+/// Makes the headings continuous across north, e.g. 350, 10 -> 350, 370.
+pub fn unwrap(headings: &[f32]) -> Vec<f32> {
+    let mut offset = 0.0;
+    let mut unwrapped: Vec<f32> = Vec::with_capacity(headings.len());
+    for (i, heading) in headings.iter().enumerate() {
+        if 0 < i {
+            let diff = heading - headings[i - 1];
+            if 180.0 < diff {
+                offset -= 360.0;
+            } else if diff < -180.0 {
+                offset += 360.0;
+            }
+        }
+        unwrapped.push(heading + offset);
+    }
+    unwrapped
+}
+
+/// Degrees turned over the headings.
+pub fn rotation(headings: &[f32]) -> f32 {
+    let unwrapped = unwrap(headings);
+    match (unwrapped.first(), unwrapped.last()) {
+        (Some(first), Some(last)) => last - first,
+        _ => 0.0,
+    }
+}
+
+/// Commanded / measured rotation, averaged over the turns. 1.0 if nothing was measured.
+pub fn turn_ratio(commanded: f32, measured: &[f32]) -> f32 {
+    let ratios: Vec<f32> = measured
+        .iter()
+        .filter(|m| 1.0 < m.abs())
+        .map(|m| commanded / m.abs())
+        .collect();
+    match ratios.is_empty() {
+        true => 1.0,
+        false => ratios.iter().sum::<f32>() / ratios.len() as f32,
+    }
+}
+
+/// Semicircular deviation (sin, cos) of the compass from the samples (time in ms, heading) of a
+/// full turn at a steady rate. The heading is expected to change linearly from the first sample
+/// to the last, and the first harmonic of what is left over is the deviation caused by the
+/// magnetism of the body.
+pub fn deviation(samples: &[(u64, f32)]) -> (f32, f32) {
+    let (Some(first), Some(last)) = (samples.first(), samples.last()) else {
+        return (0.0, 0.0);
+    };
+    if samples.len() < 3 || last.0 <= first.0 {
+        return (0.0, 0.0);
+    }
+    let headings: Vec<f32> = samples.iter().map(|(_, h)| *h).collect();
+    let unwrapped = unwrap(&headings);
+    let rate = (unwrapped[unwrapped.len() - 1] - unwrapped[0]) / (last.0 - first.0) as f32;
+    let residuals: Vec<f32> = samples
+        .iter()
+        .zip(&unwrapped)
+        .map(|((t, _), h)| h - unwrapped[0] - rate * (t - first.0) as f32)
+        .collect();
+    let n = residuals.len() as f32;
+    let mean = residuals.iter().sum::<f32>() / n;
+    let (mut s, mut c) = (0.0, 0.0);
+    for (residual, heading) in residuals.iter().zip(&headings) {
+        let rad = heading * PI / 180.0;
+        s += (residual - mean) * rad.sin();
+        c += (residual - mean) * rad.cos();
+    }
+    (2.0 * s / n, 2.0 * c / n)
+}
+
+/// Power shifted from the left wheel to the right one to cancel the drift in degrees per second.
+/// Drifting clockwise, the left wheel is the stronger.
+pub fn balance(drift: f32) -> f32 {
+    (drift * BALANCE_PER_DEG_S).clamp(-MAX_BALANCE, MAX_BALANCE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn calibration_test() {
+        assert_eq!(imu_bias(&[]), (0.0, 0.0));
+        assert_eq!(imu_bias(&[(1.0, -2.0), (3.0, 0.0)]), (2.0, -1.0));
+
+        assert_eq!(unwrap(&[350.0, 10.0, 30.0]), vec![350.0, 370.0, 390.0]);
+        assert_eq!(rotation(&[10.0, 350.0, 300.0]), -70.0);
+        assert!((turn_ratio(360.0, &[400.0, -320.0]) - 1.0125).abs() < 1e-6);
+        assert_eq!(turn_ratio(360.0, &[]), 1.0);
+
+        // A steady turn reads no deviation.
+        let steady: Vec<(u64, f32)> = (0..36).map(|i| (i * 100, (i * 10) as f32)).collect();
+        let (s, c) = deviation(&steady);
+        assert!(s.abs() < 0.01 && c.abs() < 0.01);
+        // The heading reads 5 degrees more facing east.
+        let deviated: Vec<(u64, f32)> = (0..36)
+            .map(|i| {
+                let h = (i * 10) as f32;
+                (i * 100, h + 5.0 * h.to_radians().sin())
+            })
+            .collect();
+        let (s, c) = deviation(&deviated);
+        assert!((s - 5.0).abs() < 0.5 && c.abs() < 0.5);
+        let calibration = Calibration {
+            deviation: (s, c),
+            ..Default::default()
+        };
+        assert!((calibration.correct_heading(95.0) - 90.0).abs() < 0.5);
+
+        assert!((balance(5.0) - 0.1).abs() < 1e-6);
+        assert_eq!(balance(-50.0), -MAX_BALANCE);
+    }
+
+    #[test]
+    fn persistence_test() {
+        let dir = "/tmp/roktracktest/calibration/";
+        fs::create_dir_all(dir).unwrap();
+        let calibration = Calibration {
+            imu_bias: (1.5, -0.5),
+            turn_ratio: 1.1,
+            ..Default::default()
+        };
+        calibration.save(&path(dir)).unwrap();
+        assert_eq!(Calibration::load(&path(dir)), Some(calibration));
+    }
+}
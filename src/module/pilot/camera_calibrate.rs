@@ -0,0 +1,173 @@
+//! Camera Calibration Pilot
+//!
+//! Stands still while a checkerboard is shown to the camera, and computes the intrinsics of the
+//! camera from the frames where it's found (see `vision::intrinsics`). The vision undistorts the
+//! detections with them from then on.
+
+// # Normal flow of act phase
+//
+// Capture * n  <- Stand still and look for the checkerboard in the last frame, once per interval.
+//    |            Keep the frame where it's found whole, and beep to have the board moved.
+// Complete  <- Compute and save the intrinsics, have the vision load them, and report MissionComplete.
+//
+// If the intrinsics can't be computed, e.g. the board wasn't tilted enough, the captures start over.
+
+use std::fs;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use super::{
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    define,
+    device::motor::Motor,
+    device::Roktrack,
+    pilot::base,
+    pilot::RoktrackState,
+    util::init::RoktrackProperty,
+    vision::{checkerboard, detector::Detection, intrinsics, VisionMgmtCommand},
+};
+
+pub struct CameraCalibrate {
+    views: Vec<Vec<(f32, f32)>>, // Corners of the checkerboard in the frames kept
+    size: (u32, u32),            // Size of the frames in px
+    last: Option<Instant>,       // Time of the last look for the checkerboard
+    risks: RiskEngine,           // System risks to check before driving
+}
+
+impl CameraCalibrate {
+    pub fn new() -> Self {
+        Self {
+            views: vec![],
+            size: (0, 0),
+            last: None,
+            risks: risk::builtin().without(risk::SystemRisk::Stuck),
+        }
+    }
+}
+
+impl Default for CameraCalibrate {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for CameraCalibrate {
+    /// Function called from a thread to handle the Camera Calibration Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        _detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start CameraCalibrate Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Stand still
+        device.inner.clone().lock().unwrap().pause();
+        device.inner.clone().lock().unwrap().work_motor.stop();
+
+        // Give time to move the board.
+        let conf = &property.conf.camera_calibrate;
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < Duration::from_millis(conf.interval_ms))
+        {
+            return;
+        }
+        self.last = Some(Instant::now());
+
+        let img = match image::open(&property.path.img.last) {
+            Ok(img) => img.to_luma8(),
+            Err(e) => {
+                log::debug!("Frame Unavailable: {}", e);
+                return;
+            }
+        };
+        let (cols, rows) = (conf.cols as usize, conf.rows as usize);
+        let Some(corners) = checkerboard::find_corners(&img, cols, rows) else {
+            log::debug!("Checkerboard Not Found.");
+            return;
+        };
+        self.size = img.dimensions();
+        self.views.push(corners);
+        self.keep(&img, &property.path.dir.data);
+        log::info!("Checkerboard Captured: {}/{}", self.views.len(), conf.views);
+        device.inner.clone().lock().unwrap().speak("button_sound");
+
+        if conf.views as usize <= self.views.len() {
+            self.complete(state, device, tx, &property);
+        }
+        log::debug!("End CameraCalibrate Handle");
+    }
+
+    /// Start over next time.
+    fn on_abort(
+        &mut self,
+        _state: &mut RoktrackState,
+        _device: &mut Roktrack,
+        _property: &RoktrackProperty,
+    ) {
+        log::info!("Camera Calibration Aborted.");
+        *self = Self::new();
+    }
+}
+
+impl CameraCalibrate {
+    /// Keeps the frame of the checkerboard in the data directory, to look into the calibration.
+    fn keep(&self, img: &image::GrayImage, data_dir: &str) {
+        let dir = Path::new(data_dir).join(define::path::CAMERA_CALIBRATION_DIR);
+        let path = dir.join(format!("view_{:02}.jpg", self.views.len()));
+        let kept = fs::create_dir_all(&dir)
+            .map_err(image::ImageError::from)
+            .and_then(|_| img.save(&path));
+        if let Err(e) = kept {
+            log::warn!("Can't Keep Checkerboard Frame: {}, {}", path.display(), e);
+        }
+    }
+
+    /// Computes and saves the intrinsics, and finishes the mission. Starts over if they can't be
+    /// computed.
+    fn complete(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        tx: Sender<VisionMgmtCommand>,
+        property: &RoktrackProperty,
+    ) {
+        let conf = &property.conf.camera_calibrate;
+        let (width, height) = self.size;
+        let result = intrinsics::calibrate(
+            &self.views,
+            conf.cols as usize,
+            conf.rows as usize,
+            width,
+            height,
+        );
+        match result {
+            Ok(result) => {
+                log::info!("Camera Calibration Complete: {:?}", result);
+                if let Err(e) = result.save(&intrinsics::path(&property.path.dir.data)) {
+                    log::error!("Can't Save Camera Calibration: {}", e);
+                }
+                tx.send(VisionMgmtCommand::LoadIntrinsics).unwrap();
+                let _ = base::mission_complete(state, device);
+                *self = Self::new();
+            }
+            Err(e) => {
+                log::warn!("Camera Calibration Failed: {}. Start Over.", e);
+                self.views.clear();
+            }
+        }
+    }
+}
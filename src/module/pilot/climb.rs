@@ -0,0 +1,301 @@
+//! Climb Pilot
+//!
+//! Climbs the slope toward the marker at the top (or the bottom).
+//! The pitch and the roll are read from the IMU, the motor power is adapted to the slope,
+//! and the climb is aborted when the slope is steeper than configured.
+//! Without an IMU, the climb relies on vision only.
+
+// # Normal flow of act phase
+//
+// StartTurn  <- The marker is not visible. Turn on the spot to search for it.
+//    |
+// TurnKeep * n
+//    |
+// Proceed * n  <- Climb toward the marker with the power adapted to the slope.
+//    |
+// Summit  <- The marker is close enough. Stop and report MissionComplete.
+//
+// InclineExceeded  <- Too steep. Stop and report InclineExceeded.
+// TurnCountExceeded  <- The process is stopped because it was not found after the specified number of turns.
+
+use std::sync::mpsc::Sender;
+
+use super::{
+    lost::LossMonitor,
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::Roktrack,
+    pilot::base,
+    pilot::RoktrackState,
+    util::{common::send_line_notify_with_image, conf::Climb as ClimbConf, init::RoktrackProperty},
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Height of the marker in the image (ratio) at which the climb is finished.
+const SUMMIT_HEIGHT_RATIO: f32 = 0.8;
+// Range of the motor power adapted to the slope. Below 0.4, the motors make an unusual noise.
+const MIN_POWER: f64 = 0.4;
+const MAX_POWER: f64 = 1.0;
+
+pub struct Climb {
+    slope: Slope,      // Last reported slope
+    lost: LossMonitor, // Frames without the tracked marker
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Climb {
+    pub fn new() -> Self {
+        Self {
+            slope: Slope::Flat,
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Climb {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Climb {
+    /// Function called from a thread to handle the Climb Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Climb Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Measure the slope
+        let conf = &property.conf.climb;
+        let tilt = device.inner.clone().lock().unwrap().imu.tilt();
+        log::debug!("Tilt (pitch, roll): {:?}", tilt);
+
+        // The biggest marker is the closest one, which is taken as the goal.
+        let detections = sort::big(detections);
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+        let marker = detections.first().cloned().unwrap_or_default();
+        log::debug!("Marker Selected: {:?}", marker);
+
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
+        // Turn on the work motor
+        device.inner.clone().lock().unwrap().work_motor.cw();
+
+        let action = assess_situation(state, &marker, tilt, conf);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::InclineExceeded) => {
+                let (pitch, roll) = tilt.unwrap_or_default();
+                log::warn!("Incline Exceeded! pitch: {}, roll: {}", pitch, roll);
+                state.state = false;
+                state.msg = ChildMsg::to_u8(ChildMsg::InclineExceeded);
+                device.inner.clone().lock().unwrap().stop();
+                tx.send(VisionMgmtCommand::Off).unwrap();
+                let msg = format!(
+                    "Climb aborted. Too steep. pitch: {:.1}, roll: {:.1}",
+                    pitch, roll
+                );
+                let _ = send_line_notify_with_image(&msg, &property.path.img.last, property.conf);
+                Ok(())
+            }
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::Summit) => {
+                log::info!("Summit Reached.");
+                device.inner.clone().lock().unwrap().speak("reach_top");
+                state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                base::mission_complete(state, device)
+            }
+            Some(ActPhase::Proceed) => {
+                // Stop searching once the marker is in sight.
+                state.turn_count = 0;
+                if let Some((pitch, _)) = tilt {
+                    let slope = Slope::from_pitch(pitch, conf.slope);
+                    if slope != self.slope {
+                        log::info!("Slope Changed: {:?}", slope);
+                        match slope {
+                            Slope::Up => state.msg = ChildMsg::to_u8(ChildMsg::ClimbUp),
+                            Slope::Down => state.msg = ChildMsg::to_u8(ChildMsg::ClimbDown),
+                            Slope::Flat => {}
+                        }
+                        self.slope = slope;
+                    }
+                    let mut device_lock = device.inner.lock().unwrap();
+                    device_lock.drive_motor_left.power =
+                        power(property.conf.pwm.pwm_power_left, pitch, conf);
+                    device_lock.drive_motor_right.power =
+                        power(property.conf.pwm.pwm_power_right, pitch, conf);
+                }
+                base::proceed(state, device, marker, tx)
+            }
+            None => Ok(()),
+        };
+        log::debug!("End Climb Handle");
+    }
+}
+
+/// Slopes
+///
+#[derive(Debug, Clone, PartialEq)]
+enum Slope {
+    Flat,
+    Up,
+    Down,
+}
+
+impl Slope {
+    /// Slope from the pitch in degrees. Within the threshold it's flat.
+    fn from_pitch(pitch: f32, threshold: f32) -> Self {
+        if threshold < pitch {
+            Slope::Up
+        } else if pitch < -threshold {
+            Slope::Down
+        } else {
+            Slope::Flat
+        }
+    }
+}
+
+/// Motor power adapted to the pitch. More power going up, less going down.
+fn power(base: f64, pitch: f32, conf: &ClimbConf) -> f64 {
+    (base * (1.0 + conf.power_gain * pitch as f64)).clamp(MIN_POWER, MAX_POWER)
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Climb Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    InclineExceeded,
+    TurnCountExceeded,
+    TurnKeep,
+    StartTurn,
+    Summit,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(
+    state: &RoktrackState,
+    marker: &Detection,
+    tilt: Option<(f32, f32)>,
+    conf: &ClimbConf,
+) -> Option<ActPhase> {
+    if tilt
+        .is_some_and(|(pitch, roll)| conf.max_incline < pitch.abs() || conf.max_roll < roll.abs())
+    {
+        Some(ActPhase::InclineExceeded)
+    } else if marker.h == 0 {
+        if 10 <= state.turn_count {
+            Some(ActPhase::TurnCountExceeded)
+        } else if 0 < state.turn_count {
+            Some(ActPhase::TurnKeep)
+        } else {
+            Some(ActPhase::StartTurn)
+        }
+    } else if state.img_height as f32 * SUMMIT_HEIGHT_RATIO <= marker.h as f32 {
+        Some(ActPhase::Summit)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn slope_test() {
+        let conf = ClimbConf::default();
+        assert_eq!(Slope::from_pitch(10.0, conf.slope), Slope::Up);
+        assert_eq!(Slope::from_pitch(-10.0, conf.slope), Slope::Down);
+        assert_eq!(Slope::from_pitch(3.0, conf.slope), Slope::Flat);
+        // 10 degrees up adds 20%, down takes 20%, within the range of the power.
+        assert!((power(0.7, 10.0, &conf) - 0.84).abs() < 1e-6);
+        assert!((power(0.7, -10.0, &conf) - 0.56).abs() < 1e-6);
+        assert_eq!(power(0.9, 20.0, &conf), MAX_POWER);
+        assert_eq!(power(0.5, -20.0, &conf), MIN_POWER);
+
+        let state = RoktrackState::new();
+        let marker = Detection::default();
+        assert_eq!(
+            assess_situation(&state, &marker, Some((30.0, 0.0)), &conf),
+            Some(ActPhase::InclineExceeded)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker, Some((0.0, -20.0)), &conf),
+            Some(ActPhase::InclineExceeded)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker, None, &conf),
+            Some(ActPhase::StartTurn)
+        );
+    }
+}
@@ -0,0 +1,119 @@
+//! Dataset Capture Pilot
+//!
+//! Stands still while the objects are shown to the camera, and keeps the frames with the current
+//! detections as their labels (see `vision::dataset`), to train the pylon or the animal models
+//! with the data of the field. The missed or wrong detections are corrected in the review.
+
+// # Normal flow of act phase
+//
+// Capture * n  <- Stand still and keep the last frame with the detections, once per interval.
+//    |            To review first, or in the dataset right away.
+// Complete  <- Report MissionComplete.
+
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+
+use super::{
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    device::motor::Motor,
+    device::Roktrack,
+    pilot::base,
+    pilot::RoktrackState,
+    util::init::RoktrackProperty,
+    vision::{dataset, detector::Detection, VisionMgmtCommand},
+};
+
+pub struct DatasetCapture {
+    frames: u16,           // Frames kept
+    last: Option<Instant>, // Time of the last frame kept
+    risks: RiskEngine,     // System risks to check before driving
+}
+
+impl DatasetCapture {
+    pub fn new() -> Self {
+        Self {
+            frames: 0,
+            last: None,
+            risks: risk::builtin().without(risk::SystemRisk::Stuck),
+        }
+    }
+}
+
+impl Default for DatasetCapture {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for DatasetCapture {
+    /// Function called from a thread to handle the Dataset Capture Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        _tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start DatasetCapture Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Stand still
+        device.inner.clone().lock().unwrap().pause();
+        device.inner.clone().lock().unwrap().work_motor.stop();
+
+        // Give time to move the objects.
+        let conf = &property.conf.dataset_capture;
+        if self
+            .last
+            .is_some_and(|last| last.elapsed() < Duration::from_millis(conf.interval_ms))
+        {
+            return;
+        }
+        self.last = Some(Instant::now());
+
+        // The detections are of the square input of the model, as wide as the frame.
+        let labels = dataset::labels(detections, state.img_width);
+        let name = Local::now().format("%Y%m%d_%H%M%S_%3f").to_string();
+        if let Err(e) = dataset::save(
+            &property.path.dir.data,
+            &property.path.img.last,
+            &labels,
+            &name,
+            conf.review,
+        ) {
+            log::warn!("Can't Keep Dataset Frame: {}", e);
+            return;
+        }
+        self.frames += 1;
+        log::info!("Dataset Frame Captured: {}/{}", self.frames, conf.frames);
+
+        if conf.frames <= self.frames {
+            log::info!("Dataset Capture Complete.");
+            let _ = base::mission_complete(state, device);
+            *self = Self::new();
+        }
+        log::debug!("End DatasetCapture Handle");
+    }
+
+    /// Start over next time.
+    fn on_abort(
+        &mut self,
+        _state: &mut RoktrackState,
+        _device: &mut Roktrack,
+        _property: &RoktrackProperty,
+    ) {
+        log::info!("Dataset Capture Aborted.");
+        *self = Self::new();
+    }
+}
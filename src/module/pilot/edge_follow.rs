@@ -0,0 +1,215 @@
+//! Edge Following Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// Proceed * n  <- Keep the grass boundary at the configured position in the image.
+//    |
+// SteerLeft / SteerRight  <- The boundary has drifted.
+//    |
+// Proceed * n
+//
+// The boundary comes from the grass segmentation as detections of `segment::EDGE_CLASS`.
+// Only the near half of the image is used, since the far boundary is too coarse to steer by.
+// When the boundary is lost, the robot pauses and halts after a while, e.g. at the end of a driveway.
+
+use std::sync::mpsc::Sender;
+
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::RoktrackState,
+    util::init::RoktrackProperty,
+    vision::detector::{segment, Detection, FilterClass, RoktrackClasses, SessionType},
+    vision::VisionMgmtCommand,
+};
+
+// Tolerated difference (ratio to the image width) before correcting the direction.
+const STEER_TOLERANCE: f32 = 0.05;
+
+// Duration of a correcting turn in ms.
+const TURN_STEP_MS: u64 = 100;
+
+// Number of frames without the boundary before halting.
+const LOST_LIMIT: u8 = 10;
+
+pub struct EdgeFollow {
+    lost: u8,          // Frames without the boundary
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl EdgeFollow {
+    pub fn new() -> Self {
+        Self {
+            lost: 0,
+            // Half of the view is off the grass along the boundary.
+            risks: risk::builtin().without(risk::SystemRisk::OffGrass),
+        }
+    }
+}
+
+impl Default for EdgeFollow {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for EdgeFollow {
+    /// Function called from a thread to handle the Edge Following Drive Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start EdgeFollow Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        let edges = RoktrackClasses::filter(detections, segment::EDGE_CLASS);
+        let edge = near_edge(&edges);
+        log::debug!("Edge Position: {:?}", edge);
+
+        let action = assess_situation(edge, property.conf.edge.position, self.lost);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        match action {
+            ActPhase::LostCountExceeded => {
+                self.lost = 0;
+                let _ = base::halt(state, device, tx);
+            }
+            ActPhase::Lost => {
+                self.lost += 1;
+                device.inner.clone().lock().unwrap().pause();
+            }
+            ActPhase::SteerLeft | ActPhase::SteerRight | ActPhase::Proceed => {
+                self.lost = 0;
+                let binding = device.inner.clone();
+                let mut device_lock = binding.lock().unwrap();
+                device_lock.work_motor.cw();
+                match action {
+                    ActPhase::SteerLeft => device_lock.left(TURN_STEP_MS),
+                    ActPhase::SteerRight => device_lock.right(TURN_STEP_MS),
+                    _ => device_lock.forward(0),
+                }
+            }
+        }
+        log::debug!("End EdgeFollow Handle");
+    }
+}
+
+/// Mean horizontal position (ratio to the image width) of the boundary in the near half of the image.
+pub fn near_edge(edges: &[Detection]) -> Option<f32> {
+    let sz = SessionType::Seg.get_imgsz() as f32;
+    let near: Vec<f32> = edges
+        .iter()
+        .filter(|e| sz / 2.0 <= e.yc)
+        .map(|e| e.xc / sz)
+        .collect();
+    if near.is_empty() {
+        None
+    } else {
+        Some(near.iter().sum::<f32>() / near.len() as f32)
+    }
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Edge Following Drive Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    LostCountExceeded,
+    Lost,
+    SteerLeft,
+    SteerRight,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+///
+/// Turning right moves the boundary to the left in the image, whichever side the grass is on.
+fn assess_situation(edge: Option<f32>, position: f32, lost: u8) -> ActPhase {
+    match edge {
+        None if LOST_LIMIT <= lost + 1 => ActPhase::LostCountExceeded,
+        None => ActPhase::Lost,
+        Some(x) if position + STEER_TOLERANCE < x => ActPhase::SteerRight,
+        Some(x) if x < position - STEER_TOLERANCE => ActPhase::SteerLeft,
+        Some(_) => ActPhase::Proceed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steer_test() {
+        let edge = |xc: f32, yc: f32| Detection {
+            xc,
+            yc,
+            cls: segment::EDGE_CLASS,
+            ..Default::default()
+        };
+        // The far boundary is ignored
+        assert_eq!(near_edge(&[edge(0.0, 20.0)]), None);
+        let x = near_edge(&[edge(0.0, 20.0), edge(160.0, 180.0), edge(224.0, 300.0)]);
+        assert!((x.unwrap() - 0.6).abs() < 1e-6);
+
+        assert_eq!(assess_situation(Some(0.52), 0.5, 0), ActPhase::Proceed);
+        assert_eq!(assess_situation(Some(0.7), 0.5, 0), ActPhase::SteerRight);
+        assert_eq!(assess_situation(Some(0.3), 0.5, 0), ActPhase::SteerLeft);
+        assert_eq!(assess_situation(None, 0.5, 0), ActPhase::Lost);
+        assert_eq!(
+            assess_situation(None, 0.5, LOST_LIMIT - 1),
+            ActPhase::LostCountExceeded
+        );
+    }
+}
@@ -30,27 +30,65 @@
 // InvertPhase
 //   | (CW laps)
 // MissionComplete
+//
+// # Obstacle avoidance
+//
+// Obstacle ahead (seen by the camera or measured by the ultrasonic sensor)
+//   |
+// Turn away -> Forward (clearance) -> Turn back -> Forward (pass) -> Turn back
+//   -> Forward (clearance) -> Turn away  <- Back on the lane, heading the same way as before.
+//
+// # Lane keeping (triangulation enabled)
+//
+// Fix the position with the target and another pylon in sight at the start of the pass
+//   |
+// Proceed * n  <- Aim off the target to steer back to the line from the fix to the target.
 
 use std::sync::mpsc::Sender;
 
 use crate::module::{
     device::motor::Motor,
-    device::Roktrack,
+    device::{Chassis, Roktrack},
     pilot::base,
     pilot::{Phase, RoktrackState},
+    util::conf::{Avoidance, Motion},
     util::init::RoktrackProperty,
-    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
     vision::VisionMgmtCommand,
+    vision::{
+        detector::{sort, Category, Detection, FilterClass, RoktrackClasses},
+        tracker,
+    },
 };
 
-use super::{base::select_marker, PilotHandler};
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    base::select_marker,
+    lost::LossMonitor,
+    person,
+    risk::{self, RiskEngine},
+    triangulation::Lane,
+    PilotHandler,
+};
+
+// Horizontal band of the image (ratio) regarded as the lane ahead.
+const LANE_BAND: (f32, f32) = (0.3, 0.7);
 
-#[derive(Clone, Copy)]
-pub struct Fill {}
+#[derive(Clone)]
+pub struct Fill {
+    detour: Option<ManeuverPlan>, // Moves around an obstacle
+    lane: Lane,                   // Lane of the current pass fixed with the pylons
+    lost: LossMonitor,            // Frames without the tracked marker
+    risks: RiskEngine,            // System risks to check before driving
+}
 
 impl Fill {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            detour: None,
+            lane: Lane::default(),
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
+        }
     }
 }
 
@@ -71,11 +109,7 @@ impl PilotHandler for Fill {
     ) {
         log::debug!("Start Fill Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) | Some(SystemRisk::HighTemp) => Some(base::stop(device)),
-            Some(SystemRisk::Bumped) => Some(base::escape(state, device)),
-            None => None,
-        };
+        let system_risk = self.risks.handle(state, device, &property);
         if system_risk.is_some() {
             log::debug!("System Risk Exists. Continue.");
             return; // Risk exists, continue
@@ -83,9 +117,10 @@ impl PilotHandler for Fill {
 
         // Assess and handle vision safety
         let vision_risk = match assess_vision_risk(detections, device) {
-            Some(VisionRisk::PersonDetected) | Some(VisionRisk::RoktrackDetected) => {
-                Some(base::stop(device))
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
             }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
             None => None,
         };
         if vision_risk.is_some() {
@@ -93,6 +128,33 @@ impl PilotHandler for Fill {
             return; // Risk exists, continue
         }
 
+        // Go around an obstacle, then rejoin the lane.
+        if let Some(plan) = self.detour.as_mut() {
+            if !maneuver::is_idle(device) {
+                return;
+            }
+            match plan.next_step() {
+                Some(step) => {
+                    maneuver::start(device, step);
+                    return;
+                }
+                None => {
+                    log::info!("Detour Finished. Rejoin the Lane.");
+                    self.detour = None;
+                }
+            }
+        }
+        let avoidance = &property.conf.avoidance;
+        // Not while turning on the spot to look for the next marker.
+        if avoidance.enabled && state.turn_count <= 0 {
+            if let Some(side) = assess_obstacle(state, detections, avoidance) {
+                log::info!("Obstacle Ahead. Detour on the {:?} Side.", side);
+                device.inner.clone().lock().unwrap().pause();
+                self.detour = Some(detour(side, avoidance, &property.conf.motion));
+                return;
+            }
+        }
+
         // Sort markers based on the current phase
         let detections = match state.phase {
             Phase::CCW => sort::right(detections),
@@ -100,9 +162,36 @@ impl PilotHandler for Fill {
         };
 
         // Get the first detected marker or a default one
-        let marker = select_marker(property, state, detections, device);
+        let marker = select_marker(property.clone(), state, detections.clone(), device);
+        // Keep to the marker being approached by its tracking ID, and pick anew while turning.
+        if 0 < state.turn_count {
+            state.marker_track = None;
+        }
+        let mut marker = tracker::follow(&mut state.marker_track, marker, &detections);
         log::debug!("Marker Selected: {:?}", marker);
 
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
+        // Keep to the lane with the other pylons in sight.
+        let triangulation = &property.conf.triangulation;
+        if triangulation.enabled {
+            marker.xc = self.lane.keep(state, &marker, &detections, triangulation);
+        }
+
         // Turn on the work motor
         device.inner.clone().lock().unwrap().work_motor.cw();
 
@@ -128,31 +217,77 @@ impl PilotHandler for Fill {
         };
         log::debug!("End Fill Handle");
     }
+
+    /// Keep the rest of the detour move cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some(plan) = self.detour.as_mut() {
+            plan.interrupt(maneuver::remaining(device));
+        }
+    }
 }
 
-/// System Risks
-///
-#[derive(Debug, Clone)]
-enum SystemRisk {
-    StateOff,
-    HighTemp,
-    Bumped,
+/// Side to pass an obstacle on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Side {
+    Left,
+    Right,
 }
-/// Identify system-related risks
+
+/// Finds an obstacle close ahead and the side to pass it on.
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
-    if !state.state {
-        Some(SystemRisk::StateOff)
-    } else if state.pi_temp > 70.0 {
-        device.inner.clone().lock().unwrap().speak("high_temp");
-        Some(SystemRisk::HighTemp)
-    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
-        device.inner.clone().lock().unwrap().speak("bumped");
-        Some(SystemRisk::Bumped)
-    } else {
-        None
+/// An obstacle seen by the camera is passed on the opposite side.
+/// It is close by the distance measured by the stereo or the depth camera if enabled, otherwise by its height.
+/// One only measured by the ultrasonic sensor or the depth ahead is passed on the inner side of the laps.
+fn assess_obstacle(
+    state: &RoktrackState,
+    dets: &mut [Detection],
+    conf: &Avoidance,
+) -> Option<Side> {
+    let width = state.img_width as f32;
+    let seen = RoktrackClasses::filter_category(dets, Category::Obstacle)
+        .into_iter()
+        .filter(|det| match det.distance {
+            Some(distance) if 0.0 < conf.distance => distance <= conf.distance,
+            _ => state.img_height as f32 * conf.height_ratio <= det.h as f32,
+        })
+        .filter(|det| (det.x1 as f32) < width * LANE_BAND.1 && width * LANE_BAND.0 < det.x2 as f32)
+        .max_by_key(|det| det.h);
+    if let Some(det) = seen {
+        return match det.xc < width / 2.0 {
+            true => Some(Side::Right),
+            false => Some(Side::Left),
+        };
+    }
+    let ranged = matches!(state.telemetry.range_cm,
+        Some(range) if 0 < conf.range_cm && range <= conf.range_cm);
+    let deep = matches!(state.depth_ahead,
+        Some(depth) if 0.0 < conf.distance && depth <= conf.distance);
+    match ranged || deep {
+        true => match state.phase {
+            Phase::CCW => Some(Side::Left),
+            Phase::CW => Some(Side::Right),
+        },
+        false => None,
     }
 }
+
+/// Plans the detour around an obstacle, ending on the lane in the same heading.
+fn detour(side: Side, conf: &Avoidance, motion: &Motion) -> ManeuverPlan {
+    let (away, back) = match side {
+        Side::Left => (
+            Maneuver::Left(motion.quarter_turn_ms),
+            Maneuver::Right(motion.quarter_turn_ms),
+        ),
+        Side::Right => (
+            Maneuver::Right(motion.quarter_turn_ms),
+            Maneuver::Left(motion.quarter_turn_ms),
+        ),
+    };
+    let clearance = Maneuver::Forward(maneuver::travel_time(conf.clearance, motion.speed));
+    let pass = Maneuver::Forward(maneuver::travel_time(conf.pass_length, motion.speed));
+    ManeuverPlan::new(vec![away, clearance, back, pass, back, clearance, away])
+}
+
 /// Vision-related risks
 ///
 #[derive(Debug, Clone)]
@@ -225,3 +360,112 @@ fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhas
         Some(ActPhase::Proceed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn assess_obstacle_test() {
+        let mut state = RoktrackState::new();
+        let conf = Avoidance::default();
+        let obstacle = Detection {
+            x1: 100,
+            x2: 150,
+            xc: 125.0,
+            h: 120,
+            cls: RoktrackClasses::OBSTACLE.to_u32(),
+            ..Default::default()
+        };
+        // Close on the left of the lane, passed on the right.
+        assert_eq!(
+            assess_obstacle(&state, &mut [obstacle.clone()], &conf),
+            Some(Side::Right)
+        );
+        // A lawn obstacle alike
+        let hose = Detection {
+            cls: RoktrackClasses::HOSE.to_u32(),
+            ..obstacle.clone()
+        };
+        assert_eq!(
+            assess_obstacle(&state, &mut [hose], &conf),
+            Some(Side::Right)
+        );
+        // Far away, or out of the lane.
+        let far = Detection {
+            h: 50,
+            ..obstacle.clone()
+        };
+        assert_eq!(assess_obstacle(&state, &mut [far], &conf), None);
+        let aside = Detection {
+            x1: 250,
+            x2: 300,
+            xc: 275.0,
+            ..obstacle.clone()
+        };
+        assert_eq!(assess_obstacle(&state, &mut [aside], &conf), None);
+        // By the measured distance rather than the height when enabled.
+        let measured = |distance| Detection {
+            h: 50,
+            distance: Some(distance),
+            ..obstacle.clone()
+        };
+        assert_eq!(assess_obstacle(&state, &mut [measured(0.5)], &conf), None);
+        let by_distance = Avoidance {
+            distance: 0.8,
+            ..Default::default()
+        };
+        assert_eq!(
+            assess_obstacle(&state, &mut [measured(0.5)], &by_distance),
+            Some(Side::Right)
+        );
+        assert_eq!(
+            assess_obstacle(&state, &mut [measured(1.5)], &by_distance),
+            None
+        );
+        // Measured by the ultrasonic sensor only when enabled.
+        state.telemetry.range_cm = Some(20);
+        assert_eq!(assess_obstacle(&state, &mut [], &conf), None);
+        let conf = Avoidance {
+            range_cm: 30,
+            ..Default::default()
+        };
+        assert_eq!(assess_obstacle(&state, &mut [], &conf), Some(Side::Left));
+        // The depth ahead only when by the distance.
+        state.telemetry.range_cm = None;
+        state.depth_ahead = Some(0.5);
+        assert_eq!(assess_obstacle(&state, &mut [], &conf), None);
+        assert_eq!(
+            assess_obstacle(&state, &mut [], &by_distance),
+            Some(Side::Left)
+        );
+        state.depth_ahead = Some(1.5);
+        assert_eq!(assess_obstacle(&state, &mut [], &by_distance), None);
+    }
+
+    #[test]
+    fn detour_test() {
+        let conf = Avoidance::default();
+        let motion = Motion {
+            speed: 0.5,
+            quarter_turn_ms: 1000,
+        };
+        let mut plan = detour(Side::Right, &conf, &motion);
+        let mut steps = vec![];
+        while let Some(step) = plan.next_step() {
+            steps.push(step);
+        }
+        assert_eq!(
+            steps,
+            vec![
+                Maneuver::Right(1000),
+                Maneuver::Forward(1000),
+                Maneuver::Left(1000),
+                Maneuver::Forward(2000),
+                Maneuver::Left(1000),
+                Maneuver::Forward(1000),
+                Maneuver::Right(1000),
+            ]
+        );
+    }
+}
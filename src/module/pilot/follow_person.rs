@@ -1,24 +1,57 @@
 //! Follow Person Pilot
 //!
+//! The following distance is kept with the height of the person in the image as a proxy for range.
+//! The speed is proportional to the difference from the following distance, and the robot stops
+//! when it gets there. It starts again only after the person gets farther by the hysteresis,
+//! so that it does not oscillate between stop and full speed.
+//!
+//! The person can also raise a hand to stop the robot, and wave it to resume.
 
+use std::collections::VecDeque;
 use std::sync::mpsc::Sender;
 
-use super::PilotHandler;
+use super::{
+    lost::LossMonitor,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
 use crate::module::{
     device::Chassis,
     device::Roktrack,
     pilot::base,
     pilot::RoktrackState,
+    util::conf::FollowPerson as FollowPersonConf,
     util::init::RoktrackProperty,
-    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::detector::{pose::HAND_RAISED_CLASS, sort, Detection, FilterClass, RoktrackClasses},
     vision::VisionMgmtCommand,
 };
 
-pub struct FollowPerson {}
+// Frames looked back for gestures.
+const GESTURE_WINDOW: usize = 8;
+
+// Frames the hand is held up still to stop.
+const HOLD_FRAMES: usize = 4;
+
+// Movement of the hand (ratio to the image width) regarded as a swing of a wave.
+const WAVE_AMPLITUDE: f32 = 0.03;
+
+pub struct FollowPerson {
+    moving: bool,                 // Whether following, for the hysteresis
+    stopped: bool,                // Stopped by the gesture
+    hands: VecDeque<Option<f32>>, // Position of the raised hand in recent frames
+    lost: LossMonitor,            // Frames without the tracked marker
+    risks: RiskEngine,            // System risks to check before driving
+}
 
 impl FollowPerson {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            moving: false,
+            stopped: false,
+            hands: VecDeque::new(),
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
+        }
     }
 }
 
@@ -36,20 +69,45 @@ impl PilotHandler for FollowPerson {
         device: &mut Roktrack,
         detections: &mut [Detection],
         tx: Sender<VisionMgmtCommand>,
-        _property: RoktrackProperty,
+        property: RoktrackProperty,
     ) {
         log::debug!("Start FollowPerson Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) | Some(SystemRisk::HighTemp) => Some(base::stop(device)),
-            Some(SystemRisk::Bumped) => Some(base::escape(state, device)),
-            None => None,
-        };
+        let system_risk = self.risks.handle(state, device, &property);
         if system_risk.is_some() {
             log::debug!("System Risk Exists. Continue.");
             return; // Risk exists, continue
         }
 
+        // Stop and resume by the gestures of the person.
+        if property.conf.follow_person.gesture {
+            let hand = detections
+                .iter()
+                .find(|det| det.cls == HAND_RAISED_CLASS)
+                .map(|det| det.xc / state.img_width as f32);
+            self.hands.push_back(hand);
+            if GESTURE_WINDOW < self.hands.len() {
+                self.hands.pop_front();
+            }
+            match recognize(&self.hands) {
+                Some(Gesture::Stop) if !self.stopped => {
+                    log::info!("Stop Gesture Recognized.");
+                    self.stopped = true;
+                    self.hands.clear();
+                }
+                Some(Gesture::Resume) if self.stopped => {
+                    log::info!("Resume Gesture Recognized.");
+                    self.stopped = false;
+                    self.hands.clear();
+                }
+                _ => {}
+            }
+            if self.stopped {
+                device.inner.lock().unwrap().pause();
+                return;
+            }
+        }
+
         // Sort markers based on the current phase
         let detections = sort::big(detections);
         let detections =
@@ -59,6 +117,22 @@ impl PilotHandler for FollowPerson {
         let marker = detections.first().cloned().unwrap_or_default();
         log::debug!("Marker Selected: {:?}", marker);
 
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
         let action = assess_situation(state, &marker);
         log::debug!("Action is {:?}", action);
 
@@ -72,41 +146,93 @@ impl PilotHandler for FollowPerson {
             Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
             Some(ActPhase::Stand) => base::stand(state, tx),
             Some(ActPhase::StartTurn) => base::start_turn(state, device),
-            Some(ActPhase::ReachMarker) => {
-                log::debug!("Reach Marker pausing.");
-                device.inner.lock().unwrap().pause();
-                Ok(())
+            Some(ActPhase::ReachMarker) | Some(ActPhase::Proceed) => {
+                let ratio = marker.h as f32 / state.img_height as f32;
+                let (moving, speed) = control(ratio, self.moving, &property.conf.follow_person);
+                self.moving = moving;
+                if moving {
+                    log::debug!("Follow the person. speed: {}", speed);
+                    let mut device_lock = device.inner.lock().unwrap();
+                    device_lock.drive_motor_left.power = property.conf.pwm.pwm_power_left * speed;
+                    device_lock.drive_motor_right.power = property.conf.pwm.pwm_power_right * speed;
+                    drop(device_lock);
+                    base::proceed(state, device, marker, tx)
+                } else {
+                    log::debug!("Keep the distance pausing.");
+                    device.inner.lock().unwrap().pause();
+                    Ok(())
+                }
             }
-            Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
             None => Ok(()),
         };
         log::debug!("End FollowPerson Handle");
     }
 }
 
-/// System Risks
-///
-#[derive(Debug, Clone)]
-enum SystemRisk {
-    StateOff,
-    HighTemp,
-    Bumped,
+/// Decides whether to follow and the speed (ratio to the motor power) from the height of the person.
+fn control(ratio: f32, moving: bool, conf: &FollowPersonConf) -> (bool, f64) {
+    let error = conf.distance_ratio - ratio;
+    let moving = match moving {
+        true => 0.0 < error,
+        false => conf.hysteresis < error,
+    };
+    if moving {
+        let speed = (conf.gain * error).clamp(conf.min_speed.min(1.0), 1.0);
+        (true, speed as f64)
+    } else {
+        (false, 0.0)
+    }
+}
+
+/// Gestures of the person followed.
+#[derive(Debug, Clone, PartialEq)]
+enum Gesture {
+    Stop,   // Hand raised and held still
+    Resume, // Hand waved
 }
-/// Identify system-related risks
+
+/// Recognizes a gesture from the positions of the raised hand in recent frames.
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
-    if !state.state {
-        Some(SystemRisk::StateOff)
-    } else if state.pi_temp > 70.0 {
-        device.inner.clone().lock().unwrap().speak("high_temp");
-        Some(SystemRisk::HighTemp)
-    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
-        device.inner.clone().lock().unwrap().speak("bumped");
-        Some(SystemRisk::Bumped)
+/// Each position is the x of the hand (ratio to the image width), or None when no hand is raised.
+fn recognize(hands: &VecDeque<Option<f32>>) -> Option<Gesture> {
+    // A wave turns back at least twice.
+    let mut turns = 0;
+    let mut direction = 0.0;
+    let mut anchor = None;
+    for x in hands.iter().flatten() {
+        let Some(a) = anchor else {
+            anchor = Some(*x);
+            continue;
+        };
+        let d: f32 = x - a;
+        if WAVE_AMPLITUDE <= d.abs() {
+            if direction != 0.0 && d.signum() != direction {
+                turns += 1;
+            }
+            direction = d.signum();
+            anchor = Some(*x);
+        }
+    }
+    if 2 <= turns {
+        return Some(Gesture::Resume);
+    }
+    // The hand held still in the latest frames.
+    let recent: Vec<f32> = hands
+        .iter()
+        .rev()
+        .take(HOLD_FRAMES)
+        .flatten()
+        .copied()
+        .collect();
+    let spread = recent.iter().fold(f32::MIN, |m, x| m.max(*x))
+        - recent.iter().fold(f32::MAX, |m, x| m.min(*x));
+    if recent.len() == HOLD_FRAMES && spread < WAVE_AMPLITUDE {
+        Some(Gesture::Stop)
     } else {
         None
     }
 }
+
 /// Actions for Fill Drive Pilot
 ///
 #[derive(Debug, Clone)]
@@ -155,3 +281,39 @@ fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhas
         Some(ActPhase::Proceed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_test() {
+        let conf = FollowPersonConf::default(); // 0.6, gain 2.0, min 0.5, hysteresis 0.1
+                                                // Far away at full speed, slowing down on the way.
+        assert_eq!(control(0.1, false, &conf), (true, 1.0));
+        let (moving, speed) = control(0.35, true, &conf);
+        assert!(moving && (speed - 0.5).abs() < 1e-6);
+        assert_eq!(control(0.55, true, &conf), (true, 0.5));
+        // Stops at the distance.
+        assert_eq!(control(0.6, true, &conf), (false, 0.0));
+        // Not again until the person gets farther by the hysteresis.
+        assert_eq!(control(0.55, false, &conf), (false, 0.0));
+        assert_eq!(control(0.45, false, &conf), (true, 0.5));
+    }
+
+    #[test]
+    fn gesture_test() {
+        let hands = |xs: &[Option<f32>]| xs.iter().copied().collect::<VecDeque<_>>();
+        assert_eq!(recognize(&hands(&[])), None);
+        assert_eq!(recognize(&hands(&[None, Some(0.5), Some(0.5)])), None);
+        // Held still
+        let still = [None, Some(0.5), Some(0.51), Some(0.5), Some(0.5)];
+        assert_eq!(recognize(&hands(&still)), Some(Gesture::Stop));
+        // Lowered
+        let lowered = [Some(0.5), Some(0.5), Some(0.5), Some(0.5), None];
+        assert_eq!(recognize(&hands(&lowered)), None);
+        // Waved
+        let waved = [Some(0.5), Some(0.6), Some(0.5), Some(0.6), None];
+        assert_eq!(recognize(&hands(&waved)), Some(Gesture::Resume));
+    }
+}
@@ -0,0 +1,148 @@
+//! Geofence
+//!
+//! Keeps the robot in the allowed area whatever the pilot does, so that a bug in a single mode
+//! can't send it off the field. The area is a polygon of GPS positions and/or a maximum distance
+//! from the first position of the job, taken again when a job starts or resumes. Without a position, nothing tells where it is, so it's
+//! never out of the area.
+//!
+//! # Polygon file
+//! One `<lat>,<lon>` vertex in degrees per line, in the same format as the waypoint file.
+
+use std::path::Path;
+
+use super::waypoint;
+use crate::module::util::conf::Geofence as GeofenceConf;
+
+/// Ways to leave the allowed area.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Breach {
+    OutsidePolygon,
+    TooFar(f64), // Distance from the start in m
+}
+
+/// Allowed area.
+#[derive(Debug, Clone, Default)]
+pub struct Geofence {
+    polygon: Vec<(f64, f64)>,  // Vertices (latitude and longitude)
+    max_distance: f64,         // Maximum distance from the start in m (0: disabled)
+    start: Option<(f64, f64)>, // First position of the job
+}
+
+impl Geofence {
+    /// Creates a new Geofence instance with the polygon and the maximum distance.
+    pub fn new(polygon: Vec<(f64, f64)>, max_distance: f64) -> Self {
+        Self {
+            polygon,
+            max_distance,
+            start: None,
+        }
+    }
+
+    /// Creates the geofence from the configuration, loading the polygon from the data directory.
+    pub fn from_conf(conf: &GeofenceConf, data_dir: &str) -> Self {
+        let mut polygon = vec![];
+        if !conf.file.is_empty() {
+            let path = Path::new(data_dir).join(&conf.file);
+            match waypoint::load(&path) {
+                Ok(vertices) if 3 <= vertices.len() => {
+                    log::info!("Geofence Loaded: {} ({})", vertices.len(), path.display());
+                    polygon = vertices;
+                }
+                Ok(vertices) => {
+                    log::error!("Too Few Geofence Vertices: {}", vertices.len())
+                }
+                Err(e) => log::error!("Can't Load Geofence: {}, {}", path.display(), e),
+            }
+        }
+        Self::new(polygon, conf.max_distance)
+    }
+
+    /// Whether any limit is set.
+    pub fn is_enabled(&self) -> bool {
+        !self.polygon.is_empty() || 0.0 < self.max_distance
+    }
+
+    /// Forgets the start, so that the next position is taken as the start of a new job.
+    pub fn reset(&mut self) {
+        self.start = None;
+    }
+
+    /// Checks the position while driving. The first position since the reset is taken as the start.
+    pub fn check(&mut self, position: Option<(f64, f64)>) -> Option<Breach> {
+        let position = position?;
+        let start = *self.start.get_or_insert(position);
+        if !self.polygon.is_empty() && !contains(&self.polygon, position) {
+            return Some(Breach::OutsidePolygon);
+        }
+        let distance = waypoint::distance(start, position);
+        if 0.0 < self.max_distance && self.max_distance < distance {
+            return Some(Breach::TooFar(distance));
+        }
+        None
+    }
+}
+
+/// Whether the position is inside the polygon (ray casting toward the east).
+fn contains(polygon: &[(f64, f64)], position: (f64, f64)) -> bool {
+    let (lat, lon) = position;
+    let mut inside = false;
+    // Each vertex with the previous one
+    let edges = polygon
+        .iter()
+        .zip(polygon.iter().cycle().skip(polygon.len() - 1));
+    for (&(lat_i, lon_i), &(lat_j, lon_j)) in edges {
+        if (lat_i > lat) != (lat_j > lat)
+            && lon < (lon_j - lon_i) * (lat - lat_i) / (lat_j - lat_i) + lon_i
+        {
+            inside = !inside;
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geofence_test() {
+        let square = vec![
+            (35.0, 139.0),
+            (35.0, 139.001),
+            (35.001, 139.001),
+            (35.001, 139.0),
+        ];
+        assert!(contains(&square, (35.0005, 139.0005)));
+        assert!(!contains(&square, (35.002, 139.0005)));
+        assert!(!contains(&square, (35.0005, 138.999)));
+
+        let mut fence = Geofence::new(square.clone(), 0.0);
+        assert!(fence.is_enabled());
+        assert_eq!(fence.check(None), None);
+        assert_eq!(fence.check(Some((35.0005, 139.0005))), None);
+        assert_eq!(
+            fence.check(Some((35.002, 139.0005))),
+            Some(Breach::OutsidePolygon)
+        );
+
+        // About 111 m per 0.001 degrees of latitude
+        let mut fence = Geofence::new(vec![], 50.0);
+        assert_eq!(fence.check(Some((35.0, 139.0))), None);
+        assert_eq!(fence.check(Some((35.0004, 139.0))), None);
+        assert!(matches!(
+            fence.check(Some((35.001, 139.0))),
+            Some(Breach::TooFar(d)) if 100.0 < d
+        ));
+
+        // The next job starts where the robot is then, e.g. on another lawn.
+        fence.reset();
+        assert_eq!(fence.check(Some((35.01, 139.0))), None);
+        assert_eq!(fence.check(Some((35.0104, 139.0))), None);
+        assert!(matches!(
+            fence.check(Some((35.0, 139.0))),
+            Some(Breach::TooFar(d)) if 1000.0 < d
+        ));
+
+        assert!(!Geofence::default().is_enabled());
+    }
+}
@@ -0,0 +1,256 @@
+//! Mission Lifecycle
+//!
+//! The drive loop watches the drive state and reports the start, completion and abort of a mission
+//! to the pilot (`PilotHandler::on_start` etc.) and then to the hooks registered here. Concerns shared
+//! by the modes attach to the hooks once, instead of each pilot taking care of them.
+//!
+//! | event    | when                                                                   |
+//! |----------|------------------------------------------------------------------------|
+//! | Start    | The drive turns on, except resuming from a pause                       |
+//! | Complete | The drive turns off with MissionComplete                               |
+//! | Abort    | The drive turns off otherwise, e.g. turned off, halted or out of bounds |
+//!
+//! The reason of an abort is the message of the state (`RoktrackState::msg`) at the time.
+//!
+//! # Built-in hooks
+//!
+//! | hook       | Complete                          | Abort                             |
+//! |------------|-----------------------------------|-----------------------------------|
+//! | announce   | Speak "mission_complete"          |                                   |
+//! | notify     | Notify the owner                  | (notified where it occurs)        |
+//! | statistics | Log the duration and the work     | Log the duration and the work     |
+//! | persist    | Clear the fill progress           | Save the fill progress to resume  |
+
+use std::time::{Duration, Instant};
+
+use super::{
+    progress::{self, FillProgress},
+    Modes, PilotHandler, RoktrackState,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::Roktrack,
+    util::{common::send_line_notify_with_image, init::RoktrackProperty},
+};
+
+/// Events of the lifecycle of a mission.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    Start,
+    Complete,
+    Abort,
+}
+
+/// A mission reported to the hooks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Mission {
+    pub event: Event,
+    pub mode: Modes,
+    pub elapsed: Duration, // Since the start
+}
+
+/// Function called on the events.
+pub type Hook = fn(&Mission, &mut RoktrackState, &mut Roktrack, &RoktrackProperty);
+
+/// Watches the drive state and calls the hooks in the order of the registration.
+#[derive(Clone, Default)]
+pub struct Lifecycle {
+    running: bool,                    // Whether a mission is running, including paused
+    started: Option<Instant>,         // Time of the start
+    hooks: Vec<(&'static str, Hook)>, // Hooks with their names
+}
+
+impl Lifecycle {
+    /// Creates a lifecycle without hooks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a hook, replacing the previous one of the same name.
+    pub fn register(&mut self, name: &'static str, hook: Hook) {
+        match self.hooks.iter_mut().find(|(n, _)| *n == name) {
+            Some(registered) => registered.1 = hook,
+            None => self.hooks.push((name, hook)),
+        }
+    }
+
+    /// Observes the drive state. Returns the mission when an event occurs.
+    /// A pause turns the drive off for a while, but the mission goes on.
+    pub fn observe(
+        &mut self,
+        running: bool,
+        paused: bool,
+        msg: u8,
+        mode: Modes,
+        now: Instant,
+    ) -> Option<Mission> {
+        let running = running || paused;
+        if running == self.running {
+            return None;
+        }
+        self.running = running;
+        let elapsed = self
+            .started
+            .map(|started| now.duration_since(started))
+            .unwrap_or_default();
+        let event = match running {
+            true => {
+                self.started = Some(now);
+                Event::Start
+            }
+            false if msg == ChildMsg::to_u8(ChildMsg::MissionComplete) => Event::Complete,
+            false => Event::Abort,
+        };
+        Some(Mission {
+            event,
+            mode,
+            elapsed: match event {
+                Event::Start => Duration::ZERO,
+                _ => elapsed,
+            },
+        })
+    }
+
+    /// Reports the mission to the pilot, then to the hooks.
+    pub fn dispatch(
+        &self,
+        mission: &Mission,
+        handler: &mut Box<dyn PilotHandler>,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: &RoktrackProperty,
+    ) {
+        log::info!("Mission {:?}: {:?}", mission.event, mission.mode);
+        match mission.event {
+            Event::Start => handler.on_start(state, device, property),
+            Event::Complete => handler.on_complete(state, device, property),
+            Event::Abort => handler.on_abort(state, device, property),
+        }
+        for (name, hook) in self.hooks.iter() {
+            log::debug!("Lifecycle Hook: {}", name);
+            hook(mission, state, device, property);
+        }
+    }
+}
+
+/// Lifecycle with the built-in hooks.
+pub fn builtin() -> Lifecycle {
+    let mut lifecycle = Lifecycle::new();
+    lifecycle.register("announce", announce);
+    lifecycle.register("notify", notify);
+    lifecycle.register("statistics", statistics);
+    lifecycle.register("persist", persist);
+    lifecycle
+}
+
+/// Announces the completion.
+fn announce(
+    mission: &Mission,
+    _state: &mut RoktrackState,
+    device: &mut Roktrack,
+    _property: &RoktrackProperty,
+) {
+    if mission.event == Event::Complete {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("mission_complete");
+    }
+}
+
+/// Notifies the owner of the completion. Aborts are notified where they occur.
+fn notify(
+    mission: &Mission,
+    _state: &mut RoktrackState,
+    _device: &mut Roktrack,
+    property: &RoktrackProperty,
+) {
+    if mission.event == Event::Complete {
+        let msg = format!(
+            "{:?} complete in {} min.",
+            mission.mode,
+            mission.elapsed.as_secs() / 60
+        );
+        let _ = send_line_notify_with_image(&msg, &property.path.img.last, property.conf.clone());
+    }
+}
+
+/// Logs the duration and the work of the mission.
+fn statistics(
+    mission: &Mission,
+    state: &mut RoktrackState,
+    _device: &mut Roktrack,
+    _property: &RoktrackProperty,
+) {
+    if mission.event != Event::Start {
+        log::info!(
+            "Mission {:?}: {:?} in {} s, msg: {}, work: {:?}",
+            mission.event,
+            mission.mode,
+            mission.elapsed.as_secs(),
+            state.msg,
+            state.telemetry.work
+        );
+    }
+}
+
+/// Keeps the progress of the fill mission to resume it, or clears it when complete.
+fn persist(
+    mission: &Mission,
+    state: &mut RoktrackState,
+    _device: &mut Roktrack,
+    property: &RoktrackProperty,
+) {
+    if mission.mode != Modes::Fill {
+        return;
+    }
+    match mission.event {
+        Event::Complete => progress::clear(&property.path.dir.data),
+        Event::Abort => {
+            let saved = FillProgress::from_state(state, chrono::Utc::now().timestamp_millis());
+            if let Err(e) = saved.save(&progress::path(&property.path.dir.data)) {
+                log::warn!("Can't Save Fill Progress: {}", e);
+            }
+        }
+        Event::Start => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lifecycle_test() {
+        let t0 = Instant::now();
+        let t1 = t0 + Duration::from_secs(60);
+        let complete = ChildMsg::to_u8(ChildMsg::MissionComplete);
+        let mut lifecycle = Lifecycle::new();
+        assert_eq!(lifecycle.observe(false, false, 0, Modes::Fill, t0), None);
+        let start = lifecycle.observe(true, false, 0, Modes::Fill, t0).unwrap();
+        assert_eq!(start.event, Event::Start);
+        assert_eq!(lifecycle.observe(true, false, 0, Modes::Fill, t0), None);
+        // Paused and resumed
+        assert_eq!(lifecycle.observe(false, true, 0, Modes::Fill, t0), None);
+        assert_eq!(lifecycle.observe(true, false, 0, Modes::Fill, t0), None);
+        let end = lifecycle.observe(false, false, complete, Modes::Fill, t1);
+        assert_eq!(
+            end,
+            Some(Mission {
+                event: Event::Complete,
+                mode: Modes::Fill,
+                elapsed: Duration::from_secs(60),
+            })
+        );
+        // Turned off while paused
+        lifecycle.observe(true, false, 0, Modes::Fill, t0);
+        lifecycle.observe(false, true, 0, Modes::Fill, t0);
+        let end = lifecycle.observe(false, false, 0, Modes::Fill, t1).unwrap();
+        assert_eq!(end.event, Event::Abort);
+
+        let names: Vec<&str> = builtin().hooks.iter().map(|(name, _)| *name).collect();
+        assert_eq!(names, vec!["announce", "notify", "statistics", "persist"]);
+    }
+}
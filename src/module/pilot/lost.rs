@@ -0,0 +1,158 @@
+//! Vision-Loss Failsafe
+//!
+//! While a pilot is tracking a marker, the last command keeps the motors running until the next one.
+//! When the marker goes out of sight, the robot must not keep driving blind on that command.
+//!
+//! | frames without the marker    | reaction                                          |
+//! |------------------------------|---------------------------------------------------|
+//! | < `frames`                   | Pause and wait for the vision to catch up         |
+//! | `frames`                     | Stop                                              |
+//! | up to `frames + search_steps`| Turn step by step in the lap direction to find it |
+//! | beyond                       | Halt with TargetLost                              |
+//!
+//! Only the marker seen once while tracking counts. The pilots' own searches (e.g. the turns for
+//! the next marker) aren't tracking, and reset the monitor.
+
+use std::sync::mpsc::Sender;
+
+use super::{base, Phase, RoktrackState};
+use crate::module::{
+    com::ChildMsg,
+    device::{Chassis, Roktrack},
+    util::conf::VisionLoss,
+    vision::VisionMgmtCommand,
+};
+
+/// Reactions to the loss of the marker.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Loss {
+    Tracking,   // In sight, or not tracking
+    Missing,    // Out of sight for a few frames
+    Stop,       // Out of sight for `frames`
+    Search(u8), // Searching, with the step
+    Lost,       // Not found by the search
+}
+
+/// Counts the frames without the tracked marker.
+#[derive(Debug, Clone, Default)]
+pub struct LossMonitor {
+    tracked: bool, // Whether the marker has been seen since the tracking started
+    missing: u16,  // Frames without the marker in a row
+}
+
+impl LossMonitor {
+    /// Observes whether the marker is expected and seen in the frame.
+    pub fn observe(&mut self, expected: bool, seen: bool, conf: &VisionLoss) -> Loss {
+        if !expected || conf.frames == 0 {
+            *self = Self::default();
+            return Loss::Tracking;
+        }
+        if seen {
+            self.tracked = true;
+            self.missing = 0;
+            return Loss::Tracking;
+        }
+        if !self.tracked {
+            return Loss::Tracking;
+        }
+        self.missing = self.missing.saturating_add(1);
+        let frames = conf.frames as u16;
+        match self.missing {
+            n if n < frames => Loss::Missing,
+            n if n == frames => Loss::Stop,
+            n if n <= frames + conf.search_steps as u16 => Loss::Search((n - frames) as u8),
+            _ => Loss::Lost,
+        }
+    }
+
+    /// Observes the marker while tracking it (not turning), and responds to the loss.
+    /// Returns Some when the pilot must not drive on in this frame, like `base::stop`.
+    pub fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        tx: Sender<VisionMgmtCommand>,
+        seen: bool,
+        conf: &VisionLoss,
+    ) -> Option<Result<(), Box<dyn std::error::Error>>> {
+        let loss = self.observe(state.turn_count == 0, seen, conf);
+        respond(self, loss, state, device, tx, conf)
+    }
+}
+
+/// Responds to the loss of the marker.
+fn respond(
+    monitor: &mut LossMonitor,
+    loss: Loss,
+    state: &mut RoktrackState,
+    device: &mut Roktrack,
+    tx: Sender<VisionMgmtCommand>,
+    conf: &VisionLoss,
+) -> Option<Result<(), Box<dyn std::error::Error>>> {
+    match loss {
+        Loss::Tracking => None,
+        Loss::Missing => {
+            device.inner.clone().lock().unwrap().pause();
+            Some(Ok(()))
+        }
+        Loss::Stop => {
+            log::warn!("Marker Lost. Stop and search for it.");
+            state.msg = ChildMsg::to_u8(ChildMsg::TargetLost);
+            Some(base::stop(device))
+        }
+        Loss::Search(step) => {
+            log::debug!("Searching the lost marker. step: {}", step);
+            match state.phase {
+                Phase::CCW => device.inner.clone().lock().unwrap().left(conf.step_ms),
+                Phase::CW => device.inner.clone().lock().unwrap().right(conf.step_ms),
+            };
+            Some(Ok(()))
+        }
+        Loss::Lost => {
+            log::warn!("Marker Lost. Halted!");
+            *monitor = LossMonitor::default();
+            state.state = false;
+            state.msg = ChildMsg::to_u8(ChildMsg::TargetLost);
+            device.inner.clone().lock().unwrap().stop();
+            device.inner.clone().lock().unwrap().speak("cone_not_found");
+            tx.send(VisionMgmtCommand::Off).unwrap();
+            Some(Ok(()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loss_test() {
+        let conf = VisionLoss {
+            frames: 2,
+            search_steps: 2,
+            step_ms: 500,
+        };
+        let mut monitor = LossMonitor::default();
+        // Never seen, e.g. before the first marker is found.
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Tracking);
+        assert_eq!(monitor.observe(true, true, &conf), Loss::Tracking);
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Missing);
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Stop);
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Search(1));
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Search(2));
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Lost);
+        // Found again by the search.
+        let mut monitor = LossMonitor::default();
+        monitor.observe(true, true, &conf);
+        monitor.observe(true, false, &conf);
+        assert_eq!(monitor.observe(true, true, &conf), Loss::Tracking);
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Missing);
+        // The pilot's own search resets the tracking.
+        monitor.observe(false, false, &conf);
+        assert_eq!(monitor.observe(true, false, &conf), Loss::Tracking);
+        // Disabled
+        let disabled = VisionLoss { frames: 0, ..conf };
+        monitor.observe(true, true, &disabled);
+        assert_eq!(monitor.observe(true, false, &disabled), Loss::Tracking);
+    }
+}
@@ -0,0 +1,204 @@
+//! Timed Maneuvers
+//!
+//! Patterns without markers along the way (spiral, stripes, patrol routes) are driven as a plan of
+//! timed moves. A move is started once the previous one has finished.
+
+use std::collections::VecDeque;
+use std::path::Path;
+
+use crate::module::device::{Chassis, Roktrack};
+
+/// Timed move in milliseconds.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Maneuver {
+    Forward(u64),
+    Backward(u64),
+    Left(u64),
+    Right(u64),
+}
+
+impl Maneuver {
+    /// The move that undoes this one.
+    pub fn inverse(&self) -> Maneuver {
+        match *self {
+            Maneuver::Forward(ms) => Maneuver::Backward(ms),
+            Maneuver::Backward(ms) => Maneuver::Forward(ms),
+            Maneuver::Left(ms) => Maneuver::Right(ms),
+            Maneuver::Right(ms) => Maneuver::Left(ms),
+        }
+    }
+
+    /// Duration of the move in ms.
+    pub fn duration(&self) -> u64 {
+        match *self {
+            Maneuver::Forward(ms)
+            | Maneuver::Backward(ms)
+            | Maneuver::Left(ms)
+            | Maneuver::Right(ms) => ms,
+        }
+    }
+
+    /// The same move for another duration.
+    pub fn with_duration(&self, ms: u64) -> Maneuver {
+        match *self {
+            Maneuver::Forward(_) => Maneuver::Forward(ms),
+            Maneuver::Backward(_) => Maneuver::Backward(ms),
+            Maneuver::Left(_) => Maneuver::Left(ms),
+            Maneuver::Right(_) => Maneuver::Right(ms),
+        }
+    }
+}
+
+/// Sequence of moves.
+#[derive(Debug, Clone)]
+pub struct ManeuverPlan {
+    steps: VecDeque<Maneuver>,
+    total: usize,
+    current: Option<Maneuver>, // Move taken last
+}
+
+impl ManeuverPlan {
+    /// Creates a plan from moves.
+    pub fn new(steps: Vec<Maneuver>) -> Self {
+        Self {
+            total: steps.len(),
+            steps: steps.into(),
+            current: None,
+        }
+    }
+
+    /// Takes the next move.
+    pub fn next_step(&mut self) -> Option<Maneuver> {
+        self.current = self.steps.pop_front();
+        self.current
+    }
+
+    /// Puts back the rest of the move cut short, so that it is taken next.
+    pub fn interrupt(&mut self, remaining_ms: u64) {
+        if let Some(step) = self.current.take() {
+            if 0 < remaining_ms {
+                self.steps.push_front(step.with_duration(remaining_ms));
+            }
+        }
+    }
+
+    /// Whether all moves have been taken.
+    pub fn is_done(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Remaining work (1.0 -> 0.0), in the same way as `RoktrackState::rest`.
+    pub fn rest(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.steps.len() as f32 / self.total as f32
+        }
+    }
+}
+
+/// Whether the previous move has finished.
+pub fn is_idle(device: &Roktrack) -> bool {
+    chrono::Utc::now().timestamp_millis() as u64 > device.inner.lock().unwrap().target_time
+}
+
+/// Remaining time of the move in progress in ms, as passed to `start`.
+pub fn remaining(device: &Roktrack) -> u64 {
+    let device_lock = device.inner.lock().unwrap();
+    let now = chrono::Utc::now().timestamp_millis() as u64;
+    (device_lock.target_time.saturating_sub(now) as f32 / device_lock.turn_adj.max(0.01)) as u64
+}
+
+/// Starts a move.
+pub fn start(device: &mut Roktrack, maneuver: Maneuver) {
+    log::debug!("Start Maneuver: {:?}", maneuver);
+    let binding = device.inner.clone();
+    let mut device_lock = binding.lock().unwrap();
+    match maneuver {
+        Maneuver::Forward(ms) => device_lock.forward(ms),
+        Maneuver::Backward(ms) => device_lock.backward(ms),
+        Maneuver::Left(ms) => device_lock.left(ms),
+        Maneuver::Right(ms) => device_lock.right(ms),
+    }
+}
+
+/// Loads a route from a file.
+pub fn load(path: &Path) -> Result<Vec<Maneuver>, Box<dyn std::error::Error>> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Parses a route of `<forward|backward|left|right> <ms>` lines.
+///
+/// Empty lines and lines starting with `#` are skipped.
+pub fn parse(text: &str) -> Result<Vec<Maneuver>, Box<dyn std::error::Error>> {
+    let mut route = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (name, ms) = line
+            .split_once(char::is_whitespace)
+            .ok_or(format!("Invalid maneuver at line {}", i + 1))?;
+        let ms = ms.trim().parse()?;
+        route.push(match name {
+            "forward" => Maneuver::Forward(ms),
+            "backward" => Maneuver::Backward(ms),
+            "left" => Maneuver::Left(ms),
+            "right" => Maneuver::Right(ms),
+            _ => return Err(format!("Unknown maneuver at line {}: {}", i + 1, name).into()),
+        });
+    }
+    Ok(route)
+}
+
+/// Milliseconds to cover the distance at the speed.
+pub fn travel_time(distance: f32, speed: f32) -> u64 {
+    if speed <= 0.0 {
+        0
+    } else {
+        (distance.max(0.0) / speed * 1000.0) as u64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_test() {
+        let mut plan = ManeuverPlan::new(vec![Maneuver::Forward(1000), Maneuver::Left(500)]);
+        assert_eq!(plan.rest(), 1.0);
+        assert_eq!(plan.next_step(), Some(Maneuver::Forward(1000)));
+        assert_eq!(plan.rest(), 0.5);
+        assert_eq!(plan.next_step(), Some(Maneuver::Left(500)));
+        assert!(plan.is_done());
+        // The rest of the move cut short is taken next.
+        plan.interrupt(200);
+        assert_eq!(plan.next_step(), Some(Maneuver::Left(200)));
+        plan.interrupt(0);
+        assert_eq!(plan.next_step(), None);
+        assert_eq!(travel_time(1.5, 0.5), 3000);
+        assert_eq!(travel_time(1.0, 0.0), 0);
+        assert_eq!(Maneuver::Forward(100).inverse(), Maneuver::Backward(100));
+        assert_eq!(Maneuver::Left(100).inverse(), Maneuver::Right(100));
+        assert_eq!(Maneuver::Left(100).with_duration(30), Maneuver::Left(30));
+        assert_eq!(Maneuver::Backward(100).duration(), 100);
+    }
+
+    #[test]
+    fn parse_test() {
+        let text = "# route\nforward 3000\n\nleft  1000\nbackward 500\n";
+        assert_eq!(
+            parse(text).unwrap(),
+            vec![
+                Maneuver::Forward(3000),
+                Maneuver::Left(1000),
+                Maneuver::Backward(500)
+            ]
+        );
+        assert!(parse("forward").is_err());
+        assert!(parse("jump 100").is_err());
+        assert!(parse("left soon").is_err());
+    }
+}
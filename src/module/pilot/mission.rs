@@ -0,0 +1,265 @@
+//! Mission Sequencer Pilot
+//!
+
+// # General flow
+//
+// Step 1 (e.g. PerimeterTrim)
+//   | until the step completes, or its time is up
+// Step 2 (e.g. Fill)
+//   |
+// Step n (e.g. ReturnToDock)
+//   |
+// MissionComplete
+//
+// Each step drives with the handler of its mode, under the configuration overridden by the step.
+// The progress is reported over the whole mission.
+// When a step halts (e.g. the marker is not found), the whole mission is aborted and starts over
+// from the first step on the next start. Turning off only pauses the current step.
+
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use super::{Modes, PilotHandler};
+use crate::module::{
+    com::ChildMsg,
+    device::Roktrack,
+    pilot::base,
+    pilot::RoktrackState,
+    util::conf::{Config, MissionStep},
+    util::init::RoktrackProperty,
+    vision::detector::Detection,
+    vision::VisionMgmtCommand,
+};
+
+/// Creates the handler of the mode of a step.
+pub type StepFactory = Box<
+    dyn Fn(Modes, Sender<VisionMgmtCommand>, Config) -> Option<Box<dyn PilotHandler>> + Send + Sync,
+>;
+
+/// Handler of the step in progress.
+struct Current {
+    handler: Box<dyn PilotHandler>,
+    conf: Config,
+    started: Instant,
+    rest: f32, // Remaining work of the step
+}
+
+pub struct Mission {
+    steps: Vec<MissionStep>,
+    index: usize,
+    current: Option<Current>,
+    factory: StepFactory,
+    paused_at: Option<Instant>,
+}
+
+impl Mission {
+    pub fn new(steps: Vec<MissionStep>, factory: StepFactory) -> Self {
+        Self {
+            steps,
+            index: 0,
+            current: None,
+            factory,
+            paused_at: None,
+        }
+    }
+
+    /// Prepares the handler of the current step.
+    fn start_step(
+        &self,
+        tx: Sender<VisionMgmtCommand>,
+        conf: &Config,
+    ) -> Result<Current, Box<dyn std::error::Error>> {
+        let step = &self.steps[self.index];
+        let mode = Modes::from_string(&step.mode);
+        if mode == Modes::Mission {
+            return Err("A mission can't be nested.".into());
+        }
+        let conf = override_conf(conf, &step.conf)?;
+        let handler =
+            (self.factory)(mode, tx, conf.clone()).ok_or(format!("Unknown mode: {}", step.mode))?;
+        Ok(Current {
+            handler,
+            conf,
+            started: Instant::now(),
+            rest: 1.0,
+        })
+    }
+
+    /// Aborts the mission. It starts over from the first step.
+    fn abort(&mut self) {
+        log::error!("Mission Aborted at Step {}.", self.index + 1);
+        self.index = 0;
+        self.current = None;
+    }
+}
+
+impl PilotHandler for Mission {
+    /// Function called from a thread to handle the Mission Sequencer logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Mission Handle");
+        if self.steps.is_empty() {
+            log::error!("No Mission Steps.");
+            let _ = base::halt(state, device, tx);
+            return;
+        }
+
+        // Prepare the step.
+        if self.current.is_none() {
+            match self.start_step(tx.clone(), &property.conf) {
+                Ok(current) => {
+                    log::info!(
+                        "Mission Step Started: {} / {} ({})",
+                        self.index + 1,
+                        self.steps.len(),
+                        self.steps[self.index].mode
+                    );
+                    self.current = Some(current);
+                }
+                Err(e) => {
+                    log::error!("Can't Start Mission Step {}: {}", self.index + 1, e);
+                    self.abort();
+                    let _ = base::halt(state, device, tx);
+                    return;
+                }
+            }
+        }
+        let Some(current) = self.current.as_mut() else {
+            return;
+        };
+
+        // Drive the step with its own progress.
+        let was_on = state.state;
+        state.rest = current.rest;
+        let mut property = property;
+        property.conf = current.conf.clone();
+        current
+            .handler
+            .handle(state, device, detections, tx.clone(), property);
+        current.rest = state.rest;
+
+        let step = &self.steps[self.index];
+        let timed_out = step.until == "time"
+            && Duration::from_secs(step.minutes * 60) <= current.started.elapsed();
+        // Only messages of this call count, not the ones left from before a pause.
+        let stopped = was_on && !state.state;
+        let completed =
+            (stopped && state.msg == ChildMsg::to_u8(ChildMsg::MissionComplete)) || timed_out;
+        state.rest = progress(self.index, self.steps.len(), current.rest);
+
+        if completed {
+            log::info!(
+                "Mission Step Completed: {} / {}",
+                self.index + 1,
+                self.steps.len()
+            );
+            self.index += 1;
+            self.current = None;
+            if self.steps.len() <= self.index {
+                self.index = 0;
+                state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                let _ = base::mission_complete(state, device);
+                state.rest = 0.0;
+                return;
+            }
+            // Go on to the next step from a clean state.
+            state.reset();
+            state.state = true;
+            state.rest = progress(self.index, self.steps.len(), 1.0);
+            tx.send(VisionMgmtCommand::On).unwrap();
+        } else if stopped && state.msg == ChildMsg::to_u8(ChildMsg::TargetNotFound) {
+            self.abort();
+        }
+        log::debug!("End Mission Handle");
+    }
+
+    fn pause(&mut self, device: &mut Roktrack) {
+        self.paused_at = Some(Instant::now());
+        if let Some(current) = self.current.as_mut() {
+            current.handler.pause(device);
+        }
+    }
+
+    /// The time of the step is counted without the pause.
+    fn resume(&mut self, device: &mut Roktrack) {
+        if let Some(current) = self.current.as_mut() {
+            if let Some(paused_at) = self.paused_at.take() {
+                current.started += paused_at.elapsed();
+            }
+            current.handler.resume(device);
+        }
+    }
+}
+
+/// Remaining work of the whole mission (1.0 -> 0.0) from the remaining work of the current step.
+pub fn progress(index: usize, steps: usize, step_rest: f32) -> f32 {
+    if steps == 0 {
+        0.0
+    } else {
+        1.0 - (index as f32 + 1.0 - step_rest.clamp(0.0, 1.0)) / steps as f32
+    }
+}
+
+/// Applies the overrides of a step to the configuration.
+pub fn override_conf(
+    conf: &Config,
+    overrides: &toml::Table,
+) -> Result<Config, Box<dyn std::error::Error>> {
+    let mut value = toml::Value::try_from(conf)?;
+    merge(&mut value, overrides);
+    Ok(value.try_into()?)
+}
+
+/// Merges the tables recursively.
+fn merge(base: &mut toml::Value, overrides: &toml::Table) {
+    if let toml::Value::Table(table) = base {
+        for (key, value) in overrides {
+            match (table.get_mut(key), value) {
+                (Some(base @ toml::Value::Table(_)), toml::Value::Table(value)) => {
+                    merge(base, value)
+                }
+                _ => {
+                    table.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::util::conf;
+
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn progress_test() {
+        assert_eq!(progress(0, 2, 1.0), 1.0);
+        assert_eq!(progress(0, 2, 0.0), 0.5);
+        assert_eq!(progress(1, 2, 0.5), 0.25);
+        assert_eq!(progress(1, 2, -0.2), 0.0);
+        assert_eq!(progress(0, 0, 1.0), 0.0);
+    }
+
+    #[test]
+    fn override_conf_test() {
+        fs::create_dir_all(Path::new("/tmp/roktracktest/")).unwrap();
+        let base = conf::toml::load("/tmp/roktracktest/").unwrap();
+        let overrides: toml::Table = toml::from_str("spiral = { laps = 2 }").unwrap();
+        let conf = override_conf(&base, &overrides).unwrap();
+        assert_eq!(conf.spiral.laps, 2);
+        assert_eq!(conf.spiral.pitch, base.spiral.pitch);
+        assert_eq!(conf.system.lang, base.system.lang);
+
+        let invalid: toml::Table = toml::from_str("spiral = { laps = 'many' }").unwrap();
+        assert!(override_conf(&base, &invalid).is_err());
+    }
+}
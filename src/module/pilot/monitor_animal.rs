@@ -1,25 +1,48 @@
 //! Monitoring Animal Pilot
+//!
+//! Each species gets its own response, configured in the `[monitor_animal]` section:
+//! notify only, play a sound, flash the light, or chase it like the animal deterrent mode.
+//! All responses are notified.
+//! The pets of the house, enrolled in the `[pets]` section, are neither responded to nor notified.
 
 use std::sync::mpsc::Sender;
 
-use super::PilotHandler;
+use super::{
+    animal_deterrent::{self, AnimalDeterrent},
+    risk::{self, RiskEngine, SystemRisk},
+    PilotHandler,
+};
 use crate::module::{
-    device::Roktrack,
-    pilot::base,
+    device::{speaker, Roktrack},
     pilot::RoktrackState,
-    util::{common::send_line_notify_with_image, init::RoktrackProperty},
+    util::{
+        common::send_line_notify_with_image, conf::MonitorAnimal as MonitorAnimalConf,
+        init::RoktrackProperty,
+    },
     vision::detector::{AnimalClasses, Detection},
-    vision::VisionMgmtCommand,
+    vision::{pet::Pets, VisionMgmtCommand},
 };
 
+// Number of flashes of the light.
+const FLASH_TIMES: u8 = 3;
+
 pub struct MonitorAnimal {
     last_detected_time: u64,
+    deterrent: Option<(AnimalDeterrent, String)>, // Chasing, with the species
+    pets: Option<Pets>,                           // Pets enrolled at the first call
+    risks: RiskEngine,                            // System risks to check before driving
 }
 
 impl MonitorAnimal {
     pub fn new() -> Self {
         Self {
             last_detected_time: 0,
+            deterrent: None,
+            pets: None,
+            // Standing still, no sign to turn back from
+            risks: risk::builtin()
+                .without(SystemRisk::Bumped)
+                .without(SystemRisk::NoGo),
         }
     }
 }
@@ -37,62 +60,175 @@ impl PilotHandler for MonitorAnimal {
         state: &mut RoktrackState,
         device: &mut Roktrack,
         detections: &mut [Detection],
-        _tx: Sender<VisionMgmtCommand>,
+        tx: Sender<VisionMgmtCommand>,
         property: RoktrackProperty,
     ) {
         log::debug!("Start MonitorAnimal Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) | Some(SystemRisk::HighTemp) => Some(base::stop(device)),
-            None => None,
-        };
+        let system_risk = self.risks.handle(state, device, &property);
         if system_risk.is_some() {
             log::debug!("System Risk Exists. Continue.");
             return; // Risk exists, continue
         }
 
+        // The pets are left alone.
+        let mut detections = animal_deterrent::wild(&mut self.pets, detections, state, &property);
+
+        // Chase the animal until back to the post.
+        if let Some((deterrent, species)) = self.deterrent.as_mut() {
+            let mut property = property;
+            property.conf.deterrent.species = vec![species.clone()];
+            deterrent.handle(state, device, &mut detections, tx, property);
+            if deterrent.is_at_post() {
+                log::info!("Deterrent Finished. Monitor Again.");
+                self.deterrent = None;
+            }
+            return;
+        }
+
         // Check animal exist
         if !detections.is_empty() {
             log::warn!("Animal Detected!!");
-            device
-                .inner
-                .clone()
-                .lock()
-                .unwrap()
-                .speak("animal_detecting");
+            let cls = detections.first().unwrap().cls;
+            match response(cls, &property.conf.monitor_animal) {
+                Response::Notify => device
+                    .inner
+                    .clone()
+                    .lock()
+                    .unwrap()
+                    .speak("animal_detecting"),
+                Response::Sound(sound) => {
+                    if let Err(e) = speaker::play(&sound) {
+                        log::error!("Can't Play Sound: {}, {}", sound, e);
+                    }
+                }
+                Response::Light => {
+                    device
+                        .inner
+                        .clone()
+                        .lock()
+                        .unwrap()
+                        .speak("animal_detecting");
+                    device
+                        .inner
+                        .clone()
+                        .lock()
+                        .unwrap()
+                        .light
+                        .flash(FLASH_TIMES);
+                }
+                Response::Deterrent => {
+                    if let Some(animal) = AnimalClasses::from_u32(cls) {
+                        let species = format!("{:?}", animal).to_lowercase();
+                        log::info!("Start Deterring: {}", species);
+                        self.deterrent = Some((AnimalDeterrent::new(), species));
+                    }
+                }
+            }
             // Get now.
             let utc = chrono::Utc::now();
             if self.last_detected_time + 60000 < utc.timestamp_millis() as u64 {
                 log::debug!("Interval time has elapsed. Re-detection is notified.");
                 self.last_detected_time = utc.timestamp_millis() as u64;
-                let msg = format!(
-                    "{:?} detected.",
-                    AnimalClasses::from_u32(detections.first().unwrap().cls)
-                        .expect("Unknown animal.")
-                );
+                // The warm bodies of the thermal camera are of unknown species.
+                let msg = match AnimalClasses::from_u32(detections.first().unwrap().cls) {
+                    Some(animal) => format!("{:?} detected.", animal),
+                    None => "Animal detected by its warmth.".to_string(),
+                };
                 let _ = send_line_notify_with_image(&msg, &property.path.img.last, property.conf);
             }
         }
         log::debug!("End MonitorAnimal Handle");
     }
+
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some((deterrent, _)) = self.deterrent.as_mut() {
+            deterrent.pause(device);
+        }
+    }
+
+    fn resume(&mut self, device: &mut Roktrack) {
+        if let Some((deterrent, _)) = self.deterrent.as_mut() {
+            deterrent.resume(device);
+        }
+    }
+}
+
+/// Responses to animals.
+#[derive(Debug, Clone, PartialEq)]
+enum Response {
+    Notify,
+    Sound(String),
+    Light,
+    Deterrent,
 }
 
-/// System Risks
-///
-#[derive(Debug, Clone)]
-enum SystemRisk {
-    StateOff,
-    HighTemp,
+/// Response configured for the species of the class.
+fn response(cls: u32, conf: &MonitorAnimalConf) -> Response {
+    let name = AnimalClasses::from_u32(cls)
+        .map(|animal| format!("{:?}", animal).to_lowercase())
+        .unwrap_or_default();
+    let (action, sound) = conf
+        .responses
+        .iter()
+        .find(|r| r.species.to_lowercase() == name)
+        .map(|r| (r.action.as_str(), r.sound.clone()))
+        .unwrap_or((conf.default_action.as_str(), String::new()));
+    match action {
+        "notify" => Response::Notify,
+        "sound" if !sound.is_empty() => Response::Sound(sound),
+        "light" => Response::Light,
+        "deterrent" => Response::Deterrent,
+        _ => {
+            log::warn!("Invalid Response to {}: {}", name, action);
+            Response::Notify
+        }
+    }
 }
-/// Identify system-related risks
-///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
-    if !state.state {
-        Some(SystemRisk::StateOff)
-    } else if state.pi_temp > 70.0 {
-        device.inner.clone().lock().unwrap().speak("high_temp");
-        Some(SystemRisk::HighTemp)
-    } else {
-        None
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::module::util::conf::AnimalResponse;
+
+    #[test]
+    fn response_test() {
+        let conf = MonitorAnimalConf {
+            default_action: "light".to_string(),
+            responses: vec![
+                AnimalResponse {
+                    species: "Deer".to_string(),
+                    action: "deterrent".to_string(),
+                    sound: String::new(),
+                },
+                AnimalResponse {
+                    species: "cat".to_string(),
+                    action: "sound".to_string(),
+                    sound: "cat.mp3".to_string(),
+                },
+                AnimalResponse {
+                    species: "boar".to_string(),
+                    action: "sound".to_string(),
+                    sound: String::new(),
+                },
+            ],
+        };
+        assert_eq!(
+            response(AnimalClasses::DEER.to_u32(), &conf),
+            Response::Deterrent
+        );
+        assert_eq!(
+            response(AnimalClasses::CAT.to_u32(), &conf),
+            Response::Sound("cat.mp3".to_string())
+        );
+        // A sound without the file is only notified.
+        assert_eq!(
+            response(AnimalClasses::BOAR.to_u32(), &conf),
+            Response::Notify
+        );
+        assert_eq!(
+            response(AnimalClasses::FOX.to_u32(), &conf),
+            Response::Light
+        );
     }
 }
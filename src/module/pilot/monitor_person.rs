@@ -1,27 +1,84 @@
 //! Monitoring Person Pilot
+//!
+//! The interval between notifications, the daily quiet hours and the spoken warning
+//! are configured in the `[monitor_person]` section.
+//! Persons standing in the ignore zones (e.g. the public sidewalk) are not alerted.
+//! With the re-identification, a person is alerted once per visit, not again while lingering,
+//! e.g. the gardener at work (see `vision::reid`).
+//! With the pose estimation, a person lying on the ground for a while, e.g. an elderly relative
+//! fallen in the garden, is alerted as an emergency, even in the quiet hours and within the cooldown.
 
 use std::sync::mpsc::Sender;
 
-use super::PilotHandler;
+use chrono::NaiveTime;
+
+use super::{
+    risk::{self, RiskEngine, SystemRisk},
+    PilotHandler,
+};
 use crate::module::{
     device::Roktrack,
-    pilot::base,
     pilot::RoktrackState,
     util::{common::send_line_notify_with_image, init::RoktrackProperty},
-    vision::detector::{Detection, FilterClass, RoktrackClasses},
-    vision::VisionMgmtCommand,
+    vision::detector::{pose::FALLEN_CLASS, Detection, FilterClass, RoktrackClasses},
+    vision::{reid, VisionMgmtCommand},
 };
 
+// Time in ms the person lying may be missed for, keeping the fall.
+const FALLEN_GRACE_MS: u64 = 3000;
+
 pub struct MonitorPerson {
     last_detected_time: u64,
+    visitors: reid::Gallery, // Persons seen lately, by the appearance
+    fall: FallWatch,         // Person lying on the ground
+    risks: RiskEngine,       // System risks to check before driving
 }
 
 impl MonitorPerson {
     pub fn new() -> Self {
         Self {
             last_detected_time: 0,
+            visitors: reid::Gallery::default(),
+            fall: FallWatch::default(),
+            // Standing still, no sign to turn back from
+            risks: risk::builtin()
+                .without(SystemRisk::Bumped)
+                .without(SystemRisk::NoGo),
         }
     }
+
+    /// Whether any of the persons is a new visit, by the appearance in the last frame.
+    /// None if it can't be told, e.g. all too small.
+    fn new_visit(
+        &mut self,
+        persons: &[Detection],
+        state: &RoktrackState,
+        property: &RoktrackProperty,
+        now: u64,
+    ) -> Option<bool> {
+        let img = match image::open(&property.path.img.last) {
+            Ok(img) => img.to_rgb8(),
+            Err(e) => {
+                log::warn!("Can't Read Frame For Re-identification: {}", e);
+                return None;
+            }
+        };
+        // The detections are of the square input of the model, as wide as the frame.
+        let embeddings: Vec<reid::Embedding> = persons
+            .iter()
+            .filter_map(|det| reid::embed(&img, det, state.img_width))
+            .collect();
+        if embeddings.is_empty() {
+            return None;
+        }
+        // Every person is observed, to keep each visit going.
+        let conf = &property.conf.monitor_person;
+        Some(embeddings.into_iter().fold(false, |new, embedding| {
+            self.visitors
+                .observe(embedding, now, conf.reid_similarity, conf.visit_gap)
+                || new
+        }))
+    }
 }
 
 impl Default for MonitorPerson {
@@ -42,28 +99,73 @@ impl PilotHandler for MonitorPerson {
     ) {
         log::debug!("Start MonitorPerson Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) | Some(SystemRisk::HighTemp) => Some(base::stop(device)),
-            None => None,
-        };
+        let system_risk = self.risks.handle(state, device, &property);
         if system_risk.is_some() {
             log::debug!("System Risk Exists. Continue.");
             return; // Risk exists, continue
         }
 
+        // A person lying on the ground is alerted before all.
+        let conf = &property.conf.monitor_person;
+        if conf.fallen {
+            let fallen = detections
+                .iter()
+                .any(|det| det.cls == FALLEN_CLASS && !is_ignored(det, state, &conf.ignore_zones));
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            if self
+                .fall
+                .update(fallen, now, conf.fallen_time * 1000, conf.cooldown * 1000)
+            {
+                log::error!("Person Fallen!!");
+                let _ = send_line_notify_with_image(
+                    "EMERGENCY: A person is lying on the ground.",
+                    &property.path.img.last,
+                    property.conf.clone(),
+                );
+                return;
+            }
+        }
+
         // Check prtson exist
-        if !RoktrackClasses::filter(detections, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        let persons = RoktrackClasses::filter(detections, RoktrackClasses::PERSON.to_u32());
+        let persons: Vec<Detection> = persons
+            .into_iter()
+            .filter(|det| !is_ignored(det, state, &conf.ignore_zones))
+            .collect();
+        if !persons.is_empty() {
             log::warn!("Person Detected!!");
-            device
-                .inner
-                .clone()
-                .lock()
-                .unwrap()
-                .speak("person_detecting_warn");
             // Get now.
             let utc = chrono::Utc::now();
-            if self.last_detected_time + 60000 < utc.timestamp_millis() as u64 {
-                log::debug!("Interval time has elapsed. Re-detection is notified.");
+            // Tell the visits apart even in the quiet hours, not to alert those lingering after.
+            let new_visit = match conf.reid {
+                true => self.new_visit(&persons, state, &property, utc.timestamp_millis() as u64),
+                false => None,
+            };
+            if new_visit == Some(false) {
+                log::debug!("Same visit. Not notified.");
+                return;
+            }
+            if in_quiet_hours(
+                chrono::Local::now().time(),
+                &conf.quiet_start,
+                &conf.quiet_end,
+            ) {
+                log::debug!("Quiet hours. Not notified.");
+                return;
+            }
+            if conf.speak {
+                device
+                    .inner
+                    .clone()
+                    .lock()
+                    .unwrap()
+                    .speak("person_detecting_warn");
+            }
+            // A new visit is notified right away, otherwise once per cooldown.
+            if new_visit == Some(true)
+                || self.last_detected_time + conf.cooldown * 1000 < utc.timestamp_millis() as u64
+            {
+                log::debug!("New visit or interval time has elapsed. Re-detection is notified.");
                 self.last_detected_time = utc.timestamp_millis() as u64;
                 let _ = send_line_notify_with_image(
                     "Person detected.",
@@ -76,22 +178,134 @@ impl PilotHandler for MonitorPerson {
     }
 }
 
-/// System Risks
-///
-#[derive(Debug, Clone)]
-enum SystemRisk {
-    StateOff,
-    HighTemp,
+/// Watch of a person lying on the ground
+#[derive(Debug, Default)]
+struct FallWatch {
+    since: Option<u64>,   // Time lying since in ms
+    last_seen: u64,       // Time seen lying last in ms
+    alerted: Option<u64>, // Time alerted last in ms
+}
+
+impl FallWatch {
+    /// Updates with whether anyone is lying at the time in ms, and returns whether to alert: once
+    /// lying for `hold` ms, and again every `repeat` ms while still lying.
+    fn update(&mut self, fallen: bool, now: u64, hold: u64, repeat: u64) -> bool {
+        if fallen {
+            self.since.get_or_insert(now);
+            self.last_seen = now;
+        } else if self.last_seen + FALLEN_GRACE_MS < now {
+            // Up again, or gone
+            self.since = None;
+            self.alerted = None;
+        }
+        match (fallen, self.since, self.alerted) {
+            (true, Some(since), _) if since + hold <= now => {}
+            _ => return false,
+        }
+        if self.alerted.is_some_and(|alerted| now < alerted + repeat) {
+            return false;
+        }
+        self.alerted = Some(now);
+        true
+    }
+}
+
+/// Whether the person stands in an ignore zone, judged by the foot of the bounding box.
+fn is_ignored(det: &Detection, state: &RoktrackState, zones: &[Vec<[f32; 2]>]) -> bool {
+    let foot = [
+        det.xc / state.img_width as f32,
+        det.y2 as f32 / state.img_height as f32,
+    ];
+    zones.iter().any(|zone| contains(zone, foot))
+}
+
+/// Whether the polygon contains the point (ray casting).
+fn contains(polygon: &[[f32; 2]], point: [f32; 2]) -> bool {
+    let mut inside = false;
+    let mut j = polygon.len().wrapping_sub(1);
+    for i in 0..polygon.len() {
+        let ([xi, yi], [xj, yj]) = (polygon[i], polygon[j]);
+        if (yi > point[1]) != (yj > point[1])
+            && point[0] < (xj - xi) * (point[1] - yi) / (yj - yi) + xi
+        {
+            inside = !inside;
+        }
+        j = i;
+    }
+    inside
 }
-/// Identify system-related risks
+
+/// Whether the time is within the daily quiet hours, which may span midnight.
 ///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
-    if !state.state {
-        Some(SystemRisk::StateOff)
-    } else if state.pi_temp > 70.0 {
-        device.inner.clone().lock().unwrap().speak("high_temp");
-        Some(SystemRisk::HighTemp)
+/// Empty or invalid times mean no quiet hours.
+pub fn in_quiet_hours(now: NaiveTime, start: &str, end: &str) -> bool {
+    let (Ok(start), Ok(end)) = (
+        NaiveTime::parse_from_str(start, "%H:%M"),
+        NaiveTime::parse_from_str(end, "%H:%M"),
+    ) else {
+        return false;
+    };
+    if start <= end {
+        start <= now && now < end
     } else {
-        None
+        start <= now || now < end
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ignore_zone_test() {
+        let sidewalk = vec![[0.0, 0.0], [1.0, 0.0], [1.0, 0.2], [0.0, 0.2]];
+        let triangle = vec![[0.5, 0.5], [1.0, 1.0], [0.0, 1.0]];
+        assert!(contains(&sidewalk, [0.5, 0.1]));
+        assert!(!contains(&sidewalk, [0.5, 0.3]));
+        assert!(contains(&triangle, [0.5, 0.9]));
+        assert!(!contains(&triangle, [0.1, 0.6]));
+        assert!(!contains(&[], [0.5, 0.5]));
+        // Judged by the foot.
+        let state = RoktrackState::new();
+        let person = Detection {
+            xc: 160.0,
+            y2: 40,
+            ..Default::default()
+        };
+        assert!(is_ignored(&person, &state, &[sidewalk.clone()]));
+        let person = Detection { y2: 200, ..person };
+        assert!(!is_ignored(&person, &state, &[sidewalk]));
+    }
+
+    #[test]
+    fn fall_watch_test() {
+        let mut fall = FallWatch::default();
+        assert!(!fall.update(false, 0, 10_000, 60_000));
+        // Alerted once lying for the hold, through a frame missed
+        assert!(!fall.update(true, 1_000, 10_000, 60_000));
+        assert!(!fall.update(false, 2_000, 10_000, 60_000));
+        assert!(!fall.update(true, 3_000, 10_000, 60_000));
+        assert!(fall.update(true, 11_000, 10_000, 60_000));
+        // Again after the repeat while still lying
+        assert!(!fall.update(true, 30_000, 10_000, 60_000));
+        assert!(fall.update(true, 71_000, 10_000, 60_000));
+        // Up again, and lying anew
+        assert!(!fall.update(false, 80_000, 10_000, 60_000));
+        assert!(!fall.update(true, 81_000, 10_000, 60_000));
+        assert!(fall.update(true, 91_000, 10_000, 60_000));
+    }
+
+    #[test]
+    fn quiet_hours_test() {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        assert!(!in_quiet_hours(time(23, 0), "", ""));
+        assert!(!in_quiet_hours(time(23, 0), "22:00", "late"));
+        // Within a day
+        assert!(in_quiet_hours(time(12, 30), "12:00", "13:00"));
+        assert!(!in_quiet_hours(time(13, 0), "12:00", "13:00"));
+        // Over midnight
+        assert!(in_quiet_hours(time(23, 0), "22:00", "06:00"));
+        assert!(in_quiet_hours(time(5, 59), "22:00", "06:00"));
+        assert!(!in_quiet_hours(time(12, 0), "22:00", "06:00"));
     }
 }
@@ -0,0 +1,75 @@
+//! Night Operation Profile
+//!
+//! During the configured hours, the robot drives slower, turns the light on as the headlight,
+//! speaks quieter and reacts to people farther away. The pilots honor it without knowing it,
+//! since the limits are applied to the device and the person detected policy.
+//! With `ir`, the camera sees by the IR, which the detector takes in grayscale, so that the people
+//! are still detected in the dark, e.g. by the person monitoring.
+
+use chrono::NaiveTime;
+
+use super::monitor_person::in_quiet_hours;
+use crate::module::{
+    device::Roktrack,
+    util::conf::{Night, Person},
+};
+
+/// Whether the time is within the night hours. Empty or invalid times mean no night profile.
+pub fn is_night(now: NaiveTime, conf: &Night) -> bool {
+    in_quiet_hours(now, &conf.start, &conf.end)
+}
+
+/// Applies the profile of the day or the night to the device.
+pub fn apply(device: &mut Roktrack, conf: &Night, night: bool) {
+    let binding = device.inner.clone();
+    let mut device_lock = binding.lock().unwrap();
+    match night {
+        true => {
+            device_lock.speed_limit = conf.speed;
+            device_lock.volume = conf.volume;
+            device_lock.light.set(conf.headlight);
+            device_lock.night_vision.set(conf.ir);
+        }
+        false => {
+            device_lock.speed_limit = 1.0;
+            device_lock.volume = 1.0;
+            device_lock.light.set(false);
+            device_lock.night_vision.set(false);
+        }
+    }
+}
+
+/// Height of a person (ratio to the image height) to react to. The stricter one at night.
+pub fn stop_height(person: &Person, conf: &Night, night: bool) -> f32 {
+    match night {
+        true => person.stop_height.min(conf.stop_height),
+        false => person.stop_height,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn night_test() {
+        let time = |h, m| NaiveTime::from_hms_opt(h, m, 0).unwrap();
+        let mut conf = Night::default();
+        assert!(!is_night(time(23, 0), &conf));
+        conf.start = "19:00".to_string();
+        conf.end = "06:00".to_string();
+        assert!(is_night(time(23, 0), &conf));
+        assert!(!is_night(time(12, 0), &conf));
+
+        let person = Person {
+            stop_height: 0.3,
+            ..Default::default()
+        };
+        conf.stop_height = 0.1;
+        assert_eq!(stop_height(&person, &conf, false), 0.3);
+        assert_eq!(stop_height(&person, &conf, true), 0.1);
+        // Never looser at night
+        conf.stop_height = 0.5;
+        assert_eq!(stop_height(&person, &conf, true), 0.3);
+    }
+}
@@ -1,23 +1,43 @@
 //! OneWay Drive Pilot
+//!
+//! The turn geometry is configurable, because the grip differs between wet grass and concrete.
+//! Each turning step pivots for `pivot_ms`, followed by a short forward move when `radius` is set.
+//! When the next marker is found, the overshoot is compensated by `overshoot_ms`.
 
+use std::f32::consts::FRAC_PI_2;
 use std::sync::mpsc::Sender;
 
-use super::PilotHandler;
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    lost::LossMonitor,
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
 use crate::module::{
     device::motor::Motor,
     device::Roktrack,
     pilot::base,
     pilot::{Phase, RoktrackState},
+    util::conf::{Motion, OneWay as OneWayConf},
     util::init::RoktrackProperty,
     vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
     vision::VisionMgmtCommand,
 };
 
-pub struct OneWay {}
+pub struct OneWay {
+    plan: Option<ManeuverPlan>, // Moves to finish before looking again
+    lost: LossMonitor,          // Frames without the tracked marker
+    risks: RiskEngine,          // System risks to check before driving
+}
 
 impl OneWay {
     pub fn new() -> Self {
-        Self {}
+        Self {
+            plan: None,
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
+        }
     }
 }
 
@@ -35,15 +55,11 @@ impl PilotHandler for OneWay {
         device: &mut Roktrack,
         detections: &mut [Detection],
         tx: Sender<VisionMgmtCommand>,
-        _property: RoktrackProperty,
+        property: RoktrackProperty,
     ) {
         log::debug!("Start OneWay Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) | Some(SystemRisk::HighTemp) => Some(base::stop(device)),
-            Some(SystemRisk::Bumped) => Some(base::escape(state, device)),
-            None => None,
-        };
+        let system_risk = self.risks.handle(state, device, &property);
         if system_risk.is_some() {
             log::debug!("System Risk Exists. Continue.");
             return; // Risk exists, continue
@@ -51,9 +67,10 @@ impl PilotHandler for OneWay {
 
         // Assess and handle vision safety
         let vision_risk = match assess_vision_risk(detections, device) {
-            Some(VisionRisk::PersonDetected) | Some(VisionRisk::RoktrackDetected) => {
-                Some(base::stop(device))
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
             }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
             None => None,
         };
         if vision_risk.is_some() {
@@ -61,6 +78,20 @@ impl PilotHandler for OneWay {
             return; // Risk exists, continue
         }
 
+        // Finish the arc or the compensation of the turn.
+        if let Some(plan) = self.plan.as_mut() {
+            if !maneuver::is_idle(device) {
+                return;
+            }
+            match plan.next_step() {
+                Some(step) => {
+                    maneuver::start(device, step);
+                    return;
+                }
+                None => self.plan = None,
+            }
+        }
+
         // Sort markers based on the current phase
         let detections = match state.turn_count {
             1 => sort::small(detections),
@@ -74,6 +105,22 @@ impl PilotHandler for OneWay {
         let marker = detections.first().cloned().unwrap_or_default();
         log::debug!("Marker Selected: {:?}", marker);
 
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
         // Turn on the work motor
         device.inner.clone().lock().unwrap().work_motor.cw();
 
@@ -92,33 +139,67 @@ impl PilotHandler for OneWay {
             Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
             None => Ok(()),
         };
+
+        // Apply the turn geometry.
+        let conf = &property.conf.oneway;
+        match action {
+            Some(ActPhase::TurnMarkerInvisible)
+            | Some(ActPhase::TurnKeep)
+            | Some(ActPhase::StartTurn)
+            | Some(ActPhase::ReachMarker) => {
+                maneuver::start(device, pivot(&state.phase, conf.pivot_ms));
+                let arc = arc_time(conf, &property.conf.motion);
+                if 0 < arc {
+                    self.plan = Some(ManeuverPlan::new(vec![Maneuver::Forward(arc)]));
+                }
+            }
+            Some(ActPhase::TurnMarkerFound) => {
+                if let Some(step) = compensation(&state.phase, conf.overshoot_ms) {
+                    maneuver::start(device, step);
+                    // Wait for the compensation before proceeding.
+                    self.plan = Some(ManeuverPlan::new(vec![]));
+                }
+            }
+            _ => {}
+        }
         log::debug!("End OneWay Handle");
     }
+
+    /// Keep the rest of the move cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some(plan) = self.plan.as_mut() {
+            plan.interrupt(maneuver::remaining(device));
+        }
+    }
 }
 
-/// System Risks
-///
-#[derive(Debug, Clone)]
-enum SystemRisk {
-    StateOff,
-    HighTemp,
-    Bumped,
+/// Turning step in the direction of the laps.
+fn pivot(phase: &Phase, ms: u64) -> Maneuver {
+    match phase {
+        Phase::CCW => Maneuver::Left(ms),
+        Phase::CW => Maneuver::Right(ms),
+    }
 }
-/// Identify system-related risks
-///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
-    if !state.state {
-        Some(SystemRisk::StateOff)
-    } else if state.pi_temp > 70.0 {
-        device.inner.clone().lock().unwrap().speak("high_temp");
-        Some(SystemRisk::HighTemp)
-    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
-        device.inner.clone().lock().unwrap().speak("bumped");
-        Some(SystemRisk::Bumped)
-    } else {
-        None
+
+/// Forward time after each turning step, so that the turn follows the radius.
+fn arc_time(conf: &OneWayConf, motion: &Motion) -> u64 {
+    if motion.quarter_turn_ms == 0 {
+        return 0;
     }
+    let angle = FRAC_PI_2 * conf.pivot_ms as f32 / motion.quarter_turn_ms as f32;
+    maneuver::travel_time(conf.radius * angle, motion.speed)
 }
+
+/// Move to compensate the overshoot of the turn when the next marker is found.
+fn compensation(phase: &Phase, overshoot_ms: i64) -> Option<Maneuver> {
+    let ms = overshoot_ms.unsigned_abs();
+    match overshoot_ms {
+        0 => None,
+        i if 0 < i => Some(pivot(phase, ms).inverse()),
+        _ => Some(pivot(phase, ms)),
+    }
+}
+
 /// Vision-related risks
 ///
 #[derive(Debug, Clone)]
@@ -182,3 +263,26 @@ fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhas
         Some(ActPhase::Proceed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn turn_geometry_test() {
+        let motion = Motion {
+            speed: 0.5,
+            quarter_turn_ms: 1000,
+        };
+        let mut conf = OneWayConf::default();
+        assert_eq!(pivot(&Phase::CCW, conf.pivot_ms), Maneuver::Left(500));
+        assert_eq!(arc_time(&conf, &motion), 0);
+        // A quarter of the circle every two steps.
+        conf.radius = 1.0;
+        assert_eq!(arc_time(&conf, &motion), 1570);
+        // Overshoot
+        assert_eq!(compensation(&Phase::CCW, 0), None);
+        assert_eq!(compensation(&Phase::CCW, 200), Some(Maneuver::Right(200)));
+        assert_eq!(compensation(&Phase::CW, -100), Some(Maneuver::Right(100)));
+    }
+}
@@ -0,0 +1,307 @@
+//! Orchard Lane Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// Proceed * n  <- Keep the center between the two rows in the middle of the image.
+//    |
+// SteerLeft / SteerRight  <- The center has drifted.
+//    |
+// Pass * n  <- No row in sight. Keep going to clear the last trees.
+//    |
+// LaneEnd  <- U-turn into the next lane, offset by the row spacing.
+//    |
+// MissionComplete  <- After the configured lanes, or no row found after a U-turn.
+//
+// The rows are markers or tree trunks (the class is configured), split by the side of the image.
+// The nearest one of each side is used. With only one row in sight, the center is kept at
+// `row_offset` from it. The first U-turn follows the lap direction (left for CCW), then they alternate.
+
+use std::sync::mpsc::Sender;
+
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::conf::{Motion, Orchard as OrchardConf},
+    util::init::RoktrackProperty,
+    vision::detector::{Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Tolerated difference (ratio to the image width) before correcting the direction.
+const STEER_TOLERANCE: f32 = 0.05;
+
+// Duration of a correcting turn in ms.
+const TURN_STEP_MS: u64 = 100;
+
+// Number of frames without a row before the lane is over.
+const END_FRAMES: u8 = 10;
+
+pub struct Orchard {
+    plan: Option<ManeuverPlan>, // U-turn into the next lane
+    lost: u8,                   // Frames without a row
+    in_lane: bool,              // Whether a row has been seen since the last U-turn
+    lanes: u8,                  // Lanes done
+    risks: RiskEngine,          // System risks to check before driving
+}
+
+impl Orchard {
+    pub fn new() -> Self {
+        Self {
+            plan: None,
+            lost: 0,
+            in_lane: false,
+            lanes: 0,
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Orchard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Orchard {
+    /// Function called from a thread to handle the Orchard Lane Drive Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        _tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Orchard Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Finish the U-turn first.
+        if let Some(plan) = self.plan.as_mut() {
+            if !maneuver::is_idle(device) {
+                return;
+            }
+            match plan.next_step() {
+                Some(step) => {
+                    maneuver::start(device, step);
+                    return;
+                }
+                None => self.plan = None,
+            }
+        }
+
+        let conf = property.conf.orchard.clone();
+        let rows = RoktrackClasses::filter(detections, conf.class);
+        let center = lane_center(&rows, state.img_width, conf.row_offset);
+        log::debug!("Lane Center: {:?}", center);
+
+        let action = assess_situation(center, self.lost, self.in_lane);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        match action {
+            ActPhase::MissionComplete => self.complete(state, device),
+            ActPhase::LaneEnd => {
+                self.lanes = self.lanes.saturating_add(1);
+                self.lost = 0;
+                self.in_lane = false;
+                log::info!("Lane End. lanes: {}", self.lanes);
+                if 0 < conf.lanes {
+                    state.rest = 1.0 - self.lanes as f32 / conf.lanes as f32;
+                }
+                if 0 < conf.lanes && conf.lanes <= self.lanes {
+                    self.complete(state, device);
+                } else {
+                    device.inner.clone().lock().unwrap().pause();
+                    self.plan = Some(u_turn(
+                        &conf,
+                        &property.conf.motion,
+                        &state.phase,
+                        self.lanes,
+                    ));
+                }
+            }
+            ActPhase::Pass | ActPhase::SteerLeft | ActPhase::SteerRight | ActPhase::Proceed => {
+                if action == ActPhase::Pass {
+                    self.lost += 1;
+                } else {
+                    self.lost = 0;
+                    self.in_lane = true;
+                }
+                let binding = device.inner.clone();
+                let mut device_lock = binding.lock().unwrap();
+                device_lock.work_motor.cw();
+                match action {
+                    ActPhase::SteerLeft => device_lock.left(TURN_STEP_MS),
+                    ActPhase::SteerRight => device_lock.right(TURN_STEP_MS),
+                    _ => device_lock.forward(0),
+                }
+            }
+        }
+        log::debug!("End Orchard Handle");
+    }
+
+    /// Keep the rest of the U-turn cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some(plan) = self.plan.as_mut() {
+            plan.interrupt(maneuver::remaining(device));
+        }
+    }
+}
+
+impl Orchard {
+    /// Finishes the mission.
+    fn complete(&mut self, state: &mut RoktrackState, device: &mut Roktrack) {
+        log::info!("Orchard Complete. lanes: {}", self.lanes);
+        state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+        let _ = base::mission_complete(state, device);
+        *self = Self::new();
+    }
+}
+
+/// Center of the lane (ratio to the image width) between the nearest rows of each side.
+pub fn lane_center(rows: &[Detection], img_width: u32, row_offset: f32) -> Option<f32> {
+    let width = img_width.max(1) as f32;
+    let nearest = |left: bool| {
+        rows.iter()
+            .filter(|row| (row.xc / width < 0.5) == left)
+            .max_by_key(|row| row.h)
+            .map(|row| row.xc / width)
+    };
+    match (nearest(true), nearest(false)) {
+        (Some(left), Some(right)) => Some((left + right) / 2.0),
+        (Some(left), None) => Some(left + row_offset),
+        (None, Some(right)) => Some(right - row_offset),
+        (None, None) => None,
+    }
+}
+
+/// Plans the U-turn after the lanes done. U-turns alternate, starting in the lap direction.
+fn u_turn(conf: &OrchardConf, motion: &Motion, phase: &Phase, lanes: u8) -> ManeuverPlan {
+    let left = (*phase == Phase::CCW) == (lanes % 2 == 1);
+    let turn = match left {
+        true => Maneuver::Left(motion.quarter_turn_ms),
+        false => Maneuver::Right(motion.quarter_turn_ms),
+    };
+    let offset = Maneuver::Forward(maneuver::travel_time(conf.row_spacing, motion.speed));
+    ManeuverPlan::new(vec![turn, offset, turn])
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Orchard Lane Drive Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    MissionComplete,
+    LaneEnd,
+    Pass,
+    SteerLeft,
+    SteerRight,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+///
+/// Turning right moves the center to the left in the image.
+fn assess_situation(center: Option<f32>, lost: u8, in_lane: bool) -> ActPhase {
+    match center {
+        None if END_FRAMES <= lost + 1 && in_lane => ActPhase::LaneEnd,
+        None if END_FRAMES <= lost + 1 => ActPhase::MissionComplete,
+        None => ActPhase::Pass,
+        Some(x) if 0.5 + STEER_TOLERANCE < x => ActPhase::SteerRight,
+        Some(x) if x < 0.5 - STEER_TOLERANCE => ActPhase::SteerLeft,
+        Some(_) => ActPhase::Proceed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lane_test() {
+        let row = |xc: f32, h: u32| Detection {
+            xc,
+            h,
+            ..Default::default()
+        };
+        // The nearest rows of each side
+        let rows = [row(60.0, 100), row(120.0, 40), row(300.0, 90)];
+        assert!((lane_center(&rows, 320, 0.35).unwrap() - 0.5625).abs() < 1e-6);
+        assert!((lane_center(&rows[..1], 320, 0.35).unwrap() - 0.5375).abs() < 1e-6);
+        assert_eq!(lane_center(&[], 320, 0.35), None);
+
+        assert_eq!(assess_situation(Some(0.52), 0, true), ActPhase::Proceed);
+        assert_eq!(assess_situation(Some(0.7), 0, true), ActPhase::SteerRight);
+        assert_eq!(assess_situation(Some(0.3), 0, true), ActPhase::SteerLeft);
+        assert_eq!(assess_situation(None, 0, true), ActPhase::Pass);
+        assert_eq!(
+            assess_situation(None, END_FRAMES - 1, true),
+            ActPhase::LaneEnd
+        );
+        assert_eq!(
+            assess_situation(None, END_FRAMES - 1, false),
+            ActPhase::MissionComplete
+        );
+
+        let conf = OrchardConf::default();
+        let motion = Motion {
+            speed: 0.5,
+            quarter_turn_ms: 1000,
+        };
+        let mut plan = u_turn(&conf, &motion, &Phase::CCW, 1);
+        assert_eq!(plan.next_step(), Some(Maneuver::Left(1000)));
+        assert_eq!(plan.next_step(), Some(Maneuver::Forward(6000)));
+        let mut plan = u_turn(&conf, &motion, &Phase::CCW, 2);
+        assert_eq!(plan.next_step(), Some(Maneuver::Right(1000)));
+    }
+}
@@ -0,0 +1,186 @@
+//! Patrol Pilot
+//!
+
+// # Normal flow of act phase
+//
+// Wait  <- Until the interval has elapsed since the previous lap.
+//    |
+// Patrol * n  <- Drive the recorded route, watching for persons the whole time.
+//    |
+// Wait
+//
+// When a person is detected, the robot pauses and notifies, as MonitorPerson does.
+// The interrupted move is restarted once the person has gone.
+//
+// # Route file
+// One `<forward|backward|left|right> <ms>` per line. Empty lines and lines starting with `#` are skipped.
+// The route should end where it started, so that it can be looped.
+
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    risk::{self, Response, RiskEngine, SystemRisk},
+    PilotHandler,
+};
+use crate::module::{
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::RoktrackState,
+    util::{common::send_line_notify_with_image, init::RoktrackProperty},
+    vision::detector::{Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+pub struct Patrol {
+    route: Option<Vec<Maneuver>>,
+    plan: Option<ManeuverPlan>,
+    current: Option<Maneuver>, // Move in progress
+    interrupted: bool,
+    next_lap: Option<Instant>,
+    last_detected_time: u64,
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Patrol {
+    pub fn new() -> Self {
+        Self {
+            route: None,
+            plan: None,
+            current: None,
+            interrupted: false,
+            next_lap: None,
+            last_detected_time: 0,
+            risks: risk::builtin().respond_with(SystemRisk::Bumped, Response::Stop),
+        }
+    }
+}
+
+impl Default for Patrol {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Patrol {
+    /// Function called from a thread to handle the Patrol Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Patrol Handle");
+        // A move cut short by a risk or a person is restarted later.
+        let moving = !maneuver::is_idle(device);
+
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            self.interrupted |= moving;
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Check person exist
+        if !RoktrackClasses::filter(detections, RoktrackClasses::PERSON.to_u32()).is_empty() {
+            log::warn!("Person Detected!!");
+            device.inner.clone().lock().unwrap().pause();
+            self.interrupted |= moving;
+            device
+                .inner
+                .clone()
+                .lock()
+                .unwrap()
+                .speak("person_detecting_warn");
+            // Get now.
+            let utc = chrono::Utc::now();
+            if self.last_detected_time + 60000 < utc.timestamp_millis() as u64 {
+                log::debug!("Interval time has elapsed. Re-detection is notified.");
+                self.last_detected_time = utc.timestamp_millis() as u64;
+                let _ = send_line_notify_with_image(
+                    "Person detected on patrol.",
+                    &property.path.img.last,
+                    property.conf,
+                );
+            }
+            return;
+        }
+
+        // Load the route at the first call.
+        let conf = property.conf.patrol.clone();
+        if self.route.is_none() {
+            let path = Path::new(&property.path.dir.data).join(&conf.file);
+            match maneuver::load(&path) {
+                Ok(route) if !route.is_empty() => {
+                    log::info!("Patrol Route Loaded: {} ({})", route.len(), path.display());
+                    self.route = Some(route);
+                }
+                Ok(_) => {
+                    log::error!("No Patrol Route: {}", path.display());
+                    let _ = base::halt(state, device, tx);
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Can't Load Patrol Route: {}, {}", path.display(), e);
+                    let _ = base::halt(state, device, tx);
+                    return;
+                }
+            }
+        }
+
+        // Restart the interrupted move.
+        if self.interrupted {
+            self.interrupted = false;
+            if let Some(step) = self.current {
+                log::debug!("Patrol Resumed.");
+                maneuver::start(device, step);
+                return;
+            }
+        }
+
+        // Wait for the previous move.
+        if !maneuver::is_idle(device) {
+            return;
+        }
+
+        // The work motor is never used.
+        device.inner.clone().lock().unwrap().work_motor.stop();
+
+        match self.plan.as_mut() {
+            Some(plan) => match plan.next_step() {
+                Some(step) => {
+                    self.current = Some(step);
+                    maneuver::start(device, step);
+                    state.rest = plan.rest();
+                }
+                None => {
+                    log::info!("Patrol Lap Finished. Next in {} min.", conf.interval);
+                    self.plan = None;
+                    self.current = None;
+                    self.next_lap = Some(Instant::now() + Duration::from_secs(conf.interval * 60));
+                    device.inner.clone().lock().unwrap().pause();
+                }
+            },
+            None => {
+                if self.next_lap.is_some_and(|next| Instant::now() < next) {
+                    return;
+                }
+                log::info!("Patrol Lap Started.");
+                self.plan = Some(ManeuverPlan::new(self.route.clone().unwrap_or_default()));
+                state.rest = 1.0;
+            }
+        }
+        log::debug!("End Patrol Handle");
+    }
+
+    /// Restart the move cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        self.interrupted |= !maneuver::is_idle(device);
+    }
+}
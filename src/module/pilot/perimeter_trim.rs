@@ -0,0 +1,227 @@
+//! Perimeter Trim Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// Proceed * n
+//    |
+// ReachMarker  <- Closer to the marker than Fill, to trim along the boundary.
+//    |
+// TurnKeep
+//    |
+// TurnMarkerInvisible * n
+//    |
+// TurnMarkerFound
+//    |
+// Proceed * n
+//
+// # General flow
+//
+// Start
+//   | (laps along the markers, without shrinking to the inside)
+// MissionComplete  <- After `markers` * `laps` markers have been reached.
+
+use std::sync::mpsc::Sender;
+
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::Roktrack,
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+use super::{
+    base::select_marker,
+    lost::LossMonitor,
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+
+pub struct PerimeterTrim {
+    reached: u32,      // Number of markers reached
+    lost: LossMonitor, // Frames without the tracked marker
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl PerimeterTrim {
+    pub fn new() -> Self {
+        Self {
+            reached: 0,
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for PerimeterTrim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for PerimeterTrim {
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start PerimeterTrim Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Sort markers based on the current phase
+        let detections = match state.phase {
+            Phase::CCW => sort::right(detections),
+            Phase::CW => sort::left(detections),
+        };
+
+        let conf = property.conf.perimeter_trim.clone();
+        let total = conf.markers as u32 * conf.laps as u32;
+
+        // Get the first detected marker or a default one
+        let marker = select_marker(property.clone(), state, detections, device);
+        log::debug!("Marker Selected: {:?}", marker);
+
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
+        // Turn on the work motor
+        device.inner.clone().lock().unwrap().work_motor.cw();
+
+        let action = assess_situation(state, &marker, conf.approach);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnMarkerInvisible) => base::reset_ex_height(state, device),
+            Some(ActPhase::TurnMarkerFound) => {
+                // Keep the same distance to every marker instead of shrinking the laps.
+                state.msg = ChildMsg::to_u8(ChildMsg::NewTargetFound);
+                device.inner.clone().lock().unwrap().speak("new_cone_found");
+                state.target_height = (state.img_height as f32 * conf.approach) as u16;
+                state.turn_count = 0;
+                Ok(())
+            }
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::Stand) => base::stand(state, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) => {
+                self.reached += 1;
+                state.rest = 1.0 - self.reached as f32 / total.max(1) as f32;
+                log::debug!("Trim Marker Reached. {} / {}", self.reached, total);
+                if total <= self.reached {
+                    state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                    base::mission_complete(state, device)
+                } else {
+                    base::reach_marker(state, device, marker)
+                }
+            }
+            Some(ActPhase::Proceed) => base::proceed(state, device, marker, tx),
+            None => Ok(()),
+        };
+        log::debug!("End PerimeterTrim Handle");
+    }
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Perimeter Trim Drive Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnMarkerInvisible,
+    TurnMarkerFound,
+    TurnKeep,
+    Stand,
+    StartTurn,
+    ReachMarker,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection, approach: f32) -> Option<ActPhase> {
+    if 10 <= state.turn_count {
+        Some(ActPhase::TurnCountExceeded)
+    } else if 0 < state.turn_count {
+        if marker.h == 0 {
+            Some(ActPhase::TurnMarkerInvisible)
+        } else if (marker.h as f32) < state.ex_height as f32 - state.img_height as f32 * 0.015 {
+            Some(ActPhase::TurnMarkerFound)
+        } else {
+            Some(ActPhase::TurnKeep)
+        }
+    } else if marker.h == 0 {
+        if state.turn_count == -1 {
+            Some(ActPhase::Stand)
+        } else if state.turn_count == 0 {
+            Some(ActPhase::StartTurn)
+        } else {
+            None
+        }
+    } else if state.img_height as f32 * approach <= marker.h as f32 {
+        Some(ActPhase::ReachMarker)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
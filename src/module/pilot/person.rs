@@ -0,0 +1,140 @@
+//! Person Detected Policy
+//!
+//! The reaction to a person in sight depends on the deployment, so it's configured per mode
+//! and shared by the pilots instead of being baked into each of them.
+//!
+//! | policy | reaction                                                      |
+//! |--------|---------------------------------------------------------------|
+//! | pause  | Stop and wait for the person to go away                       |
+//! | slow   | Keep going below `slow_speed` while the person is in sight    |
+//! | detour | Back off and go around                                        |
+//! | warn   | Keep going, only speaking the warning                         |
+//!
+//! People farther than the stop distance (lower than `stop_height` in the image) are only warned of.
+//! The distance is stricter at night (see the night profile). Where the distance to a person is
+//! estimated, `stop_distance` in m is used instead if set.
+
+use super::{base, night, Modes, RoktrackState};
+use crate::module::{
+    device::Roktrack,
+    util::conf::Person,
+    util::init::RoktrackProperty,
+    vision::detector::{Detection, FilterClass, RoktrackClasses},
+};
+
+/// Reactions to a detected person.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PersonPolicy {
+    Pause,
+    Slow,
+    Detour,
+    Warn,
+}
+
+impl PersonPolicy {
+    /// Convert a string to a policy. Unknown ones are taken as the safest, pause.
+    pub fn from_string(s: &str) -> PersonPolicy {
+        match s {
+            "pause" => PersonPolicy::Pause,
+            "slow" => PersonPolicy::Slow,
+            "detour" => PersonPolicy::Detour,
+            "warn" => PersonPolicy::Warn,
+            _ => {
+                log::warn!("Invalid Person Policy: {}", s);
+                PersonPolicy::Pause
+            }
+        }
+    }
+}
+
+/// Policy of the mode, or the default one.
+pub fn policy(conf: &Person, mode: Modes) -> PersonPolicy {
+    let policy = conf
+        .modes
+        .iter()
+        .find(|(name, _)| Modes::from_string(name) == mode)
+        .map_or(&conf.policy, |(_, policy)| policy);
+    PersonPolicy::from_string(policy)
+}
+
+/// Whether a person is within the stop distance, i.e. as high as `stop_height` in the image,
+/// or within `stop_distance` in m if the distance is estimated.
+pub fn is_near(
+    dets: &mut [Detection],
+    img_height: u32,
+    stop_height: f32,
+    stop_distance: f32,
+) -> bool {
+    RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32())
+        .iter()
+        .any(|det| match det.distance {
+            Some(distance) if 0.0 < stop_distance => distance <= stop_distance,
+            _ => stop_height <= det.h as f32 / img_height.max(1) as f32,
+        })
+}
+
+/// Responds to a person in sight by the policy of the mode.
+/// Returns Some when the pilot must not drive on in this frame, like `base::stop`.
+pub fn respond(
+    state: &mut RoktrackState,
+    device: &mut Roktrack,
+    dets: &mut [Detection],
+    property: &RoktrackProperty,
+) -> Option<Result<(), Box<dyn std::error::Error>>> {
+    let stop_height = night::stop_height(&property.conf.person, &property.conf.night, state.night);
+    let stop_distance = property.conf.person.stop_distance;
+    if !is_near(dets, state.img_height, stop_height, stop_distance) {
+        log::debug!("Person Detected Far Away. Keep Going.");
+        return None;
+    }
+    let policy = policy(&property.conf.person, state.mode);
+    log::debug!("Person Detected. Policy: {:?}", policy);
+    match policy {
+        PersonPolicy::Pause => Some(base::stop(device)),
+        PersonPolicy::Detour => Some(base::escape(state, device)),
+        PersonPolicy::Slow => {
+            device.inner.clone().lock().unwrap().caution = Some(property.conf.person.slow_speed);
+            None
+        }
+        PersonPolicy::Warn => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_test() {
+        let mut conf = Person::default();
+        assert_eq!(policy(&conf, Modes::Fill), PersonPolicy::Pause);
+        conf.modes.insert("patrol".to_string(), "warn".to_string());
+        conf.modes.insert("fill".to_string(), "slow".to_string());
+        assert_eq!(policy(&conf, Modes::Patrol), PersonPolicy::Warn);
+        assert_eq!(policy(&conf, Modes::Fill), PersonPolicy::Slow);
+        assert_eq!(policy(&conf, Modes::Spiral), PersonPolicy::Pause);
+        assert_eq!(PersonPolicy::from_string("detour"), PersonPolicy::Detour);
+        assert_eq!(PersonPolicy::from_string("run"), PersonPolicy::Pause);
+    }
+
+    #[test]
+    fn near_test() {
+        let person = |h: u32| Detection {
+            h,
+            cls: RoktrackClasses::PERSON.to_u32(),
+            ..Default::default()
+        };
+        assert!(is_near(&mut [person(24)], 240, 0.0, 0.0));
+        assert!(!is_near(&mut [person(24)], 240, 0.2, 0.0));
+        assert!(is_near(&mut [person(24), person(60)], 240, 0.2, 0.0));
+        assert!(!is_near(&mut [], 240, 0.0, 0.0));
+        // By the estimated distance
+        let ranged = |h: u32, distance: f32| Detection {
+            distance: Some(distance),
+            ..person(h)
+        };
+        assert!(is_near(&mut [ranged(24, 2.5)], 240, 0.2, 3.0));
+        assert!(!is_near(&mut [ranged(60, 4.0)], 240, 0.2, 3.0));
+        assert!(is_near(&mut [ranged(60, 4.0)], 240, 0.2, 0.0));
+    }
+}
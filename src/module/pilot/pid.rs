@@ -0,0 +1,111 @@
+//! PID Heading Controller
+//!
+//! Steers toward the marker by the difference of the power of the drive motors,
+//! instead of pivoting in steps, for smoother tracks and less wheel scrubbing.
+//! The error is the offset of the marker from the target direction (ratio to the image width).
+
+use crate::module::util::conf::{Heading, PidGains};
+
+// Without an update for this long in ms, the track is taken as a new one.
+const RESET_MS: u64 = 1000;
+// Limit of the integral term, against the windup while the motors can't follow.
+const INTEGRAL_LIMIT: f64 = 1.0;
+// Limit of the correction of the power.
+const CORRECTION_LIMIT: f64 = 0.3;
+
+/// PID controller over the heading error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pid {
+    gains: PidGains,
+    integral: f64,
+    last: Option<(f64, u64)>, // Last error and time in ms
+    applied: f64,             // Correction applied to the power
+}
+
+impl Pid {
+    /// Creates a new Pid instance with the gains.
+    pub fn new(gains: PidGains) -> Self {
+        Self {
+            gains,
+            integral: 0.0,
+            last: None,
+            applied: 0.0,
+        }
+    }
+
+    /// Creates the controller of the configured surface. None for the bang-bang corrections.
+    pub fn from_conf(conf: &Heading) -> Option<Self> {
+        match conf.controller.as_str() {
+            "pid" => match conf.gains.get(&conf.surface) {
+                Some(gains) => Some(Self::new(gains.clone())),
+                None => {
+                    log::warn!("No Gains For The Surface: {}", conf.surface);
+                    None
+                }
+            },
+            "bang_bang" => None,
+            _ => {
+                log::warn!("Invalid Heading Controller: {}", conf.controller);
+                None
+            }
+        }
+    }
+
+    /// Updates the controller with the error at the time in ms.
+    /// Returns the change of the correction since the last update, to be added to the power
+    /// of the right motor and taken from the left one.
+    pub fn update(&mut self, error: f32, now: u64) -> f64 {
+        let error = error as f64;
+        let derivative = match self.last {
+            Some((last_error, last_time)) if now.saturating_sub(last_time) <= RESET_MS => {
+                let dt = now.saturating_sub(last_time).max(1) as f64 / 1000.0;
+                self.integral = (self.integral + error * dt).clamp(-INTEGRAL_LIMIT, INTEGRAL_LIMIT);
+                (error - last_error) / dt
+            }
+            _ => {
+                self.integral = 0.0;
+                0.0
+            }
+        };
+        self.last = Some((error, now));
+        let correction =
+            (self.gains.kp * error + self.gains.ki * self.integral + self.gains.kd * derivative)
+                .clamp(-CORRECTION_LIMIT, CORRECTION_LIMIT);
+        let step = correction - self.applied;
+        self.applied = correction;
+        step
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pid_test() {
+        let gains = PidGains {
+            kp: 0.5,
+            ki: 0.1,
+            kd: 0.0,
+        };
+        let mut pid = Pid::new(gains.clone());
+        // Proportional only on the first update.
+        assert!((pid.update(0.2, 0) - 0.1).abs() < 1e-6);
+        // The integral grows while the error stays, adding only the change.
+        assert!((pid.update(0.2, 500) - 0.01).abs() < 1e-6);
+        // Back on track, the correction is taken back except the integral.
+        assert!((pid.update(0.0, 1000) + 0.1).abs() < 1e-6);
+        // After a gap, the integral is cleared.
+        assert!((pid.update(0.0, 5000) + 0.01).abs() < 1e-6);
+        // The correction is limited.
+        let mut pid = Pid::new(gains);
+        assert!((pid.update(1.0, 0) - CORRECTION_LIMIT).abs() < 1e-6);
+
+        let mut conf = Heading::default();
+        assert!(Pid::from_conf(&conf).is_some());
+        conf.surface = "sand".to_string();
+        assert!(Pid::from_conf(&conf).is_none());
+        conf.controller = "bang_bang".to_string();
+        assert!(Pid::from_conf(&conf).is_none());
+    }
+}
@@ -0,0 +1,122 @@
+//! Fill Progress Persistence
+//!
+//! The progress of the fill mission is saved to the data directory while filling, so that after
+//! a battery swap or a crash the robot resumes the mission where it stopped instead of mowing the
+//! area from scratch. The saved progress is cleared when the mission is completed or reset.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Phase, RoktrackState};
+use crate::module::define;
+
+/// Progress of the fill mission.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct FillProgress {
+    pub cw: bool,              // Direction of laps (true: CW)
+    pub rest: f32,             // Remaining work, which tells the current lap
+    pub constant: f32,         // Amount subtracted from rest for each marker approach
+    pub target: f32,           // Marker height at which to turn (ratio to the image height)
+    pub turn_count: i8,        // Continuous turn counter
+    pub marker_id: Option<u8>, // Last marker with the ID
+    pub saved_at: i64,         // Time of the save in ms
+}
+
+impl FillProgress {
+    /// Takes the progress from the state.
+    pub fn from_state(state: &RoktrackState, now: i64) -> Self {
+        Self {
+            cw: state.phase == Phase::CW,
+            rest: state.rest,
+            constant: state.constant,
+            target: state.target_height as f32 / state.img_height.max(1) as f32,
+            turn_count: state.turn_count,
+            marker_id: state.marker_id,
+            saved_at: now,
+        }
+    }
+
+    /// Restores the progress to the state, scaling the target height to the current resolution.
+    pub fn apply(&self, state: &mut RoktrackState) {
+        state.phase = match self.cw {
+            true => Phase::CW,
+            false => Phase::CCW,
+        };
+        state.rest = self.rest;
+        state.constant = self.constant;
+        state.target_height = (self.target * state.img_height as f32) as u16;
+        state.turn_count = self.turn_count;
+        state.marker_id = self.marker_id;
+    }
+
+    /// Loads the saved progress. None if there is none.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        match toml::from_str(&content) {
+            Ok(progress) => Some(progress),
+            Err(e) => {
+                log::warn!("Invalid Fill Progress: {}, {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Saves the progress.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        // Write to a temporary file first, so that a crash doesn't leave a broken one.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+}
+
+/// Path of the progress file in the data directory.
+pub fn path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join(define::path::FILL_PROGRESS_FILE)
+}
+
+/// Clears the saved progress.
+pub fn clear(data_dir: &str) {
+    let path = path(data_dir);
+    if path.exists() {
+        match fs::remove_file(&path) {
+            Ok(_) => log::info!("Fill Progress Cleared."),
+            Err(e) => log::warn!("Can't Clear Fill Progress: {}, {}", path.display(), e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn progress_test() {
+        let dir = "/tmp/roktracktest/progress/";
+        fs::create_dir_all(dir).unwrap();
+        clear(dir);
+        assert_eq!(FillProgress::load(&path(dir)), None);
+
+        let mut state = RoktrackState::new();
+        state.invert_phase();
+        state.rest = 0.6;
+        state.marker_id = Some(3);
+        let progress = FillProgress::from_state(&state, 1_700_000_000_000);
+        progress.save(&path(dir)).unwrap();
+        let loaded = FillProgress::load(&path(dir)).unwrap();
+        assert_eq!(loaded, progress);
+
+        let mut resumed = RoktrackState::new();
+        loaded.apply(&mut resumed);
+        assert_eq!(resumed.phase, Phase::CW);
+        assert_eq!(resumed.rest, 0.6);
+        assert_eq!(resumed.marker_id, Some(3));
+        assert_eq!(resumed.target_height, state.target_height);
+
+        clear(dir);
+        assert_eq!(FillProgress::load(&path(dir)), None);
+    }
+}
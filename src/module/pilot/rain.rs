@@ -0,0 +1,96 @@
+//! Rain Abort Policy
+//!
+//! When it starts raining during a mission, the pilot is let to finish the current pass, so that
+//! the job resumes from a clean boundary. Then the progress is saved and the robot heads back to
+//! the dock or stops. A pass ends when the robot reaches the marker and starts turning for the next
+//! one. Modes without passes are aborted at once, and a pass running too long after `pass_timeout`.
+
+use std::time::{Duration, Instant};
+
+use super::{work_rate, Modes};
+use crate::module::util::conf::Rain as RainConf;
+
+/// Actions after the current pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Return,
+    Stop,
+}
+
+/// Action configured when it rains.
+pub fn action(conf: &RainConf) -> Action {
+    match conf.action.as_str() {
+        "return" => Action::Return,
+        "stop" => Action::Stop,
+        _ => {
+            log::warn!("Invalid Rain Action: {}", conf.action);
+            Action::Return
+        }
+    }
+}
+
+/// Waits for the end of the current pass after it started raining.
+#[derive(Debug, Clone, Default)]
+pub struct RainAbort {
+    since: Option<Instant>, // Time when the rain was first reported
+    turn_count: i8,         // Turn counter of the last frame
+}
+
+impl RainAbort {
+    /// Observes the rain and the pilot. Returns true when the mission is to be aborted.
+    /// The rain once reported is kept, even if the sensor dries in the meantime.
+    pub fn observe(
+        &mut self,
+        raining: bool,
+        mode: Modes,
+        turn_count: i8,
+        now: Instant,
+        conf: &RainConf,
+    ) -> bool {
+        let last = std::mem::replace(&mut self.turn_count, turn_count);
+        if raining && self.since.is_none() {
+            log::warn!("Rain Detected. Abort after the current pass.");
+            self.since = Some(now);
+        }
+        let Some(since) = self.since else {
+            return false;
+        };
+        !work_rate::is_fill_type(mode)
+            || (last <= 0 && 0 < turn_count)
+            || Duration::from_secs(conf.pass_timeout) <= now.duration_since(since)
+    }
+
+    /// Forgets the rain, e.g. once aborted or when the job is restarted.
+    pub fn reset(&mut self) {
+        self.since = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rain_test() {
+        let conf = RainConf::default();
+        let t0 = Instant::now();
+        let mut rain = RainAbort::default();
+        assert!(!rain.observe(false, Modes::Fill, 0, t0, &conf));
+        // Finish the pass.
+        assert!(!rain.observe(true, Modes::Fill, 0, t0, &conf));
+        assert!(!rain.observe(false, Modes::Fill, 0, t0, &conf));
+        assert!(rain.observe(false, Modes::Fill, 1, t0, &conf));
+        // Or give up waiting.
+        let mut rain = RainAbort::default();
+        rain.observe(true, Modes::Fill, 0, t0, &conf);
+        let late = t0 + Duration::from_secs(conf.pass_timeout);
+        assert!(rain.observe(true, Modes::Fill, 0, late, &conf));
+        rain.reset();
+        assert!(!rain.observe(false, Modes::Fill, 0, late, &conf));
+        // No pass to finish
+        let mut rain = RainAbort::default();
+        assert!(rain.observe(true, Modes::Patrol, 0, t0, &conf));
+
+        assert_eq!(action(&conf), Action::Return);
+    }
+}
@@ -0,0 +1,379 @@
+//! Pilot Handler Registry
+//!
+//! Handlers are created by the factory registered for each mode.
+//! Factories can be replaced while the drive thread is running, and custom handlers are
+//! registered for `Modes::Custom` without touching the built-in modes.
+//!
+//! The model files configured for a mode (`models` in the `[vision]` section) replace the detection
+//! models switched by its factory, e.g. to watch animals with a model trained for the site.
+//!
+//! # Example
+//!
+//! ```ignore
+//! let registry = registry::builtin();
+//! registry.lock().unwrap().register(Modes::Custom(100), |tx, _conf| {
+//!     tx.send(VisionMgmtCommand::SwitchSessionPylon).unwrap();
+//!     Some(Box::new(MyHandler::new()))
+//! });
+//! drive::run_with_registry(property, registry);
+//! ```
+
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
+
+use super::{
+    animal_deterrent::AnimalDeterrent, around::Around, calibrate::Calibrate,
+    camera_calibrate::CameraCalibrate, climb::Climb, dataset_capture::DatasetCapture,
+    edge_follow::EdgeFollow, fill::Fill, follow_person::FollowPerson, mission::Mission,
+    monitor_animal::MonitorAnimal, monitor_person::MonitorPerson, oneway::OneWay, orchard::Orchard,
+    patrol::Patrol, perimeter_trim::PerimeterTrim, return_to_dock::ReturnToDock,
+    round_trip::RoundTrip, spiral::Spiral, spot::Spot, stripe::Stripe, trailer_dock::TrailerDock,
+    waypoint::Waypoint, Modes, PilotHandler,
+};
+use crate::module::{
+    define,
+    util::conf::{Config, Vision},
+    vision::VisionMgmtCommand,
+};
+
+/// Creates the handler of a mode, switching the vision session it needs.
+pub type HandlerFactory =
+    Box<dyn Fn(Sender<VisionMgmtCommand>, Config) -> Option<Box<dyn PilotHandler>> + Send + Sync>;
+
+/// Registry shared between threads.
+pub type SharedRegistry = Arc<Mutex<Registry>>;
+
+/// Factories of handlers keyed by mode.
+#[derive(Default)]
+pub struct Registry {
+    factories: HashMap<Modes, HandlerFactory>,
+    revision: u64, // Incremented on every change
+}
+
+impl Registry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the factory of a mode, replacing the previous one.
+    pub fn register<F>(&mut self, mode: Modes, factory: F)
+    where
+        F: Fn(Sender<VisionMgmtCommand>, Config) -> Option<Box<dyn PilotHandler>>
+            + Send
+            + Sync
+            + 'static,
+    {
+        self.factories.insert(mode, Box::new(factory));
+        self.revision += 1;
+    }
+
+    /// Unregisters the factory of a mode. Returns whether it was registered.
+    pub fn unregister(&mut self, mode: Modes) -> bool {
+        let removed = self.factories.remove(&mode).is_some();
+        if removed {
+            self.revision += 1;
+        }
+        removed
+    }
+
+    /// Whether a factory is registered for the mode.
+    pub fn contains(&self, mode: Modes) -> bool {
+        self.factories.contains_key(&mode)
+    }
+
+    /// Registered modes in the order of their numbers.
+    pub fn modes(&self) -> Vec<Modes> {
+        let mut modes: Vec<Modes> = self.factories.keys().copied().collect();
+        modes.sort_by_key(|mode| Modes::to_u8(*mode));
+        modes
+    }
+
+    /// Revision of the registry, which changes whenever a factory is registered or unregistered.
+    pub fn revision(&self) -> u64 {
+        self.revision
+    }
+
+    /// Creates the handler of a mode.
+    pub fn create(
+        &self,
+        mode: Modes,
+        tx: Sender<VisionMgmtCommand>,
+        conf: Config,
+    ) -> Option<Box<dyn PilotHandler>> {
+        let handler = self
+            .factories
+            .get(&mode)
+            .and_then(|factory| factory(tx.clone(), conf.clone()))?;
+        if let Some(path) = model(&conf.vision, mode) {
+            tx.send(VisionMgmtCommand::LoadModel(path)).unwrap();
+        }
+        Some(handler)
+    }
+}
+
+/// Model file configured for the mode.
+fn model(conf: &Vision, mode: Modes) -> Option<String> {
+    conf.models
+        .iter()
+        .find(|(name, _)| Modes::from_string(name) == mode)
+        .map(|(_, path)| path.clone())
+}
+
+/// Switches the vision session and the resolution for a handler.
+fn switch_session(tx: &Sender<VisionMgmtCommand>, session: VisionMgmtCommand) {
+    tx.send(session).unwrap();
+    tx.send(VisionMgmtCommand::SwitchSz320).unwrap();
+}
+
+/// Whether the model of an optional session is in place. Logs the feature going without it.
+fn model_available(path: &str, feature: &str) -> bool {
+    let available = Path::new(path).is_file();
+    if !available {
+        log::error!("{} Unavailable. Model Not Found: {}", feature, path);
+    }
+    available
+}
+
+/// Pylon session of the modes driving over the lawn, with the grass segmentation to keep on it.
+fn pylon_session(conf: &Config) -> VisionMgmtCommand {
+    match 0.0 < conf.risk.min_grass
+        && model_available(define::path::GRASS_SEG_320_MODEL, "Grass Check")
+    {
+        true => VisionMgmtCommand::SwitchSessionPylonGrass,
+        false => VisionMgmtCommand::SwitchSessionPylon,
+    }
+}
+
+/// Creates a registry of the built-in modes.
+pub fn builtin() -> SharedRegistry {
+    let registry = Arc::new(Mutex::new(Registry::new()));
+    // Missions create the handlers of their steps from the same registry.
+    let steps = Arc::downgrade(&registry);
+    {
+        let mut r = registry.lock().unwrap();
+        r.register(Modes::Fill, |tx, conf| {
+            match conf.vision.ocr {
+                true => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonOcr),
+                false => switch_session(&tx, pylon_session(&conf)),
+            }
+            Some(Box::new(Fill::new()))
+        });
+        r.register(Modes::OneWay, |tx, conf| {
+            switch_session(&tx, pylon_session(&conf));
+            Some(Box::new(OneWay::new()))
+        });
+        r.register(Modes::Climb, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(Climb::new()))
+        });
+        // The start marker is told apart by its number.
+        r.register(Modes::Around, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonOcr);
+            Some(Box::new(Around::new()))
+        });
+        r.register(Modes::MonitorPerson, |tx, conf| {
+            // Persons lying on the ground are told by their poses.
+            match conf.monitor_person.fallen
+                && model_available(define::path::POSE_320_MODEL, "Fallen Person Alert")
+            {
+                true => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonPose),
+                false => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon),
+            }
+            Some(Box::new(MonitorPerson::new()))
+        });
+        r.register(Modes::MonitorAnimal, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionAnimal);
+            Some(Box::new(MonitorAnimal::new()))
+        });
+        r.register(Modes::RoundTrip, |tx, conf| {
+            // Waypoints are told apart by the numbers on the markers.
+            match conf.round_trip.waypoints.is_empty() {
+                true => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon),
+                false => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonOcr),
+            }
+            Some(Box::new(RoundTrip::new()))
+        });
+        r.register(Modes::FollowPerson, |tx, conf| {
+            match conf.follow_person.gesture
+                && model_available(define::path::POSE_320_MODEL, "Gestures")
+            {
+                true => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonPose),
+                false => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon),
+            }
+            Some(Box::new(FollowPerson::new()))
+        });
+        r.register(Modes::ReturnToDock, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(ReturnToDock::new()))
+        });
+        r.register(Modes::Spiral, |tx, conf| {
+            switch_session(&tx, pylon_session(&conf));
+            Some(Box::new(Spiral::new()))
+        });
+        r.register(Modes::Stripe, |tx, conf| {
+            switch_session(&tx, pylon_session(&conf));
+            Some(Box::new(Stripe::new()))
+        });
+        r.register(Modes::PerimeterTrim, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(PerimeterTrim::new()))
+        });
+        r.register(Modes::Waypoint, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(Waypoint::new()))
+        });
+        r.register(Modes::Spot, |tx, conf| {
+            switch_session(&tx, pylon_session(&conf));
+            Some(Box::new(Spot::new()))
+        });
+        // The edge is told by the grass segmentation, so the mode is refused without its model.
+        r.register(Modes::EdgeFollow, |tx, _| {
+            if !model_available(define::path::GRASS_SEG_320_MODEL, "Edge Follow") {
+                return None;
+            }
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonGrass);
+            Some(Box::new(EdgeFollow::new()))
+        });
+        r.register(Modes::AnimalDeterrent, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionAnimal);
+            Some(Box::new(AnimalDeterrent::new()))
+        });
+        r.register(Modes::Patrol, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(Patrol::new()))
+        });
+        r.register(Modes::Orchard, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(Orchard::new()))
+        });
+        r.register(Modes::TrailerDock, |tx, conf| {
+            // The ramp is told apart by the number on the marker.
+            match conf.trailer_dock.ramp_marker {
+                0 => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon),
+                _ => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylonOcr),
+            }
+            Some(Box::new(TrailerDock::new()))
+        });
+        // The dance needs no detections, only the frames to pace it.
+        r.register(Modes::Calibrate, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(Calibrate::new()))
+        });
+        // The frames are taken by the vision, but the detections aren't needed.
+        r.register(Modes::CameraCalibrate, |tx, _| {
+            switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon);
+            Some(Box::new(CameraCalibrate::new()))
+        });
+        // The frames are labeled by the model to train.
+        r.register(Modes::DatasetCapture, |tx, conf| {
+            match conf.dataset_capture.model.as_str() {
+                "animal" => switch_session(&tx, VisionMgmtCommand::SwitchSessionAnimal),
+                _ => switch_session(&tx, VisionMgmtCommand::SwitchSessionPylon),
+            }
+            Some(Box::new(DatasetCapture::new()))
+        });
+        // Each step switches the session for its own mode.
+        r.register(Modes::Mission, move |_, conf| {
+            let registry = steps.clone();
+            Some(Box::new(Mission::new(
+                conf.mission.steps,
+                Box::new(move |mode, tx, conf| {
+                    registry.upgrade()?.lock().unwrap().create(mode, tx, conf)
+                }),
+            )))
+        });
+    }
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Idle;
+    impl PilotHandler for Idle {}
+
+    #[test]
+    fn registry_test() {
+        let mut registry = Registry::new();
+        assert!(registry.modes().is_empty());
+        registry.register(Modes::Custom(100), |_, _| Some(Box::new(Idle)));
+        registry.register(Modes::Fill, |_, _| None);
+        assert_eq!(registry.modes(), vec![Modes::Fill, Modes::Custom(100)]);
+        assert_eq!(registry.revision(), 2);
+        assert!(registry.unregister(Modes::Fill));
+        assert!(!registry.unregister(Modes::Fill));
+        assert_eq!(registry.revision(), 3);
+        assert!(registry.contains(Modes::Custom(100)));
+
+        let builtin = builtin();
+        let builtin = builtin.lock().unwrap();
+        assert!(builtin.contains(Modes::Mission));
+        assert!(builtin.contains(Modes::Climb));
+        assert!(builtin.contains(Modes::Around));
+        assert!(builtin.contains(Modes::Orchard));
+        assert!(builtin.contains(Modes::TrailerDock));
+        assert!(builtin.contains(Modes::Calibrate));
+        assert!(builtin.contains(Modes::CameraCalibrate));
+        assert!(builtin.contains(Modes::DatasetCapture));
+    }
+
+    #[test]
+    fn model_available_test() {
+        let builtin = builtin();
+        let builtin = builtin.lock().unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        std::fs::create_dir_all(Path::new("/tmp/roktracktest/")).unwrap();
+        let mut conf = crate::module::util::conf::toml::load("/tmp/roktracktest/").unwrap();
+        conf.vision.ocr = false;
+        conf.risk.min_grass = 0.5;
+        conf.follow_person.gesture = true;
+        // The sessions are switched only with the models in place.
+        let grass = Path::new(define::path::GRASS_SEG_320_MODEL).is_file();
+        let pose = Path::new(define::path::POSE_320_MODEL).is_file();
+        assert_eq!(
+            builtin
+                .create(Modes::EdgeFollow, tx.clone(), conf.clone())
+                .is_some(),
+            grass
+        );
+        assert!(builtin
+            .create(Modes::Fill, tx.clone(), conf.clone())
+            .is_some());
+        assert!(builtin.create(Modes::FollowPerson, tx, conf).is_some());
+        let sessions: Vec<VisionMgmtCommand> = rx.try_iter().collect();
+        let grass_sessions = sessions
+            .iter()
+            .filter(|c| matches!(c, VisionMgmtCommand::SwitchSessionPylonGrass))
+            .count();
+        let pose_sessions = sessions
+            .iter()
+            .filter(|c| matches!(c, VisionMgmtCommand::SwitchSessionPylonPose))
+            .count();
+        assert_eq!(grass_sessions, if grass { 2 } else { 0 });
+        assert_eq!(pose_sessions, if pose { 1 } else { 0 });
+    }
+
+    #[test]
+    fn model_test() {
+        let mut conf = Vision {
+            detector: "onnx".to_string(),
+            ocr: false,
+            models: HashMap::new(),
+            max_latency_ms: 0,
+        };
+        assert_eq!(model(&conf, Modes::MonitorAnimal), None);
+        conf.models.insert(
+            "monitor_animal".to_string(),
+            "asset/model/deer_320_320.onnx".to_string(),
+        );
+        assert_eq!(
+            model(&conf, Modes::MonitorAnimal),
+            Some("asset/model/deer_320_320.onnx".to_string())
+        );
+        assert_eq!(model(&conf, Modes::Fill), None);
+    }
+}
@@ -0,0 +1,165 @@
+//! Return To Dock Pilot
+//!
+
+// # Normal flow of act phase
+//
+// StartTurn  <- The dock marker is not visible. Turn on the spot to search for it.
+//    |
+// TurnKeep * n
+//    |
+// Proceed * n  <- Home in on the dock marker.
+//    |
+// Docked  <- The marker is close enough. Stop and report MissionComplete.
+//
+// TurnCountExceeded  <- The process is stopped because it was not found after the specified number of turns.
+
+use std::sync::mpsc::Sender;
+
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::Roktrack,
+    pilot::base,
+    pilot::RoktrackState,
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Height of the dock marker in the image (ratio) at which the robot is docked.
+const DOCKED_HEIGHT_RATIO: f32 = 0.9;
+
+pub struct ReturnToDock {
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl ReturnToDock {
+    pub fn new() -> Self {
+        Self {
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for ReturnToDock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for ReturnToDock {
+    /// Function called from a thread to handle the Return To Dock Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start ReturnToDock Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // The biggest marker is the closest one, which is taken as the dock.
+        let detections = sort::big(detections);
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+        let marker = detections.first().cloned().unwrap_or_default();
+        log::debug!("Dock Marker Selected: {:?}", marker);
+
+        // No mowing on the way back
+        device.inner.clone().lock().unwrap().work_motor.stop();
+
+        let action = assess_situation(state, &marker);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::Docked) => {
+                log::info!("Docked.");
+                state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                base::mission_complete(state, device)
+            }
+            Some(ActPhase::Proceed) => {
+                // Stop searching once the dock is in sight.
+                state.turn_count = 0;
+                base::proceed(state, device, marker, tx)
+            }
+            None => Ok(()),
+        };
+        log::debug!("End ReturnToDock Handle");
+    }
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Return To Dock Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnKeep,
+    StartTurn,
+    Docked,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
+    if marker.h == 0 {
+        if 10 <= state.turn_count {
+            Some(ActPhase::TurnCountExceeded)
+        } else if 0 < state.turn_count {
+            Some(ActPhase::TurnKeep)
+        } else {
+            Some(ActPhase::StartTurn)
+        }
+    } else if state.img_height as f32 * DOCKED_HEIGHT_RATIO <= marker.h as f32 {
+        Some(ActPhase::Docked)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
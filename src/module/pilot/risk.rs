@@ -0,0 +1,369 @@
+//! System Risk Engine shared by the pilots.
+//!
+//! Checks are registered once with a priority and a response, and the pilots consult the engine
+//! before driving. Pilots drop the checks which don't apply to them (e.g. the bumper while monitoring)
+//! or replace the responses (e.g. stopping instead of escaping).
+//!
+//! # Built-in checks (in the order of the priority)
+//!
+//! | risk          | response | condition                                             |
+//! |---------------|----------|-------------------------------------------------------|
+//! | StateOff      | Stop     | The drive is off                                      |
+//! | EmergencyStop | Halt     | The E-stop button is pressed                          |
+//! | Tilted        | Halt     | The IMU tilts more than `max_tilt`                    |
+//! | HighTemp      | Stop     | The SoC is hotter than `max_temp`                     |
+//! | HotPart       | Halt     | A part in the thermal view is over `part_limit`       |
+//! | LowBattery    | Halt     | The battery is lower than `critical_battery_mv`       |
+//! | CommLoss      | Stop     | No message from the commander for `comm_timeout`      |
+//! | Stuck         | Recover  | Forward without a change of the scene for `seconds`   |
+//! | NoGo          | Escape   | A no-go QR code sign is close ahead                   |
+//! | OffGrass      | Escape   | Less grass than `min_grass` ahead, where segmented    |
+//! | Bumped        | Escape   | The bumper is pressed                                 |
+//!
+//! After `attempts` recoveries in vain, the drive is halted and the owner is notified.
+
+use super::{base, RoktrackState};
+use crate::module::{
+    com::ChildMsg,
+    device::Roktrack,
+    util::{common::send_line_notify_with_image, conf::Config, init::RoktrackProperty},
+    vision::detector::segment::GrassMask,
+};
+
+// Region of the image ahead of the robot (ratios to the image: x1, y1, x2, y2)
+const AHEAD: (f32, f32, f32, f32) = (0.3, 0.7, 0.7, 1.0);
+
+/// System Risks
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SystemRisk {
+    StateOff,
+    EmergencyStop,
+    Tilted,
+    HighTemp,
+    HotPart,
+    LowBattery,
+    CommLoss,
+    Stuck,
+    NoGo,
+    OffGrass,
+    Bumped,
+}
+
+/// Responses to the risks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Response {
+    Stop,    // Stop and wait for the risk to go away
+    Escape,  // Back off and go around
+    Halt,    // Stop and turn the drive off
+    Recover, // Reverse and rotate to retry, and halt after the attempts in vain
+}
+
+/// Function to check whether the risk exists.
+pub type Check = fn(&RoktrackState, &Roktrack, &Config) -> bool;
+
+/// A registered check.
+#[derive(Clone)]
+pub struct RiskCheck {
+    pub risk: SystemRisk,
+    pub priority: u8, // Lower is checked first
+    pub response: Response,
+    pub voice: Option<&'static str>, // Spoken when the risk is found
+    pub check: Check,
+}
+
+/// Checks in the order of the priority.
+#[derive(Clone, Default)]
+pub struct RiskEngine {
+    checks: Vec<RiskCheck>,
+}
+
+impl RiskEngine {
+    /// Creates an engine without checks.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers a check, replacing the previous one of the same risk.
+    pub fn register(
+        &mut self,
+        risk: SystemRisk,
+        priority: u8,
+        response: Response,
+        voice: Option<&'static str>,
+        check: Check,
+    ) {
+        self.checks.retain(|c| c.risk != risk);
+        self.checks.push(RiskCheck {
+            risk,
+            priority,
+            response,
+            voice,
+            check,
+        });
+        self.checks.sort_by_key(|c| c.priority);
+    }
+
+    /// Drops the check of the risk.
+    pub fn without(mut self, risk: SystemRisk) -> Self {
+        self.checks.retain(|c| c.risk != risk);
+        self
+    }
+
+    /// Replaces the response to the risk.
+    pub fn respond_with(mut self, risk: SystemRisk, response: Response) -> Self {
+        for c in self.checks.iter_mut().filter(|c| c.risk == risk) {
+            c.response = response;
+        }
+        self
+    }
+
+    /// Registered risks in the order of the priority.
+    pub fn risks(&self) -> Vec<SystemRisk> {
+        self.checks.iter().map(|c| c.risk).collect()
+    }
+
+    /// Finds the risk of the highest priority.
+    pub fn assess(
+        &self,
+        state: &RoktrackState,
+        device: &Roktrack,
+        conf: &Config,
+    ) -> Option<&RiskCheck> {
+        self.checks.iter().find(|c| (c.check)(state, device, conf))
+    }
+
+    /// Finds the risk of the highest priority and responds to it. Returns the risk found.
+    pub fn handle(
+        &self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        property: &RoktrackProperty,
+    ) -> Option<SystemRisk> {
+        let found = self.assess(state, device, &property.conf)?;
+        log::debug!("System Risk Found: {:?}", found.risk);
+        if let Some(voice) = found.voice {
+            device.inner.clone().lock().unwrap().speak(voice);
+        }
+        let _ = match found.response {
+            Response::Stop => base::stop(device),
+            Response::Escape => base::escape(state, device),
+            Response::Halt => {
+                log::warn!("Halted by the risk: {:?}", found.risk);
+                state.state = false;
+                state.msg = ChildMsg::to_u8(ChildMsg::Halt);
+                base::stop(device)
+            }
+            Response::Recover if property.conf.stuck.attempts <= state.stuck.attempts => {
+                log::warn!("Stuck! Give up after {} attempts.", state.stuck.attempts);
+                state.state = false;
+                state.msg = ChildMsg::to_u8(ChildMsg::Stuck);
+                let _ = send_line_notify_with_image(
+                    "Stuck. Please help me out.",
+                    &property.path.img.last,
+                    property.conf.clone(),
+                );
+                base::stop(device)
+            }
+            Response::Recover => base::recover(state, device),
+        };
+        Some(found.risk)
+    }
+}
+
+/// Creates an engine of the built-in checks.
+pub fn builtin() -> RiskEngine {
+    let mut engine = RiskEngine::new();
+    engine.register(
+        SystemRisk::StateOff,
+        0,
+        Response::Stop,
+        None,
+        |state, _, _| !state.state,
+    );
+    engine.register(
+        SystemRisk::EmergencyStop,
+        10,
+        Response::Halt,
+        None,
+        |_, device, _| device.inner.clone().lock().unwrap().estop.pressed(),
+    );
+    engine.register(
+        SystemRisk::Tilted,
+        20,
+        Response::Halt,
+        None,
+        |_, device, conf| {
+            0.0 < conf.risk.max_tilt
+                && device
+                    .inner
+                    .clone()
+                    .lock()
+                    .unwrap()
+                    .imu
+                    .tilt()
+                    .is_some_and(|(pitch, roll)| is_tilted(pitch, roll, conf.risk.max_tilt))
+        },
+    );
+    engine.register(
+        SystemRisk::HighTemp,
+        30,
+        Response::Stop,
+        Some("high_temp"),
+        |state, _, conf| state.pi_temp > conf.risk.max_temp,
+    );
+    engine.register(
+        SystemRisk::HotPart,
+        35,
+        Response::Halt,
+        None,
+        |state, _, conf| is_hot(state.part_temp, conf.thermal.part_limit),
+    );
+    engine.register(
+        SystemRisk::LowBattery,
+        40,
+        Response::Halt,
+        None,
+        |state, _, conf| is_below(state.telemetry.battery_mv, conf.risk.critical_battery_mv),
+    );
+    engine.register(
+        SystemRisk::CommLoss,
+        50,
+        Response::Stop,
+        None,
+        |state, _, conf| {
+            let now = chrono::Utc::now().timestamp_millis();
+            is_lost(state.last_contact, now, conf.risk.comm_timeout)
+        },
+    );
+    engine.register(
+        SystemRisk::Stuck,
+        55,
+        Response::Recover,
+        None,
+        |state, device, conf| {
+            let now = chrono::Utc::now().timestamp_millis() as u64;
+            let forward_since = device.inner.clone().lock().unwrap().forward_since;
+            state.stuck.is_stuck(forward_since, now, conf.stuck.seconds)
+        },
+    );
+    engine.register(
+        SystemRisk::NoGo,
+        57,
+        Response::Escape,
+        None,
+        |state, _, _| state.no_go,
+    );
+    engine.register(
+        SystemRisk::OffGrass,
+        58,
+        Response::Escape,
+        None,
+        |state, _, conf| is_off_grass(state.grass.as_ref(), conf.risk.min_grass),
+    );
+    engine.register(
+        SystemRisk::Bumped,
+        60,
+        Response::Escape,
+        Some("bumped"),
+        |_, device, _| device.inner.clone().lock().unwrap().bumper.switch.is_low(),
+    );
+    engine
+}
+
+/// Whether the pitch or the roll in degrees is over the limit.
+fn is_tilted(pitch: f32, roll: f32, max_tilt: f32) -> bool {
+    max_tilt < pitch.abs() || max_tilt < roll.abs()
+}
+
+/// Whether the part is hotter than the limit. 0 disables the check.
+fn is_hot(temp: Option<f32>, limit: f32) -> bool {
+    0.0 < limit && temp.is_some_and(|temp| limit < temp)
+}
+
+/// Whether the battery is below the critical voltage. 0 disables the check.
+fn is_below(battery_mv: Option<u16>, critical_mv: u16) -> bool {
+    0 < critical_mv && battery_mv.is_some_and(|mv| mv < critical_mv)
+}
+
+/// Whether the commander has been silent for the timeout in seconds since the last contact in ms.
+/// Before the first contact, or with the timeout of 0, it's never lost.
+fn is_lost(last_contact: Option<i64>, now: i64, timeout: u64) -> bool {
+    0 < timeout && last_contact.is_some_and(|last| ((timeout * 1000) as i64) < now - last)
+}
+
+/// Whether there is less grass ahead than the ratio. 0 disables the check, and without the mask
+/// it's never off the grass.
+fn is_off_grass(grass: Option<&GrassMask>, min_grass: f32) -> bool {
+    0.0 < min_grass && grass.is_some_and(|mask| mask.ratio(AHEAD) < min_grass)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn risk_engine_test() {
+        let engine = builtin();
+        assert_eq!(
+            engine.risks(),
+            vec![
+                SystemRisk::StateOff,
+                SystemRisk::EmergencyStop,
+                SystemRisk::Tilted,
+                SystemRisk::HighTemp,
+                SystemRisk::HotPart,
+                SystemRisk::LowBattery,
+                SystemRisk::CommLoss,
+                SystemRisk::Stuck,
+                SystemRisk::NoGo,
+                SystemRisk::OffGrass,
+                SystemRisk::Bumped,
+            ]
+        );
+        let engine = engine
+            .without(SystemRisk::Bumped)
+            .respond_with(SystemRisk::HighTemp, Response::Halt);
+        assert!(!engine.risks().contains(&SystemRisk::Bumped));
+        assert!(engine
+            .checks
+            .iter()
+            .any(|c| c.risk == SystemRisk::HighTemp && c.response == Response::Halt));
+
+        // A registered check is ordered by its priority.
+        let mut engine = RiskEngine::new();
+        engine.register(SystemRisk::Bumped, 5, Response::Stop, None, |_, _, _| true);
+        engine.register(SystemRisk::HighTemp, 1, Response::Stop, None, |_, _, _| {
+            true
+        });
+        assert_eq!(
+            engine.risks(),
+            vec![SystemRisk::HighTemp, SystemRisk::Bumped]
+        );
+    }
+
+    #[test]
+    fn risk_condition_test() {
+        assert!(is_tilted(30.0, 0.0, 25.0));
+        assert!(is_tilted(0.0, -30.0, 25.0));
+        assert!(!is_tilted(10.0, 10.0, 25.0));
+        assert!(is_hot(Some(75.0), 60.0));
+        assert!(!is_hot(Some(45.0), 60.0));
+        assert!(!is_hot(None, 60.0));
+        assert!(!is_hot(Some(75.0), 0.0));
+        assert!(is_below(Some(10000), 10500));
+        assert!(!is_below(None, 10500));
+        assert!(!is_below(Some(10000), 0));
+        assert!(is_lost(Some(0), 31000, 30));
+        assert!(!is_lost(Some(0), 29000, 30));
+        assert!(!is_lost(None, 31000, 30));
+        assert!(!is_lost(Some(0), 31000, 0));
+        // Pavement in the lower half
+        let mask = GrassMask::new((0..100).map(|i| (i < 50) as u8 as f32).collect(), 10, 10);
+        assert!(is_off_grass(mask.as_ref(), 0.5));
+        assert!(!is_off_grass(mask.as_ref(), 0.0));
+        assert!(!is_off_grass(None, 0.5));
+        let mask = GrassMask::new(vec![1.0; 100], 10, 10);
+        assert!(!is_off_grass(mask.as_ref(), 0.5));
+    }
+}
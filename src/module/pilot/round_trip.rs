@@ -1,8 +1,19 @@
 //! Roundtrip Pilot between marker and person.
+//!
+//! When waypoints are configured, the robot shuttles along the markers with those numbers instead.
+//! Each leg between two waypoints is driven back and forth for its repeat count,
+//! and the path is driven back to the first waypoint before starting over.
+//!
+//! e.g. waypoints [1, 2, 3] and repeats [2, 1]
+//! 1 -> 2 -> 1 -> 2 -> 3 -> 2 -> 1 -> 2 -> 1 -> 2 -> ...
 
 use std::sync::mpsc::Sender;
 
-use super::PilotHandler;
+use super::{
+    lost::LossMonitor,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
 use crate::module::{
     device::Roktrack,
     pilot::base,
@@ -14,12 +25,18 @@ use crate::module::{
 
 pub struct RoundTrip {
     target_object: RoundTripObject,
+    waypoint: usize,   // Number of waypoints reached
+    lost: LossMonitor, // Frames without the tracked marker
+    risks: RiskEngine, // System risks to check before driving
 }
 
 impl RoundTrip {
     pub fn new() -> Self {
         Self {
             target_object: RoundTripObject::Marker,
+            waypoint: 0,
+            lost: LossMonitor::default(),
+            risks: risk::builtin(),
         }
     }
 }
@@ -38,27 +55,31 @@ impl PilotHandler for RoundTrip {
         device: &mut Roktrack,
         detections: &mut [Detection],
         tx: Sender<VisionMgmtCommand>,
-        _property: RoktrackProperty,
+        property: RoktrackProperty,
     ) {
         log::debug!("Start RoundTrip Handle");
         // Assess and handle system safety
-        let system_risk = match assess_system_risk(state, device) {
-            Some(SystemRisk::StateOff) | Some(SystemRisk::HighTemp) => Some(base::stop(device)),
-            Some(SystemRisk::Bumped) => Some(base::escape(state, device)),
-            None => None,
-        };
+        let system_risk = self.risks.handle(state, device, &property);
         if system_risk.is_some() {
             log::debug!("System Risk Exists. Continue.");
             return; // Risk exists, continue
         }
 
         // Sort markers based on the current target object
+        let conf = &property.conf.round_trip;
+        let waypoint = target_waypoint(&conf.waypoints, &conf.repeats, self.waypoint);
         let detections = sort::big(detections);
-        let detections = match self.target_object {
-            RoundTripObject::Marker => {
+        let detections = match (waypoint, &self.target_object) {
+            (Some(id), _) => {
                 RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32())
+                    .into_iter()
+                    .filter(|det| det.ids.contains(&id))
+                    .collect()
             }
-            RoundTripObject::Person => {
+            (None, RoundTripObject::Marker) => {
+                RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32())
+            }
+            (None, RoundTripObject::Person) => {
                 RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PERSON).to_u32())
             }
         };
@@ -67,7 +88,23 @@ impl PilotHandler for RoundTrip {
         let marker = detections.first().cloned().unwrap_or_default();
         log::debug!("Marker Selected: {:?}", marker);
 
-        let action = assess_situation(state, &marker);
+        // Don't drive blind while the tracked marker is out of sight.
+        if self
+            .lost
+            .handle(
+                state,
+                device,
+                tx.clone(),
+                marker.h != 0,
+                &property.conf.vision_loss,
+            )
+            .is_some()
+        {
+            log::debug!("Marker Lost. Continue.");
+            return;
+        }
+
+        let action = assess_situation(state, &marker, conf.reach_distance);
         log::debug!("Action is {:?}", action);
 
         // Handle the current phase
@@ -78,6 +115,11 @@ impl PilotHandler for RoundTrip {
             Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
             Some(ActPhase::Stand) => base::stand(state, tx),
             Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) if waypoint.is_some() => {
+                log::info!("Waypoint Reached: {}", waypoint.unwrap());
+                self.waypoint += 1;
+                base::reach_marker(state, device, marker)
+            }
             Some(ActPhase::ReachMarker) => {
                 self.target_object = match self.target_object {
                     RoundTripObject::Marker => {
@@ -98,6 +140,37 @@ impl PilotHandler for RoundTrip {
     }
 }
 
+/// Number of the marker to head for after reaching `reached` waypoints. None without waypoints.
+fn target_waypoint(waypoints: &[u8], repeats: &[u8], reached: usize) -> Option<u8> {
+    let first = *waypoints.first()?;
+    let cycle = cycle(waypoints, repeats);
+    match (reached, cycle.is_empty()) {
+        (0, _) | (_, true) => Some(first),
+        _ => Some(cycle[(reached - 1) % cycle.len()]),
+    }
+}
+
+/// Waypoints visited from the first one until back to it.
+fn cycle(waypoints: &[u8], repeats: &[u8]) -> Vec<u8> {
+    let repeat = |leg: usize| repeats.get(leg).copied().unwrap_or(1).max(1);
+    let mut cycle = vec![];
+    // Forward
+    for (leg, pair) in waypoints.windows(2).enumerate() {
+        cycle.push(pair[1]);
+        for _ in 1..repeat(leg) {
+            cycle.extend([pair[0], pair[1]]);
+        }
+    }
+    // Backward
+    for (leg, pair) in waypoints.windows(2).enumerate().rev() {
+        cycle.push(pair[0]);
+        for _ in 1..repeat(leg) {
+            cycle.extend([pair[1], pair[0]]);
+        }
+    }
+    cycle
+}
+
 //// Target Object
 #[derive(Debug, Clone)]
 pub enum RoundTripObject {
@@ -115,29 +188,6 @@ impl RoundTripObject {
     }
 }
 
-/// System Risks
-///
-#[derive(Debug, Clone)]
-enum SystemRisk {
-    StateOff,
-    HighTemp,
-    Bumped,
-}
-/// Identify system-related risks
-///
-fn assess_system_risk(state: &RoktrackState, device: &Roktrack) -> Option<SystemRisk> {
-    if !state.state {
-        Some(SystemRisk::StateOff)
-    } else if state.pi_temp > 70.0 {
-        device.inner.clone().lock().unwrap().speak("high_temp");
-        Some(SystemRisk::HighTemp)
-    } else if device.inner.clone().lock().unwrap().bumper.switch.is_low() {
-        device.inner.clone().lock().unwrap().speak("bumped");
-        Some(SystemRisk::Bumped)
-    } else {
-        None
-    }
-}
 /// Actions for Fill Drive Pilot
 ///
 #[derive(Debug, Clone)]
@@ -151,8 +201,21 @@ enum ActPhase {
     ReachMarker,
     Proceed,
 }
+/// Whether the marker is reached, by its distance if estimated and `reach_distance` is set,
+/// otherwise by its height.
+fn reached(state: &RoktrackState, marker: &Detection, reach_distance: f32) -> bool {
+    match marker.distance {
+        Some(distance) if 0.0 < reach_distance => distance <= reach_distance,
+        _ => state.target_height <= marker.h as u16,
+    }
+}
+
 /// Function to assess the current situation and determine the appropriate action phase
-fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
+fn assess_situation(
+    state: &RoktrackState,
+    marker: &Detection,
+    reach_distance: f32,
+) -> Option<ActPhase> {
     if 7 <= state.turn_count {
         Some(ActPhase::TurnCountExceeded)
     } else if 0 < state.turn_count {
@@ -171,9 +234,44 @@ fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhas
         } else {
             None
         }
-    } else if state.target_height <= marker.h as u16 {
+    } else if reached(state, marker, reach_distance) {
         Some(ActPhase::ReachMarker)
     } else {
         Some(ActPhase::Proceed)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waypoint_test() {
+        assert_eq!(cycle(&[1, 2, 3], &[2]), vec![2, 1, 2, 3, 2, 1, 2, 1]);
+        assert_eq!(cycle(&[1, 2], &[]), vec![2, 1]);
+        assert!(cycle(&[1], &[]).is_empty());
+        // Head for the first waypoint, then follow the cycle.
+        assert_eq!(target_waypoint(&[], &[], 0), None);
+        assert_eq!(target_waypoint(&[1], &[], 5), Some(1));
+        assert_eq!(target_waypoint(&[1, 2], &[], 0), Some(1));
+        assert_eq!(target_waypoint(&[1, 2], &[], 1), Some(2));
+        assert_eq!(target_waypoint(&[1, 2], &[], 2), Some(1));
+        assert_eq!(target_waypoint(&[1, 2], &[], 3), Some(2));
+    }
+
+    #[test]
+    fn reach_test() {
+        let state = RoktrackState::new();
+        let marker = |h: u32, distance: Option<f32>| Detection {
+            h,
+            distance,
+            ..Default::default()
+        };
+        // By the height
+        assert!(reached(&state, &marker(230, None), 1.0));
+        assert!(!reached(&state, &marker(100, Some(0.5)), 0.0));
+        // By the distance, a small tag far below the height of the pylons
+        assert!(reached(&state, &marker(40, Some(0.5)), 1.0));
+        assert!(!reached(&state, &marker(230, Some(1.5)), 1.0));
+    }
+}
@@ -0,0 +1,42 @@
+//! Speed Profiles
+//!
+//! Each mode drives at its own speeds, e.g. quietly while patrolling at night and at full
+//! power while filling during the day. The speed is a ratio to the motor power, applied on top
+//! of the corrections of the heading, when cruising, turning, or approaching the marker.
+
+use super::Modes;
+use crate::module::util::conf::{Speed, SpeedProfile};
+
+// Height of the marker (ratio to the target height) from which the robot is approaching it.
+const APPROACH_RATIO: f32 = 0.7;
+
+/// Profile of the mode. Modes without a profile run at full power.
+pub fn profile(conf: &Speed, mode: Modes) -> SpeedProfile {
+    conf.profiles
+        .iter()
+        .find(|(name, _)| Modes::from_string(name) == mode)
+        .map(|(_, profile)| profile.clone())
+        .unwrap_or_default()
+}
+
+/// Whether the marker of the height is close enough to approach it slowly.
+pub fn is_approaching(height: u16, target_height: u16) -> bool {
+    0 < height && target_height as f32 * APPROACH_RATIO < height as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn speed_test() {
+        let conf = Speed::default();
+        assert_eq!(profile(&conf, Modes::Patrol).cruise, 0.6);
+        assert_eq!(profile(&conf, Modes::Fill).approach, 0.8);
+        assert_eq!(profile(&conf, Modes::Spiral), SpeedProfile::default());
+
+        assert!(is_approaching(200, 216));
+        assert!(!is_approaching(100, 216));
+        assert!(!is_approaching(0, 0));
+    }
+}
@@ -0,0 +1,250 @@
+//! Spiral Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// StartTurn / TurnKeep * n  <- Search for the start marker.
+//    |
+// Proceed * n
+//    |
+// ReachMarker  <- Plan the spiral from here.
+//    |
+// Spiral * n  <- Forward along a leg, then a quarter turn in the lap direction.
+//    |
+// MissionComplete
+//
+// The legs grow by the pitch every half lap (outward), or shrink (inward).
+// Inward spirals start on the outer edge, so the marker is placed at a corner of the area.
+
+use std::sync::mpsc::Sender;
+
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::conf::{Motion, Spiral as SpiralConf},
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Height of the start marker in the image (ratio) at which the spiral starts.
+const REACH_HEIGHT_RATIO: f32 = 0.9;
+
+pub struct Spiral {
+    plan: Option<ManeuverPlan>,
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Spiral {
+    pub fn new() -> Self {
+        Self {
+            plan: None,
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Spiral {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Spiral {
+    /// Function called from a thread to handle the Spiral Drive Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Spiral Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Drive the spiral once planned.
+        if let Some(plan) = self.plan.as_mut() {
+            if !maneuver::is_idle(device) {
+                return;
+            }
+            device.inner.clone().lock().unwrap().work_motor.cw();
+            match plan.next_step() {
+                Some(step) => maneuver::start(device, step),
+                None => {
+                    state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                    let _ = base::mission_complete(state, device);
+                    self.plan = None;
+                    return;
+                }
+            }
+            state.rest = plan.rest();
+            log::debug!("Spiral. rest: {}", state.rest);
+            return;
+        }
+
+        // Otherwise head for the start marker.
+        let detections = sort::big(detections);
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+        let marker = detections.first().cloned().unwrap_or_default();
+        log::debug!("Start Marker Selected: {:?}", marker);
+
+        let action = assess_situation(state, &marker);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) => {
+                device.inner.clone().lock().unwrap().pause();
+                state.msg = ChildMsg::to_u8(ChildMsg::ReachTarget);
+                state.turn_count = 0;
+                let plan = plan(&property.conf.spiral, &property.conf.motion, &state.phase);
+                log::info!(
+                    "Start Spiral. pitch: {}, laps: {}, direction: {}",
+                    property.conf.spiral.pitch,
+                    property.conf.spiral.laps,
+                    property.conf.spiral.direction
+                );
+                self.plan = Some(plan);
+                Ok(())
+            }
+            Some(ActPhase::Proceed) => {
+                state.turn_count = 0;
+                base::proceed(state, device, marker, tx)
+            }
+            None => Ok(()),
+        };
+        log::debug!("End Spiral Handle");
+    }
+
+    /// Keep the rest of the move cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some(plan) = self.plan.as_mut() {
+            plan.interrupt(maneuver::remaining(device));
+        }
+    }
+}
+
+/// Lengths of the legs of a square spiral in meters.
+pub fn legs(pitch: f32, laps: u8, inward: bool) -> Vec<f32> {
+    let mut legs: Vec<f32> = (0..laps as usize * 4)
+        .map(|i| pitch * (i / 2 + 1) as f32)
+        .collect();
+    if inward {
+        legs.reverse();
+    }
+    legs
+}
+
+/// Plans the spiral. The turns follow the lap direction.
+fn plan(conf: &SpiralConf, motion: &Motion, phase: &Phase) -> ManeuverPlan {
+    let turn = match phase {
+        Phase::CCW => Maneuver::Left(motion.quarter_turn_ms),
+        Phase::CW => Maneuver::Right(motion.quarter_turn_ms),
+    };
+    let steps = legs(conf.pitch, conf.laps, conf.direction == "inward")
+        .into_iter()
+        .flat_map(|leg| {
+            [
+                Maneuver::Forward(maneuver::travel_time(leg, motion.speed)),
+                turn,
+            ]
+        })
+        .collect();
+    ManeuverPlan::new(steps)
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Spiral Drive Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnKeep,
+    StartTurn,
+    ReachMarker,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
+    if marker.h == 0 {
+        if 10 <= state.turn_count {
+            Some(ActPhase::TurnCountExceeded)
+        } else if 0 < state.turn_count {
+            Some(ActPhase::TurnKeep)
+        } else {
+            Some(ActPhase::StartTurn)
+        }
+    } else if state.img_height as f32 * REACH_HEIGHT_RATIO <= marker.h as f32 {
+        Some(ActPhase::ReachMarker)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legs_test() {
+        assert_eq!(legs(0.5, 1, false), vec![0.5, 0.5, 1.0, 1.0]);
+        assert_eq!(legs(0.5, 1, true), vec![1.0, 1.0, 0.5, 0.5]);
+        assert_eq!(legs(0.5, 2, false).len(), 8);
+        assert!(legs(0.5, 0, false).is_empty());
+    }
+}
@@ -0,0 +1,302 @@
+//! Spot Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// StartTurn / TurnKeep * n  <- Search for the marker.
+//    |
+// Orbit * n  <- Circle the marker, keeping it on the side at the apparent height of the radius.
+//    |
+// Widen  <- After a lap, the radius grows by the pitch.
+//    |
+// MissionComplete  <- Beyond the maximum radius.
+//
+// The radius is estimated from the apparent height of the marker, calibrated by its height at 1 m.
+// A lap is timed from its circumference and the speed of the chassis.
+// The marker is kept on the left in CCW phase, on the right in CW phase.
+
+use std::sync::mpsc::Sender;
+use std::time::{Duration, Instant};
+
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Horizontal position (ratio to the image width) of the marker while orbiting.
+const SIDE_POSITION: f32 = 0.2;
+
+// Tolerated difference (ratio to the image width) before correcting the direction.
+const STEER_TOLERANCE: f32 = 0.05;
+
+// Duration of a correcting turn in ms.
+const TURN_STEP_MS: u64 = 100;
+
+pub struct Spot {
+    radius: Option<f32>,
+    lap_started: Option<Instant>,
+    paused_at: Option<Instant>,
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Spot {
+    pub fn new() -> Self {
+        Self {
+            radius: None,
+            lap_started: None,
+            paused_at: None,
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Spot {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Spot {
+    /// Function called from a thread to handle the Spot Drive Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Spot Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        let conf = property.conf.spot.clone();
+        let radius = *self.radius.get_or_insert(conf.pitch);
+
+        // Widen the orbit after each lap.
+        let lap_time = lap_time(radius, property.conf.motion.speed);
+        if self
+            .lap_started
+            .is_some_and(|started| started.elapsed() >= lap_time)
+        {
+            let radius = radius + conf.pitch;
+            if conf.max_radius < radius {
+                state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                let _ = base::mission_complete(state, device);
+                self.radius = None;
+                self.lap_started = None;
+                return;
+            }
+            log::debug!("Spot Widened. radius: {}", radius);
+            self.radius = Some(radius);
+            self.lap_started = Some(Instant::now());
+            state.rest = 1.0 - radius / conf.max_radius.max(conf.pitch);
+        }
+
+        // The biggest marker is the closest one, which is taken as the center.
+        let detections = sort::big(detections);
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+        let marker = detections.first().cloned().unwrap_or_default();
+        log::debug!("Center Marker Selected: {:?}", marker);
+
+        let target = target_height(self.radius.unwrap_or(radius), conf.height_at_1m);
+        let action = assess_situation(state, &marker, target);
+        log::debug!("Action is {:?}", action);
+
+        // Turn on the work motor
+        device.inner.clone().lock().unwrap().work_motor.cw();
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::SteerLeft) | Some(ActPhase::SteerRight) | Some(ActPhase::Orbit) => {
+                state.turn_count = 0;
+                self.lap_started.get_or_insert_with(Instant::now);
+                let binding = device.inner.clone();
+                let mut device_lock = binding.lock().unwrap();
+                match action {
+                    Some(ActPhase::SteerLeft) => device_lock.left(TURN_STEP_MS),
+                    Some(ActPhase::SteerRight) => device_lock.right(TURN_STEP_MS),
+                    _ => device_lock.forward(0),
+                }
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        log::debug!("End Spot Handle");
+    }
+
+    fn pause(&mut self, _device: &mut Roktrack) {
+        self.paused_at = Some(Instant::now());
+    }
+
+    /// The lap is timed without the pause.
+    fn resume(&mut self, _device: &mut Roktrack) {
+        if let (Some(paused_at), Some(started)) = (self.paused_at.take(), self.lap_started) {
+            self.lap_started = Some(started + paused_at.elapsed());
+        }
+    }
+}
+
+/// Apparent height of the marker (ratio to the image height) at the radius in meters.
+pub fn target_height(radius: f32, height_at_1m: f32) -> f32 {
+    height_at_1m / radius.max(0.1)
+}
+
+/// Time to drive once around the circle.
+pub fn lap_time(radius: f32, speed: f32) -> Duration {
+    if speed <= 0.0 {
+        Duration::ZERO
+    } else {
+        Duration::from_secs_f32(2.0 * std::f32::consts::PI * radius.max(0.0) / speed)
+    }
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Spot Drive Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnKeep,
+    StartTurn,
+    SteerLeft,
+    SteerRight,
+    Orbit,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+///
+/// Too close to the marker, it is kept nearer to the image edge so that the robot moves away,
+/// and too far, nearer to the center.
+fn assess_situation(state: &RoktrackState, marker: &Detection, target: f32) -> Option<ActPhase> {
+    if marker.h == 0 {
+        return if 10 <= state.turn_count {
+            Some(ActPhase::TurnCountExceeded)
+        } else if 0 < state.turn_count {
+            Some(ActPhase::TurnKeep)
+        } else {
+            Some(ActPhase::StartTurn)
+        };
+    }
+    let width = state.img_width as f32;
+    let radial_error = (marker.h as f32 / state.img_height as f32 - target) / target;
+    let side = (SIDE_POSITION - radial_error * SIDE_POSITION).clamp(0.0, 0.5);
+    // Horizontal position of the marker seen from the side it is kept on
+    let x = match state.phase {
+        Phase::CCW => marker.xc / width,
+        Phase::CW => 1.0 - marker.xc / width,
+    };
+    let towards_marker = match state.phase {
+        Phase::CCW => ActPhase::SteerLeft,
+        Phase::CW => ActPhase::SteerRight,
+    };
+    let away_from_marker = match state.phase {
+        Phase::CCW => ActPhase::SteerRight,
+        Phase::CW => ActPhase::SteerLeft,
+    };
+    // Turning away from the marker moves it towards the image edge.
+    if side + STEER_TOLERANCE < x {
+        Some(away_from_marker)
+    } else if x < side - STEER_TOLERANCE {
+        Some(towards_marker)
+    } else {
+        Some(ActPhase::Orbit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orbit_test() {
+        assert_eq!(target_height(2.0, 0.5), 0.25);
+        assert_eq!(lap_time(1.0, 0.0), Duration::ZERO);
+        assert!((lap_time(1.0, std::f32::consts::PI).as_secs_f32() - 2.0).abs() < 1e-3);
+
+        let state = RoktrackState::new(); // CCW, 320 x 240
+        let marker = |xc: f32, h: u32| Detection {
+            xc,
+            h,
+            ..Default::default()
+        };
+        // At the radius and on the left side
+        assert_eq!(
+            assess_situation(&state, &marker(64.0, 60), 0.25),
+            Some(ActPhase::Orbit)
+        );
+        // Heading to the marker
+        assert_eq!(
+            assess_situation(&state, &marker(160.0, 60), 0.25),
+            Some(ActPhase::SteerRight)
+        );
+        // Too close, move away
+        assert_eq!(
+            assess_situation(&state, &marker(64.0, 120), 0.25),
+            Some(ActPhase::SteerRight)
+        );
+        // Too far, move closer
+        assert_eq!(
+            assess_situation(&state, &marker(64.0, 30), 0.25),
+            Some(ActPhase::SteerLeft)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker(0.0, 0), 0.25),
+            Some(ActPhase::StartTurn)
+        );
+    }
+}
@@ -0,0 +1,266 @@
+//! Stripe Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// StartTurn / TurnKeep * n  <- Search for the start marker.
+//    |
+// Proceed * n
+//    |
+// ReachMarker  <- Plan the lanes from here.
+//    |
+// Stripe * n  <- Forward along a lane, then a U-turn offset by the lane width.
+//    |
+// MissionComplete
+//
+// The marker is placed at a corner of the area, the first lane runs straight ahead of it.
+// The first U-turn follows the lap direction (left for CCW), then they alternate.
+
+use std::sync::mpsc::Sender;
+
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::{Phase, RoktrackState},
+    util::conf::{Motion, Stripe as StripeConf},
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Height of the start marker in the image (ratio) at which the first lane starts.
+const REACH_HEIGHT_RATIO: f32 = 0.9;
+
+pub struct Stripe {
+    plan: Option<ManeuverPlan>,
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Stripe {
+    pub fn new() -> Self {
+        Self {
+            plan: None,
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Stripe {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Stripe {
+    /// Function called from a thread to handle the Stripe Drive Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Stripe Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Drive the lanes once planned.
+        if let Some(plan) = self.plan.as_mut() {
+            if !maneuver::is_idle(device) {
+                return;
+            }
+            device.inner.clone().lock().unwrap().work_motor.cw();
+            match plan.next_step() {
+                Some(step) => maneuver::start(device, step),
+                None => {
+                    state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                    let _ = base::mission_complete(state, device);
+                    self.plan = None;
+                    return;
+                }
+            }
+            state.rest = plan.rest();
+            log::debug!("Stripe. rest: {}", state.rest);
+            return;
+        }
+
+        // Otherwise head for the start marker.
+        let detections = sort::big(detections);
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+        let marker = detections.first().cloned().unwrap_or_default();
+        log::debug!("Start Marker Selected: {:?}", marker);
+
+        let action = assess_situation(state, &marker);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::ReachMarker) => {
+                device.inner.clone().lock().unwrap().pause();
+                state.msg = ChildMsg::to_u8(ChildMsg::ReachTarget);
+                state.turn_count = 0;
+                let plan = plan(&property.conf.stripe, &property.conf.motion, &state.phase);
+                log::info!(
+                    "Start Stripe. lane_width: {}, lane_length: {}, lanes: {}",
+                    property.conf.stripe.lane_width,
+                    property.conf.stripe.lane_length,
+                    property.conf.stripe.lanes
+                );
+                self.plan = Some(plan);
+                Ok(())
+            }
+            Some(ActPhase::Proceed) => {
+                state.turn_count = 0;
+                base::proceed(state, device, marker, tx)
+            }
+            None => Ok(()),
+        };
+        log::debug!("End Stripe Handle");
+    }
+
+    /// Keep the rest of the move cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some(plan) = self.plan.as_mut() {
+            plan.interrupt(maneuver::remaining(device));
+        }
+    }
+}
+
+/// Plans the lanes. U-turns alternate, starting in the lap direction.
+fn plan(conf: &StripeConf, motion: &Motion, phase: &Phase) -> ManeuverPlan {
+    let lane = Maneuver::Forward(maneuver::travel_time(conf.lane_length, motion.speed));
+    let offset = Maneuver::Forward(maneuver::travel_time(conf.lane_width, motion.speed));
+    let mut left = *phase == Phase::CCW;
+    let mut steps = vec![];
+    for i in 0..conf.lanes {
+        steps.push(lane);
+        if i + 1 == conf.lanes {
+            break;
+        }
+        let turn = match left {
+            true => Maneuver::Left(motion.quarter_turn_ms),
+            false => Maneuver::Right(motion.quarter_turn_ms),
+        };
+        steps.extend([turn, offset, turn]);
+        left = !left;
+    }
+    ManeuverPlan::new(steps)
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Stripe Drive Pilot
+///
+#[derive(Debug, Clone)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnKeep,
+    StartTurn,
+    ReachMarker,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(state: &RoktrackState, marker: &Detection) -> Option<ActPhase> {
+    if marker.h == 0 {
+        if 10 <= state.turn_count {
+            Some(ActPhase::TurnCountExceeded)
+        } else if 0 < state.turn_count {
+            Some(ActPhase::TurnKeep)
+        } else {
+            Some(ActPhase::StartTurn)
+        }
+    } else if state.img_height as f32 * REACH_HEIGHT_RATIO <= marker.h as f32 {
+        Some(ActPhase::ReachMarker)
+    } else {
+        Some(ActPhase::Proceed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn plan_test() {
+        let conf = StripeConf {
+            lane_width: 0.5,
+            lane_length: 3.0,
+            lanes: 3,
+        };
+        let motion = Motion {
+            speed: 0.5,
+            quarter_turn_ms: 1000,
+        };
+        let mut plan = plan(&conf, &motion, &Phase::CCW);
+        let mut steps = vec![];
+        while let Some(step) = plan.next_step() {
+            steps.push(step);
+        }
+        assert_eq!(
+            steps,
+            vec![
+                Maneuver::Forward(6000),
+                Maneuver::Left(1000),
+                Maneuver::Forward(1000),
+                Maneuver::Left(1000),
+                Maneuver::Forward(6000),
+                Maneuver::Right(1000),
+                Maneuver::Forward(1000),
+                Maneuver::Right(1000),
+                Maneuver::Forward(6000),
+            ]
+        );
+    }
+}
@@ -0,0 +1,194 @@
+//! Stuck Detection
+//!
+//! The robot is stuck when it has been commanded forward but neither the scene, the position nor
+//! the pose by the visual odometry has changed for a while, e.g. a wheel spinning in a hollow or the
+//! body caught on a root.
+//! The scene is the set of detections, compared by the class, the center and the height.
+//! Without anything in sight, a position or the odometry, nothing tells whether it's moving, so it's
+//! never stuck.
+//! The recoveries are counted until the drive goes on for `seconds` after the last one.
+
+use crate::module::{
+    util::conf::Stuck,
+    vision::{detector::Detection, odometry::Pose},
+};
+
+// Distance in m regarded as a move of the position.
+const MOVED_M: f64 = 0.5;
+// Turn in degrees regarded as a move of the pose.
+const TURNED_DEG: f32 = 15.0;
+// Length of a degree of latitude in m.
+const DEGREE_M: f64 = 111_000.0;
+
+/// Watches the scene for changes.
+#[derive(Debug, Clone, Default)]
+pub struct StuckMonitor {
+    scene: Vec<(u32, f32, f32)>, // Class, center and height (ratio to the image)
+    position: Option<(f64, f64)>, // Latitude and longitude
+    odometry: Option<Pose>,      // Pose by the visual odometry
+    changed: u64,                // Time of the last change in ms
+    recovered: Option<u64>,      // Time of the last recovery in ms
+    pub attempts: u8,            // Recoveries in a row
+}
+
+impl StuckMonitor {
+    /// Observes the scene at the time in ms.
+    pub fn observe(
+        &mut self,
+        dets: &[Detection],
+        position: Option<(f64, f64)>,
+        odometry: Option<Pose>,
+        img: (u32, u32),
+        conf: &Stuck,
+        now: u64,
+    ) {
+        let mut scene: Vec<(u32, f32, f32)> = dets
+            .iter()
+            .map(|det| {
+                (
+                    det.cls,
+                    det.xc / img.0.max(1) as f32,
+                    det.h as f32 / img.1.max(1) as f32,
+                )
+            })
+            .collect();
+        scene.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let blind = scene.is_empty() && position.is_none() && odometry.is_none();
+        if blind
+            || scene_changed(&self.scene, &scene, conf.tolerance)
+            || position_changed(self.position, position)
+            || odometry_changed(self.odometry, odometry)
+        {
+            self.scene = scene;
+            self.position = position;
+            self.odometry = odometry;
+            self.changed = now;
+            // The rotation of the recovery itself changes the scene.
+            if self
+                .recovered
+                .map_or(true, |at| conf.seconds * 1000 <= now.saturating_sub(at))
+            {
+                self.recovered = None;
+                self.attempts = 0;
+            }
+        }
+    }
+
+    /// Whether forward has been commanded since `forward_since` without a change for `seconds`.
+    pub fn is_stuck(&self, forward_since: Option<u64>, now: u64, seconds: u64) -> bool {
+        let limit = seconds * 1000;
+        0 < seconds
+            && forward_since.is_some_and(|since| limit <= now.saturating_sub(since))
+            && limit <= now.saturating_sub(self.changed)
+    }
+
+    /// Starts waiting again after a recovery.
+    pub fn recovered(&mut self, now: u64) {
+        self.changed = now;
+        self.recovered = Some(now);
+        self.attempts = self.attempts.saturating_add(1);
+    }
+}
+
+/// Whether any detection has appeared, disappeared or moved beyond the tolerance.
+fn scene_changed(last: &[(u32, f32, f32)], scene: &[(u32, f32, f32)], tolerance: f32) -> bool {
+    last.len() != scene.len()
+        || last.iter().zip(scene).any(|(a, b)| {
+            a.0 != b.0 || tolerance < (a.1 - b.1).abs() || tolerance < (a.2 - b.2).abs()
+        })
+}
+
+/// Whether the position has moved.
+fn position_changed(last: Option<(f64, f64)>, position: Option<(f64, f64)>) -> bool {
+    match (last, position) {
+        (Some((lat1, lon1)), Some((lat2, lon2))) => {
+            let dy = (lat2 - lat1) * DEGREE_M;
+            let dx = (lon2 - lon1) * DEGREE_M * lat1.to_radians().cos();
+            MOVED_M < (dx * dx + dy * dy).sqrt()
+        }
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+/// Whether the pose by the visual odometry has moved or turned.
+fn odometry_changed(last: Option<Pose>, odometry: Option<Pose>) -> bool {
+    match (last, odometry) {
+        (Some(last), Some(pose)) => {
+            MOVED_M < last.distance(&pose) as f64 || TURNED_DEG < last.turn(&pose)
+        }
+        (None, None) => false,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stuck_test() {
+        let marker = Detection {
+            xc: 160.0,
+            h: 48,
+            ..Default::default()
+        };
+        let conf = Stuck::default();
+        let mut monitor = StuckMonitor::default();
+        monitor.observe(&[marker.clone()], None, None, (320, 240), &conf, 0);
+        // The same scene while driving forward.
+        monitor.observe(&[marker.clone()], None, None, (320, 240), &conf, 5000);
+        assert!(!monitor.is_stuck(Some(0), 5000, 10));
+        assert!(monitor.is_stuck(Some(0), 10000, 10));
+        // Not driving forward long enough, or disabled.
+        assert!(!monitor.is_stuck(Some(5000), 10000, 10));
+        assert!(!monitor.is_stuck(None, 10000, 10));
+        assert!(!monitor.is_stuck(Some(0), 10000, 0));
+        // A recovery waits again.
+        monitor.recovered(10000);
+        assert_eq!(monitor.attempts, 1);
+        assert!(!monitor.is_stuck(Some(0), 15000, 10));
+        let turned = Detection {
+            xc: 40.0,
+            ..marker.clone()
+        };
+        monitor.observe(&[turned.clone()], None, None, (320, 240), &conf, 11000);
+        assert_eq!(monitor.attempts, 1);
+        // The marker grows as the robot moves.
+        let closer = Detection {
+            h: 60,
+            ..turned.clone()
+        };
+        monitor.observe(&[closer], None, None, (320, 240), &conf, 20000);
+        assert_eq!(monitor.attempts, 0);
+        assert!(!monitor.is_stuck(Some(0), 25000, 10));
+        // Nothing in sight tells nothing.
+        monitor.observe(&[], None, None, (320, 240), &conf, 40000);
+        assert!(!monitor.is_stuck(Some(0), 45000, 10));
+        // But the odometry does.
+        let pose = Pose::default();
+        monitor.observe(&[], None, Some(pose), (320, 240), &conf, 45000);
+        monitor.observe(&[], None, Some(pose), (320, 240), &conf, 50000);
+        assert!(monitor.is_stuck(Some(0), 55000, 10));
+        let turned = Pose {
+            heading: 30.0,
+            ..pose
+        };
+        monitor.observe(&[], None, Some(turned), (320, 240), &conf, 55000);
+        assert!(!monitor.is_stuck(Some(0), 60000, 10));
+    }
+
+    #[test]
+    fn position_changed_test() {
+        assert!(!position_changed(None, None));
+        assert!(position_changed(None, Some((35.0, 135.0))));
+        assert!(!position_changed(
+            Some((35.0, 135.0)),
+            Some((35.000001, 135.0))
+        ));
+        assert!(position_changed(
+            Some((35.0, 135.0)),
+            Some((35.00001, 135.0))
+        ));
+    }
+}
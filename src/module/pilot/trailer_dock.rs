@@ -0,0 +1,342 @@
+//! Trailer Docking Assist Pilot
+//!
+
+// # Normal flow of act phase
+//
+// StartTurn / TurnKeep * n  <- Search for the ramp marker.
+//    |
+// AlignLeft / AlignRight  <- Center the marker, so that the robot faces the ramp squarely.
+//    |
+// Approach * n  <- Drive slowly to the foot of the ramp.
+//    |
+// Climb  <- Drive up the ramp (or back in after a half turn) and report ClimbUp.
+//    |
+// Seat * n  <- Wait for the robot to be level on the trailer, and report TarailerPrepaired.
+//
+// TurnCountExceeded  <- The process is stopped because it was not found after the specified number of turns.
+// The robot is halted when it isn't seated after `settle_frames`, e.g. stuck halfway on the ramp.
+// Without the IMU, the robot is taken as seated at the end of the climb.
+// Backing in with the rear camera, the marker is searched for and approached behind by it, without
+// the half turn, and the people are watched behind.
+
+use std::sync::mpsc::Sender;
+
+use super::maneuver::{self, Maneuver, ManeuverPlan};
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::RoktrackState,
+    util::conf::{Motion, TrailerDock as TrailerDockConf},
+    util::init::RoktrackProperty,
+    vision::detector::{sort, Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Duration of an aligning turn in ms.
+const TURN_STEP_MS: u64 = 100;
+
+pub struct TrailerDock {
+    climb: Option<ManeuverPlan>, // Moves up the ramp
+    settle: Option<u8>,          // Frames waited for the robot to be seated
+    risks: RiskEngine,           // System risks to check before driving
+}
+
+impl TrailerDock {
+    pub fn new() -> Self {
+        Self {
+            climb: None,
+            settle: None,
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for TrailerDock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for TrailerDock {
+    /// Function called from a thread to handle the Trailer Docking Assist Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start TrailerDock Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Look behind by the rear camera when backing in.
+        let conf = property.conf.trailer_dock.clone();
+        let backing = conf.direction == "backward" && property.conf.rear_camera.enabled;
+        let mut rear = state.rear.clone();
+        let detections = match backing {
+            true => &mut rear[..],
+            false => detections,
+        };
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // No mowing on the trailer
+        device.inner.clone().lock().unwrap().work_motor.stop();
+
+        // Climb the ramp once at its foot.
+        if let Some(plan) = self.climb.as_mut() {
+            if !maneuver::is_idle(device) {
+                return;
+            }
+            match plan.next_step() {
+                Some(step) => {
+                    state.msg = ChildMsg::to_u8(ChildMsg::ClimbUp);
+                    maneuver::start(device, step);
+                }
+                None => {
+                    device.inner.clone().lock().unwrap().pause();
+                    self.climb = None;
+                    self.settle = Some(0);
+                }
+            }
+            return;
+        }
+
+        // Confirm the seating before signalling the parent.
+        if let Some(frames) = self.settle {
+            let tilt = device.inner.clone().lock().unwrap().imu.tilt();
+            log::debug!("Seating. tilt: {:?}, frames: {}", tilt, frames);
+            if is_seated(tilt, conf.seated_tilt) {
+                log::info!("Seated on the trailer.");
+                state.msg = ChildMsg::to_u8(ChildMsg::TarailerPrepaired);
+                let _ = base::mission_complete(state, device);
+                *self = Self::new();
+            } else if conf.settle_frames <= frames + 1 {
+                log::warn!("Not Seated on the trailer. tilt: {:?}", tilt);
+                state.state = false;
+                state.msg = ChildMsg::to_u8(ChildMsg::Halt);
+                device.inner.clone().lock().unwrap().stop();
+                tx.send(VisionMgmtCommand::Off).unwrap();
+                *self = Self::new();
+            } else {
+                self.settle = Some(frames + 1);
+            }
+            return;
+        }
+
+        // The biggest marker (of the number) is taken as the ramp.
+        let detections = sort::big(detections);
+        let detections =
+            RoktrackClasses::filter(&mut detections.clone(), (RoktrackClasses::PYLON).to_u32());
+        let marker = detections
+            .into_iter()
+            .find(|det| conf.ramp_marker == 0 || det.ids.contains(&conf.ramp_marker))
+            .unwrap_or_default();
+        log::debug!("Ramp Marker Selected: {:?}", marker);
+
+        let action = assess_situation(state, &marker, &conf);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        let _ = match action {
+            Some(ActPhase::TurnCountExceeded) => base::halt(state, device, tx),
+            Some(ActPhase::TurnKeep) => base::keep_turn(state, device, tx),
+            Some(ActPhase::StartTurn) => base::start_turn(state, device),
+            Some(ActPhase::AlignLeft) => {
+                state.turn_count = 0;
+                device.inner.clone().lock().unwrap().left(TURN_STEP_MS);
+                Ok(())
+            }
+            Some(ActPhase::AlignRight) => {
+                state.turn_count = 0;
+                device.inner.clone().lock().unwrap().right(TURN_STEP_MS);
+                Ok(())
+            }
+            Some(ActPhase::Approach) => {
+                state.turn_count = 0;
+                match backing {
+                    true => device.inner.clone().lock().unwrap().backward(0),
+                    false => device.inner.clone().lock().unwrap().forward(0),
+                }
+                Ok(())
+            }
+            Some(ActPhase::Climb) => {
+                log::info!("Climb the ramp. direction: {}", conf.direction);
+                device.inner.clone().lock().unwrap().pause();
+                let speed = device.inner.clone().lock().unwrap().speed.cruise;
+                self.climb = Some(climb_plan(&conf, &property.conf.motion, speed, backing));
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        log::debug!("End TrailerDock Handle");
+    }
+
+    /// Keep the rest of the climb cut short by the pause.
+    fn pause(&mut self, device: &mut Roktrack) {
+        if let Some(plan) = self.climb.as_mut() {
+            plan.interrupt(maneuver::remaining(device));
+        }
+    }
+}
+
+/// Plans the climb at the speed ratio of the profile.
+/// Backing in starts with a half turn, unless the ramp is already behind, found by the rear camera.
+fn climb_plan(conf: &TrailerDockConf, motion: &Motion, speed: f64, rear: bool) -> ManeuverPlan {
+    let ms = maneuver::travel_time(conf.climb_distance, motion.speed * speed as f32);
+    match conf.direction.as_str() {
+        "backward" if rear => ManeuverPlan::new(vec![Maneuver::Backward(ms)]),
+        "backward" => ManeuverPlan::new(vec![
+            Maneuver::Left(motion.quarter_turn_ms * 2),
+            Maneuver::Backward(ms),
+        ]),
+        _ => ManeuverPlan::new(vec![Maneuver::Forward(ms)]),
+    }
+}
+
+/// Whether the robot is level on the trailer. Without the IMU, it's taken as seated.
+fn is_seated(tilt: Option<(f32, f32)>, seated_tilt: f32) -> bool {
+    tilt.map_or(true, |(pitch, roll)| {
+        pitch.abs() < seated_tilt && roll.abs() < seated_tilt
+    })
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Trailer Docking Assist Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    TurnCountExceeded,
+    TurnKeep,
+    StartTurn,
+    AlignLeft,
+    AlignRight,
+    Approach,
+    Climb,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(
+    state: &RoktrackState,
+    marker: &Detection,
+    conf: &TrailerDockConf,
+) -> Option<ActPhase> {
+    let offset = marker.xc / state.img_width.max(1) as f32 - 0.5;
+    if marker.h == 0 {
+        if 10 <= state.turn_count {
+            Some(ActPhase::TurnCountExceeded)
+        } else if 0 < state.turn_count {
+            Some(ActPhase::TurnKeep)
+        } else {
+            Some(ActPhase::StartTurn)
+        }
+    } else if conf.align_tolerance < offset {
+        Some(ActPhase::AlignRight)
+    } else if offset < -conf.align_tolerance {
+        Some(ActPhase::AlignLeft)
+    } else if state.img_height as f32 * conf.ramp_height <= marker.h as f32 {
+        Some(ActPhase::Climb)
+    } else {
+        Some(ActPhase::Approach)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailer_dock_test() {
+        let state = RoktrackState::new();
+        let conf = TrailerDockConf::default();
+        let marker = |xc: f32, h: u32| Detection {
+            xc,
+            h,
+            ..Default::default()
+        };
+        assert_eq!(
+            assess_situation(&state, &marker(0.0, 0), &conf),
+            Some(ActPhase::StartTurn)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker(240.0, 100), &conf),
+            Some(ActPhase::AlignRight)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker(80.0, 100), &conf),
+            Some(ActPhase::AlignLeft)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker(165.0, 100), &conf),
+            Some(ActPhase::Approach)
+        );
+        assert_eq!(
+            assess_situation(&state, &marker(160.0, 200), &conf),
+            Some(ActPhase::Climb)
+        );
+
+        let motion = Motion {
+            speed: 0.5,
+            quarter_turn_ms: 1000,
+        };
+        let mut plan = climb_plan(&conf, &motion, 0.5, false);
+        assert_eq!(plan.next_step(), Some(Maneuver::Forward(6000)));
+        let backward = TrailerDockConf {
+            direction: "backward".to_string(),
+            ..conf.clone()
+        };
+        let mut plan = climb_plan(&backward, &motion, 1.0, false);
+        assert_eq!(plan.next_step(), Some(Maneuver::Left(2000)));
+        assert_eq!(plan.next_step(), Some(Maneuver::Backward(3000)));
+        // Already facing away with the rear camera
+        let mut plan = climb_plan(&backward, &motion, 1.0, true);
+        assert_eq!(plan.next_step(), Some(Maneuver::Backward(3000)));
+        assert_eq!(plan.next_step(), None);
+
+        assert!(is_seated(None, 3.0));
+        assert!(is_seated(Some((1.0, -2.0)), 3.0));
+        assert!(!is_seated(Some((8.0, 0.0)), 3.0));
+    }
+}
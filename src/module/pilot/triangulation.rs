@@ -0,0 +1,176 @@
+//! Multi-Marker Triangulation
+//!
+//! With two or more pylons in sight, the position of the robot can be fixed relative to them. The
+//! distance to a pylon follows from its height in the image, and the bearing from its position.
+//! The position is taken in the frame of the target pylon (the origin) and another one in sight
+//! (on the x axis), so that it doesn't depend on the heading of the robot.
+//!
+//! Fill takes the fix at the start of a pass, and keeps to the line from there to the target by
+//! shifting its aim. This keeps the lanes straight and brings the robot back to its lane after a
+//! detour, instead of heading to the target from wherever the detour ended. The correction fades
+//! as the target nears, so that the robot passes it as without the fix.
+
+use crate::module::{
+    pilot::RoktrackState,
+    util::conf::Triangulation,
+    vision::detector::{Detection, RoktrackClasses},
+};
+
+// Change of the distance between the pylons (ratio) beyond which the other pylon is another one.
+const BASELINE_TOLERANCE: f32 = 0.3;
+
+/// Position of the robot relative to two pylons.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub position: (f32, f32), // Position in the frame of the target and the reference in m
+    pub baseline: f32,        // Distance between the target and the reference in m
+}
+
+/// Position of a pylon relative to the robot in m (right, ahead), by the pinhole camera model.
+pub fn locate(det: &Detection, img_width: u32, conf: &Triangulation) -> Option<(f32, f32)> {
+    if det.h == 0 || img_width == 0 {
+        return None;
+    }
+    let focal = img_width as f32 / 2.0 / (conf.fov.to_radians() / 2.0).tan();
+    let ahead = focal * conf.marker_height / det.h as f32;
+    let right = ahead * (det.xc - img_width as f32 / 2.0) / focal;
+    Some((right, ahead))
+}
+
+/// Fixes the position of the robot from the positions of the target and the reference pylons
+/// relative to it. None if they are at the same place.
+pub fn fix(target: (f32, f32), reference: (f32, f32)) -> Option<Fix> {
+    let (dx, dy) = (reference.0 - target.0, reference.1 - target.1);
+    let baseline = dx.hypot(dy);
+    if baseline < f32::EPSILON {
+        return None;
+    }
+    // Axes of the frame: x toward the reference, y a quarter turn counterclockwise from it.
+    let x_axis = (dx / baseline, dy / baseline);
+    let y_axis = (-x_axis.1, x_axis.0);
+    let robot = (-target.0, -target.1);
+    Some(Fix {
+        position: (
+            robot.0 * x_axis.0 + robot.1 * x_axis.1,
+            robot.0 * y_axis.0 + robot.1 * y_axis.1,
+        ),
+        baseline,
+    })
+}
+
+/// Lane of the current pass, from the fix at its start to the target.
+#[derive(Debug, Clone, Default)]
+pub struct Lane {
+    start: Option<Fix>, // Fix at the start of the pass
+}
+
+impl Lane {
+    /// Observes the fix while tracking the target. Returns the distance off the lane in m
+    /// (positive: on the left of it), faded as the target nears.
+    pub fn observe(&mut self, tracking: bool, fix: Option<Fix>) -> Option<f32> {
+        if !tracking {
+            self.start = None;
+            return None;
+        }
+        let fix = fix?;
+        let Some(start) = self.start else {
+            log::debug!("Lane Fixed. start: {:?}", fix);
+            self.start = Some(fix);
+            return Some(0.0);
+        };
+        if BASELINE_TOLERANCE * start.baseline < (fix.baseline - start.baseline).abs() {
+            log::debug!("Another Pylon as the Reference. Ignore the fix.");
+            return None;
+        }
+        let (s, p) = (start.position, fix.position);
+        let length = s.0.hypot(s.1);
+        if length < f32::EPSILON {
+            return None;
+        }
+        // Along the lane toward the target at the origin
+        let along = (-s.0 / length, -s.1 / length);
+        let off = along.0 * (p.1 - s.1) - along.1 * (p.0 - s.0);
+        let fade = (p.0.hypot(p.1) / length).min(1.0);
+        Some(off * fade)
+    }
+
+    /// Aims to keep to the lane, with the nearest other pylon in sight as the reference.
+    /// Returns the x coordinate of the target to steer to.
+    pub fn keep(
+        &mut self,
+        state: &RoktrackState,
+        marker: &Detection,
+        dets: &[Detection],
+        conf: &Triangulation,
+    ) -> f32 {
+        let reference = dets
+            .iter()
+            .filter(|det| det.cls == RoktrackClasses::PYLON.to_u32())
+            .filter(|det| {
+                (det.x1, det.y1, det.x2, det.y2) != (marker.x1, marker.y1, marker.x2, marker.y2)
+            })
+            .max_by_key(|det| det.h);
+        let fix = match (
+            locate(marker, state.img_width, conf),
+            reference.and_then(|det| locate(det, state.img_width, conf)),
+        ) {
+            (Some(target), Some(reference)) => fix(target, reference),
+            _ => None,
+        };
+        let tracking = state.turn_count == 0 && marker.h != 0;
+        match self.observe(tracking, fix) {
+            Some(off) => aim(marker.xc, off, state.img_width, conf),
+            None => marker.xc,
+        }
+    }
+}
+
+/// Shifts the aim to steer back to the lane. Off on the left, aim more to the right.
+pub fn aim(xc: f32, off: f32, img_width: u32, conf: &Triangulation) -> f32 {
+    xc + (off * conf.gain).clamp(-conf.max_shift, conf.max_shift) * img_width as f32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn triangulation_test() {
+        let conf = Triangulation::default();
+        let width = 640;
+        let focal = width as f32 / 2.0 / (conf.fov.to_radians() / 2.0).tan();
+        // A pylon seen at (right, ahead) in m
+        let seen = |right: f32, ahead: f32| Detection {
+            xc: width as f32 / 2.0 + right * focal / ahead,
+            h: (focal * conf.marker_height / ahead).round() as u32,
+            ..Default::default()
+        };
+        let (right, ahead) = locate(&seen(1.0, 5.0), width, &conf).unwrap();
+        assert!((right - 1.0).abs() < 0.1 && (ahead - 5.0).abs() < 0.1);
+        assert_eq!(locate(&Detection::default(), width, &conf), None);
+
+        // Target straight ahead and the reference on its right.
+        let start = fix((0.0, 5.0), (2.0, 5.0)).unwrap();
+        assert!((start.position.0 - 0.0).abs() < 1e-6 && (start.position.1 + 5.0).abs() < 1e-6);
+        assert!((start.baseline - 2.0).abs() < 1e-6);
+        assert_eq!(fix((1.0, 1.0), (1.0, 1.0)), None);
+
+        let mut lane = Lane::default();
+        assert_eq!(lane.observe(true, Some(start)), Some(0.0));
+        // 0.5 m to the left after a detour, 4 m before the target, and heading askew.
+        let turned = |(x, y): (f32, f32)| {
+            let (s, c) = 0.2_f32.sin_cos();
+            (x * c - y * s, x * s + y * c)
+        };
+        let detoured = fix(turned((0.5, 4.0)), turned((2.5, 4.0))).unwrap();
+        let off = lane.observe(true, Some(detoured)).unwrap();
+        assert!((off - 0.5 * 0.8).abs() < 0.01);
+        assert!(width as f32 / 2.0 < aim(width as f32 / 2.0, off, width, &conf));
+        // The reference is another pylon.
+        let other = fix((0.5, 4.0), (6.0, 4.0)).unwrap();
+        assert_eq!(lane.observe(true, Some(other)), None);
+        // A new pass
+        lane.observe(false, None);
+        assert_eq!(lane.observe(true, Some(detoured)), Some(0.0));
+    }
+}
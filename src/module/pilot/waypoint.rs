@@ -0,0 +1,305 @@
+//! GPS Waypoint Drive Pilot
+//!
+
+// # Normal flow of act phase
+//
+// Turn * n  <- Align the compass heading with the bearing to the waypoint.
+//    |
+// Proceed * n
+//    |
+// Arrive  <- Within the arrival radius. Head for the next waypoint.
+//    |
+// MissionComplete  <- After the last waypoint.
+//
+// The position and the heading come from the telemetry (e.g. a NUS sensor pod).
+// Vision is only used for safety, so markers are not needed.
+//
+// # Waypoint file
+// One `<lat>,<lon>` in degrees per line. Empty lines and lines starting with `#` are skipped.
+
+use std::path::Path;
+use std::sync::mpsc::Sender;
+
+use super::{
+    person,
+    risk::{self, RiskEngine},
+    PilotHandler,
+};
+use crate::module::{
+    com::ChildMsg,
+    device::motor::Motor,
+    device::{Chassis, Roktrack},
+    pilot::base,
+    pilot::RoktrackState,
+    util::conf::Waypoint as WaypointConf,
+    util::init::RoktrackProperty,
+    vision::detector::{Detection, FilterClass, RoktrackClasses},
+    vision::VisionMgmtCommand,
+};
+
+// Mean radius of the earth in meters.
+const EARTH_RADIUS: f64 = 6_371_000.0;
+
+// Duration of a correcting turn in ms.
+const TURN_STEP_MS: u64 = 200;
+
+pub struct Waypoint {
+    waypoints: Option<Vec<(f64, f64)>>,
+    current: usize,
+    risks: RiskEngine, // System risks to check before driving
+}
+
+impl Waypoint {
+    pub fn new() -> Self {
+        Self {
+            waypoints: None,
+            current: 0,
+            risks: risk::builtin(),
+        }
+    }
+}
+
+impl Default for Waypoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PilotHandler for Waypoint {
+    /// Function called from a thread to handle the Waypoint Drive Pilot logic
+    fn handle(
+        &mut self,
+        state: &mut RoktrackState,
+        device: &mut Roktrack,
+        detections: &mut [Detection],
+        tx: Sender<VisionMgmtCommand>,
+        property: RoktrackProperty,
+    ) {
+        log::debug!("Start Waypoint Handle");
+        // Assess and handle system safety
+        let system_risk = self.risks.handle(state, device, &property);
+        if system_risk.is_some() {
+            log::debug!("System Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Assess and handle vision safety
+        let vision_risk = match assess_vision_risk(detections, device) {
+            Some(VisionRisk::PersonDetected) => {
+                person::respond(state, device, detections, &property)
+            }
+            Some(VisionRisk::RoktrackDetected) => Some(base::stop(device)),
+            None => None,
+        };
+        if vision_risk.is_some() {
+            log::debug!("Vision Risk Exists. Continue.");
+            return; // Risk exists, continue
+        }
+
+        // Load the mission at the first call.
+        let conf = property.conf.waypoint.clone();
+        if self.waypoints.is_none() {
+            let path = Path::new(&property.path.dir.data).join(&conf.file);
+            match load(&path) {
+                Ok(waypoints) if !waypoints.is_empty() => {
+                    log::info!("Waypoints Loaded: {} ({})", waypoints.len(), path.display());
+                    self.waypoints = Some(waypoints);
+                }
+                Ok(_) => {
+                    log::error!("No Waypoints: {}", path.display());
+                    let _ = base::halt(state, device, tx);
+                    return;
+                }
+                Err(e) => {
+                    log::error!("Can't Load Waypoints: {}, {}", path.display(), e);
+                    let _ = base::halt(state, device, tx);
+                    return;
+                }
+            }
+        }
+        let waypoints = self.waypoints.clone().unwrap_or_default();
+
+        // Wait for the position and the heading.
+        let (position, heading) = match (state.telemetry.position, state.telemetry.heading) {
+            (Some(position), Some(heading)) => (position, heading),
+            _ => {
+                log::debug!("Waiting for GPS and Compass.");
+                device.inner.clone().lock().unwrap().pause();
+                return;
+            }
+        };
+
+        let target = match waypoints.get(self.current) {
+            Some(target) => *target,
+            None => {
+                state.msg = ChildMsg::to_u8(ChildMsg::MissionComplete);
+                let _ = base::mission_complete(state, device);
+                return;
+            }
+        };
+
+        // Turn on the work motor
+        device.inner.clone().lock().unwrap().work_motor.cw();
+
+        let action = assess_situation(position, heading, target, &conf);
+        log::debug!("Action is {:?}", action);
+
+        // Handle the current phase
+        match action {
+            ActPhase::Arrive => {
+                log::info!(
+                    "Waypoint Reached: {} / {}",
+                    self.current + 1,
+                    waypoints.len()
+                );
+                self.current += 1;
+                state.msg = ChildMsg::to_u8(ChildMsg::ReachTarget);
+                state.rest = 1.0 - self.current as f32 / waypoints.len() as f32;
+                device.inner.clone().lock().unwrap().pause();
+            }
+            ActPhase::TurnLeft => device.inner.clone().lock().unwrap().left(TURN_STEP_MS),
+            ActPhase::TurnRight => device.inner.clone().lock().unwrap().right(TURN_STEP_MS),
+            ActPhase::Proceed => device.inner.clone().lock().unwrap().forward(0),
+        }
+        log::debug!("End Waypoint Handle");
+    }
+}
+
+/// Loads waypoints from a file.
+pub fn load(path: &Path) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error>> {
+    parse(&std::fs::read_to_string(path)?)
+}
+
+/// Parses `<lat>,<lon>` lines.
+pub fn parse(text: &str) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error>> {
+    let mut waypoints = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (lat, lon) = line
+            .split_once(',')
+            .ok_or(format!("Invalid waypoint at line {}", i + 1))?;
+        waypoints.push((lat.trim().parse()?, lon.trim().parse()?));
+    }
+    Ok(waypoints)
+}
+
+/// Distance between two positions in meters (equirectangular approximation).
+pub fn distance(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let x = (to.1 - from.1).to_radians() * ((from.0 + to.0) / 2.0).to_radians().cos();
+    let y = (to.0 - from.0).to_radians();
+    (x * x + y * y).sqrt() * EARTH_RADIUS
+}
+
+/// Initial bearing from one position to another in degrees clockwise from north.
+pub fn bearing(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lat2) = (from.0.to_radians(), to.0.to_radians());
+    let dlon = (to.1 - from.1).to_radians();
+    let y = dlon.sin() * lat2.cos();
+    let x = lat1.cos() * lat2.sin() - lat1.sin() * lat2.cos() * dlon.cos();
+    y.atan2(x).to_degrees().rem_euclid(360.0)
+}
+
+/// Signed difference of two headings in degrees (-180 -> 180), positive clockwise.
+pub fn heading_error(heading: f64, bearing: f64) -> f64 {
+    (bearing - heading + 180.0).rem_euclid(360.0) - 180.0
+}
+
+/// Vision-related risks
+///
+#[derive(Debug, Clone)]
+enum VisionRisk {
+    PersonDetected,
+    RoktrackDetected,
+}
+/// Identify vision-related risks
+///
+fn assess_vision_risk(dets: &mut [Detection], device: &Roktrack) -> Option<VisionRisk> {
+    if !RoktrackClasses::filter(dets, RoktrackClasses::PERSON.to_u32()).is_empty() {
+        device
+            .inner
+            .clone()
+            .lock()
+            .unwrap()
+            .speak("person_detecting");
+        Some(VisionRisk::PersonDetected)
+    } else if !RoktrackClasses::filter(dets, RoktrackClasses::ROKTRACK.to_u32()).is_empty() {
+        Some(VisionRisk::RoktrackDetected)
+    } else {
+        None
+    }
+}
+/// Actions for Waypoint Drive Pilot
+///
+#[derive(Debug, Clone, PartialEq)]
+enum ActPhase {
+    Arrive,
+    TurnLeft,
+    TurnRight,
+    Proceed,
+}
+/// Function to assess the current situation and determine the appropriate action phase
+fn assess_situation(
+    position: (f64, f64),
+    heading: f32,
+    target: (f64, f64),
+    conf: &WaypointConf,
+) -> ActPhase {
+    if distance(position, target) <= conf.arrival_radius as f64 {
+        return ActPhase::Arrive;
+    }
+    let error = heading_error(heading as f64, bearing(position, target));
+    if error < -(conf.heading_tolerance as f64) {
+        ActPhase::TurnLeft
+    } else if conf.heading_tolerance as f64 <= error {
+        ActPhase::TurnRight
+    } else {
+        ActPhase::Proceed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn geometry_test() {
+        let origin = (35.0, 139.0);
+        // About 111 m per 0.001 degrees of latitude
+        let north = (35.001, 139.0);
+        assert!((distance(origin, north) - 111.2).abs() < 0.5);
+        assert!(bearing(origin, north).abs() < 0.01);
+        let east = (35.0, 139.001);
+        assert!((bearing(origin, east) - 90.0).abs() < 0.01);
+        assert_eq!(heading_error(350.0, 10.0), 20.0);
+        assert_eq!(heading_error(10.0, 350.0), -20.0);
+
+        let conf = WaypointConf::default();
+        assert_eq!(
+            assess_situation(origin, 0.0, north, &conf),
+            ActPhase::Proceed
+        );
+        assert_eq!(
+            assess_situation(origin, 0.0, east, &conf),
+            ActPhase::TurnRight
+        );
+        assert_eq!(
+            assess_situation(origin, 180.0, east, &conf),
+            ActPhase::TurnLeft
+        );
+        assert_eq!(
+            assess_situation(origin, 0.0, origin, &conf),
+            ActPhase::Arrive
+        );
+    }
+
+    #[test]
+    fn parse_test() {
+        let text = "# mission\n35.0, 139.0\n\n35.001,139.001\n";
+        assert_eq!(parse(text).unwrap(), vec![(35.0, 139.0), (35.001, 139.001)]);
+        assert!(parse("35.0").is_err());
+        assert!(parse("north,139.0").is_err());
+    }
+}
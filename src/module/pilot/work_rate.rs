@@ -0,0 +1,120 @@
+//! Work Rate Estimation
+//!
+//! Fill-type pilots cover the lane width along the distance they drive forward.
+//! The distance is estimated from the time driving forward at the cruise speed, so the area is
+//! a rough figure, but good enough to compare the jobs and to tell when one will be finished.
+//! The completion time is extrapolated from the progress (`rest`) so far.
+
+use super::Modes;
+use crate::module::{com::telemetry::Work, util::conf::Config};
+
+/// Distance and time of the current job.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkRate {
+    distance: f64,     // Distance driven forward in m
+    elapsed: u64,      // Time driving in ms
+    last: Option<u64>, // Time of the last observation in ms
+}
+
+impl WorkRate {
+    /// Observes the drive at the time in ms. The speed in m/s counts only while driving forward.
+    pub fn observe(&mut self, driving: bool, forward: bool, speed: f64, now: u64) {
+        if let Some(last) = self.last.filter(|_| driving) {
+            let dt = now.saturating_sub(last);
+            self.elapsed += dt;
+            if forward {
+                self.distance += speed * dt as f64 / 1000.0;
+            }
+        }
+        self.last = driving.then_some(now);
+    }
+
+    /// Covered area in m².
+    pub fn area(&self, lane_width: f32) -> f64 {
+        self.distance * lane_width as f64
+    }
+
+    /// Covered area per hour in m².
+    pub fn area_per_hour(&self, lane_width: f32) -> f64 {
+        if self.elapsed == 0 {
+            0.0
+        } else {
+            self.area(lane_width) / (self.elapsed as f64 / 3_600_000.0)
+        }
+    }
+
+    /// Remaining time in ms, extrapolated from the remaining work (1.0 -> 0.0).
+    pub fn remaining(&self, rest: f32) -> Option<u64> {
+        let done = (1.0 - rest.clamp(0.0, 1.0)) as f64;
+        (0.0 < done && 0 < self.elapsed).then(|| (self.elapsed as f64 * (1.0 - done) / done) as u64)
+    }
+
+    /// Summary for the telemetry.
+    pub fn summary(&self, lane_width: f32, rest: f32) -> Work {
+        Work {
+            area: self.area(lane_width).min(u16::MAX as f64) as u16,
+            area_per_hour: self.area_per_hour(lane_width).min(u16::MAX as f64) as u16,
+            eta_min: self
+                .remaining(rest)
+                .map(|ms| (ms / 60_000).min(u16::MAX as u64) as u16),
+        }
+    }
+}
+
+/// Whether the mode covers the ground in lanes, whose work rate is estimated.
+pub fn is_fill_type(mode: Modes) -> bool {
+    matches!(
+        mode,
+        Modes::Fill
+            | Modes::OneWay
+            | Modes::Around
+            | Modes::Spiral
+            | Modes::Stripe
+            | Modes::PerimeterTrim
+            | Modes::Spot
+            | Modes::EdgeFollow
+            | Modes::Orchard
+    )
+}
+
+/// Lane width of the mode in m.
+pub fn lane_width(mode: Modes, conf: &Config) -> f32 {
+    match mode {
+        Modes::Stripe => conf.stripe.lane_width,
+        Modes::Spiral => conf.spiral.pitch,
+        _ => conf.work_rate.lane_width,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn work_rate_test() {
+        let mut work = WorkRate::default();
+        work.observe(true, true, 0.5, 0);
+        // 10 minutes forward at 0.5 m/s, and 10 minutes turning.
+        work.observe(true, true, 0.5, 600_000);
+        work.observe(true, false, 0.5, 1_200_000);
+        // Not counted while off.
+        work.observe(false, true, 0.5, 1_800_000);
+        work.observe(true, true, 0.5, 2_400_000);
+        assert!((work.area(0.5) - 150.0).abs() < 1e-6);
+        assert!((work.area_per_hour(0.5) - 450.0).abs() < 1e-6);
+        // A quarter done in 20 minutes, 60 minutes to go.
+        assert_eq!(work.remaining(0.75), Some(3_600_000));
+        assert_eq!(work.remaining(1.0), None);
+        assert_eq!(
+            work.summary(0.5, 0.75),
+            Work {
+                area: 150,
+                area_per_hour: 450,
+                eta_min: Some(60),
+            }
+        );
+        assert_eq!(WorkRate::default().area_per_hour(0.5), 0.0);
+        assert!(is_fill_type(Modes::Stripe));
+        assert!(!is_fill_type(Modes::MonitorPerson));
+    }
+}
@@ -1,6 +1,7 @@
 //! Config Handler.
 
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 
 /// Provides TOML config file handling.
 pub mod toml {
@@ -68,6 +69,130 @@ pub struct Config {
     pub vision: Vision,
     pub notification: Notification,
     pub detectthreshold: DetectThreshold,
+    #[serde(default)]
+    pub com: Com,
+    #[serde(default)]
+    pub lora: Lora,
+    #[serde(default)]
+    pub udp: Udp,
+    #[serde(default)]
+    pub mqtt: Mqtt,
+    #[serde(default)]
+    pub espnow: EspNow,
+    #[serde(default)]
+    pub proximity: Proximity,
+    #[serde(default)]
+    pub uart: Uart,
+    #[serde(default)]
+    pub websocket: WebSocket,
+    #[serde(default)]
+    pub grpc: Grpc,
+    #[serde(default)]
+    pub nus: Nus,
+    #[serde(default)]
+    pub dock: Dock,
+    #[serde(default)]
+    pub motion: Motion,
+    #[serde(default)]
+    pub spiral: Spiral,
+    #[serde(default)]
+    pub stripe: Stripe,
+    #[serde(default)]
+    pub perimeter_trim: PerimeterTrim,
+    #[serde(default)]
+    pub waypoint: Waypoint,
+    #[serde(default)]
+    pub spot: Spot,
+    #[serde(default)]
+    pub edge: Edge,
+    #[serde(default)]
+    pub deterrent: Deterrent,
+    #[serde(default)]
+    pub patrol: Patrol,
+    #[serde(default)]
+    pub mission: Mission,
+    #[serde(default)]
+    pub avoidance: Avoidance,
+    #[serde(default)]
+    pub oneway: OneWay,
+    #[serde(default)]
+    pub monitor_person: MonitorPerson,
+    #[serde(default)]
+    pub monitor_animal: MonitorAnimal,
+    #[serde(default)]
+    pub follow_person: FollowPerson,
+    #[serde(default)]
+    pub round_trip: RoundTrip,
+    #[serde(default)]
+    pub climb: Climb,
+    #[serde(default)]
+    pub around: Around,
+    #[serde(default)]
+    pub heading: Heading,
+    #[serde(default)]
+    pub risk: Risk,
+    #[serde(default)]
+    pub stuck: Stuck,
+    #[serde(default)]
+    pub battery: Battery,
+    #[serde(default)]
+    pub geofence: Geofence,
+    #[serde(default)]
+    pub speed: Speed,
+    #[serde(default)]
+    pub work_rate: WorkRate,
+    #[serde(default)]
+    pub orchard: Orchard,
+    #[serde(default)]
+    pub trailer_dock: TrailerDock,
+    #[serde(default)]
+    pub progress: Progress,
+    #[serde(default)]
+    pub person: Person,
+    #[serde(default)]
+    pub vision_loss: VisionLoss,
+    #[serde(default)]
+    pub night: Night,
+    #[serde(default)]
+    pub calibrate: Calibrate,
+    #[serde(default)]
+    pub rain: Rain,
+    #[serde(default)]
+    pub triangulation: Triangulation,
+    #[serde(default)]
+    pub tracking: Tracking,
+    #[serde(default)]
+    pub range: Range,
+    #[serde(default)]
+    pub camera_calibrate: CameraCalibrate,
+    #[serde(default)]
+    pub fiducial: Fiducial,
+    #[serde(default)]
+    pub stereo: Stereo,
+    #[serde(default)]
+    pub depth_camera: DepthCamera,
+    #[serde(default)]
+    pub rear_camera: RearCamera,
+    #[serde(default)]
+    pub dataset_capture: DatasetCapture,
+    #[serde(default)]
+    pub tiling: Tiling,
+    #[serde(default)]
+    pub exposure: Exposure,
+    #[serde(default)]
+    pub odometry: Odometry,
+    #[serde(default)]
+    pub stream: Stream,
+    #[serde(default)]
+    pub debug_overlay: DebugOverlay,
+    #[serde(default)]
+    pub pets: Pets,
+    #[serde(default)]
+    pub events: Events,
+    #[serde(default)]
+    pub qr: Qr,
+    #[serde(default)]
+    pub thermal: Thermal,
 }
 
 /// Represents system-related configuration parameters.
@@ -87,6 +212,8 @@ pub struct Drive {
     pub minimum_pylon_height: u16,
     pub turn_adj: f32,
     pub motor_driver: String,
+    #[serde(default)]
+    pub dry_run: bool, // Log motor commands instead of driving the motors
 }
 
 /// Represents camera-related configuration parameters.
@@ -96,6 +223,8 @@ pub struct Camera {
     pub grab_times: u8,
     pub width: u16,
     pub height: u16,
+    #[serde(default)]
+    pub source: String, // Source of the frames (empty: 'v4l2')
 }
 
 /// Represents pin-related configuration parameters.
@@ -109,6 +238,20 @@ pub struct Pin {
     pub work1_pin: u8,
     pub work2_pin: u8,
     pub work_ctrl_positive: bool,
+    #[serde(default)]
+    pub pause_button_pin: u8,
+    #[serde(default)]
+    pub light_pin: u8,
+    #[serde(default)]
+    pub imu_address: u16,
+    #[serde(default)]
+    pub estop_pin: u8,
+    #[serde(default)]
+    pub rain_pin: u8,
+    #[serde(default)]
+    pub ir_cut_pin: u8,
+    #[serde(default)]
+    pub ir_pin: u8,
 }
 
 /// Represents PWM-related configuration parameters.
@@ -123,6 +266,10 @@ pub struct Pwm {
 pub struct Vision {
     pub detector: String,
     pub ocr: bool,
+    #[serde(default)]
+    pub models: HashMap<String, String>,
+    #[serde(default)]
+    pub max_latency_ms: u64, // Latency from the capture to the detections to warn over (0: never)
 }
 
 /// Represents notification-related configuration parameters.
@@ -132,12 +279,1402 @@ pub struct Notification {
 }
 
 /// Represents detection threshold-related configuration parameters.
-#[derive(Debug, Deserialize, Serialize, Clone)]
+/// A class threshold of 0 falls back to `default`.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
 pub struct DetectThreshold {
+    pub default: f32,
     pub pylon: f32,
     pub person: f32,
     pub animal: f32,
     pub roktrack: f32,
+    pub obstacle: f32,
+    pub iou: f32,
+    pub max_detections: usize,
+}
+
+/// Represents communication-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Com {
+    pub transport: String,
+    pub gatt: bool,
+    pub key: String,
+    pub extended: bool,
+    pub adapter: String,
+    pub scan_adapter: String,
+    pub trusted_only: bool,
+    pub pairing_window: u64,
+    pub manufacturer_id: u16,
+    pub scan_uuids: Vec<String>,
+    pub scan_mode: String,
+    pub scan_watchdog: u64,
+}
+
+impl Default for Com {
+    fn default() -> Self {
+        Self {
+            transport: String::new(),
+            gatt: false,
+            key: String::new(),
+            extended: false,
+            adapter: String::new(),
+            scan_adapter: String::new(),
+            trusted_only: false,
+            pairing_window: 60,
+            manufacturer_id: 0xFFFF,
+            scan_uuids: vec![],
+            scan_mode: String::from("filtered"),
+            scan_watchdog: 60,
+        }
+    }
+}
+
+/// Represents LoRa radio-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Lora {
+    pub frequency: u32,
+    pub spreading_factor: u8,
+    pub tx_power: u8,
+    pub spi_bus: u8,
+    pub reset_pin: u8,
+    pub duty_cycle: f32,
+}
+
+impl Default for Lora {
+    fn default() -> Self {
+        Self {
+            frequency: 923_200_000,
+            spreading_factor: 9,
+            tx_power: 13,
+            spi_bus: 0,
+            reset_pin: 17,
+            duty_cycle: 10.0,
+        }
+    }
+}
+
+/// Represents UDP multicast-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Udp {
+    pub group: String,
+    pub port: u16,
+    pub interface: String,
+}
+
+impl Default for Udp {
+    fn default() -> Self {
+        Self {
+            group: String::from("239.255.82.75"),
+            port: 8275,
+            interface: String::from("0.0.0.0"),
+        }
+    }
+}
+
+/// Represents MQTT-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Mqtt {
+    pub enable: bool,
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: String,
+    pub password: String,
+    pub topic_prefix: String,
+}
+
+impl Default for Mqtt {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            host: String::from("localhost"),
+            port: 1883,
+            client_id: String::new(),
+            username: String::new(),
+            password: String::new(),
+            topic_prefix: String::from("roktrack"),
+        }
+    }
+}
+
+/// Represents ESP-NOW serial bridge-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct EspNow {
+    pub port: String,
+    pub baud_rate: u32,
+}
+
+impl Default for EspNow {
+    fn default() -> Self {
+        Self {
+            port: String::from("/dev/serial0"),
+            baud_rate: 460800,
+        }
+    }
+}
+
+/// Represents RSSI-based proximity estimation parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Proximity {
+    pub rssi_at_1m: f32,
+    pub path_loss_exponent: f32,
+    pub near_distance: f32,
+    pub far_distance: f32,
+}
+
+impl Default for Proximity {
+    fn default() -> Self {
+        Self {
+            rssi_at_1m: -59.0,
+            path_loss_exponent: 2.5,
+            near_distance: 1.5,
+            far_distance: 5.0,
+        }
+    }
+}
+
+/// Represents wired UART link-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Uart {
+    pub trailer: bool,
+    pub port: String,
+    pub baud_rate: u32,
+}
+
+impl Default for Uart {
+    fn default() -> Self {
+        Self {
+            trailer: false,
+            port: String::from("/dev/ttyAMA1"),
+            baud_rate: 115200,
+        }
+    }
+}
+
+/// Represents WebSocket stream-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct WebSocket {
+    pub enable: bool,
+    pub bind: String,
+    pub port: u16,
+}
+
+impl Default for WebSocket {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            bind: String::from("0.0.0.0"),
+            port: 8276,
+        }
+    }
+}
+
+/// Represents gRPC remote control-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Grpc {
+    pub enable: bool,
+    pub bind: String,
+    pub port: u16,
+    pub token: String,
+}
+
+impl Default for Grpc {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            bind: String::from("127.0.0.1"),
+            port: 50051,
+            token: String::new(),
+        }
+    }
+}
+
+/// Represents Nordic UART Service client-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Nus {
+    pub enable: bool,
+    pub name: String,
+}
+
+/// Represents dock-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Dock {
+    pub low_battery_mv: u16,
+}
+
+/// Represents the motion of the chassis for timed maneuvers.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Motion {
+    pub speed: f32,
+    pub quarter_turn_ms: u64,
+}
+
+impl Default for Motion {
+    fn default() -> Self {
+        Self {
+            speed: 0.3,
+            quarter_turn_ms: 1000,
+        }
+    }
+}
+
+/// Represents spiral mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Spiral {
+    pub direction: String,
+    pub pitch: f32,
+    pub laps: u8,
+}
+
+impl Default for Spiral {
+    fn default() -> Self {
+        Self {
+            direction: String::from("outward"),
+            pitch: 0.5,
+            laps: 5,
+        }
+    }
+}
+
+/// Represents stripe mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Stripe {
+    pub lane_width: f32,
+    pub lane_length: f32,
+    pub lanes: u8,
+}
+
+impl Default for Stripe {
+    fn default() -> Self {
+        Self {
+            lane_width: 0.5,
+            lane_length: 10.0,
+            lanes: 10,
+        }
+    }
+}
+
+/// Represents perimeter trim mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct PerimeterTrim {
+    pub laps: u8,
+    pub markers: u8,
+    pub approach: f32,
+}
+
+impl Default for PerimeterTrim {
+    fn default() -> Self {
+        Self {
+            laps: 1,
+            markers: 4,
+            approach: 0.95,
+        }
+    }
+}
+
+/// Represents waypoint mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Waypoint {
+    pub file: String,
+    pub arrival_radius: f32,
+    pub heading_tolerance: f32,
+    pub share: bool,
+}
+
+impl Default for Waypoint {
+    fn default() -> Self {
+        Self {
+            file: String::from("waypoints.csv"),
+            arrival_radius: 1.0,
+            heading_tolerance: 15.0,
+            share: false,
+        }
+    }
+}
+
+/// Represents spot mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Spot {
+    pub pitch: f32,
+    pub max_radius: f32,
+    pub height_at_1m: f32,
+}
+
+impl Default for Spot {
+    fn default() -> Self {
+        Self {
+            pitch: 0.5,
+            max_radius: 3.0,
+            height_at_1m: 0.5,
+        }
+    }
+}
+
+/// Represents edge following mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Edge {
+    pub grass_side: String,
+    pub position: f32,
+}
+
+impl Default for Edge {
+    fn default() -> Self {
+        Self {
+            grass_side: "left".to_string(),
+            position: 0.5,
+        }
+    }
+}
+
+/// Represents animal deterrent mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Deterrent {
+    pub species: Vec<String>,
+    pub max_distance: f32,
+    pub sound: String,
+    pub repeat: u8,
+}
+
+impl Default for Deterrent {
+    fn default() -> Self {
+        Self {
+            species: vec!["deer".to_string(), "monkey".to_string(), "boar".to_string()],
+            max_distance: 3.0,
+            sound: "asset/audio/deterrent.mp3".to_string(),
+            repeat: 3,
+        }
+    }
+}
+
+/// Represents patrol mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Patrol {
+    pub file: String,
+    pub interval: u64,
+}
+
+impl Default for Patrol {
+    fn default() -> Self {
+        Self {
+            file: "patrol.txt".to_string(),
+            interval: 30,
+        }
+    }
+}
+
+/// Represents mission-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct Mission {
+    pub steps: Vec<MissionStep>,
+}
+
+/// Represents a step of the mission.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MissionStep {
+    pub mode: String,
+    pub until: String,
+    pub minutes: u64,
+    pub conf: ::toml::Table,
+}
+
+impl Default for MissionStep {
+    fn default() -> Self {
+        Self {
+            mode: "fill".to_string(),
+            until: "complete".to_string(),
+            minutes: 0,
+            conf: ::toml::Table::new(),
+        }
+    }
+}
+
+/// Represents obstacle avoidance-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Avoidance {
+    pub enabled: bool,
+    pub height_ratio: f32,
+    pub range_cm: u16,
+    pub clearance: f32,
+    pub pass_length: f32,
+    pub distance: f32,
+}
+
+impl Default for Avoidance {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            height_ratio: 0.4,
+            range_cm: 0,
+            clearance: 0.5,
+            pass_length: 1.0,
+            distance: 0.0,
+        }
+    }
+}
+
+/// Represents one-way mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct OneWay {
+    pub pivot_ms: u64,
+    pub radius: f32,
+    pub overshoot_ms: i64,
+}
+
+impl Default for OneWay {
+    fn default() -> Self {
+        Self {
+            pivot_ms: 500,
+            radius: 0.0,
+            overshoot_ms: 0,
+        }
+    }
+}
+
+/// Represents person monitoring mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MonitorPerson {
+    pub cooldown: u64,
+    pub quiet_start: String,
+    pub quiet_end: String,
+    pub speak: bool,
+    pub ignore_zones: Vec<Vec<[f32; 2]>>, // Polygons of (x, y) in the image (ratio)
+    pub reid: bool,
+    pub reid_similarity: f32,
+    pub visit_gap: u64,
+    pub fallen: bool,
+    pub fallen_time: u64,
+}
+
+impl Default for MonitorPerson {
+    fn default() -> Self {
+        Self {
+            cooldown: 60,
+            quiet_start: String::new(),
+            quiet_end: String::new(),
+            speak: true,
+            ignore_zones: vec![],
+            reid: true,
+            reid_similarity: 0.8,
+            visit_gap: 300,
+            fallen: false,
+            fallen_time: 10,
+        }
+    }
+}
+
+/// Represents animal monitoring mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct MonitorAnimal {
+    pub default_action: String,
+    pub responses: Vec<AnimalResponse>,
+}
+
+impl Default for MonitorAnimal {
+    fn default() -> Self {
+        Self {
+            default_action: "notify".to_string(),
+            responses: vec![],
+        }
+    }
+}
+
+/// Represents the response to a species.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+pub struct AnimalResponse {
+    pub species: String,
+    pub action: String,
+    #[serde(default)]
+    pub sound: String,
+}
+
+/// Represents follow person mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct FollowPerson {
+    pub distance_ratio: f32,
+    pub gain: f32,
+    pub min_speed: f32,
+    pub hysteresis: f32,
+    pub gesture: bool,
+}
+
+impl Default for FollowPerson {
+    fn default() -> Self {
+        Self {
+            distance_ratio: 0.6,
+            gain: 2.0,
+            min_speed: 0.5,
+            hysteresis: 0.1,
+            gesture: true,
+        }
+    }
+}
+
+/// Represents round trip mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(default)]
+pub struct RoundTrip {
+    pub waypoints: Vec<u8>,
+    pub repeats: Vec<u8>,
+    pub reach_distance: f32,
+}
+
+/// Represents climb mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Climb {
+    pub max_incline: f32,
+    pub max_roll: f32,
+    pub slope: f32,
+    pub power_gain: f64,
+}
+
+impl Default for Climb {
+    fn default() -> Self {
+        Self {
+            max_incline: 25.0,
+            max_roll: 15.0,
+            slope: 5.0,
+            power_gain: 0.02,
+        }
+    }
+}
+
+/// Represents around mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Around {
+    pub start_marker: u8,
+    pub laps: u8,
+    pub approach: f32,
+}
+
+impl Default for Around {
+    fn default() -> Self {
+        Self {
+            start_marker: 1,
+            laps: 0,
+            approach: 0.9,
+        }
+    }
+}
+
+/// Represents heading control-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Heading {
+    pub controller: String,
+    pub surface: String,
+    pub gains: HashMap<String, PidGains>,
+}
+
+impl Default for Heading {
+    fn default() -> Self {
+        Self {
+            controller: "pid".to_string(),
+            surface: "grass".to_string(),
+            gains: HashMap::from([
+                (
+                    "grass".to_string(),
+                    PidGains {
+                        kp: 0.3,
+                        ki: 0.05,
+                        kd: 0.05,
+                    },
+                ),
+                (
+                    "concrete".to_string(),
+                    PidGains {
+                        kp: 0.2,
+                        ki: 0.02,
+                        kd: 0.05,
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+/// Represents the gains of a PID controller.
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+pub struct PidGains {
+    pub kp: f64,
+    pub ki: f64,
+    pub kd: f64,
+}
+
+/// Represents system risk-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Risk {
+    pub max_temp: f32,
+    pub max_tilt: f32,
+    pub critical_battery_mv: u16,
+    pub comm_timeout: u64,
+    pub min_grass: f32,
+}
+
+impl Default for Risk {
+    fn default() -> Self {
+        Self {
+            max_temp: 70.0,
+            max_tilt: 0.0,
+            critical_battery_mv: 0,
+            comm_timeout: 0,
+            min_grass: 0.0,
+        }
+    }
+}
+
+/// Represents stuck detection-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Stuck {
+    pub seconds: u64,
+    pub tolerance: f32,
+    pub attempts: u8,
+}
+
+impl Default for Stuck {
+    fn default() -> Self {
+        Self {
+            seconds: 10,
+            tolerance: 0.02,
+            attempts: 3,
+        }
+    }
+}
+
+/// Represents battery policy-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Battery {
+    pub action: String,
+    pub full_mv: u16,
+    pub empty_mv: u16,
+    pub drain_per_hour: f32,
+    pub reserve: f32,
+    pub job_minutes: u64,
+}
+
+impl Default for Battery {
+    fn default() -> Self {
+        Self {
+            action: "return".to_string(),
+            full_mv: 12600,
+            empty_mv: 10500,
+            drain_per_hour: 0.0,
+            reserve: 20.0,
+            job_minutes: 60,
+        }
+    }
+}
+
+/// Represents geofence-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Geofence {
+    pub file: String,
+    pub max_distance: f64,
+}
+
+impl Default for Geofence {
+    fn default() -> Self {
+        Self {
+            file: String::new(),
+            max_distance: 0.0,
+        }
+    }
+}
+
+/// Represents speed profile-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Speed {
+    pub profiles: HashMap<String, SpeedProfile>,
+}
+
+impl Default for Speed {
+    fn default() -> Self {
+        Self {
+            profiles: HashMap::from([
+                (
+                    "fill".to_string(),
+                    SpeedProfile {
+                        cruise: 1.0,
+                        turn: 1.0,
+                        approach: 0.8,
+                    },
+                ),
+                (
+                    "patrol".to_string(),
+                    SpeedProfile {
+                        cruise: 0.6,
+                        turn: 0.6,
+                        approach: 0.6,
+                    },
+                ),
+                (
+                    "trailer_dock".to_string(),
+                    SpeedProfile {
+                        cruise: 0.5,
+                        turn: 0.5,
+                        approach: 0.5,
+                    },
+                ),
+            ]),
+        }
+    }
+}
+
+/// Represents the speeds of a mode (ratio to the motor power).
+#[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
+#[serde(default)]
+pub struct SpeedProfile {
+    pub cruise: f64,
+    pub turn: f64,
+    pub approach: f64,
+}
+
+impl Default for SpeedProfile {
+    fn default() -> Self {
+        Self {
+            cruise: 1.0,
+            turn: 1.0,
+            approach: 1.0,
+        }
+    }
+}
+
+/// Represents work rate-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct WorkRate {
+    pub lane_width: f32,
+}
+
+impl Default for WorkRate {
+    fn default() -> Self {
+        Self { lane_width: 0.5 }
+    }
+}
+
+/// Represents orchard mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Orchard {
+    pub class: u32,
+    pub row_offset: f32,
+    pub row_spacing: f32,
+    pub lanes: u8,
+}
+
+impl Default for Orchard {
+    fn default() -> Self {
+        Self {
+            class: 0,
+            row_offset: 0.35,
+            row_spacing: 3.0,
+            lanes: 0,
+        }
+    }
+}
+
+/// Represents trailer docking mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct TrailerDock {
+    pub ramp_marker: u8,
+    pub align_tolerance: f32,
+    pub ramp_height: f32,
+    pub direction: String,
+    pub climb_distance: f32,
+    pub seated_tilt: f32,
+    pub settle_frames: u8,
+}
+
+impl Default for TrailerDock {
+    fn default() -> Self {
+        Self {
+            ramp_marker: 0,
+            align_tolerance: 0.05,
+            ramp_height: 0.8,
+            direction: "forward".to_string(),
+            climb_distance: 1.5,
+            seated_tilt: 3.0,
+            settle_frames: 10,
+        }
+    }
+}
+
+/// Represents progress persistence-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Progress {
+    pub resume: bool,
+    pub interval: u64,
+}
+
+impl Default for Progress {
+    fn default() -> Self {
+        Self {
+            resume: true,
+            interval: 10,
+        }
+    }
+}
+
+/// Represents person detection-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Person {
+    pub policy: String,
+    pub slow_speed: f64,
+    pub stop_height: f32,
+    pub stop_distance: f32,
+    pub modes: HashMap<String, String>,
+}
+
+impl Default for Person {
+    fn default() -> Self {
+        Self {
+            policy: "pause".to_string(),
+            slow_speed: 0.5,
+            stop_height: 0.0,
+            stop_distance: 0.0,
+            modes: HashMap::new(),
+        }
+    }
+}
+
+/// Represents vision-loss failsafe-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct VisionLoss {
+    pub frames: u8,
+    pub search_steps: u8,
+    pub step_ms: u64,
+}
+
+impl Default for VisionLoss {
+    fn default() -> Self {
+        Self {
+            frames: 5,
+            search_steps: 8,
+            step_ms: 500,
+        }
+    }
+}
+
+/// Represents night operation-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Night {
+    pub start: String,
+    pub end: String,
+    pub speed: f64,
+    pub headlight: bool,
+    pub volume: f32,
+    pub stop_height: f32,
+    pub ir: bool,
+}
+
+impl Default for Night {
+    fn default() -> Self {
+        Self {
+            start: String::new(),
+            end: String::new(),
+            speed: 0.6,
+            headlight: true,
+            volume: 0.3,
+            stop_height: 0.0,
+            ir: false,
+        }
+    }
+}
+
+/// Represents calibration mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Calibrate {
+    pub rest_frames: u16,
+    pub straight_ms: u64,
+}
+
+impl Default for Calibrate {
+    fn default() -> Self {
+        Self {
+            rest_frames: 30,
+            straight_ms: 5000,
+        }
+    }
+}
+
+/// Represents rain-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Rain {
+    pub action: String,
+    pub pass_timeout: u64,
+}
+
+impl Default for Rain {
+    fn default() -> Self {
+        Self {
+            action: "return".to_string(),
+            pass_timeout: 120,
+        }
+    }
+}
+
+/// Represents multi-marker triangulation-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Triangulation {
+    pub enabled: bool,
+    pub marker_height: f32,
+    pub fov: f32,
+    pub gain: f32,
+    pub max_shift: f32,
+}
+
+impl Default for Triangulation {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            marker_height: 0.45,
+            fov: 62.2,
+            gain: 0.3,
+            max_shift: 0.2,
+        }
+    }
+}
+
+impl Default for DetectThreshold {
+    fn default() -> Self {
+        Self {
+            default: 0.5,
+            pylon: 0.4,
+            person: 0.7,
+            animal: 0.0,
+            roktrack: 0.5,
+            obstacle: 0.0,
+            iou: 0.7,
+            max_detections: 30,
+        }
+    }
+}
+
+/// Represents multi-object tracking-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Tracking {
+    pub enabled: bool,
+    pub iou: f32,
+    pub high_score: f32,
+    pub max_age: u8,
+}
+
+impl Default for Tracking {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            iou: 0.3,
+            high_score: 0.6,
+            max_age: 10,
+        }
+    }
+}
+
+/// Represents monocular distance estimation-related configuration parameters.
+/// The heights are those of the objects in m (0: not estimated).
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Range {
+    pub enabled: bool,
+    pub vfov: f32,
+    pub pylon: f32,
+    pub person: f32,
+    pub roktrack: f32,
+}
+
+impl Default for Range {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            vfov: 48.8,
+            pylon: 0.45,
+            person: 1.7,
+            roktrack: 0.3,
+        }
+    }
+}
+
+/// Represents camera calibration mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct CameraCalibrate {
+    pub cols: u8,
+    pub rows: u8,
+    pub views: u8,
+    pub interval_ms: u64,
+}
+
+impl Default for CameraCalibrate {
+    fn default() -> Self {
+        Self {
+            cols: 9,
+            rows: 6,
+            views: 15,
+            interval_ms: 2000,
+        }
+    }
+}
+
+/// Represents fiducial tag-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Fiducial {
+    pub enabled: bool,
+    pub size: f32,
+}
+
+impl Default for Fiducial {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            size: 0.15,
+        }
+    }
+}
+
+/// Represents stereo camera-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Stereo {
+    pub enabled: bool,
+    pub device: String,
+    pub baseline: f32,
+    pub max_disparity: u32,
+    pub block: u32,
+    pub max_skew_ms: u64,
+}
+
+impl Default for Stereo {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: String::from("/dev/video1"),
+            baseline: 0.06,
+            max_disparity: 48,
+            block: 9,
+            max_skew_ms: 20,
+        }
+    }
+}
+
+/// Represents depth camera-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DepthCamera {
+    pub enabled: bool,
+    pub color_device: String,
+    pub color_format: String,
+    pub depth_device: String,
+    pub width: u16,
+    pub height: u16,
+    pub scale: f32,
+    pub max_skew_ms: u64,
+}
+
+impl Default for DepthCamera {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color_device: String::from("/dev/video4"),
+            color_format: String::from("YUYV"),
+            depth_device: String::from("/dev/video0"),
+            width: 640,
+            height: 480,
+            scale: 0.001,
+            max_skew_ms: 20,
+        }
+    }
+}
+
+/// Represents rear camera-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct RearCamera {
+    pub enabled: bool,
+    pub device: String,
+}
+
+impl Default for RearCamera {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            device: String::from("/dev/video2"),
+        }
+    }
+}
+
+/// Represents dataset capture mode-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DatasetCapture {
+    pub model: String,
+    pub frames: u16,
+    pub interval_ms: u64,
+    pub review: bool,
+}
+
+impl Default for DatasetCapture {
+    fn default() -> Self {
+        Self {
+            model: String::from("pylon"),
+            frames: 100,
+            interval_ms: 2000,
+            review: true,
+        }
+    }
+}
+
+/// Represents ROI tiling-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Tiling {
+    pub enabled: bool,
+    pub always: bool,
+    pub top: f32,
+    pub bottom: f32,
+    pub tiles: u8,
+    pub overlap: f32,
+}
+
+impl Default for Tiling {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            always: false,
+            top: 0.25,
+            bottom: 0.6,
+            tiles: 2,
+            overlap: 0.2,
+        }
+    }
+}
+
+/// Represents camera exposure-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Exposure {
+    pub auto_exposure: bool,
+    pub exposure: i32,
+    pub auto_white_balance: bool,
+    pub white_balance: i32,
+    pub glare: bool,
+    pub glare_ratio: f32,
+    pub min_exposure: i32,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self {
+            auto_exposure: true,
+            exposure: 50,
+            auto_white_balance: true,
+            white_balance: 5000,
+            glare: true,
+            glare_ratio: 0.1,
+            min_exposure: 5,
+        }
+    }
+}
+
+/// Represents visual odometry-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Odometry {
+    pub enabled: bool,
+    pub top: f32,
+    pub bottom: f32,
+    pub hfov: f32,
+    pub meters_per_px: f32,
+}
+
+impl Default for Odometry {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top: 0.6,
+            bottom: 1.0,
+            hfov: 62.2,
+            meters_per_px: 0.005,
+        }
+    }
+}
+
+/// Represents live video stream-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Stream {
+    pub enable: bool,
+    pub bind: String,
+    pub port: u16,
+    pub quality: u8,
+}
+
+impl Default for Stream {
+    fn default() -> Self {
+        Self {
+            enable: false,
+            bind: String::from("0.0.0.0"),
+            port: 8277,
+            quality: 70,
+        }
+    }
+}
+
+/// Represents debug overlay-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct DebugOverlay {
+    pub enabled: bool,
+    pub interval_ms: u64,
+    pub save: bool,
+    pub keep: usize,
+}
+
+impl Default for DebugOverlay {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 1000,
+            save: true,
+            keep: 300,
+        }
+    }
+}
+
+/// Represents pet registry-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Pets {
+    pub enabled: bool,
+    pub dir: String,
+    pub similarity: f32,
+}
+
+impl Default for Pets {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            dir: "pets".to_string(),
+            similarity: 0.85,
+        }
+    }
+}
+
+/// Represents detection event-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Events {
+    pub enabled: bool,
+    pub interval_ms: u64,
+    pub frames: bool,
+}
+
+impl Default for Events {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_ms: 1000,
+            frames: true,
+        }
+    }
+}
+
+/// Represents QR code sign-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Qr {
+    pub enabled: bool,
+    pub gap: u64,
+    pub no_go_ratio: f32,
+}
+
+impl Default for Qr {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            gap: 5,
+            no_go_ratio: 0.15,
+        }
+    }
+}
+
+/// Represents thermal camera-related configuration parameters.
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde(default)]
+pub struct Thermal {
+    pub enabled: bool,
+    pub frame: String,
+    pub format: String,
+    pub width: u16,
+    pub height: u16,
+    pub view: Vec<f32>,
+    pub always: bool,
+    pub body_min: f32,
+    pub body_max: f32,
+    pub min_pixels: u32,
+    pub part_region: Vec<f32>,
+    pub part_limit: f32,
+}
+
+impl Default for Thermal {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            frame: String::from("/run/user/1000/roktrack/vision_thermal.raw"),
+            format: String::from("f32"),
+            width: 32,
+            height: 24,
+            view: vec![0.0, 0.0, 1.0, 1.0],
+            always: false,
+            body_min: 28.0,
+            body_max: 40.0,
+            min_pixels: 3,
+            part_region: vec![],
+            part_limit: 60.0,
+        }
+    }
 }
 
 // Default configuration data in TOML format
@@ -150,16 +1687,18 @@ const DEFAULT_CONFIG: &str = r#"
 
 [drive]
   default_state = 'on' # Default state of the drive ('on' or 'off')
-  mode = 'fill' # Drive mode ('fill', 'oneway', 'climb')
+  mode = 'fill' # Drive mode ('fill', 'oneway', 'climb', 'around', 'return_to_dock', 'spiral', 'stripe', 'perimeter_trim', 'waypoint', 'spot', 'edge_follow' (needs asset/model/grass_seg_320_320.onnx, not shipped), 'animal_deterrent', 'patrol', 'mission', 'orchard', 'trailer_dock', 'calibrate', 'camera_calibrate', 'dataset_capture')
   minimum_pylon_height = 0 # Minimum pylon height for operations
   turn_adj = 1 # Turn adjustment factor
   motor_driver = 'ZK_5AD' # Motor driver type ('ZK_5AD', 'IRF3205')
+  dry_run = false # Log motor commands instead of driving the motors (for bench tests)
 
 [camera]
   video_idx = -1 # Video index (-1 for default)
   grab_times = 3 # Number of image grabs
   width = 1280 # Image width
   height = 720 # Image height
+  source = 'v4l2' # Source of the frames ('v4l2': USB webcams, 'legacy': the legacy Pi camera stack, 'libcamera': Pi Camera v3 and the others of libcamera)
 
 [pin]
   left_pin1 = 22 # Left motor control pin 1 (DIGITAL)
@@ -170,23 +1709,456 @@ const DEFAULT_CONFIG: &str = r#"
   work1_pin = 14 # Work motor control pin 1 (for relay, use 17)
   work2_pin = 18 # Work motor control pin 2
   work_ctrl_positive = false # Work motor control polarity (for relay, set to true)
+  pause_button_pin = 0 # Pause / resume button pin (0 for none)
+  light_pin = 0 # Light pin to deter animals (0 for none)
+  estop_pin = 0 # Emergency stop button pin, which halts the drive (0 for none)
+  rain_pin = 0 # Rain sensor pin, which aborts the mission (0 for none)
+  ir_cut_pin = 0 # IR-cut filter switch pin of the camera, high to remove the filter (0 for none, e.g. Pi NoIR camera)
+  ir_pin = 0 # IR illuminator pin (0 for none)
+  imu_address = 0 # I2C address of the IMU (MPU-6050) to measure the slope, e.g. 0x68 (0 for none)
 
 [pwm]
   pwm_power_left = 1.0 # PWM power for the left motor (in percentage)
   pwm_power_right = 1.0 # PWM power for the right motor (in percentage)
 
 [vision]
-  detector = 'yolov7onnx' # Object detection model ('yolov7onnx', deprecated models)
+  detector = 'onnx' # Object detection backend ('onnx', 'openvino', 'tflite' or 'edgetpu'; the latter three need the features of the same names)
   ocr = true # Enable optical character recognition (OCR)
+  models = {} # Model files replacing the detection models of the modes, e.g. { monitor_animal = 'asset/model/my_animal_320_320.onnx' }
+  max_latency_ms = 500 # Latency from the capture to the detections in ms to warn over, driving blind for it (0: never)
 
 [notification]
   line_notify_token = 'YOUR-LINE-NOTIFY-TOKEN' # Line Notify token for notifications
 
 [detectthreshold]
-  pylon = 0 # Detection threshold for pylons
+  default = 0.5 # Detection threshold for the classes without their own (0 below) and custom models
+  pylon = 0.4 # Detection threshold for pylons, lower to find distant ones
   person = 0.7 # Detection threshold for people
   animal = 0 # Detection threshold for animals
   roktrack = 0.5 # Detection threshold for Roktrack objects
+  obstacle = 0 # Detection threshold for obstacles (rocks, toys, hoses and others; needs a model trained with them, the shipped ones aren't)
+  iou = 0.7 # IoU at or above which the boxes of the same class are merged (NMS)
+  max_detections = 30 # Maximum number of detections per image, by score
+
+[com]
+  transport = 'ble' # Transport to exchange states and commands with neighbors ('ble', 'lora', 'udp', 'espnow', 'uart')
+  gatt = false # Expose a GATT server for phone control (paired with the passkey in the log)
+  key = '' # Shared AES-128 key (32 hex chars) to encrypt broadcasts, empty to disable
+  extended = false # Use BLE 5 extended advertising to broadcast telemetry (requires a BLE 5 adapter)
+  adapter = '' # Bluetooth adapter for broadcasting and GATT ('hci0', 'hci1', ...), empty for the first one
+  scan_adapter = '' # Bluetooth adapter for listening to neighbors, empty to use the same as above
+  trusted_only = false # Ignore broadcasts from devices that are not paired
+  pairing_window = 60 # Seconds after startup in which devices heard are paired, 0 to disable pairing
+  manufacturer_id = 65535 # Company ID of the broadcasts (65535 is reserved for testing), must match among the robots
+  scan_uuids = [] # Only listen to devices advertising these service UUIDs ('FEAA' or 128-bit), empty for all devices
+  scan_mode = 'filtered' # 'filtered' to process only broadcasts with the manufacturer ID, 'all' to also log other devices
+  scan_watchdog = 60 # Seconds without any BLE event before scanning is restarted (and the adapter reset), 0 to disable
+
+[lora]
+  frequency = 923200000 # Carrier frequency in Hz (923.2 MHz for AS923, 868.1 MHz for EU868, 915 MHz for US915)
+  spreading_factor = 9 # Spreading factor (7-12), higher reaches further but slower
+  tx_power = 13 # Output power in dBm (2-17), follow the local regulations
+  spi_bus = 0 # SPI bus of the SX1276/RFM95 module (CE0 is used)
+  reset_pin = 17 # GPIO pin connected to RESET
+  duty_cycle = 10.0 # Most time on air in %, follow the local regulations (1 for EU868, 10 for AS923 in Japan, 100 for none)
+
+[udp]
+  group = '239.255.82.75' # Multicast group shared by the robots and the commander
+  port = 8275 # Port of the multicast group
+  interface = '0.0.0.0' # Address of the interface to join on (e.g. the wlan0 address), 0.0.0.0 for the default
+
+[mqtt]
+  enable = false # Publish states and receive commands via an MQTT broker
+  host = 'localhost' # Broker host
+  port = 1883 # Broker port
+  client_id = '' # Client ID, empty to generate one
+  username = '' # Username, empty for anonymous
+  password = '' # Password
+  topic_prefix = 'roktrack' # Prefix of the topics (<prefix>/<identifier>/state, <prefix>/command)
+
+[espnow]
+  port = '/dev/serial0' # UART connected to the ESP32 bridge
+  baud_rate = 460800 # Baud rate of the UART
+
+[proximity]
+  rssi_at_1m = -59.0 # RSSI in dBm measured 1 m away from another robot
+  path_loss_exponent = 2.5 # 2.0 in free space, larger among grass and obstacles
+  near_distance = 1.5 # Neighbors closer than this (m) are near
+  far_distance = 5.0 # Neighbors farther than this (m) are far
+
+[uart]
+  trailer = false # Also exchange states and commands with a trailer controller wired to the UART
+  port = '/dev/ttyAMA1' # UART connected to the trailer (or the neighbors with transport = 'uart')
+  baud_rate = 115200 # Baud rate of the UART
+
+[websocket]
+  enable = false # Stream neighbors, states and detections as JSON to WebSocket clients
+  bind = '0.0.0.0' # Address to listen on, '127.0.0.1' for local tools only
+  port = 8276 # Port to listen on
+
+[grpc]
+  enable = false # Accept commands and state queries via gRPC (see proto/roktrack.proto)
+  bind = '127.0.0.1' # Address to listen on. '0.0.0.0' exposes it to the LAN, without TLS
+  port = 50051 # Port to listen on
+  token = '' # Token required as 'authorization: Bearer <token>' to start the robot or change its mode. Empty accepts only stopping
+
+[nus]
+  enable = false # Read sensors of a BLE peripheral speaking the Nordic UART Service (e.g. an ESP32 sensor pod)
+  name = '' # Prefix of the peripheral name, empty for the first one found
+
+[dock]
+  low_battery_mv = 0 # Return to the dock marker below this battery voltage in mV (0: disabled)
+
+[motion]
+  speed = 0.3 # Forward speed in m/s, for patterns driven by time (spiral, stripe, spot)
+  quarter_turn_ms = 1000 # Time to turn 90 degrees in ms
+
+[spiral]
+  direction = 'outward' # Spiral from the marker 'outward', or 'inward' from a corner marker
+  pitch = 0.5 # Growth of the legs every half lap in m (about the cutting width)
+  laps = 5 # Number of laps
+
+[stripe]
+  lane_width = 0.5 # Offset between lanes in m (about the cutting width)
+  lane_length = 10.0 # Length of a lane in m
+  lanes = 10 # Number of lanes
+
+[perimeter_trim]
+  laps = 1 # Number of laps along the boundary
+  markers = 4 # Number of markers on the boundary
+  approach = 0.95 # Marker height (ratio to the image) at which to turn, higher than fill (0.9) to trim closer
+
+[waypoint]
+  file = 'waypoints.csv' # Waypoints file ('<lat>,<lon>' per line), relative to the data directory
+  arrival_radius = 1.0 # Distance in m at which a waypoint is reached
+  heading_tolerance = 15.0 # Heading error in degrees tolerated before correcting the direction
+  share = false # Send the waypoints file to the neighbors when a waypoint job starts, and take the ones received while stopped
+
+[spot]
+  pitch = 0.5 # Growth of the radius every lap in m (about the cutting width)
+  max_radius = 3.0 # Stop beyond this radius in m
+  height_at_1m = 0.5 # Apparent height of the marker at 1 m (ratio to the image height)
+
+[edge]
+  grass_side = 'left' # Side of the grass seen from the robot ('left' or 'right')
+  position = 0.5 # Horizontal position of the boundary to keep (ratio to the image width)
+
+[deterrent]
+  species = ['deer', 'monkey', 'boar'] # Species to deter (e.g., 'bear', 'deer', 'monkey', 'boar', 'racoon')
+  max_distance = 3.0 # Maximum distance to drive toward the animal from the post in m
+  sound = 'asset/audio/deterrent.mp3' # Sound played to deter the animal
+  repeat = 3 # Number of times to play the sound
+
+[patrol]
+  file = 'patrol.txt' # Route file in the data directory (one '<forward|backward|left|right> <ms>' per line)
+  interval = 30 # Interval between laps in minutes
+
+[mission]
+  # Steps of the mission mode in order. Each step runs until it completes ('complete'), or for the minutes ('time').
+  # The configuration can be overridden for each step by 'conf'.
+  # e.g., [{ mode = 'perimeter_trim', until = 'complete' }, { mode = 'fill', until = 'complete', conf = { drive = { turn_adj = 1.2 } } }, { mode = 'return_to_dock', until = 'complete' }]
+  steps = []
+
+[avoidance]
+  enabled = true # Go around obstacles in fill mode
+  height_ratio = 0.4 # Height of an obstacle in the image (ratio) at which the detour starts
+  range_cm = 0 # Distance measured by the ultrasonic sensor in cm at which the detour starts (0: unused)
+  clearance = 0.5 # Sideways distance from the lane during the detour in m
+  pass_length = 1.0 # Distance driven alongside the obstacle in m
+  distance = 0.0 # Distance to an obstacle measured by the stereo or the depth camera in m at which the detour starts (0: by the height)
+
+[oneway]
+  pivot_ms = 500 # Duration of each turning step in ms
+  radius = 0.0 # Turn radius in m (0: pivot on the spot)
+  overshoot_ms = 0 # Turn back by this time in ms when the next marker is found (negative: turn further)
+
+[monitor_person]
+  cooldown = 60 # Minimum interval between notifications in seconds
+  quiet_start = '' # Start of the daily quiet hours, e.g. '22:00' (empty: none)
+  quiet_end = '' # End of the daily quiet hours, e.g. '06:00'
+  speak = true # Speak the warning when a person is detected
+  # Polygons in the image where persons are ignored, with (x, y) vertices in ratios of the image size.
+  # e.g. The public sidewalk at the top of the image: [[[0.0, 0.0], [1.0, 0.0], [1.0, 0.2], [0.0, 0.2]]]
+  ignore_zones = []
+  reid = true # Tell the persons by the appearance, to notify once per visit instead of once per cooldown
+  reid_similarity = 0.8 # Least similarity of the appearance to be the same person (0.0-1.0)
+  visit_gap = 300 # Seconds unseen after which the same person is a new visit
+  fallen = false # Alert a person lying on the ground as an emergency, with the pose estimation model (not shipped; put asset/model/pose_yolov8_nano_fixed_320_320.onnx to enable)
+  fallen_time = 10 # Seconds lying before the alert, repeated every cooldown while still lying
+
+[monitor_animal]
+  default_action = 'notify' # Response to species not listed ('notify', 'sound', 'light', 'deterrent')
+  # Responses by species. 'sound' plays the file, 'light' flashes the light, 'deterrent' chases the animal.
+  # e.g. [{ species = 'deer', action = 'deterrent' }, { species = 'cat', action = 'sound', sound = 'asset/audio/cat.mp3' }]
+  responses = []
+
+[follow_person]
+  distance_ratio = 0.6 # Height of the person in the image (ratio) at the following distance
+  gain = 2.0 # Proportional gain from the height difference to the speed
+  min_speed = 0.5 # Minimum speed while following (ratio to the motor power)
+  hysteresis = 0.1 # The person must get this much smaller (ratio) before following again
+  gesture = true # Raise a hand to stop following, and wave it to resume, with the pose estimation model (not shipped; put asset/model/pose_yolov8_nano_fixed_320_320.onnx to enable)
+
+[round_trip]
+  waypoints = [] # Numbers of the markers to visit in order, e.g. [1, 2, 3] (empty: between a marker and a person)
+  repeats = [] # Round trips of each leg between the waypoints, e.g. [2, 1] (1 if not given)
+  reach_distance = 0.0 # Distance to the marker in m to take it as reached, where estimated (see [range] and [fiducial]), instead of the height (0.0: by the height)
+
+[climb]
+  max_incline = 25.0 # Maximum pitch in degrees. Steeper slopes abort the climb
+  max_roll = 15.0 # Maximum roll in degrees. Steeper sideways tilts abort the climb
+  slope = 5.0 # Pitch in degrees above which the robot is on a slope
+  power_gain = 0.02 # Motor power added per degree of pitch (reduced when going down)
+
+[around]
+  start_marker = 1 # Number on the start marker, whose passage is counted as a lap
+  laps = 0 # Number of laps to stop after (0 for no limit)
+  approach = 0.9 # Marker height (ratio to the image) at which to turn
+
+[heading]
+  controller = 'pid' # Steering toward the marker ('pid' or 'bang_bang' for the former step corrections)
+  surface = 'grass' # Surface whose gains are used
+
+[heading.gains.grass]
+  kp = 0.3 # Proportional gain over the marker offset (ratio to the image width)
+  ki = 0.05 # Integral gain
+  kd = 0.05 # Derivative gain
+
+[heading.gains.concrete]
+  kp = 0.2
+  ki = 0.02
+  kd = 0.05
+
+[risk]
+  max_temp = 70.0 # SoC temperature in degrees Celsius above which the robot stops
+  max_tilt = 0.0 # Pitch or roll in degrees above which the drive is halted (0: disabled, needs the IMU)
+  critical_battery_mv = 0 # Battery voltage in mV below which the drive is halted (0: disabled)
+  comm_timeout = 0 # Seconds without a message from the commander before the robot stops (0: disabled)
+  min_grass = 0.0 # Ratio of the grass ahead below which the robot backs off and goes around, e.g. at pavement or flower beds (0.0: disabled). Segments the grass in fill, one_way, spiral, stripe and spot modes with the grass segmentation model (not shipped; put asset/model/grass_seg_320_320.onnx to enable)
+
+[stuck]
+  seconds = 10 # Seconds driving forward without a change of the scene or the position to be stuck (0: disabled)
+  tolerance = 0.02 # Change of a detection (ratio to the image) regarded as a change of the scene
+  attempts = 3 # Recoveries (reverse and rotate) before giving up
+
+[battery]
+  # The warning voltage is low_battery_mv in the [dock] section.
+  action = 'return' # Action below the warning voltage ('return' to the dock marker, or 'stop')
+  full_mv = 12600 # Battery voltage in mV when fully charged
+  empty_mv = 10500 # Battery voltage in mV when empty
+  drain_per_hour = 0.0 # Charge consumed per hour of driving in %, to refuse to start a job without enough charge (0: disabled)
+  reserve = 20.0 # Charge in % to keep at the end of the job
+  job_minutes = 60 # Estimated minutes of a job without a time limit (each step of a mission)
+
+[geofence]
+  # Enforced under every mode. Leaving the area stops the drive and notifies the owner.
+  file = '' # Polygon file of the allowed area ('<lat>,<lon>' per vertex and line), relative to the data directory ('': none)
+  max_distance = 0.0 # Maximum distance in m from the first position while driving (0: disabled)
+
+[speed]
+  # Speeds of each mode (ratio to the motor power). Modes without a profile run at full power.
+  # Low ratios make the motors quieter but may make an unusual noise below 0.4 of the power.
+
+[speed.profiles.fill]
+  cruise = 1.0 # Driving forward
+  turn = 1.0 # Turning and backing
+  approach = 0.8 # Driving forward close to the marker
+
+[speed.profiles.patrol]
+  cruise = 0.6 # Creep quietly at night
+  turn = 0.6
+  approach = 0.6
+
+[speed.profiles.trailer_dock]
+  cruise = 0.5 # Align and climb the ramp slowly
+  turn = 0.5
+  approach = 0.5
+
+[work_rate]
+  lane_width = 0.5 # Cutting width in m to estimate the covered area (stripe uses its lane_width, spiral its pitch)
+
+[orchard]
+  class = 0 # Class of the row objects (0: markers, or the tree trunks of a custom model)
+  row_offset = 0.35 # Offset of the lane center from a row (ratio to the image width) when only one row is in sight
+  row_spacing = 3.0 # Distance between the lanes in m, driven sideways in the U-turns
+  lanes = 0 # Number of lanes to mow (0: until no row is found after a U-turn)
+
+[trailer_dock]
+  # The speeds are reduced by the trailer_dock profile in the [speed] section.
+  ramp_marker = 0 # Number on the ramp marker (0: the biggest marker)
+  align_tolerance = 0.05 # Offset of the marker from the center (ratio to the image width) tolerated while approaching
+  ramp_height = 0.8 # Marker height (ratio to the image) at the foot of the ramp
+  direction = 'forward' # Climbing direction ('forward', or 'backward' to back in after a half turn)
+  climb_distance = 1.5 # Distance in m from the foot of the ramp to the seat on the trailer
+  seated_tilt = 3.0 # Pitch and roll in degrees below which the robot is seated (needs the IMU)
+  settle_frames = 10 # Frames to wait for the robot to be seated before giving up
+
+[progress]
+  resume = true # Resume the fill mission from the saved progress after a restart (e.g. a battery swap or a crash)
+  interval = 10 # Seconds between the saves of the progress while filling (0: disabled)
+
+[person]
+  policy = 'pause' # Reaction to a person in sight ('pause', 'slow', 'detour' or 'warn' to only speak)
+  slow_speed = 0.5 # Speed (ratio to the motor power) while a person is in sight with the 'slow' policy
+  stop_height = 0.0 # Height of a person (ratio to the image height) to react to, i.e. the stop distance (0.0: any)
+  stop_distance = 0.0 # Distance to a person in m to react to, where estimated (see [range]), instead of the height (0.0: by the height)
+  modes = {} # Policies of the modes overriding the default, e.g. { patrol = 'warn', fill = 'slow' }
+
+[vision_loss]
+  frames = 5 # Frames without the tracked marker before searching for it (0: disabled)
+  search_steps = 8 # Turns of the search before halting with TargetLost
+  step_ms = 500 # Duration of a search turn in ms
+
+[night]
+  start = '' # Start of the night profile, e.g. '19:00' (empty: none)
+  end = '' # End of the night profile, e.g. '06:00'
+  speed = 0.6 # Speed limit (ratio to the motor power) at night
+  headlight = true # Turn the light on at night
+  volume = 0.3 # Volume of the voice at night (0.0 -> 1.0)
+  stop_height = 0.0 # Height of a person (ratio to the image height) to react to at night, if lower (farther) than the one of the day
+  ir = false # See by the IR at night: remove the IR-cut filter (ir_cut_pin) and turn the IR illuminator (ir_pin) on
+
+[calibrate]
+  rest_frames = 30 # Frames standing still on level ground to measure the IMU bias
+  straight_ms = 5000 # Duration of the straight run to measure the drift of the wheels in ms
+
+[rain]
+  # Rain is reported by the sensor of rain_pin in the [pin] section, or by the sensor peripheral (rain=1).
+  action = 'return' # Action after the current pass when it rains ('return' to the dock marker, or 'stop')
+  pass_timeout = 120 # Seconds to wait for the current pass to end before aborting
+
+[triangulation]
+  enabled = false # Keep the lane in fill mode by the position fixed with two or more pylons in sight
+  marker_height = 0.45 # Height of the pylons in m
+  fov = 62.2 # Horizontal field of view of the camera in degrees
+  gain = 0.3 # Shift of the aim (ratio to the image width) per m off the lane
+  max_shift = 0.2 # Largest shift of the aim (ratio to the image width)
+
+[tracking]
+  enabled = true # Assign IDs to the detections kept across frames, so that the pilots follow the same pylon
+  iou = 0.3 # IoU with the predicted box at or above which a detection continues a track
+  high_score = 0.6 # Score from which a detection starts a track, lower ones only continue tracks
+  max_age = 10 # Frames a track is kept without detections
+
+[range]
+  enabled = true # Estimate the distance to the objects of known heights from their heights in the image
+  vfov = 48.8 # Vertical field of view of the camera in degrees
+  pylon = 0.45 # Height of the pylons in m (0: not estimated)
+  person = 1.7 # Height of people in m (0: not estimated)
+  roktrack = 0.3 # Height of other Roktracks in m (0: not estimated)
+
+[camera_calibrate]
+  # Show a checkerboard to the camera, tilted at different places of the view, while the robot stands still.
+  cols = 9 # Inner corners of the checkerboard in a row
+  rows = 6 # Inner corners of the checkerboard in a column
+  views = 15 # Frames of the checkerboard to compute the intrinsics from
+  interval_ms = 2000 # Interval between the frames to move the checkerboard in ms
+
+[fiducial]
+  # Tags of the original ArUco dictionary (DICT_ARUCO_ORIGINAL), found as numbered markers with their IDs 0 - 255.
+  enabled = false # Find the tags in the frames along with the detections
+  size = 0.15 # Side of the black square of the tags in m, to estimate the distance to them
+
+[stereo]
+  # A second camera of the same model on the right of the first, mounted parallel at the same height.
+  enabled = false # Measure the distances to the detections with the second camera
+  device = '/dev/video1' # Device of the second camera
+  baseline = 0.06 # Distance between the centers of the lenses in m
+  max_disparity = 48 # Largest shift of the objects between the frames, scaled to 320 px wide, to search (the nearest measured)
+  block = 9 # Side of the blocks matched between the frames in px
+  max_skew_ms = 20 # Largest time between the frames of the cameras in ms. Retaken if apart more
+
+[depth_camera]
+  # A depth camera, e.g. RealSense D400 series or OAK-D, streaming the color and the depth (Z16) over V4L2 instead of the camera.
+  # The depth is taken as aligned to the color, of the same field of view.
+  enabled = false # Capture the color for the detector and the depth to measure the distances by
+  color_device = '/dev/video4' # Device of the color stream
+  color_format = 'YUYV' # Format of the color stream ('YUYV' or 'MJPG')
+  depth_device = '/dev/video0' # Device of the depth stream
+  width = 640 # Width of the depth stream
+  height = 480 # Height of the depth stream
+  scale = 0.001 # Depth unit in m
+  max_skew_ms = 20 # Largest time between the color and the depth in ms. Retaken if apart more
+
+[rear_camera]
+  # A camera facing backward, of the same model as the front one. Its detections guide the backing into the trailer and the reversing.
+  enabled = false # Capture and detect with the rear camera after the front one
+  device = '/dev/video2' # Device of the rear camera
+
+[dataset_capture]
+  # Keep the frames with the detections as the labels of the YOLO format in the dataset directory, while the robot stands still.
+  # Turn off the fiducial tags not to label them as the pylons.
+  model = 'pylon' # Model to label the frames by ('pylon', 'animal')
+  frames = 100 # Frames to keep
+  interval_ms = 2000 # Interval between the frames to move the objects in ms
+  review = true # Keep the frames to review with `roktrack review` before adding them to the dataset
+
+[tiling]
+  # Detect again in the tiles of the band around the horizon, magnified, to find the far pylons.
+  enabled = false # Enable the second pass in the tiles
+  always = false # Detect in the tiles every frame (false: only when no pylon is found in the whole frame)
+  top = 0.25 # Top of the band in ratio of the frame height
+  bottom = 0.6 # Bottom of the band in ratio of the frame height
+  tiles = 2 # Tiles across the band
+  overlap = 0.2 # Overlap of the tiles in ratio of their width
+
+[exposure]
+  auto_exposure = true # Exposure by the camera (false: locked at the exposure below)
+  exposure = 50 # Exposure time in 100 us, locked, or to start the glare compensation at, shorter than the auto exposure in the sun
+  auto_white_balance = true # White balance by the camera (false: locked at the temperature below)
+  white_balance = 5000 # White balance temperature in K
+  glare = true # Shorten the exposure while the frames are blown out, e.g. toward the low sun
+  glare_ratio = 0.1 # Ratio of the blown out pixels in the frame to shorten the exposure over
+  min_exposure = 5 # Shortest exposure time in 100 us
+
+[odometry]
+  enabled = false # Estimate the motion by the flow of the ground in the frames, e.g. without wheel encoders
+  top = 0.6 # Top of the ground to track in ratio of the frame height, below the horizon
+  bottom = 1.0 # Bottom of the ground to track in ratio of the frame height, above the body if in sight
+  hfov = 62.2 # Horizontal field of view of the camera in degrees
+  meters_per_px = 0.005 # Length of the ground a px covers in the frame downscaled to 160 px wide in m, calibrated by driving a known distance
+
+[stream]
+  enable = false # Stream the camera as MJPEG over HTTP, raw at /raw and with the detections at /annotated
+  bind = '0.0.0.0' # Address to listen on, '127.0.0.1' for local tools only
+  port = 8277 # Port to listen on
+  quality = 70 # JPEG quality of the annotated frames (1-100)
+
+[debug_overlay]
+  enabled = false # Draw the detections, the target, the movement and the state on the frames to diagnose the pilots
+  interval_ms = 1000 # Interval to draw the frames at in ms
+  save = true # Keep the frames in debug/ of the data directory (streamed at /debug with the stream enabled either way)
+  keep = 300 # Frames to keep, the oldest removed
+
+[pets]
+  enabled = false # Ignore the pets of the house in the animal monitoring and the deterrent
+  dir = 'pets' # Photos of the pets cropped to them, in a folder per species in the data directory (e.g. pets/dog/pochi.jpg)
+  similarity = 0.85 # Least similarity of the appearance to a photo to be the pet (0.0-1.0)
+
+[events]
+  enabled = false # Record the detections of each session in events/ of the data directory, exported to COCO JSON with `roktrack export`
+  interval_ms = 1000 # Interval to record the frames with any detection at in ms
+  frames = true # Keep the frames referred to by the events
+
+[qr]
+  enabled = false # Take the commands of the QR code signs, 'roktrack:off' or 'roktrack:stop' to turn off, 'roktrack:pause' or 'roktrack:nogo' (signs never start the robot or change its mode)
+  gap = 5 # Seconds out of view after which a sign gives its command again
+  no_go_ratio = 0.15 # Height of a 'roktrack:nogo' sign (ratio to the image) at which the robot turns back
+
+[thermal]
+  # A thermal sensor, e.g. MLX90640 or Lepton, looking along the camera. Its driver writes the frames to the file.
+  enabled = false # Find the warm bodies at night and watch the parts of the robot in view
+  frame = '/run/user/1000/roktrack/vision_thermal.raw' # File of the last frame written by the driver of the sensor
+  format = 'f32' # Format of the frames ('f32': °C in 32-bit floats, e.g. MLX90640, 'y16': 0.01 K in 16 bits, e.g. Lepton)
+  width = 32 # Width of the frames (MLX90640: 32, Lepton: 80 or 160)
+  height = 24 # Height of the frames (MLX90640: 24, Lepton: 60 or 120)
+  view = [0.0, 0.0, 1.0, 1.0] # Region of the frames of the camera the sensor sees (x1, y1, x2, y2 in ratios)
+  always = false # Add the warm bodies by day too, not only in the night hours of [night]
+  body_min = 28.0 # Lowest temperature of the bodies in °C
+  body_max = 40.0 # Highest temperature of the bodies in °C
+  min_pixels = 3 # Fewest pixels of the thermal frame of a body
+  part_region = [] # Region of the thermal frame showing the motors or the battery (x1, y1, x2, y2 in ratios, empty: none)
+  part_limit = 60.0 # Temperature of the parts in °C over which the robot halts (0: no check)
 "#;
 
 #[cfg(test)]
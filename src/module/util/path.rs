@@ -126,7 +126,11 @@ pub mod dir {
         let log_dir = create_dir_from_path_list(&[&data_dir, define::path::LOG_DIR])
             .expect("Can't create LOG_DIR");
         let last_img = super::join(&[&tmp_dir, define::path::LAST_IMAGE]);
+        let right_img = super::join(&[&tmp_dir, define::path::LAST_RIGHT_IMAGE]);
+        let depth_img = super::join(&[&tmp_dir, define::path::LAST_DEPTH_IMAGE]);
+        let rear_img = super::join(&[&tmp_dir, define::path::LAST_REAR_IMAGE]);
         let crop_img = super::join(&[&tmp_dir, define::path::CROP_IMAGE]);
+        let tile_img = super::join(&[&tmp_dir, define::path::TILE_IMAGE]);
         RoktrackPath {
             dir: RoktrackDir {
                 data: data_dir,
@@ -136,7 +140,11 @@ pub mod dir {
             },
             img: RoktrackImg {
                 last: super::join(&[tmp_dir.as_str(), last_img.as_str()]),
+                right: super::join(&[tmp_dir.as_str(), right_img.as_str()]),
+                depth: super::join(&[tmp_dir.as_str(), depth_img.as_str()]),
+                rear: super::join(&[tmp_dir.as_str(), rear_img.as_str()]),
                 crop: super::join(&[tmp_dir.as_str(), crop_img.as_str()]),
+                tile: super::join(&[tmp_dir.as_str(), tile_img.as_str()]),
             },
         }
     }
@@ -175,8 +183,16 @@ pub struct RoktrackDir {
 pub struct RoktrackImg {
     /// Last Image Path
     pub last: String,
+    /// Last Image Path of the Second Camera
+    pub right: String,
+    /// Last Depth Path of the Depth Camera
+    pub depth: String,
+    /// Last Image Path of the Rear Camera
+    pub rear: String,
     /// Cropped Image Path
     pub crop: String,
+    /// Tile Image Path
+    pub tile: String,
 }
 
 #[cfg(test)]
@@ -244,8 +260,20 @@ mod tests {
         // Assert that the last image path matches the expected path
         assert_eq!(res.img.last, "/run/user/1000/roktrack/vision.jpg");
 
+        // Assert that the right image path matches the expected path
+        assert_eq!(res.img.right, "/run/user/1000/roktrack/vision_right.jpg");
+
+        // Assert that the depth path matches the expected path
+        assert_eq!(res.img.depth, "/run/user/1000/roktrack/vision_depth.raw");
+
+        // Assert that the rear image path matches the expected path
+        assert_eq!(res.img.rear, "/run/user/1000/roktrack/vision_rear.jpg");
+
         // Assert that the crop image path matches the expected path
         assert_eq!(res.img.crop, "/run/user/1000/roktrack/crop.jpg");
+
+        // Assert that the tile image path matches the expected path
+        assert_eq!(res.img.tile, "/run/user/1000/roktrack/tile.jpg");
     }
 
     #[test]
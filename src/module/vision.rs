@@ -3,7 +3,7 @@
 // Import the necessary standard library modules
 use std::{
     sync::{
-        mpsc::{Receiver, Sender}, // For sending and receiving messages between threads
+        mpsc::Receiver, // For receiving messages from other threads
         Arc,
         Mutex, // For sharing and synchronizing data between threads
     },
@@ -12,22 +12,50 @@ use std::{
 };
 
 // Import the Detection type from the detector submodule
-use self::detector::Detection;
+use self::detector::{segment::GrassMask, Detection, RoktrackClasses};
 // Import the RoktrackProperty type from the init submodule in the util module
 use super::util::init::RoktrackProperty;
+// Import the night hours to fuse the warm bodies in
+use super::pilot::night;
+// Import the bounded channel that keeps the latest detections
+use super::com::channel::BoundedSender;
 
+pub mod benchmark; // Declare the benchmark submodule
 pub mod camera; // Declare the camera submodule
+pub mod checkerboard; // Declare the checkerboard submodule
+pub mod dataset; // Declare the dataset submodule
+pub mod depth; // Declare the depth submodule
 pub mod detector; // Declare the detector submodule
+pub mod events; // Declare the events submodule
+pub mod exposure; // Declare the exposure submodule
+pub mod fiducial; // Declare the fiducial submodule
+pub mod intrinsics; // Declare the intrinsics submodule
+pub mod metrics; // Declare the metrics submodule
+pub mod odometry; // Declare the odometry submodule
+pub mod overlay; // Declare the overlay submodule
+pub mod pet; // Declare the pet submodule
+pub mod qr; // Declare the qr submodule
+pub mod range; // Declare the range submodule
+pub mod reid; // Declare the reid submodule
+pub mod stereo; // Declare the stereo submodule
+pub mod stream; // Declare the stream submodule
+pub mod thermal; // Declare the thermal submodule
+pub mod tiling; // Declare the tiling submodule
+pub mod tracker; // Declare the tracker submodule
 
 /// This enum defines the commands that can be used to control the vision thread.
 pub enum VisionMgmtCommand {
-    On,                    // Turn on the vision thread
-    Off,                   // Turn off the vision thread
-    SwitchSessionPylon,    // Switch to the pylon detection session
-    SwitchSessionPylonOcr, // Switch to the pylon OCR detection session
-    SwitchSessionAnimal,   // Switch to the animal detection session
-    SwitchSz320,           // Switch to the 320x240 resolution
-    SwitchSz640,           // Switch to the 640x480 resolution
+    On,                      // Turn on the vision thread
+    Off,                     // Turn off the vision thread
+    SwitchSessionPylon,      // Switch to the pylon detection session
+    SwitchSessionPylonOcr,   // Switch to the pylon OCR detection session
+    SwitchSessionAnimal,     // Switch to the animal detection session
+    SwitchSessionPylonGrass, // Switch to the pylon detection session with grass segmentation
+    SwitchSessionPylonPose,  // Switch to the pylon detection session with pose estimation
+    SwitchSz320,             // Switch to the 320x240 resolution
+    SwitchSz640,             // Switch to the 640x480 resolution
+    LoadModel(String),       // Load the model file as the detection model
+    LoadIntrinsics,          // Load the intrinsics of the camera saved by the camera calibration
 }
 
 /// This struct provides a means of image processing using a camera and a detector.
@@ -35,32 +63,108 @@ pub struct RoktrackVision {
     inner: Arc<Mutex<RoktrackVisionInner>>, // A shared and synchronized wrapper for the inner struct that contains the camera and detector fields
     property: Arc<RoktrackProperty>, // A shared wrapper for the property struct that contains the paths and configurations
     state: Arc<Mutex<bool>>,
+    model: Arc<Mutex<String>>,            // Name of the active model
+    grass: Arc<Mutex<Option<GrassMask>>>, // Drivable grass in the last frame (None: not segmented)
+    depth_ahead: Arc<Mutex<Option<f32>>>, // Nearest depth ahead in the last frame in m (None: not measured)
+    odometry: Arc<Mutex<Option<odometry::Pose>>>, // Pose by the visual odometry (None: not estimated)
+    part_temp: Arc<Mutex<Option<f32>>>, // Hottest part of the robot seen by the thermal camera in °C (None: not seen)
+    metrics: Arc<Mutex<Option<metrics::Metrics>>>, // FPS and latency of the detections (None: not detected yet)
+    stream: Option<stream::StreamServer>, // Video stream for the operators (None: disabled)
 }
 
 /// This impl block defines the methods for the RoktrackVision struct.
 impl RoktrackVision {
     /// This method creates a new instance of the RoktrackVision struct with the given property.
     pub fn new(property: RoktrackProperty) -> Self {
+        // Start the video stream for the operators, if enabled
+        let stream = match property.conf.stream.enable {
+            true => match stream::StreamServer::new(property.conf.stream.clone()) {
+                Ok(server) => Some(server),
+                Err(e) => {
+                    log::error!("Can't start the video stream: {}", e);
+                    None
+                }
+            },
+            false => None,
+        };
         Self {
             // Create a new Arc<Mutex<RoktrackVisionInner>> by calling the new method on the RoktrackVisionInner struct and cloning the property
             inner: Arc::new(Mutex::new(RoktrackVisionInner::new(property.clone()))),
             // Create a new Arc<RoktrackProperty> by calling the new method on the Arc type and passing the property
             property: Arc::new(property),
             state: Arc::new(Mutex::new(true)),
+            model: Arc::new(Mutex::new(String::new())),
+            grass: Arc::new(Mutex::new(None)),
+            depth_ahead: Arc::new(Mutex::new(None)),
+            odometry: Arc::new(Mutex::new(None)),
+            part_temp: Arc::new(Mutex::new(None)),
+            metrics: Arc::new(Mutex::new(None)),
+            stream,
         }
     }
 
+    /// This method returns the name of the active model, which is updated by the inference thread.
+    pub fn model(&self) -> Arc<Mutex<String>> {
+        self.model.clone()
+    }
+
+    /// This method returns the mask of the drivable grass, which is updated by the inference thread.
+    pub fn grass(&self) -> Arc<Mutex<Option<GrassMask>>> {
+        self.grass.clone()
+    }
+
+    /// This method returns the nearest depth ahead, the proximity of the obstacles, which is updated by the inference thread.
+    pub fn depth_ahead(&self) -> Arc<Mutex<Option<f32>>> {
+        self.depth_ahead.clone()
+    }
+
+    /// This method returns the pose estimated by the visual odometry since the start, which is updated by the inference thread.
+    pub fn odometry(&self) -> Arc<Mutex<Option<odometry::Pose>>> {
+        self.odometry.clone()
+    }
+
+    /// This method returns the hottest temperature of the parts of the robot in view of the thermal camera, which is updated by the inference thread.
+    pub fn part_temp(&self) -> Arc<Mutex<Option<f32>>> {
+        self.part_temp.clone()
+    }
+
+    /// This method returns the FPS and the latency of the detections, which are updated by the inference thread.
+    pub fn metrics(&self) -> Arc<Mutex<Option<metrics::Metrics>>> {
+        self.metrics.clone()
+    }
+
+    /// This method returns the video stream, to stream the frames drawn by the others too.
+    pub fn stream(&self) -> Option<stream::StreamServer> {
+        self.stream.clone()
+    }
+
     /// This method spawns a new thread that runs the inference loop for image processing.
     /// It takes two arguments: a sender and a receiver for communicating with other threads.
     /// It returns a handle to the spawned thread.
     pub fn run(
         &self,
-        tx: Sender<Vec<Detection>>, // The sender for sending the detection results to other threads
+        tx: BoundedSender<Vec<Detection>>, // The sender for sending the detection results to other threads, keeping the latest
         rx: Receiver<VisionMgmtCommand>, // The receiver for receiving management commands from other threads
     ) -> JoinHandle<()> {
         let local_self = self.inner.clone(); // Clone the inner field to avoid borrowing issues
         let local_property = self.property.clone(); // Clone the property field to avoid borrowing issues
         let local_state = self.state.clone();
+        let local_model = self.model.clone();
+        let local_grass = self.grass.clone();
+        let local_depth_ahead = self.depth_ahead.clone();
+        let local_odometry = self.odometry.clone();
+        let local_part_temp = self.part_temp.clone();
+        let local_metrics = self.metrics.clone();
+        // Measure the frames detected in a row
+        let mut meter = metrics::Meter::default();
+        let stream = self.stream.clone();
+        // Record the detections of the session for the researchers, if enabled
+        let mut recorder = self
+            .property
+            .conf
+            .events
+            .enabled
+            .then(|| events::EventRecorder::new(&self.property.path.dir.data));
 
         // Spawn a new thread and run an infinite loop
         thread::spawn(move || loop {
@@ -72,6 +176,11 @@ impl RoktrackVision {
             match rx.try_recv() {
                 Ok(VisionMgmtCommand::Off) => {
                     *local_state.lock().unwrap() = false;
+                    // The frames are no longer continuous
+                    local_self.lock().unwrap().tracker.reset();
+                    local_self.lock().unwrap().odometry.reset();
+                    meter = metrics::Meter::default();
+                    *local_metrics.lock().unwrap() = None;
                     continue; // If the command is Off, skip the rest of the loop and try again
                 }
                 Ok(VisionMgmtCommand::On) => {
@@ -79,36 +188,99 @@ impl RoktrackVision {
                 } // If the command is On, do nothing and proceed
                 Ok(VisionMgmtCommand::SwitchSessionPylon) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSessionPylon Received");
-                    local_self.lock().unwrap().det.sessions =
-                        detector::onnx::YoloV8::build_pylon_sessions().unwrap();
+                    // Not every backend supports the models, so keep the current ones then
+                    let loaded = local_self.lock().unwrap().det.load(detector::Bundle::Pylon);
+                    if let Err(e) = loaded {
+                        log::error!("Pylon Session Unavailable: {}", e);
+                    }
                 }
                 Ok(VisionMgmtCommand::SwitchSessionPylonOcr) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSessionPylonOcr Received");
-                    // If the command is SwitchSessionPylonOcr, lock the inner field and update the detector sessions with the pylon OCR sessions
-                    local_self.lock().unwrap().det.sessions =
-                        detector::onnx::YoloV8::build_pylon_ocr_sessions().unwrap();
+                    // If the command is SwitchSessionPylonOcr, lock the inner field and load the pylon OCR sessions
+                    let loaded = local_self
+                        .lock()
+                        .unwrap()
+                        .det
+                        .load(detector::Bundle::PylonOcr);
+                    if let Err(e) = loaded {
+                        log::error!("Pylon OCR Session Unavailable: {}", e);
+                    }
                 }
                 Ok(VisionMgmtCommand::SwitchSessionAnimal) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSessionAnimal Received");
-                    // If the command is SwitchSessionAnimal, lock the inner field and update the detector sessions with the animal sessions
-                    local_self.lock().unwrap().det.sessions =
-                        detector::onnx::YoloV8::build_animal_sessions().unwrap();
+                    // If the command is SwitchSessionAnimal, lock the inner field and load the animal sessions
+                    let loaded = local_self
+                        .lock()
+                        .unwrap()
+                        .det
+                        .load(detector::Bundle::Animal);
+                    if let Err(e) = loaded {
+                        log::error!("Animal Session Unavailable: {}", e);
+                    }
+                }
+                Ok(VisionMgmtCommand::SwitchSessionPylonGrass) => {
+                    log::debug!("Vision VisionMgmtCommand::SwitchSessionPylonGrass Received");
+                    // The segmentation model is optional, so keep the current sessions without it
+                    let loaded = local_self
+                        .lock()
+                        .unwrap()
+                        .det
+                        .load(detector::Bundle::PylonGrass);
+                    if let Err(e) = loaded {
+                        log::error!("Grass Segmentation Session Unavailable: {}", e);
+                    }
+                }
+                Ok(VisionMgmtCommand::SwitchSessionPylonPose) => {
+                    log::debug!("Vision VisionMgmtCommand::SwitchSessionPylonPose Received");
+                    // The pose model is optional, so keep the current sessions without it
+                    let loaded = local_self
+                        .lock()
+                        .unwrap()
+                        .det
+                        .load(detector::Bundle::PylonPose);
+                    if let Err(e) = loaded {
+                        log::error!("Pose Estimation Session Unavailable: {}", e);
+                    }
                 }
                 Ok(VisionMgmtCommand::SwitchSz320) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSz320 Received");
                     // If the command is SwitchSz320, lock the inner field and update the detector session type with Sz320
-                    local_self.lock().unwrap().det.session_type =
-                        detector::onnx::SessionType::Sz320;
+                    local_self
+                        .lock()
+                        .unwrap()
+                        .det
+                        .set_session_type(detector::SessionType::Sz320);
                 }
                 Ok(VisionMgmtCommand::SwitchSz640) => {
                     log::debug!("Vision VisionMgmtCommand::SwitchSz640 Received");
                     // If the command is SwitchSz640, lock the inner field and update the detector session type with Sz640
-                    local_self.lock().unwrap().det.session_type =
-                        detector::onnx::SessionType::Sz640;
+                    local_self
+                        .lock()
+                        .unwrap()
+                        .det
+                        .set_session_type(detector::SessionType::Sz640);
+                }
+                Ok(VisionMgmtCommand::LoadModel(path)) => {
+                    log::debug!("Vision VisionMgmtCommand::LoadModel Received: {}", path);
+                    // Keep the current models if the file can't be loaded
+                    let loaded = local_self.lock().unwrap().det.load_model(&path);
+                    if let Err(e) = loaded {
+                        log::error!("Model Unavailable: {}, {}", path, e);
+                    }
+                }
+                Ok(VisionMgmtCommand::LoadIntrinsics) => {
+                    log::debug!("Vision VisionMgmtCommand::LoadIntrinsics Received");
+                    // Undistort with the new intrinsics from the next frame on
+                    let path = intrinsics::path(&local_property.path.dir.data);
+                    local_self.lock().unwrap().intrinsics = intrinsics::Intrinsics::load(&path);
                 }
                 Err(_) => {} // If there is no command or an error, do nothing and proceed
             }
 
+            // Reflect the active model, which the commands may have replaced
+            let model = local_self.lock().unwrap().det.model();
+            *local_model.lock().unwrap() = model;
+
             // If local state is off, processing is suspended.
             if !local_state.lock().unwrap().to_owned() {
                 continue;
@@ -118,17 +290,67 @@ impl RoktrackVision {
             // Take an image using the camera
             {
                 log::debug!("Vision Camera Process Start");
+                let captured = chrono::Utc::now().timestamp_millis() as u64;
                 let res_take = local_self.lock().unwrap().cam.take_picture(); // Lock the inner field and call the take method on the camera field
                 log::debug!("Vision Camera Process End");
                 if res_take.is_ok() {
-                    let session_type = local_self.lock().unwrap().det.session_type.clone(); // Lock the inner field and clone the session type from the detector field
-                    let dets = local_self // Lock the inner field and call the infer method on the detector field with the image path and session type as arguments
+                    // Shorten the exposure of the next frames while this one is blown out, e.g. toward the low sun
+                    if local_property.conf.exposure.glare {
+                        let compensated = local_self.lock().unwrap().cam.compensate_glare();
+                        if let Err(e) = compensated {
+                            log::warn!("Vision Glare Compensation Failed: {}", e);
+                        }
+                    }
+                    // Estimate the motion since the last frame by the flow of the ground
+                    let odometry_conf = &local_property.conf.odometry;
+                    if odometry_conf.enabled {
+                        match image::open(&local_property.path.img.last) {
+                            Ok(img) => {
+                                let mut inner = local_self.lock().unwrap();
+                                let step = inner.odometry.update(&img.to_luma8(), odometry_conf);
+                                log::debug!(
+                                    "Vision Odometry: {:?} -> {:?}",
+                                    step,
+                                    inner.odometry.pose
+                                );
+                                *local_odometry.lock().unwrap() = Some(inner.odometry.pose);
+                            }
+                            Err(e) => log::warn!("Vision Odometry Failed: {}", e),
+                        }
+                    }
+                    let dets = local_self // Lock the inner field and call the detect method on the detector field with the image path as argument
                         .lock()
                         .unwrap()
                         .det
-                        .infer(&local_property.path.img.last, session_type);
+                        .detect(&local_property.path.img.last);
                     let mut dets = dets.unwrap();
                     log::debug!("Vision Detected: {:?}", dets.clone());
+                    // Look for the far pylons in the tiles around the horizon
+                    let tiling = &local_property.conf.tiling;
+                    let model = local_self.lock().unwrap().det.model();
+                    let missed = !dets
+                        .iter()
+                        .any(|det| det.cls == RoktrackClasses::PYLON.to_u32());
+                    if tiling.enabled && model.starts_with("pylon") && (tiling.always || missed) {
+                        let vision = local_self.lock().unwrap();
+                        let imgsz = vision.det.session_type().get_imgsz();
+                        let tiled = tiling::detect(
+                            vision.det.as_ref(),
+                            &local_property.path.img.last,
+                            &local_property.path.img.tile,
+                            imgsz,
+                            tiling,
+                        );
+                        match tiled {
+                            Ok(tiled) => tiling::merge(
+                                &mut dets,
+                                tiled,
+                                local_property.conf.detectthreshold.iou as f64,
+                            ),
+                            Err(e) => log::warn!("Vision Tiling Failed: {}", e),
+                        }
+                        log::debug!("Vision Detected With Tiles: {:?}", dets.clone());
+                    }
                     // Handle ocr
                     let ocr_support = local_self.lock().unwrap().det.support_ocr();
                     if ocr_support {
@@ -144,6 +366,215 @@ impl RoktrackVision {
                             .unwrap();
                         log::debug!("Vision Detected With Ocr: {:?}", dets.clone());
                     }
+                    // Handle grass segmentation
+                    let seg_support = local_self.lock().unwrap().det.support_segmentation();
+                    let mut grass = None;
+                    if seg_support {
+                        let grass_left = local_property.conf.edge.grass_side != "right";
+                        let mask = local_self
+                            .lock()
+                            .unwrap()
+                            .det
+                            .segment(&local_property.path.img.last);
+                        match mask {
+                            Ok(mask) => {
+                                dets.extend(mask.boundary(grass_left));
+                                grass = Some(mask.pooled(detector::segment::POOLED_CELLS));
+                            }
+                            Err(e) => log::warn!("Vision Segmentation Failed: {}", e),
+                        }
+                        log::debug!("Vision Detected With Edges: {:?}", dets.clone());
+                    }
+                    *local_grass.lock().unwrap() = grass;
+                    // Handle pose estimation
+                    let pose_support = local_self.lock().unwrap().det.support_pose();
+                    if pose_support {
+                        let poses = local_self
+                            .lock()
+                            .unwrap()
+                            .det
+                            .pose(&local_property.path.img.last);
+                        match poses {
+                            Ok(poses) => dets.extend(poses),
+                            Err(e) => log::warn!("Vision Pose Estimation Failed: {}", e),
+                        }
+                        log::debug!("Vision Detected With Poses: {:?}", dets.clone());
+                    }
+                    // Undistort the boxes, so that the angles and the heights near the edges are as in the center
+                    let imgsz = local_self.lock().unwrap().det.session_type().get_imgsz();
+                    if let Some(intrinsics) = &local_self.lock().unwrap().intrinsics {
+                        intrinsics.undistort(&mut dets, imgsz);
+                    }
+                    // Estimate the distances to the objects of known heights, which only the pylon models detect
+                    if local_property.conf.range.enabled && model.starts_with("pylon") {
+                        range::annotate(&mut dets, imgsz, &local_property.conf.range);
+                    }
+                    // Measure the distances with the second camera or the depth camera, over the estimates by the heights
+                    let mut depth_map = None;
+                    let stereo = &local_property.conf.stereo;
+                    if stereo.enabled {
+                        let left = image::open(&local_property.path.img.last);
+                        let right = image::open(&local_property.path.img.right);
+                        match (left, right) {
+                            (Ok(left), Ok(right)) => {
+                                let (left, right) = (left.to_luma8(), right.to_luma8());
+                                let focal = match &local_self.lock().unwrap().intrinsics {
+                                    Some(i) if (i.width, i.height) == left.dimensions() => i.focal,
+                                    _ => {
+                                        let vfov = local_property.conf.range.vfov.to_radians();
+                                        left.height() as f32 / 2.0 / (vfov / 2.0).tan()
+                                    }
+                                };
+                                depth_map = stereo::depth_map(&left, &right, focal, stereo);
+                                if depth_map.is_none() {
+                                    log::warn!("Vision Stereo Frames Of Different Sizes");
+                                }
+                            }
+                            (Err(e), _) | (_, Err(e)) => {
+                                log::warn!("Vision Stereo Depth Failed: {}", e)
+                            }
+                        }
+                    }
+                    let depth_camera = &local_property.conf.depth_camera;
+                    if depth_camera.enabled {
+                        match std::fs::read(&local_property.path.img.depth) {
+                            Ok(data) => {
+                                let (w, h) =
+                                    (depth_camera.width as u32, depth_camera.height as u32);
+                                depth_map =
+                                    depth::DepthMap::from_z16(&data, w, h, depth_camera.scale);
+                                if depth_map.is_none() {
+                                    log::warn!(
+                                        "Vision Depth Of Different Size: {} bytes",
+                                        data.len()
+                                    );
+                                }
+                            }
+                            Err(e) => log::warn!("Vision Depth Read Failed: {}", e),
+                        }
+                    }
+                    if let Some(map) = &depth_map {
+                        depth::annotate(&mut dets, map, imgsz);
+                        log::debug!("Vision Detected With Depths: {:?}", dets.clone());
+                    }
+                    *local_depth_ahead.lock().unwrap() =
+                        depth_map.as_ref().and_then(|map| map.nearest());
+                    // Find the warm bodies in the dark and the hot parts with the thermal camera
+                    let thermal_camera = &local_property.conf.thermal;
+                    let mut part_temp = None;
+                    if thermal_camera.enabled {
+                        match std::fs::read(&thermal_camera.frame) {
+                            Ok(data) => match thermal::ThermalFrame::read(&data, thermal_camera) {
+                                Some(frame) => {
+                                    let now = chrono::Local::now().time();
+                                    if thermal_camera.always
+                                        || night::is_night(now, &local_property.conf.night)
+                                    {
+                                        let warm = thermal::warm(&frame, thermal_camera, imgsz);
+                                        thermal::fuse(&mut dets, warm, model.starts_with("animal"));
+                                        log::debug!(
+                                            "Vision Detected With Warmth: {:?}",
+                                            dets.clone()
+                                        );
+                                    }
+                                    part_temp = frame.hottest(&thermal_camera.part_region);
+                                }
+                                None => log::warn!(
+                                    "Vision Thermal Frame Of Different Size: {} bytes",
+                                    data.len()
+                                ),
+                            },
+                            Err(e) => log::warn!("Vision Thermal Read Failed: {}", e),
+                        }
+                    }
+                    *local_part_temp.lock().unwrap() = part_temp;
+                    // Find the fiducial tags as the numbered markers, with the distances by their poses
+                    let fiducial = &local_property.conf.fiducial;
+                    if fiducial.enabled {
+                        match image::open(&local_property.path.img.last) {
+                            Ok(img) => {
+                                let tags = fiducial::detect(
+                                    &img.to_luma8(),
+                                    imgsz,
+                                    local_self.lock().unwrap().intrinsics.as_ref(),
+                                    local_property.conf.range.vfov,
+                                    fiducial,
+                                );
+                                dets.extend(tags);
+                            }
+                            Err(e) => log::warn!("Vision Tag Detection Failed: {}", e),
+                        }
+                        log::debug!("Vision Detected With Tags: {:?}", dets.clone());
+                    }
+                    // Read the QR code signs for their commands
+                    if local_property.conf.qr.enabled {
+                        match image::open(&local_property.path.img.last) {
+                            Ok(img) => dets.extend(qr::detect(&img.to_luma8(), imgsz)),
+                            Err(e) => log::warn!("Vision QR Code Detection Failed: {}", e),
+                        }
+                        log::debug!("Vision Detected With Signs: {:?}", dets.clone());
+                    }
+                    // Keep the IDs of the objects across frames
+                    let tracking = &local_property.conf.tracking;
+                    if tracking.enabled {
+                        dets = local_self.lock().unwrap().tracker.update(dets, tracking);
+                        log::debug!("Vision Detected With Tracks: {:?}", dets.clone());
+                    }
+                    // Record the detections of the front camera
+                    if let Some(recorder) = recorder.as_mut() {
+                        let recorded = recorder.record(
+                            &local_property.path.img.last,
+                            &dets,
+                            imgsz,
+                            &model,
+                            &local_property.conf.events,
+                        );
+                        if let Err(e) = recorded {
+                            log::warn!("Vision Event Recording Failed: {}", e);
+                        }
+                    }
+                    // Detect behind with the rear camera, tagged apart from the front
+                    let has_rear = local_self.lock().unwrap().cam.has_rear();
+                    if has_rear {
+                        let res_rear = local_self.lock().unwrap().cam.take_rear_picture();
+                        let rear = res_rear.and_then(|_| {
+                            local_self
+                                .lock()
+                                .unwrap()
+                                .det
+                                .detect(&local_property.path.img.rear)
+                        });
+                        match rear {
+                            Ok(rear) => dets.extend(rear.into_iter().map(|det| Detection {
+                                camera: camera::REAR,
+                                ..det
+                            })),
+                            Err(e) => log::warn!("Vision Rear Detection Failed: {}", e),
+                        }
+                        log::debug!("Vision Detected With Rear: {:?}", dets.clone());
+                    }
+                    // Stream the frame with the detections to the operators watching
+                    if let Some(stream) = &stream {
+                        let imgsz = local_self.lock().unwrap().det.session_type().get_imgsz();
+                        if let Err(e) = stream.publish(&local_property.path.img.last, &dets, imgsz)
+                        {
+                            log::warn!("Vision Stream Failed: {}", e);
+                        }
+                    }
+                    // Measure the latency from the capture, which the pilots drive blind for
+                    let detected = chrono::Utc::now().timestamp_millis() as u64;
+                    let metrics = meter.record(captured, detected);
+                    log::debug!("Vision Metrics: {:?}", metrics);
+                    *local_metrics.lock().unwrap() = Some(metrics);
+                    match meter.crossed(local_property.conf.vision.max_latency_ms) {
+                        Some(true) => log::warn!(
+                            "Vision Latency Over The Limit: {} ms ({:.1} fps)",
+                            metrics.latency_ms,
+                            metrics.fps
+                        ),
+                        Some(false) => log::info!("Vision Latency Back: {} ms", metrics.latency_ms),
+                        None => {}
+                    }
                     tx.send(dets).unwrap(); // Send the detection results to other threads using the sender
                 }
             }
@@ -154,8 +585,11 @@ impl RoktrackVision {
 
 /// This struct contains the fields for the camera and the detector that are used for image processing.
 pub struct RoktrackVisionInner {
-    pub cam: camera::V4l2Camera, // The camera field that uses the V4l2 module
-    pub det: detector::onnx::YoloV8, // The detector field that uses the YoloV8 module with onnx runtime
+    pub cam: Box<dyn camera::ImageSource>, // The camera field that uses the source selected by the config
+    pub det: Box<dyn detector::Detector>, // The detector field that uses the backend selected by the config
+    pub tracker: tracker::Tracker, // The tracker field that keeps the IDs of the objects across frames
+    pub odometry: odometry::VisualOdometry, // The odometry field that follows the ground across frames
+    pub intrinsics: Option<intrinsics::Intrinsics>, // The intrinsics field to undistort the detections (None: not calibrated)
 }
 
 /// This impl block defines the methods for the RoktrackVisionInner struct.
@@ -163,10 +597,16 @@ impl RoktrackVisionInner {
     /// This method creates a new instance of the RoktrackVisionInner struct with the given property.
     pub fn new(property: RoktrackProperty) -> Self {
         Self {
-            // Create a new camera::V4l2 instance by calling the new method on the V4l2 module and passing the property
-            cam: camera::V4l2Camera::new(property.clone()),
-            // Create a new detector::onnx::YoloV8 instance by calling the new method on the YoloV8 module
-            det: detector::onnx::YoloV8::new(),
+            // Start the camera of the source selected by the config
+            cam: camera::build(&property).expect("Can't start capturing"),
+            // Create the detector of the backend selected by the config
+            det: detector::build(&property.conf).expect("Can't initialize detector."),
+            // Start without tracks
+            tracker: tracker::Tracker::default(),
+            // Start at the origin
+            odometry: odometry::VisualOdometry::default(),
+            // Load the intrinsics saved by the camera calibration, if any
+            intrinsics: intrinsics::Intrinsics::load(&intrinsics::path(&property.path.dir.data)),
         }
     }
 }
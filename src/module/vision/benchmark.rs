@@ -0,0 +1,285 @@
+//! Model Benchmark
+//!
+//! Which size of the models to run trades the accuracy for the speed, which depends on the Pi and
+//! the backend. `roktrack benchmark` on the console runs the models of the configured backend at
+//! both sizes over the sample images, and reports the speed and the accuracy of each:
+//!
+//! asset/bench/<bundle>/<name>.txt  <- The reference labels of asset/img/<name>.jpg, for the models
+//!                                     of the bundle (`pylon` or `animal`), in the YOLO format
+//!
+//! A detection is right if it's of the class of a label it overlaps by `MATCH_IOU` or more, each
+//! label matched once. The latencies are of the detector only, without the capture and the rest of
+//! the pipeline (see `metrics`).
+
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use super::detector::{self, Bundle, Detection, SessionType};
+use crate::module::{define, util::conf::Config};
+
+// Timed runs over each image, after the one scored
+const RUNS: usize = 10;
+// Least IoU of a detection and a label to match
+const MATCH_IOU: f32 = 0.5;
+// Bundles to benchmark, by the directories of their samples
+const BUNDLES: [Bundle; 2] = [Bundle::Pylon, Bundle::Animal];
+// Sizes to benchmark
+const SIZES: [SessionType; 2] = [SessionType::Sz320, SessionType::Sz640];
+
+/// Result of a model at a size
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    pub model: String,
+    pub imgsz: u32,
+    pub latencies: Vec<f64>, // Latency of each run in ms, sorted
+    pub matched: usize,      // Detections matching the labels
+    pub detected: usize,     // Detections
+    pub labeled: usize,      // Labels
+}
+
+impl Report {
+    /// Frames per second by the mean latency.
+    pub fn fps(&self) -> f64 {
+        let total: f64 = self.latencies.iter().sum();
+        match 0.0 < total {
+            true => self.latencies.len() as f64 * 1000.0 / total,
+            false => 0.0,
+        }
+    }
+
+    /// Latency of the percentile (0-100) in ms, by the nearest rank.
+    pub fn percentile(&self, p: f64) -> f64 {
+        if self.latencies.is_empty() {
+            return 0.0;
+        }
+        let rank = (p / 100.0 * self.latencies.len() as f64).ceil() as usize;
+        self.latencies[rank.clamp(1, self.latencies.len()) - 1]
+    }
+
+    /// Ratio of the detections matching the labels. None without any detection.
+    pub fn precision(&self) -> Option<f64> {
+        (0 < self.detected).then(|| self.matched as f64 / self.detected as f64)
+    }
+
+    /// Ratio of the labels matched. None without any label.
+    pub fn recall(&self) -> Option<f64> {
+        (0 < self.labeled).then(|| self.matched as f64 / self.labeled as f64)
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let ratio = |r: Option<f64>| r.map_or("-".to_string(), |r| format!("{:.2}", r));
+        write!(
+            f,
+            "{:<24} {:>5} {:>7.1} {:>8.1} {:>8.1} {:>8.1} {:>9} {:>7}",
+            self.model,
+            self.imgsz,
+            self.fps(),
+            self.percentile(50.0),
+            self.percentile(90.0),
+            self.percentile(99.0),
+            ratio(self.precision()),
+            ratio(self.recall()),
+        )
+    }
+}
+
+/// Parses the labels of the YOLO format as the boxes in the input of the size `imgsz`, which is
+/// the frame resized. The lines not of the format are left out.
+pub fn reference(text: &str, imgsz: u32) -> Vec<Detection> {
+    let sz = imgsz as f32;
+    text.lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [cls, xc, yc, w, h] = fields.as_slice() else {
+                return None;
+            };
+            let cls = cls.parse::<u32>().ok()?;
+            let [xc, yc, w, h] = [xc, yc, w, h].map(|v| v.parse::<f32>().ok().map(|v| v * sz));
+            let (xc, yc, w, h) = (xc?, yc?, w?, h?);
+            Some(Detection {
+                x1: (xc - w / 2.0).max(0.0) as u32,
+                y1: (yc - h / 2.0).max(0.0) as u32,
+                x2: (xc + w / 2.0) as u32,
+                y2: (yc + h / 2.0) as u32,
+                xc,
+                yc,
+                cls,
+                prob: 1.0,
+                w: w as u32,
+                h: h as u32,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// IoU of the boxes.
+fn iou(a: &Detection, b: &Detection) -> f32 {
+    let w = a.x2.min(b.x2).saturating_sub(a.x1.max(b.x1)) as f32;
+    let h = a.y2.min(b.y2).saturating_sub(a.y1.max(b.y1)) as f32;
+    let area = |d: &Detection| (d.x2.saturating_sub(d.x1) * d.y2.saturating_sub(d.y1)) as f32;
+    let union = area(a) + area(b) - w * h;
+    match 0.0 < union {
+        true => w * h / union,
+        false => 0.0,
+    }
+}
+
+/// Number of the detections matching the labels, the best first, each label matched once.
+pub fn matched(dets: &[Detection], labels: &[Detection]) -> usize {
+    let mut dets: Vec<&Detection> = dets.iter().collect();
+    dets.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+    let mut used = vec![false; labels.len()];
+    let mut matched = 0;
+    for det in dets {
+        let best = labels
+            .iter()
+            .enumerate()
+            .filter(|(i, label)| !used[*i] && label.cls == det.cls)
+            .map(|(i, label)| (i, iou(det, label)))
+            .filter(|(_, iou)| MATCH_IOU <= *iou)
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, _)) = best {
+            used[i] = true;
+            matched += 1;
+        }
+    }
+    matched
+}
+
+/// The sample images of the bundle, with their labels, in the order of the names.
+fn samples(bundle: Bundle) -> Vec<(PathBuf, String)> {
+    let dir = Path::new(define::path::BENCHMARK_DIR).join(bundle.name());
+    let Ok(entries) = fs::read_dir(&dir) else {
+        return vec![];
+    };
+    let mut samples: Vec<(PathBuf, String)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "txt"))
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_string_lossy().to_string();
+            let image = Path::new(define::path::IMG_ASSET_DIR).join(format!("{}.jpg", stem));
+            match (image.is_file(), fs::read_to_string(&path)) {
+                (true, Ok(labels)) => Some((image, labels)),
+                _ => {
+                    log::warn!("Benchmark Sample Unavailable: {}", path.display());
+                    None
+                }
+            }
+        })
+        .collect();
+    samples.sort();
+    samples
+}
+
+/// Runs the models of the configured backend over the samples, and reports each on the output.
+pub fn run(
+    conf: &Config,
+    mut output: impl Write,
+) -> Result<Vec<Report>, Box<dyn std::error::Error>> {
+    let mut detector = detector::build(conf)?;
+    writeln!(output, "Detector: {}", conf.vision.detector)?;
+    writeln!(
+        output,
+        "{:<24} {:>5} {:>7} {:>8} {:>8} {:>8} {:>9} {:>7}",
+        "model", "size", "fps", "p50 ms", "p90 ms", "p99 ms", "precision", "recall"
+    )?;
+    let mut reports = vec![];
+    for bundle in BUNDLES {
+        let samples = samples(bundle);
+        if samples.is_empty() {
+            writeln!(output, "{}: No samples.", bundle.name())?;
+            continue;
+        }
+        if let Err(e) = detector.load(bundle) {
+            writeln!(output, "{}: {}", bundle.name(), e)?;
+            continue;
+        }
+        for size in SIZES {
+            let imgsz = size.get_imgsz();
+            detector.set_session_type(size);
+            let mut report = Report {
+                model: detector.model(),
+                imgsz,
+                ..Default::default()
+            };
+            for (image, labels) in &samples {
+                let image = image.to_string_lossy();
+                let labels = reference(labels, imgsz);
+                // The first run warms up, and is scored.
+                let dets = detector.detect(&image)?;
+                report.matched += matched(&dets, &labels);
+                report.detected += dets.len();
+                report.labeled += labels.len();
+                for _ in 0..RUNS {
+                    let start = Instant::now();
+                    detector.detect(&image)?;
+                    report
+                        .latencies
+                        .push(start.elapsed().as_secs_f64() * 1000.0);
+                }
+            }
+            report.latencies.sort_by(f64::total_cmp);
+            writeln!(output, "{}", report)?;
+            reports.push(report);
+        }
+    }
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn benchmark_test() {
+        let labels = reference(
+            "0 0.25 0.5 0.25 0.5\n1 0.75 0.5 0.25 0.25\nnot a label\n",
+            320,
+        );
+        assert_eq!(labels.len(), 2);
+        assert_eq!(
+            (labels[0].x1, labels[0].y1, labels[0].x2, labels[0].y2),
+            (40, 80, 120, 240)
+        );
+
+        // A close match, a duplicate, a wrong class and a miss
+        let det = |x1: u32, y1: u32, x2: u32, y2: u32, cls: u32, prob: f32| Detection {
+            x1,
+            y1,
+            x2,
+            y2,
+            cls,
+            prob,
+            ..Default::default()
+        };
+        let dets = [
+            det(45, 85, 120, 240, 0, 0.9),
+            det(40, 80, 110, 230, 0, 0.6),
+            det(200, 120, 280, 200, 0, 0.8),
+        ];
+        assert_eq!(matched(&dets, &labels), 1);
+        assert_eq!(matched(&[], &labels), 0);
+
+        let report = Report {
+            model: "pylon".to_string(),
+            imgsz: 320,
+            latencies: vec![10.0, 20.0, 30.0, 40.0],
+            matched: 1,
+            detected: 3,
+            labeled: 2,
+        };
+        assert_eq!(report.fps(), 40.0);
+        assert_eq!(report.percentile(50.0), 20.0);
+        assert_eq!(report.percentile(99.0), 40.0);
+        assert_eq!(report.percentile(0.0), 10.0);
+        assert_eq!(report.recall(), Some(0.5));
+        assert_eq!(Report::default().precision(), None);
+        assert_eq!(Report::default().fps(), 0.0);
+    }
+}
@@ -1,19 +1,210 @@
 //! Camera Modules
 //!
+//! The frames come from one of the sources implementing `ImageSource`, selected by `source` in the
+//! `[camera]` section of the config.
+//!
+//! | source      | stack                                              | cameras                       |
+//! |-------------|----------------------------------------------------|-------------------------------|
+//! | 'v4l2'      | V4L2                                               | USB webcams, depth cameras    |
+//! | 'legacy'    | V4L2 of the legacy Pi camera stack (bcm2835-v4l2)  | Pi Camera v1 and v2           |
+//! | 'libcamera' | MJPEG streamed by `rpicam-vid` or `libcamera-vid`  | Pi Camera v3, HQ and the rest |
+//!
+//! The second, the depth and the rear cameras are of V4L2, and only with the V4L2 sources.
+//!
+//! With the stereo camera enabled, the second camera on the right is captured along with the first,
+//! and the pair is retaken until the frames are close enough in time to measure the depth by.
+//! With the depth camera enabled, its color stream is captured instead of the camera, along with
+//! its depth stream in the same way.
+//! With the rear camera enabled, it is captured after the front for the detections behind.
+//!
+//! The frames buffered before a capture are dropped, so that the detections are always of the
+//! latest scene, however long the last inference took.
+//!
+//! The exposure and the white balance are locked or left to the camera as configured, and the
+//! exposure of the V4L2 cameras is shortened while the frames are blown out (see `exposure`).
 
-use rscam::{Camera, Config};
+use rscam::{Camera, Config, Frame};
 use std::fs;
 use std::io::Write;
 
-use crate::module::util::init::RoktrackProperty;
+use super::depth;
+use super::exposure::{self, Control, Glare};
+use crate::module::util::{conf::Camera as CameraConf, init::RoktrackProperty};
+
+// Times to retake the pair of the frames apart in time
+const SYNC_RETRIES: u8 = 5;
+// Most frames buffered before a capture to drop
+const MAX_STALE_FRAMES: u8 = 4;
+// V4L2 exposure modes
+const EXPOSURE_MANUAL: i32 = 1;
+const EXPOSURE_APERTURE_PRIORITY: i32 = 3; // Auto exposure of the UVC cameras
+
+/// ID of the front camera, which the detections are of by default
+pub const FRONT: u8 = 0;
+/// ID of the rear camera
+pub const REAR: u8 = 1;
+
+/// Sources of the frames
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    V4l2,
+    Legacy,
+    Libcamera,
+}
+
+impl Source {
+    pub fn from_string(s: &str) -> Source {
+        match s {
+            // Empty in the older configs.
+            "v4l2" | "" => Source::V4l2,
+            "legacy" => Source::Legacy,
+            "libcamera" => Source::Libcamera,
+            _ => {
+                log::warn!("Invalid Camera Source: {}. Use V4L2.", s);
+                Source::V4l2
+            }
+        }
+    }
+}
+
+/// Source of the frames
+///
+/// Captures the frames to detect in, and saves them to the last image of the paths.
+pub trait ImageSource: Send {
+    /// Captures a frame taken after the call and saves it, along with those of the second or the
+    /// depth camera, if any.
+    fn take_picture(&self) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Shortens the exposure while the last frame is blown out, and gives it back once it's clear.
+    fn compensate_glare(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    /// Whether the rear camera is available.
+    fn has_rear(&self) -> bool {
+        false
+    }
+
+    /// Captures a frame from the rear camera and saves it to its file.
+    fn take_rear_picture(&self) -> Result<(), Box<dyn std::error::Error>> {
+        Err("No rear camera.".into())
+    }
+}
+
+/// Device of the camera, by its video index (-1: the default).
+fn device(conf: &CameraConf) -> String {
+    format!("/dev/video{}", conf.video_idx.max(0))
+}
+
+/// Starts the image source of the configured source.
+///
+pub fn build(
+    property: &RoktrackProperty,
+) -> Result<Box<dyn ImageSource>, Box<dyn std::error::Error>> {
+    let source = Source::from_string(&property.conf.camera.source);
+    log::info!("Camera Source: {:?}", source);
+    match source {
+        // The depth camera is the camera, whichever the source.
+        Source::Libcamera if property.conf.depth_camera.enabled => {
+            log::warn!("Depth Camera Enabled. Use V4L2.");
+            Ok(Box::new(V4l2Camera::new(property.clone())?))
+        }
+        Source::Libcamera => Ok(Box::new(libcamera::LibcameraCamera::new(property.clone())?)),
+        Source::Legacy => match V4l2Camera::new(property.clone()) {
+            Ok(camera) => Ok(Box::new(camera)),
+            Err(e) => Err(format!("{} (Is bcm2835-v4l2 loaded?)", e).into()),
+        },
+        Source::V4l2 => Ok(Box::new(V4l2Camera::new(property.clone())?)),
+    }
+}
 
 /// Represents a V4L2 camera configuration and capture functionality.
 ///
 pub struct V4l2Camera {
     cap: Camera,                // The camera instance for capturing frames.
+    right: Option<Camera>,      // The second camera on the right (None: monocular).
+    depth: Option<Camera>,      // The depth stream of the depth camera (None: no depth camera).
+    rear: Option<Camera>,       // The rear camera (None: front only).
+    glare: Glare,               // Compensation of the glare in the frames.
     property: RoktrackProperty, // Configuration properties for the camera.
 }
 
+/// Opens and starts the camera of the device.
+fn open(
+    device: &str,
+    resolution: (u32, u32),
+    format: &[u8],
+) -> Result<Camera, Box<dyn std::error::Error>> {
+    let mut cap = Camera::new(device)?;
+
+    // Configure and start the camera with specified settings.
+    cap.start(&Config {
+        interval: (1, 30), // 30 fps.
+        resolution,
+        format,
+        nbuffers: 1,
+        ..Default::default()
+    })?;
+    Ok(cap)
+}
+
+/// Now on the clock of the timestamps of the frames (CLOCK_MONOTONIC) in us.
+fn now_us() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut ts) };
+    ts.tv_sec as u64 * 1_000_000 + ts.tv_nsec as u64 / 1000
+}
+
+/// Captures the first frame of the camera taken after the call, dropping those buffered before.
+fn capture_latest(cap: &Camera) -> std::io::Result<Frame> {
+    let start = now_us();
+    for _ in 0..MAX_STALE_FRAMES {
+        let frame = cap.capture()?;
+        if start <= frame.get_timestamp() {
+            return Ok(frame);
+        }
+    }
+    cap.capture()
+}
+
+/// Sets the exposure to the camera.
+fn set_exposure(cap: &Camera, control: Control) -> std::io::Result<()> {
+    match control {
+        Control::Auto => cap.set_control(rscam::CID_EXPOSURE_AUTO, &EXPOSURE_APERTURE_PRIORITY),
+        Control::Manual(exposure) => {
+            cap.set_control(rscam::CID_EXPOSURE_AUTO, &EXPOSURE_MANUAL)?;
+            cap.set_control(rscam::CID_EXPOSURE_ABSOLUTE, &exposure)
+        }
+    }
+}
+
+/// Sets the white balance to the camera, by it or locked at the temperature.
+fn set_white_balance(cap: &Camera, auto: bool, temperature: i32) -> std::io::Result<()> {
+    cap.set_control(rscam::CID_AUTO_WHITE_BALANCE, &auto)?;
+    match auto {
+        true => Ok(()),
+        false => cap.set_control(rscam::CID_WHITE_BALANCE_TEMPERATURE, &temperature),
+    }
+}
+
+/// Saves the frame of the second stream, or removes the last one not to measure by it.
+fn save_or_remove(frame: Option<Frame>, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    match frame {
+        Some(frame) => {
+            let mut file = fs::File::create(path)?;
+            file.write_all(&frame[..])?;
+        }
+        None => {
+            let _ = fs::remove_file(path);
+        }
+    }
+    Ok(())
+}
+
 impl V4l2Camera {
     /// Creates a new V4L2 camera instance with the specified properties.
     ///
@@ -23,41 +214,354 @@ impl V4l2Camera {
     ///
     /// # Returns
     ///
-    /// A `V4l2Camera` instance.
+    /// A `V4l2Camera` instance, or the error if the camera can't start.
     ///
-    pub fn new(property: RoktrackProperty) -> Self {
-        let mut cap = Camera::new("/dev/video0").expect("Can't create camera.");
-
-        // Configure and start the camera with specified settings.
-        cap.start(&Config {
-            interval: (1, 30), // 30 fps.
-            resolution: (
-                property.conf.camera.width as u32,
-                property.conf.camera.height as u32,
+    pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+        let camera = &property.conf.camera;
+        let resolution = (camera.width as u32, camera.height as u32);
+        let depth_camera = &property.conf.depth_camera;
+        let (device, format) = match depth_camera.enabled {
+            true => (
+                depth_camera.color_device.clone(),
+                depth_camera.color_format.as_bytes(),
             ),
-            format: b"MJPG",
-            nbuffers: 1,
-            ..Default::default()
-        })
-        .expect("Can't start capturing");
+            false => (device(camera), b"MJPG".as_slice()),
+        };
+        let cap = open(&device, resolution, format)?;
+        // Without the second camera, the distances are estimated as without the stereo camera.
+        let right = match property.conf.stereo.enabled {
+            true => open(&property.conf.stereo.device, resolution, b"MJPG")
+                .map_err(|e| log::warn!("Stereo Camera Unavailable: {}", e))
+                .ok(),
+            false => None,
+        };
+        let depth = match depth_camera.enabled {
+            true => open(
+                &depth_camera.depth_device,
+                (depth_camera.width as u32, depth_camera.height as u32),
+                b"Z16 ",
+            )
+            .map_err(|e| log::warn!("Depth Stream Unavailable: {}", e))
+            .ok(),
+            false => None,
+        };
+        let rear = match property.conf.rear_camera.enabled {
+            true => open(&property.conf.rear_camera.device, resolution, b"MJPG")
+                .map_err(|e| log::warn!("Rear Camera Unavailable: {}", e))
+                .ok(),
+            false => None,
+        };
 
-        Self { cap, property }
+        let camera = Self {
+            cap,
+            right,
+            depth,
+            rear,
+            glare: Glare::default(),
+            property,
+        };
+        camera.lock_controls();
+        Ok(camera)
+    }
+
+    /// The cameras of the frames to detect in and to measure by, which are exposed alike.
+    fn cameras(&self) -> impl Iterator<Item = &Camera> {
+        std::iter::once(&self.cap).chain(self.right.as_ref())
+    }
+
+    /// Sets the exposure and the white balance of the config. The cameras without the controls are
+    /// left as they are.
+    fn lock_controls(&self) {
+        let conf = &self.property.conf.exposure;
+        for cap in self.cameras() {
+            if let Err(e) = set_exposure(cap, exposure::base(conf)) {
+                log::warn!("Exposure Control Unavailable: {}", e);
+            }
+            if let Err(e) = set_white_balance(cap, conf.auto_white_balance, conf.white_balance) {
+                log::warn!("White Balance Control Unavailable: {}", e);
+            }
+        }
+    }
+
+    /// Saves the frame of the camera as the image, converted if not of JPEG.
+    fn save(&self, frame: &Frame) -> Result<(), Box<dyn std::error::Error>> {
+        let path = self.property.path.img.last.clone();
+        if &frame.format == b"YUYV" {
+            let (width, height) = frame.resolution;
+            let img = depth::yuyv_to_rgb(&frame[..], width, height)
+                .ok_or("Color frame of different size.")?;
+            img.save(path)?;
+            return Ok(());
+        }
+
+        // Save the original image to the specified file path.
+        let mut file = fs::File::create(path)?;
+        file.write_all(&frame[..])?;
+
+        Ok(())
+    }
+
+    /// Captures the frames of the camera and the other close in time.
+    /// The other is None if they are still apart after the retries.
+    fn capture_pair(
+        &self,
+        other: &Camera,
+        max_skew_ms: u64,
+    ) -> Result<(Frame, Option<Frame>), Box<dyn std::error::Error>> {
+        let max_skew = max_skew_ms * 1000; // Timestamps in us
+        let mut retries = 0;
+        loop {
+            let frame = capture_latest(&self.cap)?;
+            let other_frame = capture_latest(other)?;
+            let skew = frame.get_timestamp().abs_diff(other_frame.get_timestamp());
+            if skew <= max_skew {
+                return Ok((frame, Some(other_frame)));
+            }
+            if SYNC_RETRIES <= retries {
+                log::warn!("Camera Streams Out Of Sync: {} us", skew);
+                return Ok((frame, None));
+            }
+            retries += 1;
+        }
+    }
+}
+
+impl ImageSource for V4l2Camera {
+    /// Shortens the exposure while the last frame is blown out, and gives it back once it's clear.
+    fn compensate_glare(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let img = image::open(&self.property.path.img.last)?.to_luma8();
+        let ratio = exposure::blown_out(&img);
+        if let Some(control) = self.glare.update(ratio, &self.property.conf.exposure) {
+            log::info!("Glare Compensation: {:?}, blown out {:.2}", control, ratio);
+            for cap in self.cameras() {
+                set_exposure(cap, control)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the rear camera is available.
+    fn has_rear(&self) -> bool {
+        self.rear.is_some()
+    }
+
+    /// Captures a frame from the rear camera and saves it to its file.
+    fn take_rear_picture(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let rear = self.rear.as_ref().ok_or("No rear camera.")?;
+        let frame = capture_latest(rear)?;
+        let mut file = fs::File::create(self.property.path.img.rear.clone())?;
+        file.write_all(&frame[..])?;
+        Ok(())
     }
 
     /// Captures a frame from the camera and saves it to a file.
     ///
     /// This method captures a frame from the camera and saves it to a file specified
     /// in the `RoktrackProperty`. The images are saved with a specific filename format.
-    pub fn take_picture(&self) -> Result<(), Box<dyn std::error::Error>> {
-        for _ in 0..3 {
-            let _ = self.cap.capture(); // Grab a frame to reduce delay.
+    /// With the second camera or the depth stream, its frame is saved along, or removed if the
+    /// pair isn't in sync.
+    fn take_picture(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(depth) = &self.depth {
+            let max_skew_ms = self.property.conf.depth_camera.max_skew_ms;
+            let (frame, depth_frame) = self.capture_pair(depth, max_skew_ms)?;
+            self.save(&frame)?;
+            return save_or_remove(depth_frame, &self.property.path.img.depth);
+        }
+        if let Some(right) = &self.right {
+            let max_skew_ms = self.property.conf.stereo.max_skew_ms;
+            let (frame, right_frame) = self.capture_pair(right, max_skew_ms)?;
+            self.save(&frame)?;
+            return save_or_remove(right_frame, &self.property.path.img.right);
+        }
+        let frame = capture_latest(&self.cap)?; // get picture
+        self.save(&frame)
+    }
+}
+
+pub mod libcamera {
+    //! Cameras of libcamera
+    //!
+    //! The Pi Camera v3 and the others of libcamera aren't V4L2 devices to capture from, so the
+    //! frames are streamed as MJPEG by `rpicam-vid` (`libcamera-vid` before Bookworm) to its standard
+    //! output and read by a thread, which keeps the latest one. The exposure and the white balance
+    //! are set on the start, and the glare compensation isn't supported.
+
+    use std::io::Read;
+    use std::process::{Child, Command, Stdio};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{ImageSource, RoktrackProperty};
+    use crate::module::vision::exposure::{self, Control};
+
+    // Commands of the stream, the newer first
+    const COMMANDS: [&str; 2] = ["rpicam-vid", "libcamera-vid"];
+    // Longest time to wait for a frame
+    const FRAME_TIMEOUT: Duration = Duration::from_secs(2);
+    // Interval to look for a new frame
+    const POLL_INTERVAL: Duration = Duration::from_millis(5);
+    // Size of the reads from the stream
+    const CHUNK_SIZE: usize = 64 * 1024;
+
+    /// White balance mode of libcamera closest to the temperature in K.
+    pub fn awb_mode(temperature: i32) -> &'static str {
+        match temperature {
+            ..=3000 => "incandescent",
+            3001..=4000 => "tungsten",
+            4001..=5000 => "fluorescent",
+            5001..=6000 => "daylight",
+            _ => "cloudy",
         }
-        let frame = self.cap.capture()?; // get picture
+    }
 
-        // Save the original image to the specified file path.
-        let mut file = fs::File::create(self.property.path.img.last.clone())?;
-        file.write_all(&frame[..])?;
+    /// Range of the first whole JPEG image in the stream, from its SOI to its EOI.
+    pub fn next_jpeg(buf: &[u8]) -> Option<(usize, usize)> {
+        let start = buf.windows(2).position(|w| w == [0xFF, 0xD8])?;
+        let end = buf[start + 2..]
+            .windows(2)
+            .position(|w| w == [0xFF, 0xD9])?;
+        Some((start, start + 2 + end + 2))
+    }
 
-        Ok(())
+    /// Represents a camera streamed by libcamera.
+    ///
+    pub struct LibcameraCamera {
+        child: Child,                       // The process streaming the frames.
+        latest: Arc<Mutex<(u64, Vec<u8>)>>, // The latest frame and its number.
+        property: RoktrackProperty,         // Configuration properties for the camera.
+    }
+
+    impl LibcameraCamera {
+        /// Starts streaming the frames of the camera of the index.
+        pub fn new(property: RoktrackProperty) -> Result<Self, Box<dyn std::error::Error>> {
+            let camera = &property.conf.camera;
+            let conf = &property.conf.exposure;
+            let mut args = vec![
+                "--timeout".to_string(),
+                "0".to_string(),
+                "--nopreview".to_string(),
+                "--codec".to_string(),
+                "mjpeg".to_string(),
+                "--framerate".to_string(),
+                "30".to_string(),
+                "--width".to_string(),
+                camera.width.to_string(),
+                "--height".to_string(),
+                camera.height.to_string(),
+                "--camera".to_string(),
+                camera.video_idx.max(0).to_string(),
+            ];
+            if let Control::Manual(exposure) = exposure::base(conf) {
+                // In us
+                args.extend(["--shutter".to_string(), (exposure * 100).to_string()]);
+            }
+            if !conf.auto_white_balance {
+                args.extend([
+                    "--awb".to_string(),
+                    awb_mode(conf.white_balance).to_string(),
+                ]);
+            }
+            args.extend(["--output".to_string(), "-".to_string()]);
+            let mut child = COMMANDS
+                .iter()
+                .find_map(|command| {
+                    Command::new(command)
+                        .args(&args)
+                        .stdout(Stdio::piped())
+                        .stderr(Stdio::null())
+                        .spawn()
+                        .map_err(|e| log::debug!("Can't Start {}: {}", command, e))
+                        .ok()
+                })
+                .ok_or("Neither rpicam-vid nor libcamera-vid is available.")?;
+            let mut stdout = child.stdout.take().ok_or("No stream of libcamera.")?;
+            let latest = Arc::new(Mutex::new((0, vec![])));
+            let local_latest = latest.clone();
+            thread::spawn(move || {
+                let mut buf = vec![];
+                let mut chunk = vec![0; CHUNK_SIZE];
+                loop {
+                    let n = match stdout.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(n) => n,
+                        Err(e) => {
+                            log::error!("Libcamera Stream Read Failed: {}", e);
+                            break;
+                        }
+                    };
+                    buf.extend_from_slice(&chunk[..n]);
+                    while let Some((start, end)) = next_jpeg(&buf) {
+                        let mut latest = local_latest.lock().unwrap();
+                        *latest = (latest.0 + 1, buf[start..end].to_vec());
+                        drop(latest);
+                        buf.drain(..end);
+                    }
+                }
+                log::warn!("Libcamera Stream End");
+            });
+            Ok(Self {
+                child,
+                latest,
+                property,
+            })
+        }
+    }
+
+    impl ImageSource for LibcameraCamera {
+        /// Waits for a frame streamed after the call and saves it.
+        fn take_picture(&self) -> Result<(), Box<dyn std::error::Error>> {
+            let start = Instant::now();
+            let seq = self.latest.lock().unwrap().0;
+            loop {
+                let latest = self.latest.lock().unwrap();
+                if seq < latest.0 {
+                    std::fs::write(&self.property.path.img.last, &latest.1)?;
+                    return Ok(());
+                }
+                drop(latest);
+                if FRAME_TIMEOUT < start.elapsed() {
+                    return Err("No frame from libcamera.".into());
+                }
+                thread::sleep(POLL_INTERVAL);
+            }
+        }
+    }
+
+    impl Drop for LibcameraCamera {
+        fn drop(&mut self) {
+            let _ = self.child.kill();
+            let _ = self.child.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn source_test() {
+        assert_eq!(Source::from_string("libcamera"), Source::Libcamera);
+        assert_eq!(Source::from_string("legacy"), Source::Legacy);
+        assert_eq!(Source::from_string(""), Source::V4l2);
+        assert_eq!(Source::from_string("picamera"), Source::V4l2);
+        assert_eq!(libcamera::awb_mode(2700), "incandescent");
+        assert_eq!(libcamera::awb_mode(5500), "daylight");
+        assert_eq!(libcamera::awb_mode(7000), "cloudy");
+    }
+
+    #[test]
+    fn next_jpeg_test() {
+        // Garbage, an image, and the next partly
+        let buf = [
+            0x00, 0xFF, 0xD8, 0x01, 0xFF, 0x00, 0xFF, 0xD9, 0xFF, 0xD8, 0x02,
+        ];
+        assert_eq!(libcamera::next_jpeg(&buf), Some((1, 8)));
+        assert_eq!(libcamera::next_jpeg(&buf[8..]), None);
+        assert_eq!(
+            libcamera::next_jpeg(&[0xFF, 0xD8, 0xFF, 0xD9]),
+            Some((0, 4))
+        );
     }
 }
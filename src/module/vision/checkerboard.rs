@@ -0,0 +1,269 @@
+//! Checkerboard Corner Detection
+//!
+//! Finds the inner corners of a checkerboard shown to the camera for the calibration. The corners
+//! are the peaks of the ChESS response, where the opposite points on a ring around a pixel are of
+//! the same shade and the neighboring ones of the other. They are arranged in the grid by growing
+//! it from the one nearest the center, predicting the next corner from the spacing so far, which
+//! follows the perspective of a tilted board.
+
+use image::{imageops, GrayImage};
+
+// Radius of the ring of the ChESS response in px
+const RING_RADIUS: f32 = 5.0;
+// Response (ratio to the highest) from which a peak is taken as a corner
+const RESPONSE_RATIO: f32 = 0.3;
+// Distance (ratio to the spacing) within which a corner is taken as the predicted one
+const MATCH_TOLERANCE: f32 = 0.35;
+// Width of the image to find the corners in px. Larger ones are scaled down.
+const MAX_WIDTH: u32 = 640;
+
+/// A corner arranged in the grid
+#[derive(Debug, Clone, Copy)]
+struct Cell {
+    at: (i32, i32), // Position in the grid
+    index: usize,   // Index of the candidate
+    e1: (f32, f32), // Spacing to the next corner along the first axis in px
+    e2: (f32, f32), // Spacing to the next corner along the second axis in px
+}
+
+/// Finds the `cols` * `rows` inner corners of a checkerboard in the image.
+/// Returns their positions in px in the order of the rows, or None if the board isn't found whole.
+pub fn find_corners(img: &GrayImage, cols: usize, rows: usize) -> Option<Vec<(f32, f32)>> {
+    let scale = (img.width() as f32 / MAX_WIDTH as f32).max(1.0);
+    let small = match 1.0 < scale {
+        true => imageops::resize(
+            img,
+            (img.width() as f32 / scale) as u32,
+            (img.height() as f32 / scale) as u32,
+            imageops::FilterType::Triangle,
+        ),
+        false => img.clone(),
+    };
+    let candidates = candidates(&imageops::blur(&small, 1.0));
+    let grid = arrange(&candidates, cols, rows)?;
+    Some(grid.iter().map(|(x, y)| (x * scale, y * scale)).collect())
+}
+
+/// ChESS response of the pixel. High at the X-shaped corners.
+fn response(img: &GrayImage, x: u32, y: u32) -> f32 {
+    let pixel = |x: i64, y: i64| img.get_pixel(x as u32, y as u32)[0] as f32;
+    let ring: Vec<f32> = (0..16)
+        .map(|n| {
+            let angle = n as f32 * std::f32::consts::PI / 8.0;
+            pixel(
+                x as i64 + (RING_RADIUS * angle.cos()).round() as i64,
+                y as i64 + (RING_RADIUS * angle.sin()).round() as i64,
+            )
+        })
+        .collect();
+    let sum: f32 = (0..4)
+        .map(|n| ((ring[n] + ring[n + 8]) - (ring[n + 4] + ring[n + 12])).abs())
+        .sum();
+    let diff: f32 = (0..8).map(|n| (ring[n] - ring[n + 8]).abs()).sum();
+    let ring_mean = ring.iter().sum::<f32>() / 16.0;
+    let mut local = 0.0;
+    for (dx, dy) in [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)] {
+        local += pixel(x as i64 + dx, y as i64 + dy);
+    }
+    sum - diff - 16.0 * (ring_mean - local / 5.0).abs()
+}
+
+/// Peaks of the response refined to subpixels.
+fn candidates(img: &GrayImage) -> Vec<(f32, f32)> {
+    let (w, h) = img.dimensions();
+    let margin = RING_RADIUS as u32 + 1;
+    if w <= margin * 2 || h <= margin * 2 {
+        return vec![];
+    }
+    let mut responses = vec![0.0_f32; (w * h) as usize];
+    for y in margin..h - margin {
+        for x in margin..w - margin {
+            responses[(y * w + x) as usize] = response(img, x, y).max(0.0);
+        }
+    }
+    let max = responses.iter().cloned().fold(0.0, f32::max);
+    if max <= 0.0 {
+        return vec![];
+    }
+    let at = |x: u32, y: u32| responses[(y * w + x) as usize];
+    let mut peaks = vec![];
+    let nms = 3;
+    for y in margin + nms..h - margin - nms {
+        for x in margin + nms..w - margin - nms {
+            let r = at(x, y);
+            if r < max * RESPONSE_RATIO {
+                continue;
+            }
+            let is_peak = (y - nms..=y + nms).all(|ny| {
+                (x - nms..=x + nms).all(|nx| {
+                    let other = at(nx, ny);
+                    other < r || (other == r && (ny, nx) >= (y, x))
+                })
+            });
+            if !is_peak {
+                continue;
+            }
+            // Centroid of the response around the peak
+            let (mut sx, mut sy, mut sr) = (0.0, 0.0, 0.0);
+            for ny in y - 1..=y + 1 {
+                for nx in x - 1..=x + 1 {
+                    let r = at(nx, ny);
+                    sx += nx as f32 * r;
+                    sy += ny as f32 * r;
+                    sr += r;
+                }
+            }
+            peaks.push((sx / sr, sy / sr));
+        }
+    }
+    peaks
+}
+
+/// Arranges the candidates in the grid of `cols` * `rows` corners.
+fn arrange(points: &[(f32, f32)], cols: usize, rows: usize) -> Option<Vec<(f32, f32)>> {
+    if points.len() < cols * rows || cols < 2 || rows < 2 {
+        return None;
+    }
+    let sub = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0, a.1 - b.1);
+    let norm = |v: (f32, f32)| v.0.hypot(v.1);
+    let n = points.len() as f32;
+    let center = points
+        .iter()
+        .fold((0.0, 0.0), |acc, p| (acc.0 + p.0 / n, acc.1 + p.1 / n));
+    let nearest = |from: (f32, f32), exclude: &dyn Fn(usize) -> bool| {
+        points
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| !exclude(*i))
+            .min_by(|a, b| norm(sub(*a.1, from)).total_cmp(&norm(sub(*b.1, from))))
+            .map(|(i, _)| i)
+    };
+    let start = nearest(center, &|_| false)?;
+    let p0 = points[start];
+    // The spacings along the two axes from the nearest corners
+    let a = nearest(p0, &|i| i == start)?;
+    let e1 = sub(points[a], p0);
+    let b = nearest(p0, &|i| {
+        let v = sub(points[i], p0);
+        i == start || 0.5 < ((v.0 * e1.0 + v.1 * e1.1) / (norm(v) * norm(e1))).abs()
+    })?;
+    let e2 = sub(points[b], p0);
+
+    // Grow the grid from the start, keeping the spacings of each corner.
+    let mut cells = vec![Cell {
+        at: (0, 0),
+        index: start,
+        e1,
+        e2,
+    }];
+    let mut queue = vec![0];
+    while let Some(c) = queue.pop() {
+        let Cell { at, index, e1, e2 } = cells[c];
+        let p = points[index];
+        for (di, dj) in [(1, 0), (-1, 0), (0, 1), (0, -1)] {
+            let next = (at.0 + di, at.1 + dj);
+            if cells.iter().any(|cell| cell.at == next) {
+                continue;
+            }
+            let step = match (di, dj) {
+                (1, 0) => e1,
+                (-1, 0) => (-e1.0, -e1.1),
+                (0, 1) => e2,
+                _ => (-e2.0, -e2.1),
+            };
+            let predicted = (p.0 + step.0, p.1 + step.1);
+            let Some(found) = nearest(predicted, &|k| cells.iter().any(|cell| cell.index == k))
+            else {
+                continue;
+            };
+            if MATCH_TOLERANCE * norm(step) < norm(sub(points[found], predicted)) {
+                continue;
+            }
+            let seen = sub(points[found], p);
+            let (e1, e2) = match (di, dj) {
+                (1, 0) => (seen, e2),
+                (-1, 0) => ((-seen.0, -seen.1), e2),
+                (0, 1) => (e1, seen),
+                _ => (e1, (-seen.0, -seen.1)),
+            };
+            cells.push(Cell {
+                at: next,
+                index: found,
+                e1,
+                e2,
+            });
+            queue.push(cells.len() - 1);
+        }
+    }
+
+    let min_i = cells.iter().map(|cell| cell.at.0).min()?;
+    let max_i = cells.iter().map(|cell| cell.at.0).max()?;
+    let min_j = cells.iter().map(|cell| cell.at.1).min()?;
+    let max_j = cells.iter().map(|cell| cell.at.1).max()?;
+    let span = ((max_i - min_i + 1) as usize, (max_j - min_j + 1) as usize);
+    if cells.len() != cols * rows {
+        return None;
+    }
+    let transpose = match span {
+        s if s == (cols, rows) => false,
+        s if s == (rows, cols) => true,
+        _ => return None,
+    };
+    let mut grid = vec![(0.0, 0.0); cols * rows];
+    for cell in cells {
+        let (i, j) = ((cell.at.0 - min_i) as usize, (cell.at.1 - min_j) as usize);
+        let (col, row) = if transpose { (j, i) } else { (i, j) };
+        grid[row * cols + col] = points[cell.index];
+    }
+    Some(grid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Renders a board of (cols + 1) * (rows + 1) squares seen through the mapping of the board
+    /// coordinates to the image.
+    fn render(
+        cols: usize,
+        rows: usize,
+        (w, h): (u32, u32),
+        to_board: impl Fn(f32, f32) -> (f32, f32),
+    ) -> GrayImage {
+        GrayImage::from_fn(w, h, |x, y| {
+            let (bx, by) = to_board(x as f32 + 0.5, y as f32 + 0.5);
+            let inside = 0.0 <= bx && bx < (cols + 1) as f32 && 0.0 <= by && by < (rows + 1) as f32;
+            let dark = inside && (bx.floor() as i32 + by.floor() as i32) % 2 == 0;
+            Luma([if dark { 20 } else { 230 }])
+        })
+    }
+
+    #[test]
+    fn checkerboard_test() {
+        let (cols, rows) = (7, 5);
+        // 30 px squares from (100, 80), tilted a little
+        let (ox, oy, size, tilt) = (100.0, 80.0, 30.0, 0.1_f32);
+        let to_board = |x: f32, y: f32| {
+            let (dx, dy) = (x - ox, y - oy);
+            let (s, c) = tilt.sin_cos();
+            ((dx * c + dy * s) / size, (-dx * s + dy * c) / size)
+        };
+        let img = render(cols, rows, (400, 300), to_board);
+        let corners = find_corners(&img, cols, rows).unwrap();
+        assert_eq!(corners.len(), cols * rows);
+        let (s, c) = tilt.sin_cos();
+        for j in 1..=rows {
+            for i in 1..=cols {
+                let (bx, by) = (i as f32 * size, j as f32 * size);
+                let expected = (ox + bx * c - by * s, oy + bx * s + by * c);
+                assert!(corners
+                    .iter()
+                    .any(|p| (p.0 - expected.0).hypot(p.1 - expected.1) < 1.5));
+            }
+        }
+        // Not the whole board
+        assert_eq!(find_corners(&img, cols + 1, rows), None);
+        assert_eq!(find_corners(&GrayImage::new(400, 300), cols, rows), None);
+    }
+}
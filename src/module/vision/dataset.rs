@@ -0,0 +1,256 @@
+//! Dataset of the Field
+//!
+//! Frames kept with the detections as their labels, in the YOLO format, to train the models further
+//! with the data of the field:
+//!
+//! dataset/images/<name>.jpg   <- The frames
+//! dataset/labels/<name>.txt   <- A line of `class x_center y_center width height` per object,
+//!                                in ratios of the frame
+//!
+//! The samples to review are kept in `dataset/review/` instead, and moved to the above once
+//! accepted as they are or with the labels corrected by hand, with `roktrack review` on the console.
+//! The rejected ones are removed.
+
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use super::detector::Detection;
+use crate::module::define;
+
+// Subdirectories of the dataset
+const IMAGES_DIR: &str = "images";
+const LABELS_DIR: &str = "labels";
+const REVIEW_DIR: &str = "review";
+// Least class of the detections added by the vision, not by the models (e.g. the grass edges)
+const SYNTHETIC_CLASS: u32 = 100;
+
+/// The directory of the dataset in the data directory.
+pub fn dir(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join(define::path::DATASET_DIR)
+}
+
+/// Formats the detections in the input of the size `imgsz`, which is the frame resized, as the
+/// labels of the YOLO format. Those not of the models are left out.
+pub fn labels(dets: &[Detection], imgsz: u32) -> String {
+    if imgsz == 0 {
+        return String::new();
+    }
+    let sz = imgsz as f32;
+    dets.iter()
+        .filter(|det| det.cls < SYNTHETIC_CLASS)
+        .map(|det| {
+            let (w, h) = ((det.x2 - det.x1) as f32, (det.y2 - det.y1) as f32);
+            format!(
+                "{} {:.6} {:.6} {:.6} {:.6}\n",
+                det.cls,
+                (det.x1 as f32 + w / 2.0) / sz,
+                (det.y1 as f32 + h / 2.0) / sz,
+                w / sz,
+                h / sz,
+            )
+        })
+        .collect()
+}
+
+/// Parses the labels entered by hand, keeping the lines of the YOLO format in the frame.
+/// The line numbers of the others are returned as invalid.
+pub fn parse_labels(text: &str) -> (String, Vec<usize>) {
+    let mut labels = String::new();
+    let mut invalid = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let valid = fields.len() == 5
+            && fields[0].parse::<u32>().is_ok()
+            && fields[1..]
+                .iter()
+                .all(|v| v.parse::<f32>().is_ok_and(|v| (0.0..=1.0).contains(&v)));
+        match valid {
+            true => labels.push_str(&format!("{}\n", fields.join(" "))),
+            false if fields.is_empty() => {}
+            false => invalid.push(i + 1),
+        }
+    }
+    (labels, invalid)
+}
+
+/// Keeps the frame with the labels as `name`, to review first or in the dataset right away.
+pub fn save(
+    data_dir: &str,
+    frame: &str,
+    labels: &str,
+    name: &str,
+    review: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = dir(data_dir);
+    let (images, label_dir) = match review {
+        true => (dir.join(REVIEW_DIR), dir.join(REVIEW_DIR)),
+        false => (dir.join(IMAGES_DIR), dir.join(LABELS_DIR)),
+    };
+    fs::create_dir_all(&images)?;
+    fs::create_dir_all(&label_dir)?;
+    fs::copy(frame, images.join(format!("{}.jpg", name)))?;
+    fs::write(label_dir.join(format!("{}.txt", name)), labels)?;
+    Ok(())
+}
+
+/// Names of the samples to review, in the order of the capture.
+pub fn pending(data_dir: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir(data_dir).join(REVIEW_DIR)) else {
+        return vec![];
+    };
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jpg"))
+        .filter_map(|path| Some(path.file_stem()?.to_str()?.to_string()))
+        .collect();
+    names.sort();
+    names
+}
+
+/// Moves the sample under review to the dataset, with the labels corrected if given.
+pub fn accept(
+    data_dir: &str,
+    name: &str,
+    labels: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let dir = dir(data_dir);
+    let review = dir.join(REVIEW_DIR);
+    fs::create_dir_all(dir.join(IMAGES_DIR))?;
+    fs::create_dir_all(dir.join(LABELS_DIR))?;
+    let label = dir.join(LABELS_DIR).join(format!("{}.txt", name));
+    match labels {
+        Some(labels) => {
+            fs::write(&label, labels)?;
+            let _ = fs::remove_file(review.join(format!("{}.txt", name)));
+        }
+        None => fs::rename(review.join(format!("{}.txt", name)), &label)?,
+    }
+    fs::rename(
+        review.join(format!("{}.jpg", name)),
+        dir.join(IMAGES_DIR).join(format!("{}.jpg", name)),
+    )?;
+    Ok(())
+}
+
+/// Removes the sample under review.
+pub fn reject(data_dir: &str, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let review = dir(data_dir).join(REVIEW_DIR);
+    fs::remove_file(review.join(format!("{}.jpg", name)))?;
+    let _ = fs::remove_file(review.join(format!("{}.txt", name)));
+    Ok(())
+}
+
+/// Reviews the samples one by one on the console: accepts, rejects, or corrects the labels of
+/// each, until all are reviewed or quit. Returns the numbers of the accepted and the rejected.
+pub fn review(
+    data_dir: &str,
+    mut input: impl BufRead,
+    mut output: impl Write,
+) -> Result<(usize, usize), Box<dyn std::error::Error>> {
+    let (mut accepted, mut rejected) = (0, 0);
+    let names = pending(data_dir);
+    let review = dir(data_dir).join(REVIEW_DIR);
+    for (i, name) in names.iter().enumerate() {
+        let image = review.join(format!("{}.jpg", name));
+        let labels = fs::read_to_string(review.join(format!("{}.txt", name))).unwrap_or_default();
+        writeln!(output, "[{}/{}] {}", i + 1, names.len(), image.display())?;
+        write!(output, "{}", labels)?;
+        writeln!(output, "[a]ccept, [r]eject, [e]dit labels, [s]kip, [q]uit?")?;
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            break;
+        }
+        match line.trim() {
+            "a" => {
+                accept(data_dir, name, None)?;
+                accepted += 1;
+            }
+            "r" => {
+                reject(data_dir, name)?;
+                rejected += 1;
+            }
+            "e" => {
+                writeln!(
+                    output,
+                    "Labels (class x_center y_center width height), then an empty line:"
+                )?;
+                let mut text = String::new();
+                loop {
+                    let mut line = String::new();
+                    if input.read_line(&mut line)? == 0 || line.trim().is_empty() {
+                        break;
+                    }
+                    text.push_str(&line);
+                }
+                let (labels, invalid) = parse_labels(&text);
+                if !invalid.is_empty() {
+                    writeln!(output, "Invalid lines left out: {:?}", invalid)?;
+                }
+                accept(data_dir, name, Some(&labels))?;
+                accepted += 1;
+            }
+            "q" => break,
+            _ => {}
+        }
+    }
+    writeln!(output, "Accepted: {}, Rejected: {}", accepted, rejected)?;
+    Ok((accepted, rejected))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn labels_test() {
+        let det = |cls: u32| Detection {
+            cls,
+            x1: 80,
+            y1: 160,
+            x2: 120,
+            y2: 240,
+            ..Default::default()
+        };
+        assert_eq!(
+            labels(&[det(0), det(100)], 320),
+            "0 0.312500 0.625000 0.125000 0.250000\n"
+        );
+        assert_eq!(labels(&[det(0)], 0), "");
+
+        let (labels, invalid) = parse_labels("1 0.5 0.5 0.2 0.4\n\n1 0.5 0.5\n2 0.5 1.5 0.1 0.1\n");
+        assert_eq!(labels, "1 0.5 0.5 0.2 0.4\n");
+        assert_eq!(invalid, vec![3, 4]);
+    }
+
+    #[test]
+    fn review_test() {
+        let data_dir = "/tmp/roktracktest/dataset_test/";
+        let _ = fs::remove_dir_all(data_dir);
+        fs::create_dir_all(data_dir).unwrap();
+        let frame = Path::new(data_dir).join("frame.jpg");
+        fs::write(&frame, b"jpg").unwrap();
+        let frame = frame.to_str().unwrap();
+        let label = "0 0.500000 0.500000 0.100000 0.200000\n";
+        for name in ["1", "2", "3", "4"] {
+            save(data_dir, frame, label, name, true).unwrap();
+        }
+        save(data_dir, frame, label, "0", false).unwrap();
+        assert_eq!(pending(data_dir), vec!["1", "2", "3", "4"]);
+
+        // Accept, reject, correct, and leave the last
+        let input = Cursor::new("a\nr\ne\n1 0.2 0.2 0.1 0.1\n\ns\n");
+        let mut output = vec![];
+        assert_eq!(review(data_dir, input, &mut output).unwrap(), (2, 1));
+        assert_eq!(pending(data_dir), vec!["4"]);
+        let dir = dir(data_dir);
+        let read = |name: &str| fs::read_to_string(dir.join(LABELS_DIR).join(name)).unwrap();
+        assert_eq!(read("0.txt"), label);
+        assert_eq!(read("1.txt"), label);
+        assert!(!dir.join(IMAGES_DIR).join("2.jpg").exists());
+        assert_eq!(read("3.txt"), "1 0.2 0.2 0.1 0.1\n");
+        assert!(dir.join(IMAGES_DIR).join("3.jpg").exists());
+    }
+}
@@ -0,0 +1,185 @@
+//! Depth Maps
+//!
+//! Distances per pixel of the frames, measured with the stereo camera (see `stereo`) or read from a
+//! depth camera. Each detection is given the median depth of the center of its box, which is then
+//! the distance measured instead of the one estimated from the height, and the nearest depth ahead
+//! is sent to the pilots as the proximity of the obstacles, like the range of the ultrasonic sensor.
+//!
+//! A depth camera, such as the RealSense D400 series or the OAK-D, is read through V4L2: the color
+//! frames for the detector from one node and the 16-bit depths (Z16) from another. The depths are
+//! taken as aligned to the color frames, i.e. of the same field of view.
+
+use image::{Rgb, RgbImage};
+
+use super::detector::Detection;
+
+// Part of the box around its center whose depth is taken, off the background at the edges
+const CENTER_RATIO: f32 = 0.5;
+// Least ratio of the known depths in the center of a box to give it the depth
+const MIN_KNOWN: f32 = 0.1;
+// Region ahead of the mower (x1, y1, x2, y2 in ratios of the frame), above the ground at the bottom
+const AHEAD: (f32, f32, f32, f32) = (0.3, 0.2, 0.7, 0.8);
+// Ratio of the known depths ahead nearer than the proximity, over the noise of single pixels
+const NEAREST_RATIO: f32 = 0.05;
+
+/// Depth per pixel of a frame (`width` * `height`, row-major)
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthMap {
+    pub width: u32,
+    pub height: u32,
+    pub depth: Vec<f32>, // Depth in m (0: unknown)
+}
+
+impl DepthMap {
+    /// Reads the depth frame of the Z16 format, of the depths in the units of `scale` m.
+    /// None if the data isn't of the size.
+    pub fn from_z16(data: &[u8], width: u32, height: u32, scale: f32) -> Option<Self> {
+        if data.len() != (width * height * 2) as usize {
+            return None;
+        }
+        let depth = data
+            .chunks_exact(2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]) as f32 * scale)
+            .collect();
+        Some(Self {
+            width,
+            height,
+            depth,
+        })
+    }
+
+    /// The known depths in the region (x1, y1, x2, y2 in px), clipped by the map.
+    fn known(&self, (x1, y1, x2, y2): (u32, u32, u32, u32)) -> (Vec<f32>, usize) {
+        let (x2, y2) = (x2.min(self.width), y2.min(self.height));
+        let known = (y1..y2)
+            .flat_map(|y| (x1..x2).map(move |x| (x, y)))
+            .map(|(x, y)| self.depth[(y * self.width + x) as usize])
+            .filter(|depth| 0.0 < *depth)
+            .collect();
+        let total = x2.saturating_sub(x1) * y2.saturating_sub(y1);
+        (known, total as usize)
+    }
+
+    /// The proximity of the obstacles: the nearest depth ahead in m. None if too little is known.
+    pub fn nearest(&self) -> Option<f32> {
+        let (w, h) = (self.width as f32, self.height as f32);
+        let region = (
+            (w * AHEAD.0) as u32,
+            (h * AHEAD.1) as u32,
+            (w * AHEAD.2) as u32,
+            (h * AHEAD.3) as u32,
+        );
+        let (mut known, total) = self.known(region);
+        if known.is_empty() || (known.len() as f32) < total as f32 * MIN_KNOWN {
+            return None;
+        }
+        known.sort_by(f32::total_cmp);
+        Some(known[(known.len() as f32 * NEAREST_RATIO) as usize])
+    }
+}
+
+/// Gives the detections in the input of the size `imgsz`, which is the frame resized, the median
+/// depth of the center of their boxes as the distances.
+pub fn annotate(dets: &mut [Detection], map: &DepthMap, imgsz: u32) {
+    if imgsz == 0 {
+        return;
+    }
+    let (sx, sy) = (
+        map.width as f32 / imgsz as f32,
+        map.height as f32 / imgsz as f32,
+    );
+    let margin = (1.0 - CENTER_RATIO) / 2.0;
+    for det in dets.iter_mut() {
+        let (w, h) = ((det.x2 - det.x1) as f32, (det.y2 - det.y1) as f32);
+        let region = (
+            ((det.x1 as f32 + w * margin) * sx) as u32,
+            ((det.y1 as f32 + h * margin) * sy) as u32,
+            ((det.x2 as f32 - w * margin) * sx).ceil() as u32,
+            ((det.y2 as f32 - h * margin) * sy).ceil() as u32,
+        );
+        let (mut known, total) = map.known(region);
+        if known.is_empty() || (known.len() as f32) < total as f32 * MIN_KNOWN {
+            continue;
+        }
+        known.sort_by(f32::total_cmp);
+        det.distance = Some(known[known.len() / 2]);
+    }
+}
+
+/// Converts the color frame of the YUYV format to RGB. None if the data isn't of the size.
+pub fn yuyv_to_rgb(data: &[u8], width: u32, height: u32) -> Option<RgbImage> {
+    if width % 2 == 1 || data.len() != (width * height * 2) as usize {
+        return None;
+    }
+    let mut img = RgbImage::new(width, height);
+    // Y0 U Y1 V for each pair of pixels, by BT.601
+    for (i, v) in data.chunks_exact(4).enumerate() {
+        let (u, v, ys) = (v[1] as f32 - 128.0, v[3] as f32 - 128.0, [v[0], v[2]]);
+        for (k, y) in ys.into_iter().enumerate() {
+            let y = y as f32;
+            let rgb = [y + 1.402 * v, y - 0.344 * u - 0.714 * v, y + 1.772 * u]
+                .map(|c| c.round().clamp(0.0, 255.0) as u8);
+            let at = i as u32 * 2 + k as u32;
+            img.put_pixel(at % width, at / width, Rgb(rgb));
+        }
+    }
+    Some(img)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn depth_test() {
+        // 2 m on the left half and 1 m on the right half in mm, unknown on the first column
+        let (w, h) = (320, 180);
+        let data: Vec<u8> = (0..w * h)
+            .flat_map(|i| {
+                match i % w {
+                    0 => 0_u16,
+                    x if x < 160 => 2000,
+                    _ => 1000,
+                }
+                .to_le_bytes()
+            })
+            .collect();
+        let map = DepthMap::from_z16(&data, w, h, 0.001).unwrap();
+        assert_eq!(map.depth[90 * 320 + 80], 2.0);
+        assert_eq!(map.depth[90 * 320 + 240], 1.0);
+        assert_eq!(DepthMap::from_z16(&data[2..], w, h, 0.001), None);
+        // The nearest ahead
+        assert_eq!(map.nearest(), Some(1.0));
+        let unknown = DepthMap::from_z16(&vec![0; data.len()], w, h, 0.001).unwrap();
+        assert_eq!(unknown.nearest(), None);
+
+        // Boxes in the input of 320 * 320
+        let det = |x1: u32, x2: u32| Detection {
+            x1,
+            y1: 100,
+            x2,
+            y2: 220,
+            ..Default::default()
+        };
+        let mut dets = [det(40, 120), det(200, 280), det(0, 1)];
+        annotate(&mut dets, &map, 320);
+        assert_eq!(dets[0].distance, Some(2.0));
+        assert_eq!(dets[1].distance, Some(1.0));
+        assert_eq!(dets[2].distance, None);
+        let mut dets = [det(40, 120)];
+        annotate(&mut dets, &unknown, 320);
+        assert_eq!(dets[0].distance, None);
+    }
+
+    #[test]
+    fn yuyv_test() {
+        // Gray, then red
+        let data = [128, 128, 128, 128, 76, 85, 76, 255];
+        let img = yuyv_to_rgb(&data, 2, 2).unwrap();
+        assert_eq!(img.get_pixel(0, 0), &Rgb([128, 128, 128]));
+        assert_eq!(img.get_pixel(1, 0), &Rgb([128, 128, 128]));
+        let red = img.get_pixel(0, 1);
+        assert!(250 <= red[0] && red[1] < 5 && red[2] < 5);
+        assert_eq!(yuyv_to_rgb(&data[..4], 2, 2), None);
+    }
+}
@@ -1,5 +1,239 @@
 //! Provide Object Detection
 //!
+//! The models run on one of the backends implementing `Detector`, selected by `detector` in the
+//! `[vision]` section of the config.
+//!
+//! | detector   | runtime                                 | models                       | feature    |
+//! |------------|-----------------------------------------|------------------------------|------------|
+//! | 'onnx'     | ONNX Runtime on the CPU                 | asset/model/*.onnx           |            |
+//! | 'openvino' | ONNX Runtime with the OpenVINO provider | asset/model/*.onnx           | `openvino` |
+//! | 'tflite'   | TensorFlow Lite                         | asset/model/*.tflite         | `tflite`   |
+//! | 'edgetpu'  | TensorFlow Lite with the Coral Edge TPU | asset/model/*_edgetpu.tflite | `edgetpu`  |
+//!
+//! A backend not built in falls back to ONNX Runtime on the CPU, as does the Edge TPU backend
+//! without the accelerator plugged in. Other NPUs, e.g. the Hailo on the Pi 5, aren't supported yet.
+//!
+//! The raw detections are filtered by the score thresholds of their classes, merged by NMS and
+//! capped to the best ones, as configured in the `[detectthreshold]` section.
+
+use super::camera::FRONT;
+use crate::module::util::{
+    conf::{Config, DetectThreshold},
+    init::RoktrackProperty,
+};
+
+/// Session Types
+///
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionType {
+    Sz320, // basic 320 * 320 inference
+    Sz640, // basic 640 * 640 inference
+    Ocr,   // ocr 96 * 96 inference
+    Seg,   // grass segmentation 320 * 320 inference
+    Pose,  // person pose estimation 320 * 320 inference
+}
+/// Session Type methods
+///
+impl SessionType {
+    pub fn get_imgsz(&self) -> u32 {
+        match self {
+            Self::Sz320 => 320,
+            Self::Sz640 => 640,
+            Self::Ocr => 96,
+            Self::Seg => 320,
+            Self::Pose => 320,
+        }
+    }
+}
+
+/// Bundles of the models used together
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bundle {
+    Pylon,      // pylon detection
+    PylonOcr,   // pylon detection with digit OCR
+    Animal,     // animal detection
+    PylonGrass, // pylon detection with grass segmentation
+    PylonPose,  // pylon detection with person pose estimation
+}
+
+impl Bundle {
+    /// Name of the bundle as the active model
+    pub fn name(&self) -> &'static str {
+        match self {
+            Bundle::Pylon => "pylon",
+            Bundle::PylonOcr => "pylon_ocr",
+            Bundle::Animal => "animal",
+            Bundle::PylonGrass => "pylon_grass",
+            Bundle::PylonPose => "pylon_pose",
+        }
+    }
+}
+
+/// Name of a model file as the active model, e.g. "animal_320_320" for "asset/model/animal_320_320.onnx".
+pub fn model_name(path: &str) -> String {
+    std::path::Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Runtimes to run the models
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backend {
+    Onnx,
+    OpenVino,
+    TfLite,
+    EdgeTpu,
+}
+
+impl Backend {
+    pub fn from_string(s: &str) -> Backend {
+        match s {
+            // 'yolov7onnx' is the name in the older configs.
+            "onnx" | "yolov7onnx" => Backend::Onnx,
+            "openvino" => Backend::OpenVino,
+            "tflite" => Backend::TfLite,
+            "edgetpu" => Backend::EdgeTpu,
+            _ => {
+                log::warn!("Invalid Detector: {}. Use ONNX Runtime.", s);
+                Backend::Onnx
+            }
+        }
+    }
+}
+
+/// Object detection backend
+///
+/// Runs a bundle of models on the images taken by the camera. A backend may not support every
+/// bundle. Then `load` fails and the current models are kept.
+pub trait Detector: Send {
+    /// Builds the models of the bundle, replacing the current ones.
+    fn load(&mut self, bundle: Bundle) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Loads a model file as the detection model of both resolutions, replacing the current ones.
+    fn load_model(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        Err(format!("Loading {} is not supported.", path).into())
+    }
+
+    /// Name of the active models, the bundle or the model file.
+    fn model(&self) -> String;
+
+    /// Resolution of the detection.
+    fn session_type(&self) -> SessionType;
+
+    /// Switches the resolution of the detection.
+    fn set_session_type(&mut self, session_type: SessionType);
+
+    /// Detects objects in the image at the current resolution.
+    fn detect(&self, impath: &str) -> Result<Vec<Detection>, Box<dyn std::error::Error>>;
+
+    /// Whether the current models support OCR
+    fn support_ocr(&self) -> bool {
+        false
+    }
+
+    /// Reads the numbers on the markers.
+    fn ocr(
+        &self,
+        _impath: &str,
+        dets: Vec<Detection>,
+        _property: RoktrackProperty,
+    ) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+        Ok(dets)
+    }
+
+    /// Whether the current models support grass segmentation
+    fn support_segmentation(&self) -> bool {
+        false
+    }
+
+    /// Finds the drivable grass.
+    fn segment(&self, _impath: &str) -> Result<segment::GrassMask, Box<dyn std::error::Error>> {
+        Err("Segmentation is not supported.".into())
+    }
+
+    /// Whether the current models support pose estimation
+    fn support_pose(&self) -> bool {
+        false
+    }
+
+    /// Finds raised hands of persons.
+    fn pose(&self, _impath: &str) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+        Err("Pose estimation is not supported.".into())
+    }
+}
+
+/// Builds the detector of the configured backend with the pylon models.
+///
+pub fn build(conf: &Config) -> Result<Box<dyn Detector>, Box<dyn std::error::Error>> {
+    let backend = Backend::from_string(&conf.vision.detector);
+    let thresholds = conf.detectthreshold.clone();
+    log::info!("Detector Backend: {:?}", backend);
+    match backend {
+        #[cfg(feature = "tflite")]
+        Backend::TfLite => Ok(Box::new(tflite::YoloV8Lite::new(
+            Bundle::Pylon,
+            thresholds,
+        )?)),
+        #[cfg(not(feature = "tflite"))]
+        Backend::TfLite => {
+            log::warn!("TFLite Backend Not Built In. Use ONNX Runtime.");
+            Ok(Box::new(onnx::YoloV8::new(Backend::Onnx, thresholds)?))
+        }
+        #[cfg(feature = "edgetpu")]
+        Backend::EdgeTpu => match edgetpu::YoloV8EdgeTpu::new(Bundle::Pylon, thresholds.clone()) {
+            Ok(detector) => Ok(Box::new(detector)),
+            Err(e) => {
+                log::warn!("Edge TPU Unavailable: {}. Use ONNX Runtime.", e);
+                Ok(Box::new(onnx::YoloV8::new(Backend::Onnx, thresholds)?))
+            }
+        },
+        #[cfg(not(feature = "edgetpu"))]
+        Backend::EdgeTpu => {
+            log::warn!("Edge TPU Backend Not Built In. Use ONNX Runtime.");
+            Ok(Box::new(onnx::YoloV8::new(Backend::Onnx, thresholds)?))
+        }
+        #[cfg(not(feature = "openvino"))]
+        Backend::OpenVino => {
+            log::warn!("OpenVINO Backend Not Built In. Use ONNX Runtime.");
+            Ok(Box::new(onnx::YoloV8::new(Backend::Onnx, thresholds)?))
+        }
+        _ => Ok(Box::new(onnx::YoloV8::new(backend, thresholds)?)),
+    }
+}
+
+/// Class sets of the models, to look up the score thresholds
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Classes {
+    Roktrack, // RoktrackClasses
+    Animal,   // AnimalClasses
+    Other,    // digits and custom models
+}
+
+/// Score threshold of the class. A threshold of 0 falls back to the default.
+///
+fn threshold(conf: &DetectThreshold, classes: Classes, cls: u32) -> f32 {
+    let th = match classes {
+        Classes::Roktrack => match RoktrackClasses::from_u32(cls) {
+            Some(RoktrackClasses::PYLON) => conf.pylon,
+            Some(RoktrackClasses::PERSON) => conf.person,
+            Some(RoktrackClasses::ROKTRACK) => conf.roktrack,
+            Some(class) if class.category() == Category::Obstacle => conf.obstacle,
+            _ => 0.0,
+        },
+        Classes::Animal => conf.animal,
+        Classes::Other => 0.0,
+    };
+    if th > 0.0 {
+        th
+    } else {
+        conf.default
+    }
+}
+
 pub mod onnx {
     use crate::module::{define, util::init::RoktrackProperty};
     use image::{imageops::FilterType, io::Reader, ImageBuffer, Pixel, Rgb};
@@ -10,27 +244,10 @@ pub mod onnx {
     };
     use std::path::Path;
 
-    use super::Detection;
+    use super::{
+        Backend, Bundle, Classes, DetectThreshold, Detection, Detector, SessionType, FRONT,
+    };
 
-    /// Session Types
-    ///
-    #[derive(Debug, Clone, PartialEq)]
-    pub enum SessionType {
-        Sz320, // basic 320 * 320 inference
-        Sz640, // basic 640 * 640 inference
-        Ocr,   // ocr 96 * 96 inference
-    }
-    /// Session Type methods
-    ///
-    impl SessionType {
-        fn get_imgsz(&self) -> u32 {
-            match self {
-                Self::Sz320 => 320,
-                Self::Sz640 => 640,
-                Self::Ocr => 96,
-            }
-        }
-    }
     /// Bundled Sessions
     ///
     pub enum Sessions {
@@ -47,42 +264,65 @@ pub mod onnx {
             sz320: Session,
             sz640: Session,
         },
+        PylonGrass {
+            sz320: Session,
+            sz640: Session,
+            seg: Session,
+        },
+        PylonPose {
+            sz320: Session,
+            sz640: Session,
+            pose: Session,
+        },
+        Custom {
+            model: Session,
+            imgsz: Option<u32>, // Fixed input size (None: dynamic)
+        },
     }
 
     /// YoloV8 session store.
     ///
     pub struct YoloV8 {
+        pub backend: Backend,
         pub sessions: Sessions,
         pub session_type: SessionType,
-    }
-
-    impl Default for YoloV8 {
-        fn default() -> Self {
-            Self::new()
-        }
+        pub model: String,
+        pub thresholds: DetectThreshold,
     }
 
     /// Methods for yolov8.
     ///
     impl YoloV8 {
-        /// yolov8's constructor.
+        /// yolov8's constructor with the pylon sessions.
         ///
-        pub fn new() -> Self {
-            Self {
-                sessions: Self::build_pylon_sessions().expect("Can't initialize pylon sessions"),
+        pub fn new(
+            backend: Backend,
+            thresholds: DetectThreshold,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            Ok(Self {
+                sessions: Self::build_sessions(backend, Bundle::Pylon)?,
+                backend,
                 session_type: SessionType::Sz320,
-            }
+                model: Bundle::Pylon.name().to_string(),
+                thresholds,
+            })
         }
         /// get session
         ///
         pub fn get_session(
+            backend: Backend,
             name: &str,
             model_path: &str,
         ) -> Result<Session, Box<dyn std::error::Error>> {
+            let provider = match backend {
+                #[cfg(feature = "openvino")]
+                Backend::OpenVino => ExecutionProvider::OpenVINO(Default::default()),
+                _ => ExecutionProvider::CPU(Default::default()),
+            };
             let environment = Environment::builder()
                 .with_name(name)
                 .with_log_level(LoggingLevel::Warning)
-                .with_execution_providers([ExecutionProvider::CPU(Default::default())])
+                .with_execution_providers([provider])
                 .build()?
                 .into_arc();
             let session = SessionBuilder::new(&environment)?
@@ -91,48 +331,64 @@ pub mod onnx {
                 .with_model_from_file(model_path)?;
             Ok(session)
         }
-        /// Build Pylon Session Bundle
+        /// Build Session Bundle
         ///
-        pub fn build_pylon_sessions() -> Result<Sessions, Box<dyn std::error::Error>> {
-            let sessions = Sessions::Pylon {
-                sz320: Self::get_session("pylon_sz320", define::path::PYLON_320_MODEL)?,
-                sz640: Self::get_session("pylon_sz640", define::path::PYLON_640_MODEL)?,
+        pub fn build_sessions(
+            backend: Backend,
+            bundle: Bundle,
+        ) -> Result<Sessions, Box<dyn std::error::Error>> {
+            let session = |name, model_path| Self::get_session(backend, name, model_path);
+            let pylon = |name, model_path| {
+                let session = session(name, model_path)?;
+                // 1 * (4 + classes) * boxes
+                let rows = session
+                    .outputs
+                    .first()
+                    .and_then(|output| output.dimensions.get(1).copied().flatten());
+                super::check_classes(model_path, rows);
+                Ok::<_, Box<dyn std::error::Error>>(session)
             };
-            Ok(sessions)
-        }
-        /// Build Pylon OCR Session Bundle
-        ///
-        pub fn build_pylon_ocr_sessions() -> Result<Sessions, Box<dyn std::error::Error>> {
-            let sessions = Sessions::PylonOcr {
-                sz320: Self::get_session("pylon_sz320", define::path::PYLON_320_MODEL)?,
-                sz640: Self::get_session("pylon_sz640", define::path::PYLON_640_MODEL)?,
-                ocr: Self::get_session("pylon_ocr", define::path::DIGIT_OCR_96_MODEL)?,
-            };
-            Ok(sessions)
-        }
-        /// Build Animal Session Bundle
-        ///
-        pub fn build_animal_sessions() -> Result<Sessions, Box<dyn std::error::Error>> {
-            let sessions = Sessions::Animal {
-                sz320: Self::get_session("animal_sz320", define::path::ANIMAL_320_MODEL)?,
-                sz640: Self::get_session("animal_sz640", define::path::ANIMAL_640_MODEL)?,
+            let sessions = match bundle {
+                Bundle::Pylon => Sessions::Pylon {
+                    sz320: pylon("pylon_sz320", define::path::PYLON_320_MODEL)?,
+                    sz640: pylon("pylon_sz640", define::path::PYLON_640_MODEL)?,
+                },
+                Bundle::PylonOcr => Sessions::PylonOcr {
+                    sz320: pylon("pylon_sz320", define::path::PYLON_320_MODEL)?,
+                    sz640: pylon("pylon_sz640", define::path::PYLON_640_MODEL)?,
+                    ocr: session("pylon_ocr", define::path::DIGIT_OCR_96_MODEL)?,
+                },
+                Bundle::Animal => Sessions::Animal {
+                    sz320: session("animal_sz320", define::path::ANIMAL_320_MODEL)?,
+                    sz640: session("animal_sz640", define::path::ANIMAL_640_MODEL)?,
+                },
+                Bundle::PylonGrass => Sessions::PylonGrass {
+                    sz320: pylon("pylon_sz320", define::path::PYLON_320_MODEL)?,
+                    sz640: pylon("pylon_sz640", define::path::PYLON_640_MODEL)?,
+                    seg: session("grass_seg", define::path::GRASS_SEG_320_MODEL)?,
+                },
+                Bundle::PylonPose => Sessions::PylonPose {
+                    sz320: pylon("pylon_sz320", define::path::PYLON_320_MODEL)?,
+                    sz640: pylon("pylon_sz640", define::path::PYLON_640_MODEL)?,
+                    pose: session("person_pose", define::path::POSE_320_MODEL)?,
+                },
             };
             Ok(sessions)
         }
-        /// Infer
+        /// Load an image as the input tensor of the model.
         ///
-        pub fn infer(
-            &self,
+        fn load_tensor(
             impath: &str,
-            session_type: SessionType,
-        ) -> Result<Vec<super::Detection>, Box<dyn std::error::Error>> {
-            let sz = session_type.get_imgsz();
+            sz: u32,
+        ) -> Result<ndarray::CowArray<'static, f32, IxDyn>, Box<dyn std::error::Error>> {
             // Load image and resize to model's shape, converting to RGB format
             let img: ImageBuffer<Rgb<u8>, Vec<u8>> = image::open(Path::new(impath))?
                 .resize_exact(sz, sz, FilterType::Nearest)
                 .to_rgb8();
+            // The frames of the IR at night in grayscale
+            let img = super::infrared::prepare(img);
 
-            let array = ndarray::CowArray::from(
+            Ok(ndarray::CowArray::from(
                 ndarray::Array::from_shape_fn((1, 3, sz as usize, sz as usize), |(_, c, j, i)| {
                     let pixel = img.get_pixel(i as u32, j as u32);
                     let channels = pixel.channels();
@@ -141,7 +397,23 @@ pub mod onnx {
                     (channels[c] as f32) / 255.0
                 })
                 .into_dyn(),
-            );
+            ))
+        }
+        /// Infer
+        ///
+        pub fn infer(
+            &self,
+            impath: &str,
+            session_type: SessionType,
+        ) -> Result<Vec<super::Detection>, Box<dyn std::error::Error>> {
+            // A custom model of a fixed size runs at its size, and the boxes are scaled back.
+            let sz = match &self.sessions {
+                Sessions::Custom {
+                    imgsz: Some(imgsz), ..
+                } => *imgsz,
+                _ => session_type.get_imgsz(),
+            };
+            let array = Self::load_tensor(impath, sz)?;
 
             let session = match &self.sessions {
                 Sessions::Pylon { sz320, sz640 } => match session_type {
@@ -159,6 +431,20 @@ pub mod onnx {
                     SessionType::Sz640 => sz640,
                     _ => panic!("Invalid Session Type"),
                 },
+                Sessions::PylonGrass { sz320, sz640, .. } => match session_type {
+                    SessionType::Sz320 => sz320,
+                    SessionType::Sz640 => sz640,
+                    _ => panic!("Invalid Session Type"),
+                },
+                Sessions::PylonPose { sz320, sz640, .. } => match session_type {
+                    SessionType::Sz320 => sz320,
+                    SessionType::Sz640 => sz640,
+                    _ => panic!("Invalid Session Type"),
+                },
+                Sessions::Custom { model, .. } => match session_type {
+                    SessionType::Sz320 | SessionType::Sz640 => model,
+                    _ => panic!("Invalid Session Type"),
+                },
             };
 
             let tensor = vec![Value::from_array(session.allocator(), &array)?];
@@ -171,23 +457,132 @@ pub mod onnx {
                 .view()
                 .t()
                 .into_owned();
-            convert_yolo_fmt(out)
+            let classes = match (&self.sessions, &session_type) {
+                (_, SessionType::Ocr) | (Sessions::Custom { .. }, _) => Classes::Other,
+                (Sessions::Animal { .. }, _) => Classes::Animal,
+                _ => Classes::Roktrack,
+            };
+            let dets = convert_yolo_fmt(out, &self.thresholds, classes)?;
+            Ok(rescale(dets, sz, session_type.get_imgsz()))
+        }
+    }
+
+    impl Detector for YoloV8 {
+        fn load(&mut self, bundle: Bundle) -> Result<(), Box<dyn std::error::Error>> {
+            self.sessions = Self::build_sessions(self.backend, bundle)?;
+            self.model = bundle.name().to_string();
+            Ok(())
+        }
+
+        fn load_model(&mut self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+            let model = Self::get_session(self.backend, "custom", path)?;
+            // NCHW
+            let imgsz = model
+                .inputs
+                .first()
+                .and_then(|input| input.dimensions.get(2).copied().flatten());
+            self.sessions = Sessions::Custom { model, imgsz };
+            self.model = super::model_name(path);
+            Ok(())
+        }
+
+        fn model(&self) -> String {
+            self.model.clone()
+        }
+
+        fn session_type(&self) -> SessionType {
+            self.session_type.clone()
+        }
+
+        fn set_session_type(&mut self, session_type: SessionType) {
+            self.session_type = session_type;
+        }
+
+        fn detect(&self, impath: &str) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            self.infer(impath, self.session_type.clone())
         }
 
         /// Whether the current session supports OCR
-        pub fn support_ocr(&self) -> bool {
+        fn support_ocr(&self) -> bool {
             match self.sessions {
                 Sessions::Pylon { .. } => false,
                 Sessions::PylonOcr { .. } => true,
                 Sessions::Animal { .. } => false,
+                Sessions::PylonGrass { .. } => false,
+                Sessions::PylonPose { .. } => false,
+                Sessions::Custom { .. } => false,
+            }
+        }
+
+        /// Whether the current session supports grass segmentation
+        fn support_segmentation(&self) -> bool {
+            matches!(self.sessions, Sessions::PylonGrass { .. })
+        }
+
+        /// Finds the drivable grass.
+        ///
+        /// The model outputs the grass probability per pixel (1 * 1 * 320 * 320).
+        fn segment(
+            &self,
+            impath: &str,
+        ) -> Result<super::segment::GrassMask, Box<dyn std::error::Error>> {
+            let session = match &self.sessions {
+                Sessions::PylonGrass { seg, .. } => seg,
+                _ => return Err("Segmentation is not supported.".into()),
+            };
+            let sz = SessionType::Seg.get_imgsz();
+            let array = Self::load_tensor(impath, sz)?;
+            let tensor = vec![Value::from_array(session.allocator(), &array)?];
+            let outs = session.run(tensor)?;
+            let out = outs.get(0).unwrap().try_extract::<f32>()?;
+            let mask: Vec<f32> = out.view().iter().copied().collect();
+            let len = mask.len();
+            super::segment::GrassMask::new(mask, sz, sz)
+                .ok_or_else(|| format!("Unexpected segmentation output. len: {}", len).into())
+        }
+
+        /// Whether the current session supports pose estimation
+        fn support_pose(&self) -> bool {
+            matches!(self.sessions, Sessions::PylonPose { .. })
+        }
+
+        /// Finds raised hands of persons, and persons lying on the ground.
+        ///
+        /// The model outputs boxes with 17 keypoints (1 * 56 * n): xc, yc, w, h, conf and (x, y, conf) * 17.
+        /// The raised hands are returned as detections of `pose::HAND_RAISED_CLASS`, and the persons
+        /// lying as `pose::FALLEN_CLASS`.
+        fn pose(&self, impath: &str) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            let session = match &self.sessions {
+                Sessions::PylonPose { pose, .. } => pose,
+                _ => return Err("Pose estimation is not supported.".into()),
+            };
+            let array = Self::load_tensor(impath, SessionType::Pose.get_imgsz())?;
+            let tensor = vec![Value::from_array(session.allocator(), &array)?];
+            let outs = session.run(tensor)?;
+            let out = outs
+                .get(0)
+                .unwrap()
+                .try_extract::<f32>()?
+                .view()
+                .t()
+                .into_owned();
+            let mut poses = vec![];
+            for row in out.slice(s![.., .., 0]).axis_iter(Axis(0)) {
+                let row: Vec<f32> = row.iter().copied().collect();
+                if row.len() < 5 + 17 * 3 || row[4] < 0.5 {
+                    continue;
+                }
+                poses.extend(super::pose::raised_hands(&row[5..]));
+                poses.extend(super::pose::fallen(&row));
             }
+            Ok(poses)
         }
 
         /// Detects numbers in the vicinity of the marker.
         ///
         /// The bbox of the marker detected in low resolution is extracted
         /// from the high resolution image by ratio and applied to OCR.
-        pub fn ocr(
+        fn ocr(
             &self,
             impath: &str,
             dets: Vec<Detection>,
@@ -246,8 +641,10 @@ pub mod onnx {
     }
 
     #[warn(clippy::manual_retain)]
-    fn convert_yolo_fmt(
+    pub(super) fn convert_yolo_fmt(
         out: Array<f32, IxDyn>,
+        thresholds: &DetectThreshold,
+        classes: Classes,
     ) -> Result<Vec<super::Detection>, Box<dyn std::error::Error>> {
         // https://github.com/AndreyGermanov/yolov8_onnx_rust
         let mut bboxes = vec![];
@@ -261,10 +658,10 @@ pub mod onnx {
                 .map(|(index, value)| (index, *value))
                 .reduce(|accum, row| if row.1 > accum.1 { row } else { accum })
                 .unwrap();
-            if prob < 0.5 {
+            let cls = class_id as u32;
+            if prob < super::threshold(thresholds, classes, cls) {
                 continue;
             }
-            let cls = class_id as u32;
             let xc = row[0];
             let yc = row[1];
             let w = row[2] as u32;
@@ -286,16 +683,53 @@ pub mod onnx {
                 w,
                 h,
                 ids,
+                track: None,
+                distance: None,
+                camera: FRONT,
             })
         }
+        Ok(suppress(bboxes, thresholds))
+    }
+
+    /// Merges the overlapping boxes and keeps the best ones up to the maximum number.
+    ///
+    pub(super) fn suppress(
+        mut bboxes: Vec<Detection>,
+        thresholds: &DetectThreshold,
+    ) -> Vec<Detection> {
         bboxes.sort_by(|box1, box2| box2.prob.total_cmp(&box1.prob));
-        Ok(merge_bboxes(bboxes))
+        let mut merged = merge_bboxes(bboxes, thresholds.iou as f64);
+        merged.truncate(thresholds.max_detections);
+        merged
+    }
+
+    /// Scales the boxes detected in an image of the size `from` to the size `to`.
+    ///
+    pub(super) fn rescale(dets: Vec<Detection>, from: u32, to: u32) -> Vec<Detection> {
+        if from == to || from == 0 {
+            return dets;
+        }
+        let ratio = to as f32 / from as f32;
+        let scale = |v: u32| (v as f32 * ratio) as u32;
+        dets.into_iter()
+            .map(|det| Detection {
+                x1: scale(det.x1),
+                y1: scale(det.y1),
+                x2: scale(det.x2),
+                y2: scale(det.y2),
+                xc: det.xc * ratio,
+                yc: det.yc * ratio,
+                w: scale(det.w),
+                h: scale(det.h),
+                ..det
+            })
+            .collect()
     }
 
     /// Function to compute the IoU of two rectangles.
     /// https://python-ai-learn.com/2021/02/06/iou/
     ///
-    fn iou(r1: Detection, r2: Detection) -> f64 {
+    pub fn iou(r1: Detection, r2: Detection) -> f64 {
         let x1 = r1.x1.max(r2.x1) as f64;
         let y1 = r1.y1.max(r2.y1) as f64;
         let x2 = r1.x2.min(r2.x2) as f64;
@@ -309,9 +743,9 @@ pub mod onnx {
         intersection / union
     }
 
-    /// Merges bounding boxes whose IoU is greater than or equal to 0.7.
+    /// Merges bounding boxes whose IoU is greater than or equal to the threshold.
     ///
-    fn merge_bboxes(bboxes: Vec<Detection>) -> Vec<Detection> {
+    fn merge_bboxes(bboxes: Vec<Detection>, iou_threshold: f64) -> Vec<Detection> {
         let mut merged_bboxes = Vec::new();
         let mut used = vec![false; bboxes.len()];
         for i in 0..bboxes.len() {
@@ -324,7 +758,7 @@ pub mod onnx {
                 if used[j] || bboxes[i].cls != bboxes[j].cls {
                     continue;
                 }
-                if iou(bboxes[i].clone(), bboxes[j].clone()) >= 0.7 {
+                if iou(bboxes[i].clone(), bboxes[j].clone()) >= iou_threshold {
                     let x1 = merged_bbox.x1.min(bboxes[j].x1);
                     let y1 = merged_bbox.y1.min(bboxes[j].y1);
                     let x2 = merged_bbox.x2.max(bboxes[j].x2);
@@ -347,6 +781,9 @@ pub mod onnx {
                         w,
                         h,
                         ids,
+                        track: None,
+                        distance: None,
+                        camera: FRONT,
                     };
                     used[j] = true;
                 }
@@ -357,6 +794,552 @@ pub mod onnx {
     }
 }
 
+/// TensorFlow Lite backend
+///
+/// Runs the models converted to TFLite, put next to the ONNX ones with the same names
+/// (e.g. `roktrack_yolov8_nano_fixed_320_320.tflite`). Only the pylon and animal detections are
+/// supported.
+#[cfg(feature = "tflite")]
+pub mod tflite {
+    use crate::module::define;
+    use image::imageops::FilterType;
+    use ndarray::{Array, IxDyn};
+    use std::path::Path;
+    use tflitec::interpreter::{Interpreter, Options};
+
+    use super::{
+        onnx::convert_yolo_fmt, Bundle, Classes, DetectThreshold, Detection, Detector, SessionType,
+    };
+
+    /// YoloV8 interpreters of TFLite.
+    ///
+    pub struct YoloV8Lite {
+        pub sz320: Interpreter,
+        pub sz640: Interpreter,
+        pub session_type: SessionType,
+        pub model: String,
+        classes: Classes,
+        pub thresholds: DetectThreshold,
+    }
+
+    /// Class set of the bundle
+    ///
+    fn classes(bundle: Bundle) -> Classes {
+        match bundle {
+            Bundle::Animal => Classes::Animal,
+            _ => Classes::Roktrack,
+        }
+    }
+
+    /// Methods for yolov8 on TFLite.
+    ///
+    impl YoloV8Lite {
+        /// Constructor with the interpreters of the bundle.
+        ///
+        pub fn new(
+            bundle: Bundle,
+            thresholds: DetectThreshold,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let (sz320, sz640) = Self::build_interpreters(bundle)?;
+            Ok(Self {
+                sz320,
+                sz640,
+                session_type: SessionType::Sz320,
+                model: bundle.name().to_string(),
+                classes: classes(bundle),
+                thresholds,
+            })
+        }
+        /// Build the interpreters of the bundle.
+        ///
+        pub fn build_interpreters(
+            bundle: Bundle,
+        ) -> Result<(Interpreter, Interpreter), Box<dyn std::error::Error>> {
+            let pylon = |model_path| {
+                let interpreter = Self::get_interpreter(model_path)?;
+                // 1 * (4 + classes) * boxes
+                let rows = interpreter.output(0)?.shape().dimensions().get(1).copied();
+                super::check_classes(model_path, rows.map(|rows| rows as u32));
+                Ok::<_, Box<dyn std::error::Error>>(interpreter)
+            };
+            match bundle {
+                Bundle::Pylon => Ok((
+                    pylon(define::path::PYLON_320_MODEL)?,
+                    pylon(define::path::PYLON_640_MODEL)?,
+                )),
+                Bundle::Animal => Ok((
+                    Self::get_interpreter(define::path::ANIMAL_320_MODEL)?,
+                    Self::get_interpreter(define::path::ANIMAL_640_MODEL)?,
+                )),
+                _ => Err(format!("{:?} is not supported by TFLite.", bundle).into()),
+            }
+        }
+        /// get interpreter of the TFLite model next to the ONNX one.
+        ///
+        pub fn get_interpreter(
+            model_path: &str,
+        ) -> Result<Interpreter, Box<dyn std::error::Error>> {
+            let path = Path::new(model_path).with_extension("tflite");
+            let options = Options {
+                thread_count: 4,
+                ..Default::default()
+            };
+            let interpreter = Interpreter::with_model_path(&path.to_string_lossy(), Some(options))?;
+            interpreter.allocate_tensors()?;
+            Ok(interpreter)
+        }
+    }
+
+    impl Detector for YoloV8Lite {
+        fn load(&mut self, bundle: Bundle) -> Result<(), Box<dyn std::error::Error>> {
+            (self.sz320, self.sz640) = Self::build_interpreters(bundle)?;
+            self.model = bundle.name().to_string();
+            self.classes = classes(bundle);
+            Ok(())
+        }
+
+        fn model(&self) -> String {
+            self.model.clone()
+        }
+
+        fn session_type(&self) -> SessionType {
+            self.session_type.clone()
+        }
+
+        fn set_session_type(&mut self, session_type: SessionType) {
+            self.session_type = session_type;
+        }
+
+        fn detect(&self, impath: &str) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            let (interpreter, sz) = match self.session_type {
+                SessionType::Sz640 => (&self.sz640, 640),
+                _ => (&self.sz320, 320),
+            };
+            // The input is NHWC in range [0, 1].
+            let img = image::open(Path::new(impath))?
+                .resize_exact(sz, sz, FilterType::Nearest)
+                .to_rgb8();
+            let img = super::infrared::prepare(img);
+            let input: Vec<f32> = img.as_raw().iter().map(|c| *c as f32 / 255.0).collect();
+            interpreter.copy(&input[..], 0)?;
+            interpreter.invoke()?;
+            // The output is the same as the ONNX one (1 * (4 + classes) * boxes).
+            let output = interpreter.output(0)?;
+            let out = Array::from_shape_vec(
+                IxDyn(output.shape().dimensions()),
+                output.data::<f32>().to_vec(),
+            )?;
+            convert_yolo_fmt(out.t().into_owned(), &self.thresholds, self.classes)
+        }
+    }
+}
+
+#[cfg(feature = "edgetpu")]
+pub mod edgetpu {
+    //! The Coral Edge TPU, over USB, PCIe or M.2, through the TensorFlow Lite C API with the
+    //! delegate of libedgetpu. The models are compiled for the Edge TPU and quantized to 8 bits,
+    //! e.g. "roktrack_yolov8_nano_fixed_320_320_edgetpu.tflite" next to the ONNX one.
+
+    use crate::module::define;
+    use image::{imageops::FilterType, RgbImage};
+    use ndarray::{Array, IxDyn};
+    use std::ffi::{c_char, c_int, c_void, CString};
+    use std::path::Path;
+
+    use super::{
+        onnx::convert_yolo_fmt, Bundle, Classes, DetectThreshold, Detection, Detector, SessionType,
+    };
+
+    // TfLiteStatus of success
+    const OK: c_int = 0;
+    // TfLiteType of the tensors
+    const FLOAT32: c_int = 1;
+    const UINT8: c_int = 3;
+    const INT8: c_int = 9;
+
+    /// TfLiteQuantizationParams: real = scale * (quantized - zero_point)
+    #[repr(C)]
+    struct QuantizationParams {
+        scale: f32,
+        zero_point: i32,
+    }
+
+    /// edgetpu_device
+    #[repr(C)]
+    struct EdgeTpuDevice {
+        device_type: c_int, // 0: PCIe, 1: USB
+        path: *const c_char,
+    }
+
+    #[link(name = "tensorflowlite_c")]
+    extern "C" {
+        fn TfLiteModelCreateFromFile(path: *const c_char) -> *mut c_void;
+        fn TfLiteModelDelete(model: *mut c_void);
+        fn TfLiteInterpreterOptionsCreate() -> *mut c_void;
+        fn TfLiteInterpreterOptionsDelete(options: *mut c_void);
+        fn TfLiteInterpreterOptionsAddDelegate(options: *mut c_void, delegate: *mut c_void);
+        fn TfLiteInterpreterCreate(model: *const c_void, options: *const c_void) -> *mut c_void;
+        fn TfLiteInterpreterDelete(interpreter: *mut c_void);
+        fn TfLiteInterpreterAllocateTensors(interpreter: *mut c_void) -> c_int;
+        fn TfLiteInterpreterInvoke(interpreter: *mut c_void) -> c_int;
+        fn TfLiteInterpreterGetInputTensor(interpreter: *const c_void, index: i32) -> *mut c_void;
+        fn TfLiteInterpreterGetOutputTensor(
+            interpreter: *const c_void,
+            index: i32,
+        ) -> *const c_void;
+        fn TfLiteTensorType(tensor: *const c_void) -> c_int;
+        fn TfLiteTensorNumDims(tensor: *const c_void) -> i32;
+        fn TfLiteTensorDim(tensor: *const c_void, index: i32) -> i32;
+        fn TfLiteTensorByteSize(tensor: *const c_void) -> usize;
+        fn TfLiteTensorQuantizationParams(tensor: *const c_void) -> QuantizationParams;
+        fn TfLiteTensorCopyFromBuffer(
+            tensor: *mut c_void,
+            data: *const c_void,
+            size: usize,
+        ) -> c_int;
+        fn TfLiteTensorCopyToBuffer(tensor: *const c_void, data: *mut c_void, size: usize)
+            -> c_int;
+    }
+
+    #[link(name = "edgetpu")]
+    extern "C" {
+        fn edgetpu_list_devices(num_devices: *mut usize) -> *mut EdgeTpuDevice;
+        fn edgetpu_free_devices(devices: *mut EdgeTpuDevice);
+        fn edgetpu_create_delegate(
+            device_type: c_int,
+            name: *const c_char,
+            options: *const c_void,
+            num_options: usize,
+        ) -> *mut c_void;
+        fn edgetpu_free_delegate(delegate: *mut c_void);
+    }
+
+    /// The delegate of the first Edge TPU found, shared by the interpreters.
+    ///
+    struct Delegate(*mut c_void);
+
+    impl Delegate {
+        fn new() -> Result<Self, Box<dyn std::error::Error>> {
+            let mut num = 0;
+            // Safety: the list is freed after the delegate is created from its first device.
+            unsafe {
+                let devices = edgetpu_list_devices(&mut num);
+                if devices.is_null() || num == 0 {
+                    return Err("No Edge TPU found.".into());
+                }
+                let device = &*devices;
+                let delegate =
+                    edgetpu_create_delegate(device.device_type, device.path, std::ptr::null(), 0);
+                edgetpu_free_devices(devices);
+                if delegate.is_null() {
+                    return Err("Can't open the Edge TPU.".into());
+                }
+                Ok(Self(delegate))
+            }
+        }
+    }
+
+    impl Drop for Delegate {
+        fn drop(&mut self) {
+            // Safety: created by edgetpu_create_delegate, after the interpreters using it are deleted.
+            unsafe { edgetpu_free_delegate(self.0) }
+        }
+    }
+
+    /// An interpreter of a model on the Edge TPU.
+    ///
+    struct Interpreter {
+        model: *mut c_void,
+        interpreter: *mut c_void,
+    }
+
+    impl Interpreter {
+        fn new(model_path: &str, delegate: &Delegate) -> Result<Self, Box<dyn std::error::Error>> {
+            let path = CString::new(model_path)?;
+            // Safety: the options are only needed while the interpreter is created.
+            unsafe {
+                let model = TfLiteModelCreateFromFile(path.as_ptr());
+                if model.is_null() {
+                    return Err(format!("Can't load {}.", model_path).into());
+                }
+                let options = TfLiteInterpreterOptionsCreate();
+                TfLiteInterpreterOptionsAddDelegate(options, delegate.0);
+                let interpreter = TfLiteInterpreterCreate(model, options);
+                TfLiteInterpreterOptionsDelete(options);
+                let interpreter = Self { model, interpreter };
+                if interpreter.interpreter.is_null()
+                    || TfLiteInterpreterAllocateTensors(interpreter.interpreter) != OK
+                {
+                    return Err(format!("Can't run {} on the Edge TPU.", model_path).into());
+                }
+                Ok(interpreter)
+            }
+        }
+
+        /// Input size of the model (NHWC), as it's square.
+        fn imgsz(&self) -> u32 {
+            // Safety: the tensors live as long as the interpreter.
+            unsafe {
+                let input = TfLiteInterpreterGetInputTensor(self.interpreter, 0);
+                TfLiteTensorDim(input, 1) as u32
+            }
+        }
+
+        /// Rows of the output (1 * (4 + classes) * boxes).
+        fn rows(&self) -> u32 {
+            // Safety: the tensors live as long as the interpreter.
+            unsafe {
+                let output = TfLiteInterpreterGetOutputTensor(self.interpreter, 0);
+                TfLiteTensorDim(output, 1) as u32
+            }
+        }
+
+        /// Runs the model on the image of its input size, and returns the output dequantized.
+        fn run(&self, img: &RgbImage) -> Result<Array<f32, IxDyn>, Box<dyn std::error::Error>> {
+            // Safety: the buffers are of the byte sizes of the tensors.
+            unsafe {
+                let input = TfLiteInterpreterGetInputTensor(self.interpreter, 0);
+                let data = quantize(
+                    img.as_raw(),
+                    TfLiteTensorType(input),
+                    TfLiteTensorQuantizationParams(input),
+                )?;
+                if data.len() != TfLiteTensorByteSize(input) {
+                    return Err("Input of different size.".into());
+                }
+                if TfLiteTensorCopyFromBuffer(input, data.as_ptr() as *const c_void, data.len())
+                    != OK
+                    || TfLiteInterpreterInvoke(self.interpreter) != OK
+                {
+                    return Err("Edge TPU inference failed.".into());
+                }
+                let output = TfLiteInterpreterGetOutputTensor(self.interpreter, 0);
+                let mut data = vec![0_u8; TfLiteTensorByteSize(output)];
+                if TfLiteTensorCopyToBuffer(output, data.as_mut_ptr() as *mut c_void, data.len())
+                    != OK
+                {
+                    return Err("Can't read the output.".into());
+                }
+                let shape: Vec<usize> = (0..TfLiteTensorNumDims(output))
+                    .map(|i| TfLiteTensorDim(output, i) as usize)
+                    .collect();
+                let values = dequantize(
+                    &data,
+                    TfLiteTensorType(output),
+                    TfLiteTensorQuantizationParams(output),
+                )?;
+                Ok(Array::from_shape_vec(IxDyn(&shape), values)?)
+            }
+        }
+    }
+
+    impl Drop for Interpreter {
+        fn drop(&mut self) {
+            // Safety: created by TfLiteInterpreterCreate and TfLiteModelCreateFromFile.
+            unsafe {
+                if !self.interpreter.is_null() {
+                    TfLiteInterpreterDelete(self.interpreter);
+                }
+                TfLiteModelDelete(self.model);
+            }
+        }
+    }
+
+    /// The pixels in range [0, 255] as the input of the type.
+    fn quantize(
+        pixels: &[u8],
+        tensor_type: c_int,
+        params: QuantizationParams,
+    ) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        // In range [0, 1] by the scale, or as they are without it
+        let q = |c: &u8| match 0.0 < params.scale {
+            true => (*c as f32 / 255.0 / params.scale).round() + params.zero_point as f32,
+            false => *c as f32,
+        };
+        match tensor_type {
+            UINT8 => Ok(pixels
+                .iter()
+                .map(|c| q(c).clamp(0.0, 255.0) as u8)
+                .collect()),
+            INT8 => Ok(pixels
+                .iter()
+                .map(|c| q(c).clamp(-128.0, 127.0) as i8 as u8)
+                .collect()),
+            FLOAT32 => Ok(pixels
+                .iter()
+                .flat_map(|c| (*c as f32 / 255.0).to_ne_bytes())
+                .collect()),
+            _ => Err(format!("Input of type {} is not supported.", tensor_type).into()),
+        }
+    }
+
+    /// The output of the type as real values.
+    fn dequantize(
+        data: &[u8],
+        tensor_type: c_int,
+        params: QuantizationParams,
+    ) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let real = |q: f32| params.scale * (q - params.zero_point as f32);
+        match tensor_type {
+            UINT8 => Ok(data.iter().map(|q| real(*q as f32)).collect()),
+            INT8 => Ok(data.iter().map(|q| real(*q as i8 as f32)).collect()),
+            FLOAT32 => Ok(data
+                .chunks_exact(4)
+                .map(|v| f32::from_ne_bytes([v[0], v[1], v[2], v[3]]))
+                .collect()),
+            _ => Err(format!("Output of type {} is not supported.", tensor_type).into()),
+        }
+    }
+
+    /// Path of the model compiled for the Edge TPU next to the ONNX one.
+    fn model_path(onnx_path: &str) -> String {
+        let path = Path::new(onnx_path);
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        path.with_file_name(format!("{}_edgetpu.tflite", stem))
+            .to_string_lossy()
+            .to_string()
+    }
+
+    /// YoloV8 interpreters on the Edge TPU.
+    ///
+    pub struct YoloV8EdgeTpu {
+        sz320: Interpreter,
+        sz640: Interpreter,
+        delegate: Delegate, // Dropped after the interpreters
+        pub session_type: SessionType,
+        pub model: String,
+        classes: Classes,
+        pub thresholds: DetectThreshold,
+    }
+
+    // Safety: the interpreters are only run by the vision thread holding the detector.
+    unsafe impl Send for YoloV8EdgeTpu {}
+
+    /// Class set of the bundle
+    ///
+    fn classes(bundle: Bundle) -> Classes {
+        match bundle {
+            Bundle::Animal => Classes::Animal,
+            _ => Classes::Roktrack,
+        }
+    }
+
+    /// Methods for yolov8 on the Edge TPU.
+    ///
+    impl YoloV8EdgeTpu {
+        /// Constructor with the interpreters of the bundle on the first Edge TPU found.
+        ///
+        pub fn new(
+            bundle: Bundle,
+            thresholds: DetectThreshold,
+        ) -> Result<Self, Box<dyn std::error::Error>> {
+            let delegate = Delegate::new()?;
+            let (sz320, sz640) = Self::build_interpreters(bundle, &delegate)?;
+            Ok(Self {
+                sz320,
+                sz640,
+                delegate,
+                session_type: SessionType::Sz320,
+                model: bundle.name().to_string(),
+                classes: classes(bundle),
+                thresholds,
+            })
+        }
+        /// Build the interpreters of the bundle.
+        ///
+        fn build_interpreters(
+            bundle: Bundle,
+            delegate: &Delegate,
+        ) -> Result<(Interpreter, Interpreter), Box<dyn std::error::Error>> {
+            let (sz320, sz640) = match bundle {
+                Bundle::Pylon => (define::path::PYLON_320_MODEL, define::path::PYLON_640_MODEL),
+                Bundle::Animal => (
+                    define::path::ANIMAL_320_MODEL,
+                    define::path::ANIMAL_640_MODEL,
+                ),
+                _ => return Err(format!("{:?} is not supported by the Edge TPU.", bundle).into()),
+            };
+            let interpreters = (
+                Interpreter::new(&model_path(sz320), delegate)?,
+                Interpreter::new(&model_path(sz640), delegate)?,
+            );
+            if bundle == Bundle::Pylon {
+                super::check_classes(sz320, Some(interpreters.0.rows()));
+                super::check_classes(sz640, Some(interpreters.1.rows()));
+            }
+            Ok(interpreters)
+        }
+    }
+
+    impl Detector for YoloV8EdgeTpu {
+        fn load(&mut self, bundle: Bundle) -> Result<(), Box<dyn std::error::Error>> {
+            (self.sz320, self.sz640) = Self::build_interpreters(bundle, &self.delegate)?;
+            self.model = bundle.name().to_string();
+            self.classes = classes(bundle);
+            Ok(())
+        }
+
+        fn model(&self) -> String {
+            self.model.clone()
+        }
+
+        fn session_type(&self) -> SessionType {
+            self.session_type.clone()
+        }
+
+        fn set_session_type(&mut self, session_type: SessionType) {
+            self.session_type = session_type;
+        }
+
+        fn detect(&self, impath: &str) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+            let interpreter = match self.session_type {
+                SessionType::Sz640 => &self.sz640,
+                _ => &self.sz320,
+            };
+            let sz = interpreter.imgsz();
+            let img = image::open(Path::new(impath))?
+                .resize_exact(sz, sz, FilterType::Nearest)
+                .to_rgb8();
+            let img = super::infrared::prepare(img);
+            // The output is the same as the ONNX one (1 * (4 + classes) * boxes).
+            let out = interpreter.run(&img)?;
+            let dets = convert_yolo_fmt(out.t().into_owned(), &self.thresholds, self.classes)?;
+            Ok(super::onnx::rescale(
+                dets,
+                sz,
+                self.session_type.get_imgsz(),
+            ))
+        }
+    }
+}
+
+/// The Roktrack classes a model with the output rows (1 * (4 + classes) * boxes) can't detect.
+///
+pub fn missing_classes(rows: u32) -> Vec<RoktrackClasses> {
+    (rows.saturating_sub(4)..)
+        .map_while(RoktrackClasses::from_u32)
+        .collect()
+}
+
+/// Warn when the pylon model doesn't output all the Roktrack classes.
+///
+/// The shipped models are trained with the pylon, person and roktrack only, so the obstacles of
+/// the other classes are never seen by the camera until the models are retrained.
+pub fn check_classes(model_path: &str, rows: Option<u32>) {
+    let Some(rows) = rows else {
+        return;
+    };
+    let missing = missing_classes(rows);
+    if !missing.is_empty() {
+        log::warn!(
+            "{} Outputs {} Classes. Unavailable Classes: {:?}",
+            model_path,
+            rows.saturating_sub(4),
+            missing
+        );
+    }
+}
+
 /// A trait for filtering detection results by class
 ///
 pub trait FilterClass {
@@ -365,11 +1348,27 @@ pub trait FilterClass {
 
 /// Roktrack base model's classes
 ///
+/// The lawn obstacles are told apart by the model, as the obstacles of other kinds, but are all
+/// avoided alike (see `Category`).
 #[derive(Debug, Clone, PartialEq)]
 pub enum RoktrackClasses {
     PYLON,
     PERSON,
     ROKTRACK,
+    OBSTACLE, // Obstacles of other kinds
+    ROCK,
+    TOY,
+    HOSE,
+}
+
+/// Categories of the classes, as the pilots treat them
+///
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Category {
+    Marker,   // To drive by
+    Person,   // To keep away from
+    Roktrack, // Other robots
+    Obstacle, // To avoid
 }
 /// Convert int to RoktrackClasses
 ///
@@ -379,6 +1378,10 @@ impl RoktrackClasses {
             0 => Some(RoktrackClasses::PYLON),
             1 => Some(RoktrackClasses::PERSON),
             2 => Some(RoktrackClasses::ROKTRACK),
+            3 => Some(RoktrackClasses::OBSTACLE),
+            4 => Some(RoktrackClasses::ROCK),
+            5 => Some(RoktrackClasses::TOY),
+            6 => Some(RoktrackClasses::HOSE),
             _ => None,
         }
     }
@@ -387,8 +1390,32 @@ impl RoktrackClasses {
             RoktrackClasses::PYLON => 0,
             RoktrackClasses::PERSON => 1,
             RoktrackClasses::ROKTRACK => 2,
+            RoktrackClasses::OBSTACLE => 3,
+            RoktrackClasses::ROCK => 4,
+            RoktrackClasses::TOY => 5,
+            RoktrackClasses::HOSE => 6,
         }
     }
+    pub fn category(&self) -> Category {
+        match self {
+            RoktrackClasses::PYLON => Category::Marker,
+            RoktrackClasses::PERSON => Category::Person,
+            RoktrackClasses::ROKTRACK => Category::Roktrack,
+            RoktrackClasses::OBSTACLE
+            | RoktrackClasses::ROCK
+            | RoktrackClasses::TOY
+            | RoktrackClasses::HOSE => Category::Obstacle,
+        }
+    }
+    /// The detections of the classes of the category.
+    pub fn filter_category(dets: &[Detection], category: Category) -> Vec<Detection> {
+        dets.iter()
+            .filter(|det| {
+                RoktrackClasses::from_u32(det.cls).is_some_and(|c| c.category() == category)
+            })
+            .cloned()
+            .collect()
+    }
 }
 /// Filter By Class
 ///
@@ -483,6 +1510,9 @@ pub struct Detection {
     pub w: u32,
     pub h: u32,
     pub ids: Vec<u8>,
+    pub track: Option<u32>, // ID kept across frames by the tracker (None: not tracked)
+    pub distance: Option<f32>, // Distance estimated from the height in m (None: unknown)
+    pub camera: u8,         // ID of the camera that saw it (see camera::FRONT and camera::REAR)
 }
 /// Detection default method.
 ///
@@ -509,10 +1539,311 @@ impl Detection {
             w: 0,
             h: 0,
             ids: vec![],
+            track: None,
+            distance: None,
+            camera: FRONT,
         }
     }
 }
 
+pub mod segment {
+    //! Grass segmentation post-processing
+    //!
+    //! The mask of the drivable grass is sent to the pilots pooled into cells, and its boundary as
+    //! the detections.
+
+    use super::{Detection, FRONT};
+
+    /// Class of the detections marking the boundary of the grass.
+    pub const EDGE_CLASS: u32 = 100;
+
+    /// Cells of a side of the mask sent to the pilots.
+    pub const POOLED_CELLS: u32 = 32;
+
+    // Number of horizontal bands sampled for the boundary.
+    const BANDS: u32 = 8;
+
+    // Probability above which a pixel is grass.
+    const GRASS_THRESHOLD: f32 = 0.5;
+
+    /// Probability of the drivable grass per pixel (`width` * `height`, row-major)
+    #[derive(Debug, Clone, PartialEq)]
+    pub struct GrassMask {
+        pub width: u32,
+        pub height: u32,
+        pub grass: Vec<f32>,
+    }
+
+    impl GrassMask {
+        /// Creates a mask of the probabilities. None if they aren't `width` * `height`.
+        pub fn new(grass: Vec<f32>, width: u32, height: u32) -> Option<Self> {
+            match grass.len() == (width * height) as usize {
+                true => Some(Self {
+                    width,
+                    height,
+                    grass,
+                }),
+                false => None,
+            }
+        }
+
+        /// Averages the mask into `cells` * `cells` cells, or fewer if the mask is smaller.
+        pub fn pooled(&self, cells: u32) -> Self {
+            let (cols, rows) = (cells.min(self.width), cells.min(self.height));
+            let mut grass = vec![];
+            for row in 0..rows {
+                let ys = row * self.height / rows..(row + 1) * self.height / rows;
+                for col in 0..cols {
+                    let xs = col * self.width / cols..(col + 1) * self.width / cols;
+                    let sum: f32 = ys
+                        .clone()
+                        .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                        .map(|(x, y)| self.grass[(y * self.width + x) as usize])
+                        .sum();
+                    grass.push(sum / (xs.len() * ys.len()).max(1) as f32);
+                }
+            }
+            Self {
+                width: cols,
+                height: rows,
+                grass,
+            }
+        }
+
+        /// Ratio of the grass in the region of the ratios to the mask (x1, y1, x2, y2).
+        /// 1.0 for an empty region.
+        pub fn ratio(&self, (x1, y1, x2, y2): (f32, f32, f32, f32)) -> f32 {
+            let at = |ratio: f32, size: u32| (ratio.clamp(0.0, 1.0) * size as f32) as u32;
+            let xs = at(x1, self.width)..at(x2, self.width);
+            let ys = at(y1, self.height)..at(y2, self.height);
+            let total = xs.len() * ys.len();
+            if total == 0 {
+                return 1.0;
+            }
+            let grass = ys
+                .flat_map(|y| xs.clone().map(move |x| (x, y)))
+                .filter(|(x, y)| GRASS_THRESHOLD < self.grass[(y * self.width + x) as usize])
+                .count();
+            grass as f32 / total as f32
+        }
+
+        /// Finds where the grass ends in each band of the mask.
+        ///
+        /// The center row of each band is scanned from the grass side.
+        /// Bands without grass at that side or without an end of the grass are skipped.
+        pub fn boundary(&self, grass_left: bool) -> Vec<Detection> {
+            let (mask, width, height) = (&self.grass, self.width, self.height);
+            let band_height = height / BANDS;
+            let mut edges = vec![];
+            if band_height == 0 {
+                return edges;
+            }
+            for band in 0..BANDS {
+                let y = band * band_height + band_height / 2;
+                let row = &mask[(y * width) as usize..((y + 1) * width) as usize];
+                let is_grass = |x: u32| GRASS_THRESHOLD < row[x as usize];
+                let xs: Vec<u32> = match grass_left {
+                    true => (0..width).collect(),
+                    false => (0..width).rev().collect(),
+                };
+                if !is_grass(xs[0]) {
+                    continue;
+                }
+                if let Some(x) = xs.into_iter().find(|x| !is_grass(*x)) {
+                    edges.push(Detection {
+                        x1: x,
+                        y1: band * band_height,
+                        x2: x,
+                        y2: (band + 1) * band_height,
+                        xc: x as f32,
+                        yc: y as f32,
+                        cls: EDGE_CLASS,
+                        prob: 1.0,
+                        w: 0,
+                        h: band_height,
+                        ids: vec![],
+                        track: None,
+                        distance: None,
+                        camera: FRONT,
+                    });
+                }
+            }
+            edges
+        }
+    }
+}
+
+pub mod pose {
+    //! Pose estimation post-processing
+    //!
+
+    use super::{Detection, FRONT};
+
+    /// Class of the detections marking raised hands.
+    pub const HAND_RAISED_CLASS: u32 = 110;
+
+    /// Class of the detections marking persons lying on the ground.
+    pub const FALLEN_CLASS: u32 = 111;
+
+    // Keypoint indices of the COCO format.
+    const NOSE: usize = 0;
+    const LEFT_SHOULDER: usize = 5;
+    const RIGHT_SHOULDER: usize = 6;
+    const LEFT_WRIST: usize = 9;
+    const RIGHT_WRIST: usize = 10;
+    const LEFT_HIP: usize = 11;
+    const RIGHT_HIP: usize = 12;
+
+    // Confidence above which a keypoint is visible.
+    const VISIBLE_THRESHOLD: f32 = 0.5;
+
+    // Angle of the torso from the vertical in degrees beyond which the person is lying.
+    const LYING_ANGLE: f32 = 60.0;
+
+    // Aspect ratio (width / height) of the box beyond which the person is lying, without the torso.
+    const LYING_ASPECT: f32 = 1.5;
+
+    /// The keypoint of the index, if visible.
+    fn keypoint(keypoints: &[f32], i: usize) -> Option<(f32, f32)> {
+        let k = keypoints.get(i * 3..i * 3 + 3)?;
+        (VISIBLE_THRESHOLD < k[2]).then_some((k[0], k[1]))
+    }
+
+    /// The midpoint of the visible keypoints of the pair, or the one visible.
+    fn midpoint(keypoints: &[f32], (a, b): (usize, usize)) -> Option<(f32, f32)> {
+        match (keypoint(keypoints, a), keypoint(keypoints, b)) {
+            (Some(a), Some(b)) => Some(((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Finds wrists above the head (or the shoulder when the head is not visible).
+    ///
+    /// `keypoints` is (x, y, conf) * 17 of a person.
+    pub fn raised_hands(keypoints: &[f32]) -> Vec<Detection> {
+        let point = |i: usize| keypoint(keypoints, i);
+        let mut hands = vec![];
+        for (wrist, shoulder) in [(LEFT_WRIST, LEFT_SHOULDER), (RIGHT_WRIST, RIGHT_SHOULDER)] {
+            let (Some((x, y)), Some(top)) = (point(wrist), point(NOSE).or(point(shoulder))) else {
+                continue;
+            };
+            if y < top.1 {
+                hands.push(Detection {
+                    x1: x as u32,
+                    y1: y as u32,
+                    x2: x as u32,
+                    y2: y as u32,
+                    xc: x,
+                    yc: y,
+                    cls: HAND_RAISED_CLASS,
+                    prob: 1.0,
+                    w: 0,
+                    h: 0,
+                    ids: vec![],
+                    track: None,
+                    distance: None,
+                    camera: FRONT,
+                });
+            }
+        }
+        hands
+    }
+
+    /// Finds the person lying on the ground, by the torso from the shoulders to the hips leaning
+    /// over, or by the box wider than tall when the torso is not visible.
+    ///
+    /// `row` is xc, yc, w, h, conf and (x, y, conf) * 17 of a person.
+    pub fn fallen(row: &[f32]) -> Option<Detection> {
+        let [xc, yc, w, h, conf]: [f32; 5] = row.get(..5)?.try_into().ok()?;
+        let keypoints = &row[5..];
+        let shoulders = midpoint(keypoints, (LEFT_SHOULDER, RIGHT_SHOULDER));
+        let hips = midpoint(keypoints, (LEFT_HIP, RIGHT_HIP));
+        let lying = match (shoulders, hips) {
+            (Some(s), Some(h)) if s != h => {
+                let angle = (s.0 - h.0).abs().atan2((s.1 - h.1).abs()).to_degrees();
+                LYING_ANGLE < angle
+            }
+            _ => h * LYING_ASPECT < w,
+        };
+        lying.then(|| Detection {
+            x1: (xc - w / 2.0).max(0.0) as u32,
+            y1: (yc - h / 2.0).max(0.0) as u32,
+            x2: (xc + w / 2.0).max(0.0) as u32,
+            y2: (yc + h / 2.0).max(0.0) as u32,
+            xc,
+            yc,
+            cls: FALLEN_CLASS,
+            prob: conf,
+            w: w as u32,
+            h: h as u32,
+            ids: vec![],
+            track: None,
+            distance: None,
+            camera: FRONT,
+        })
+    }
+}
+
+pub mod infrared {
+    //! IR frame pre-processing
+    //!
+    //! Without the IR-cut filter under the IR illuminator, the frames are of a single tint and dim.
+    //! They are given to the models in grayscale stretched over the whole range, which the models
+    //! trained on the color frames take better than the tinted ones.
+
+    use image::{Rgb, RgbImage};
+
+    // Mean spread of the channels (after removing the tint) below which the frame is of the IR
+    const IR_CHROMA: f32 = 8.0;
+    // Ratio of the darkest and the brightest pixels clipped by the stretch
+    const CLIP_RATIO: f32 = 0.01;
+
+    /// Whether the frame is of the IR, i.e. has little color besides its tint.
+    pub fn is_infrared(img: &RgbImage) -> bool {
+        let n = (img.width() * img.height()) as f32;
+        if n == 0.0 {
+            return false;
+        }
+        let mut mean = [0.0_f32; 3];
+        for pixel in img.pixels() {
+            for (m, c) in mean.iter_mut().zip(pixel.0) {
+                *m += c as f32 / n;
+            }
+        }
+        let chroma: f32 = img
+            .pixels()
+            .map(|pixel| {
+                let c = [0, 1, 2].map(|i| pixel[i] as f32 - mean[i]);
+                c.iter().cloned().fold(f32::MIN, f32::max)
+                    - c.iter().cloned().fold(f32::MAX, f32::min)
+            })
+            .sum();
+        chroma / n < IR_CHROMA
+    }
+
+    /// Converts the frame of the IR to grayscale stretched over the whole range. Others are kept.
+    pub fn prepare(img: RgbImage) -> RgbImage {
+        if !is_infrared(&img) {
+            return img;
+        }
+        let luma: Vec<f32> = img
+            .pixels()
+            .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+            .collect();
+        let mut sorted = luma.clone();
+        sorted.sort_by(f32::total_cmp);
+        let clip = (sorted.len() as f32 * CLIP_RATIO) as usize;
+        let (low, high) = (sorted[clip], sorted[sorted.len() - 1 - clip]);
+        let range = (high - low).max(1.0);
+        let mut gray = RgbImage::new(img.width(), img.height());
+        for (pixel, l) in gray.pixels_mut().zip(luma) {
+            let v = ((l - low) / range * 255.0).round().clamp(0.0, 255.0) as u8;
+            *pixel = Rgb([v, v, v]);
+        }
+        gray
+    }
+}
+
 pub mod sort {
     //! Detections sort methods
     //!
@@ -561,6 +1892,99 @@ mod tests {
 
     use super::*;
 
+    #[test]
+    fn backend_test() {
+        assert_eq!(Backend::from_string("onnx"), Backend::Onnx);
+        assert_eq!(Backend::from_string("yolov7onnx"), Backend::Onnx);
+        assert_eq!(Backend::from_string("openvino"), Backend::OpenVino);
+        assert_eq!(Backend::from_string("tflite"), Backend::TfLite);
+        assert_eq!(Backend::from_string("edgetpu"), Backend::EdgeTpu);
+        assert_eq!(Backend::from_string("unknown"), Backend::Onnx);
+        assert_eq!(SessionType::Sz640.get_imgsz(), 640);
+    }
+
+    #[test]
+    fn model_test() {
+        assert_eq!(Bundle::PylonOcr.name(), "pylon_ocr");
+        assert_eq!(
+            model_name("asset/model/animal_320_320.onnx"),
+            "animal_320_320"
+        );
+        let det = Detection {
+            x1: 10,
+            y1: 20,
+            x2: 30,
+            y2: 60,
+            xc: 20.0,
+            yc: 40.0,
+            w: 20,
+            h: 40,
+            ..Default::default()
+        };
+        let scaled = onnx::rescale(vec![det.clone()], 320, 640);
+        assert_eq!((scaled[0].x1, scaled[0].y2, scaled[0].h), (20, 120, 80));
+        assert_eq!(scaled[0].xc, 40.0);
+        assert_eq!(onnx::rescale(vec![det.clone()], 320, 320)[0].h, 40);
+    }
+
+    #[test]
+    fn missing_classes_test() {
+        // The shipped models output the pylon, person and roktrack only.
+        assert_eq!(
+            missing_classes(4 + 3),
+            vec![
+                RoktrackClasses::OBSTACLE,
+                RoktrackClasses::ROCK,
+                RoktrackClasses::TOY,
+                RoktrackClasses::HOSE
+            ]
+        );
+        assert!(missing_classes(4 + 7).is_empty());
+        assert!(missing_classes(4 + 80).is_empty());
+    }
+
+    #[test]
+    fn threshold_test() {
+        let conf = DetectThreshold::default();
+        let pylon = RoktrackClasses::PYLON.to_u32();
+        let obstacle = RoktrackClasses::OBSTACLE.to_u32();
+        assert_eq!(threshold(&conf, Classes::Roktrack, pylon), conf.pylon);
+        assert_eq!(threshold(&conf, Classes::Roktrack, obstacle), conf.default);
+        let hose = RoktrackClasses::HOSE.to_u32();
+        let obstacles = DetectThreshold {
+            obstacle: 0.3,
+            ..conf.clone()
+        };
+        assert_eq!(threshold(&obstacles, Classes::Roktrack, hose), 0.3);
+        assert_eq!(threshold(&obstacles, Classes::Roktrack, pylon), conf.pylon);
+        assert_eq!(threshold(&conf, Classes::Animal, pylon), conf.default);
+        assert_eq!(threshold(&conf, Classes::Other, 7), conf.default);
+
+        let det = |x1: u32, prob: f32| Detection {
+            x1,
+            y1: 0,
+            x2: x1 + 100,
+            y2: 100,
+            prob,
+            ..Default::default()
+        };
+        // Overlapping boxes are merged into the best one, and the rest capped.
+        let dets = vec![det(0, 0.6), det(1, 0.9), det(200, 0.5), det(400, 0.8)];
+        let conf = DetectThreshold {
+            max_detections: 2,
+            ..Default::default()
+        };
+        let kept = onnx::suppress(dets.clone(), &conf);
+        assert_eq!(kept.len(), 2);
+        assert_eq!((kept[0].prob, kept[0].x1, kept[0].x2), (0.9, 0, 101));
+        assert_eq!(kept[1].prob, 0.8);
+        let conf = DetectThreshold {
+            iou: 1.0,
+            ..Default::default()
+        };
+        assert_eq!(onnx::suppress(dets, &conf).len(), 4);
+    }
+
     #[test]
     fn sort_detection_test() {
         // center
@@ -576,6 +2000,9 @@ mod tests {
             w: 10,
             h: 10,
             ids: vec![],
+            track: None,
+            distance: None,
+            camera: FRONT,
         };
         // left top big
         let d1 = Detection {
@@ -590,6 +2017,9 @@ mod tests {
             w: 10,
             h: 15,
             ids: vec![],
+            track: None,
+            distance: None,
+            camera: FRONT,
         };
         // right bottom small
         let d2 = Detection {
@@ -604,6 +2034,9 @@ mod tests {
             w: 10,
             h: 5,
             ids: vec![],
+            track: None,
+            distance: None,
+            camera: FRONT,
         };
         let mut dets = [d0.clone(), d1.clone(), d2.clone()];
         let right = sort::right(&mut dets).first().unwrap().clone();
@@ -620,35 +2053,142 @@ mod tests {
         assert_eq!(big, d1.clone());
     }
 
+    #[test]
+    fn grass_boundary_test() {
+        // Grass on the left 6 columns
+        let mask: Vec<f32> = (0..16 * 16)
+            .map(|i| if i % 16 < 6 { 0.9 } else { 0.1 })
+            .collect();
+        let mask = segment::GrassMask::new(mask, 16, 16).unwrap();
+        let edges = mask.boundary(true);
+        assert_eq!(edges.len(), 8);
+        assert!(edges
+            .iter()
+            .all(|e| e.xc == 6.0 && e.cls == segment::EDGE_CLASS));
+        assert_eq!(edges[0].yc, 1.0);
+        // No grass on the right
+        assert!(mask.boundary(false).is_empty());
+        // All grass
+        let all = segment::GrassMask::new(vec![0.9; 256], 16, 16).unwrap();
+        assert!(all.boundary(true).is_empty());
+        assert_eq!(segment::GrassMask::new(vec![0.9; 255], 16, 16), None);
+
+        // Pooled into 4 * 4 cells of 4 * 4 px, the second column half grass
+        let pooled = mask.pooled(4);
+        assert_eq!((pooled.width, pooled.height), (4, 4));
+        assert!((pooled.grass[0] - 0.9).abs() < 1e-6);
+        assert!((pooled.grass[1] - 0.5).abs() < 1e-6);
+        assert!((pooled.grass[2] - 0.1).abs() < 1e-6);
+        assert_eq!(mask.pooled(32).width, 16);
+        // Grass in the left half
+        assert_eq!(mask.ratio((0.0, 0.0, 0.25, 1.0)), 1.0);
+        assert_eq!(mask.ratio((0.5, 0.5, 1.0, 1.0)), 0.0);
+        assert_eq!(mask.ratio((0.0, 0.0, 0.5, 1.0)), 0.75);
+        assert_eq!(mask.ratio((0.5, 0.5, 0.5, 1.0)), 1.0);
+    }
+
+    #[test]
+    fn raised_hands_test() {
+        let mut keypoints = vec![0.0; 17 * 3];
+        let mut set = |i: usize, x: f32, y: f32| {
+            keypoints[i * 3..i * 3 + 3].copy_from_slice(&[x, y, 0.9]);
+        };
+        set(0, 160.0, 60.0); // nose
+        set(9, 140.0, 40.0); // left wrist above the head
+        set(10, 180.0, 150.0); // right wrist down
+        let hands = pose::raised_hands(&keypoints);
+        assert_eq!(hands.len(), 1);
+        assert_eq!(hands[0].xc, 140.0);
+        assert_eq!(hands[0].cls, pose::HAND_RAISED_CLASS);
+        // Without the head nor the shoulders
+        assert!(pose::raised_hands(&[0.0; 17 * 3]).is_empty());
+        assert!(pose::raised_hands(&[]).is_empty());
+    }
+
+    #[test]
+    fn fallen_test() {
+        // A person standing, then lying along the ground
+        let mut row = vec![160.0, 160.0, 60.0, 200.0, 0.9];
+        row.extend([0.0; 17 * 3]);
+        let set = |row: &mut Vec<f32>, i: usize, x: f32, y: f32| {
+            row[5 + i * 3..5 + i * 3 + 3].copy_from_slice(&[x, y, 0.9]);
+        };
+        set(&mut row, 5, 150.0, 100.0); // left shoulder
+        set(&mut row, 6, 170.0, 100.0); // right shoulder
+        set(&mut row, 11, 152.0, 180.0); // left hip
+        set(&mut row, 12, 168.0, 180.0); // right hip
+        assert_eq!(pose::fallen(&row), None);
+        set(&mut row, 5, 100.0, 150.0);
+        set(&mut row, 6, 100.0, 170.0);
+        set(&mut row, 11, 180.0, 148.0);
+        set(&mut row, 12, 180.0, 172.0);
+        let fallen = pose::fallen(&row).unwrap();
+        assert_eq!(fallen.cls, pose::FALLEN_CLASS);
+        assert_eq!(
+            (fallen.x1, fallen.y1, fallen.x2, fallen.y2),
+            (130, 60, 190, 260)
+        );
+        // By the box without the torso
+        let mut row = vec![160.0, 200.0, 200.0, 80.0, 0.9];
+        row.extend([0.0; 17 * 3]);
+        assert!(pose::fallen(&row).is_some());
+        row[2] = 100.0;
+        assert_eq!(pose::fallen(&row), None);
+        assert_eq!(pose::fallen(&[]), None);
+    }
+
     #[test]
     fn roktrack_detect_object_test() {
-        let detector = onnx::YoloV8::new();
-        let dets = detector.infer("asset/img/pylon_10m.jpg", onnx::SessionType::Sz320);
+        let detector = onnx::YoloV8::new(Backend::Onnx, DetectThreshold::default()).unwrap();
+        let dets = detector.infer("asset/img/pylon_10m.jpg", SessionType::Sz320);
         assert!(dets.unwrap().len() == 2);
-        let dets = detector.infer("asset/img/person.jpg", onnx::SessionType::Sz320);
+        let dets = detector.infer("asset/img/person.jpg", SessionType::Sz320);
         assert!(dets.unwrap().len() == 1);
     }
 
     #[test]
     fn animal_detect_object_test() {
-        let mut detector = onnx::YoloV8::new();
-        detector.sessions = onnx::YoloV8::build_animal_sessions().unwrap();
-        let dets = detector.infer("asset/img/bear.jpg", onnx::SessionType::Sz320);
+        let mut detector = onnx::YoloV8::new(Backend::Onnx, DetectThreshold::default()).unwrap();
+        detector.load(Bundle::Animal).unwrap();
+        let dets = detector.infer("asset/img/bear.jpg", SessionType::Sz320);
         let dets = AnimalClasses::filter(&mut dets.unwrap(), AnimalClasses::BEAR.to_u32());
         assert!(dets.len() == 1);
-        let dets = detector.infer("asset/img/monkey.jpg", onnx::SessionType::Sz320);
+        let dets = detector.infer("asset/img/monkey.jpg", SessionType::Sz320);
         let dets = AnimalClasses::filter(&mut dets.unwrap(), AnimalClasses::MONKEY.to_u32());
         assert!(dets.len() == 2);
     }
 
     #[test]
     fn pylon_detect_resolution_test() {
-        let detector = onnx::YoloV8::new();
-        let dets = detector.infer("asset/img/pylon_10m.jpg", onnx::SessionType::Sz320);
+        let detector = onnx::YoloV8::new(Backend::Onnx, DetectThreshold::default()).unwrap();
+        let dets = detector.infer("asset/img/pylon_10m.jpg", SessionType::Sz320);
         let dets = RoktrackClasses::filter(&mut dets.unwrap(), RoktrackClasses::PYLON.to_u32());
         assert_eq!(dets.len(), 2);
-        let dets = detector.infer("asset/img/pylon_10m.jpg", onnx::SessionType::Sz640);
+        let dets = detector.infer("asset/img/pylon_10m.jpg", SessionType::Sz640);
         let dets = RoktrackClasses::filter(&mut dets.unwrap(), RoktrackClasses::PYLON.to_u32());
         assert_eq!(dets.len(), 2);
     }
+
+    #[test]
+    fn infrared_test() {
+        use image::{Rgb, RgbImage};
+        // Dim and tinted magenta, of the IR
+        let ir = RgbImage::from_fn(64, 64, |x, _| {
+            let v = 20 + x as u8;
+            Rgb([v + 30, v, v + 25])
+        });
+        assert!(infrared::is_infrared(&ir));
+        let gray = infrared::prepare(ir);
+        let (dark, bright) = (gray.get_pixel(0, 0), gray.get_pixel(63, 0));
+        assert_eq!(dark[0], dark[1]);
+        assert_eq!(dark[1], dark[2]);
+        assert!(dark[0] < 10 && 245 < bright[0]);
+        // Of color, kept
+        let color = RgbImage::from_fn(64, 64, |x, _| match x < 32 {
+            true => Rgb([40, 160, 40]),
+            false => Rgb([120, 120, 200]),
+        });
+        assert!(!infrared::is_infrared(&color));
+        assert_eq!(infrared::prepare(color.clone()), color);
+    }
 }
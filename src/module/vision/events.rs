@@ -0,0 +1,290 @@
+//! Detection Events
+//!
+//! The detections of the field runs, recorded for the researchers to analyze and to retrain the
+//! models from. Each run of the program is a session, recorded as it goes as a line of JSON per
+//! frame with any detection:
+//!
+//! events/<session>/events.jsonl     <- {"time", "frame", "model", "width", "height", "detections"}
+//! events/<session>/frames/<n>.jpg   <- The frames referred to, if kept
+//!
+//! Each detection of the front camera is {"class", "name", "bbox", "score", "track"}, with the box
+//! as [x, y, width, height] in px of the frame, as in COCO. The sessions are converted to COCO JSON,
+//! as events/<session>/coco.json, with `roktrack export` on the console.
+
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use serde_json::{json, Value};
+
+use super::detector::{AnimalClasses, Detection, RoktrackClasses};
+use crate::module::{define, util::conf::Events as EventsConf};
+
+// Lines of the events of a session
+const EVENTS_FILE: &str = "events.jsonl";
+// Frames of a session
+const FRAMES_DIR: &str = "frames";
+// Events of a session in COCO JSON
+const COCO_FILE: &str = "coco.json";
+
+/// Name of the class of the model, e.g. `pylon` or `deer`.
+pub fn class_name(model: &str, cls: u32) -> String {
+    let name = match model.starts_with("animal") {
+        true => AnimalClasses::from_u32(cls).map(|c| format!("{:?}", c)),
+        false => RoktrackClasses::from_u32(cls).map(|c| format!("{:?}", c)),
+    };
+    name.map(|name| name.to_lowercase())
+        .unwrap_or_else(|| format!("class_{}", cls))
+}
+
+/// The event of the frame of the size, and the detections in it in the input of the size `imgsz`.
+pub fn event(
+    time: &str,
+    frame: Option<&str>,
+    model: &str,
+    (width, height): (u32, u32),
+    dets: &[Detection],
+    imgsz: u32,
+) -> Value {
+    let sx = width as f32 / imgsz.max(1) as f32;
+    let sy = height as f32 / imgsz.max(1) as f32;
+    let detections: Vec<Value> = dets
+        .iter()
+        .map(|det| {
+            json!({
+                "class": det.cls,
+                "name": class_name(model, det.cls),
+                "bbox": [
+                    det.x1 as f32 * sx,
+                    det.y1 as f32 * sy,
+                    det.x2.saturating_sub(det.x1) as f32 * sx,
+                    det.y2.saturating_sub(det.y1) as f32 * sy,
+                ],
+                "score": det.prob,
+                "track": det.track,
+            })
+        })
+        .collect();
+    json!({
+        "time": time,
+        "frame": frame,
+        "model": model,
+        "width": width,
+        "height": height,
+        "detections": detections,
+    })
+}
+
+/// Converts the lines of the events to COCO JSON. The lines not of the events are left out.
+pub fn coco(lines: &str) -> Value {
+    let mut categories: BTreeMap<String, usize> = BTreeMap::new();
+    let (mut images, mut annotations) = (vec![], vec![]);
+    let events = lines
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok());
+    for (i, event) in events.enumerate() {
+        let image_id = i + 1;
+        images.push(json!({
+            "id": image_id,
+            "file_name": event["frame"],
+            "width": event["width"],
+            "height": event["height"],
+            "date_captured": event["time"],
+        }));
+        let detections = event["detections"].as_array().cloned().unwrap_or_default();
+        for det in detections {
+            let name = det["name"].as_str().unwrap_or_default().to_string();
+            let next = categories.len() + 1;
+            let category_id = *categories.entry(name).or_insert(next);
+            let bbox: Vec<f64> = det["bbox"]
+                .as_array()
+                .map(|b| b.iter().filter_map(|v| v.as_f64()).collect())
+                .unwrap_or_default();
+            let area = bbox.get(2).zip(bbox.get(3)).map(|(w, h)| w * h);
+            annotations.push(json!({
+                "id": annotations.len() + 1,
+                "image_id": image_id,
+                "category_id": category_id,
+                "bbox": bbox,
+                "area": area.unwrap_or_default(),
+                "iscrowd": 0,
+                "score": det["score"],
+                "attributes": {"track": det["track"]},
+            }));
+        }
+    }
+    let mut categories: Vec<(String, usize)> = categories.into_iter().collect();
+    categories.sort_by_key(|(_, id)| *id);
+    let categories: Vec<Value> = categories
+        .into_iter()
+        .map(|(name, id)| json!({"id": id, "name": name}))
+        .collect();
+    json!({
+        "info": {"description": "Roktrack detection events"},
+        "images": images,
+        "annotations": annotations,
+        "categories": categories,
+    })
+}
+
+/// Recorder of the events of the session
+pub struct EventRecorder {
+    dir: PathBuf, // Directory of the session
+    frames: u64,  // Frames recorded
+    last: u64,    // Time recorded last in ms
+}
+
+impl EventRecorder {
+    /// Starts the session named by the time in the data directory.
+    pub fn new(data_dir: &str) -> Self {
+        let session = Local::now().format("%Y%m%d_%H%M%S").to_string();
+        Self {
+            dir: Path::new(data_dir)
+                .join(define::path::EVENTS_DIR)
+                .join(session),
+            frames: 0,
+            last: 0,
+        }
+    }
+
+    /// Records the detections of the frame at the path, in the input of the size `imgsz`, at most
+    /// once per the interval. The frames without any detection aren't recorded.
+    pub fn record(
+        &mut self,
+        frame: &str,
+        dets: &[Detection],
+        imgsz: u32,
+        model: &str,
+        conf: &EventsConf,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let now = Local::now();
+        let ms = now.timestamp_millis() as u64;
+        if dets.is_empty() || ms < self.last + conf.interval_ms {
+            return Ok(());
+        }
+        self.last = ms;
+        let size = image::image_dimensions(frame)?;
+        let name = format!("{:06}.jpg", self.frames);
+        fs::create_dir_all(&self.dir)?;
+        if conf.frames {
+            fs::create_dir_all(self.dir.join(FRAMES_DIR))?;
+            fs::copy(frame, self.dir.join(FRAMES_DIR).join(&name))?;
+        }
+        self.frames += 1;
+        let reference = format!("{}/{}", FRAMES_DIR, name);
+        let reference = conf.frames.then_some(reference.as_str());
+        let event = event(&now.to_rfc3339(), reference, model, size, dets, imgsz);
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.dir.join(EVENTS_FILE))?;
+        writeln!(file, "{}", event)?;
+        Ok(())
+    }
+}
+
+/// Converts every session in the data directory to COCO JSON. Returns the sessions converted.
+pub fn export(data_dir: &str, mut output: impl Write) -> Result<usize, Box<dyn std::error::Error>> {
+    let dir = Path::new(data_dir).join(define::path::EVENTS_DIR);
+    let mut sessions: Vec<PathBuf> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.join(EVENTS_FILE).is_file())
+            .collect(),
+        Err(_) => vec![],
+    };
+    sessions.sort();
+    for session in &sessions {
+        let lines = fs::read_to_string(session.join(EVENTS_FILE))?;
+        let coco = coco(&lines);
+        fs::write(
+            session.join(COCO_FILE),
+            serde_json::to_string_pretty(&coco)?,
+        )?;
+        writeln!(
+            output,
+            "{}: {} frames, {} detections",
+            session.join(COCO_FILE).display(),
+            coco["images"].as_array().map_or(0, |a| a.len()),
+            coco["annotations"].as_array().map_or(0, |a| a.len()),
+        )?;
+    }
+    writeln!(output, "Exported: {}", sessions.len())?;
+    Ok(sessions.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_test() {
+        assert_eq!(class_name("pylon_320", 0), "pylon");
+        assert_eq!(class_name("animal_320", 1), "deer");
+        assert_eq!(class_name("pylon_320", 120), "class_120");
+
+        // A pylon and a person in the input of 320 * 320, of the frame of 640 * 480
+        let dets = [
+            Detection {
+                x1: 10,
+                y1: 20,
+                x2: 30,
+                y2: 60,
+                cls: 0,
+                prob: 0.5,
+                track: Some(3),
+                ..Default::default()
+            },
+            Detection {
+                x1: 100,
+                y1: 0,
+                x2: 150,
+                y2: 160,
+                cls: 1,
+                prob: 0.75,
+                ..Default::default()
+            },
+        ];
+        let first = event(
+            "2024-05-01T10:00:00+09:00",
+            Some("frames/000000.jpg"),
+            "pylon_320",
+            (640, 480),
+            &dets,
+            320,
+        );
+        assert_eq!(
+            first["detections"][0]["bbox"],
+            json!([20.0, 30.0, 40.0, 60.0])
+        );
+        assert_eq!(first["detections"][0]["track"], json!(3));
+        assert_eq!(first["detections"][1]["name"], json!("person"));
+        let second = event(
+            "2024-05-01T10:00:01+09:00",
+            None,
+            "pylon_320",
+            (640, 480),
+            &dets[..1],
+            320,
+        );
+        let lines = format!("{}\n{}\nnot an event\n", first, second);
+
+        let coco = coco(&lines);
+        assert_eq!(coco["images"].as_array().unwrap().len(), 2);
+        assert_eq!(coco["images"][0]["file_name"], json!("frames/000000.jpg"));
+        assert_eq!(coco["images"][1]["file_name"], Value::Null);
+        assert_eq!(
+            coco["categories"],
+            json!([{"id": 1, "name": "pylon"}, {"id": 2, "name": "person"}])
+        );
+        let annotations = coco["annotations"].as_array().unwrap();
+        assert_eq!(annotations.len(), 3);
+        assert_eq!(annotations[1]["category_id"], json!(2));
+        assert_eq!(annotations[1]["area"], json!(100.0 * 240.0));
+        assert_eq!(annotations[2]["image_id"], json!(2));
+        assert_eq!(annotations[2]["score"], json!(0.5));
+    }
+}
@@ -0,0 +1,130 @@
+//! Exposure Control
+//!
+//! The camera meters the whole frame, so driving toward the low sun in the evening blows out the
+//! ground and the pylons on it, and the marker is lost. When too much of the frame is blown out,
+//! the exposure is taken over and halved frame by frame until it isn't, and then given back step
+//! by step once the frames are clear, to the auto exposure or to the locked one.
+
+use image::GrayImage;
+
+use crate::module::util::conf::Exposure as ExposureConf;
+
+// Luma at or above which a pixel is blown out
+const BLOWN: u8 = 250;
+// Clear frames to lengthen the exposure again after
+const CLEAR_FRAMES: u8 = 10;
+
+/// Exposure to set to the camera
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Control {
+    Auto,        // By the camera
+    Manual(i32), // Exposure time in 100 us
+}
+
+/// Ratio of the blown out pixels in the frame.
+pub fn blown_out(img: &GrayImage) -> f32 {
+    let total = img.width() * img.height();
+    if total == 0 {
+        return 0.0;
+    }
+    let blown = img.pixels().filter(|p| BLOWN <= p[0]).count();
+    blown as f32 / total as f32
+}
+
+/// Exposure locked by the config, or the auto exposure.
+pub fn base(conf: &ExposureConf) -> Control {
+    match conf.auto_exposure {
+        true => Control::Auto,
+        false => Control::Manual(conf.exposure),
+    }
+}
+
+/// Compensation of the glare
+#[derive(Debug, Default)]
+pub struct Glare {
+    exposure: Option<i32>, // Exposure taken over (None: as the base)
+    clear: u8,             // Clear frames in a row
+}
+
+impl Glare {
+    /// Updates with the ratio of the blown out pixels in the frame, and returns the exposure to set
+    /// if it's changed.
+    pub fn update(&mut self, ratio: f32, conf: &ExposureConf) -> Option<Control> {
+        if conf.glare_ratio < ratio {
+            self.clear = 0;
+            let next = match self.exposure {
+                None => conf.exposure,
+                Some(exposure) => (exposure / 2).max(conf.min_exposure),
+            };
+            if self.exposure == Some(next) {
+                return None;
+            }
+            self.exposure = Some(next);
+            return Some(Control::Manual(next));
+        }
+        // Not lengthened right at the threshold, not to blow out again
+        let exposure = self.exposure?;
+        if conf.glare_ratio / 2.0 < ratio {
+            self.clear = 0;
+            return None;
+        }
+        self.clear += 1;
+        if self.clear < CLEAR_FRAMES {
+            return None;
+        }
+        self.clear = 0;
+        match exposure * 2 {
+            next if next < conf.exposure => {
+                self.exposure = Some(next);
+                Some(Control::Manual(next))
+            }
+            _ => {
+                self.exposure = None;
+                Some(base(conf))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    #[test]
+    fn glare_test() {
+        // A quarter blown out
+        let img = GrayImage::from_fn(100, 100, |x, _| Luma([if x < 25 { 255 } else { 100 }]));
+        assert_eq!(blown_out(&img), 0.25);
+        assert_eq!(blown_out(&GrayImage::new(0, 0)), 0.0);
+
+        let conf = ExposureConf {
+            exposure: 160,
+            min_exposure: 20,
+            glare_ratio: 0.1,
+            ..Default::default()
+        };
+        let mut glare = Glare::default();
+        assert_eq!(glare.update(0.0, &conf), None);
+        // Taken over and shortened down to the least
+        assert_eq!(glare.update(0.3, &conf), Some(Control::Manual(160)));
+        assert_eq!(glare.update(0.3, &conf), Some(Control::Manual(80)));
+        assert_eq!(glare.update(0.3, &conf), Some(Control::Manual(40)));
+        assert_eq!(glare.update(0.3, &conf), Some(Control::Manual(20)));
+        assert_eq!(glare.update(0.3, &conf), None);
+        // Kept near the threshold
+        for _ in 0..20 {
+            assert_eq!(glare.update(0.08, &conf), None);
+        }
+        // Lengthened after the clear frames, and given back
+        let mut changes = vec![];
+        for _ in 0..40 {
+            changes.extend(glare.update(0.0, &conf));
+        }
+        assert_eq!(
+            changes,
+            vec![Control::Manual(40), Control::Manual(80), Control::Auto]
+        );
+        assert_eq!(glare.update(0.0, &conf), None);
+    }
+}
@@ -0,0 +1,437 @@
+//! Fiducial Tag Detection
+//!
+//! Finds the tags of the original ArUco dictionary (`DICT_ARUCO_ORIGINAL` of OpenCV) in the frames,
+//! as an alternative to the markers detected by the models. They are cheap to print, carry their
+//! IDs, and give their distance by their pose.
+//!
+//! A tag is a black square of 7 * 7 cells, whose inner 5 * 5 cells carry the ID. Each row of them
+//! is one of 4 code words, 2 bits of the ID, so that a tag is read in one rotation only.
+//!
+//! 1. The frame is binarized by the local mean, and the dark regions are taken as candidates.
+//! 2. The convex hull of a candidate is fitted by a quadrilateral, and the cells are sampled
+//!    through the homography from the tag to it.
+//! 3. The rows are read as the code words in each rotation of the quadrilateral.
+//!
+//! The tags are sent as the pylon class with their IDs, as the numbers read by the OCR, so that the
+//! modes following the numbered markers, e.g. the waypoints of the round trip, work with them. Only
+//! the IDs 0 - 255 are taken.
+
+use image::GrayImage;
+
+use super::detector::{Detection, RoktrackClasses};
+use super::intrinsics::{self, Intrinsics, Pose};
+use crate::module::util::conf::Fiducial as FiducialConf;
+
+// Code words of the rows, 1 for the white cells. The index is the 2 bits of the ID.
+const CODE_WORDS: [[bool; 5]; 4] = [
+    [true, false, false, false, false],
+    [true, false, true, true, true],
+    [false, true, false, false, true],
+    [false, true, true, true, false],
+];
+// Cells of a side of the tag, including the black border
+const CELLS: usize = 7;
+// Shortest side of a tag in px
+const MIN_SIDE: f32 = 14.0;
+// Difference in brightness from the local mean, under which a pixel is taken as dark
+const THRESHOLD_OFFSET: i64 = 7;
+// Lowest difference between the white and the black cells
+const MIN_CONTRAST: f32 = 40.0;
+// Lowest ratio of the area of the quadrilateral to the convex hull of the candidate
+const MIN_FILL: f32 = 0.9;
+
+/// A tag found in the frame
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub id: u16,                  // ID in the dictionary
+    pub corners: [(f32, f32); 4], // Corners from the top left of the tag, clockwise, in px
+}
+
+/// Finds the tags in the frame.
+pub fn find_tags(img: &GrayImage) -> Vec<Tag> {
+    let dark = binarize(img);
+    let mut tags = vec![];
+    for boundary in regions(&dark, img.width() as usize, img.height() as usize) {
+        let Some(quad) = quadrilateral(&hull(boundary)) else {
+            continue;
+        };
+        if let Some(tag) = decode(img, quad) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+/// Dark pixels, darker than the mean around by the offset.
+fn binarize(img: &GrayImage) -> Vec<bool> {
+    let (w, h) = (img.width() as usize, img.height() as usize);
+    // Integral image
+    let mut sum = vec![0_i64; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0;
+        for x in 0..w {
+            row += img.get_pixel(x as u32, y as u32)[0] as i64;
+            sum[(y + 1) * (w + 1) + x + 1] = sum[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let radius = (w.max(h) / 20).max(3);
+    let mut dark = vec![false; w * h];
+    for y in 0..h {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(h));
+        for x in 0..w {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(w));
+            let area = ((x1 - x0) * (y1 - y0)) as i64;
+            let local = sum[y1 * (w + 1) + x1] - sum[y0 * (w + 1) + x1] - sum[y1 * (w + 1) + x0]
+                + sum[y0 * (w + 1) + x0];
+            let value = img.get_pixel(x as u32, y as u32)[0] as i64;
+            dark[y * w + x] = value * area < local - THRESHOLD_OFFSET * area;
+        }
+    }
+    dark
+}
+
+/// Boundaries of the dark regions which may be tags, the pixels next to the bright ones.
+fn regions(dark: &[bool], w: usize, h: usize) -> Vec<Vec<(f32, f32)>> {
+    let mut seen = vec![false; w * h];
+    let mut boundaries = vec![];
+    for start in 0..w * h {
+        if !dark[start] || seen[start] {
+            continue;
+        }
+        seen[start] = true;
+        let mut stack = vec![start];
+        let mut boundary = vec![];
+        let mut on_edge = false;
+        let (mut min, mut max) = ((w, h), (0, 0));
+        while let Some(i) = stack.pop() {
+            let (x, y) = (i % w, i / w);
+            min = (min.0.min(x), min.1.min(y));
+            max = (max.0.max(x), max.1.max(y));
+            if x == 0 || y == 0 || x == w - 1 || y == h - 1 {
+                on_edge = true;
+                continue;
+            }
+            let mut inner = true;
+            for n in [i - 1, i + 1, i - w, i + w] {
+                if !dark[n] {
+                    inner = false;
+                } else if !seen[n] {
+                    seen[n] = true;
+                    stack.push(n);
+                }
+            }
+            if !inner {
+                boundary.push((x as f32, y as f32));
+            }
+        }
+        // A tag is whole in the frame, and neither too small nor too large.
+        let size = ((max.0 - min.0) as f32, (max.1 - min.1) as f32);
+        if on_edge || size.0.min(size.1) < MIN_SIDE || w.min(h) as f32 / 2.0 < size.0.max(size.1) {
+            continue;
+        }
+        boundaries.push(boundary);
+    }
+    boundaries
+}
+
+/// Convex hull of the points (monotone chain).
+fn hull(mut points: Vec<(f32, f32)>) -> Vec<(f32, f32)> {
+    points.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.total_cmp(&b.1)));
+    points.dedup();
+    if points.len() < 3 {
+        return points;
+    }
+    let cross = |o: (f32, f32), a: (f32, f32), b: (f32, f32)| {
+        (a.0 - o.0) * (b.1 - o.1) - (a.1 - o.1) * (b.0 - o.0)
+    };
+    let mut hull: Vec<(f32, f32)> = vec![];
+    for pass in [points.clone(), points.into_iter().rev().collect()] {
+        let base = hull.len();
+        for p in pass {
+            while base + 2 <= hull.len()
+                && cross(hull[hull.len() - 2], hull[hull.len() - 1], p) <= 0.0
+            {
+                hull.pop();
+            }
+            hull.push(p);
+        }
+        hull.pop();
+    }
+    hull
+}
+
+/// Area of the polygon.
+fn area(polygon: &[(f32, f32)]) -> f32 {
+    let n = polygon.len();
+    let twice: f32 = (0..n)
+        .map(|i| {
+            let (a, b) = (polygon[i], polygon[(i + 1) % n]);
+            a.0 * b.1 - b.0 * a.1
+        })
+        .sum();
+    twice.abs() / 2.0
+}
+
+/// Quadrilateral fitting the convex hull, clockwise in the image. None if the hull isn't one.
+fn quadrilateral(hull: &[(f32, f32)]) -> Option<[(f32, f32); 4]> {
+    if hull.len() < 4 {
+        return None;
+    }
+    let distance = |a: (f32, f32), b: (f32, f32)| (a.0 - b.0).hypot(a.1 - b.1);
+    let farthest = |from: &dyn Fn((f32, f32)) -> f32| {
+        hull.iter()
+            .copied()
+            .max_by(|a, b| from(*a).total_cmp(&from(*b)))
+    };
+    let n = hull.len() as f32;
+    let center = hull
+        .iter()
+        .fold((0.0, 0.0), |acc, p| (acc.0 + p.0 / n, acc.1 + p.1 / n));
+    // The diagonal, and the farthest corners on both sides of it
+    let a = farthest(&|p| distance(p, center))?;
+    let c = farthest(&|p| distance(p, a))?;
+    let side = |p: (f32, f32)| (c.0 - a.0) * (p.1 - a.1) - (c.1 - a.1) * (p.0 - a.0);
+    let b = farthest(&|p| side(p))?;
+    let d = farthest(&|p| -side(p))?;
+    let mut quad = [a, b, c, d];
+    if area(&quad) < area(hull) * MIN_FILL {
+        return None;
+    }
+    let sides = (0..4).map(|i| distance(quad[i], quad[(i + 1) % 4]));
+    if sides.fold(f32::MAX, f32::min) < MIN_SIDE {
+        return None;
+    }
+    // Clockwise in the image, whose y axis points down
+    let turn = (b.0 - a.0) * (c.1 - a.1) - (b.1 - a.1) * (c.0 - a.0);
+    if turn < 0.0 {
+        quad.swap(1, 3);
+    }
+    Some(quad)
+}
+
+/// Reads the tag in the quadrilateral, trying each of its corners as the top left.
+fn decode(img: &GrayImage, quad: [(f32, f32); 4]) -> Option<Tag> {
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let pixel = |x: f32, y: f32| {
+        let (x, y) = (x.clamp(0.0, w - 1.0), y.clamp(0.0, h - 1.0));
+        img.get_pixel(x as u32, y as u32)[0] as f32
+    };
+    let size = CELLS as f64;
+    let grid = [(0.0, 0.0), (size, 0.0), (size, size), (0.0, size)];
+    for rotation in 0..4 {
+        let corners: [(f32, f32); 4] = std::array::from_fn(|i| quad[(i + rotation) % 4]);
+        let image: Vec<(f64, f64)> = corners.iter().map(|&(x, y)| (x as f64, y as f64)).collect();
+        let homography = intrinsics::homography(&grid, &image)?;
+        // Mean brightness around the center of each cell
+        let mut cells = [[0.0_f32; CELLS]; CELLS];
+        for (row, cells) in cells.iter_mut().enumerate() {
+            for (col, cell) in cells.iter_mut().enumerate() {
+                let (x, y) = intrinsics::project(&homography, (col as f64 + 0.5, row as f64 + 0.5));
+                let (x, y) = (x as f32, y as f32);
+                *cell = [(0.0, 0.0), (-1.0, 0.0), (1.0, 0.0), (0.0, -1.0), (0.0, 1.0)]
+                    .iter()
+                    .map(|(dx, dy)| pixel(x + dx, y + dy))
+                    .sum::<f32>()
+                    / 5.0;
+            }
+        }
+        let all = cells.iter().flatten();
+        let (min, max) = all.fold((f32::MAX, 0.0_f32), |(min, max), v| {
+            (min.min(*v), max.max(*v))
+        });
+        if max - min < MIN_CONTRAST {
+            return None;
+        }
+        let threshold = (min + max) / 2.0;
+        let border = (0..CELLS).all(|i| {
+            [
+                cells[0][i],
+                cells[CELLS - 1][i],
+                cells[i][0],
+                cells[i][CELLS - 1],
+            ]
+            .iter()
+            .all(|v| *v < threshold)
+        });
+        if !border {
+            return None;
+        }
+        let id = cells[1..CELLS - 1].iter().try_fold(0_u16, |id, row| {
+            let bits: Vec<bool> = row[1..CELLS - 1].iter().map(|v| threshold < *v).collect();
+            let word = CODE_WORDS.iter().position(|word| word[..] == bits[..])?;
+            Some((id << 2) | word as u16)
+        });
+        if let Some(id) = id {
+            return Some(Tag { id, corners });
+        }
+    }
+    None
+}
+
+/// Distance in m to the tag of the side in m, by its pose seen with the focal length in px.
+fn distance(corners: &[(f32, f32); 4], size: f32, focal: f32, center: (f32, f32)) -> Option<f32> {
+    let half = size as f64 / 2.0;
+    let tag = [(-half, -half), (half, -half), (half, half), (-half, half)];
+    let image: Vec<(f64, f64)> = corners
+        .iter()
+        .map(|(u, v)| {
+            (
+                ((u - center.0) / focal) as f64,
+                ((v - center.1) / focal) as f64,
+            )
+        })
+        .collect();
+    let pose = Pose::from_homography(&intrinsics::homography(&tag, &image)?, 1.0);
+    let t = pose.translation;
+    Some((t[0] * t[0] + t[1] * t[1] + t[2] * t[2]).sqrt() as f32)
+}
+
+/// Finds the tags in the frame, as the detections in the input of the size `imgsz`, which is the
+/// frame resized. The corners are undistorted by the intrinsics if calibrated, and the focal
+/// length is from them or the vertical field of view.
+pub fn detect(
+    img: &GrayImage,
+    imgsz: u32,
+    intrinsics: Option<&Intrinsics>,
+    vfov: f32,
+    conf: &FiducialConf,
+) -> Vec<Detection> {
+    let (w, h) = (img.width() as f32, img.height() as f32);
+    let intrinsics =
+        intrinsics.filter(|i| (i.width, i.height) == img.dimensions() && 0.0 < i.focal);
+    let (focal, center) = match intrinsics {
+        Some(intrinsics) => (intrinsics.focal, intrinsics.center),
+        None => (
+            h / 2.0 / (vfov.to_radians() / 2.0).tan(),
+            (w / 2.0, h / 2.0),
+        ),
+    };
+    let (sx, sy) = (imgsz as f32 / w, imgsz as f32 / h);
+    let mut dets = vec![];
+    for tag in find_tags(img) {
+        let Ok(id) = u8::try_from(tag.id) else {
+            log::debug!("Tag Out Of Range: {}", tag.id);
+            continue;
+        };
+        let corners = match intrinsics {
+            Some(intrinsics) => tag.corners.map(|p| intrinsics.undistort_point(p)),
+            None => tag.corners,
+        };
+        let x1 = corners
+            .iter()
+            .map(|p| p.0)
+            .fold(f32::MAX, f32::min)
+            .max(0.0)
+            * sx;
+        let y1 = corners
+            .iter()
+            .map(|p| p.1)
+            .fold(f32::MAX, f32::min)
+            .max(0.0)
+            * sy;
+        let x2 = corners.iter().map(|p| p.0).fold(0.0, f32::max) * sx;
+        let y2 = corners.iter().map(|p| p.1).fold(0.0, f32::max) * sy;
+        dets.push(Detection {
+            x1: x1 as u32,
+            y1: y1 as u32,
+            x2: x2 as u32,
+            y2: y2 as u32,
+            xc: (x1 + x2) / 2.0,
+            yc: (y1 + y2) / 2.0,
+            cls: RoktrackClasses::PYLON.to_u32(),
+            prob: 1.0,
+            w: (x2 - x1) as u32,
+            h: (y2 - y1) as u32,
+            ids: vec![id],
+            distance: distance(&corners, conf.size, focal, center),
+            ..Default::default()
+        });
+    }
+    dets
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Cells of the tag, true for the white ones.
+    fn pattern(id: u16) -> [[bool; CELLS]; CELLS] {
+        let mut cells = [[false; CELLS]; CELLS];
+        for row in 0..5 {
+            let word = CODE_WORDS[((id >> (2 * (4 - row))) & 3) as usize];
+            cells[row + 1][1..CELLS - 1].copy_from_slice(&word);
+        }
+        cells
+    }
+
+    /// Renders the tag on white, mapping the image to the cells of the tag.
+    fn render(id: u16, (w, h): (u32, u32), to_tag: impl Fn(f32, f32) -> (f32, f32)) -> GrayImage {
+        let cells = pattern(id);
+        GrayImage::from_fn(w, h, |x, y| {
+            let (cx, cy) = to_tag(x as f32 + 0.5, y as f32 + 0.5);
+            let inside = 0.0 <= cx && cx < CELLS as f32 && 0.0 <= cy && cy < CELLS as f32;
+            let white = !inside || cells[cy as usize][cx as usize];
+            Luma([if white { 220 } else { 30 }])
+        })
+    }
+
+    /// Mapping of the image to the cells of the tag centered at (240, 180), of 20 px cells rotated
+    /// by the angle.
+    fn rotated(angle: f32) -> impl Fn(f32, f32) -> (f32, f32) {
+        let (s, c) = angle.sin_cos();
+        move |x, y| {
+            let (dx, dy) = ((x - 240.0) / 20.0, (y - 180.0) / 20.0);
+            (dx * c + dy * s + 3.5, -dx * s + dy * c + 3.5)
+        }
+    }
+
+    #[test]
+    fn fiducial_test() {
+        for (id, angle) in [
+            (123, 0.2),
+            (300, 0.2 + std::f32::consts::FRAC_PI_2),
+            (7, 3.0),
+        ] {
+            let tags = find_tags(&render(id, (480, 360), rotated(angle)));
+            assert_eq!(tags.len(), 1);
+            assert_eq!(tags[0].id, id);
+            // The top left of the tag first
+            let (s, c) = angle.sin_cos();
+            let expected = (240.0 + 70.0 * (s - c), 180.0 - 70.0 * (s + c));
+            let (x, y) = tags[0].corners[0];
+            assert!((x - expected.0).abs() < 2.0 && (y - expected.1).abs() < 2.0);
+        }
+        // Mirrored
+        let img = render(123, (480, 360), |x, y| rotated(0.2)(y - 60.0, x + 60.0));
+        assert!(find_tags(&img).is_empty());
+        assert!(find_tags(&GrayImage::from_pixel(480, 360, Luma([200]))).is_empty());
+
+        // A tag of 0.14 m, 140 px high in the center seen with the focal length of 1000 px, is 1 m
+        // away.
+        let corners = [
+            (430.0, 290.0),
+            (570.0, 290.0),
+            (570.0, 430.0),
+            (430.0, 430.0),
+        ];
+        let d = distance(&corners, 0.14, 1000.0, (500.0, 360.0)).unwrap();
+        assert!((d - 1.0).abs() < 0.01);
+
+        // Sent as the numbered marker
+        let conf = FiducialConf::default();
+        let dets = detect(
+            &render(42, (480, 360), rotated(0.2)),
+            320,
+            None,
+            48.8,
+            &conf,
+        );
+        assert_eq!(dets.len(), 1);
+        assert_eq!(dets[0].ids, vec![42]);
+        assert_eq!(dets[0].cls, RoktrackClasses::PYLON.to_u32());
+        assert!(dets[0].distance.is_some());
+        let img = render(300, (480, 360), rotated(0.2));
+        assert!(detect(&img, 320, None, 48.8, &conf).is_empty());
+    }
+}
@@ -0,0 +1,550 @@
+//! Camera Intrinsics
+//!
+//! The camera calibration mode finds a checkerboard in the frames from different angles and
+//! computes the focal length and the radial distortion of the lens from them, after Zhang's
+//! method. The pixels are taken as square and the principal point as the center of the image.
+//!
+//! 1. The homography from the board to the image of each view is estimated from the corners.
+//! 2. The focal length follows from the axes of the board being orthogonal, which the homographies
+//!    of the tilted views constrain, and the pose of the board in each view from the homography.
+//! 3. The focal length, the distortion and the poses are refined together by Levenberg-Marquardt,
+//!    minimizing the distance of the corners from their projections.
+//!
+//! The results are saved to the data directory, and the vision undistorts the detections with
+//! them, so that the angles and the heights near the edges of the image are as in the center.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use super::detector::Detection;
+use crate::module::define;
+
+// Iterations of the refinement at most
+const REFINE_ITERATIONS: usize = 50;
+// Iterations to invert the distortion
+const UNDISTORT_ITERATIONS: usize = 5;
+
+/// Intrinsic parameters of the camera.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Intrinsics {
+    pub width: u32,         // Width of the calibrated frames in px
+    pub height: u32,        // Height of the calibrated frames in px
+    pub focal: f32,         // Focal length in px
+    pub center: (f32, f32), // Principal point in px
+    pub k1: f32,            // Radial distortion coefficient of r^2
+    pub k2: f32,            // Radial distortion coefficient of r^4
+    pub error: f32,         // RMS reprojection error in px
+    pub calibrated_at: i64, // Time of the calibration in ms
+}
+
+impl Intrinsics {
+    /// Loads the saved intrinsics. None if there are none.
+    pub fn load(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        match toml::from_str(&content) {
+            Ok(intrinsics) => Some(intrinsics),
+            Err(e) => {
+                log::warn!("Invalid Camera Calibration: {}, {}", path.display(), e);
+                None
+            }
+        }
+    }
+
+    /// Saves the intrinsics.
+    pub fn save(&self, path: &Path) -> Result<(), Box<dyn std::error::Error>> {
+        // Write to a temporary file first, so that a crash doesn't leave a broken one.
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, toml::to_string(self)?)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Undistorts a point in px.
+    pub fn undistort_point(&self, (u, v): (f32, f32)) -> (f32, f32) {
+        if self.focal <= 0.0 {
+            return (u, v);
+        }
+        let (xd, yd) = (
+            (u - self.center.0) / self.focal,
+            (v - self.center.1) / self.focal,
+        );
+        let (mut x, mut y) = (xd, yd);
+        for _ in 0..UNDISTORT_ITERATIONS {
+            let r2 = x * x + y * y;
+            let radial = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+            (x, y) = (xd / radial, yd / radial);
+        }
+        (
+            self.center.0 + x * self.focal,
+            self.center.1 + y * self.focal,
+        )
+    }
+
+    /// Undistorts the boxes detected in the input of the size `imgsz`, which is the frame resized.
+    pub fn undistort(&self, dets: &mut [Detection], imgsz: u32) {
+        if self.focal <= 0.0 || imgsz == 0 {
+            return;
+        }
+        let (sx, sy) = (
+            self.width as f32 / imgsz as f32,
+            self.height as f32 / imgsz as f32,
+        );
+        let undistort = |x: f32, y: f32| {
+            let (u, v) = self.undistort_point((x * sx, y * sy));
+            (u / sx, v / sy)
+        };
+        for det in dets.iter_mut() {
+            let corners = [
+                undistort(det.x1 as f32, det.y1 as f32),
+                undistort(det.x2 as f32, det.y1 as f32),
+                undistort(det.x1 as f32, det.y2 as f32),
+                undistort(det.x2 as f32, det.y2 as f32),
+            ];
+            let (xc, yc) = undistort(det.xc, det.yc);
+            let x1 = corners
+                .iter()
+                .map(|c| c.0)
+                .fold(f32::MAX, f32::min)
+                .max(0.0);
+            let y1 = corners
+                .iter()
+                .map(|c| c.1)
+                .fold(f32::MAX, f32::min)
+                .max(0.0);
+            let x2 = corners.iter().map(|c| c.0).fold(0.0, f32::max);
+            let y2 = corners.iter().map(|c| c.1).fold(0.0, f32::max);
+            det.x1 = x1 as u32;
+            det.y1 = y1 as u32;
+            det.x2 = x2 as u32;
+            det.y2 = y2 as u32;
+            det.w = det.x2.saturating_sub(det.x1);
+            det.h = det.y2.saturating_sub(det.y1);
+            det.xc = xc;
+            det.yc = yc;
+        }
+    }
+}
+
+/// Path of the camera calibration file in the data directory.
+pub fn path(data_dir: &str) -> PathBuf {
+    Path::new(data_dir).join(define::path::CAMERA_CALIBRATION_FILE)
+}
+
+/// Solves the linear equations by Gaussian elimination. None if they are singular.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n).max_by(|&i, &j| a[i][col].abs().total_cmp(&a[j][col].abs()))?;
+        if a[pivot][col].abs() < 1e-12 {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            let pivot_row = a[col].clone();
+            for (value, p) in a[row].iter_mut().zip(pivot_row).skip(col) {
+                *value -= factor * p;
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    Some(x)
+}
+
+/// Least squares solution of the overdetermined equations, rows of (coefficients, value).
+fn least_squares(rows: &[(Vec<f64>, f64)]) -> Option<Vec<f64>> {
+    let n = rows.first()?.0.len();
+    let mut ata = vec![vec![0.0; n]; n];
+    let mut atb = vec![0.0; n];
+    for (coefficients, value) in rows {
+        for (i, ci) in coefficients.iter().enumerate() {
+            for (j, cj) in coefficients.iter().enumerate() {
+                ata[i][j] += ci * cj;
+            }
+            atb[i] += ci * value;
+        }
+    }
+    solve(ata, atb)
+}
+
+/// Homography (row-major, h33 = 1) from the board to the image.
+pub(super) fn homography(board: &[(f64, f64)], image: &[(f64, f64)]) -> Option<[f64; 9]> {
+    let mut rows = vec![];
+    for (&(x, y), &(u, v)) in board.iter().zip(image) {
+        rows.push((vec![x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y], u));
+        rows.push((vec![0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y], v));
+    }
+    let h = least_squares(&rows)?;
+    Some([h[0], h[1], h[2], h[3], h[4], h[5], h[6], h[7], 1.0])
+}
+
+/// Projects a point of the board by the homography.
+pub(super) fn project(h: &[f64; 9], (x, y): (f64, f64)) -> (f64, f64) {
+    let w = h[6] * x + h[7] * y + h[8];
+    (
+        (h[0] * x + h[1] * y + h[2]) / w,
+        (h[3] * x + h[4] * y + h[5]) / w,
+    )
+}
+
+/// Rotation matrix of the rotation vector (Rodrigues' formula).
+fn rotation(r: [f64; 3]) -> [[f64; 3]; 3] {
+    let angle = (r[0] * r[0] + r[1] * r[1] + r[2] * r[2]).sqrt();
+    if angle < 1e-12 {
+        return [[1.0, -r[2], r[1]], [r[2], 1.0, -r[0]], [-r[1], r[0], 1.0]];
+    }
+    let (x, y, z) = (r[0] / angle, r[1] / angle, r[2] / angle);
+    let (s, c) = angle.sin_cos();
+    let v = 1.0 - c;
+    [
+        [c + x * x * v, x * y * v - z * s, x * z * v + y * s],
+        [y * x * v + z * s, c + y * y * v, y * z * v - x * s],
+        [z * x * v - y * s, z * y * v + x * s, c + z * z * v],
+    ]
+}
+
+/// Pose of the board in a view, from the board to the camera.
+#[derive(Debug, Clone)]
+pub(super) struct Pose {
+    rotation: [[f64; 3]; 3],
+    pub(super) translation: [f64; 3],
+}
+
+impl Pose {
+    /// Pose from the homography of the view, with the focal length in the normalized coordinates.
+    pub(super) fn from_homography(h: &[f64; 9], focal: f64) -> Self {
+        let column = |i: usize| [h[i] / focal, h[3 + i] / focal, h[6 + i]];
+        let norm = |v: [f64; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+        let (c1, c2, c3) = (column(0), column(1), column(2));
+        // The board is in front of the camera.
+        let lambda = match c3[2] < 0.0 {
+            true => -1.0 / norm(c1),
+            false => 1.0 / norm(c1),
+        };
+        let r1 = c1.map(|v| v * lambda);
+        let r2 = c2.map(|v| v * lambda);
+        // Make the axes orthonormal.
+        let dot = r1[0] * r2[0] + r1[1] * r2[1] + r1[2] * r2[2];
+        let r2 = [
+            r2[0] - dot * r1[0],
+            r2[1] - dot * r1[1],
+            r2[2] - dot * r1[2],
+        ];
+        let r2 = r2.map(|v| v / norm(r2));
+        let r3 = [
+            r1[1] * r2[2] - r1[2] * r2[1],
+            r1[2] * r2[0] - r1[0] * r2[2],
+            r1[0] * r2[1] - r1[1] * r2[0],
+        ];
+        Self {
+            rotation: [
+                [r1[0], r2[0], r3[0]],
+                [r1[1], r2[1], r3[1]],
+                [r1[2], r2[2], r3[2]],
+            ],
+            translation: c3.map(|v| v * lambda),
+        }
+    }
+
+    /// Pose moved by the step of the rotation vector and the translation.
+    fn moved(&self, step: &[f64]) -> Self {
+        let r = rotation([step[0], step[1], step[2]]);
+        let mut rotation = [[0.0; 3]; 3];
+        for (row, r_row) in rotation.iter_mut().zip(r.iter()) {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| r_row[k] * self.rotation[k][j]).sum();
+            }
+        }
+        Self {
+            rotation,
+            translation: [
+                self.translation[0] + step[3],
+                self.translation[1] + step[4],
+                self.translation[2] + step[5],
+            ],
+        }
+    }
+}
+
+/// Camera of the refinement: focal length in the normalized coordinates, k1, k2 and the poses.
+#[derive(Debug, Clone)]
+struct Camera {
+    focal: f64,
+    k1: f64,
+    k2: f64,
+    poses: Vec<Pose>,
+}
+
+impl Camera {
+    /// Camera moved by the step of the parameters, the focal length, k1, k2 and 6 for each pose.
+    fn moved(&self, step: &[f64]) -> Self {
+        Self {
+            focal: self.focal + step[0],
+            k1: self.k1 + step[1],
+            k2: self.k2 + step[2],
+            poses: self
+                .poses
+                .iter()
+                .zip(step[3..].chunks(6))
+                .map(|(pose, step)| pose.moved(step))
+                .collect(),
+        }
+    }
+
+    /// Differences of the projections of the board from the corners, x and y of each.
+    fn residuals(&self, board: &[(f64, f64)], views: &[Vec<(f64, f64)>]) -> Vec<f64> {
+        let mut residuals = vec![];
+        for (pose, view) in self.poses.iter().zip(views) {
+            let (r, t) = (&pose.rotation, &pose.translation);
+            for (&(x, y), &(u, v)) in board.iter().zip(view) {
+                let z = r[2][0] * x + r[2][1] * y + t[2];
+                let px = (r[0][0] * x + r[0][1] * y + t[0]) / z;
+                let py = (r[1][0] * x + r[1][1] * y + t[1]) / z;
+                let r2 = px * px + py * py;
+                let radial = 1.0 + self.k1 * r2 + self.k2 * r2 * r2;
+                residuals.push(self.focal * px * radial - u);
+                residuals.push(self.focal * py * radial - v);
+            }
+        }
+        residuals
+    }
+}
+
+/// Refines the camera by Levenberg-Marquardt, with the Jacobian by central differences.
+fn refine(mut camera: Camera, board: &[(f64, f64)], views: &[Vec<(f64, f64)>]) -> Camera {
+    let cost =
+        |camera: &Camera| -> f64 { camera.residuals(board, views).iter().map(|r| r * r).sum() };
+    let n = 3 + 6 * camera.poses.len();
+    let mut current = cost(&camera);
+    let mut damping = 1e-3;
+    for _ in 0..REFINE_ITERATIONS {
+        let residuals = camera.residuals(board, views);
+        let jacobian: Vec<Vec<f64>> = (0..n)
+            .map(|i| {
+                let eps = match i {
+                    0 => 1e-7 * camera.focal.abs().max(1.0),
+                    _ => 1e-7,
+                };
+                let mut step = vec![0.0; n];
+                step[i] = eps;
+                let plus = camera.moved(&step).residuals(board, views);
+                step[i] = -eps;
+                let minus = camera.moved(&step).residuals(board, views);
+                plus.iter()
+                    .zip(minus)
+                    .map(|(p, m)| (p - m) / (2.0 * eps))
+                    .collect()
+            })
+            .collect();
+        let dot = |a: &[f64], b: &[f64]| a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>();
+        let jtj: Vec<Vec<f64>> = jacobian
+            .iter()
+            .map(|a| jacobian.iter().map(|b| dot(a, b)).collect())
+            .collect();
+        let gradient: Vec<f64> = jacobian.iter().map(|a| -dot(a, &residuals)).collect();
+
+        // Damp more until the step lowers the cost.
+        let mut improved = false;
+        while damping < 1e10 {
+            let mut damped = jtj.clone();
+            for (i, row) in damped.iter_mut().enumerate() {
+                row[i] *= 1.0 + damping;
+            }
+            let Some(step) = solve(damped, gradient.clone()) else {
+                damping *= 10.0;
+                continue;
+            };
+            let moved = camera.moved(&step);
+            let next = cost(&moved);
+            if next < current {
+                improved = current - next > current * 1e-12;
+                camera = moved;
+                current = next;
+                damping /= 10.0;
+                break;
+            }
+            damping *= 10.0;
+        }
+        if !improved {
+            break;
+        }
+    }
+    camera
+}
+
+/// Computes the intrinsics from the corners of the views of a board of `cols` * `rows` inner
+/// corners, found in the frames of `width` * `height` px.
+pub fn calibrate(
+    views: &[Vec<(f32, f32)>],
+    cols: usize,
+    rows: usize,
+    width: u32,
+    height: u32,
+) -> Result<Intrinsics, Box<dyn std::error::Error>> {
+    let views: Vec<&Vec<(f32, f32)>> = views.iter().filter(|v| v.len() == cols * rows).collect();
+    if views.len() < 3 {
+        return Err("Not enough views of the checkerboard.".into());
+    }
+    // Board coordinates in squares around the center, and image coordinates around the principal
+    // point scaled by the size of the frame, for the conditioning.
+    let board: Vec<(f64, f64)> = (0..rows)
+        .flat_map(|j| (0..cols).map(move |i| (i as f64, j as f64)))
+        .map(|(i, j)| (i - (cols - 1) as f64 / 2.0, j - (rows - 1) as f64 / 2.0))
+        .collect();
+    let scale = width.max(height) as f64;
+    let center = (width as f32 / 2.0, height as f32 / 2.0);
+    let normalized: Vec<Vec<(f64, f64)>> = views
+        .iter()
+        .map(|view| {
+            view.iter()
+                .map(|(u, v)| ((u - center.0) as f64 / scale, (v - center.1) as f64 / scale))
+                .collect()
+        })
+        .collect();
+    let homographies = normalized
+        .iter()
+        .map(|view| homography(&board, view))
+        .collect::<Option<Vec<[f64; 9]>>>()
+        .ok_or("Can't estimate the homography.")?;
+
+    // The axes of the board are orthogonal and of the same length: with B = diag(a, a, 1)
+    // where a = 1 / f^2, h1' B h2 = 0 and h1' B h1 = h2' B h2.
+    let mut equations = vec![];
+    for h in homographies.iter() {
+        let (h1, h2) = ([h[0], h[3], h[6]], [h[1], h[4], h[7]]);
+        equations.push((vec![h1[0] * h2[0] + h1[1] * h2[1]], -h1[2] * h2[2]));
+        equations.push((
+            vec![h1[0] * h1[0] + h1[1] * h1[1] - h2[0] * h2[0] - h2[1] * h2[1]],
+            -(h1[2] * h1[2] - h2[2] * h2[2]),
+        ));
+    }
+    let a = least_squares(&equations).ok_or("Can't estimate the focal length.")?[0];
+    if a <= 0.0 {
+        return Err("Can't estimate the focal length. Tilt the checkerboard more.".into());
+    }
+    let focal = 1.0 / a.sqrt();
+
+    // Refine all from no distortion.
+    let camera = Camera {
+        focal,
+        k1: 0.0,
+        k2: 0.0,
+        poses: homographies
+            .iter()
+            .map(|h| Pose::from_homography(h, focal))
+            .collect(),
+    };
+    let camera = refine(camera, &board, &normalized);
+    let residuals = camera.residuals(&board, &normalized);
+    let error = (residuals.iter().map(|r| r * r).sum::<f64>() * 2.0 / residuals.len() as f64)
+        .sqrt()
+        * scale;
+    if !camera.focal.is_finite() || camera.focal <= 0.0 {
+        return Err("Can't estimate the focal length. Tilt the checkerboard more.".into());
+    }
+    Ok(Intrinsics {
+        width,
+        height,
+        focal: (camera.focal * scale) as f32,
+        center,
+        k1: camera.k1 as f32,
+        k2: camera.k2 as f32,
+        error: error as f32,
+        calibrated_at: chrono::Utc::now().timestamp_millis(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Distorts a point in px, as the lens does.
+    fn distort(intrinsics: &Intrinsics, (u, v): (f32, f32)) -> (f32, f32) {
+        let (cx, cy) = intrinsics.center;
+        let (x, y) = ((u - cx) / intrinsics.focal, (v - cy) / intrinsics.focal);
+        let r2 = x * x + y * y;
+        let radial = 1.0 + intrinsics.k1 * r2 + intrinsics.k2 * r2 * r2;
+        (
+            cx + x * radial * intrinsics.focal,
+            cy + y * radial * intrinsics.focal,
+        )
+    }
+
+    #[test]
+    fn intrinsics_test() {
+        let truth = Intrinsics {
+            width: 1280,
+            height: 720,
+            focal: 1000.0,
+            center: (640.0, 360.0),
+            k1: -0.2,
+            k2: 0.05,
+            ..Default::default()
+        };
+        let (cols, rows) = (9, 6);
+        // A board of 0.1 m squares, tilted about both axes, 1.2 m - 1.6 m away
+        let view = |yaw: f32, pitch: f32, (tx, ty, tz): (f32, f32, f32)| -> Vec<(f32, f32)> {
+            let mut corners = vec![];
+            for j in 0..rows {
+                for i in 0..cols {
+                    let (x, y) = ((i as f32 - 4.0) * 0.1, (j as f32 - 2.5) * 0.1);
+                    // Rotate about the y axis, then the x axis.
+                    let (x, z) = (x * yaw.cos(), -x * yaw.sin());
+                    let (y, z) = (
+                        y * pitch.cos() - z * pitch.sin(),
+                        y * pitch.sin() + z * pitch.cos(),
+                    );
+                    let (x, y, z) = (x + tx, y + ty, z + tz);
+                    let ideal = (
+                        truth.center.0 + truth.focal * x / z,
+                        truth.center.1 + truth.focal * y / z,
+                    );
+                    corners.push(distort(&truth, ideal));
+                }
+            }
+            corners
+        };
+        let views = vec![
+            view(0.5, 0.1, (0.0, 0.0, 1.5)),
+            view(-0.4, 0.3, (0.2, 0.1, 1.4)),
+            view(0.2, -0.5, (-0.2, -0.1, 1.6)),
+            view(-0.3, -0.3, (0.3, -0.1, 1.2)),
+        ];
+        let intrinsics = calibrate(&views, cols, rows, 1280, 720).unwrap();
+        assert!((intrinsics.focal - truth.focal).abs() < 1.0);
+        assert!((intrinsics.k1 - truth.k1).abs() < 0.01);
+        assert!((intrinsics.k2 - truth.k2).abs() < 0.01);
+        assert!(intrinsics.error < 0.1);
+        assert!(calibrate(&views[..2], cols, rows, 1280, 720).is_err());
+
+        // Back and forth
+        let p = (1200.0, 650.0);
+        let q = truth.undistort_point(distort(&truth, p));
+        assert!((q.0 - p.0).abs() < 0.5 && (q.1 - p.1).abs() < 0.5);
+
+        // A box near the corner is distorted inward by the barrel, undistorted outward.
+        let mut dets = [Detection {
+            x1: 280,
+            y1: 280,
+            x2: 300,
+            y2: 300,
+            xc: 290.0,
+            yc: 290.0,
+            w: 20,
+            h: 20,
+            ..Default::default()
+        }];
+        truth.undistort(&mut dets, 320);
+        assert!(290.0 < dets[0].xc && 20 < dets[0].h);
+        assert_eq!(solve(vec![vec![0.0]], vec![1.0]), None);
+    }
+}
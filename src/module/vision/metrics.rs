@@ -0,0 +1,88 @@
+//! Vision Metrics
+//!
+//! The pilots act on the detections of a frame taken a while ago, driving blind in the meantime. The
+//! latency from the capture to the detections and the frames detected per second are measured in
+//! the vision thread, and sent to the pilots and to the commander in the telemetry. A latency over
+//! the limit of the config is warned of, once each time it goes over.
+
+// Weight of the latest frame in the smoothed FPS
+const SMOOTHING: f32 = 0.2;
+
+/// Metrics of the vision pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Metrics {
+    pub fps: f32,        // Frames detected per second, smoothed (0.0: only one frame yet)
+    pub latency_ms: u64, // Time from the capture to the detections of the last frame in ms
+}
+
+/// Meter of the frames detected in a row
+#[derive(Debug, Default)]
+pub struct Meter {
+    metrics: Option<Metrics>, // Metrics so far (None: no frame yet)
+    last: Option<u64>,        // Time of the last detections in ms
+    over: bool,               // Whether the latency is over the limit
+}
+
+impl Meter {
+    /// Records the frame captured and detected at the times in ms. Returns the metrics so far.
+    pub fn record(&mut self, captured: u64, detected: u64) -> Metrics {
+        let fps = self
+            .last
+            .map(|last| 1000.0 / detected.saturating_sub(last).max(1) as f32);
+        let smoothed = match (self.metrics, fps) {
+            (Some(metrics), Some(fps)) if 0.0 < metrics.fps => {
+                metrics.fps + (fps - metrics.fps) * SMOOTHING
+            }
+            (_, fps) => fps.unwrap_or_default(),
+        };
+        let metrics = Metrics {
+            fps: smoothed,
+            latency_ms: detected.saturating_sub(captured),
+        };
+        self.metrics = Some(metrics);
+        self.last = Some(detected);
+        metrics
+    }
+
+    /// Whether the latency of the last frame went over the limit in ms (Some(true)) or back under
+    /// it (Some(false)). None if neither, or the limit is 0.
+    pub fn crossed(&mut self, max_latency_ms: u64) -> Option<bool> {
+        let latency_ms = self.metrics?.latency_ms;
+        let over = 0 < max_latency_ms && max_latency_ms < latency_ms;
+        if over == self.over {
+            return None;
+        }
+        self.over = over;
+        Some(over)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn meter_test() {
+        let mut meter = Meter::default();
+        assert_eq!(meter.crossed(300), None);
+        // The first frame has no rate yet.
+        let metrics = meter.record(1000, 1150);
+        assert_eq!(metrics.fps, 0.0);
+        assert_eq!(metrics.latency_ms, 150);
+        assert_eq!(meter.crossed(300), None);
+        // 5 fps, then 10 fps smoothed
+        assert_eq!(meter.record(1100, 1350).fps, 5.0);
+        let metrics = meter.record(1300, 1450);
+        assert!((metrics.fps - 6.0).abs() < 1e-4);
+        // Over the limit once, and back under it
+        let metrics = meter.record(1450, 1850);
+        assert_eq!(metrics.latency_ms, 400);
+        assert_eq!(meter.crossed(300), Some(true));
+        assert_eq!(meter.crossed(300), None);
+        meter.record(1900, 2000);
+        assert_eq!(meter.crossed(300), Some(false));
+        // No limit
+        meter.record(2000, 2500);
+        assert_eq!(meter.crossed(0), None);
+    }
+}
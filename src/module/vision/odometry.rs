@@ -0,0 +1,291 @@
+//! Visual Odometry
+//!
+//! Without the wheel encoders, only the frames tell how far the robot has moved or turned. The
+//! ground right ahead, at the bottom of the frame, is textured by the grass and the soil, and flows
+//! through the frame as the robot moves: down as it drives forward, and sideways as it turns.
+//! The patches of the most texture are picked in the ground of the last frame, and found again in
+//! the next one by the block matching. The median of their shifts, not misled by the swaying blades
+//! or something passing by, is taken as the turn by the field of view, and as the distance by the
+//! length of the ground a px covers, which depends on the mounting of the camera.
+//!
+//! The steps are integrated into the pose since the start, which drifts as any dead reckoning does.
+
+use image::{imageops, GrayImage};
+
+use crate::module::util::conf::Odometry as OdometryConf;
+
+// Width of the ground to match in px, downscaled for speed
+const WIDTH: u32 = 160;
+// Cells of the ground to pick a patch in, across and down
+const CELLS: (u32, u32) = (8, 2);
+// Half size of a patch in px
+const PATCH: i32 = 4;
+// Farthest shift to search for in px
+const SEARCH: i32 = 12;
+// Least texture of a patch to match by, the mean absolute gradient
+const MIN_TEXTURE: f32 = 4.0;
+// Most mean absolute difference of a match
+const MAX_DIFF: f32 = 16.0;
+// Least patches matched to estimate by
+const MIN_MATCHES: usize = 4;
+
+/// Motion between two frames
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Step {
+    pub forward: f32, // Distance driven forward in m
+    pub turn: f32,    // Turn in degrees, clockwise
+}
+
+/// Pose since the start
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Pose {
+    pub x: f32,       // To the right of the start in m
+    pub y: f32,       // Ahead of the start in m
+    pub heading: f32, // From the heading at the start in degrees, clockwise, -180 to 180
+}
+
+impl Pose {
+    /// Moves by the step, along the heading halfway through the turn.
+    pub fn apply(&mut self, step: Step) {
+        let heading = (self.heading + step.turn / 2.0).to_radians();
+        self.x += step.forward * heading.sin();
+        self.y += step.forward * heading.cos();
+        self.heading = normalize(self.heading + step.turn);
+    }
+
+    /// Distance to the other pose in m.
+    pub fn distance(&self, other: &Pose) -> f32 {
+        (self.x - other.x).hypot(self.y - other.y)
+    }
+
+    /// Turn to the other pose in degrees, 0 to 180.
+    pub fn turn(&self, other: &Pose) -> f32 {
+        normalize(other.heading - self.heading).abs()
+    }
+}
+
+/// Estimator of the pose by the flow of the ground
+#[derive(Debug, Default)]
+pub struct VisualOdometry {
+    last: Option<GrayImage>, // Ground of the last frame
+    pub pose: Pose,          // Pose since the start
+}
+
+impl VisualOdometry {
+    /// Estimates the step since the last frame and moves the pose by it.
+    /// None if there is no last frame or the ground is too plain to match.
+    pub fn update(&mut self, frame: &GrayImage, conf: &OdometryConf) -> Option<Step> {
+        let ground = ground(frame, conf)?;
+        let step = self
+            .last
+            .as_ref()
+            .and_then(|last| estimate(last, &ground, conf));
+        self.last = Some(ground);
+        if let Some(step) = step {
+            self.pose.apply(step);
+        }
+        step
+    }
+
+    /// Forgets the last frame when the frames are no longer continuous, keeping the pose.
+    pub fn reset(&mut self) {
+        self.last = None;
+    }
+}
+
+/// Angle in degrees within -180 to 180.
+fn normalize(degrees: f32) -> f32 {
+    let degrees = degrees.rem_euclid(360.0);
+    match 180.0 < degrees {
+        true => degrees - 360.0,
+        false => degrees,
+    }
+}
+
+/// The ground of the frame, between the top and the bottom in ratios of the height, downscaled.
+fn ground(frame: &GrayImage, conf: &OdometryConf) -> Option<GrayImage> {
+    if frame.width() == 0 || frame.height() == 0 {
+        return None;
+    }
+    let small = match frame.width() == WIDTH {
+        true => frame.clone(),
+        false => {
+            let height = (frame.height() * WIDTH / frame.width()).max(1);
+            imageops::resize(frame, WIDTH, height, imageops::FilterType::Triangle)
+        }
+    };
+    let top = (small.height() as f32 * conf.top.clamp(0.0, 1.0)) as u32;
+    let bottom = (small.height() as f32 * conf.bottom.clamp(0.0, 1.0)) as u32;
+    if bottom <= top {
+        return None;
+    }
+    Some(imageops::crop_imm(&small, 0, top, WIDTH, bottom - top).to_image())
+}
+
+/// Luma at the px as i32.
+fn luma(img: &GrayImage, x: i32, y: i32) -> i32 {
+    img.get_pixel(x as u32, y as u32)[0] as i32
+}
+
+/// Texture of the patch around the px, the lesser of the mean absolute gradients across and down,
+/// so that it's matched in both directions.
+fn texture(img: &GrayImage, x: i32, y: i32) -> f32 {
+    let (mut gx, mut gy) = (0, 0);
+    for v in y - PATCH..=y + PATCH {
+        for u in x - PATCH..=x + PATCH {
+            gx += (luma(img, u + 1, v) - luma(img, u - 1, v)).abs();
+            gy += (luma(img, u, v + 1) - luma(img, u, v - 1)).abs();
+        }
+    }
+    let n = ((2 * PATCH + 1) * (2 * PATCH + 1)) as f32;
+    gx.min(gy) as f32 / n
+}
+
+/// The centers of the patches of the most texture, one per cell at most.
+fn features(img: &GrayImage) -> Vec<(i32, i32)> {
+    let (width, height) = (img.width() as i32, img.height() as i32);
+    let margin = PATCH + 1;
+    if width <= 2 * margin || height <= 2 * margin {
+        return vec![];
+    }
+    let (cols, rows) = (CELLS.0 as i32, CELLS.1 as i32);
+    let (cell_w, cell_h) = ((width - 2 * margin) / cols, (height - 2 * margin) / rows);
+    let mut found = vec![];
+    for row in 0..rows {
+        for col in 0..cols {
+            let best = (0..cell_h.max(1))
+                .step_by(2)
+                .flat_map(|v| (0..cell_w.max(1)).step_by(2).map(move |u| (u, v)))
+                .map(|(u, v)| (margin + col * cell_w + u, margin + row * cell_h + v))
+                .map(|(x, y)| (texture(img, x, y), (x, y)))
+                .max_by(|a, b| a.0.total_cmp(&b.0));
+            if let Some((score, point)) = best {
+                if MIN_TEXTURE <= score {
+                    found.push(point);
+                }
+            }
+        }
+    }
+    found
+}
+
+/// Shift of the patch around the px of the last frame in the next one, if matched.
+fn track(last: &GrayImage, next: &GrayImage, (x, y): (i32, i32)) -> Option<(i32, i32)> {
+    let (width, height) = (next.width() as i32, next.height() as i32);
+    let (mut min, mut best) = (i32::MAX, None);
+    for dy in -SEARCH..=SEARCH {
+        for dx in -SEARCH..=SEARCH {
+            let (cx, cy) = (x + dx, y + dy);
+            if cx < PATCH || cy < PATCH || width <= cx + PATCH || height <= cy + PATCH {
+                continue;
+            }
+            let mut sad = 0;
+            for v in -PATCH..=PATCH {
+                for u in -PATCH..=PATCH {
+                    sad += (luma(last, x + u, y + v) - luma(next, cx + u, cy + v)).abs();
+                }
+            }
+            if sad < min {
+                (min, best) = (sad, Some((dx, dy)));
+            }
+        }
+    }
+    let n = ((2 * PATCH + 1) * (2 * PATCH + 1)) as f32;
+    best.filter(|_| min as f32 / n <= MAX_DIFF)
+}
+
+/// Median of the values, which aren't empty.
+fn median(mut values: Vec<f32>) -> f32 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    match values.len() % 2 {
+        0 => (values[mid - 1] + values[mid]) / 2.0,
+        _ => values[mid],
+    }
+}
+
+/// Estimates the step between the grounds of two frames by the median flow of their patches.
+fn estimate(last: &GrayImage, next: &GrayImage, conf: &OdometryConf) -> Option<Step> {
+    if last.dimensions() != next.dimensions() {
+        return None;
+    }
+    let shifts: Vec<(i32, i32)> = features(last)
+        .into_iter()
+        .filter_map(|point| track(last, next, point))
+        .collect();
+    if shifts.len() < MIN_MATCHES {
+        return None;
+    }
+    let dx = median(shifts.iter().map(|s| s.0 as f32).collect());
+    let dy = median(shifts.iter().map(|s| s.1 as f32).collect());
+    // The ground flows to the left as the robot turns to the right, and down as it drives forward.
+    Some(Step {
+        forward: dy * conf.meters_per_px,
+        turn: -dx * conf.hfov / WIDTH as f32,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    // Ground textured at random
+    fn texture_at(x: i32, y: i32) -> u8 {
+        let mut h = (x as u32)
+            .wrapping_mul(374_761_393)
+            .wrapping_add((y as u32).wrapping_mul(668_265_263));
+        h = (h ^ (h >> 13)).wrapping_mul(1_274_126_177);
+        (h ^ (h >> 16)) as u8
+    }
+
+    #[test]
+    fn odometry_test() {
+        let conf = OdometryConf {
+            top: 0.5,
+            bottom: 1.0,
+            hfov: 64.0,
+            meters_per_px: 0.01,
+            ..Default::default()
+        };
+        let frame = |dx: i32, dy: i32| {
+            GrayImage::from_fn(WIDTH, 120, |x, y| {
+                Luma([texture_at(x as i32 - dx, y as i32 - dy)])
+            })
+        };
+        let mut odometry = VisualOdometry::default();
+        assert_eq!(odometry.update(&frame(0, 0), &conf), None);
+        // Driven forward and turned to the right
+        let step = odometry.update(&frame(-5, 3), &conf).unwrap();
+        assert!((step.forward - 0.03).abs() < 1e-6);
+        assert!((step.turn - 2.0).abs() < 1e-6);
+        assert_eq!(odometry.pose.heading, step.turn);
+        // Standing still
+        let step = odometry.update(&frame(-5, 3), &conf).unwrap();
+        assert_eq!(step, Step::default());
+        // Too plain to match
+        let plain = GrayImage::from_pixel(WIDTH, 120, Luma([128]));
+        assert_eq!(odometry.update(&plain, &conf), None);
+        odometry.reset();
+        assert_eq!(odometry.update(&frame(0, 0), &conf), None);
+    }
+
+    #[test]
+    fn pose_test() {
+        let mut pose = Pose::default();
+        pose.apply(Step {
+            forward: 1.0,
+            turn: 90.0,
+        });
+        assert!((pose.x - 0.5f32.sqrt()).abs() < 1e-6);
+        assert!((pose.y - 0.5f32.sqrt()).abs() < 1e-6);
+        assert_eq!(pose.heading, 90.0);
+        pose.apply(Step {
+            forward: 0.0,
+            turn: 120.0,
+        });
+        assert_eq!(pose.heading, -150.0);
+        assert_eq!(Pose::default().turn(&pose), 150.0);
+        assert!((Pose::default().distance(&pose) - 1.0).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,401 @@
+//! Debug Overlay
+//!
+//! Why a pilot turned, stopped or went for another pylon is hard to tell in the field afterwards.
+//! The frame is drawn with the detections, the target chosen among them, the movement commanded
+//! and the values of the state, and kept in `debug/` and streamed at `/debug` at a low rate:
+//!
+//! +--------------------------+
+//! | MODE FILL                |  <- The values of the state
+//! | MOVE LEFT                |
+//! |      [ ]      [#]        |  <- The detections, and the target in white with a line to it
+//! |             \  |         |
+//! |            <-- ^         |  <- The movement commanded
+//! +--------------------------+
+
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use image::{Rgb, RgbImage};
+
+use super::{camera, detector::Detection, stream::Feed, stream::StreamServer};
+use crate::module::{define, device::Movement, pilot::RoktrackState, util::init::RoktrackProperty};
+
+// Colors of the boxes by class, cycled
+const COLORS: [[u8; 3]; 7] = [
+    [255, 64, 0],
+    [0, 160, 255],
+    [255, 0, 160],
+    [255, 255, 0],
+    [160, 160, 160],
+    [0, 255, 160],
+    [0, 128, 0],
+];
+// Color of the target, the movement and the text
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+// Color behind the text
+const SHADE: Rgb<u8> = Rgb([0, 0, 0]);
+// Size of a glyph of the font in dots
+const GLYPH: (u32, u32) = (3, 5);
+
+/// Glyph of the character in rows of 3 dots, capitalized. The unknown ones are shown as `?`.
+fn glyph(c: char) -> [u8; 5] {
+    match c.to_ascii_uppercase() {
+        '0' => [0b111, 0b101, 0b101, 0b101, 0b111],
+        '1' => [0b010, 0b110, 0b010, 0b010, 0b111],
+        '2' => [0b111, 0b001, 0b111, 0b100, 0b111],
+        '3' => [0b111, 0b001, 0b111, 0b001, 0b111],
+        '4' => [0b101, 0b101, 0b111, 0b001, 0b001],
+        '5' => [0b111, 0b100, 0b111, 0b001, 0b111],
+        '6' => [0b111, 0b100, 0b111, 0b101, 0b111],
+        '7' => [0b111, 0b001, 0b001, 0b001, 0b001],
+        '8' => [0b111, 0b101, 0b111, 0b101, 0b111],
+        '9' => [0b111, 0b101, 0b111, 0b001, 0b111],
+        'A' => [0b010, 0b101, 0b111, 0b101, 0b101],
+        'B' => [0b110, 0b101, 0b110, 0b101, 0b110],
+        'C' => [0b011, 0b100, 0b100, 0b100, 0b011],
+        'D' => [0b110, 0b101, 0b101, 0b101, 0b110],
+        'E' => [0b111, 0b100, 0b110, 0b100, 0b111],
+        'F' => [0b111, 0b100, 0b110, 0b100, 0b100],
+        'G' => [0b011, 0b100, 0b101, 0b101, 0b011],
+        'H' => [0b101, 0b101, 0b111, 0b101, 0b101],
+        'I' => [0b111, 0b010, 0b010, 0b010, 0b111],
+        'J' => [0b001, 0b001, 0b001, 0b101, 0b010],
+        'K' => [0b101, 0b101, 0b110, 0b101, 0b101],
+        'L' => [0b100, 0b100, 0b100, 0b100, 0b111],
+        'M' => [0b101, 0b111, 0b111, 0b101, 0b101],
+        'N' => [0b110, 0b101, 0b101, 0b101, 0b101],
+        'O' => [0b010, 0b101, 0b101, 0b101, 0b010],
+        'P' => [0b110, 0b101, 0b110, 0b100, 0b100],
+        'Q' => [0b010, 0b101, 0b101, 0b110, 0b011],
+        'R' => [0b110, 0b101, 0b110, 0b101, 0b101],
+        'S' => [0b011, 0b100, 0b010, 0b001, 0b110],
+        'T' => [0b111, 0b010, 0b010, 0b010, 0b010],
+        'U' => [0b101, 0b101, 0b101, 0b101, 0b111],
+        'V' => [0b101, 0b101, 0b101, 0b101, 0b010],
+        'W' => [0b101, 0b101, 0b111, 0b111, 0b101],
+        'X' => [0b101, 0b101, 0b010, 0b101, 0b101],
+        'Y' => [0b101, 0b101, 0b010, 0b010, 0b010],
+        'Z' => [0b111, 0b001, 0b010, 0b100, 0b111],
+        '.' => [0b000, 0b000, 0b000, 0b000, 0b010],
+        ':' => [0b000, 0b010, 0b000, 0b010, 0b000],
+        '/' => [0b001, 0b001, 0b010, 0b100, 0b100],
+        '-' => [0b000, 0b000, 0b111, 0b000, 0b000],
+        '%' => [0b101, 0b001, 0b010, 0b100, 0b101],
+        '_' => [0b000, 0b000, 0b000, 0b000, 0b111],
+        ' ' => [0b000; 5],
+        _ => [0b111, 0b001, 0b010, 0b000, 0b010],
+    }
+}
+
+/// Fills the rectangle, clipped by the frame.
+pub fn fill_rect(img: &mut RgbImage, x: u32, y: u32, width: u32, height: u32, color: Rgb<u8>) {
+    let (w, h) = img.dimensions();
+    for v in y..(y + height).min(h) {
+        for u in x..(x + width).min(w) {
+            img.put_pixel(u, v, color);
+        }
+    }
+}
+
+/// Draws the line, clipped by the frame.
+pub fn draw_line(img: &mut RgbImage, from: (i32, i32), to: (i32, i32), color: Rgb<u8>) {
+    let (w, h) = img.dimensions();
+    let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).max(1);
+    for i in 0..=steps {
+        let x = from.0 + (to.0 - from.0) * i / steps;
+        let y = from.1 + (to.1 - from.1) * i / steps;
+        if 0 <= x && 0 <= y && (x as u32) < w && (y as u32) < h {
+            img.put_pixel(x as u32, y as u32, color);
+        }
+    }
+}
+
+/// Draws the box of the corners in the frame, `thickness` px inward.
+pub fn draw_box(
+    img: &mut RgbImage,
+    p1: (u32, u32),
+    p2: (u32, u32),
+    thickness: u32,
+    color: Rgb<u8>,
+) {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    let (x1, x2) = (p1.0.min(width - 1), p2.0.min(width - 1));
+    let (y1, y2) = (p1.1.min(height - 1), p2.1.min(height - 1));
+    for t in 0..thickness {
+        for x in x1..=x2 {
+            img.put_pixel(x, (y1 + t).min(y2), color);
+            img.put_pixel(x, y2.saturating_sub(t).max(y1), color);
+        }
+        for y in y1..=y2 {
+            img.put_pixel((x1 + t).min(x2), y, color);
+            img.put_pixel(x2.saturating_sub(t).max(x1), y, color);
+        }
+    }
+}
+
+/// Draws the text from the top left, at `scale` px a dot.
+pub fn draw_text(img: &mut RgbImage, x: u32, y: u32, text: &str, scale: u32, color: Rgb<u8>) {
+    for (i, c) in text.chars().enumerate() {
+        let left = x + i as u32 * (GLYPH.0 + 1) * scale;
+        for (row, bits) in glyph(c).iter().enumerate() {
+            for col in 0..GLYPH.0 {
+                if bits >> (GLYPH.0 - 1 - col) & 1 == 1 {
+                    let (u, v) = (left + col * scale, y + row as u32 * scale);
+                    fill_rect(img, u, v, scale, scale, color);
+                }
+            }
+        }
+    }
+}
+
+/// The corners of the detection, in the input of the size `imgsz`, in the frame.
+fn corners(img: &RgbImage, det: &Detection, imgsz: u32) -> ((u32, u32), (u32, u32)) {
+    let sx = img.width() as f32 / imgsz as f32;
+    let sy = img.height() as f32 / imgsz as f32;
+    (
+        ((det.x1 as f32 * sx) as u32, (det.y1 as f32 * sy) as u32),
+        ((det.x2 as f32 * sx) as u32, (det.y2 as f32 * sy) as u32),
+    )
+}
+
+/// Draws the boxes of the detections of the front camera, in the input of the size `imgsz`, on the
+/// frame, colored by the class.
+pub fn boxes(img: &mut RgbImage, dets: &[Detection], imgsz: u32) {
+    if imgsz == 0 {
+        return;
+    }
+    let thickness = (img.width() / 320).max(1);
+    for det in dets.iter().filter(|det| det.camera == camera::FRONT) {
+        let color = Rgb(COLORS[det.cls as usize % COLORS.len()]);
+        let (p1, p2) = corners(img, det, imgsz);
+        draw_box(img, p1, p2, thickness, color);
+    }
+}
+
+/// Draws the decision of the pilot on the frame: the detections, the target, the movement and the
+/// lines of the values of the state.
+pub fn render(
+    img: &mut RgbImage,
+    dets: &[Detection],
+    target: Option<&Detection>,
+    movement: Movement,
+    lines: &[String],
+    imgsz: u32,
+) {
+    let (width, height) = img.dimensions();
+    if width == 0 || height == 0 || imgsz == 0 {
+        return;
+    }
+    boxes(img, dets, imgsz);
+    let scale = (width / 160).max(1);
+    let bottom = (width as i32 / 2, height as i32 - 1);
+    // The target, and the way to it from the robot
+    if let Some(target) = target {
+        let (p1, p2) = corners(img, target, imgsz);
+        draw_box(img, p1, p2, scale, WHITE);
+        let center = (((p1.0 + p2.0) / 2) as i32, p2.1 as i32);
+        draw_line(img, bottom, center, WHITE);
+    }
+    // The movement, as an arrow from the bottom center
+    let len = (height / 8) as i32;
+    let tip = match movement {
+        Movement::Forward => Some((bottom.0, bottom.1 - len)),
+        Movement::Backward => Some((bottom.0, bottom.1 - len / 4)),
+        Movement::Left => Some((bottom.0 - len, bottom.1 - len / 2)),
+        Movement::Right => Some((bottom.0 + len, bottom.1 - len / 2)),
+        Movement::Stop => None,
+    };
+    if let Some(tip) = tip {
+        let from = (bottom.0, bottom.1 - len / 2);
+        for d in 0..scale as i32 {
+            draw_line(img, (from.0 + d, from.1 + d), (tip.0 + d, tip.1 + d), WHITE);
+        }
+        let head = 3 * scale;
+        let (x, y) = (
+            (tip.0 - head as i32 / 2).max(0),
+            (tip.1 - head as i32 / 2).max(0),
+        );
+        fill_rect(img, x as u32, y as u32, head, head, WHITE);
+    }
+    // The values, on the shade to read on the grass
+    let line_height = (GLYPH.1 + 2) * scale;
+    for (i, line) in lines.iter().enumerate() {
+        let y = scale + i as u32 * line_height;
+        let w = line.chars().count() as u32 * (GLYPH.0 + 1) * scale + scale;
+        fill_rect(
+            img,
+            0,
+            y.saturating_sub(scale),
+            w + scale,
+            line_height,
+            SHADE,
+        );
+        draw_text(img, scale, y, line, scale, WHITE);
+    }
+}
+
+/// The values of the state the pilots decide by, a line each.
+pub fn lines(state: &RoktrackState, movement: Movement) -> Vec<String> {
+    let mut lines = vec![
+        format!("MODE {:?}", state.mode),
+        format!("MOVE {:?}", movement),
+        format!("REST {:.2} PHASE {:?}", state.rest, state.phase),
+        format!("TURNS {} LAPS {}", state.turn_count, state.laps),
+    ];
+    if let Some(target) = &state.target {
+        lines.push(format!("TARGET H {}/{}", target.h, state.target_height));
+    }
+    if let Some(depth) = state.depth_ahead {
+        lines.push(format!("DEPTH {:.2}M", depth));
+    }
+    if let Some(id) = state.marker_id {
+        lines.push(format!("MARKER ID {}", id));
+    }
+    lines.push(format!("TEMP {:.1}", state.pi_temp));
+    lines
+}
+
+/// Keeper of the debug frames, at a low rate
+#[derive(Debug, Default)]
+pub struct DebugOverlay {
+    last: Option<Instant>, // Time of the last frame drawn
+}
+
+impl DebugOverlay {
+    /// Draws the decision on the last frame, and keeps it and streams it, once per interval.
+    pub fn update(
+        &mut self,
+        property: &RoktrackProperty,
+        state: &RoktrackState,
+        dets: &[Detection],
+        movement: Movement,
+        stream: Option<&StreamServer>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let conf = &property.conf.debug_overlay;
+        let watched = stream.is_some_and(|stream| stream.watched(Feed::Debug));
+        if !conf.save && !watched {
+            return Ok(());
+        }
+        let interval = Duration::from_millis(conf.interval_ms);
+        if self.last.is_some_and(|last| last.elapsed() < interval) {
+            return Ok(());
+        }
+        self.last = Some(Instant::now());
+
+        let mut img = image::open(&property.path.img.last)?.to_rgb8();
+        // The detections are of the square input of the model, as wide as the frame.
+        render(
+            &mut img,
+            dets,
+            state.target.as_ref(),
+            movement,
+            &lines(state, movement),
+            state.img_width,
+        );
+        if conf.save {
+            let dir = Path::new(&property.path.dir.data).join(define::path::DEBUG_DIR);
+            fs::create_dir_all(&dir)?;
+            let name = Local::now().format("%Y%m%d_%H%M%S_%3f").to_string();
+            img.save(dir.join(format!("{}.jpg", name)))?;
+            prune(&dir, conf.keep)?;
+        }
+        if let Some(stream) = stream.filter(|_| watched) {
+            stream.send_image(Feed::Debug, &img)?;
+        }
+        Ok(())
+    }
+}
+
+/// Removes the oldest frames in the directory beyond the number to keep.
+fn prune(dir: &Path, keep: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut frames: Vec<_> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "jpg"))
+        .collect();
+    frames.sort();
+    let excess = frames.len().saturating_sub(keep);
+    for frame in &frames[..excess] {
+        fs::remove_file(frame)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boxes_test() {
+        let mut img = RgbImage::new(640, 480);
+        let det = Detection {
+            cls: 1,
+            x1: 80,
+            y1: 80,
+            x2: 160,
+            y2: 160,
+            ..Default::default()
+        };
+        let rear = Detection {
+            camera: camera::REAR,
+            x1: 0,
+            y1: 0,
+            x2: 40,
+            y2: 40,
+            ..Default::default()
+        };
+        boxes(&mut img, &[det, rear], 320);
+        // In the frame of 640 * 480, 2 px thick
+        let color = Rgb(COLORS[1]);
+        assert_eq!(*img.get_pixel(160, 120), color);
+        assert_eq!(*img.get_pixel(161, 121), color);
+        assert_eq!(*img.get_pixel(319, 239), color);
+        assert_eq!(*img.get_pixel(240, 120), color);
+        assert_eq!(*img.get_pixel(240, 180), Rgb([0, 0, 0]));
+        // Not of the front camera
+        assert_eq!(*img.get_pixel(0, 0), Rgb([0, 0, 0]));
+    }
+
+    #[test]
+    fn render_test() {
+        let mut img = RgbImage::new(320, 240);
+        let target = Detection {
+            x1: 200,
+            y1: 100,
+            x2: 240,
+            y2: 160,
+            ..Default::default()
+        };
+        let lines = vec![String::from("MODE FILL")];
+        render(&mut img, &[], Some(&target), Movement::Left, &lines, 320);
+        // The target in white, 2 px thick, in the frame of 320 * 240
+        assert_eq!(*img.get_pixel(200, 75), WHITE);
+        assert_eq!(*img.get_pixel(201, 76), WHITE);
+        assert_eq!(*img.get_pixel(220, 100), Rgb([0, 0, 0]));
+        // The way to it from the bottom center
+        assert_eq!(*img.get_pixel(160, 239), WHITE);
+        // The arrow to the left
+        assert_eq!(*img.get_pixel(140, 225), WHITE);
+        assert_eq!(*img.get_pixel(180, 225), Rgb([0, 0, 0]));
+        // The top left dot of `M`, 2 px a dot
+        assert_eq!(*img.get_pixel(2, 2), WHITE);
+        assert_eq!(*img.get_pixel(4, 2), SHADE);
+    }
+
+    #[test]
+    fn glyph_test() {
+        let mut img = RgbImage::new(16, 8);
+        draw_text(&mut img, 0, 0, "1a", 1, WHITE);
+        // `1` from the 2nd dot of the top row, and `A` from the 4th px
+        assert_eq!(*img.get_pixel(0, 0), Rgb([0, 0, 0]));
+        assert_eq!(*img.get_pixel(1, 0), WHITE);
+        assert_eq!(*img.get_pixel(5, 0), WHITE);
+        assert_eq!(*img.get_pixel(4, 1), WHITE);
+        assert_eq!(glyph('~'), glyph('?'));
+    }
+}
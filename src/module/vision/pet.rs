@@ -0,0 +1,145 @@
+//! Pet Registry
+//!
+//! The detector tells a dog from a deer, but not our own dog from a stray one, so the monitoring
+//! and the deterrent would chase the pets of the house around the yard. The pets are enrolled from
+//! a few photos each, cropped to the pet, in a folder per species in the data directory:
+//!
+//! <data>/pets/dog/pochi_1.jpg
+//! <data>/pets/dog/pochi_2.jpg
+//! <data>/pets/cat/tama.jpg
+//!
+//! An animal of an enrolled species whose appearance is like any of the photos (see `reid`) is a
+//! pet, and the rest, of any species, are wild.
+
+use std::fs;
+use std::path::Path;
+
+use image::RgbImage;
+
+use super::{
+    detector::{AnimalClasses, Detection},
+    reid::{self, Embedding},
+};
+
+/// Pets enrolled from the photos
+#[derive(Debug, Clone, Default)]
+pub struct Pets {
+    photos: Vec<(u32, Embedding)>, // Class of the species and the appearance of each photo
+}
+
+impl Pets {
+    /// Enrolls the photos in the folders of the species in the directory.
+    pub fn load(dir: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut photos = vec![];
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let species = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or_default()
+                .to_string();
+            if !path.is_dir() {
+                continue;
+            }
+            let Some(cls) = class(&species) else {
+                log::warn!("Unknown Species of Pets: {}", path.display());
+                continue;
+            };
+            for photo in fs::read_dir(&path)? {
+                let photo = photo?.path();
+                let embedding = image::open(&photo)
+                    .ok()
+                    .and_then(|img| reid::embed_photo(&img.to_rgb8()));
+                match embedding {
+                    Some(embedding) => photos.push((cls, embedding)),
+                    None => log::warn!("Can't Enroll Pet Photo: {}", photo.display()),
+                }
+            }
+        }
+        Ok(Self { photos })
+    }
+
+    /// Number of the photos enrolled.
+    pub fn len(&self) -> usize {
+        self.photos.len()
+    }
+
+    /// Whether no photo is enrolled.
+    pub fn is_empty(&self) -> bool {
+        self.photos.is_empty()
+    }
+
+    /// Whether any pet of the species of the class is enrolled.
+    pub fn enrolled(&self, cls: u32) -> bool {
+        self.photos.iter().any(|(c, _)| *c == cls)
+    }
+
+    /// Whether the animal of the detection, in the input of the size `imgsz`, in the frame is a pet,
+    /// like any photo of its species by `similarity` or more.
+    pub fn is_pet(&self, img: &RgbImage, det: &Detection, imgsz: u32, similarity: f32) -> bool {
+        let Some(embedding) = reid::embed(img, det, imgsz) else {
+            return false;
+        };
+        self.photos
+            .iter()
+            .filter(|(cls, _)| *cls == det.cls)
+            .any(|(_, photo)| similarity <= photo.similarity(&embedding))
+    }
+}
+
+/// Class of the species by its name (e.g. `dog`).
+fn class(species: &str) -> Option<u32> {
+    (0..)
+        .map_while(AnimalClasses::from_u32)
+        .find(|animal| format!("{:?}", animal).eq_ignore_ascii_case(species))
+        .map(|animal| animal.to_u32())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn pet_test() {
+        assert_eq!(class("Dog"), Some(AnimalClasses::DOG.to_u32()));
+        assert_eq!(class("deer"), Some(AnimalClasses::DEER.to_u32()));
+        assert_eq!(class("unicorn"), None);
+
+        // A brown dog enrolled
+        let brown = RgbImage::from_pixel(60, 60, Rgb([140, 90, 40]));
+        let pets = Pets {
+            photos: vec![(
+                AnimalClasses::DOG.to_u32(),
+                reid::embed_photo(&brown).unwrap(),
+            )],
+        };
+        assert!(pets.enrolled(AnimalClasses::DOG.to_u32()));
+        assert!(!pets.enrolled(AnimalClasses::DEER.to_u32()));
+
+        // The brown dog and a white one on the left, a deer of the same brown on the right,
+        // in the frame of 640 * 480 and the input of 320 * 320
+        let mut img = RgbImage::from_pixel(640, 480, Rgb([90, 140, 60]));
+        for y in 120..360 {
+            for x in 0..640 {
+                let color = match x {
+                    0..=159 | 480.. => Rgb([140, 90, 40]),
+                    160..=319 => Rgb([230, 230, 225]),
+                    _ => continue,
+                };
+                img.put_pixel(x, y, color);
+            }
+        }
+        let det = |x1: u32, cls: AnimalClasses| Detection {
+            x1,
+            y1: 80,
+            x2: x1 + 80,
+            y2: 240,
+            cls: cls.to_u32(),
+            ..Default::default()
+        };
+        assert!(pets.is_pet(&img, &det(0, AnimalClasses::DOG), 320, 0.85));
+        assert!(!pets.is_pet(&img, &det(80, AnimalClasses::DOG), 320, 0.85));
+        assert!(!pets.is_pet(&img, &det(240, AnimalClasses::DEER), 320, 0.85));
+    }
+}
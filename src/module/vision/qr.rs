@@ -0,0 +1,178 @@
+//! QR Code Command Signs
+//!
+//! Printed QR codes give the commands without any radio link, held up in front of the camera or
+//! planted in the lawn. A sign carries `roktrack:` and the command, so that the codes on anything
+//! else in the garden are ignored:
+//!
+//! roktrack:off    <- Turn the robot off, as does 'stop', or 'pause' it (see `to_parent_msg`)
+//! roktrack:nogo   <- Don't drive beyond the sign (see `pilot::risk`)
+//!
+//! Anyone can print a sign, so the signs only ever stop the robot. They never start it or change
+//! its mode, which is left to the commander.
+//!
+//! The signs are sent as detections of `QR_CLASS` with the command as their IDs. A command is
+//! given once when its sign comes into view, and again only after it's been out of view for a while.
+
+use std::collections::HashMap;
+
+use image::GrayImage;
+
+use super::detector::Detection;
+use crate::module::com::ParentMsg;
+
+/// Class of the detections marking the signs.
+pub const QR_CLASS: u32 = 120;
+
+/// Command of the signs not to drive beyond.
+pub const NO_GO: &str = "nogo";
+
+// Prefix of the content of the signs
+const PREFIX: &str = "roktrack:";
+
+/// Command of the content of a sign, if it's a sign.
+pub fn parse(content: &str) -> Option<String> {
+    let command = content.trim().strip_prefix(PREFIX)?.trim();
+    (!command.is_empty()).then(|| command.to_lowercase())
+}
+
+/// Finds the signs in the frame, as the detections in the input of the size `imgsz`.
+pub fn detect(img: &GrayImage, imgsz: u32) -> Vec<Detection> {
+    let (w, h) = img.dimensions();
+    let (sx, sy) = (imgsz as f32 / w as f32, imgsz as f32 / h as f32);
+    let mut prepared =
+        rqrr::PreparedImage::prepare_from_greyscale(w as usize, h as usize, |x, y| {
+            img.get_pixel(x as u32, y as u32)[0]
+        });
+    let mut dets = vec![];
+    for grid in prepared.detect_grids() {
+        let content = match grid.decode() {
+            Ok((_, content)) => content,
+            Err(e) => {
+                log::debug!("QR Code Unreadable: {:?}", e);
+                continue;
+            }
+        };
+        let Some(command) = parse(&content) else {
+            log::debug!("QR Code Not A Sign: {}", content);
+            continue;
+        };
+        // The box around the corners
+        let xs: Vec<f32> = grid.bounds.iter().map(|p| p.x.max(0) as f32 * sx).collect();
+        let ys: Vec<f32> = grid.bounds.iter().map(|p| p.y.max(0) as f32 * sy).collect();
+        let x1 = xs.iter().copied().fold(f32::MAX, f32::min);
+        let x2 = xs.iter().copied().fold(0.0, f32::max);
+        let y1 = ys.iter().copied().fold(f32::MAX, f32::min);
+        let y2 = ys.iter().copied().fold(0.0, f32::max);
+        dets.push(Detection {
+            x1: x1 as u32,
+            y1: y1 as u32,
+            x2: x2 as u32,
+            y2: y2 as u32,
+            xc: (x1 + x2) / 2.0,
+            yc: (y1 + y2) / 2.0,
+            cls: QR_CLASS,
+            prob: 1.0,
+            w: (x2 - x1) as u32,
+            h: (y2 - y1) as u32,
+            ids: command.into_bytes(),
+            ..Default::default()
+        });
+    }
+    dets
+}
+
+/// Command of the detection, if it's a sign.
+pub fn command(det: &Detection) -> Option<String> {
+    (det.cls == QR_CLASS).then(|| String::from_utf8_lossy(&det.ids).to_string())
+}
+
+/// Command of the commander that a sign gives, only those stopping the robot.
+///
+/// 'stop' turns the robot off, since the Stop of the commander doesn't stop a job.
+pub fn to_parent_msg(command: &str) -> Option<ParentMsg> {
+    match command {
+        "off" | "stop" => Some(ParentMsg::Off),
+        "pause" => Some(ParentMsg::Pause),
+        _ => None,
+    }
+}
+
+/// Whether a no-go sign is close, as tall as the ratio of the input of the size `imgsz` or taller.
+pub fn no_go_close(dets: &[Detection], imgsz: u32, ratio: f32) -> bool {
+    dets.iter()
+        .any(|det| command(det).as_deref() == Some(NO_GO) && imgsz as f32 * ratio <= det.h as f32)
+}
+
+/// Signs in view, to give their commands once
+#[derive(Debug, Default)]
+pub struct Signs {
+    seen: HashMap<String, u64>, // Time each command was seen last in ms
+}
+
+impl Signs {
+    /// Observes the detections at the time in ms, and returns the commands to give: those of the
+    /// signs not seen for `gap` ms. The no-go signs aren't commands to give.
+    pub fn observe(&mut self, dets: &[Detection], now: u64, gap: u64) -> Vec<String> {
+        let mut commands = vec![];
+        for command in dets.iter().filter_map(command) {
+            if command == NO_GO {
+                continue;
+            }
+            let fresh = match self.seen.insert(command.clone(), now) {
+                Some(last) => last + gap < now,
+                None => true,
+            };
+            if fresh && !commands.contains(&command) {
+                commands.push(command);
+            }
+        }
+        self.seen.retain(|_, last| now <= *last + gap);
+        commands
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sign_test() {
+        assert_eq!(
+            parse("roktrack:perimeter_trim"),
+            Some("perimeter_trim".to_string())
+        );
+        assert_eq!(parse(" roktrack: NoGo\n"), Some("nogo".to_string()));
+        assert!(to_parent_msg("stop") == Some(ParentMsg::Off));
+        assert!(to_parent_msg("pause") == Some(ParentMsg::Pause));
+        assert!(to_parent_msg("on").is_none());
+        assert!(to_parent_msg("perimeter_trim").is_none());
+        assert_eq!(parse("roktrack:"), None);
+        assert_eq!(parse("https://example.com"), None);
+
+        let sign = |command: &str, h: u32| Detection {
+            cls: QR_CLASS,
+            h,
+            ids: command.as_bytes().to_vec(),
+            ..Default::default()
+        };
+        let pylon = Detection {
+            h: 200,
+            ..Default::default()
+        };
+        assert_eq!(command(&sign("off", 10)), Some("off".to_string()));
+        assert_eq!(command(&pylon), None);
+        assert!(no_go_close(&[pylon.clone(), sign(NO_GO, 64)], 320, 0.2));
+        assert!(!no_go_close(&[sign(NO_GO, 40)], 320, 0.2));
+        assert!(!no_go_close(&[pylon, sign("off", 200)], 320, 0.2));
+
+        // Given once while in view, and again after out of view for the gap
+        let mut signs = Signs::default();
+        let held = [sign("pause", 50), sign(NO_GO, 50)];
+        assert_eq!(signs.observe(&held, 0, 3000), vec!["pause"]);
+        assert!(signs.observe(&held, 1000, 3000).is_empty());
+        assert!(signs.observe(&[], 2000, 3000).is_empty());
+        assert!(signs.observe(&held, 4000, 3000).is_empty());
+        assert!(signs.observe(&[], 8000, 3000).is_empty());
+        assert_eq!(signs.observe(&held, 9000, 3000), vec!["pause"]);
+    }
+}
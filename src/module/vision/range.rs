@@ -0,0 +1,73 @@
+//! Monocular Distance Estimation
+//!
+//! The distance to an object of a known height follows from its height in the image by the pinhole
+//! camera model. The models take the image resized to a square, so the height is taken as a ratio
+//! to the input size and the vertical field of view of the camera.
+//!
+//! distance = height / (2 * tan(vfov / 2) * h / imgsz)
+//!
+//! Only the classes of the pylon models have known heights. A box cut off by the edge of the image
+//! is lower than the object, so the distance is overestimated then.
+
+use super::detector::{Detection, RoktrackClasses};
+use crate::module::util::conf::Range as RangeConf;
+
+/// Distance in m to an object of the height in m, seen `h` high in the input of the size `imgsz`.
+pub fn distance(h: u32, imgsz: u32, height: f32, vfov: f32) -> Option<f32> {
+    if h == 0 || imgsz == 0 || height <= 0.0 {
+        return None;
+    }
+    let view = 2.0 * (vfov.to_radians() / 2.0).tan() * h as f32 / imgsz as f32;
+    Some(height / view)
+}
+
+/// Height in m of the class of the pylon models, if known.
+fn height(cls: u32, conf: &RangeConf) -> f32 {
+    match RoktrackClasses::from_u32(cls) {
+        Some(RoktrackClasses::PYLON) => conf.pylon,
+        Some(RoktrackClasses::PERSON) => conf.person,
+        Some(RoktrackClasses::ROKTRACK) => conf.roktrack,
+        _ => 0.0,
+    }
+}
+
+/// Annotates the detections of the pylon models with the estimated distances.
+pub fn annotate(dets: &mut [Detection], imgsz: u32, conf: &RangeConf) {
+    for det in dets.iter_mut() {
+        det.distance = distance(det.h, imgsz, height(det.cls, conf), conf.vfov);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn range_test() {
+        let conf = RangeConf::default();
+        // A pylon filling the view vertically
+        let full = 2.0 * (conf.vfov.to_radians() / 2.0).tan();
+        let d = distance(320, 320, full, conf.vfov).unwrap();
+        assert!((d - 1.0).abs() < 1e-4);
+        // Half as high, twice as far
+        let d = distance(160, 320, full, conf.vfov).unwrap();
+        assert!((d - 2.0).abs() < 1e-4);
+        assert_eq!(distance(0, 320, 0.45, conf.vfov), None);
+
+        let mut dets = [
+            Detection {
+                cls: RoktrackClasses::PYLON.to_u32(),
+                h: 40,
+                ..Default::default()
+            },
+            Detection {
+                cls: RoktrackClasses::OBSTACLE.to_u32(),
+                h: 40,
+                ..Default::default()
+            },
+        ];
+        annotate(&mut dets, 320, &conf);
+        assert!(dets[0].distance.unwrap() > 1.0);
+        assert_eq!(dets[1].distance, None);
+    }
+}
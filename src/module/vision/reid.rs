@@ -0,0 +1,232 @@
+//! Person Re-identification
+//!
+//! A gardener working in the yard is detected in every frame for hours, and shouldn't be alerted
+//! again and again. Each person is described by the appearance, the colors of the clothes on the
+//! upper and the lower body, and compared with the visitors seen lately. Only a person unlike any
+//! of them is a new visit to alert; a visit ends once its visitor hasn't been seen for a while.
+//!
+//! The colors are of the chromaticity, the ratios of R and G to the sum, which is the same in the sun
+//! and in the shade, along with the brightness in coarse bins shared by the neighbors, so that the
+//! black and the white clothes are told apart while a cloud passing over isn't a new visit.
+
+use image::RgbImage;
+
+use super::detector::Detection;
+
+// Bins of each ratio of the chromaticity
+const CHROMA_BINS: usize = 6;
+// Bins of the brightness
+const BRIGHTNESS_BINS: usize = 4;
+// Ratio of the box trimmed on the left and the right, the background around the body
+const TRIM: f32 = 0.15;
+// Least size of the box in px of the frame to describe
+const MIN_SIZE: u32 = 8;
+// Weight of the latest appearance of a visitor
+const LEARNING_RATE: f32 = 0.1;
+
+/// Appearance of a person, normalized
+#[derive(Debug, Clone, PartialEq)]
+pub struct Embedding(Vec<f32>);
+
+impl Embedding {
+    /// Cosine similarity to the other, 0.0 (unlike) to 1.0 (alike).
+    pub fn similarity(&self, other: &Embedding) -> f32 {
+        self.0.iter().zip(&other.0).map(|(a, b)| a * b).sum()
+    }
+
+    /// Moves toward the other by the rate.
+    fn blend(&mut self, other: &Embedding, rate: f32) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a = *a * (1.0 - rate) + b * rate;
+        }
+        normalize(&mut self.0);
+    }
+}
+
+/// Scales the vector to the unit length.
+fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if 0.0 < norm {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+/// Histograms of the chromaticity and of the brightness of the region of the frame, each
+/// normalized.
+fn histogram(img: &RgbImage, x1: u32, y1: u32, x2: u32, y2: u32) -> Vec<f32> {
+    let mut chroma = vec![0.0; CHROMA_BINS * CHROMA_BINS];
+    let mut brightness = vec![0.0; BRIGHTNESS_BINS];
+    let bin = |v: f32| ((v * CHROMA_BINS as f32) as usize).min(CHROMA_BINS - 1);
+    for y in y1..y2 {
+        for x in x1..x2 {
+            let [r, g, b] = img.get_pixel(x, y).0.map(|c| c as f32);
+            let sum = r + g + b;
+            if 0.0 < sum {
+                chroma[bin(r / sum) * CHROMA_BINS + bin(g / sum)] += 1.0;
+            }
+            // Shared by the two bins around, by the distance to their centers
+            let pos = (sum / 765.0 * BRIGHTNESS_BINS as f32 - 0.5)
+                .clamp(0.0, (BRIGHTNESS_BINS - 1) as f32);
+            let lower = (pos as usize).min(BRIGHTNESS_BINS - 2);
+            let frac = pos - lower as f32;
+            brightness[lower] += 1.0 - frac;
+            brightness[lower + 1] += frac;
+        }
+    }
+    normalize(&mut chroma);
+    normalize(&mut brightness);
+    chroma.extend(brightness);
+    chroma
+}
+
+/// Describes the person of the detection, in the input of the size `imgsz`, in the frame.
+/// None if the box is too small to tell.
+pub fn embed(img: &RgbImage, det: &Detection, imgsz: u32) -> Option<Embedding> {
+    let (width, height) = img.dimensions();
+    if imgsz == 0 {
+        return None;
+    }
+    let sx = width as f32 / imgsz as f32;
+    let sy = height as f32 / imgsz as f32;
+    describe(
+        img,
+        det.x1 as f32 * sx,
+        det.y1 as f32 * sy,
+        det.x2 as f32 * sx,
+        det.y2 as f32 * sy,
+    )
+}
+
+/// Describes the whole photo, e.g. of a pet cropped to it.
+pub fn embed_photo(img: &RgbImage) -> Option<Embedding> {
+    let (width, height) = img.dimensions();
+    describe(img, 0.0, 0.0, width as f32, height as f32)
+}
+
+/// Describes the region of the frame, trimmed on the sides.
+fn describe(img: &RgbImage, x1: f32, y1: f32, x2: f32, y2: f32) -> Option<Embedding> {
+    let (width, height) = img.dimensions();
+    let trim = (x2 - x1) * TRIM;
+    let x1 = ((x1 + trim) as u32).min(width);
+    let x2 = ((x2 - trim) as u32).min(width);
+    let y1 = (y1 as u32).min(height);
+    let y2 = (y2 as u32).min(height);
+    if x2 < x1 + MIN_SIZE || y2 < y1 + MIN_SIZE {
+        return None;
+    }
+    // The upper and the lower body, each as heavy
+    let middle = (y1 + y2) / 2;
+    let mut v = histogram(img, x1, y1, x2, middle);
+    v.extend(histogram(img, x1, middle, x2, y2));
+    normalize(&mut v);
+    Some(Embedding(v))
+}
+
+/// A person seen lately
+#[derive(Debug, Clone)]
+struct Visitor {
+    embedding: Embedding, // Appearance, following the latest
+    last_seen: u64,       // Time seen last in ms
+}
+
+/// Visitors seen lately
+#[derive(Debug, Clone, Default)]
+pub struct Gallery {
+    visitors: Vec<Visitor>,
+}
+
+impl Gallery {
+    /// Observes the person at the time in ms. Returns whether the person is a new visit, unlike
+    /// any visitor seen within `visit_gap` seconds by `similarity` or more.
+    pub fn observe(
+        &mut self,
+        embedding: Embedding,
+        now: u64,
+        similarity: f32,
+        visit_gap: u64,
+    ) -> bool {
+        self.visitors
+            .retain(|visitor| now.saturating_sub(visitor.last_seen) <= visit_gap * 1000);
+        let best = self
+            .visitors
+            .iter_mut()
+            .map(|visitor| (visitor.embedding.similarity(&embedding), visitor))
+            .filter(|(s, _)| similarity <= *s)
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        match best {
+            Some((_, visitor)) => {
+                visitor.embedding.blend(&embedding, LEARNING_RATE);
+                visitor.last_seen = now;
+                false
+            }
+            None => {
+                self.visitors.push(Visitor {
+                    embedding,
+                    last_seen: now,
+                });
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    #[test]
+    fn reid_test() {
+        // A person in a red shirt and blue pants, then in the shade, and one in green and gray,
+        // side by side in the frame of 640 * 480
+        let mut img = RgbImage::from_pixel(640, 480, Rgb([90, 140, 60]));
+        let mut dress = |x0: u32, upper: [u8; 3], lower: [u8; 3]| {
+            for y in 81..399 {
+                for x in x0..x0 + 80 {
+                    img.put_pixel(x, y, Rgb(if y < 240 { upper } else { lower }));
+                }
+            }
+        };
+        dress(40, [200, 40, 40], [40, 50, 150]);
+        dress(240, [150, 30, 30], [30, 38, 112]);
+        dress(440, [40, 160, 60], [128, 128, 128]);
+        // In the input of 320 * 320
+        let det = |x: u32| Detection {
+            x1: x / 2,
+            y1: 54,
+            x2: x / 2 + 40,
+            y2: 266,
+            ..Default::default()
+        };
+        let red = embed(&img, &det(40), 320).unwrap();
+        let shade = embed(&img, &det(240), 320).unwrap();
+        let green = embed(&img, &det(440), 320).unwrap();
+        assert!((red.similarity(&red) - 1.0).abs() < 1e-5);
+        assert!(0.8 < red.similarity(&shade));
+        assert!(red.similarity(&green) < 0.5);
+        // Too small to tell
+        let tiny = Detection {
+            x2: 4,
+            y2: 4,
+            ..Default::default()
+        };
+        assert_eq!(embed(&img, &tiny, 320), None);
+        // The photo cropped to the person
+        let mut photo = RgbImage::from_pixel(80, 318, Rgb([40, 50, 150]));
+        for y in 0..159 {
+            for x in 0..80 {
+                photo.put_pixel(x, y, Rgb([200, 40, 40]));
+            }
+        }
+        assert!(0.99 < embed_photo(&photo).unwrap().similarity(&red));
+
+        // A visit for each, until unseen for the gap
+        let mut gallery = Gallery::default();
+        assert!(gallery.observe(red.clone(), 0, 0.8, 300));
+        assert!(!gallery.observe(shade.clone(), 60_000, 0.8, 300));
+        assert!(gallery.observe(green.clone(), 120_000, 0.8, 300));
+        assert!(!gallery.observe(red.clone(), 350_000, 0.8, 300));
+        assert!(gallery.observe(green, 500_000, 0.8, 300));
+        assert!(!gallery.observe(red, 600_000, 0.8, 300));
+    }
+}
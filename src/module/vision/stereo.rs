@@ -0,0 +1,156 @@
+//! Stereo Depth
+//!
+//! With a second camera on the right of the first, facing the same way at the `baseline`, the
+//! distance to a point follows from how far it shifts between the frames (the disparity):
+//!
+//! depth = focal * baseline / disparity
+//!
+//! The frames are taken as rectified, i.e. the rows of the cameras aligned, which holds for two
+//! cameras of the same model mounted parallel on a rigid bar. The disparity is found by matching
+//! the blocks along the rows, in the frames scaled down to `MAX_WIDTH` for the speed. The blocks
+//! without texture, which match anywhere, and those at the left edge, which the right camera
+//! doesn't see, are left unknown.
+
+use image::{imageops, GrayImage};
+
+use super::depth::DepthMap;
+use crate::module::util::conf::Stereo as StereoConf;
+
+// Width of the frames to match in px. Larger ones are scaled down.
+const MAX_WIDTH: u32 = 320;
+// Ratio of the cost of the best match to the second best over which the match is ambiguous
+const UNIQUENESS: f32 = 0.85;
+
+/// Sums of the blocks of the side `2 * radius + 1` around each pixel, clipped at the edges.
+fn box_sum(values: &[u32], w: usize, h: usize, radius: usize) -> Vec<u32> {
+    let mut integral = vec![0_u32; (w + 1) * (h + 1)];
+    for y in 0..h {
+        let mut row = 0;
+        for x in 0..w {
+            row += values[y * w + x];
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row;
+        }
+    }
+    let mut sums = vec![0; w * h];
+    for y in 0..h {
+        let (y0, y1) = (y.saturating_sub(radius), (y + radius + 1).min(h));
+        for x in 0..w {
+            let (x0, x1) = (x.saturating_sub(radius), (x + radius + 1).min(w));
+            sums[y * w + x] = integral[y1 * (w + 1) + x1] + integral[y0 * (w + 1) + x0]
+                - integral[y0 * (w + 1) + x1]
+                - integral[y1 * (w + 1) + x0];
+        }
+    }
+    sums
+}
+
+/// Computes the depth map of the frames of the left and the right cameras, with the focal length
+/// in px of the frames. The map is of the frames scaled down. None if they aren't of the same size.
+pub fn depth_map(
+    left: &GrayImage,
+    right: &GrayImage,
+    focal: f32,
+    conf: &StereoConf,
+) -> Option<DepthMap> {
+    if left.dimensions() != right.dimensions() || left.width() == 0 || left.height() == 0 {
+        return None;
+    }
+    let scale = (left.width() as f32 / MAX_WIDTH as f32).max(1.0);
+    let small = |img: &GrayImage| match 1.0 < scale {
+        true => imageops::resize(
+            img,
+            (img.width() as f32 / scale) as u32,
+            (img.height() as f32 / scale) as u32,
+            imageops::FilterType::Triangle,
+        ),
+        false => img.clone(),
+    };
+    let (left, right) = (small(left), small(right));
+    let (w, h) = (left.width() as usize, left.height() as usize);
+    let pixel = |img: &GrayImage, x: usize, y: usize| img.get_pixel(x as u32, y as u32)[0] as u32;
+    let radius = conf.block as usize / 2;
+    let max_disparity = (conf.max_disparity as usize).min(w - 1);
+
+    // The best and the second best costs, apart from the neighbors of the best
+    let mut best = vec![(u32::MAX, 0_usize); w * h];
+    let mut second = vec![u32::MAX; w * h];
+    for d in 1..=max_disparity {
+        let mut diff = vec![u8::MAX as u32; w * h];
+        for y in 0..h {
+            for x in d..w {
+                diff[y * w + x] = pixel(&left, x, y).abs_diff(pixel(&right, x - d, y));
+            }
+        }
+        let costs = box_sum(&diff, w, h, radius);
+        for (i, cost) in costs.into_iter().enumerate() {
+            let (best_cost, best_d) = best[i];
+            if cost < best_cost {
+                if 1 < d - best_d {
+                    second[i] = best_cost;
+                }
+                best[i] = (cost, d);
+            } else if cost < second[i] && 1 < d - best_d {
+                second[i] = cost;
+            }
+        }
+    }
+
+    let focal = focal / scale;
+    let depth = best
+        .iter()
+        .zip(second)
+        .enumerate()
+        .map(|(i, (&(cost, d), second))| {
+            // Not searched over all the disparities, at the left edge
+            let blocked = i % w < max_disparity + radius;
+            let unique = (cost as f32) < second as f32 * UNIQUENESS;
+            match 0 < d && !blocked && unique {
+                true => focal * conf.baseline / d as f32,
+                false => 0.0,
+            }
+        })
+        .collect();
+    Some(DepthMap {
+        width: w as u32,
+        height: h as u32,
+        depth,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Luma;
+
+    /// Texture of random shades.
+    fn texture(x: u32, y: u32) -> u8 {
+        let mut v = x.wrapping_mul(374_761_393) ^ y.wrapping_mul(668_265_263);
+        v = (v ^ (v >> 13)).wrapping_mul(1_274_126_177);
+        (v >> 24) as u8
+    }
+
+    #[test]
+    fn stereo_test() {
+        let conf = StereoConf::default();
+        // The left half 8 px apart, the right half 16 px apart
+        let shift = |x: u32| if x < 160 { 8 } else { 16 };
+        let left = GrayImage::from_fn(320, 180, |x, y| Luma([texture(x, y)]));
+        let right = GrayImage::from_fn(320, 180, |x, y| Luma([texture(x + shift(x), y)]));
+        // 2 m at the disparity of 8 px
+        let focal = 2.0 * 8.0 / conf.baseline;
+        let map = depth_map(&left, &right, focal, &conf).unwrap();
+        assert_eq!((map.width, map.height), (320, 180));
+        assert!((map.depth[90 * 320 + 80] - 2.0).abs() < 1e-3);
+        assert!((map.depth[90 * 320 + 240] - 1.0).abs() < 1e-3);
+        // Not seen by the right camera
+        assert_eq!(map.depth[90 * 320], 0.0);
+        // Without texture
+        let flat = GrayImage::from_pixel(320, 180, Luma([128]));
+        let map_flat = depth_map(&flat, &flat, focal, &conf).unwrap();
+        assert!(map_flat.depth.iter().all(|d| *d == 0.0));
+        assert_eq!(
+            depth_map(&left, &GrayImage::new(160, 90), focal, &conf),
+            None
+        );
+    }
+}
@@ -0,0 +1,250 @@
+//! Live Video Stream
+//!
+//! Serves the frames of the camera over HTTP as MJPEG, which any browser or video player shows
+//! as a video, so that the operators can watch what the robot sees over the LAN:
+//!
+//! http://<robot>:<port>/           <- A page showing all
+//! http://<robot>:<port>/raw        <- The frames as captured
+//! http://<robot>:<port>/annotated  <- The frames with the boxes of the detections
+//! http://<robot>:<port>/debug      <- The frames with the decisions of the pilot (see `overlay`)
+//!
+//! The frames are streamed as they're detected in, and the annotated ones are drawn only while
+//! someone is watching them.
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use image::{codecs::jpeg::JpegEncoder, RgbImage};
+
+use super::{detector::Detection, overlay};
+use crate::module::util::conf::Stream as StreamConf;
+
+// Time to wait for a slow client before dropping it.
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+// Time to wait for the request of a client.
+const READ_TIMEOUT: Duration = Duration::from_secs(2);
+// Boundary between the frames of the stream
+const BOUNDARY: &str = "frame";
+// Page showing both feeds
+const INDEX: &str = "<html><head><title>Roktrack</title></head><body>\
+<img src=\"/raw\" width=\"32%\"> <img src=\"/annotated\" width=\"32%\"> \
+<img src=\"/debug\" width=\"32%\"></body></html>";
+/// Feed of the stream
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Feed {
+    Raw,       // As captured
+    Annotated, // With the boxes of the detections
+    Debug,     // With the decisions of the pilot
+}
+
+/// Response to the request
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Route {
+    Index,
+    Feed(Feed),
+    NotFound,
+}
+
+/// Routes the request by the request line, e.g. `GET /raw HTTP/1.1`.
+pub fn route(request_line: &str) -> Route {
+    let mut fields = request_line.split_whitespace();
+    if fields.next() != Some("GET") {
+        return Route::NotFound;
+    }
+    // The query is ignored, e.g. to bust the cache of the browser.
+    let path = fields.next().unwrap_or("").split('?').next().unwrap_or("");
+    match path {
+        "/" => Route::Index,
+        "/raw" => Route::Feed(Feed::Raw),
+        "/annotated" => Route::Feed(Feed::Annotated),
+        "/debug" => Route::Feed(Feed::Debug),
+        _ => Route::NotFound,
+    }
+}
+
+/// A part of the multipart stream carrying the JPEG frame.
+pub fn part(jpeg: &[u8]) -> Vec<u8> {
+    let mut part = format!(
+        "--{}\r\nContent-Type: image/jpeg\r\nContent-Length: {}\r\n\r\n",
+        BOUNDARY,
+        jpeg.len()
+    )
+    .into_bytes();
+    part.extend_from_slice(jpeg);
+    part.extend_from_slice(b"\r\n");
+    part
+}
+
+/// A client watching a feed
+struct Client {
+    stream: TcpStream,
+    feed: Feed,
+}
+
+/// MJPEG Stream Server
+#[derive(Clone)]
+pub struct StreamServer {
+    clients: Arc<Mutex<Vec<Client>>>,
+    tx: Sender<(Feed, Vec<u8>)>,
+    quality: u8,
+}
+
+impl StreamServer {
+    /// Binds the server and starts accepting clients.
+    pub fn new(conf: StreamConf) -> Result<Self, Box<dyn std::error::Error>> {
+        let listener = TcpListener::bind((conf.bind.as_str(), conf.port))?;
+        log::info!("Video Stream Listening on {}:{}", conf.bind, conf.port);
+        let (tx, rx) = mpsc::channel();
+        let server = Self {
+            clients: Arc::new(Mutex::new(vec![])),
+            tx,
+            quality: conf.quality,
+        };
+        server.accept(listener);
+        server.forward(rx);
+        Ok(server)
+    }
+
+    /// Accepts clients in a thread.
+    fn accept(&self, listener: TcpListener) -> JoinHandle<()> {
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        log::warn!("Video Stream Accept Failed: {}", e);
+                        continue;
+                    }
+                };
+                match respond(stream) {
+                    Ok(Some(client)) => clients.lock().unwrap().push(client),
+                    Ok(None) => {}
+                    Err(e) => log::warn!("Video Stream Request Failed: {}", e),
+                }
+            }
+        })
+    }
+
+    /// Sends the frames to every client in a thread, so that the vision never blocks.
+    fn forward(&self, rx: Receiver<(Feed, Vec<u8>)>) -> JoinHandle<()> {
+        let clients = self.clients.clone();
+        thread::spawn(move || {
+            for (feed, jpeg) in rx {
+                let part = part(&jpeg);
+                // Clients that fail or are too slow are dropped.
+                clients.lock().unwrap().retain_mut(|client| {
+                    client.feed != feed || client.stream.write_all(&part).is_ok()
+                });
+            }
+        })
+    }
+
+    /// Whether anyone is watching the feed.
+    pub fn watched(&self, feed: Feed) -> bool {
+        self.clients
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|client| client.feed == feed)
+    }
+
+    /// Publishes the frame saved at the path, and the detections in it in the input of the size
+    /// `imgsz`, to the clients.
+    pub fn publish(
+        &self,
+        path: &str,
+        dets: &[Detection],
+        imgsz: u32,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let raw = std::fs::read(path)?;
+        if self.watched(Feed::Annotated) {
+            let mut img = image::load_from_memory(&raw)?.to_rgb8();
+            overlay::boxes(&mut img, dets, imgsz);
+            self.send_image(Feed::Annotated, &img)?;
+        }
+        self.tx.send((Feed::Raw, raw))?;
+        Ok(())
+    }
+
+    /// Sends the frame drawn on to the clients of the feed.
+    pub fn send_image(&self, feed: Feed, img: &RgbImage) -> Result<(), Box<dyn std::error::Error>> {
+        let mut jpeg = vec![];
+        JpegEncoder::new_with_quality(&mut jpeg, self.quality).encode_image(img)?;
+        self.tx.send((feed, jpeg))?;
+        Ok(())
+    }
+}
+
+/// Reads the request of the client and responds to it. Returns the client to stream to, if any.
+fn respond(mut stream: TcpStream) -> Result<Option<Client>, Box<dyn std::error::Error>> {
+    let peer = stream.peer_addr()?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Skip the headers.
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+    match route(&request_line) {
+        Route::Index => {
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\n\r\n{}",
+                INDEX.len(),
+                INDEX
+            )?;
+            Ok(None)
+        }
+        Route::Feed(feed) => {
+            write!(
+                stream,
+                "HTTP/1.0 200 OK\r\nContent-Type: multipart/x-mixed-replace; boundary={}\r\n\
+                 Cache-Control: no-cache\r\nConnection: close\r\n\r\n",
+                BOUNDARY
+            )?;
+            log::info!("Video Stream Client Connected: {}, {:?}", peer, feed);
+            Ok(Some(Client { stream, feed }))
+        }
+        Route::NotFound => {
+            write!(
+                stream,
+                "HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n"
+            )?;
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn route_test() {
+        assert_eq!(route("GET / HTTP/1.1\r\n"), Route::Index);
+        assert_eq!(route("GET /raw HTTP/1.1\r\n"), Route::Feed(Feed::Raw));
+        assert_eq!(
+            route("GET /annotated?t=1 HTTP/1.1\r\n"),
+            Route::Feed(Feed::Annotated)
+        );
+        assert_eq!(route("GET /debug HTTP/1.0\r\n"), Route::Feed(Feed::Debug));
+        assert_eq!(route("POST /raw HTTP/1.1\r\n"), Route::NotFound);
+        assert_eq!(route("GET /last.jpg HTTP/1.1\r\n"), Route::NotFound);
+        assert_eq!(route(""), Route::NotFound);
+
+        assert_eq!(
+            part(b"jpg"),
+            b"--frame\r\nContent-Type: image/jpeg\r\nContent-Length: 3\r\n\r\njpg\r\n"
+        );
+    }
+}
@@ -0,0 +1,275 @@
+//! Thermal Camera
+//!
+//! In the dark the detector misses the persons and the animals that the IR frames show dimly, but
+//! their bodies are warmer than the lawn at night. A low-resolution thermal sensor looking along the
+//! camera finds them as the warm blobs, which confirm the detections or are added where none is.
+//! The hot spots of the motors and the battery seen in a part of its view are watched, too.
+//!
+//! The frames are written to a file by the driver of the sensor, as the temperatures per pixel:
+//!
+//! f32  <- MLX90640 (32 * 24), the temperatures in °C as the 32-bit floats (little endian)
+//! y16  <- Lepton (80 * 60 or 160 * 120) in the radiometric mode, in the units of 0.01 K (16-bit)
+//!
+//! The view of the sensor is taken as the region of the frames of the camera given by `view`.
+
+use super::detector::{AnimalClasses, Detection, RoktrackClasses};
+use crate::module::util::conf::Thermal as ThermalConf;
+
+/// Class of the warm bodies found only by the thermal camera with the animal model.
+pub const WARM_CLASS: u32 = 130;
+
+// Score of the warm bodies found only by the thermal camera
+const WARM_PROB: f32 = 0.5;
+// Least ratio of the height to the width of a warm body to be a person standing
+const UPRIGHT: f32 = 1.2;
+// 0 °C in K
+const ZERO_CELSIUS: f32 = 273.15;
+
+/// Temperatures per pixel of a thermal frame (`width` * `height`, row-major)
+#[derive(Debug, Clone, PartialEq)]
+pub struct ThermalFrame {
+    pub width: u32,
+    pub height: u32,
+    pub temp: Vec<f32>, // Temperature in °C
+}
+
+impl ThermalFrame {
+    /// Reads the frame of the 32-bit floats in °C. None if the data isn't of the size.
+    pub fn from_f32(data: &[u8], width: u32, height: u32) -> Option<Self> {
+        if data.len() != (width * height * 4) as usize {
+            return None;
+        }
+        let temp = data
+            .chunks_exact(4)
+            .map(|v| f32::from_le_bytes([v[0], v[1], v[2], v[3]]))
+            .collect();
+        Some(Self {
+            width,
+            height,
+            temp,
+        })
+    }
+
+    /// Reads the radiometric frame of the 16-bit values in 0.01 K. None if the data isn't of the size.
+    pub fn from_y16(data: &[u8], width: u32, height: u32) -> Option<Self> {
+        if data.len() != (width * height * 2) as usize {
+            return None;
+        }
+        let temp = data
+            .chunks_exact(2)
+            .map(|v| u16::from_le_bytes([v[0], v[1]]) as f32 / 100.0 - ZERO_CELSIUS)
+            .collect();
+        Some(Self {
+            width,
+            height,
+            temp,
+        })
+    }
+
+    /// Reads the frame of the format and the size of the config. None if the data isn't of them.
+    pub fn read(data: &[u8], conf: &ThermalConf) -> Option<Self> {
+        let (width, height) = (conf.width as u32, conf.height as u32);
+        match conf.format.as_str() {
+            "f32" => Self::from_f32(data, width, height),
+            "y16" => Self::from_y16(data, width, height),
+            format => {
+                log::warn!("Invalid Thermal Format: {}", format);
+                None
+            }
+        }
+    }
+
+    /// The hottest temperature in the region (x1, y1, x2, y2 in ratios of the frame).
+    /// None if the region is empty.
+    pub fn hottest(&self, region: &[f32]) -> Option<f32> {
+        let [x1, y1, x2, y2] = <[f32; 4]>::try_from(region).ok()?;
+        let px = |r: f32, size: u32| ((r.clamp(0.0, 1.0) * size as f32).round()) as u32;
+        let (x1, x2) = (px(x1, self.width), px(x2, self.width));
+        let (y1, y2) = (px(y1, self.height), px(y2, self.height));
+        (y1..y2)
+            .flat_map(|y| (x1..x2).map(move |x| (x, y)))
+            .map(|(x, y)| self.temp[(y * self.width + x) as usize])
+            .reduce(f32::max)
+    }
+
+    /// Boxes (x1, y1, x2, y2 in px, exclusive ends) of the blobs of the pixels between the
+    /// temperatures, of `min_pixels` or more pixels each, connected to the sides.
+    pub fn blobs(
+        &self,
+        min_temp: f32,
+        max_temp: f32,
+        min_pixels: u32,
+    ) -> Vec<(u32, u32, u32, u32)> {
+        let (w, h) = (self.width as usize, self.height as usize);
+        let warm: Vec<bool> = self
+            .temp
+            .iter()
+            .map(|t| (min_temp..=max_temp).contains(t))
+            .collect();
+        let mut visited = vec![false; w * h];
+        let mut boxes = vec![];
+        for start in 0..w * h {
+            if !warm[start] || visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut stack = vec![start];
+            let (mut x1, mut y1, mut x2, mut y2) = (w, h, 0, 0);
+            let mut pixels = 0;
+            while let Some(i) = stack.pop() {
+                let (x, y) = (i % w, i / w);
+                (x1, y1, x2, y2) = (x1.min(x), y1.min(y), x2.max(x + 1), y2.max(y + 1));
+                pixels += 1;
+                let sides = [
+                    (0 < x).then(|| i - 1),
+                    (x + 1 < w).then(|| i + 1),
+                    (0 < y).then(|| i - w),
+                    (y + 1 < h).then(|| i + w),
+                ];
+                for j in sides.into_iter().flatten() {
+                    if warm[j] && !visited[j] {
+                        visited[j] = true;
+                        stack.push(j);
+                    }
+                }
+            }
+            if min_pixels <= pixels {
+                boxes.push((x1 as u32, y1 as u32, x2 as u32, y2 as u32));
+            }
+        }
+        boxes
+    }
+}
+
+/// The warm bodies in the frame, as the detections in the input of the size `imgsz`.
+pub fn warm(frame: &ThermalFrame, conf: &ThermalConf, imgsz: u32) -> Vec<Detection> {
+    let [vx1, vy1, vx2, vy2] =
+        <[f32; 4]>::try_from(conf.view.as_slice()).unwrap_or([0.0, 0.0, 1.0, 1.0]);
+    let sx = (vx2 - vx1) * imgsz as f32 / frame.width.max(1) as f32;
+    let sy = (vy2 - vy1) * imgsz as f32 / frame.height.max(1) as f32;
+    let (ox, oy) = (vx1 * imgsz as f32, vy1 * imgsz as f32);
+    frame
+        .blobs(conf.body_min, conf.body_max, conf.min_pixels)
+        .into_iter()
+        .map(|(x1, y1, x2, y2)| {
+            let (x1, x2) = (ox + x1 as f32 * sx, ox + x2 as f32 * sx);
+            let (y1, y2) = (oy + y1 as f32 * sy, oy + y2 as f32 * sy);
+            Detection {
+                x1: x1 as u32,
+                y1: y1 as u32,
+                x2: x2 as u32,
+                y2: y2 as u32,
+                xc: (x1 + x2) / 2.0,
+                yc: (y1 + y2) / 2.0,
+                cls: WARM_CLASS,
+                prob: WARM_PROB,
+                w: (x2 - x1) as u32,
+                h: (y2 - y1) as u32,
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+/// Adds the warm bodies not detected to the detections, of the animal model or not. Those of the
+/// animal model are added as `WARM_CLASS`, and those of the others as the persons if upright.
+pub fn fuse(dets: &mut Vec<Detection>, warm: Vec<Detection>, animal: bool) {
+    let is_body = |cls: u32| match animal {
+        true => AnimalClasses::from_u32(cls).is_some(),
+        false => cls == RoktrackClasses::PERSON.to_u32(),
+    };
+    for body in warm {
+        let detected = dets
+            .iter()
+            .any(|det| is_body(det.cls) && overlaps(det, &body));
+        if detected {
+            continue;
+        }
+        match animal {
+            true => dets.push(body),
+            false if UPRIGHT * body.w as f32 <= body.h as f32 => dets.push(Detection {
+                cls: RoktrackClasses::PERSON.to_u32(),
+                ..body
+            }),
+            false => log::debug!("Warm Body Not Upright: {:?}", body),
+        }
+    }
+}
+
+/// Whether the boxes overlap.
+fn overlaps(a: &Detection, b: &Detection) -> bool {
+    a.x1 < b.x2 && b.x1 < a.x2 && a.y1 < b.y2 && b.y1 < a.y2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thermal_test() {
+        // 20 °C and 300.15 K (27 °C)
+        let data: Vec<u8> = [20.0_f32; 4].iter().flat_map(|t| t.to_le_bytes()).collect();
+        let frame = ThermalFrame::from_f32(&data, 2, 2).unwrap();
+        assert_eq!(frame.temp, vec![20.0; 4]);
+        assert_eq!(ThermalFrame::from_f32(&data, 3, 2), None);
+        let data: Vec<u8> = [30015_u16; 2]
+            .iter()
+            .flat_map(|t| t.to_le_bytes())
+            .collect();
+        let frame = ThermalFrame::from_y16(&data, 2, 1).unwrap();
+        assert!((frame.temp[0] - 27.0).abs() < 0.01);
+
+        // A person standing on the left, a cat lying on the right, and a hot motor at the bottom,
+        // in the lawn of 15 °C of 8 * 6
+        let mut temp = vec![15.0; 48];
+        for y in 0..4 {
+            temp[y * 8 + 1] = 33.0;
+        }
+        temp[2 * 8 + 5] = 36.0;
+        temp[2 * 8 + 6] = 36.0;
+        temp[5 * 8 + 3] = 75.0;
+        let frame = ThermalFrame {
+            width: 8,
+            height: 6,
+            temp,
+        };
+        assert_eq!(frame.blobs(28.0, 40.0, 2), vec![(1, 0, 2, 4), (5, 2, 7, 3)]);
+        assert_eq!(frame.blobs(28.0, 40.0, 3), vec![(1, 0, 2, 4)]);
+        assert_eq!(frame.hottest(&[0.0, 0.8, 1.0, 1.0]), Some(75.0));
+        assert_eq!(frame.hottest(&[0.0, 0.0, 1.0, 0.5]), Some(36.0));
+        assert_eq!(frame.hottest(&[]), None);
+
+        // Over the whole frame in the input of 320 * 320
+        let conf = ThermalConf {
+            min_pixels: 2,
+            ..Default::default()
+        };
+        let bodies = warm(&frame, &conf, 320);
+        assert_eq!(bodies.len(), 2);
+        assert_eq!(
+            (bodies[0].x1, bodies[0].y1, bodies[0].x2, bodies[0].y2),
+            (40, 0, 80, 213)
+        );
+
+        // The person is added as one, while the cat isn't
+        let mut dets = vec![];
+        fuse(&mut dets, bodies.clone(), false);
+        assert_eq!(dets.len(), 1);
+        assert_eq!(dets[0].cls, RoktrackClasses::PERSON.to_u32());
+        // Not again where detected
+        fuse(&mut dets, bodies.clone(), false);
+        assert_eq!(dets.len(), 1);
+        // Both with the animal model, the cat not again where detected
+        let mut dets = vec![Detection {
+            x1: 190,
+            y1: 100,
+            x2: 290,
+            y2: 170,
+            cls: AnimalClasses::CAT.to_u32(),
+            ..Default::default()
+        }];
+        fuse(&mut dets, bodies, true);
+        assert_eq!(dets.len(), 2);
+        assert_eq!(dets[1].cls, WARM_CLASS);
+    }
+}
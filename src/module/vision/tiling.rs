@@ -0,0 +1,195 @@
+//! ROI Tiling
+//!
+//! The far pylons are only a few px high in the input of the model, the whole frame resized, and
+//! are missed. The band of the frame around the horizon, where they're seen, is cut into tiles,
+//! each given to the model at the same input size, i.e. magnified, and the boxes found in them are
+//! put back into the input of the whole frame.
+//!
+//! The tiles overlap, so that an object cut by the border of a tile is whole in the next one. The
+//! boxes at the borders are left to the next tiles, or to the whole frame for the objects larger
+//! than the band, and those found in the whole frame too are kept as they were.
+
+use image::imageops;
+
+use super::detector::{onnx::iou, Detection, Detector};
+use crate::module::util::conf::Tiling as TilingConf;
+
+// Distance from the border of a tile in px of the input within which the boxes are taken as cut
+const BORDER: u32 = 2;
+
+/// A region of the frame in px
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tile {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// The tiles of the band around the horizon of the frame of the size, from left to right.
+pub fn tiles(width: u32, height: u32, conf: &TilingConf) -> Vec<Tile> {
+    let top = (height as f32 * conf.top.clamp(0.0, 1.0)) as u32;
+    let bottom = (height as f32 * conf.bottom.clamp(0.0, 1.0)) as u32;
+    if conf.tiles == 0 || width == 0 || bottom <= top {
+        return vec![];
+    }
+    let n = conf.tiles as u32;
+    let tile_width = ((width as f32 / n as f32) * (1.0 + conf.overlap.max(0.0))) as u32;
+    let tile_width = tile_width.min(width);
+    let step = match n {
+        1 => 0,
+        _ => (width - tile_width) / (n - 1),
+    };
+    (0..n)
+        .map(|i| Tile {
+            x: i * step,
+            y: top,
+            width: tile_width,
+            height: bottom - top,
+        })
+        .collect()
+}
+
+/// Puts the boxes detected in the input of the size `imgsz` of the tile back into the input of the
+/// whole frame of the size `frame` (width, height). The boxes cut by the borders of the tile inside
+/// the frame are left out.
+pub fn place(dets: Vec<Detection>, tile: Tile, frame: (u32, u32), imgsz: u32) -> Vec<Detection> {
+    let (width, height) = frame;
+    if width == 0 || height == 0 || imgsz == 0 {
+        return vec![];
+    }
+    let cut_left = 0 < tile.x;
+    let cut_right = tile.x + tile.width < width;
+    let cut_top = 0 < tile.y;
+    let cut_bottom = tile.y + tile.height < height;
+    // Tile input -> frame input
+    let sx = tile.width as f32 / width as f32;
+    let sy = tile.height as f32 / height as f32;
+    let ox = tile.x as f32 * imgsz as f32 / width as f32;
+    let oy = tile.y as f32 * imgsz as f32 / height as f32;
+    dets.into_iter()
+        .filter(|det| {
+            !(cut_left && det.x1 <= BORDER
+                || cut_right && imgsz <= det.x2 + BORDER
+                || cut_top && det.y1 <= BORDER
+                || cut_bottom && imgsz <= det.y2 + BORDER)
+        })
+        .map(|det| {
+            let (x1, x2) = (ox + det.x1 as f32 * sx, ox + det.x2 as f32 * sx);
+            let (y1, y2) = (oy + det.y1 as f32 * sy, oy + det.y2 as f32 * sy);
+            Detection {
+                x1: x1 as u32,
+                y1: y1 as u32,
+                x2: x2 as u32,
+                y2: y2 as u32,
+                xc: (x1 + x2) / 2.0,
+                yc: (y1 + y2) / 2.0,
+                w: (x2 - x1) as u32,
+                h: (y2 - y1) as u32,
+                ..det
+            }
+        })
+        .collect()
+}
+
+/// Adds the boxes found in the tiles to those of the whole frame, except for the ones found already,
+/// in the whole frame or in another tile, overlapping the same class by `iou_threshold` or more.
+pub fn merge(dets: &mut Vec<Detection>, mut tiled: Vec<Detection>, iou_threshold: f64) {
+    tiled.sort_by(|a, b| b.prob.total_cmp(&a.prob));
+    for det in tiled {
+        let found = dets
+            .iter()
+            .any(|d| d.cls == det.cls && iou_threshold <= iou(d.clone(), det.clone()));
+        if !found {
+            dets.push(det);
+        }
+    }
+}
+
+/// Detects the objects in the tiles of the frame, in the input of the whole frame of the size
+/// `imgsz`. Each tile is saved to `tile_path` to be given to the model.
+pub fn detect(
+    det: &dyn Detector,
+    impath: &str,
+    tile_path: &str,
+    imgsz: u32,
+    conf: &TilingConf,
+) -> Result<Vec<Detection>, Box<dyn std::error::Error>> {
+    let img = image::open(impath)?;
+    let frame = (img.width(), img.height());
+    let mut found = vec![];
+    for tile in tiles(frame.0, frame.1, conf) {
+        imageops::crop_imm(&img, tile.x, tile.y, tile.width, tile.height)
+            .to_image()
+            .save(tile_path)?;
+        found.extend(place(det.detect(tile_path)?, tile, frame, imgsz));
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tiling_test() {
+        let conf = TilingConf {
+            top: 0.25,
+            bottom: 0.5,
+            tiles: 2,
+            overlap: 0.25,
+            ..Default::default()
+        };
+        let tiles = tiles(640, 480, &conf);
+        assert_eq!(
+            tiles,
+            vec![
+                Tile {
+                    x: 0,
+                    y: 120,
+                    width: 400,
+                    height: 120
+                },
+                Tile {
+                    x: 240,
+                    y: 120,
+                    width: 400,
+                    height: 120
+                }
+            ]
+        );
+
+        // In the input of 320 * 320
+        let det = |x1: u32, y1: u32, x2: u32, y2: u32, prob: f32| Detection {
+            x1,
+            y1,
+            x2,
+            y2,
+            prob,
+            ..Default::default()
+        };
+        let placed = place(
+            vec![det(160, 80, 176, 240, 0.6), det(300, 80, 320, 240, 0.6)],
+            tiles[1],
+            (640, 480),
+            320,
+        );
+        // The second tile ends at the right edge of the frame, so the second isn't cut.
+        assert_eq!(placed.len(), 2);
+        assert_eq!((placed[0].x1, placed[0].x2), (220, 230));
+        assert_eq!((placed[0].y1, placed[0].y2), (100, 140));
+        assert_eq!(placed[0].h, 40);
+        // Cut by the right border of the first tile and by the top of the band
+        let cut = place(vec![det(300, 80, 320, 240, 0.6)], tiles[0], (640, 480), 320);
+        assert!(cut.is_empty());
+        let cut = place(vec![det(160, 0, 176, 240, 0.6)], tiles[1], (640, 480), 320);
+        assert!(cut.is_empty());
+
+        // The one found in the whole frame is kept as it was.
+        let mut dets = vec![det(219, 99, 231, 141, 0.9)];
+        merge(&mut dets, placed, 0.5);
+        assert_eq!(dets.len(), 2);
+        assert_eq!(dets[0].prob, 0.9);
+        assert_eq!(dets[1].x1, 307);
+    }
+}
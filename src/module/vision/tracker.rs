@@ -0,0 +1,184 @@
+//! Multi-Object Tracking
+//!
+//! Assigns IDs to the detections kept across frames, in the manner of SORT and ByteTrack, so that
+//! the pilots can follow the same pylon or person instead of picking one anew every frame.
+//!
+//! Each track predicts its box in the current frame from the motion of its center, and the
+//! detections of the same class are matched to the predictions greedily by IoU. The confident
+//! detections (`high_score` and above) are matched first and start new tracks. The others only
+//! continue the tracks left, so that a pylon fading in the distance keeps its ID without noise
+//! starting tracks. A track without detections is kept for `max_age` frames, so that the ID
+//! survives a missed frame.
+
+use super::detector::{onnx::iou, Detection};
+use crate::module::util::conf::Tracking;
+
+/// An object tracked across frames
+#[derive(Debug, Clone)]
+struct Track {
+    id: u32,
+    det: Detection,       // Last detection
+    velocity: (f32, f32), // Motion of the center per frame
+    age: u8,              // Frames since the last detection
+}
+
+impl Track {
+    /// Box predicted in the current frame.
+    fn predict(&self) -> Detection {
+        let (dx, dy) = (
+            self.velocity.0 * self.age as f32,
+            self.velocity.1 * self.age as f32,
+        );
+        let shift = |v: u32, d: f32| (v as f32 + d).max(0.0) as u32;
+        Detection {
+            x1: shift(self.det.x1, dx),
+            y1: shift(self.det.y1, dy),
+            x2: shift(self.det.x2, dx),
+            y2: shift(self.det.y2, dy),
+            xc: self.det.xc + dx,
+            yc: self.det.yc + dy,
+            ..self.det.clone()
+        }
+    }
+}
+
+/// Keeps the tracks and assigns their IDs to the detections.
+#[derive(Debug, Clone, Default)]
+pub struct Tracker {
+    tracks: Vec<Track>, // Live tracks
+    next_id: u32,       // ID of the next new track
+}
+
+impl Tracker {
+    /// Matches the detections of a frame to the tracks and sets their IDs.
+    pub fn update(&mut self, mut dets: Vec<Detection>, conf: &Tracking) -> Vec<Detection> {
+        for track in self.tracks.iter_mut() {
+            track.age = track.age.saturating_add(1);
+        }
+        let predictions: Vec<Detection> = self.tracks.iter().map(Track::predict).collect();
+        let mut matched = vec![false; self.tracks.len()];
+        let (high, low): (Vec<usize>, Vec<usize>) =
+            (0..dets.len()).partition(|&i| conf.high_score <= dets[i].prob);
+        for stage in [high, low] {
+            // Candidate pairs of a detection and a track, the most overlapping first
+            let mut pairs = vec![];
+            for &i in stage.iter() {
+                for (t, prediction) in predictions.iter().enumerate() {
+                    if matched[t] || prediction.cls != dets[i].cls {
+                        continue;
+                    }
+                    let overlap = iou(prediction.clone(), dets[i].clone()) as f32;
+                    if conf.iou <= overlap {
+                        pairs.push((overlap, i, t));
+                    }
+                }
+            }
+            pairs.sort_by(|a, b| b.0.total_cmp(&a.0));
+            for (_, i, t) in pairs {
+                if matched[t] || dets[i].track.is_some() {
+                    continue;
+                }
+                matched[t] = true;
+                let track = &mut self.tracks[t];
+                let frames = track.age.max(1) as f32;
+                track.velocity = (
+                    (dets[i].xc - track.det.xc) / frames,
+                    (dets[i].yc - track.det.yc) / frames,
+                );
+                track.age = 0;
+                dets[i].track = Some(track.id);
+                track.det = dets[i].clone();
+            }
+        }
+        for det in dets.iter_mut() {
+            if det.track.is_none() && conf.high_score <= det.prob {
+                det.track = Some(self.next_id);
+                self.tracks.push(Track {
+                    id: self.next_id,
+                    det: det.clone(),
+                    velocity: (0.0, 0.0),
+                    age: 0,
+                });
+                self.next_id = self.next_id.wrapping_add(1);
+            }
+        }
+        self.tracks.retain(|track| track.age <= conf.max_age);
+        dets
+    }
+
+    /// Forgets the tracks, e.g. when the frames are no longer continuous.
+    pub fn reset(&mut self) {
+        self.tracks.clear();
+    }
+}
+
+/// Follows the detection of the ID while it's in sight. Otherwise takes the picked one and
+/// follows its ID from then on.
+pub fn follow(id: &mut Option<u32>, picked: Detection, dets: &[Detection]) -> Detection {
+    if let Some(followed) = id.and_then(|id| dets.iter().find(|det| det.track == Some(id))) {
+        return followed.clone();
+    }
+    if picked.h != 0 {
+        *id = picked.track;
+    }
+    picked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker_test() {
+        let conf = Tracking::default();
+        let det = |x1: u32, prob: f32| Detection {
+            x1,
+            y1: 100,
+            x2: x1 + 40,
+            y2: 180,
+            xc: x1 as f32 + 20.0,
+            yc: 140.0,
+            prob,
+            w: 40,
+            h: 80,
+            ..Default::default()
+        };
+        let mut tracker = Tracker::default();
+        let first = tracker.update(vec![det(0, 0.9), det(200, 0.9), det(100, 0.3)], &conf);
+        assert_eq!(
+            first.iter().map(|d| d.track).collect::<Vec<_>>(),
+            vec![Some(0), Some(1), None]
+        );
+        // Moving right, the order swapped and the score fading
+        let second = tracker.update(vec![det(210, 0.9), det(10, 0.4)], &conf);
+        assert_eq!((second[0].track, second[1].track), (Some(1), Some(0)));
+        // Missed a frame, and found where the motion predicts.
+        tracker.update(vec![det(220, 0.9)], &conf);
+        let fourth = tracker.update(vec![det(30, 0.9)], &conf);
+        assert_eq!(fourth[0].track, Some(0));
+        // Another class doesn't continue the track.
+        let other = Detection {
+            cls: 1,
+            ..det(40, 0.9)
+        };
+        assert_eq!(tracker.update(vec![other], &conf)[0].track, Some(2));
+        tracker.reset();
+        assert_eq!(tracker.update(vec![det(40, 0.9)], &conf)[0].track, Some(3));
+
+        let mut id = None;
+        let dets = [
+            Detection {
+                track: Some(3),
+                ..det(40, 0.9)
+            },
+            Detection {
+                track: Some(4),
+                ..det(200, 0.9)
+            },
+        ];
+        assert_eq!(follow(&mut id, dets[1].clone(), &dets).track, Some(4));
+        assert_eq!(follow(&mut id, dets[0].clone(), &dets).track, Some(4));
+        assert_eq!(follow(&mut id, dets[0].clone(), &dets[..1]).track, Some(3));
+        assert_eq!(id, Some(3));
+    }
+}